@@ -0,0 +1,200 @@
+// ============================================================================
+// Market-Maker Credit Lines
+// ============================================================================
+//
+// An admin can grant a designated LP a temporary margin credit line, up to the
+// market's `max_credit_limit`, so it can keep quoting through volatility
+// spikes without pre-funding worst-case margin. While the line is active its
+// limit counts toward the LP's maintenance and initial margin checks (trades
+// and liquidation), but never toward withdrawals: capital cannot be taken out
+// against borrowed margin.
+//
+// The drawn part of the line — the shortfall of equity below the initial
+// margin requirement, up to the limit — pays interest every slot at the
+// line's rate. Interest is charged like the other fees (fee credits first,
+// then capital into insurance) when the crank visits the LP and before the LP
+// trades. The line is revoked outright, and the LP must then stand on its own
+// margin, when any repayment trigger fires:
+//
+//   - the expiry slot is reached
+//   - insurance falls to the risk-reduction threshold (force-realize mode)
+//   - interest could not be paid, leaving fee debt
+
+use crate::events::{EventKind, EVENT_NO_ACCOUNT};
+use crate::{
+    mul_div, neg_i128_to_u128, saturating_abs_i128, Account, Result, RiskEngine, RiskError,
+    Rounding, MAX_ACCOUNTS, MAX_ORACLE_PRICE, U128,
+};
+
+impl RiskEngine {
+    /// Grant (or replace) LP `idx`'s margin credit line (admin function): up to
+    /// `limit` of margin, paying `rate_e9` per slot on the drawn amount, until
+    /// `expiry_slot`. `limit` may not exceed `ExtParams::max_credit_limit`, and
+    /// grants are rejected while an admin council is active.
+    pub fn grant_credit_line(
+        &mut self,
+        idx: u16,
+        limit: u128,
+        rate_e9: u64,
+        expiry_slot: u64,
+    ) -> Result<()> {
+        let result = self.grant_credit_line_inner(idx, limit, rate_e9, expiry_slot);
+        self.finish_mutation("grant_credit_line", result.is_ok());
+        result
+    }
+
+    fn grant_credit_line_inner(
+        &mut self,
+        idx: u16,
+        limit: u128,
+        rate_e9: u64,
+        expiry_slot: u64,
+    ) -> Result<()> {
+        if self.admin_council.threshold > 0 {
+            return Err(RiskError::ApprovalsMissing);
+        }
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return Err(RiskError::AccountNotFound);
+        }
+        if !self.accounts[idx as usize].is_lp() {
+            return Err(RiskError::NotAnLPAccount);
+        }
+        if limit == 0 || limit > self.ext_params.max_credit_limit.get() {
+            return Err(RiskError::InvalidParams);
+        }
+        if expiry_slot <= self.current_slot {
+            return Err(RiskError::InvalidParams);
+        }
+        let current_slot = self.current_slot;
+        let account = &mut self.accounts[idx as usize];
+        account.credit_limit = U128::new(limit);
+        account.credit_rate_e9 = rate_e9;
+        account.credit_expiry_slot = expiry_slot;
+        account.credit_accrual_slot = current_slot;
+        Ok(())
+    }
+
+    /// Revoke LP `idx`'s credit line (admin function). Interest accrued up to
+    /// `now_slot` is charged first, so revoking does not forgive it.
+    pub fn revoke_credit_line(&mut self, idx: u16, now_slot: u64, oracle_price: u64) -> Result<()> {
+        let result = self.revoke_credit_line_inner(idx, now_slot, oracle_price);
+        self.finish_mutation("revoke_credit_line", result.is_ok());
+        result
+    }
+
+    fn revoke_credit_line_inner(
+        &mut self,
+        idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<()> {
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return Err(RiskError::AccountNotFound);
+        }
+        if !self.accounts[idx as usize].is_lp() {
+            return Err(RiskError::NotAnLPAccount);
+        }
+        if oracle_price == 0 || oracle_price > MAX_ORACLE_PRICE {
+            return Err(RiskError::Overflow);
+        }
+        self.settle_credit_line(idx as usize, now_slot, oracle_price);
+        let limit = self.accounts[idx as usize].credit_limit.get();
+        if limit > 0 {
+            self.clear_credit_line(idx as usize);
+            self.record_event(
+                EventKind::CreditLineRevoked,
+                idx,
+                EVENT_NO_ACCOUNT,
+                limit,
+                0,
+                oracle_price,
+            );
+        }
+        Ok(())
+    }
+
+    /// Margin credit `account` may count right now (0 once expired or while
+    /// force-realize is active).
+    pub fn credit_available(&self, account: &Account) -> u128 {
+        if self.current_slot >= account.credit_expiry_slot || self.force_realize_active() {
+            return 0;
+        }
+        account.credit_limit.get()
+    }
+
+    /// Part of `account`'s credit line in use at `oracle_price`: its equity shortfall
+    /// below the initial margin requirement, capped at the available credit.
+    pub fn credit_drawn(&self, account: &Account, oracle_price: u64) -> u128 {
+        let available = self.credit_available(account);
+        if available == 0 {
+            return 0;
+        }
+        let abs_pos = saturating_abs_i128(account.position_size.get()) as u128;
+        let notional = self.notional_at(abs_pos, oracle_price, Rounding::Up);
+        let required = self.margin_required(notional, self.initial_margin_bps_for(account));
+        let equity = self.account_equity_mtm_at_oracle(account, oracle_price);
+        core::cmp::min(required.saturating_sub(equity), available)
+    }
+
+    /// Charge LP `idx`'s credit interest up to `now_slot` and revoke the line if a
+    /// repayment trigger has fired.
+    pub(crate) fn settle_credit_line(&mut self, idx: usize, now_slot: u64, oracle_price: u64) {
+        if self.accounts[idx].credit_limit.is_zero() {
+            return;
+        }
+        let account = &self.accounts[idx];
+        let dt = now_slot.saturating_sub(account.credit_accrual_slot);
+        let interest = mul_div(
+            self.credit_drawn(account, oracle_price),
+            (account.credit_rate_e9 as u128).saturating_mul(dt as u128),
+            1_000_000_000,
+            Rounding::Up,
+        );
+        self.accounts[idx].credit_accrual_slot =
+            core::cmp::max(self.accounts[idx].credit_accrual_slot, now_slot);
+        if interest > 0 {
+            self.charge_credit_interest(idx, interest);
+        }
+
+        let account = &self.accounts[idx];
+        let expired = now_slot >= account.credit_expiry_slot;
+        if expired || self.force_realize_active() || account.fee_credits.is_negative() {
+            let limit = account.credit_limit.get();
+            self.clear_credit_line(idx);
+            self.record_event(
+                EventKind::CreditLineRevoked,
+                idx as u16,
+                EVENT_NO_ACCOUNT,
+                limit,
+                0,
+                oracle_price,
+            );
+        }
+    }
+
+    /// Draw `interest` from fee credits, then capital into insurance; any
+    /// shortfall remains as fee debt.
+    fn charge_credit_interest(&mut self, idx: usize, interest: u128) {
+        let account = &mut self.accounts[idx];
+        account.fee_credits = account.fee_credits.saturating_sub_u128(interest);
+        if !account.fee_credits.is_negative() {
+            return;
+        }
+        let owed = neg_i128_to_u128(account.fee_credits.get());
+        let current_cap = account.capital.get();
+        let pay = core::cmp::min(owed, current_cap);
+        self.set_capital(idx, current_cap.saturating_sub(pay));
+        self.insurance_fund.balance = self.insurance_fund.balance.saturating_add(pay);
+        self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue.saturating_add(pay);
+        self.revenue.credit_interest = self.revenue.credit_interest.saturating_add(pay);
+        self.accounts[idx].fee_credits = self.accounts[idx].fee_credits.saturating_add_u128(pay);
+    }
+
+    fn clear_credit_line(&mut self, idx: usize) {
+        let account = &mut self.accounts[idx];
+        account.credit_limit = U128::ZERO;
+        account.credit_rate_e9 = 0;
+        account.credit_expiry_slot = 0;
+        account.credit_accrual_slot = 0;
+    }
+}
@@ -0,0 +1,166 @@
+// ============================================================================
+// Liquidity-Mining Emissions
+// ============================================================================
+//
+// Reward points for the activity a venue wants to bootstrap. Each account
+// accrues, in collateral-unit points scaled by the `ExtParams::emission_*`
+// weights (bps):
+//
+//   maker volume   LP fill notional              × emission_volume_weight_bps
+//   open interest  |position| notional × slots   × emission_oi_weight_bps
+//   LP share       LP capital × slots            × emission_lp_weight_bps
+//
+// Time-based points accrue whenever the account is touched (every crank
+// visit, trade, deposit and withdrawal), so a balance is always weighted by
+// how long it was held. Points land in the open epoch's `emission_pending`
+// and move to `emission_claimable` once `emission_epoch_slots` rolls over.
+// A rewards distributor program reads `emissions_of` / `total_claimable_emissions`
+// to size each account's share and calls `claim_emissions` when it pays out.
+// Unclaimed points are forfeited when the account is closed; merging it into
+// another account of the same owner carries them over.
+
+use crate::events::{EventKind, EVENT_NO_ACCOUNT};
+use crate::{mul_div, Account, Result, RiskEngine, RiskError, Rounding, MAX_ACCOUNTS, U128};
+
+/// An account's reward points, brought up to a slot.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EmissionsView {
+    /// Epoch the pending points belong to
+    pub epoch: u64,
+    /// Points earned in the open epoch
+    pub pending: u128,
+    /// Points from closed epochs, ready for the distributor
+    pub claimable: u128,
+}
+
+impl RiskEngine {
+    /// Emission epoch containing `slot` (0 while emissions are disabled).
+    pub fn emission_epoch(&self, slot: u64) -> u64 {
+        slot.checked_div(self.ext_params.emission_epoch_slots).unwrap_or(0)
+    }
+
+    /// Reward points of `idx` with time-based accrual brought up to `now_slot`.
+    pub fn emissions_of(&self, idx: u16, now_slot: u64) -> Result<EmissionsView> {
+        if !self.is_used(idx as usize) {
+            return Err(RiskError::AccountNotFound);
+        }
+        Ok(self.emissions_at(&self.accounts[idx as usize], now_slot))
+    }
+
+    /// Claimable points across all accounts at `now_slot`, the denominator a
+    /// distributor splits an epoch's rewards by.
+    pub fn total_claimable_emissions(&self, now_slot: u64) -> u128 {
+        (0..MAX_ACCOUNTS)
+            .filter(|&idx| self.is_used(idx))
+            .fold(0u128, |total, idx| {
+                total.saturating_add(self.emissions_at(&self.accounts[idx], now_slot).claimable)
+            })
+    }
+
+    /// Settle `idx`'s claimable points for the rewards distributor: returns them
+    /// and resets the balance. Points of the open epoch stay pending.
+    pub fn claim_emissions(&mut self, idx: u16, now_slot: u64) -> Result<u128> {
+        let result = self.claim_emissions_inner(idx, now_slot);
+        if let Ok(points) = result {
+            if points > 0 {
+                self.record_event(EventKind::EmissionsClaimed, idx, EVENT_NO_ACCOUNT, points, 0, 0);
+            }
+        }
+        self.finish_mutation("claim_emissions", result.is_ok());
+        result
+    }
+
+    fn claim_emissions_inner(&mut self, idx: u16, now_slot: u64) -> Result<u128> {
+        if !self.is_used(idx as usize) {
+            return Err(RiskError::AccountNotFound);
+        }
+        self.accrue_emissions(idx as usize, now_slot);
+        let account = &mut self.accounts[idx as usize];
+        let points = account.emission_claimable.get();
+        account.emission_claimable = U128::ZERO;
+        Ok(points)
+    }
+
+    /// Bring `idx`'s time-based points up to `now_slot`, rolling pending points
+    /// into claimable when an epoch boundary has passed.
+    pub(crate) fn accrue_emissions(&mut self, idx: usize, now_slot: u64) {
+        let view = self.emissions_at(&self.accounts[idx], now_slot);
+        let account = &mut self.accounts[idx];
+        account.emission_epoch = view.epoch;
+        account.emission_pending = U128::new(view.pending);
+        account.emission_claimable = U128::new(view.claimable);
+        // While disabled the clock still advances, so enabling is never retroactive
+        account.emission_slot = core::cmp::max(account.emission_slot, now_slot);
+    }
+
+    /// Credit maker volume points for `notional` filled by `idx`.
+    pub(crate) fn accrue_volume_emissions(&mut self, idx: usize, notional: u128) {
+        let ext = &self.ext_params;
+        if ext.emission_epoch_slots == 0 || ext.emission_volume_weight_bps == 0 {
+            return;
+        }
+        let weight = ext.emission_volume_weight_bps as u128;
+        let points = mul_div(notional, weight, 10_000, Rounding::Down);
+        let account = &mut self.accounts[idx];
+        account.emission_pending = account.emission_pending.saturating_add(points);
+    }
+
+    /// `account`'s points with time-based accrual projected to `now_slot`.
+    fn emissions_at(&self, account: &Account, now_slot: u64) -> EmissionsView {
+        let stored = EmissionsView {
+            epoch: account.emission_epoch,
+            pending: account.emission_pending.get(),
+            claimable: account.emission_claimable.get(),
+        };
+        let epoch_slots = self.ext_params.emission_epoch_slots;
+        let last = account.emission_slot;
+        if epoch_slots == 0 || now_slot <= last {
+            return stored;
+        }
+        let epoch = self.emission_epoch(now_slot);
+        // Accounts start earning from their first accrual after emissions are enabled
+        if last == 0 {
+            return EmissionsView { epoch, ..stored };
+        }
+        if epoch <= stored.epoch {
+            let earned = self.emission_points(account, now_slot.saturating_sub(last));
+            return EmissionsView { pending: stored.pending.saturating_add(earned), ..stored };
+        }
+        // Points up to the boundary close out the old epoch; the rest are pending
+        let boundary = epoch.saturating_mul(epoch_slots);
+        let closed = self.emission_points(account, boundary.saturating_sub(last));
+        EmissionsView {
+            epoch,
+            pending: self.emission_points(account, now_slot.saturating_sub(boundary)),
+            claimable: stored
+                .claimable
+                .saturating_add(stored.pending)
+                .saturating_add(closed),
+        }
+    }
+
+    /// Time-based points `account` earns over `slots` at its current holdings.
+    fn emission_points(&self, account: &Account, slots: u64) -> u128 {
+        let ext = &self.ext_params;
+        let mut points = 0u128;
+        if ext.emission_oi_weight_bps > 0 && !account.position_size.is_zero() {
+            let abs_pos = account.position_size.unsigned_abs();
+            let notional = self.notional_at(abs_pos, account.entry_price, Rounding::Down);
+            points = points.saturating_add(mul_div(
+                notional.saturating_mul(slots as u128),
+                ext.emission_oi_weight_bps as u128,
+                10_000,
+                Rounding::Down,
+            ));
+        }
+        if ext.emission_lp_weight_bps > 0 && account.is_lp() {
+            points = points.saturating_add(mul_div(
+                account.capital.get().saturating_mul(slots as u128),
+                ext.emission_lp_weight_bps as u128,
+                10_000,
+                Rounding::Down,
+            ));
+        }
+        points
+    }
+}
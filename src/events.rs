@@ -0,0 +1,243 @@
+// ============================================================================
+// Structured Event Stream
+// ============================================================================
+//
+// Every state change the engine wants indexers to see is appended to a fixed
+// in-state ring buffer as an `EventRecord` with a monotonically increasing
+// sequence number. Hosts drain new records after each instruction (e.g. into
+// program logs) through the `EventObserver` interface, either as structs or in
+// the compact binary encoding below. A gap in sequence numbers means the ring
+// wrapped before the host drained it.
+//
+// Binary encoding (little-endian, `EVENT_ENCODED_LEN` bytes):
+//   version u8 | kind u8 | seq u64 | slot u64 | account u16 | counterparty u16 |
+//   amount u128 | value i128 | price u64
+//
+// The decoder accepts any version up to `EVENT_VERSION`; fields added by later
+// versions are appended, so decoders must consume exactly `EVENT_ENCODED_LEN`
+// bytes per record of their own version.
+
+use crate::{RiskError, Result, I128, U128};
+
+/// Current event encoding version.
+pub const EVENT_VERSION: u8 = 1;
+
+/// Encoded size of one event record.
+pub const EVENT_ENCODED_LEN: usize = 1 + 1 + 8 + 8 + 2 + 2 + 16 + 16 + 8;
+
+/// Number of records retained in the engine's ring buffer.
+pub const EVENT_LOG_LEN: usize = 64;
+
+/// Number of significant events kept per account (see `RiskEngine::account_activity`).
+pub const ACCOUNT_ACTIVITY_LEN: usize = 4;
+
+/// Sentinel for "no account" in `account` / `counterparty`.
+pub const EVENT_NO_ACCOUNT: u16 = u16::MAX;
+
+/// What changed. Field meanings per kind are listed on each variant.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EventKind {
+    /// Empty ring slot (never emitted)
+    #[default]
+    None = 0,
+    /// account, kind in `value` (0 = user, 1 = LP), fee paid in `amount`
+    AccountOpened = 1,
+    /// account, capital returned in `amount`
+    AccountClosed = 2,
+    /// account, amount
+    Deposit = 3,
+    /// account, amount
+    Withdraw = 4,
+    /// account = user, counterparty = LP, executed size in `value` (user side),
+    /// fee in `amount`, execution price in `price`
+    Trade = 5,
+    /// account, counterparty = keeper (or `EVENT_NO_ACCOUNT`), absolute position closed in
+    /// `value`, fee in `amount`, oracle price in `price`
+    Liquidation = 6,
+    /// account = caller, liquidations in `amount`, funding rate in `value`,
+    /// oracle price in `price`
+    Crank = 7,
+    /// amount
+    InsuranceTopUp = 8,
+    /// No payload
+    ParamsApplied = 9,
+    /// Deviation threshold breached. `amount` 0: crank-to-crank move, account =
+    /// caller, new oracle price in `price`. `amount` 1: execution vs oracle,
+    /// account = user, counterparty = LP, execution price in `price`. Deviation
+    /// in bps in `value`.
+    OracleDeviation = 10,
+    /// Vault corrected to the token account balance: `value` is the signed change
+    /// to `vault` (+ donation absorbed into insurance, − shortfall written off
+    /// insurance), token balance in `amount`
+    VaultReconciled = 11,
+    /// account = destination, counterparty = freed source, capital moved in
+    /// `amount`, source position netted in `value`, oracle price in `price`
+    AccountsMerged = 12,
+    /// amount withdrawn from the insurance fund
+    InsuranceWithdrawn = 13,
+    /// account compensated from the gap-insurance pool: paid in `amount`, claimed
+    /// loss in `value`, oracle price of the reverting crank in `price`
+    GapCompensation = 14,
+    /// account over the max-PnL cap given notice: force-close slot in `amount`,
+    /// unrealized PnL in `value`, oracle price in `price`
+    ForceClosePending = 15,
+    /// account's pending force-close withdrawn (back under the cap or flat),
+    /// oracle price in `price`
+    ForceCloseCancelled = 16,
+    /// account's haircut claim paid from insurance surplus: paid in `amount`, claim
+    /// still outstanding in `value`, oracle price in `price`
+    HaircutClaimPaid = 17,
+    /// account's claimable emission points settled by the rewards distributor:
+    /// points in `amount`
+    EmissionsClaimed = 18,
+    /// LP's margin credit line revoked by a repayment trigger (expiry, insurance at
+    /// the risk-reduction threshold, or unpaid interest): the limit in `amount`
+    CreditLineRevoked = 19,
+    /// account's position closed at the oracle by the crank (force-realize, max-PnL
+    /// cap, ADL-first pre-pass, or a worthless/dust position): position closed in
+    /// `value`, oracle price in `price`
+    ForceClosed = 20,
+}
+
+impl EventKind {
+    pub fn from_u8(val: u8) -> Option<Self> {
+        Some(match val {
+            0 => Self::None,
+            1 => Self::AccountOpened,
+            2 => Self::AccountClosed,
+            3 => Self::Deposit,
+            4 => Self::Withdraw,
+            5 => Self::Trade,
+            6 => Self::Liquidation,
+            7 => Self::Crank,
+            8 => Self::InsuranceTopUp,
+            9 => Self::ParamsApplied,
+            10 => Self::OracleDeviation,
+            11 => Self::VaultReconciled,
+            12 => Self::AccountsMerged,
+            13 => Self::InsuranceWithdrawn,
+            14 => Self::GapCompensation,
+            15 => Self::ForceClosePending,
+            16 => Self::ForceCloseCancelled,
+            17 => Self::HaircutClaimPaid,
+            18 => Self::EmissionsClaimed,
+            19 => Self::CreditLineRevoked,
+            20 => Self::ForceClosed,
+            _ => return None,
+        })
+    }
+}
+
+/// One state-change event.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EventRecord {
+    /// Sequence number (1-based; 0 marks an empty ring slot)
+    pub seq: u64,
+    /// Engine slot when the event was recorded
+    pub slot: u64,
+    pub amount: U128,
+    pub value: I128,
+    pub price: u64,
+    pub account: u16,
+    pub counterparty: u16,
+    pub kind: EventKind,
+}
+
+/// Compact copy of a significant event kept on the account itself (see
+/// `Account::activity`); the full record is `RiskEngine::event(seq)` while the
+/// ring still holds it.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ActivityRecord {
+    /// Sequence number in the event stream (0 marks an empty entry)
+    pub seq: u64,
+    /// Engine slot when the event was recorded
+    pub slot: u64,
+    /// Size traded or closed from the account's side (the event's `value`, negated
+    /// for the counterparty of a trade), or the amount withdrawn
+    pub amount: I128,
+    pub kind: EventKind,
+}
+
+impl EventRecord {
+    /// Encode into `out`, returning `EVENT_ENCODED_LEN`.
+    pub fn encode(&self, out: &mut [u8]) -> Result<usize> {
+        let out = out
+            .get_mut(..EVENT_ENCODED_LEN)
+            .ok_or(RiskError::InvalidParams)?;
+        out[0] = EVENT_VERSION;
+        out[1] = self.kind as u8;
+        out[2..10].copy_from_slice(&self.seq.to_le_bytes());
+        out[10..18].copy_from_slice(&self.slot.to_le_bytes());
+        out[18..20].copy_from_slice(&self.account.to_le_bytes());
+        out[20..22].copy_from_slice(&self.counterparty.to_le_bytes());
+        out[22..38].copy_from_slice(&self.amount.get().to_le_bytes());
+        out[38..54].copy_from_slice(&self.value.get().to_le_bytes());
+        out[54..62].copy_from_slice(&self.price.to_le_bytes());
+        Ok(EVENT_ENCODED_LEN)
+    }
+}
+
+/// Decode one record from the front of `bytes`.
+pub fn decode(bytes: &[u8]) -> Result<EventRecord> {
+    let b = bytes
+        .get(..EVENT_ENCODED_LEN)
+        .ok_or(RiskError::InvalidParams)?;
+    if b[0] == 0 || b[0] > EVENT_VERSION {
+        return Err(RiskError::InvalidParams);
+    }
+    let kind = EventKind::from_u8(b[1]).ok_or(RiskError::InvalidParams)?;
+    let mut u64_buf = [0u8; 8];
+    let mut u128_buf = [0u8; 16];
+    let mut rd_u64 = |r: &[u8]| {
+        u64_buf.copy_from_slice(r);
+        u64::from_le_bytes(u64_buf)
+    };
+    let seq = rd_u64(&b[2..10]);
+    let slot = rd_u64(&b[10..18]);
+    let price = rd_u64(&b[54..62]);
+    u128_buf.copy_from_slice(&b[22..38]);
+    let amount = U128::new(u128::from_le_bytes(u128_buf));
+    u128_buf.copy_from_slice(&b[38..54]);
+    let value = I128::new(i128::from_le_bytes(u128_buf));
+    Ok(EventRecord {
+        seq,
+        slot,
+        amount,
+        value,
+        price,
+        account: u16::from_le_bytes([b[18], b[19]]),
+        counterparty: u16::from_le_bytes([b[20], b[21]]),
+        kind,
+    })
+}
+
+/// Iterator over a buffer of back-to-back encoded records.
+///
+/// Stops at the first malformed or truncated record.
+pub struct EventDecoder<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> EventDecoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+}
+
+impl<'a> Iterator for EventDecoder<'a> {
+    type Item = EventRecord;
+
+    fn next(&mut self) -> Option<EventRecord> {
+        let record = decode(self.buf).ok()?;
+        self.buf = self.buf.get(EVENT_ENCODED_LEN..).unwrap_or(&[]);
+        Some(record)
+    }
+}
+
+/// Receiver for drained events (see `RiskEngine::drain_events`).
+pub trait EventObserver {
+    fn on_event(&mut self, event: &EventRecord);
+}
@@ -307,7 +307,7 @@ impl I128 {
 
     #[inline]
     pub fn abs(self) -> Self {
-        Self::new(self.get().abs())
+        Self::new(self.get().saturating_abs())
     }
 
     #[inline]
@@ -387,6 +387,96 @@ impl Ord for I128 {
     }
 }
 
+// ============================================================================
+// I128 - Shared helpers (both representations)
+// ============================================================================
+// Conversions and mixed-sign arithmetic used by PnL math, so call sites never
+// need ad-hoc `as i128` casts that silently wrap values above i128::MAX.
+
+impl I128 {
+    /// Convert from u128, saturating at i128::MAX.
+    #[inline]
+    pub const fn from_u128_clamped(val: u128) -> Self {
+        if val > i128::MAX as u128 {
+            Self::MAX
+        } else {
+            Self::new(val as i128)
+        }
+    }
+
+    /// Convert from u128, or None if it exceeds i128::MAX.
+    #[inline]
+    pub fn checked_from_u128(val: u128) -> Option<Self> {
+        i128::try_from(val).ok().map(Self::new)
+    }
+
+    /// Positive part as u128 (negative values yield 0).
+    #[inline]
+    pub fn clamp_pos_u128(self) -> u128 {
+        let v = self.get();
+        if v > 0 {
+            v.unsigned_abs()
+        } else {
+            0
+        }
+    }
+
+    #[inline]
+    pub fn saturating_mul(self, rhs: i128) -> Self {
+        Self::new(self.get().saturating_mul(rhs))
+    }
+
+    #[inline]
+    pub fn saturating_neg(self) -> Self {
+        Self::new(self.get().saturating_neg())
+    }
+
+    /// Add an unsigned amount, saturating at i128::MAX.
+    #[inline]
+    pub fn saturating_add_u128(self, rhs: u128) -> Self {
+        Self::new(self.get().saturating_add_unsigned(rhs))
+    }
+
+    /// Subtract an unsigned amount, saturating at i128::MIN.
+    #[inline]
+    pub fn saturating_sub_u128(self, rhs: u128) -> Self {
+        Self::new(self.get().saturating_sub_unsigned(rhs))
+    }
+
+    /// Compute `self * mul / div` with explicit rounding: `Down` rounds toward
+    /// negative infinity, `Up` toward positive infinity. None on overflow or
+    /// division by zero.
+    #[inline]
+    pub fn checked_mul_div(self, mul: i128, div: i128, rounding: crate::Rounding) -> Option<Self> {
+        let product = self.get().checked_mul(mul)?;
+        Self::div_rounded(product, div, rounding)
+    }
+
+    /// `checked_mul_div` with a saturating product; division by zero yields zero.
+    #[inline]
+    pub fn saturating_mul_div(self, mul: i128, div: i128, rounding: crate::Rounding) -> Self {
+        let product = self.get().saturating_mul(mul);
+        Self::div_rounded(product, div, rounding).unwrap_or(Self::ZERO)
+    }
+
+    #[inline]
+    fn div_rounded(num: i128, div: i128, rounding: crate::Rounding) -> Option<Self> {
+        let q = num.checked_div(div)?;
+        let r = num.checked_rem(div)?;
+        if r == 0 {
+            return Some(Self::new(q));
+        }
+        // Exact quotient is negative iff remainder and divisor signs differ
+        let negative = (r < 0) != (div < 0);
+        let q = match rounding {
+            crate::Rounding::Down if negative => q.checked_sub(1)?,
+            crate::Rounding::Up if !negative => q.checked_add(1)?,
+            _ => q,
+        };
+        Some(Self::new(q))
+    }
+}
+
 // ============================================================================
 // U128 - Kani-optimized version (transparent newtype)
 // ============================================================================
@@ -833,7 +923,22 @@ impl core::ops::Mul<U128> for U128 {
     }
 }
 
+impl U128 {
+    #[inline]
+    pub fn checked_add_u128(self, rhs: U128) -> Option<Self> {
+        self.checked_add(rhs.get())
+    }
+
+    #[inline]
+    pub fn checked_sub_u128(self, rhs: U128) -> Option<Self> {
+        self.checked_sub(rhs.get())
+    }
+}
+
+// Division and compound assignment keep primitive semantics (division by zero
+// panics). Engine code uses the `checked_*` methods and returns `Overflow`.
 #[cfg(not(kani))]
+#[allow(clippy::arithmetic_side_effects)]
 impl core::ops::Div<u128> for U128 {
     type Output = Self;
     fn div(self, rhs: u128) -> Self {
@@ -842,6 +947,7 @@ impl core::ops::Div<u128> for U128 {
 }
 
 #[cfg(not(kani))]
+#[allow(clippy::arithmetic_side_effects)]
 impl core::ops::Div<U128> for U128 {
     type Output = Self;
     fn div(self, rhs: U128) -> Self {
@@ -850,6 +956,7 @@ impl core::ops::Div<U128> for U128 {
 }
 
 #[cfg(not(kani))]
+#[allow(clippy::arithmetic_side_effects)]
 impl core::ops::AddAssign<u128> for U128 {
     fn add_assign(&mut self, rhs: u128) {
         *self = *self + rhs;
@@ -857,6 +964,7 @@ impl core::ops::AddAssign<u128> for U128 {
 }
 
 #[cfg(not(kani))]
+#[allow(clippy::arithmetic_side_effects)]
 impl core::ops::SubAssign<u128> for U128 {
     fn sub_assign(&mut self, rhs: u128) {
         *self = *self - rhs;
@@ -908,11 +1016,12 @@ impl core::ops::Mul<i128> for I128 {
 impl core::ops::Neg for I128 {
     type Output = Self;
     fn neg(self) -> Self {
-        Self::new(-self.get())
+        Self::new(self.get().saturating_neg())
     }
 }
 
 #[cfg(not(kani))]
+#[allow(clippy::arithmetic_side_effects)]
 impl core::ops::AddAssign<i128> for I128 {
     fn add_assign(&mut self, rhs: i128) {
         *self = *self + rhs;
@@ -920,6 +1029,7 @@ impl core::ops::AddAssign<i128> for I128 {
 }
 
 #[cfg(not(kani))]
+#[allow(clippy::arithmetic_side_effects)]
 impl core::ops::SubAssign<i128> for I128 {
     fn sub_assign(&mut self, rhs: i128) {
         *self = *self - rhs;
@@ -0,0 +1,89 @@
+// ============================================================================
+// IDL Metadata (off-chain, requires the `idl` feature)
+// ============================================================================
+//
+// Anchor-style IDL fragments generated from the engine's own definitions, so
+// TypeScript clients decode custom program errors and enum discriminants from
+// the same source the program was built from. Merge the output into the
+// wrapper program's IDL (`errors` and `types` keys).
+
+// Off-chain only: the panic-free arithmetic policy does not apply here
+#![allow(clippy::arithmetic_side_effects)]
+
+use std::fmt::Write;
+use std::string::String;
+use std::vec::Vec;
+
+use crate::{AccountKind, ClosePriceSource, EventKind, PayoffMode, RiskError};
+
+/// IDL `errors` array: `[{"code": 6000, "name": "...", "msg": "..."}, ...]`.
+pub fn idl_errors() -> String {
+    let entries: Vec<String> = RiskError::ALL
+        .iter()
+        .map(|e| {
+            std::format!(
+                "{{\"code\":{},\"name\":\"{}\",\"msg\":\"{}\"}}",
+                e.code(),
+                e.name(),
+                escape(e.message())
+            )
+        })
+        .collect();
+    std::format!("[{}]", entries.join(","))
+}
+
+/// IDL `types` array with the engine's public `u8` enums.
+pub fn idl_types() -> String {
+    let event_kinds: Vec<String> = (0..=u8::MAX)
+        .map_while(EventKind::from_u8)
+        .map(|k| std::format!("{:?}", k))
+        .collect();
+    let payoff_modes: Vec<String> = (0..=u8::MAX)
+        .map_while(PayoffMode::from_u8)
+        .map(|m| std::format!("{:?}", m))
+        .collect();
+    let close_price_sources: Vec<String> = (0..=u8::MAX)
+        .map_while(ClosePriceSource::from_u8)
+        .map(|m| std::format!("{:?}", m))
+        .collect();
+    let account_kinds: Vec<String> = [AccountKind::User, AccountKind::LP]
+        .iter()
+        .map(|k| std::format!("{:?}", k))
+        .collect();
+    std::format!(
+        "[{},{},{},{}]",
+        enum_type("EventKind", &event_kinds),
+        enum_type("PayoffMode", &payoff_modes),
+        enum_type("ClosePriceSource", &close_price_sources),
+        enum_type("AccountKind", &account_kinds)
+    )
+}
+
+/// `{"errors": [...], "types": [...]}`
+pub fn idl_metadata() -> String {
+    std::format!("{{\"errors\":{},\"types\":{}}}", idl_errors(), idl_types())
+}
+
+fn enum_type(name: &str, variants: &[String]) -> String {
+    let mut out = std::format!("{{\"name\":\"{}\",\"type\":{{\"kind\":\"enum\",\"variants\":[", name);
+    for (i, v) in variants.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "{{\"name\":\"{}\"}}", v);
+    }
+    out.push_str("]}}");
+    out
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,179 @@
+// ============================================================================
+// Keeper Planning (off-chain, requires the `std` feature)
+// ============================================================================
+//
+// Given a snapshot of the engine, work out what a keeper should submit this
+// slot: the crank calls needed to finish the current sweep (each simulated on
+// a copy of the engine, so outcome sizes are the ones the crank will actually
+// produce at this price), the accounts below maintenance margin in the order
+// they should be liquidated, and how aggressively to bid for block space.
+
+// Off-chain only: the panic-free arithmetic policy does not apply here
+#![allow(clippy::arithmetic_side_effects)]
+
+use std::boxed::Box;
+use std::vec::Vec;
+
+use crate::{
+    saturating_abs_i128, CrankOutcome, Result, RiskEngine, RiskError, Rounding,
+    ACCOUNTS_PER_CRANK, MAX_ACCOUNTS, MAX_ORACLE_PRICE,
+};
+
+/// How urgently the keeper's transactions should land.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum KeeperPriority {
+    /// Nothing pending beyond routine cranking
+    #[default]
+    Low,
+    /// Crank is due within half the staleness window
+    Normal,
+    /// Accounts are liquidatable
+    High,
+    /// Trading is blocked by a stale crank, or losses are reaching insurance
+    Urgent,
+}
+
+impl KeeperPriority {
+    /// Multiplier applied to the operator's base priority fee.
+    pub fn fee_multiplier(self) -> u64 {
+        match self {
+            Self::Low => 1,
+            Self::Normal => 2,
+            Self::High => 5,
+            Self::Urgent => 10,
+        }
+    }
+
+    /// Suggested compute-unit price for a base price in micro-lamports.
+    pub fn priority_fee_hint(self, base_micro_lamports: u64) -> u64 {
+        base_micro_lamports.saturating_mul(self.fee_multiplier())
+    }
+}
+
+/// One `keeper_crank` call and the outcome it is expected to produce.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CrankCall {
+    /// Crank cursor the call starts from
+    pub start_cursor: u16,
+    pub expected: CrankOutcome,
+}
+
+/// An account below maintenance margin at the planning price.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LiquidationTarget {
+    pub idx: u16,
+    pub position: i128,
+    /// Position notional at the oracle price
+    pub notional: u128,
+    /// Maintenance requirement minus equity (the ranking key)
+    pub margin_shortfall: u128,
+    /// Position size the planned cranks close
+    pub expected_close: u128,
+    /// Equity is exhausted: any further loss falls on insurance
+    pub bad_debt: bool,
+}
+
+/// Everything a keeper should do at one slot, from `plan_keeper_actions`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeeperPlan {
+    /// Cranks to submit in order; the last one completes the sweep
+    pub crank_calls: Vec<CrankCall>,
+    /// Liquidatable accounts, largest shortfall first
+    pub liquidation_targets: Vec<LiquidationTarget>,
+    /// Slots until the crank goes stale and trading is blocked (0 = stale now)
+    pub slots_until_stale: u64,
+    /// Liquidation fees the crank caller is expected to earn over all calls
+    pub expected_keeper_fees: u128,
+    pub priority: KeeperPriority,
+}
+
+/// Plan the keeper's work at `now_slot` and `oracle_price`, cranking as
+/// `caller_idx` with `funding_rate_bps_per_slot`.
+pub fn plan_keeper_actions(
+    engine: &RiskEngine,
+    caller_idx: u16,
+    now_slot: u64,
+    oracle_price: u64,
+    funding_rate_bps_per_slot: i64,
+) -> Result<KeeperPlan> {
+    if oracle_price == 0 || oracle_price > MAX_ORACLE_PRICE {
+        return Err(RiskError::Overflow);
+    }
+
+    let mut targets = Vec::new();
+    engine.for_each_used(|idx, account| {
+        let shortfall = engine.liq_priority_score(account, oracle_price);
+        if shortfall == 0 {
+            return;
+        }
+        let position = account.position_size.get();
+        targets.push(LiquidationTarget {
+            idx: idx as u16,
+            position,
+            notional: engine.notional_at(saturating_abs_i128(position) as u128, oracle_price, Rounding::Up),
+            margin_shortfall: shortfall,
+            expected_close: 0,
+            bad_debt: engine.account_equity_mtm_at_oracle(account, oracle_price) == 0,
+        });
+    });
+
+    // Simulate the sweep on a copy; one extra call covers a sweep that
+    // started mid-way through the index space
+    let mut sim = Box::new(engine.clone());
+    let mut plan = KeeperPlan::default();
+    let max_calls = MAX_ACCOUNTS / ACCOUNTS_PER_CRANK as usize + 2;
+    let mut stress = false;
+    for _ in 0..max_calls {
+        let start_cursor = sim.crank_cursor;
+        let expected = sim.keeper_crank(
+            caller_idx,
+            now_slot,
+            oracle_price,
+            funding_rate_bps_per_slot,
+            false,
+            0,
+            0,
+        )?;
+        plan.expected_keeper_fees = plan.expected_keeper_fees.saturating_add(expected.liq_fee_to_keeper);
+        stress |= expected.panic_needed || expected.force_realize_needed;
+        plan.crank_calls.push(CrankCall {
+            start_cursor,
+            expected,
+        });
+        if expected.sweep_complete {
+            break;
+        }
+    }
+
+    for t in targets.iter_mut() {
+        let idx = t.idx as usize;
+        let after = if sim.is_used(idx) {
+            sim.accounts[idx].position_size.get()
+        } else {
+            0
+        };
+        let before_abs = saturating_abs_i128(t.position) as u128;
+        t.expected_close = if after.signum() == t.position.signum() {
+            before_abs.saturating_sub(saturating_abs_i128(after) as u128)
+        } else {
+            before_abs
+        };
+    }
+    targets.sort_by(|a, b| b.margin_shortfall.cmp(&a.margin_shortfall).then(a.idx.cmp(&b.idx)));
+
+    plan.slots_until_stale = engine
+        .last_crank_slot
+        .saturating_add(engine.max_crank_staleness_slots)
+        .saturating_sub(now_slot);
+    plan.priority = if plan.slots_until_stale == 0 || stress || targets.iter().any(|t| t.bad_debt) {
+        KeeperPriority::Urgent
+    } else if !targets.is_empty() {
+        KeeperPriority::High
+    } else if plan.slots_until_stale <= engine.max_crank_staleness_slots / 2 {
+        KeeperPriority::Normal
+    } else {
+        KeeperPriority::Low
+    };
+    plan.liquidation_targets = targets;
+    Ok(plan)
+}
@@ -0,0 +1,434 @@
+// ============================================================================
+// Pooled LP Holdings
+// ============================================================================
+//
+// An LP account's shares (see `RiskEngine::lp_shares`) can be held by
+// depositors other than its owner, so several parties pool capital behind one
+// LP and a late depositor buys in at the current NAV per share instead of
+// diluting the fees and inventory PnL already accrued. Each outside
+// depositor's balance is an `LpHolding` record in a fixed engine table shared
+// by all LPs; the owner holds the rest of the supply. Each LP may use at most
+// `MAX_LP_HOLDINGS_PER_LP` records, and `ExtParams::lp_min_holding` keeps dust
+// deposits from taking them.
+//
+// Depositors enter through `deposit_lp_shares` and leave through
+// `redeem_lp_shares`, or, when the market has an LP withdrawal queue, through
+// `request_lp_redemption` and `claim_lp_redemption`: queued holder shares are
+// released at epoch boundaries together with the owner's. The owner's own exits
+// (`withdraw`, `withdraw_lp_shares`, the withdrawal queue) are capped at the
+// owner's balance, and the account cannot be closed while outside depositors
+// hold value in it. Records go away with the shares and any released capital:
+// when the LP's NAV reaches zero and its supply resets, or when the account slot
+// is freed.
+
+use crate::events::{EventKind, EVENT_NO_ACCOUNT};
+use crate::{
+    effective_lp_supply, mul_div, LpShares, Result, RiskEngine, RiskError, Rounding, U128,
+};
+
+/// Outside-depositor records, across all LP accounts.
+pub const MAX_LP_HOLDINGS: usize = 64;
+
+/// Outside-depositor records one LP account may use, so no single LP's
+/// depositors can take the whole table.
+pub const MAX_LP_HOLDINGS_PER_LP: usize = 16;
+
+/// Shares of an LP account held by a depositor other than its owner.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LpHolding {
+    pub holder: [u8; 32],
+    /// Shares held (0, with nothing claimable, marks a free record)
+    pub shares: U128,
+    /// Shares queued for release at the next LP withdrawal epoch
+    pub queued: U128,
+    /// Released capital awaiting `claim_lp_redemption`
+    pub claimable: U128,
+    pub lp_idx: u16,
+}
+
+impl LpHolding {
+    fn is_free(&self) -> bool {
+        self.shares.is_zero() && self.claimable.is_zero()
+    }
+}
+
+impl RiskEngine {
+    /// Shares of LP `lp_idx` held by `holder`; for the LP's owner, the supply not
+    /// held by outside depositors.
+    pub fn lp_holder_shares(&self, lp_idx: u16, holder: [u8; 32]) -> Result<u128> {
+        let info = self.lp_shares(lp_idx)?;
+        Ok(self.holder_balance(lp_idx, holder, &info))
+    }
+
+    /// Deposit `amount` into LP `lp_idx` for `holder`, minting shares to the holder
+    /// at the current NAV per share. As with `deposit`, a transfer-fee mint
+    /// credits only the net amount. An outside holding must be worth at least
+    /// `ExtParams::lp_min_holding` afterwards (InvalidParams), and a new holder
+    /// needs a free record within the LP's allowance (Overflow). Returns the
+    /// shares minted.
+    pub fn deposit_lp_shares(
+        &mut self,
+        lp_idx: u16,
+        holder: [u8; 32],
+        amount: u128,
+        now_slot: u64,
+    ) -> Result<u128> {
+        let received = self.transfer_net_amount(amount);
+        let result = self.deposit_lp_shares_inner(lp_idx, holder, received, now_slot);
+        if result.is_ok() {
+            self.record_event(EventKind::Deposit, lp_idx, EVENT_NO_ACCOUNT, received, 0, 0);
+        }
+        self.finish_mutation("deposit_lp_shares", result.is_ok());
+        result
+    }
+
+    fn deposit_lp_shares_inner(
+        &mut self,
+        lp_idx: u16,
+        holder: [u8; 32],
+        received: u128,
+        now_slot: u64,
+    ) -> Result<u128> {
+        let info = self.lp_shares(lp_idx)?;
+        let slot = if holder == self.accounts[lp_idx as usize].owner {
+            None
+        } else {
+            let slot = match self.lp_holding_slot(lp_idx, holder) {
+                Some(slot) => slot,
+                None if self.lp_holding_count(lp_idx) >= MAX_LP_HOLDINGS_PER_LP => {
+                    return Err(RiskError::Overflow);
+                }
+                None => self.free_lp_holding_slot().ok_or(RiskError::Overflow)?,
+            };
+            let held = info.value_of(self.holder_balance(lp_idx, holder, &info));
+            if held.saturating_add(received) < self.ext_params.lp_min_holding.get() {
+                return Err(RiskError::InvalidParams);
+            }
+            Some(slot)
+        };
+
+        self.deposit_inner(lp_idx, received, now_slot)?;
+        let minted = self.mint_lp_shares(lp_idx, received);
+        self.extend_lp_lockup(lp_idx, now_slot);
+        if let Some(slot) = slot {
+            // Read after minting: a supply reset clears the LP's records
+            let holding = &mut self.lp_holdings[slot];
+            holding.holder = holder;
+            holding.lp_idx = lp_idx;
+            holding.shares = holding.shares.saturating_add(minted);
+        }
+        Ok(minted)
+    }
+
+    /// Redeem `holder`'s `shares` of LP `lp_idx` at the current NAV, withdrawing
+    /// their value less any early-exit fee, which goes to the other LPs. Subject to the same margin,
+    /// utilization and queue rules as `withdraw`: with an LP withdrawal queue,
+    /// depositors use `request_lp_redemption` instead. Returns the amount withdrawn.
+    pub fn redeem_lp_shares(
+        &mut self,
+        lp_idx: u16,
+        holder: [u8; 32],
+        shares: u128,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<u128> {
+        let result = self.redeem_lp_shares_inner(lp_idx, holder, shares, now_slot, oracle_price);
+        if let Ok(amount) = result {
+            self.record_event(EventKind::Withdraw, lp_idx, EVENT_NO_ACCOUNT, amount, 0, 0);
+        }
+        self.finish_mutation("redeem_lp_shares", result.is_ok());
+        result
+    }
+
+    fn redeem_lp_shares_inner(
+        &mut self,
+        lp_idx: u16,
+        holder: [u8; 32],
+        shares: u128,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<u128> {
+        let info = self.lp_shares(lp_idx)?;
+        let balance = self.holder_balance(lp_idx, holder, &info);
+        if shares > balance {
+            return Err(RiskError::InsufficientBalance);
+        }
+        // A partial redemption may not leave an outside holding below the minimum
+        let remaining = balance.saturating_sub(shares);
+        if remaining > 0
+            && holder != self.accounts[lp_idx as usize].owner
+            && info.value_of(remaining) < self.ext_params.lp_min_holding.get()
+        {
+            return Err(RiskError::InvalidParams);
+        }
+        // Price the shares at the NAV the withdrawal will see
+        self.touch_account_full_inner(lp_idx, now_slot, oracle_price)?;
+        let info = self.lp_shares(lp_idx)?;
+        let burned = core::cmp::min(shares, info.supply);
+        let value = info.value_of(burned);
+        // The withdrawal charges the early-exit fee on top, so together they spend
+        // the shares' value once
+        let amount = self.lp_exit_net_amount(lp_idx as usize, value, now_slot)?;
+
+        // The shares pass to the owner's balance, which the withdrawal burns from
+        let slot = if holder == self.accounts[lp_idx as usize].owner {
+            None
+        } else {
+            self.lp_holding_slot(lp_idx, holder)
+        };
+        let record = slot.map(|s| self.lp_holdings[s]);
+        if let (Some(s), Some(r)) = (slot, record) {
+            self.lp_holdings[s].shares = U128::new(r.shares.get().saturating_sub(shares));
+        }
+        match self.withdraw_inner(lp_idx, amount, now_slot, oracle_price, true) {
+            Ok(_) => {
+                // Burn exactly the redeemed shares; rounding dust stays with the pool
+                self.set_lp_supply(lp_idx as usize, info.supply.saturating_sub(burned));
+                Ok(amount)
+            }
+            Err(e) => {
+                if let (Some(s), Some(r)) = (slot, record) {
+                    self.lp_holdings[s] = r;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Queue `shares` of `holder`'s balance in LP `lp_idx` for release at the next
+    /// withdrawal epoch (`ExtParams::lp_withdrawal_epoch_slots`), alongside the
+    /// owner's `request_lp_withdrawal`. Adds to any shares already queued.
+    pub fn request_lp_redemption(
+        &mut self,
+        lp_idx: u16,
+        holder: [u8; 32],
+        shares: u128,
+    ) -> Result<()> {
+        let result = self.request_lp_redemption_inner(lp_idx, holder, shares);
+        self.finish_mutation("request_lp_redemption", result.is_ok());
+        result
+    }
+
+    fn request_lp_redemption_inner(
+        &mut self,
+        lp_idx: u16,
+        holder: [u8; 32],
+        shares: u128,
+    ) -> Result<()> {
+        if self.ext_params.lp_withdrawal_epoch_slots == 0 {
+            return Err(RiskError::InvalidParams);
+        }
+        self.lp_shares(lp_idx)?;
+        if holder == self.accounts[lp_idx as usize].owner {
+            return self.request_lp_withdrawal_inner(lp_idx, shares);
+        }
+        let slot = self
+            .lp_holding_slot(lp_idx, holder)
+            .ok_or(RiskError::InsufficientBalance)?;
+        let holding = &mut self.lp_holdings[slot];
+        let queued = holding.queued.get().saturating_add(shares);
+        if queued > holding.shares.get() {
+            return Err(RiskError::InsufficientBalance);
+        }
+        holding.queued = U128::new(queued);
+        Ok(())
+    }
+
+    /// Cancel `holder`'s queued redemption from LP `lp_idx`; already released
+    /// capital stays claimable.
+    pub fn cancel_lp_redemption(&mut self, lp_idx: u16, holder: [u8; 32]) -> Result<()> {
+        let result = self.cancel_lp_redemption_inner(lp_idx, holder);
+        self.finish_mutation("cancel_lp_redemption", result.is_ok());
+        result
+    }
+
+    fn cancel_lp_redemption_inner(&mut self, lp_idx: u16, holder: [u8; 32]) -> Result<()> {
+        self.lp_shares(lp_idx)?;
+        if holder == self.accounts[lp_idx as usize].owner {
+            self.accounts[lp_idx as usize].lp_queued_shares = U128::ZERO;
+        } else if let Some(slot) = self.lp_holding_slot(lp_idx, holder) {
+            self.lp_holdings[slot].queued = U128::ZERO;
+        }
+        Ok(())
+    }
+
+    /// Pay out `holder`'s released capital from LP `lp_idx`, returning the amount
+    /// to transfer. The record is freed once it holds no shares.
+    pub fn claim_lp_redemption(&mut self, lp_idx: u16, holder: [u8; 32]) -> Result<u128> {
+        let result = self.claim_lp_redemption_inner(lp_idx, holder);
+        if let Ok(amount) = result {
+            if amount > 0 {
+                self.record_event(EventKind::Withdraw, lp_idx, EVENT_NO_ACCOUNT, amount, 0, 0);
+            }
+        }
+        self.finish_mutation("claim_lp_redemption", result.is_ok());
+        result
+    }
+
+    fn claim_lp_redemption_inner(&mut self, lp_idx: u16, holder: [u8; 32]) -> Result<u128> {
+        if !self.is_used(lp_idx as usize) || !self.accounts[lp_idx as usize].is_lp() {
+            return Err(RiskError::AccountKindMismatch);
+        }
+        if holder == self.accounts[lp_idx as usize].owner {
+            return self.claim_lp_withdrawal_inner(lp_idx);
+        }
+        let Some(slot) = self.lp_holding_slot(lp_idx, holder) else {
+            return Ok(0);
+        };
+        let amount = self.lp_holdings[slot].claimable.get();
+        if amount > self.vault.get() {
+            return Err(RiskError::InsufficientBalance);
+        }
+        self.lp_holdings[slot].claimable = U128::ZERO;
+        if self.lp_holdings[slot].is_free() {
+            self.lp_holdings[slot] = LpHolding::default();
+        }
+        self.lp_claimable_total = self.lp_claimable_total.saturating_sub(amount);
+        self.vault = self.vault.saturating_sub(amount);
+        Ok(amount)
+    }
+
+    /// Shares of LP `idx` queued by outside depositors, each capped at its balance.
+    pub(crate) fn lp_holders_queued(&self, idx: usize) -> u128 {
+        self.lp_holdings
+            .iter()
+            .filter(|h| h.lp_idx as usize == idx && !h.is_free())
+            .fold(0u128, |total, h| {
+                total.saturating_add(core::cmp::min(h.queued.get(), h.shares.get()))
+            })
+    }
+
+    /// Released capital of LP `idx` still owed to outside depositors.
+    pub(crate) fn lp_holders_claimable(&self, idx: usize) -> u128 {
+        self.lp_holdings
+            .iter()
+            .filter(|h| h.lp_idx as usize == idx)
+            .fold(0u128, |total, h| total.saturating_add(h.claimable.get()))
+    }
+
+    /// Release the holders' part of an epoch settlement of LP `idx`: `queued` shares
+    /// were queued in total, of which `burned` are burned for `payout`. Each holder
+    /// takes its pro-rata part, rounded down. Returns the (shares, payout) taken.
+    pub(crate) fn release_lp_holder_queue(
+        &mut self,
+        idx: usize,
+        queued: u128,
+        burned: u128,
+        payout: u128,
+    ) -> (u128, u128) {
+        let (mut burned_total, mut paid_total) = (0u128, 0u128);
+        for holding in self.lp_holdings.iter_mut() {
+            if holding.lp_idx as usize != idx || holding.is_free() {
+                continue;
+            }
+            let own = core::cmp::min(holding.queued.get(), holding.shares.get());
+            if own == 0 {
+                continue;
+            }
+            let burn = mul_div(burned, own, queued, Rounding::Down);
+            let paid = mul_div(payout, own, queued, Rounding::Down);
+            holding.shares = U128::new(holding.shares.get().saturating_sub(burn));
+            holding.queued = U128::new(own.saturating_sub(burn));
+            holding.claimable = holding.claimable.saturating_add(paid);
+            burned_total = burned_total.saturating_add(burn);
+            paid_total = paid_total.saturating_add(paid);
+        }
+        (burned_total, paid_total)
+    }
+
+    /// Shares of LP `idx` held by outside depositors.
+    pub(crate) fn lp_external_shares(&self, idx: usize) -> u128 {
+        self.lp_holdings
+            .iter()
+            .filter(|h| h.lp_idx as usize == idx)
+            .fold(0u128, |total, h| total.saturating_add(h.shares.get()))
+    }
+
+    /// The owner's part of LP `idx`'s `supply`.
+    pub(crate) fn lp_owner_shares(&self, idx: usize, supply: u128) -> u128 {
+        supply.saturating_sub(self.lp_external_shares(idx))
+    }
+
+    /// Whether the owner of LP `idx` can take `debit` out of its capital: the
+    /// shares it burns must come from the owner's balance.
+    pub(crate) fn check_owner_lp_exit(&self, idx: usize, debit: u128) -> Result<()> {
+        if self.lp_external_shares(idx) == 0 {
+            return Ok(());
+        }
+        let nav = self.lp_nav(idx);
+        let supply = effective_lp_supply(self.accounts[idx].lp_shares.get(), nav);
+        if supply == 0 || debit > nav {
+            return Err(RiskError::InsufficientBalance);
+        }
+        let burned = mul_div(supply, debit, nav, Rounding::Up);
+        if burned > self.lp_owner_shares(idx, supply) {
+            return Err(RiskError::InsufficientBalance);
+        }
+        Ok(())
+    }
+
+    /// Store LP `idx`'s share supply; a zero supply drops its depositors' records.
+    pub(crate) fn set_lp_supply(&mut self, idx: usize, supply: u128) {
+        if supply == 0 {
+            self.clear_lp_holdings(idx);
+        }
+        self.accounts[idx].lp_shares = U128::new(supply);
+    }
+
+    /// Drop the shares of every depositor of LP `idx`; released capital stays
+    /// claimable.
+    pub(crate) fn clear_lp_holdings(&mut self, idx: usize) {
+        for holding in self.lp_holdings.iter_mut() {
+            if holding.lp_idx as usize != idx {
+                continue;
+            }
+            holding.shares = U128::ZERO;
+            holding.queued = U128::ZERO;
+            if holding.is_free() {
+                *holding = LpHolding::default();
+            }
+        }
+    }
+
+    /// Fold the record of LP `idx`'s (new) owner into the owner's balance, queue
+    /// and claimable capital.
+    pub(crate) fn absorb_owner_lp_holding(&mut self, idx: u16) {
+        if let Some(slot) = self.lp_holding_slot(idx, self.accounts[idx as usize].owner) {
+            let holding = self.lp_holdings[slot];
+            let account = &mut self.accounts[idx as usize];
+            account.lp_queued_shares =
+                account.lp_queued_shares.saturating_add(holding.queued.get());
+            account.lp_claimable = account.lp_claimable.saturating_add(holding.claimable.get());
+            self.lp_holdings[slot] = LpHolding::default();
+        }
+    }
+
+    fn holder_balance(&self, lp_idx: u16, holder: [u8; 32], info: &LpShares) -> u128 {
+        if info.supply == 0 {
+            return 0;
+        }
+        if holder == self.accounts[lp_idx as usize].owner {
+            return self.lp_owner_shares(lp_idx as usize, info.supply);
+        }
+        self.lp_holding_slot(lp_idx, holder)
+            .map_or(0, |slot| self.lp_holdings[slot].shares.get())
+    }
+
+    fn lp_holding_slot(&self, lp_idx: u16, holder: [u8; 32]) -> Option<usize> {
+        self.lp_holdings
+            .iter()
+            .position(|h| !h.is_free() && h.lp_idx == lp_idx && h.holder == holder)
+    }
+
+    fn lp_holding_count(&self, lp_idx: u16) -> usize {
+        self.lp_holdings
+            .iter()
+            .filter(|h| !h.is_free() && h.lp_idx == lp_idx)
+            .count()
+    }
+
+    fn free_lp_holding_slot(&self) -> Option<usize> {
+        self.lp_holdings.iter().position(LpHolding::is_free)
+    }
+}
@@ -0,0 +1,427 @@
+// ============================================================================
+// Account Migration
+// ============================================================================
+//
+// Moves a user account between engine instances, e.g. when a market is
+// redeployed or split. `export_account` settles the account at the oracle,
+// hands its position to an LP of the source market, removes the account and
+// returns an `AccountExport`; the wrapper transfers `outflow()` tokens to the
+// target vault and signs the encoded export. On the target, the wrapper checks
+// the signature (and that the blob was not imported before), then
+// `import_account` recreates the account with a target LP taking the other side
+// of its position. Positions net to zero on both sides throughout.
+//
+// Blob layout (little-endian): the `ENCODED_LEN`-byte payload signed by
+// `signer`, followed by the 64-byte signature.
+//
+//   version u8 | source_market [32] | signer [32] | exported_slot u64 |
+//   source_account_id u64 | owner [32] | tag [32] | capital u128 | pnl u128 |
+//   reserved_pnl u64 | warmup_started_at_slot u64 | warmup_slope_per_step u128 |
+//   position i128 | entry_price u64 | fee_credits i128 | max_leverage u16 |
+//   reduce_only u8 | position_opened_slot u64 | markout_sum i128 |
+//   markout_notional u128 | haircut_claim u128 | emission_pending u128 |
+//   emission_claimable u128
+//
+// Version 1 blobs end after `markout_notional`; they still decode, with no
+// haircut claim or emission points.
+
+use crate::events::{EventKind, EVENT_NO_ACCOUNT};
+use crate::{
+    empty_account, saturating_abs_i128, Account, AccountKind, ByteReader, ByteWriter, MarkoutStats,
+    Result, RiskEngine, RiskError, I128, MAX_ACCOUNTS, MAX_ORACLE_PRICE, MAX_POSITION_ABS, U128,
+};
+
+/// Current account export encoding version.
+pub const ACCOUNT_EXPORT_VERSION: u8 = 2;
+
+/// Payload size of a version 1 export.
+const ACCOUNT_EXPORT_V1_LEN: usize = AccountExport::ENCODED_LEN - 16 * 3;
+
+/// Length of the wrapper's signature appended to the payload.
+pub const ACCOUNT_EXPORT_SIGNATURE_LEN: usize = 64;
+
+/// A user account in transit between two engines.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AccountExport {
+    /// Market the account left (set by the wrapper, e.g. the source slab address)
+    pub source_market: [u8; 32],
+    /// Key that signs the encoded payload
+    pub signer: [u8; 32],
+    pub exported_slot: u64,
+    /// Account ID in the source engine (the target assigns a fresh one)
+    pub source_account_id: u64,
+    pub owner: [u8; 32],
+    pub tag: [u8; 32],
+    pub capital: U128,
+    /// Positive PnL after the source haircut (losses are settled before export)
+    pub pnl: U128,
+    pub reserved_pnl: u64,
+    pub warmup_started_at_slot: u64,
+    pub warmup_slope_per_step: U128,
+    pub position: I128,
+    /// Oracle price the position was settled at on export
+    pub entry_price: u64,
+    pub fee_credits: I128,
+    pub max_leverage: u16,
+    pub reduce_only: u8,
+    pub position_opened_slot: u64,
+    /// Lifetime markout totals (see `MarkoutStats`)
+    pub markout_sum: I128,
+    pub markout_notional: U128,
+    /// Unpaid haircut claim (see `Account::haircut_claim`)
+    pub haircut_claim: U128,
+    /// Reward points of the source's open emission epoch and its closed ones
+    pub emission_pending: U128,
+    pub emission_claimable: U128,
+}
+
+impl AccountExport {
+    /// Encoded payload size (the signed bytes).
+    pub const ENCODED_LEN: usize = 1 + 32 * 2 + 8 * 2 + 32 * 2 + 16 * 2 + 8 * 2 + 16
+        + 16 + 8 + 16 + 2 + 1 + 8 + 16 + 16 + 16 * 3;
+
+    /// Encoded blob size: payload plus signature.
+    pub const SIGNED_LEN: usize = Self::ENCODED_LEN + ACCOUNT_EXPORT_SIGNATURE_LEN;
+
+    /// Collateral leaving the source vault with the account.
+    pub fn outflow(&self) -> u128 {
+        self.capital.get().saturating_add(self.pnl.get())
+    }
+
+    /// Encode the payload the wrapper signs. Returns the number of bytes written.
+    pub fn encode(&self, out: &mut [u8]) -> Result<usize> {
+        let mut w = ByteWriter { buf: out, pos: 0 };
+        w.put(&[ACCOUNT_EXPORT_VERSION])?;
+        w.put(&self.source_market)?;
+        w.put(&self.signer)?;
+        w.put(&self.exported_slot.to_le_bytes())?;
+        w.put(&self.source_account_id.to_le_bytes())?;
+        w.put(&self.owner)?;
+        w.put(&self.tag)?;
+        w.put(&self.capital.get().to_le_bytes())?;
+        w.put(&self.pnl.get().to_le_bytes())?;
+        w.put(&self.reserved_pnl.to_le_bytes())?;
+        w.put(&self.warmup_started_at_slot.to_le_bytes())?;
+        w.put(&self.warmup_slope_per_step.get().to_le_bytes())?;
+        w.put(&self.position.get().to_le_bytes())?;
+        w.put(&self.entry_price.to_le_bytes())?;
+        w.put(&self.fee_credits.get().to_le_bytes())?;
+        w.put(&self.max_leverage.to_le_bytes())?;
+        w.put(&[self.reduce_only])?;
+        w.put(&self.position_opened_slot.to_le_bytes())?;
+        w.put(&self.markout_sum.get().to_le_bytes())?;
+        w.put(&self.markout_notional.get().to_le_bytes())?;
+        w.put(&self.haircut_claim.get().to_le_bytes())?;
+        w.put(&self.emission_pending.get().to_le_bytes())?;
+        w.put(&self.emission_claimable.get().to_le_bytes())?;
+        Ok(w.pos)
+    }
+
+    /// Encode the payload followed by the wrapper's `signature` over it.
+    pub fn encode_signed(&self, signature: &[u8; 64], out: &mut [u8]) -> Result<usize> {
+        let n = self.encode(out)?;
+        let mut w = ByteWriter { buf: out, pos: n };
+        w.put(signature)?;
+        Ok(w.pos)
+    }
+
+    /// Payload size of an export encoded at `version` (None = unknown version).
+    pub fn payload_len(version: u8) -> Option<usize> {
+        match version {
+            1 => Some(ACCOUNT_EXPORT_V1_LEN),
+            ACCOUNT_EXPORT_VERSION => Some(Self::ENCODED_LEN),
+            _ => None,
+        }
+    }
+
+    /// Decode a signed blob into the export and its signature. The signature is
+    /// not checked here: the wrapper verifies it over the first
+    /// `payload_len(bytes[0])` bytes.
+    pub fn decode_signed(bytes: &[u8]) -> Result<(Self, [u8; 64])> {
+        let len = bytes
+            .first()
+            .and_then(|&version| Self::payload_len(version))
+            .ok_or(RiskError::InvalidParams)?;
+        if bytes.len() < len.saturating_add(ACCOUNT_EXPORT_SIGNATURE_LEN) {
+            return Err(RiskError::InvalidParams);
+        }
+        // Fields a version 1 payload lacks read as zero
+        let mut r = ByteReader { buf: &bytes[..len], pos: 1 };
+        let export = AccountExport {
+            source_market: r.take::<32>(),
+            signer: r.take::<32>(),
+            exported_slot: r.u64(),
+            source_account_id: r.u64(),
+            owner: r.take::<32>(),
+            tag: r.take::<32>(),
+            capital: U128::new(r.u128()),
+            pnl: U128::new(r.u128()),
+            reserved_pnl: r.u64(),
+            warmup_started_at_slot: r.u64(),
+            warmup_slope_per_step: U128::new(r.u128()),
+            position: I128::new(i128::from_le_bytes(r.take::<16>())),
+            entry_price: r.u64(),
+            fee_credits: I128::new(i128::from_le_bytes(r.take::<16>())),
+            max_leverage: u16::from_le_bytes(r.take::<2>()),
+            reduce_only: r.take::<1>()[0],
+            position_opened_slot: r.u64(),
+            markout_sum: I128::new(i128::from_le_bytes(r.take::<16>())),
+            markout_notional: U128::new(r.u128()),
+            haircut_claim: U128::new(r.u128()),
+            emission_pending: U128::new(r.u128()),
+            emission_claimable: U128::new(r.u128()),
+        };
+        let mut signature = [0u8; 64];
+        signature.copy_from_slice(&bytes[len..len.saturating_add(ACCOUNT_EXPORT_SIGNATURE_LEN)]);
+        Ok((export, signature))
+    }
+}
+
+impl RiskEngine {
+    /// Export user account `idx` for migration and remove it from this engine.
+    ///
+    /// The account is fully settled at `oracle_price` first and must not carry an
+    /// unpaid loss. An open position is taken over by LP `lp_idx`, which must stay
+    /// above maintenance margin. Positive PnL leaves at its haircut value; the
+    /// vault is debited by `AccountExport::outflow`.
+    pub fn export_account(
+        &mut self,
+        idx: u16,
+        lp_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+        source_market: [u8; 32],
+        signer: [u8; 32],
+    ) -> Result<AccountExport> {
+        let result =
+            self.export_account_inner(idx, lp_idx, now_slot, oracle_price, source_market, signer);
+        if let Ok(export) = &result {
+            self.record_event(
+                EventKind::AccountClosed,
+                idx,
+                EVENT_NO_ACCOUNT,
+                export.outflow(),
+                0,
+                0,
+            );
+        }
+        self.finish_mutation("export_account", result.is_ok());
+        result
+    }
+
+    fn export_account_inner(
+        &mut self,
+        idx: u16,
+        lp_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+        source_market: [u8; 32],
+        signer: [u8; 32],
+    ) -> Result<AccountExport> {
+        let i = idx as usize;
+        if i >= MAX_ACCOUNTS || !self.is_used(i) {
+            return Err(RiskError::AccountNotFound);
+        }
+        if !self.accounts[i].is_user() {
+            return Err(RiskError::AccountKindMismatch);
+        }
+        if oracle_price == 0 || oracle_price > MAX_ORACLE_PRICE {
+            return Err(RiskError::Overflow);
+        }
+
+        self.touch_account_full_inner(idx, now_slot, oracle_price)?;
+        self.accrue_emissions(i, now_slot);
+        let account = self.accounts[i];
+        if account.pnl.is_negative() {
+            return Err(RiskError::Undercollateralized);
+        }
+
+        // The LP inherits the position, so the book still nets to zero
+        let position = account.position_size.get();
+        if position != 0 {
+            self.assume_position(lp_idx, position, now_slot, oracle_price)?;
+        }
+
+        let pnl = self.effective_pos_pnl(account.pnl.get());
+        let export = AccountExport {
+            source_market,
+            signer,
+            exported_slot: now_slot,
+            source_account_id: account.account_id,
+            owner: account.owner,
+            tag: account.tag,
+            capital: account.capital,
+            pnl: U128::new(pnl),
+            reserved_pnl: core::cmp::min(account.reserved_pnl as u128, pnl) as u64,
+            warmup_started_at_slot: account.warmup_started_at_slot,
+            warmup_slope_per_step: account.warmup_slope_per_step,
+            position: account.position_size,
+            entry_price: if position != 0 { oracle_price } else { 0 },
+            fee_credits: account.fee_credits,
+            max_leverage: account.max_leverage,
+            reduce_only: account.reduce_only,
+            position_opened_slot: account.position_opened_slot,
+            markout_sum: account.markout.markout_sum,
+            markout_notional: account.markout.notional_sum,
+            haircut_claim: account.haircut_claim,
+            emission_pending: account.emission_pending,
+            emission_claimable: account.emission_claimable,
+        };
+
+        self.set_capital(i, 0);
+        self.set_pnl(i, 0);
+        self.total_open_interest =
+            self.total_open_interest.saturating_sub(position.unsigned_abs());
+        self.vault = self.vault.saturating_sub(export.outflow());
+        self.free_slot(idx);
+        Ok(export)
+    }
+
+    /// Recreate an exported account in this engine (the wrapper has verified the
+    /// blob's signature and moved `export.outflow()` tokens into the vault).
+    ///
+    /// An open position is entered at `oracle_price` with LP `lp_idx` taking the
+    /// other side; both must end above maintenance margin. Returns the new index.
+    pub fn import_account(
+        &mut self,
+        export: &AccountExport,
+        lp_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<u16> {
+        let result = self.import_account_inner(export, lp_idx, now_slot, oracle_price);
+        if let Ok(idx) = result {
+            self.record_event(EventKind::AccountOpened, idx, EVENT_NO_ACCOUNT, 0, 0, 0);
+        }
+        self.finish_mutation("import_account", result.is_ok());
+        result
+    }
+
+    fn import_account_inner(
+        &mut self,
+        export: &AccountExport,
+        lp_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<u16> {
+        if oracle_price == 0 || oracle_price > MAX_ORACLE_PRICE {
+            return Err(RiskError::Overflow);
+        }
+        let position = export.position.get();
+        if position.unsigned_abs() > MAX_POSITION_ABS
+            || export.reserved_pnl as u128 > export.pnl.get()
+        {
+            return Err(RiskError::InvalidParams);
+        }
+        if self.num_used_accounts as u64 >= self.params.max_accounts {
+            return Err(RiskError::Overflow);
+        }
+        let pnl = i128::try_from(export.pnl.get()).map_err(|_| RiskError::Overflow)?;
+        self.current_slot = now_slot;
+
+        let mut account = empty_account();
+        account.kind = AccountKind::User;
+        account.reserved_pnl = export.reserved_pnl;
+        account.warmup_started_at_slot = export.warmup_started_at_slot;
+        account.warmup_slope_per_step = export.warmup_slope_per_step;
+        account.position_size = export.position;
+        account.entry_price = if position != 0 { oracle_price } else { 0 };
+        account.funding_index = self.funding_index_qpb_e6;
+        account.holding_fee_index = self.holding_fee_index;
+        account.owner = export.owner;
+        account.tag = export.tag;
+        account.fee_credits = export.fee_credits;
+        account.last_fee_slot = now_slot;
+        account.max_leverage = export.max_leverage;
+        account.reduce_only = export.reduce_only;
+        account.position_opened_slot = if position != 0 { export.position_opened_slot } else { 0 };
+        account.position_modified_slot = if position != 0 { now_slot } else { 0 };
+        account.last_activity_slot = now_slot;
+        account.markout = MarkoutStats {
+            markout_sum: export.markout_sum,
+            notional_sum: export.markout_notional,
+            ..MarkoutStats::default()
+        };
+        // Points keep their split but start accruing on this market's epoch clock
+        account.haircut_claim = export.haircut_claim;
+        account.emission_pending = export.emission_pending;
+        account.emission_claimable = export.emission_claimable;
+        account.emission_epoch = self.emission_epoch(now_slot);
+        account.emission_slot = now_slot;
+
+        // Both sides must carry their positions, checked before anything moves. The
+        // haircut is taken before the import's fully backed PnL joins the totals,
+        // which can only understate the account's equity.
+        if position != 0 {
+            let prospective = Account {
+                capital: export.capital,
+                pnl: I128::new(pnl),
+                ..account
+            };
+            if !self.is_above_maintenance_margin_mtm(&prospective, oracle_price) {
+                return Err(RiskError::Undercollateralized);
+            }
+            self.assume_position(lp_idx, position.saturating_neg(), now_slot, oracle_price)?;
+        }
+
+        let idx = self.alloc_slot()?;
+        account.account_id = self.next_account_id;
+        self.next_account_id = self.next_account_id.saturating_add(1);
+        self.accounts[idx as usize] = account;
+        self.set_capital(idx as usize, export.capital.get());
+        self.set_pnl(idx as usize, pnl);
+        self.vault = self.vault.saturating_add(export.outflow());
+        self.total_open_interest =
+            self.total_open_interest.saturating_add(position.unsigned_abs());
+        Ok(idx)
+    }
+
+    /// Add `delta` to LP `lp_idx`'s position at `oracle_price` (settling the LP
+    /// first), keeping the open interest and LP aggregates in step. The LP must
+    /// stay above maintenance margin; that is checked before the position moves.
+    fn assume_position(
+        &mut self,
+        lp_idx: u16,
+        delta: i128,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<()> {
+        let l = lp_idx as usize;
+        if l >= MAX_ACCOUNTS || !self.is_used(l) {
+            return Err(RiskError::AccountNotFound);
+        }
+        if !self.accounts[l].is_lp() {
+            return Err(RiskError::NotAnLPAccount);
+        }
+        self.touch_account_full_inner(lp_idx, now_slot, oracle_price)?;
+
+        let old_pos = self.accounts[l].position_size.get();
+        let new_pos = old_pos.checked_add(delta).ok_or(RiskError::Overflow)?;
+        let (old_abs, new_abs) = (saturating_abs_i128(old_pos), saturating_abs_i128(new_pos));
+        if new_abs as u128 > MAX_POSITION_ABS {
+            return Err(RiskError::Overflow);
+        }
+        let mut lp = self.accounts[l];
+        lp.position_size = I128::new(new_pos);
+        lp.entry_price = oracle_price;
+        if new_pos != 0 && !self.is_above_maintenance_margin_mtm(&lp, oracle_price) {
+            return Err(RiskError::Undercollateralized);
+        }
+
+        self.total_open_interest = self
+            .total_open_interest
+            .saturating_sub(old_abs as u128)
+            .saturating_add(new_abs as u128);
+        self.net_lp_pos = self.net_lp_pos.saturating_add(delta);
+        self.lp_sum_abs = self
+            .lp_sum_abs
+            .saturating_sub(old_abs as u128)
+            .saturating_add(new_abs as u128);
+        let account = &mut self.accounts[l];
+        account.position_size = I128::new(new_pos);
+        account.entry_price = oracle_price;
+        account.record_position_change(old_pos, new_pos, now_slot);
+        account.last_activity_slot = now_slot;
+        Ok(())
+    }
+}
@@ -17,10 +17,16 @@
 
 #![no_std]
 #![forbid(unsafe_code)]
+// Panic-free arithmetic policy: every operator that can overflow, underflow or divide
+// by zero must be written as checked/saturating. Enforced with `--features panic-free`.
+#![cfg_attr(feature = "panic-free", deny(clippy::arithmetic_side_effects))]
 
 #[cfg(kani)]
 extern crate kani;
 
+#[cfg(feature = "std")]
+extern crate std;
+
 // ============================================================================
 // Constants
 // ============================================================================
@@ -42,6 +48,9 @@ pub const MAX_ROUNDING_SLACK: u128 = MAX_ACCOUNTS as u128;
 /// Mask for wrapping indices (MAX_ACCOUNTS must be power of 2)
 const ACCOUNT_IDX_MASK: usize = MAX_ACCOUNTS - 1;
 
+/// Most shards a crank sweep can be split into (see `RiskEngine::keeper_crank_shard`)
+pub const MAX_CRANK_SHARDS: usize = 64;
+
 /// Maximum number of dust accounts to close per crank call.
 /// Limits compute usage while still making progress on cleanup.
 pub const GC_CLOSE_BUDGET: u32 = 32;
@@ -54,10 +63,30 @@ pub const ACCOUNTS_PER_CRANK: u16 = 256;
 /// Set to 120 to keep worst-case crank CU under ~50% of Solana limit
 pub const LIQ_BUDGET_PER_CRANK: u16 = 120;
 
+/// Crank phase (see `keeper_crank_phases`): settle maintenance fees, funding, warmup
+/// and haircut claims of the visited accounts
+pub const CRANK_PHASE_SETTLE: u8 = 1 << 0;
+/// Crank phase: liquidations, force-realize and max-PnL closes of the visited
+/// accounts; only cranks running it advance the cursor and the sweep
+pub const CRANK_PHASE_LIQUIDATE: u8 = 1 << 1;
+/// Crank phase: garbage-collect dust accounts
+pub const CRANK_PHASE_GC: u8 = 1 << 2;
+/// Every crank phase (what `keeper_crank` runs)
+pub const CRANK_PHASES_ALL: u8 = CRANK_PHASE_SETTLE | CRANK_PHASE_LIQUIDATE | CRANK_PHASE_GC;
+
 /// Max number of force-realize closes per crank call.
 /// Hard CU bound in force-realize mode. Liquidations are skipped when active.
 pub const FORCE_REALIZE_BUDGET_PER_CRANK: u16 = 32;
 
+/// Default oracle price scale (prices are quote per base unit × 1e6)
+pub const DEFAULT_PRICE_SCALE: u64 = 1_000_000;
+
+/// Scale of LP share prices (NAV per share) and high-water marks
+pub const LP_SHARE_PRICE_SCALE: u128 = 1_000_000;
+
+/// Maximum decimals accepted for `ExtParams::base_decimals` / `quote_decimals`
+pub const MAX_TOKEN_DECIMALS: u8 = 18;
+
 /// Maximum oracle price (prevents overflow in mark_pnl calculations)
 /// 10^15 allows prices up to $1B with 6 decimal places
 pub const MAX_ORACLE_PRICE: u64 = 1_000_000_000_000_000;
@@ -73,6 +102,92 @@ pub const MAX_POSITION_ABS: u128 = 100_000_000_000_000_000_000;
 pub mod i128;
 pub use i128::{I128, U128};
 
+// ============================================================================
+// Event Stream (see src/events.rs)
+// ============================================================================
+pub mod events;
+pub use events::{
+    ActivityRecord, EventKind, EventObserver, EventRecord, ACCOUNT_ACTIVITY_LEN, EVENT_LOG_LEN,
+    EVENT_NO_ACCOUNT,
+};
+
+// ============================================================================
+// Portfolio Margining (see src/portfolio.rs)
+// ============================================================================
+pub mod portfolio;
+pub use portfolio::{portfolio_margin, PortfolioExposure, PortfolioMargin};
+
+// ============================================================================
+// Pyth Oracle Adapter (see src/pyth.rs)
+// ============================================================================
+#[cfg(feature = "pyth")]
+pub mod pyth;
+
+// ============================================================================
+// Switchboard Oracle Adapter (see src/switchboard.rs)
+// ============================================================================
+#[cfg(feature = "switchboard")]
+pub mod switchboard;
+
+// ============================================================================
+// SPL Token Vault Reconciliation (see src/vault.rs)
+// ============================================================================
+pub mod vault;
+pub use vault::{TokenAccount, VaultAction, VaultReconciliation};
+
+// ============================================================================
+// Account Migration (see src/migration.rs)
+// ============================================================================
+pub mod migration;
+pub use migration::AccountExport;
+
+// ============================================================================
+// Engine Snapshots (see src/snapshot.rs)
+// ============================================================================
+pub mod snapshot;
+pub use snapshot::{sha256, verify_snapshot, SnapshotAttestation};
+
+// ============================================================================
+// Liquidity-Mining Emissions (see src/emissions.rs)
+// ============================================================================
+pub mod emissions;
+pub use emissions::EmissionsView;
+
+// ============================================================================
+// Market-Maker Credit Lines (see src/credit.rs)
+// ============================================================================
+pub mod credit;
+
+// ============================================================================
+// Pooled LP Holdings (see src/lp_pool.rs)
+// ============================================================================
+pub mod lp_pool;
+pub use lp_pool::{LpHolding, MAX_LP_HOLDINGS, MAX_LP_HOLDINGS_PER_LP};
+
+// ============================================================================
+// Read Replicas (see src/replica.rs)
+// ============================================================================
+pub mod replica;
+pub use replica::{ReplicaAccount, RiskEngineView};
+
+// ============================================================================
+// IDL Metadata (see src/idl.rs)
+// ============================================================================
+#[cfg(feature = "idl")]
+pub mod idl;
+
+// ============================================================================
+// Off-chain Simulation Support (see src/scenario.rs)
+// ============================================================================
+#[cfg(feature = "std")]
+pub mod scenario;
+
+// ============================================================================
+// Keeper Planning (see src/keeper.rs)
+// ============================================================================
+#[cfg(feature = "std")]
+pub mod keeper;
+
 // ============================================================================
 // Core Data Structures
 // ============================================================================
@@ -84,6 +199,11 @@ pub enum AccountKind {
     LP = 1,
 }
 
+/// `Account::reduce_only` flag set by an admin (see `set_reduce_only_by_admin`)
+pub const REDUCE_ONLY_ADMIN: u8 = 1;
+/// `Account::reduce_only` flag set by the account itself (see `set_reduce_only`)
+pub const REDUCE_ONLY_SELF: u8 = 2;
+
 /// Unified account - can be user or LP
 ///
 /// LPs are distinguished by having kind = LP and matcher_program/context set.
@@ -164,6 +284,255 @@ pub struct Account {
     /// Last slot when maintenance fees were settled for this account
     pub last_fee_slot: u64,
 
+    // ========================================
+    // LP PnL Attribution (only meaningful for LP kind)
+    // ========================================
+    /// Lifetime decomposition of this LP's returns
+    pub lp_pnl: LpPnlAttribution,
+
+    // ========================================
+    // LP Shares (only meaningful for LP kind)
+    // ========================================
+    /// Shares outstanding against this LP's net asset value (see `lp_shares`).
+    /// Zero on accounts created before share accounting; bootstrapped at one
+    /// share per unit of NAV on first use.
+    pub lp_shares: U128,
+
+    /// Shares queued for release at the next LP withdrawal epoch
+    pub lp_queued_shares: U128,
+
+    /// Capital released by the queue, awaiting `claim_lp_withdrawal`
+    pub lp_claimable: U128,
+
+    /// Withdrawals before this slot pay `ExtParams::lp_early_exit_fee_bps`
+    pub lp_locked_until_slot: u64,
+
+    /// Highest NAV per share (e6) at which a performance fee was assessed
+    /// (0 = not yet set)
+    pub lp_high_water_mark: U128,
+
+    // ========================================
+    // Markout Tracking
+    // ========================================
+    /// Short-horizon markouts of this account's fills as taker
+    pub markout: MarkoutStats,
+
+    // ========================================
+    // Holding Fee
+    // ========================================
+    /// Holding fee index snapshot (see `RiskEngine::holding_fee_index`)
+    pub holding_fee_index: U128,
+
+    // ========================================
+    // Leverage Cap
+    // ========================================
+    /// Leverage cap this account opted into with `set_max_leverage` (0 = none)
+    pub max_leverage: u16,
+
+    // ========================================
+    // Position Age
+    // ========================================
+    /// Slot the current position was opened (or flipped); 0 when flat
+    pub position_opened_slot: u64,
+
+    /// Slot the position size last changed; 0 when flat
+    pub position_modified_slot: u64,
+
+    // ========================================
+    // Activity
+    // ========================================
+    /// Slot of the account's last deposit, withdrawal or trade (or its creation)
+    pub last_activity_slot: u64,
+
+    // ========================================
+    // Vault Drain
+    // ========================================
+    /// Start slot of the drain epoch `epoch_drain` refers to
+    pub drain_epoch_start: u64,
+
+    /// Value extracted this epoch: withdrawals plus profit realized into capital,
+    /// net of deposits (see `ExtParams::max_epoch_drain`)
+    pub epoch_drain: U128,
+
+    // ========================================
+    // Integrator Metadata
+    // ========================================
+    /// Opaque tag set at creation (`add_user_tagged`/`add_lp_tagged`): client ID,
+    /// strategy label. Never read by the engine.
+    pub tag: [u8; 32],
+
+    // ========================================
+    // Force-Close Grace
+    // ========================================
+    /// Slot from which the crank may force-close this account over the max-PnL cap
+    /// (0 = no force-close pending; see `ExtParams::force_close_grace_slots`)
+    pub force_close_deadline_slot: u64,
+
+    // ========================================
+    // Reduce-Only
+    // ========================================
+    /// Reduce-only flags (`REDUCE_ONLY_ADMIN` | `REDUCE_ONLY_SELF`); while any is set,
+    /// trades may only shrink the position toward zero
+    pub reduce_only: u8,
+
+    // ========================================
+    // Haircut Claims
+    // ========================================
+    /// Profit lost to the haircut at warmup conversion, repaid by the crank from
+    /// insurance surplus (see `ExtParams::haircut_claim_payout_bps`); forfeited on
+    /// close, carried over by `merge_accounts`
+    pub haircut_claim: U128,
+
+    // ========================================
+    // Emissions (see src/emissions.rs)
+    // ========================================
+    /// Slot up to which time-based reward points have been accrued (0 = not yet started)
+    pub emission_slot: u64,
+    /// Emission epoch `emission_pending` belongs to
+    pub emission_epoch: u64,
+    /// Points earned in the current, still open epoch
+    pub emission_pending: U128,
+    /// Points from closed epochs not yet claimed; forfeited on close
+    pub emission_claimable: U128,
+
+    // ========================================
+    // ADL Preference
+    // ========================================
+    /// 1 = the owner asked to be force-realized ahead of other accounts in stress, in
+    /// exchange for `ExtParams::adl_first_fee_discount_bps` (see `set_adl_first`)
+    pub adl_first: u8,
+
+    // ========================================
+    // Credit Line (see src/credit.rs)
+    // ========================================
+    /// Admin-granted margin credit counted toward this LP's margin checks (0 = none)
+    pub credit_limit: U128,
+    /// Interest per slot on the drawn credit, 1e9 scale
+    pub credit_rate_e9: u64,
+    /// First slot the credit line no longer applies
+    pub credit_expiry_slot: u64,
+    /// Slot credit interest has been charged up to
+    pub credit_accrual_slot: u64,
+
+    // ========================================
+    // Conditional Orders
+    // ========================================
+    /// Notional of the account's resting exposure-increasing conditional orders, as
+    /// registered by the order program (see `RiskEngine::set_conditional_notional`)
+    pub conditional_notional: U128,
+
+    // ========================================
+    // Activity Audit Trail
+    // ========================================
+    /// The account's last `ACCOUNT_ACTIVITY_LEN` significant events (trades,
+    /// liquidations, force-closes, large withdrawals); `seq == 0` marks an empty entry
+    pub activity: [ActivityRecord; ACCOUNT_ACTIVITY_LEN],
+}
+
+/// Windowed markouts of an account's taker fills (see `ExtParams::markout_*`).
+///
+/// One fill is sampled at a time: a fill made while another awaits its markout
+/// is not sampled.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MarkoutStats {
+    /// Size of the fill awaiting its markout (0 = none)
+    pub pending_size: I128,
+    /// Execution price of the pending fill
+    pub pending_price: u64,
+    /// Slot of the pending fill
+    pub pending_slot: u64,
+    /// Σ taker PnL of marked-out fills in the window (positive = taker won)
+    pub markout_sum: I128,
+    /// Σ notional of marked-out fills in the window
+    pub notional_sum: U128,
+    /// Start slot of the current window
+    pub window_start_slot: u64,
+}
+
+impl MarkoutStats {
+    /// Average markout in bps of notional (0 without samples)
+    pub fn score_bps(&self) -> i128 {
+        let notional = self.notional_sum.get();
+        if notional == 0 {
+            return 0;
+        }
+        let sum = self.markout_sum.get();
+        let bps = mul_div(sum.unsigned_abs(), 10_000, notional, Rounding::Down);
+        let bps = u128_to_i128_clamped(bps);
+        if sum < 0 {
+            bps.saturating_neg()
+        } else {
+            bps
+        }
+    }
+}
+
+/// Share supply and net asset value of an LP account, from `lp_shares`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LpShares {
+    pub supply: u128,
+    pub nav: u128,
+}
+
+impl LpShares {
+    /// Value of `shares` at the current NAV (rounded down)
+    pub fn value_of(&self, shares: u128) -> u128 {
+        mul_div(shares, self.nav, self.supply, Rounding::Down)
+    }
+
+    /// NAV per share at `LP_SHARE_PRICE_SCALE` (1.0 before any shares exist)
+    pub fn price_e6(&self) -> u128 {
+        if self.supply == 0 {
+            LP_SHARE_PRICE_SCALE
+        } else {
+            mul_div(self.nav, LP_SHARE_PRICE_SCALE, self.supply, Rounding::Down)
+        }
+    }
+}
+
+/// Share supply to price against `nav`: bootstrapped at one share per unit when
+/// unset, and reset when the outstanding shares are worthless.
+fn effective_lp_supply(shares: u128, nav: u128) -> u128 {
+    if nav == 0 {
+        0
+    } else if shares == 0 {
+        nav
+    } else {
+        shares
+    }
+}
+
+/// Lifetime decomposition of an LP account's returns, updated incrementally
+/// wherever value reaches the account.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LpPnlAttribution {
+    /// Trading fee share credited to capital
+    pub fees_earned: U128,
+    /// Execution-vs-oracle PnL on fills (what the LP's quotes captured)
+    pub spread_pnl: I128,
+    /// Mark-to-market PnL on inventory held while the oracle moved
+    pub inventory_pnl: I128,
+    /// Funding received (+) or paid (-)
+    pub funding_pnl: I128,
+    /// Liquidation fee share distributed to this LP
+    pub liquidation_proceeds: U128,
+    /// Early-exit fees paid by other LPs and credited to this one
+    pub exit_fees_received: U128,
+}
+
+impl LpPnlAttribution {
+    /// Sum of all components
+    pub fn total(&self) -> i128 {
+        self.spread_pnl
+            .get()
+            .saturating_add(self.inventory_pnl.get())
+            .saturating_add(self.funding_pnl.get())
+            .saturating_add_unsigned(self.fees_earned.get())
+            .saturating_add_unsigned(self.liquidation_proceeds.get())
+            .saturating_add_unsigned(self.exit_fees_received.get())
+    }
 }
 
 impl Account {
@@ -176,6 +545,128 @@ impl Account {
     pub fn is_user(&self) -> bool {
         matches!(self.kind, AccountKind::User)
     }
+
+    /// Slots since the current position was opened (0 when flat)
+    pub fn position_age(&self, now_slot: u64) -> u64 {
+        if self.position_size.is_zero() {
+            0
+        } else {
+            now_slot.saturating_sub(self.position_opened_slot)
+        }
+    }
+
+    /// Update position age tracking for a change from `old` to `new` at `now_slot`.
+    fn record_position_change(&mut self, old: i128, new: i128, now_slot: u64) {
+        if new == 0 {
+            self.position_opened_slot = 0;
+            self.position_modified_slot = 0;
+            return;
+        }
+        if old == 0 || (old > 0) != (new > 0) {
+            self.position_opened_slot = now_slot;
+        }
+        if old != new {
+            self.position_modified_slot = now_slot;
+        }
+    }
+}
+
+/// Aggregate of the accounts sharing one owner key (see `RiskEngine::owner_exposure`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OwnerExposure {
+    /// Number of accounts
+    pub accounts: u16,
+    /// Σ capital
+    pub capital: u128,
+    /// Σ position notional at the queried price
+    pub notional: u128,
+}
+
+/// Utilization band (see `ExtParams::utilization_soft_bps` / `utilization_hard_bps`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum UtilizationBand {
+    /// Below the soft band
+    #[default]
+    Normal,
+    /// At or above the soft band: LP withdrawals are held back
+    Soft,
+    /// At or above the hard band: risk-reducing trades only
+    Hard,
+}
+
+/// Vault utilization snapshot (see `RiskEngine::vault_utilization`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VaultUtilization {
+    /// Open interest notional (both sides) at the queried price
+    pub requirement: u128,
+    /// Vault balance not already released to LPs for claiming
+    pub available: u128,
+    /// requirement / available, in bps, rounded up (u64::MAX when nothing is available)
+    pub bps: u64,
+    pub band: UtilizationBand,
+}
+
+/// Amounts an account will settle at its next touch (see
+/// `RiskEngine::pending_settlement`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PendingSettlement {
+    /// Funding the account pays (+) or receives (−) at the current funding index;
+    /// funding since the last crank accrues into the index at the next crank
+    pub funding_owed: i128,
+    /// Maintenance fees accrued since the last fee settlement
+    pub maintenance_fee: u128,
+    /// Holding fee accrued since the last settlement
+    pub holding_fee: u128,
+    /// Fee credits available to absorb the fees above before capital is charged
+    pub fee_credits: i128,
+    /// Positive PnL that has warmed up; converts to capital (at the haircut ratio) at
+    /// the next settlement
+    pub vested_pnl: u128,
+    /// Positive PnL still in warmup
+    pub unvested_pnl: u128,
+}
+
+/// Net skew headroom (see `RiskEngine::skew_capacity`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SkewCapacity {
+    /// Largest net LP notional the LP capital can absorb (`ExtParams::skew_gap_move_bps`)
+    pub max_notional: u128,
+    /// Current net LP notional (absolute)
+    pub notional: u128,
+    /// Position units users can still buy before the cap binds
+    pub buy_remaining: u128,
+    /// Position units users can still sell before the cap binds
+    pub sell_remaining: u128,
+}
+
+/// Inventory of one LP against its initial-margin capacity (see `RiskEngine::lp_inventory`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LpInventory {
+    /// Current LP position (positive = long)
+    pub position: i128,
+    /// Notional of `position` at the queried price
+    pub notional: u128,
+    /// Largest notional the LP's MTM equity supports at its initial margin
+    pub max_notional: u128,
+    /// `notional` over `max_notional`, in bps (`u64::MAX` with no capacity and a position)
+    pub utilization_bps: u64,
+    /// Position units users can still buy from the LP (pushing it short)
+    pub buy_remaining: u128,
+    /// Position units users can still sell to the LP (pushing it long)
+    pub sell_remaining: u128,
+}
+
+/// A position older than a query threshold (see `RiskEngine::stale_positions`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StalePosition {
+    pub idx: u16,
+    pub position: i128,
+    pub opened_slot: u64,
+    pub modified_slot: u64,
+    /// Slots since `opened_slot`
+    pub age_slots: u64,
+    /// Integrator tag of the account (see `Account::tag`)
+    pub tag: [u8; 32],
 }
 
 /// Helper to create empty account
@@ -196,6 +687,35 @@ fn empty_account() -> Account {
         owner: [0; 32],
         fee_credits: I128::ZERO,
         last_fee_slot: 0,
+        lp_pnl: LpPnlAttribution::default(),
+        lp_shares: U128::ZERO,
+        lp_queued_shares: U128::ZERO,
+        lp_claimable: U128::ZERO,
+        lp_locked_until_slot: 0,
+        lp_high_water_mark: U128::ZERO,
+        markout: MarkoutStats::default(),
+        holding_fee_index: U128::ZERO,
+        max_leverage: 0,
+        position_opened_slot: 0,
+        position_modified_slot: 0,
+        last_activity_slot: 0,
+        drain_epoch_start: 0,
+        epoch_drain: U128::ZERO,
+        tag: [0; 32],
+        force_close_deadline_slot: 0,
+        reduce_only: 0,
+        haircut_claim: U128::ZERO,
+        emission_slot: 0,
+        emission_epoch: 0,
+        emission_pending: U128::ZERO,
+        emission_claimable: U128::ZERO,
+        adl_first: 0,
+        credit_limit: U128::ZERO,
+        credit_rate_e9: 0,
+        credit_expiry_slot: 0,
+        credit_accrual_slot: 0,
+        conditional_notional: U128::ZERO,
+        activity: [ActivityRecord::default(); ACCOUNT_ACTIVITY_LEN],
     }
 }
 
@@ -210,6 +730,43 @@ pub struct InsuranceFund {
     pub fee_revenue: U128,
 }
 
+/// Cumulative protocol fee revenue by source.
+///
+/// Counts only the protocol's share (what lands in the insurance fund), so the
+/// components sum to `InsuranceFund::fee_revenue` accrued since genesis. LP and
+/// keeper shares are excluded.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RevenueBreakdown {
+    /// Insurance share of trading fees
+    pub trading_fees: U128,
+    /// Liquidation fees net of keeper and distributed LP shares
+    pub liquidation_fees: U128,
+    /// Maintenance fees paid from capital or deposits, plus prepaid fee credits
+    pub maintenance_fees: U128,
+    /// Account opening fees
+    pub new_account_fees: U128,
+    /// Performance fees on LP profit above the high-water mark
+    pub lp_performance_fees: U128,
+    /// Holding fees on user position notional
+    pub holding_fees: U128,
+    /// Interest on drawn LP credit lines
+    pub credit_interest: U128,
+}
+
+impl RevenueBreakdown {
+    pub fn total(&self) -> u128 {
+        self.trading_fees
+            .get()
+            .saturating_add(self.liquidation_fees.get())
+            .saturating_add(self.maintenance_fees.get())
+            .saturating_add(self.new_account_fees.get())
+            .saturating_add(self.lp_performance_fees.get())
+            .saturating_add(self.holding_fees.get())
+            .saturating_add(self.credit_interest.get())
+    }
+}
+
 /// Outcome from oracle_close_position_core helper
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct ClosedOutcome {
@@ -225,6 +782,21 @@ pub struct ClosedOutcome {
     pub position_was_closed: bool,
 }
 
+/// Record of a single liquidation, including how the fee was split across the waterfall.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LiquidationRecord {
+    /// Absolute position size closed by the liquidation
+    pub closed_abs: u128,
+    /// Total liquidation fee charged from the liquidated account's capital
+    pub fee_total: u128,
+    /// Portion of the fee credited to the keeper/liquidator
+    pub fee_to_keeper: u128,
+    /// Portion of the fee credited to the insurance fund
+    pub fee_to_insurance: u128,
+    /// Portion of the fee credited to LP accounts (pro-rata by capital)
+    pub fee_to_lp: u128,
+}
+
 /// Risk engine parameters
 #[repr(C)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -283,1806 +855,8760 @@ pub struct RiskParams {
     /// Prevents dust positions that are uneconomical to maintain or re-liquidate.
     /// Denominated in base units (same scale as position_size.abs()).
     pub min_liquidation_abs: U128,
+
+    // ========================================
+    // Liquidation Fee Waterfall
+    // ========================================
+    /// Share of each liquidation fee paid to the keeper/liquidator (bps of the fee).
+    /// Zero routes the whole fee to the insurance fund.
+    pub liquidation_fee_keeper_share_bps: u64,
+
+    /// Share of each liquidation fee paid to LP accounts pro-rata by capital (bps of the fee).
+    /// Whatever is left after the keeper and LP shares goes to the insurance fund.
+    pub liquidation_fee_lp_share_bps: u64,
 }
 
-/// Main risk engine state - fixed slab with bitmap
-#[repr(C)]
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct RiskEngine {
-    /// Total vault balance (all deposited funds)
-    pub vault: U128,
+/// Number of collateral slots in `ExtParams::collaterals`.
+pub const MAX_COLLATERALS: usize = 4;
 
-    /// Insurance fund
-    pub insurance_fund: InsuranceFund,
+/// Number of points in `ExtParams::fee_curve`.
+pub const MAX_FEE_CURVE_POINTS: usize = 4;
 
-    /// Risk parameters
-    pub params: RiskParams,
+/// One point of the trade-size fee curve: trades of `notional` pay `fee_bps`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FeeCurvePoint {
+    /// Trade notional in collateral units (0 = unused point)
+    pub notional: U128,
+    pub fee_bps: u64,
+}
 
-    /// Current slot (for warmup calculations)
-    pub current_slot: u64,
+/// Risk treatment of one collateral asset in multi-collateral mode.
+///
+/// A slot with an all-zero `oracle` is unused and must be entirely zero.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CollateralConfig {
+    /// Identity (e.g. feed account key) of the oracle pricing this collateral
+    pub oracle: [u8; 32],
+    /// Haircut applied to the collateral's oracle value, in bps
+    pub haircut_bps: u16,
+    /// Cap on total deposits of this collateral, in its units (0 = uncapped)
+    pub deposit_cap: U128,
+}
 
-    /// Global funding index (quote per 1 base, scaled by 1e6)
-    pub funding_index_qpb_e6: I128,
+impl CollateralConfig {
+    /// Whether this slot configures a collateral.
+    #[inline]
+    pub fn is_active(&self) -> bool {
+        self.oracle != [0u8; 32]
+    }
 
-    /// Last slot when funding was accrued
-    pub last_funding_slot: u64,
+    /// Value of `amount` collateral units after the haircut.
+    #[inline]
+    pub fn haircut_value(&self, amount: u128) -> u128 {
+        mul_div(
+            amount,
+            10_000u128.saturating_sub(self.haircut_bps as u128),
+            10_000,
+            Rounding::Down,
+        )
+    }
+}
 
-    /// Funding rate (bps per slot) in effect starting at last_funding_slot.
-    /// This is the rate used for the interval [last_funding_slot, next_accrual).
-    /// Anti-retroactivity: state changes at slot t can only affect funding for slots >= t.
-    pub funding_rate_bps_per_slot_last: i64,
+/// Extended risk parameters for opt-in engine features.
+///
+/// Kept separate from `RiskParams` so new knobs do not change the core parameter
+/// layout. The all-zero value (`Default`) disables every extension.
+///
+/// Serialized with `encode`/`decode`: a small header (version, body length) followed
+/// by the fields in append-only order. Decoding tolerates shorter bodies (missing
+/// fields default to zero) and longer bodies from newer versions (unknown tail is
+/// ignored), so new fields never need a migration tag.
+///
+/// The in-memory size is fixed at `EXT_PARAMS_SIZE` so the `RiskEngine` layout does
+/// not move with each feature: new fields are carved out of the trailing `reserved`
+/// block, shrinking it by the bytes they take. The retired v2 reserved words are kept
+/// in the encoding only, written as zeros and skipped on decode.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExtParams {
+    /// Layout version (0 = unversioned default; normalized to `EXT_PARAMS_VERSION` on set)
+    pub version: u16,
 
     // ========================================
-    // Keeper Crank Tracking
+    // Oracle Failover
     // ========================================
-    /// Last slot when keeper crank was executed
-    pub last_crank_slot: u64,
+    /// Identity (e.g. feed account key) of the primary oracle feed.
+    /// All zeros = accept any primary feed identity.
+    pub primary_oracle: [u8; 32],
 
-    /// Maximum allowed staleness before crank is required (in slots)
-    pub max_crank_staleness_slots: u64,
+    /// Identity of the fallback oracle feed.
+    /// All zeros = failover disabled (primary must be usable).
+    pub fallback_oracle: [u8; 32],
+
+    /// Maximum age of a quote in slots before it is considered stale (0 = never stale)
+    pub oracle_max_staleness_slots: u64,
+
+    /// Maximum confidence interval as bps of price before a quote is rejected (0 = unchecked)
+    pub oracle_max_conf_bps: u64,
+
+    /// Maximum divergence of the fallback price from the primary price, in bps (0 = unchecked)
+    pub oracle_max_divergence_bps: u64,
 
     // ========================================
-    // Open Interest Tracking (O(1))
+    // Parameter Governance
     // ========================================
-    /// Total open interest = sum of abs(position_size) across all accounts
-    /// This measures total risk exposure in the system.
-    pub total_open_interest: U128,
+    /// Delay in slots between queueing a parameter update and it becoming applicable
+    /// (0 = applicable immediately, guardian has no window to veto)
+    pub param_timelock_slots: u64,
 
     // ========================================
-    // O(1) Aggregates (spec §2.2, §4)
+    // Crank Caps (v2)
     // ========================================
-    /// Sum of all account capital: C_tot = Σ C_i
-    /// Maintained incrementally via set_capital() helper.
-    pub c_tot: U128,
+    /// Absolute max-PnL cap used by the crank when its `max_pnl_vault_bps` argument is 0
+    /// (0 = disabled)
+    pub max_pnl_vault_bps: u64,
 
-    /// Sum of all positive PnL: PNL_pos_tot = Σ max(PNL_i, 0)
-    /// Maintained incrementally via set_pnl() helper.
-    pub pnl_pos_tot: U128,
+    /// Open-interest cap used by the crank when its `max_oi_abs` argument is 0 (0 = disabled)
+    pub max_oi_abs: U128,
+
+    /// Clamp on |funding rate| in bps per slot for rates set via the crank (0 = unclamped)
+    pub max_funding_rate_bps_per_slot: u64,
 
     // ========================================
-    // Crank Cursors (bounded scan support)
+    // Precision (v3)
     // ========================================
-    /// Cursor for liquidation scan (wraps around MAX_ACCOUNTS)
-    pub liq_cursor: u16,
+    /// Oracle price scale: prices are quote per base unit × `price_scale`
+    /// (0 = `DEFAULT_PRICE_SCALE`, i.e. e6)
+    pub price_scale: u64,
 
-    /// Cursor for garbage collection scan (wraps around MAX_ACCOUNTS)
-    pub gc_cursor: u16,
+    /// Decimals of the base asset's atomic unit, in which positions are sized
+    pub base_decimals: u8,
 
-    /// Slot when the current full sweep started (step 0 was executed)
-    pub last_full_sweep_start_slot: u64,
+    /// Decimals of the quote (collateral) token's atomic unit
+    pub quote_decimals: u8,
 
-    /// Slot when the last full sweep completed
-    pub last_full_sweep_completed_slot: u64,
+    // ========================================
+    // Payoff (v4)
+    // ========================================
+    /// Contract payoff. For inverse markets `base_decimals` / `quote_decimals`
+    /// still describe the priced pair, so the collateral is the base asset.
+    pub payoff_mode: PayoffMode,
 
-    /// Cursor: index where the next crank will start scanning
-    pub crank_cursor: u16,
+    // ========================================
+    // Market Time Series (v5)
+    // ========================================
+    /// Minimum slots between market samples recorded by the crank
+    /// (0 = recorder disabled, 1 = every crank)
+    pub series_interval_slots: u64,
 
-    /// Index where the current sweep started (for completion detection)
-    pub sweep_start_idx: u16,
+    // ========================================
+    // Liquidation Analytics (v6)
+    // ========================================
+    /// Length in slots of a liquidation analytics epoch (0 = `epoch_slots`, or one
+    /// epoch for the market's lifetime if that is 0 too). Epochs are aligned to
+    /// multiples of this length.
+    pub liq_stats_epoch_slots: u64,
 
     // ========================================
-    // Lifetime Counters (telemetry)
+    // Oracle Deviation Monitor (v7)
     // ========================================
-    /// Total number of liquidations performed (lifetime)
-    pub lifetime_liquidations: u64,
+    /// Length in slots of an oracle deviation window (0 = monitor disabled)
+    pub deviation_window_slots: u64,
 
-    /// Total number of force-realize closes performed (lifetime)
-    pub lifetime_force_realize_closes: u64,
+    /// Alert when consecutive crank prices differ by more than this, in bps (0 = no alert)
+    pub crank_deviation_alert_bps: u64,
+
+    /// Alert when an execution price differs from the oracle by more than this, in bps
+    /// (0 = no alert)
+    pub exec_deviation_alert_bps: u64,
 
     // ========================================
-    // LP Aggregates (O(1) maintained for funding/threshold)
+    // Oracle Source (v8)
     // ========================================
-    /// Net LP position: sum of position_size across all LP accounts
-    /// Updated incrementally in execute_trade and close paths
-    pub net_lp_pos: I128,
+    /// Oracle account format read by `RiskEngine::oracle_account_quote` (see `OracleSource`)
+    pub oracle_source: OracleSource,
 
-    /// Sum of abs(position_size) across all LP accounts
-    /// Updated incrementally in execute_trade and close paths
-    pub lp_sum_abs: U128,
+    // ========================================
+    // Token-2022 Transfer Fee (v9)
+    // ========================================
+    /// Collateral mint transfer fee in bps (Token-2022 `TransferFeeConfig`): `deposit`
+    /// credits the amount net of it (0 = no fee)
+    pub transfer_fee_bps: u16,
 
-    /// Max abs(position_size) across all LP accounts (monotone upper bound)
-    /// Only increases; reset via bounded sweep at sweep completion
-    pub lp_max_abs: U128,
+    /// Cap on the transfer fee per transfer, in collateral units (0 = uncapped)
+    pub transfer_fee_max: u64,
 
-    /// In-progress max abs for current sweep (reset at sweep start, committed at completion)
-    pub lp_max_abs_sweep: U128,
+    // ========================================
+    // LP Withdrawal Queue (v10)
+    // ========================================
+    /// Length of an LP withdrawal epoch in slots (0 = LP withdrawals are immediate).
+    /// When set, LPs queue withdrawals with `request_lp_withdrawal`; the crank releases
+    /// them at epoch boundaries, keeping the capital that margins open positions.
+    pub lp_withdrawal_epoch_slots: u64,
 
     // ========================================
-    // Slab Management
+    // LP Lockup (v11)
     // ========================================
-    /// Occupancy bitmap (4096 bits = 64 u64 words)
-    pub used: [u64; BITMAP_WORDS],
+    /// Slots LP capital stays locked after each deposit (0 = no lockup)
+    pub lp_lockup_slots: u64,
 
-    /// Number of used accounts (O(1) counter, fixes H2: fee bypass TOCTOU)
-    pub num_used_accounts: u16,
+    /// Fee on LP capital withdrawn while locked, in bps, credited to the other LPs
+    /// (0 = locked capital cannot be withdrawn)
+    pub lp_early_exit_fee_bps: u64,
 
-    /// Next account ID to assign (monotonically increasing, never recycled)
-    pub next_account_id: u64,
+    // ========================================
+    // LP Performance Fee (v12)
+    // ========================================
+    /// Protocol fee on LP profit above each LP's high-water mark, in bps (0 = disabled)
+    pub lp_performance_fee_bps: u64,
 
-    /// Freelist head (u16::MAX = none)
-    pub free_head: u16,
+    /// Slots between performance fee assessments, made by the first crank of each epoch
+    /// (0 = `epoch_slots`)
+    pub lp_performance_epoch_slots: u64,
 
+    // ========================================
+    // Utilization Fee (v13)
+    // ========================================
+    /// Vault utilization (`utilization_bps`) above which trades increasing the taker's
+    /// exposure pay a surcharge over `trading_fee_bps` (0 = disabled)
+    pub utilization_fee_kink_bps: u64,
 
-    /// Freelist next pointers
-    pub next_free: [u16; MAX_ACCOUNTS],
+    /// Utilization at which the surcharge reaches `utilization_fee_max_bps`; it rises
+    /// linearly from the kink and is capped beyond (must exceed the kink)
+    pub utilization_fee_full_bps: u64,
 
-    /// Account slab (4096 accounts)
-    pub accounts: [Account; MAX_ACCOUNTS],
-}
+    /// Largest surcharge in bps, added to `trading_fee_bps`
+    pub utilization_fee_max_bps: u64,
 
-// ============================================================================
-// Error Types
-// ============================================================================
+    // ========================================
+    // Markout Surcharge (v14)
+    // ========================================
+    /// Slots after a taker fill at which its markout (taker PnL from the fill price
+    /// to the oracle) is measured (0 = markouts not tracked)
+    pub markout_horizon_slots: u64,
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum RiskError {
-    /// Insufficient balance for operation
-    InsufficientBalance,
+    /// Markout statistics are halved at each window boundary (0 = never decayed)
+    pub markout_window_slots: u64,
 
-    /// Account would become undercollateralized
-    Undercollateralized,
+    /// Average markout, in bps of notional, at or above which a taker is toxic
+    pub markout_toxic_bps: u64,
 
-    /// Unauthorized operation
-    Unauthorized,
+    /// Trading fee surcharge in bps for toxic takers (0 = disabled)
+    pub markout_surcharge_bps: u64,
 
-    /// Invalid matching engine
-    InvalidMatchingEngine,
+    // ========================================
+    // Holding Fee (v15)
+    // ========================================
+    /// Fee per slot on user position notional, as a fraction scaled by 1e9, paid to
+    /// insurance independently of funding (0 = disabled). Accrues through
+    /// `RiskEngine::holding_fee_index` and settles lazily when accounts are touched.
+    pub holding_fee_per_slot_e9: u64,
 
-    /// PNL not yet warmed up
-    PnlNotWarmedUp,
+    // ========================================
+    // Leverage Caps (v16)
+    // ========================================
+    /// Global hard leverage cap on risk-increasing trades, as a multiple of equity
+    /// (0 = margin params only). Raises the effective initial margin to 1 / cap.
+    pub max_leverage: u16,
 
-    /// Arithmetic overflow
-    Overflow,
+    /// Accounts that opt into a leverage cap at or below this (`set_max_leverage`)
+    /// pay reduced trading fees (0 = no discount tier)
+    pub low_leverage_tier: u16,
 
-    /// Account not found
-    AccountNotFound,
+    /// Trading fee discount for the low-leverage tier, in bps of the fee
+    pub low_leverage_fee_discount_bps: u64,
 
-    /// Account is not an LP account
-    NotAnLPAccount,
+    // ========================================
+    // Holding Fee Escalation (v17)
+    // ========================================
+    /// Each full period of this many slots of position age adds the base holding fee
+    /// rate again (0 = flat rate). Age is measured when the fee settles.
+    pub holding_fee_escalation_slots: u64,
 
-    /// Position size mismatch
-    PositionSizeMismatch,
+    /// Cap on the escalated holding fee, as a multiple of the base rate (0 = uncapped)
+    pub holding_fee_max_multiplier: u64,
 
-    /// Account kind mismatch
-    AccountKindMismatch,
-}
+    // ========================================
+    // Liquidation Size (v18)
+    // ========================================
+    /// Minimum partial liquidation size as bps of the position; each liquidation closes at
+    /// least max(`min_liquidation_abs`, this share) (0 = absolute floor only)
+    pub min_liquidation_bps: u64,
 
-pub type Result<T> = core::result::Result<T, RiskError>;
+    // ========================================
+    // Crank Throttle (v19)
+    // ========================================
+    /// Minimum slots between cranks that start a new sweep (0 = one per slot). Cranks
+    /// inside the gap return a skipped no-op outcome; cranks continuing a sweep, or
+    /// running without the liquidation phase, run.
+    pub min_crank_interval_slots: u64,
 
-/// Outcome of a keeper crank operation
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub struct CrankOutcome {
-    /// Whether the crank successfully advanced last_crank_slot
-    pub advanced: bool,
-    /// Slots forgiven for caller's maintenance (50% discount via time forgiveness)
-    pub slots_forgiven: u64,
-    /// Whether caller's maintenance fee settle succeeded (false if undercollateralized)
-    pub caller_settle_ok: bool,
-    /// Whether force-realize mode is active (insurance at/below threshold)
-    pub force_realize_needed: bool,
-    /// Whether panic_settle_all should be called (system in stress)
-    pub panic_needed: bool,
-    /// Number of accounts liquidated during this crank
-    pub num_liquidations: u32,
-    /// Number of liquidation errors (triggers risk_reduction_only)
-    pub num_liq_errors: u16,
-    /// Number of dust accounts garbage collected during this crank
-    pub num_gc_closed: u32,
-    /// Number of positions force-closed during this crank (when force_realize_needed)
-    pub force_realize_closed: u16,
-    /// Number of force-realize errors during this crank
-    pub force_realize_errors: u16,
-    /// Number of positions force-closed due to max PnL cap
-    pub max_pnl_closed: u16,
-    /// Number of max PnL errors during this crank
-    pub max_pnl_errors: u16,
-    /// Whether OI cap is active (total_open_interest > max_oi_abs)
-    pub oi_cap_active: bool,
-    /// Index where this crank stopped (next crank continues from here)
-    pub last_cursor: u16,
-    /// Whether this crank completed a full sweep of all accounts
-    pub sweep_complete: bool,
-}
+    // ========================================
+    // Yield-Bearing Collateral (v20)
+    // ========================================
+    /// Max rise of the collateral exchange rate per update, in bps (0 = rate updates disabled)
+    pub collateral_rate_max_step_bps: u64,
 
-// ============================================================================
-// Math Helpers (Saturating Arithmetic for Safety)
-// ============================================================================
+    // ========================================
+    // Multi-Collateral (v21)
+    // ========================================
+    /// Per-collateral oracle, haircut and deposit cap (unused slots all zero)
+    pub collaterals: [CollateralConfig; MAX_COLLATERALS],
 
-#[inline]
-fn add_u128(a: u128, b: u128) -> u128 {
-    a.saturating_add(b)
-}
+    // ========================================
+    // Quanto (v22)
+    // ========================================
+    /// Decimals of the collateral token of a `PayoffMode::Quanto` market
+    pub quanto_collateral_decimals: u8,
 
-#[inline]
-fn sub_u128(a: u128, b: u128) -> u128 {
-    a.saturating_sub(b)
-}
+    // ========================================
+    // Dormant Account GC (v23)
+    // ========================================
+    /// Slots without deposits, withdrawals or trades after which the crank also closes a
+    /// flat, zero-capital account holding residual PnL, claiming the residual (after
+    /// haircut) for insurance (0 = disabled)
+    pub gc_inactive_slots: u64,
 
-#[inline]
-fn mul_u128(a: u128, b: u128) -> u128 {
-    a.saturating_mul(b)
+    // ========================================
+    // Owner Limits (v24)
+    // ========================================
+    /// Cap on the summed position notional of all accounts sharing an owner key, checked
+    /// at the oracle price on risk-increasing trades (0 = uncapped)
+    pub max_owner_notional: U128,
+
+    /// Cap on the summed capital of all accounts sharing an owner key, checked on deposit
+    /// (0 = uncapped)
+    pub max_owner_capital: U128,
+
+    // ========================================
+    // Insurance Fee Skim (v25)
+    // ========================================
+    /// Share of every trading fee routed straight to insurance before the LP split,
+    /// in bps (0 = disabled)
+    pub insurance_skim_bps: u16,
+
+    /// Insurance size, in bps of open interest, at which the skim stops (0 = no target)
+    pub insurance_target_bps: u64,
+
+    // ========================================
+    // Utilization Bands (v26)
+    // ========================================
+    /// Vault utilization (`vault_utilization`) above which LP withdrawals are held back
+    /// (0 = disabled)
+    pub utilization_soft_bps: u64,
+
+    /// Vault utilization at or above which only risk-reducing trades are accepted
+    /// (0 = disabled)
+    pub utilization_hard_bps: u64,
+
+    // ========================================
+    // Close Pricing (v27)
+    // ========================================
+    /// Price at which liquidations and max-PnL force-closes execute (oracle by default);
+    /// the insurance fund settles the difference to the oracle
+    pub close_price_source: ClosePriceSource,
+
+    /// Smoothing horizon of the mark EMA, in slots; also the maximum age of the last
+    /// execution price used by `ClosePriceSource::LastExecution`
+    pub close_price_ema_slots: u64,
+
+    // ========================================
+    // Vault Drain Cap (v28)
+    // ========================================
+    /// Cap on the value one account may extract per drain epoch (withdrawals plus
+    /// profit realized into capital, net of deposits). When set, it replaces the
+    /// unrealized max-PnL force-close in the crank (0 = disabled)
+    pub max_epoch_drain: U128,
+
+    /// Length of a drain epoch, in slots (0 = `epoch_slots`)
+    pub drain_epoch_slots: u64,
+
+    // ========================================
+    // Shared Epoch (v29)
+    // ========================================
+    /// Length of the shared market epoch, in slots (0 = no shared epoch). The crank rolls
+    /// it over; liquidation analytics, LP performance fees and the drain cap use it
+    /// whenever their own epoch length is left at 0
+    pub epoch_slots: u64,
+
+    // ========================================
+    // Skew Cap (v30)
+    // ========================================
+    /// Gap move (bps) LP capital must be able to absorb on the net skew: risk-increasing
+    /// trades may not push net LP notional past LP capital × 10_000 / this (0 = disabled)
+    pub skew_gap_move_bps: u64,
+
+    // ========================================
+    // Gap Insurance (v31)
+    // ========================================
+    /// Share (bps) of the insurance cut of each trading fee earmarked for the gap pool
+    pub gap_levy_share_bps: u64,
+
+    /// Crank-to-crank oracle move (bps) that counts as a single-print gap (0 = disabled)
+    pub oracle_gap_bps: u64,
+
+    /// Slots a gap has to revert before its liquidation claims lapse (0 = disabled)
+    pub gap_revert_slots: u64,
+
+    /// Distance (bps) from the pre-gap price within which a gap counts as reverted
+    pub gap_revert_tolerance_bps: u64,
+
+    // ========================================
+    // Force-Close Grace (v32)
+    // ========================================
+    /// Notice (slots) an account over the max-PnL cap gets before the crank force-closes it
+    /// (0 = close immediately)
+    pub force_close_grace_slots: u64,
+
+    // ========================================
+    // Crank Liquidation Budget (v33)
+    // ========================================
+    /// Liquidation notional (collateral units) one crank may close (0 = unlimited); the
+    /// crank stops at the first account it cannot finish and resumes there next time
+    pub max_crank_liq_notional: U128,
+
+    // ========================================
+    // Matcher Price Band (v34)
+    // ========================================
+    /// Widest a matcher fill may print from the oracle, in bps of the oracle price (0 = any
+    /// price); fills outside the band are rejected
+    pub exec_price_band_bps: u64,
+
+    // ========================================
+    // Smoothed Prices (v35)
+    // ========================================
+    /// Horizon (slots) of the oracle EMA funding accrues on
+    pub funding_ema_slots: u64,
+
+    /// Weight of that EMA against the spot oracle for funding, in bps (0 = spot only)
+    pub funding_ema_weight_bps: u64,
+
+    /// Horizon (slots) of the oracle EMA that confirms crank liquidations
+    pub liquidation_ema_slots: u64,
+
+    /// Weight of that EMA against the spot oracle: the crank only liquidates accounts
+    /// also below maintenance at the blended price (0 = spot only)
+    pub liquidation_ema_weight_bps: u64,
+
+    /// Horizon (slots) of the oracle EMA the max-PnL cap is measured at
+    pub max_pnl_ema_slots: u64,
+
+    /// Weight of that EMA against the spot oracle for the max-PnL cap (0 = spot only)
+    pub max_pnl_ema_weight_bps: u64,
+
+    // ========================================
+    // Haircut Claims (v36)
+    // ========================================
+    /// Share of the insurance surplus the crank pays toward a visited account's haircut
+    /// claim, in bps (0 = haircuts are final and no claims are recorded)
+    pub haircut_claim_payout_bps: u64,
+
+    // ========================================
+    // Emissions (v37)
+    // ========================================
+    /// Length of a liquidity-mining epoch in slots; points earned during an epoch become
+    /// claimable once it ends (0 = emissions disabled)
+    pub emission_epoch_slots: u64,
+
+    /// Points per unit of maker (LP) fill notional, in bps
+    pub emission_volume_weight_bps: u64,
+
+    /// Points per unit of open-interest notional (at entry price) held per slot, in bps
+    pub emission_oi_weight_bps: u64,
+
+    /// Points per unit of LP capital held per slot, in bps
+    pub emission_lp_weight_bps: u64,
+
+    // ========================================
+    // Risk-Reducing Fee Rebate (v38)
+    // ========================================
+    /// Share of the trading fee waived, in bps, on fills that shrink both the user's position
+    /// and the net LP skew (10_000 = free; 0 = no rebate)
+    pub risk_reducing_fee_discount_bps: u64,
+
+    // ========================================
+    // Event Margin (v39)
+    // ========================================
+    /// Slots either side of a margin event (a scheduled `margin_events` slot or a multiple
+    /// of `event_period_slots`) over which initial margin is tightened (0 = disabled)
+    pub event_margin_window_slots: u64,
+
+    /// Initial margin at the event slot itself, easing linearly back to the normal
+    /// requirement at the edges of the window
+    pub event_initial_margin_bps: u64,
+
+    /// Interval of recurring events such as funding settlements (0 = scheduled events only)
+    pub event_period_slots: u64,
+
+    // ========================================
+    // ADL Preference (v40)
+    // ========================================
+    /// Trading fee discount, in bps, for accounts that opted to be force-realized first
+    /// (see `RiskEngine::set_adl_first`)
+    pub adl_first_fee_discount_bps: u64,
+
+    // ========================================
+    // Fee Curve (v41)
+    // ========================================
+    /// Trading fee by trade notional, replacing `trading_fee_bps` when any point is set:
+    /// linear between points, flat beyond the ends (unused points all zero)
+    pub fee_curve: [FeeCurvePoint; MAX_FEE_CURVE_POINTS],
+
+    // ========================================
+    // Conditional Order Margin (v42)
+    // ========================================
+    /// Margin reserved against an account's registered conditional-order notional (see
+    /// `RiskEngine::set_conditional_notional`), in bps; withdrawals and risk-increasing
+    /// trades must leave it free (0 = no reservation)
+    pub conditional_margin_bps: u64,
+
+    // ========================================
+    // Account Activity (v43)
+    // ========================================
+    /// Withdrawals of at least this amount are kept in the account's activity log (see
+    /// `RiskEngine::account_activity`); 0 = every withdrawal
+    pub large_withdrawal_threshold: U128,
+
+    // ========================================
+    // Pooled LP Holdings (v44)
+    // ========================================
+    /// Smallest value, in collateral units at the LP's NAV, an outside depositor's
+    /// holding may have after a deposit or partial redemption (0 = no minimum)
+    pub lp_min_holding: U128,
+
+    // ========================================
+    // Credit Lines (v45)
+    // ========================================
+    /// Largest margin credit line an LP may be granted (0 = credit lines disabled)
+    pub max_credit_limit: U128,
+
+    /// Unused space new fields are carved from; must be zero. Not encoded.
+    pub reserved: [[u8; 16]; EXT_PARAMS_RESERVED_CHUNKS],
 }
 
-#[inline]
-fn div_u128(a: u128, b: u128) -> Result<u128> {
-    if b == 0 {
-        Err(RiskError::Overflow) // Division by zero
-    } else {
-        Ok(a / b)
-    }
+/// Current `ExtParams` layout version.
+pub const EXT_PARAMS_VERSION: u16 = 45;
+
+/// Fixed in-memory size of `ExtParams`.
+pub const EXT_PARAMS_SIZE: usize = 1536;
+
+/// 16-byte chunks left in `ExtParams::reserved`. Decrease this by the space a new
+/// field takes so `EXT_PARAMS_SIZE` still holds.
+pub const EXT_PARAMS_RESERVED_CHUNKS: usize = 29;
+
+#[cfg(not(kani))]
+const _: () = assert!(core::mem::size_of::<ExtParams>() == EXT_PARAMS_SIZE);
+
+/// Encoded header size: version (u16) + body length (u16).
+const EXT_PARAMS_HEADER_LEN: usize = 4;
+
+/// Encoded size of the retired v2 reserved block (always zero).
+const EXT_PARAMS_V2_GAP_LEN: usize = 8 * 8;
+
+/// Little-endian writer over a caller-provided buffer.
+struct ByteWriter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
 }
 
-#[inline]
-fn clamp_pos_i128(val: i128) -> u128 {
-    if val > 0 {
-        val as u128
-    } else {
-        0
+impl<'a> ByteWriter<'a> {
+    fn put(&mut self, bytes: &[u8]) -> Result<()> {
+        let end = self.pos.checked_add(bytes.len()).ok_or(RiskError::Overflow)?;
+        if end > self.buf.len() {
+            return Err(RiskError::InvalidParams);
+        }
+        self.buf[self.pos..end].copy_from_slice(bytes);
+        self.pos = end;
+        Ok(())
     }
 }
 
-#[allow(dead_code)]
-#[inline]
-fn clamp_neg_i128(val: i128) -> u128 {
-    if val < 0 {
-        neg_i128_to_u128(val)
-    } else {
-        0
-    }
+/// Little-endian reader that yields zeros past the end of its input.
+struct ByteReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
 }
 
-/// Saturating absolute value for i128 (handles i128::MIN without overflow)
-#[inline]
-fn saturating_abs_i128(val: i128) -> i128 {
-    if val == i128::MIN {
-        i128::MAX
-    } else {
-        val.abs()
+impl<'a> ByteReader<'a> {
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let mut out = [0u8; N];
+        if self.pos < self.buf.len() {
+            let n = core::cmp::min(N, self.buf.len().saturating_sub(self.pos));
+            out[..n].copy_from_slice(&self.buf[self.pos..self.pos.saturating_add(n)]);
+        }
+        self.pos = self.pos.saturating_add(N);
+        out
     }
-}
 
-/// Safely convert negative i128 to u128 (handles i128::MIN without overflow)
-///
-/// For i128::MIN, -i128::MIN would overflow because i128::MAX + 1 cannot be represented.
-/// We handle this by returning (i128::MAX as u128) + 1 = 170141183460469231731687303715884105728.
-#[inline]
-fn neg_i128_to_u128(val: i128) -> u128 {
-    debug_assert!(val < 0, "neg_i128_to_u128 called with non-negative value");
-    if val == i128::MIN {
-        (i128::MAX as u128) + 1
-    } else {
-        (-val) as u128
+    fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.take::<8>())
     }
-}
 
-/// Safely convert u128 to i128 with clamping (handles values > i128::MAX)
-///
-/// If x > i128::MAX, the cast would wrap to a negative value.
-/// We clamp to i128::MAX instead to preserve correctness of margin checks.
-#[inline]
-fn u128_to_i128_clamped(x: u128) -> i128 {
-    if x > i128::MAX as u128 {
-        i128::MAX
-    } else {
-        x as i128
+    fn u128(&mut self) -> u128 {
+        u128::from_le_bytes(self.take::<16>())
     }
 }
 
-// ============================================================================
-// Matching Engine Trait
-// ============================================================================
+impl ExtParams {
+    /// Encoded size of the current version (header + body).
+    pub const ENCODED_LEN: usize = EXT_PARAMS_HEADER_LEN
+        + 32 + 32 + 8 * 4 // v1
+        + 8 + 16 + 8 + EXT_PARAMS_V2_GAP_LEN // v2
+        + 8 + 1 + 1 // v3
+        + 1 // v4
+        + 8 // v5
+        + 8 // v6
+        + 8 * 3 // v7
+        + 1 // v8
+        + 2 + 8 // v9
+        + 8 // v10
+        + 8 + 8 // v11
+        + 8 + 8 // v12
+        + 8 + 8 + 8 // v13
+        + 8 + 8 + 8 + 8 // v14
+        + 8 // v15
+        + 2 + 2 + 8 // v16
+        + 8 + 8 // v17
+        + 8 // v18
+        + 8 // v19
+        + 8 // v20
+        + (32 + 2 + 16) * MAX_COLLATERALS // v21
+        + 1 // v22
+        + 8 // v23
+        + 16 + 16 // v24
+        + 2 + 8 // v25
+        + 8 + 8 // v26
+        + 1 + 8 // v27
+        + 16 + 8 // v28
+        + 8 // v29
+        + 8 // v30
+        + 8 + 8 + 8 + 8 // v31
+        + 8 // v32
+        + 16 // v33
+        + 8 // v34
+        + 8 + 8 + 8 + 8 + 8 + 8 // v35
+        + 8 // v36
+        + 8 + 8 + 8 + 8 // v37
+        + 8 // v38
+        + 8 + 8 + 8 // v39
+        + 8 // v40
+        + (16 + 8) * MAX_FEE_CURVE_POINTS // v41
+        + 8 // v42
+        + 16 // v43
+        + 16 // v44
+        + 16; // v45
+
+    /// Fee curve rate (bps) for a trade of `notional` (None = no curve configured).
+    pub fn fee_curve_bps(&self, notional: u128) -> Option<u64> {
+        let points = &self.fee_curve;
+        let len = points.iter().take_while(|p| !p.notional.is_zero()).count();
+        let (first, last) = (points.first()?, points[..len].last()?);
+        if notional <= first.notional.get() {
+            return Some(first.fee_bps);
+        }
+        for pair in points[..len].windows(2) {
+            let (lo, hi) = (&pair[0], &pair[1]);
+            if notional > hi.notional.get() {
+                continue;
+            }
+            let span = hi.notional.get().saturating_sub(lo.notional.get());
+            let into = notional.saturating_sub(lo.notional.get());
+            let step = |from: u64, to: u64| {
+                mul_div(to.saturating_sub(from) as u128, into, span, Rounding::Up) as u64
+            };
+            return Some(if hi.fee_bps >= lo.fee_bps {
+                lo.fee_bps.saturating_add(step(lo.fee_bps, hi.fee_bps))
+            } else {
+                lo.fee_bps.saturating_sub(step(hi.fee_bps, lo.fee_bps))
+            });
+        }
+        Some(last.fee_bps)
+    }
 
-/// Result of a successful trade execution from the matching engine
+    /// Encode into `out`, returning the number of bytes written.
+    pub fn encode(&self, out: &mut [u8]) -> Result<usize> {
+        if out.len() < Self::ENCODED_LEN {
+            return Err(RiskError::InvalidParams);
+        }
+        let body_len = (Self::ENCODED_LEN - EXT_PARAMS_HEADER_LEN) as u16;
+        let mut w = ByteWriter { buf: out, pos: 0 };
+        w.put(&EXT_PARAMS_VERSION.to_le_bytes())?;
+        w.put(&body_len.to_le_bytes())?;
+        // v1 fields
+        w.put(&self.primary_oracle)?;
+        w.put(&self.fallback_oracle)?;
+        w.put(&self.oracle_max_staleness_slots.to_le_bytes())?;
+        w.put(&self.oracle_max_conf_bps.to_le_bytes())?;
+        w.put(&self.oracle_max_divergence_bps.to_le_bytes())?;
+        w.put(&self.param_timelock_slots.to_le_bytes())?;
+        // v2 fields
+        w.put(&self.max_pnl_vault_bps.to_le_bytes())?;
+        w.put(&self.max_oi_abs.get().to_le_bytes())?;
+        w.put(&self.max_funding_rate_bps_per_slot.to_le_bytes())?;
+        w.put(&[0; EXT_PARAMS_V2_GAP_LEN])?;
+        // v3 fields
+        w.put(&self.price_scale.to_le_bytes())?;
+        w.put(&[self.base_decimals, self.quote_decimals])?;
+        // v4 fields
+        w.put(&[self.payoff_mode as u8])?;
+        // v5 fields
+        w.put(&self.series_interval_slots.to_le_bytes())?;
+        // v6 fields
+        w.put(&self.liq_stats_epoch_slots.to_le_bytes())?;
+        // v7 fields
+        w.put(&self.deviation_window_slots.to_le_bytes())?;
+        w.put(&self.crank_deviation_alert_bps.to_le_bytes())?;
+        w.put(&self.exec_deviation_alert_bps.to_le_bytes())?;
+        // v8 fields
+        w.put(&[self.oracle_source as u8])?;
+        // v9 fields
+        w.put(&self.transfer_fee_bps.to_le_bytes())?;
+        w.put(&self.transfer_fee_max.to_le_bytes())?;
+        // v10 fields
+        w.put(&self.lp_withdrawal_epoch_slots.to_le_bytes())?;
+        // v11 fields
+        w.put(&self.lp_lockup_slots.to_le_bytes())?;
+        w.put(&self.lp_early_exit_fee_bps.to_le_bytes())?;
+        // v12 fields
+        w.put(&self.lp_performance_fee_bps.to_le_bytes())?;
+        w.put(&self.lp_performance_epoch_slots.to_le_bytes())?;
+        // v13 fields
+        w.put(&self.utilization_fee_kink_bps.to_le_bytes())?;
+        w.put(&self.utilization_fee_full_bps.to_le_bytes())?;
+        w.put(&self.utilization_fee_max_bps.to_le_bytes())?;
+        // v14 fields
+        w.put(&self.markout_horizon_slots.to_le_bytes())?;
+        w.put(&self.markout_window_slots.to_le_bytes())?;
+        w.put(&self.markout_toxic_bps.to_le_bytes())?;
+        w.put(&self.markout_surcharge_bps.to_le_bytes())?;
+        // v15 fields
+        w.put(&self.holding_fee_per_slot_e9.to_le_bytes())?;
+        // v16 fields
+        w.put(&self.max_leverage.to_le_bytes())?;
+        w.put(&self.low_leverage_tier.to_le_bytes())?;
+        w.put(&self.low_leverage_fee_discount_bps.to_le_bytes())?;
+        // v17 fields
+        w.put(&self.holding_fee_escalation_slots.to_le_bytes())?;
+        w.put(&self.holding_fee_max_multiplier.to_le_bytes())?;
+        // v18 fields
+        w.put(&self.min_liquidation_bps.to_le_bytes())?;
+        // v19 fields
+        w.put(&self.min_crank_interval_slots.to_le_bytes())?;
+        // v20 fields
+        w.put(&self.collateral_rate_max_step_bps.to_le_bytes())?;
+        // v21 fields
+        for c in self.collaterals.iter() {
+            w.put(&c.oracle)?;
+            w.put(&c.haircut_bps.to_le_bytes())?;
+            w.put(&c.deposit_cap.get().to_le_bytes())?;
+        }
+        // v22 fields
+        w.put(&[self.quanto_collateral_decimals])?;
+        // v23 fields
+        w.put(&self.gc_inactive_slots.to_le_bytes())?;
+        // v24 fields
+        w.put(&self.max_owner_notional.get().to_le_bytes())?;
+        w.put(&self.max_owner_capital.get().to_le_bytes())?;
+        // v25 fields
+        w.put(&self.insurance_skim_bps.to_le_bytes())?;
+        w.put(&self.insurance_target_bps.to_le_bytes())?;
+        // v26 fields
+        w.put(&self.utilization_soft_bps.to_le_bytes())?;
+        w.put(&self.utilization_hard_bps.to_le_bytes())?;
+        // v27 fields
+        w.put(&[self.close_price_source as u8])?;
+        w.put(&self.close_price_ema_slots.to_le_bytes())?;
+        // v28 fields
+        w.put(&self.max_epoch_drain.get().to_le_bytes())?;
+        w.put(&self.drain_epoch_slots.to_le_bytes())?;
+        // v29 fields
+        w.put(&self.epoch_slots.to_le_bytes())?;
+        // v30 fields
+        w.put(&self.skew_gap_move_bps.to_le_bytes())?;
+        // v31 fields
+        w.put(&self.gap_levy_share_bps.to_le_bytes())?;
+        w.put(&self.oracle_gap_bps.to_le_bytes())?;
+        w.put(&self.gap_revert_slots.to_le_bytes())?;
+        w.put(&self.gap_revert_tolerance_bps.to_le_bytes())?;
+        // v32 fields
+        w.put(&self.force_close_grace_slots.to_le_bytes())?;
+        // v33 fields
+        w.put(&self.max_crank_liq_notional.get().to_le_bytes())?;
+        // v34 fields
+        w.put(&self.exec_price_band_bps.to_le_bytes())?;
+        // v35 fields
+        w.put(&self.funding_ema_slots.to_le_bytes())?;
+        w.put(&self.funding_ema_weight_bps.to_le_bytes())?;
+        w.put(&self.liquidation_ema_slots.to_le_bytes())?;
+        w.put(&self.liquidation_ema_weight_bps.to_le_bytes())?;
+        w.put(&self.max_pnl_ema_slots.to_le_bytes())?;
+        w.put(&self.max_pnl_ema_weight_bps.to_le_bytes())?;
+        // v36 fields
+        w.put(&self.haircut_claim_payout_bps.to_le_bytes())?;
+        // v37 fields
+        w.put(&self.emission_epoch_slots.to_le_bytes())?;
+        w.put(&self.emission_volume_weight_bps.to_le_bytes())?;
+        w.put(&self.emission_oi_weight_bps.to_le_bytes())?;
+        w.put(&self.emission_lp_weight_bps.to_le_bytes())?;
+        // v38 fields
+        w.put(&self.risk_reducing_fee_discount_bps.to_le_bytes())?;
+        // v39 fields
+        w.put(&self.event_margin_window_slots.to_le_bytes())?;
+        w.put(&self.event_initial_margin_bps.to_le_bytes())?;
+        w.put(&self.event_period_slots.to_le_bytes())?;
+        // v40 fields
+        w.put(&self.adl_first_fee_discount_bps.to_le_bytes())?;
+        // v41 fields
+        for point in self.fee_curve.iter() {
+            w.put(&point.notional.get().to_le_bytes())?;
+            w.put(&point.fee_bps.to_le_bytes())?;
+        }
+        // v42 fields
+        w.put(&self.conditional_margin_bps.to_le_bytes())?;
+        // v43 fields
+        w.put(&self.large_withdrawal_threshold.get().to_le_bytes())?;
+        // v44 fields
+        w.put(&self.lp_min_holding.get().to_le_bytes())?;
+        // v45 fields
+        w.put(&self.max_credit_limit.get().to_le_bytes())?;
+        Ok(w.pos)
+    }
+
+    /// EMA horizon (slots) and blend weight (bps) configured for `use_`.
+    pub fn price_smoothing(&self, use_: PriceUse) -> (u64, u64) {
+        match use_ {
+            PriceUse::Funding => (self.funding_ema_slots, self.funding_ema_weight_bps),
+            PriceUse::Liquidation => (self.liquidation_ema_slots, self.liquidation_ema_weight_bps),
+            PriceUse::MaxPnl => (self.max_pnl_ema_slots, self.max_pnl_ema_weight_bps),
+        }
+    }
+
+    /// Decode from `bytes`, accepting both older (shorter) and newer (longer) encodings.
+    ///
+    /// Fields absent from an older encoding decode as zero (disabled). Bytes beyond
+    /// the fields this version knows about are ignored. The result is tagged with
+    /// the encoded version, capped at `EXT_PARAMS_VERSION`.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < EXT_PARAMS_HEADER_LEN {
+            return Err(RiskError::InvalidParams);
+        }
+        let version = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let body_len = u16::from_le_bytes([bytes[2], bytes[3]]) as usize;
+        let body_end = EXT_PARAMS_HEADER_LEN.saturating_add(body_len);
+        if version == 0 || bytes.len() < body_end {
+            return Err(RiskError::InvalidParams);
+        }
+        let mut r = ByteReader {
+            buf: &bytes[EXT_PARAMS_HEADER_LEN..body_end],
+            pos: 0,
+        };
+        let mut ext = ExtParams {
+            version: core::cmp::min(version, EXT_PARAMS_VERSION),
+            ..ExtParams::default()
+        };
+        ext.primary_oracle = r.take::<32>();
+        ext.fallback_oracle = r.take::<32>();
+        ext.oracle_max_staleness_slots = r.u64();
+        ext.oracle_max_conf_bps = r.u64();
+        ext.oracle_max_divergence_bps = r.u64();
+        ext.param_timelock_slots = r.u64();
+        ext.max_pnl_vault_bps = r.u64();
+        ext.max_oi_abs = U128::new(r.u128());
+        ext.max_funding_rate_bps_per_slot = r.u64();
+        r.take::<EXT_PARAMS_V2_GAP_LEN>();
+        ext.price_scale = r.u64();
+        let [base_decimals, quote_decimals] = r.take::<2>();
+        ext.base_decimals = base_decimals;
+        ext.quote_decimals = quote_decimals;
+        let [payoff_mode] = r.take::<1>();
+        ext.payoff_mode = PayoffMode::from_u8(payoff_mode).ok_or(RiskError::InvalidParams)?;
+        ext.series_interval_slots = r.u64();
+        ext.liq_stats_epoch_slots = r.u64();
+        ext.deviation_window_slots = r.u64();
+        ext.crank_deviation_alert_bps = r.u64();
+        ext.exec_deviation_alert_bps = r.u64();
+        let [oracle_source] = r.take::<1>();
+        ext.oracle_source = OracleSource::from_u8(oracle_source).ok_or(RiskError::InvalidParams)?;
+        ext.transfer_fee_bps = u16::from_le_bytes(r.take::<2>());
+        ext.transfer_fee_max = r.u64();
+        ext.lp_withdrawal_epoch_slots = r.u64();
+        ext.lp_lockup_slots = r.u64();
+        ext.lp_early_exit_fee_bps = r.u64();
+        ext.lp_performance_fee_bps = r.u64();
+        ext.lp_performance_epoch_slots = r.u64();
+        ext.utilization_fee_kink_bps = r.u64();
+        ext.utilization_fee_full_bps = r.u64();
+        ext.utilization_fee_max_bps = r.u64();
+        ext.markout_horizon_slots = r.u64();
+        ext.markout_window_slots = r.u64();
+        ext.markout_toxic_bps = r.u64();
+        ext.markout_surcharge_bps = r.u64();
+        ext.holding_fee_per_slot_e9 = r.u64();
+        ext.max_leverage = u16::from_le_bytes(r.take::<2>());
+        ext.low_leverage_tier = u16::from_le_bytes(r.take::<2>());
+        ext.low_leverage_fee_discount_bps = r.u64();
+        ext.holding_fee_escalation_slots = r.u64();
+        ext.holding_fee_max_multiplier = r.u64();
+        ext.min_liquidation_bps = r.u64();
+        ext.min_crank_interval_slots = r.u64();
+        ext.collateral_rate_max_step_bps = r.u64();
+        for c in ext.collaterals.iter_mut() {
+            c.oracle = r.take::<32>();
+            c.haircut_bps = u16::from_le_bytes(r.take::<2>());
+            c.deposit_cap = U128::new(r.u128());
+        }
+        let [quanto_collateral_decimals] = r.take::<1>();
+        ext.quanto_collateral_decimals = quanto_collateral_decimals;
+        ext.gc_inactive_slots = r.u64();
+        ext.max_owner_notional = U128::new(r.u128());
+        ext.max_owner_capital = U128::new(r.u128());
+        ext.insurance_skim_bps = u16::from_le_bytes(r.take::<2>());
+        ext.insurance_target_bps = r.u64();
+        ext.utilization_soft_bps = r.u64();
+        ext.utilization_hard_bps = r.u64();
+        let [close_price_source] = r.take::<1>();
+        ext.close_price_source =
+            ClosePriceSource::from_u8(close_price_source).ok_or(RiskError::InvalidParams)?;
+        ext.close_price_ema_slots = r.u64();
+        ext.max_epoch_drain = U128::new(r.u128());
+        ext.drain_epoch_slots = r.u64();
+        ext.epoch_slots = r.u64();
+        ext.skew_gap_move_bps = r.u64();
+        ext.gap_levy_share_bps = r.u64();
+        ext.oracle_gap_bps = r.u64();
+        ext.gap_revert_slots = r.u64();
+        ext.gap_revert_tolerance_bps = r.u64();
+        ext.force_close_grace_slots = r.u64();
+        ext.max_crank_liq_notional = U128::new(r.u128());
+        ext.exec_price_band_bps = r.u64();
+        ext.funding_ema_slots = r.u64();
+        ext.funding_ema_weight_bps = r.u64();
+        ext.liquidation_ema_slots = r.u64();
+        ext.liquidation_ema_weight_bps = r.u64();
+        ext.max_pnl_ema_slots = r.u64();
+        ext.max_pnl_ema_weight_bps = r.u64();
+        ext.haircut_claim_payout_bps = r.u64();
+        ext.emission_epoch_slots = r.u64();
+        ext.emission_volume_weight_bps = r.u64();
+        ext.emission_oi_weight_bps = r.u64();
+        ext.emission_lp_weight_bps = r.u64();
+        ext.risk_reducing_fee_discount_bps = r.u64();
+        ext.event_margin_window_slots = r.u64();
+        ext.event_initial_margin_bps = r.u64();
+        ext.event_period_slots = r.u64();
+        ext.adl_first_fee_discount_bps = r.u64();
+        for point in ext.fee_curve.iter_mut() {
+            point.notional = U128::new(r.u128());
+            point.fee_bps = r.u64();
+        }
+        ext.conditional_margin_bps = r.u64();
+        ext.large_withdrawal_threshold = U128::new(r.u128());
+        ext.lp_min_holding = U128::new(r.u128());
+        ext.max_credit_limit = U128::new(r.u128());
+        Ok(ext)
+    }
+}
+
+/// A parameter update waiting out its timelock.
+#[repr(C)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub struct TradeExecution {
-    /// Actual execution price (may differ from oracle/requested price)
-    pub price: u64,
-    /// Actual executed size (may be partial fill)
-    pub size: i128,
+pub struct PendingParamsUpdate {
+    /// Whether an update is currently queued
+    pub active: bool,
+    /// Slot at which the update was queued
+    pub queued_slot: u64,
+    /// First slot at which the update may be applied
+    pub eta_slot: u64,
+    /// Identity of the queued update, incremented by every `queue_params_update`;
+    /// approvals name it so they cannot carry over to a re-queued update
+    pub nonce: u64,
+    /// Risk parameters to install
+    pub params: RiskParams,
+    /// Extended parameters to install
+    pub ext_params: ExtParams,
+    /// Council members who approved this update (bit i = `AdminCouncil::signers[i]`)
+    pub approvals: u8,
 }
 
-/// Trait for pluggable matching engines
-///
-/// Implementers can provide custom order matching logic via CPI.
-/// The matching engine is responsible for validating and executing trades
-/// according to its own rules (CLOB, AMM, RFQ, etc).
-pub trait MatchingEngine {
-    /// Execute a trade between LP and user
-    ///
-    /// # Arguments
-    /// * `lp_program` - The LP's matching engine program ID
-    /// * `lp_context` - The LP's matching engine context account
-    /// * `lp_account_id` - Unique ID of the LP account (never recycled)
-    /// * `oracle_price` - Current oracle price for reference
-    /// * `size` - Requested position size (positive = long, negative = short)
-    ///
-    /// # Returns
-    /// * `Ok(TradeExecution)` with actual executed price and size
-    /// * `Err(RiskError)` if the trade is rejected
-    ///
-    /// # Safety
-    /// The matching engine MUST verify user authorization before approving trades.
-    /// The risk engine will check solvency after the trade executes.
-    fn execute_match(
-        &self,
-        lp_program: &[u8; 32],
-        lp_context: &[u8; 32],
-        lp_account_id: u64,
-        oracle_price: u64,
-        size: i128,
-    ) -> Result<TradeExecution>;
+/// Maximum number of admin council signers (approvals are tracked as a `u8` bitmask)
+pub const MAX_ADMIN_SIGNERS: usize = 8;
+
+/// M-of-N admin council for sensitive actions (parameter updates, insurance
+/// withdrawals). With `threshold` 0 the council is disabled and the single admin
+/// key checked by the wrapper is sufficient.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AdminCouncil {
+    /// Signer keys; unused entries are all zeros
+    pub signers: [[u8; 32]; MAX_ADMIN_SIGNERS],
+    /// Approvals required per action (0 = council disabled)
+    pub threshold: u8,
 }
 
-/// No-op matching engine (for testing)
-/// Returns the requested price and size as-is
-pub struct NoOpMatcher;
+impl AdminCouncil {
+    /// Position of `key` among the signers (the all-zero key is never a signer)
+    pub fn signer_index(&self, key: &[u8; 32]) -> Option<usize> {
+        if *key == [0u8; 32] {
+            return None;
+        }
+        self.signers.iter().position(|k| k == key)
+    }
 
-impl MatchingEngine for NoOpMatcher {
-    fn execute_match(
-        &self,
-        _lp_program: &[u8; 32],
-        _lp_context: &[u8; 32],
-        _lp_account_id: u64,
-        oracle_price: u64,
-        size: i128,
-    ) -> Result<TradeExecution> {
-        // Return requested price/size unchanged (no actual matching logic)
-        Ok(TradeExecution {
-            price: oracle_price,
-            size,
-        })
+    /// Whether an approval bitmask meets the threshold
+    pub fn is_approved(&self, approvals: u8) -> bool {
+        approvals.count_ones() >= self.threshold as u32
+    }
+
+    /// Approval bitmask of the distinct council members among `keys`
+    fn approvals_of(&self, keys: &[[u8; 32]]) -> u8 {
+        keys.iter()
+            .filter_map(|k| self.signer_index(k))
+            .fold(0u8, |bits, i| bits | (1u8 << i))
     }
 }
 
-// ============================================================================
-// Core Implementation
-// ============================================================================
+/// An insurance fund withdrawal awaiting council approval.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PendingInsuranceWithdrawal {
+    /// Whether a withdrawal is currently queued
+    pub active: bool,
+    /// Identity of the queued withdrawal, incremented by every
+    /// `queue_insurance_withdrawal`; approvals name it
+    pub nonce: u64,
+    /// Amount to withdraw from the insurance fund
+    pub amount: U128,
+    /// Council members who approved (bit i = `AdminCouncil::signers[i]`)
+    pub approvals: u8,
+}
 
-impl RiskEngine {
-    /// Create a new risk engine (stack-allocates the full struct - avoid in BPF!)
-    ///
-    /// WARNING: This allocates ~6MB on the stack at MAX_ACCOUNTS=4096.
-    /// For Solana BPF programs, use `init_in_place` instead.
-    pub fn new(params: RiskParams) -> Self {
-        let mut engine = Self {
-            vault: U128::ZERO,
-            insurance_fund: InsuranceFund {
-                balance: U128::ZERO,
-                fee_revenue: U128::ZERO,
-            },
-            params,
-            current_slot: 0,
-            funding_index_qpb_e6: I128::ZERO,
-            last_funding_slot: 0,
-            funding_rate_bps_per_slot_last: 0,
-            last_crank_slot: 0,
-            max_crank_staleness_slots: params.max_crank_staleness_slots,
-            total_open_interest: U128::ZERO,
-            c_tot: U128::ZERO,
-            pnl_pos_tot: U128::ZERO,
-            liq_cursor: 0,
-            gc_cursor: 0,
-            last_full_sweep_start_slot: 0,
-            last_full_sweep_completed_slot: 0,
-            crank_cursor: 0,
-            sweep_start_idx: 0,
-            lifetime_liquidations: 0,
-            lifetime_force_realize_closes: 0,
-            net_lp_pos: I128::ZERO,
-            lp_sum_abs: U128::ZERO,
-            lp_max_abs: U128::ZERO,
-            lp_max_abs_sweep: U128::ZERO,
-            used: [0; BITMAP_WORDS],
-            num_used_accounts: 0,
-            next_account_id: 0,
-            free_head: 0,
-            next_free: [0; MAX_ACCOUNTS],
-            accounts: [empty_account(); MAX_ACCOUNTS],
-        };
+/// A single oracle price observation as supplied by the caller.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OracleQuote {
+    /// Identity of the feed the quote was read from
+    pub feed: [u8; 32],
+    /// Price (same scale as oracle_price everywhere else; see `ExtParams::price_scale`)
+    pub price: u64,
+    /// Confidence interval (absolute, same scale as price)
+    pub conf: u64,
+    /// Slot at which the quote was published
+    pub publish_slot: u64,
+}
 
-        // Initialize freelist: 0 -> 1 -> 2 -> ... -> 4095 -> NONE
-        for i in 0..MAX_ACCOUNTS - 1 {
-            engine.next_free[i] = (i + 1) as u16;
-        }
-        engine.next_free[MAX_ACCOUNTS - 1] = u16::MAX; // Sentinel
+/// Which oracle feed supplied the price for an oracle-selected crank.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OracleFeed {
+    /// No oracle-selected crank has run yet
+    #[default]
+    None = 0,
+    Primary = 1,
+    Fallback = 2,
+}
 
-        engine
+/// Oracle account format a market reads its price from.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OracleSource {
+    /// Pyth v2 price account (`pyth` feature)
+    #[default]
+    Pyth = 0,
+    /// Switchboard v2 aggregator account (`switchboard` feature)
+    Switchboard = 1,
+}
+
+impl OracleSource {
+    pub fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            0 => Some(Self::Pyth),
+            1 => Some(Self::Switchboard),
+            _ => None,
+        }
     }
+}
 
-    /// Initialize a RiskEngine in place (zero-copy friendly).
-    ///
-    /// PREREQUISITE: The memory backing `self` MUST be zeroed before calling.
-    /// This method only sets non-zero fields to avoid touching the entire ~6MB struct.
-    ///
-    /// This is the correct way to initialize RiskEngine in Solana BPF programs
-    /// where stack space is limited to 4KB.
-    pub fn init_in_place(&mut self, params: RiskParams) {
-        // Set params (non-zero field)
-        self.params = params;
-        self.max_crank_staleness_slots = params.max_crank_staleness_slots;
+/// Contract payoff of a market.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PayoffMode {
+    /// Linear: positions in base units, margin and PnL in the quote asset
+    #[default]
+    Linear = 0,
+    /// Inverse: positions in quote units (contracts), margin and PnL in the base
+    /// asset, e.g. SOL-margined SOL/USD
+    Inverse = 1,
+    /// Quanto: linear in base units, but margin and PnL in a third collateral asset
+    /// converted at `RiskEngine::quanto_price`, e.g. SOL-margined BTC/USD
+    Quanto = 2,
+}
 
-        // Initialize freelist: 0 -> 1 -> 2 -> ... -> MAX_ACCOUNTS-1 -> NONE
-        // All other fields are zero which is correct for:
-        // - vault, insurance_fund, current_slot, funding_index, etc. = 0
-        // - used bitmap = all zeros (no accounts in use)
-        // - accounts = all zeros (equivalent to empty_account())
-        // - free_head = 0 (first free slot is 0)
-        for i in 0..MAX_ACCOUNTS - 1 {
-            self.next_free[i] = (i + 1) as u16;
+impl PayoffMode {
+    pub fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            0 => Some(Self::Linear),
+            1 => Some(Self::Inverse),
+            2 => Some(Self::Quanto),
+            _ => None,
         }
-        self.next_free[MAX_ACCOUNTS - 1] = u16::MAX; // Sentinel
     }
+}
 
-    // ========================================
-    // Bitmap Helpers
-    // ========================================
+/// Price at which liquidations and max-PnL force-closes execute.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClosePriceSource {
+    /// The oracle price passed to the crank or liquidation call
+    #[default]
+    Oracle = 0,
+    /// The crank-maintained mark EMA (`RiskEngine::mark_ema_price`)
+    MarkEma = 1,
+    /// The most recent trade execution price reported by the matcher
+    LastExecution = 2,
+}
 
-    pub fn is_used(&self, idx: usize) -> bool {
-        if idx >= MAX_ACCOUNTS {
-            return false;
+impl ClosePriceSource {
+    pub fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            0 => Some(Self::Oracle),
+            1 => Some(Self::MarkEma),
+            2 => Some(Self::LastExecution),
+            _ => None,
         }
-        let w = idx >> 6;
-        let b = idx & 63;
-        ((self.used[w] >> b) & 1) == 1
     }
+}
 
-    fn set_used(&mut self, idx: usize) {
-        let w = idx >> 6;
-        let b = idx & 63;
-        self.used[w] |= 1u64 << b;
-    }
+/// What a smoothed oracle price is used for; each use keeps its own EMA and blend
+/// weight (`ExtParams::funding_ema_*`, `liquidation_ema_*`, `max_pnl_ema_*`).
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PriceUse {
+    /// Funding accrual
+    Funding = 0,
+    /// Confirming crank liquidations
+    Liquidation = 1,
+    /// Measuring the max-PnL cap
+    MaxPnl = 2,
+}
 
-    fn clear_used(&mut self, idx: usize) {
-        let w = idx >> 6;
-        let b = idx & 63;
-        self.used[w] &= !(1u64 << b);
-    }
+impl PriceUse {
+    pub const ALL: [PriceUse; 3] = [PriceUse::Funding, PriceUse::Liquidation, PriceUse::MaxPnl];
 
-    fn for_each_used_mut<F: FnMut(usize, &mut Account)>(&mut self, mut f: F) {
-        for (block, word) in self.used.iter().copied().enumerate() {
-            let mut w = word;
-            while w != 0 {
-                let bit = w.trailing_zeros() as usize;
-                let idx = block * 64 + bit;
-                w &= w - 1; // Clear lowest bit
-                if idx >= MAX_ACCOUNTS {
-                    continue; // Guard against stray high bits in bitmap
-                }
-                f(idx, &mut self.accounts[idx]);
-            }
+    pub fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            0 => Some(Self::Funding),
+            1 => Some(Self::Liquidation),
+            2 => Some(Self::MaxPnl),
+            _ => None,
         }
     }
+}
 
-    fn for_each_used<F: FnMut(usize, &Account)>(&self, mut f: F) {
-        for (block, word) in self.used.iter().copied().enumerate() {
-            let mut w = word;
-            while w != 0 {
-                let bit = w.trailing_zeros() as usize;
-                let idx = block * 64 + bit;
-                w &= w - 1; // Clear lowest bit
-                if idx >= MAX_ACCOUNTS {
-                    continue; // Guard against stray high bits in bitmap
-                }
-                f(idx, &self.accounts[idx]);
-            }
-        }
+/// Crank-maintained oracle EMA for one `PriceUse`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PriceEma {
+    /// EMA price (0 = not yet seeded)
+    pub price: u64,
+    /// Slot of the last update
+    pub slot: u64,
+}
+
+/// Move `ema` toward `target`, weighting the new print by `dt` over `horizon`
+/// (capped at 1). A zero `ema` or `horizon` jumps straight to `target`.
+fn ema_toward(ema: u64, target: u64, dt: u64, horizon: u64) -> u64 {
+    if ema == 0 || horizon == 0 {
+        return target;
     }
+    let dt = core::cmp::min(dt, horizon) as u128;
+    let step = |d: u128| mul_div(d, dt, horizon as u128, Rounding::Down);
+    let (ema, target) = (ema as u128, target as u128);
+    (if target >= ema {
+        ema.saturating_add(step(target.saturating_sub(ema)))
+    } else {
+        ema.saturating_sub(step(ema.saturating_sub(target)))
+    }) as u64
+}
 
-    // ========================================
-    // O(1) Aggregate Helpers (spec §4)
-    // ========================================
+/// Number of samples retained in the market time series ring.
+pub const MARKET_SERIES_LEN: usize = 128;
 
-    /// Mandatory helper: set account PnL and maintain pnl_pos_tot aggregate (spec §4.2).
-    /// All code paths that modify PnL MUST call this.
-    #[inline]
-    pub fn set_pnl(&mut self, idx: usize, new_pnl: i128) {
-        let old = self.accounts[idx].pnl.get();
-        let old_pos = if old > 0 { old as u128 } else { 0 };
-        let new_pos = if new_pnl > 0 { new_pnl as u128 } else { 0 };
-        self.pnl_pos_tot = U128::new(
-            self.pnl_pos_tot
-                .get()
-                .saturating_add(new_pos)
-                .saturating_sub(old_pos),
+/// Number of crank prices retained in the price history ring.
+pub const PRICE_HISTORY_LEN: usize = 64;
+
+/// One crank's oracle price (see `RiskEngine::price_history`).
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PricePoint {
+    /// Crank slot (0 marks an empty ring slot)
+    pub slot: u64,
+    /// Oracle price used by the crank
+    pub price: u64,
+}
+
+/// One crank-time sample of market state, for charting from on-chain data.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MarketSample {
+    /// Crank slot (0 marks an empty ring slot)
+    pub slot: u64,
+    /// Oracle price used by the crank
+    pub price: u64,
+    /// Funding rate in effect after the crank
+    pub funding_rate_bps_per_slot: i64,
+    /// Σ |position| across all accounts
+    pub open_interest: U128,
+    /// Global skew: net LP position (users are net the opposite side)
+    pub net_lp_pos: I128,
+}
+
+/// Liquidation counters for one analytics epoch.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LiquidationStats {
+    /// First slot of the epoch
+    pub epoch_start_slot: u64,
+    /// Liquidations executed
+    pub liquidations: u64,
+    /// Notional closed by liquidations, at the oracle price
+    pub volume_notional: U128,
+    /// Liquidation fees charged
+    pub penalty_total: U128,
+    /// Losses beyond capital written off when positions were closed
+    pub bad_debt: U128,
+    /// Deleveraging closes outside liquidation (force-realize and max-PnL cap)
+    pub adl_events: u64,
+}
+
+impl LiquidationStats {
+    /// Average liquidation penalty as bps of the notional closed (0 if none)
+    pub fn average_penalty_bps(&self) -> u64 {
+        if self.volume_notional.is_zero() {
+            return 0;
+        }
+        let bps = mul_div(
+            self.penalty_total.get(),
+            10_000,
+            self.volume_notional.get(),
+            Rounding::Down,
         );
-        self.accounts[idx].pnl = I128::new(new_pnl);
+        core::cmp::min(bps, u64::MAX as u128) as u64
     }
+}
 
-    /// Helper: set account capital and maintain c_tot aggregate (spec §4.1).
+/// The shared market epoch (see `ExtParams::epoch_slots`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Epoch {
+    /// Epoch number: start slot / length
+    pub index: u64,
+    /// First slot of the epoch
+    pub start_slot: u64,
+    /// Length in slots (0 = no shared epoch configured)
+    pub len: u64,
+}
+
+impl Epoch {
+    /// First slot of the next epoch
+    pub fn end_slot(&self) -> u64 {
+        self.start_slot.saturating_add(self.len)
+    }
+}
+
+/// Time-boxed trading fee override (see `RiskEngine::set_fee_holiday`).
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FeeHoliday {
+    /// First slot the override applies to
+    pub start_slot: u64,
+    /// First slot after the override (`start_slot == end_slot` = none scheduled)
+    pub end_slot: u64,
+    /// Replaces `trading_fee_bps` during the window
+    pub fee_bps: u64,
+}
+
+impl FeeHoliday {
+    pub fn is_active(&self, slot: u64) -> bool {
+        self.start_slot <= slot && slot < self.end_slot
+    }
+}
+
+/// Maximum scheduled one-off margin events (see `RiskEngine::schedule_margin_event`).
+pub const MAX_MARGIN_EVENTS: usize = 8;
+
+/// Maximum outstanding gap-insurance claims; liquidations past it go uncompensated.
+pub const MAX_GAP_CLAIMS: usize = 8;
+
+/// Loss from a liquidation at a gap print, paid if the gap reverts in time.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GapClaim {
+    /// Liquidated account slot
+    pub idx: u16,
+    /// ID of the liquidated account (a reused slot does not inherit the claim)
+    pub account_id: u64,
+    /// Equity at the pre-gap price minus equity after the liquidation (0 = empty slot)
+    pub loss: U128,
+}
+
+/// Gap-insurance pool and the open gap window (see `ExtParams::oracle_gap_bps`).
+///
+/// The pool is an earmarked slice of `insurance_fund.balance` rather than separate
+/// funds: the levy tags part of the insurance cut of trading fees, and payouts move
+/// insurance to the compensated account's capital.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GapInsurance {
+    /// Earmarked balance (payouts are capped by the insurance balance as well)
+    pub pool: U128,
+    /// Lifetime compensation paid
+    pub total_paid: U128,
+    /// Oracle price of the first crank in `last_slot` (0 = none yet)
+    pub last_price: u64,
+    /// Slot of the last observed crank
+    pub last_slot: u64,
+    /// Slot of the open gap print
+    pub gap_slot: u64,
+    /// Crank price before the gap print (0 = no gap window open)
+    pub pre_gap_price: u64,
+    /// Claims from liquidations at the gap print
+    pub claims: [GapClaim; MAX_GAP_CLAIMS],
+}
+
+/// Liquidation analytics for the current and the last completed epoch.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LiquidationAnalytics {
+    pub current: LiquidationStats,
+    pub previous: LiquidationStats,
+}
+
+/// Oracle deviation statistics for one monitor window.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeviationWindow {
+    /// First slot of the window
+    pub window_start_slot: u64,
+    /// Crank-to-crank price moves observed
+    pub crank_samples: u64,
+    /// Largest crank-to-crank move, in bps of the previous crank price
+    pub crank_max_bps: u64,
+    /// Sum of crank-to-crank moves, in bps
+    pub crank_sum_bps: U128,
+    /// Executions observed
+    pub exec_samples: u64,
+    /// Largest oracle-vs-execution gap, in bps of the oracle price
+    pub exec_max_bps: u64,
+    /// Sum of oracle-vs-execution gaps, in bps
+    pub exec_sum_bps: U128,
+}
+
+impl DeviationWindow {
+    /// Mean crank-to-crank move in bps (0 with no samples)
+    pub fn crank_mean_bps(&self) -> u64 {
+        mul_div(self.crank_sum_bps.get(), 1, self.crank_samples as u128, Rounding::Down) as u64
+    }
+
+    /// Mean oracle-vs-execution gap in bps (0 with no samples)
+    pub fn exec_mean_bps(&self) -> u64 {
+        mul_div(self.exec_sum_bps.get(), 1, self.exec_samples as u128, Rounding::Down) as u64
+    }
+}
+
+/// Oracle deviation monitor state (window length: `ExtParams::deviation_window_slots`).
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OracleDeviationMonitor {
+    /// Oracle price of the last monitored crank (0 = none yet)
+    pub last_crank_price: u64,
+    pub current: DeviationWindow,
+    pub previous: DeviationWindow,
+}
+
+/// Start of the `len`-slot window containing `now_slot` (windows aligned to multiples of `len`).
+#[inline]
+fn window_start_slot(now_slot: u64, len: u64) -> u64 {
+    now_slot.checked_div(len).unwrap_or(0).saturating_mul(len)
+}
+
+/// |a - b| in bps of `reference`, rounded up (u64::MAX if `reference` is 0).
+fn deviation_bps(a: u64, b: u64, reference: u64) -> u64 {
+    if reference == 0 {
+        return u64::MAX;
+    }
+    let bps = mul_div(a.abs_diff(b) as u128, 10_000, reference as u128, Rounding::Up);
+    core::cmp::min(bps, u64::MAX as u128) as u64
+}
+
+/// Main risk engine state - fixed slab with bitmap
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RiskEngine {
+    /// Total vault balance (all deposited funds)
+    pub vault: U128,
+
+    /// Insurance fund
+    pub insurance_fund: InsuranceFund,
+
+    /// Protocol fee revenue by source
+    pub revenue: RevenueBreakdown,
+
+    /// Risk parameters
+    pub params: RiskParams,
+
+    /// Current slot (for warmup calculations)
+    pub current_slot: u64,
+
+    /// Global funding index (quote per 1 base, scaled by 1e6)
+    pub funding_index_qpb_e6: I128,
+
+    /// Last slot when funding was accrued
+    pub last_funding_slot: u64,
+
+    /// Funding rate (bps per slot) in effect starting at last_funding_slot.
+    /// This is the rate used for the interval [last_funding_slot, next_accrual).
+    /// Anti-retroactivity: state changes at slot t can only affect funding for slots >= t.
+    pub funding_rate_bps_per_slot_last: i64,
+
+    /// Global holding fee index: Σ price × `ExtParams::holding_fee_per_slot_e9` × dt,
+    /// accrued with funding. An account owes |position| × Δindex / (divisor × 1e9).
+    pub holding_fee_index: U128,
+
+    // ========================================
+    // Keeper Crank Tracking
+    // ========================================
+    /// Last slot when keeper crank was executed
+    pub last_crank_slot: u64,
+
+    /// Maximum allowed staleness before crank is required (in slots)
+    pub max_crank_staleness_slots: u64,
+
+    // ========================================
+    // Open Interest Tracking (O(1))
+    // ========================================
+    /// Total open interest = sum of abs(position_size) across all accounts
+    /// This measures total risk exposure in the system.
+    pub total_open_interest: U128,
+
+    // ========================================
+    // O(1) Aggregates (spec §2.2, §4)
+    // ========================================
+    /// Sum of all account capital: C_tot = Σ C_i
+    /// Maintained incrementally via set_capital() helper.
+    pub c_tot: U128,
+
+    /// Sum of all positive PnL: PNL_pos_tot = Σ max(PNL_i, 0)
+    /// Maintained incrementally via set_pnl() helper.
+    pub pnl_pos_tot: U128,
+
+    // ========================================
+    // Crank Cursors (bounded scan support)
+    // ========================================
+    /// Cursor for liquidation scan (wraps around MAX_ACCOUNTS)
+    pub liq_cursor: u16,
+
+    /// Cursor for garbage collection scan (wraps around MAX_ACCOUNTS)
+    pub gc_cursor: u16,
+
+    /// Slot when the current full sweep started (step 0 was executed)
+    pub last_full_sweep_start_slot: u64,
+
+    /// Slot when the last full sweep completed
+    pub last_full_sweep_completed_slot: u64,
+
+    /// Cursor: index where the next crank will start scanning
+    pub crank_cursor: u16,
+
+    /// Index where the current sweep started (for completion detection)
+    pub sweep_start_idx: u16,
+
+    // ========================================
+    // Sharded Crank (see `keeper_crank_shard`)
+    // ========================================
+    /// Whether a sharded sweep is in progress
+    pub crank_shard_sweep_active: bool,
+
+    /// Shard count of the current sharded sweep
+    pub crank_shard_count: u16,
+
+    /// Bit i set once shard i has scanned its whole range this sweep
+    pub crank_shard_done: u64,
+
+    /// Next index each shard scans from
+    pub crank_shard_cursors: [u16; MAX_CRANK_SHARDS],
+
+    /// In-progress lp_max_abs for the sharded sweep (committed when all shards finish)
+    pub crank_shard_lp_max_abs: U128,
+
+    // ========================================
+    // Lifetime Counters (telemetry)
+    // ========================================
+    /// Total number of liquidations performed (lifetime)
+    pub lifetime_liquidations: u64,
+
+    /// Total number of force-realize closes performed (lifetime)
+    pub lifetime_force_realize_closes: u64,
+
+    // ========================================
+    // LP Aggregates (O(1) maintained for funding/threshold)
+    // ========================================
+    /// Net LP position: sum of position_size across all LP accounts
+    /// Updated incrementally in execute_trade and close paths
+    pub net_lp_pos: I128,
+
+    /// Sum of abs(position_size) across all LP accounts
+    /// Updated incrementally in execute_trade and close paths
+    pub lp_sum_abs: U128,
+
+    /// Max abs(position_size) across all LP accounts (monotone upper bound)
+    /// Only increases; reset via bounded sweep at sweep completion
+    pub lp_max_abs: U128,
+
+    /// In-progress max abs for current sweep (reset at sweep start, committed at completion)
+    pub lp_max_abs_sweep: U128,
+
+    // ========================================
+    // Extended Parameters & Oracle Tracking
+    // ========================================
+    /// Extended (opt-in) parameters; all-zero disables every extension
+    pub ext_params: ExtParams,
+
+    /// Feed used by the most recent oracle-selected crank
+    pub last_oracle_feed: OracleFeed,
+
+    /// Price accepted by the most recent oracle-selected crank
+    pub last_oracle_price: u64,
+
+    /// Publish slot of the quote accepted by the most recent oracle-selected crank
+    pub last_oracle_publish_slot: u64,
+
+    // ========================================
+    // Parameter Governance
+    // ========================================
+    /// Queued parameter update (inactive when `active` is false)
+    pub pending_params: PendingParamsUpdate,
+
+    /// Guardian key: may cancel a queued update during its timelock, nothing else.
+    /// All zeros = no guardian.
+    pub guardian: [u8; 32],
+
+    /// M-of-N council whose approvals gate sensitive admin actions
+    pub admin_council: AdminCouncil,
+
+    /// Queued insurance withdrawal (inactive when `active` is false)
+    pub pending_insurance_withdrawal: PendingInsuranceWithdrawal,
+
+    // ========================================
+    // Event Stream
+    // ========================================
+    /// Sequence number of the most recent event (0 = none yet)
+    pub event_seq: u64,
+
+    /// Mutation sequence number: bumped by every successful public state-changing
+    /// entry point and parameter change (see `with_expected_seq`)
+    pub state_seq: u64,
+
+    /// Ring buffer of the most recent events, indexed by (seq - 1) % EVENT_LOG_LEN
+    pub event_log: [EventRecord; EVENT_LOG_LEN],
+
+    /// Fill ID of the most recent trade (0 = none yet)
+    pub last_fill_id: u64,
+
+    // ========================================
+    // Market Time Series
+    // ========================================
+    /// Number of market samples ever recorded
+    pub series_count: u64,
+
+    /// Ring of the most recent samples, sample n (1-based) at (n - 1) % MARKET_SERIES_LEN
+    pub market_series: [MarketSample; MARKET_SERIES_LEN],
+
+    // ========================================
+    // Price History
+    // ========================================
+    /// Number of crank prices ever recorded (one per slot)
+    pub price_history_count: u64,
+
+    /// Ring of the most recent crank prices, point n (1-based) at (n - 1) % PRICE_HISTORY_LEN
+    pub price_history: [PricePoint; PRICE_HISTORY_LEN],
+
+    // ========================================
+    // Liquidation Analytics
+    // ========================================
+    /// Per-epoch liquidation counters (epoch length: `ExtParams::liq_stats_epoch_slots`)
+    pub liq_analytics: LiquidationAnalytics,
+
+    // ========================================
+    // Oracle Deviation Monitor
+    // ========================================
+    /// Crank-to-crank and oracle-vs-execution price deviation windows
+    pub oracle_deviation: OracleDeviationMonitor,
+
+    // ========================================
+    // LP Withdrawal Queue
+    // ========================================
+    /// Start slot of the last LP withdrawal epoch the crank settled
+    pub lp_epoch_start_slot: u64,
+
+    /// Σ lp_claimable: released LP capital still held in the vault
+    pub lp_claimable_total: U128,
+
+    // ========================================
+    // LP Performance Fee
+    // ========================================
+    /// Start slot of the last performance fee epoch the crank assessed
+    pub lp_performance_epoch_start_slot: u64,
+
+    // ========================================
+    // LP Pool Holdings
+    // ========================================
+    /// Outside depositors' shares in LP accounts (see src/lp_pool.rs)
+    pub lp_holdings: [LpHolding; MAX_LP_HOLDINGS],
+
+    // ========================================
+    // Shared Epoch
+    // ========================================
+    /// Start slot of the shared epoch as of the last crank (see `epoch`)
+    pub epoch_start_slot: u64,
+
+    /// Number of shared epoch rollovers observed by the crank
+    pub epoch_rollovers: u64,
+
+    // ========================================
+    // Yield-Bearing Collateral
+    // ========================================
+    /// Margin value of one collateral unit, 1e9 scale (0 = 1:1, not yield-bearing)
+    pub collateral_rate_e9: u64,
+
+    /// Slot of the last collateral exchange-rate update
+    pub collateral_rate_slot: u64,
+
+    // ========================================
+    // Quanto
+    // ========================================
+    /// Conversion price of a quanto market: quote per collateral unit × price scale
+    /// (0 = not yet reported; trades are rejected)
+    pub quanto_price: u64,
+
+    /// Slot of the last quanto conversion price update
+    pub quanto_price_slot: u64,
+
+    /// Oracle EMA maintained by the crank over `ExtParams::close_price_ema_slots`
+    /// (0 = not yet seeded)
+    pub mark_ema_price: u64,
+
+    /// Slot of the last mark EMA update
+    pub mark_ema_slot: u64,
+
+    /// Execution price of the most recent fill (0 = none yet)
+    pub last_exec_price: u64,
+
+    /// Slot of the most recent fill
+    pub last_exec_slot: u64,
+
+    /// Generator for randomized mechanisms (see `set_rng_seed`)
+    pub rng: Prng,
+
+    /// Scheduled promotional fee window
+    pub fee_holiday: FeeHoliday,
+
+    /// Scheduled one-off margin events such as expiries (0 = empty slot)
+    pub margin_events: [u64; MAX_MARGIN_EVENTS],
+
+    /// Gap-insurance pool and pending claims
+    pub gap_insurance: GapInsurance,
+
+    /// Oracle EMAs per `PriceUse` (see `smoothed_price`)
+    pub price_emas: [PriceEma; 3],
+
+    /// Detail of the most recent trade rejection (scratch state: a failed
+    /// transaction reverts it on-chain; read by `execute_trade_detailed`)
+    pub last_trade_rejection: TradeRejectReason,
+
+    // ========================================
+    // Slab Management
+    // ========================================
+    /// Occupancy bitmap (4096 bits = 64 u64 words)
+    pub used: [u64; BITMAP_WORDS],
+
+    /// Number of used accounts (O(1) counter, fixes H2: fee bypass TOCTOU)
+    pub num_used_accounts: u16,
+
+    /// Next account ID to assign (monotonically increasing, never recycled)
+    pub next_account_id: u64,
+
+    /// Freelist head (u16::MAX = none)
+    pub free_head: u16,
+
+
+    /// Freelist next pointers
+    pub next_free: [u16; MAX_ACCOUNTS],
+
+    /// Account slab (4096 accounts)
+    pub accounts: [Account; MAX_ACCOUNTS],
+}
+
+// ============================================================================
+// Error Types
+// ============================================================================
+
+/// Engine error. Discriminants are stable and append-only: `code()` maps them
+/// to on-chain custom error codes, so never reorder or reuse a value.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RiskError {
+    /// Insufficient balance for operation
+    InsufficientBalance = 0,
+
+    /// Account would become undercollateralized
+    Undercollateralized = 1,
+
+    /// Unauthorized operation
+    Unauthorized = 2,
+
+    /// Invalid matching engine
+    InvalidMatchingEngine = 3,
+
+    /// PNL not yet warmed up
+    PnlNotWarmedUp = 4,
+
+    /// Arithmetic overflow
+    Overflow = 5,
+
+    /// Account not found
+    AccountNotFound = 6,
+
+    /// Account is not an LP account
+    NotAnLPAccount = 7,
+
+    /// Position size mismatch
+    PositionSizeMismatch = 8,
+
+    /// Account kind mismatch
+    AccountKindMismatch = 9,
+
+    /// Parameters failed validation
+    InvalidParams = 10,
+
+    /// No oracle feed passed staleness/confidence/identity checks
+    OracleUnavailable = 11,
+
+    /// No parameter update is queued
+    NoPendingUpdate = 12,
+
+    /// Queued parameter update has not reached its eta slot
+    TimelockActive = 13,
+
+    /// LP withdrawals go through the queue, or a queued withdrawal is pending
+    WithdrawalQueued = 14,
+
+    /// LP capital is within its lockup period and early exit is disabled
+    LpLocked = 15,
+
+    /// Owner's aggregate notional or capital limit would be exceeded
+    OwnerLimitExceeded = 16,
+
+    /// Vault utilization is past the band that permits the operation
+    UtilizationTooHigh = 17,
+
+    /// Account's per-epoch vault drain cap would be exceeded
+    DrainLimitExceeded = 18,
+
+    /// Admin action lacks the required council approvals
+    ApprovalsMissing = 19,
+
+    /// Trade would push net LP skew past what LP capital can absorb
+    SkewLimitExceeded = 20,
+
+    /// Caller's expected state sequence number is out of date
+    StaleSequence = 21,
+
+    /// Account is in reduce-only mode and the trade would not reduce its position
+    ReduceOnly = 22,
+
+    /// Oracle quote was published before the newest quote already accepted
+    OracleRollback = 23,
+}
+
+/// Offset added to `RiskError` discriminants to form custom program error codes
+/// (Anchor reserves everything below 6000).
+pub const ERROR_CODE_OFFSET: u32 = 6000;
+
+impl RiskError {
+    /// Every variant, in discriminant order
+    pub const ALL: [RiskError; 24] = [
+        RiskError::InsufficientBalance,
+        RiskError::Undercollateralized,
+        RiskError::Unauthorized,
+        RiskError::InvalidMatchingEngine,
+        RiskError::PnlNotWarmedUp,
+        RiskError::Overflow,
+        RiskError::AccountNotFound,
+        RiskError::NotAnLPAccount,
+        RiskError::PositionSizeMismatch,
+        RiskError::AccountKindMismatch,
+        RiskError::InvalidParams,
+        RiskError::OracleUnavailable,
+        RiskError::NoPendingUpdate,
+        RiskError::TimelockActive,
+        RiskError::WithdrawalQueued,
+        RiskError::LpLocked,
+        RiskError::OwnerLimitExceeded,
+        RiskError::UtilizationTooHigh,
+        RiskError::DrainLimitExceeded,
+        RiskError::ApprovalsMissing,
+        RiskError::SkewLimitExceeded,
+        RiskError::StaleSequence,
+        RiskError::ReduceOnly,
+        RiskError::OracleRollback,
+    ];
+
+    /// Stable custom program error code (`ERROR_CODE_OFFSET` + discriminant)
+    pub const fn code(self) -> u32 {
+        ERROR_CODE_OFFSET.wrapping_add(self as u32)
+    }
+
+    /// Inverse of `code`
+    pub fn from_code(code: u32) -> Option<Self> {
+        let idx = code.checked_sub(ERROR_CODE_OFFSET)? as usize;
+        Self::ALL.get(idx).copied()
+    }
+
+    /// Variant name, as it appears in IDL metadata
+    pub const fn name(self) -> &'static str {
+        match self {
+            RiskError::InsufficientBalance => "InsufficientBalance",
+            RiskError::Undercollateralized => "Undercollateralized",
+            RiskError::Unauthorized => "Unauthorized",
+            RiskError::InvalidMatchingEngine => "InvalidMatchingEngine",
+            RiskError::PnlNotWarmedUp => "PnlNotWarmedUp",
+            RiskError::Overflow => "Overflow",
+            RiskError::AccountNotFound => "AccountNotFound",
+            RiskError::NotAnLPAccount => "NotAnLPAccount",
+            RiskError::PositionSizeMismatch => "PositionSizeMismatch",
+            RiskError::AccountKindMismatch => "AccountKindMismatch",
+            RiskError::InvalidParams => "InvalidParams",
+            RiskError::OracleUnavailable => "OracleUnavailable",
+            RiskError::NoPendingUpdate => "NoPendingUpdate",
+            RiskError::TimelockActive => "TimelockActive",
+            RiskError::WithdrawalQueued => "WithdrawalQueued",
+            RiskError::LpLocked => "LpLocked",
+            RiskError::OwnerLimitExceeded => "OwnerLimitExceeded",
+            RiskError::UtilizationTooHigh => "UtilizationTooHigh",
+            RiskError::DrainLimitExceeded => "DrainLimitExceeded",
+            RiskError::ApprovalsMissing => "ApprovalsMissing",
+            RiskError::SkewLimitExceeded => "SkewLimitExceeded",
+            RiskError::StaleSequence => "StaleSequence",
+            RiskError::ReduceOnly => "ReduceOnly",
+            RiskError::OracleRollback => "OracleRollback",
+        }
+    }
+
+    /// Human-readable message
+    pub const fn message(self) -> &'static str {
+        match self {
+            RiskError::InsufficientBalance => "Insufficient balance for operation",
+            RiskError::Undercollateralized => "Account would become undercollateralized",
+            RiskError::Unauthorized => "Unauthorized operation",
+            RiskError::InvalidMatchingEngine => "Invalid matching engine",
+            RiskError::PnlNotWarmedUp => "PNL not yet warmed up",
+            RiskError::Overflow => "Arithmetic overflow",
+            RiskError::AccountNotFound => "Account not found",
+            RiskError::NotAnLPAccount => "Account is not an LP account",
+            RiskError::PositionSizeMismatch => "Position size mismatch",
+            RiskError::AccountKindMismatch => "Account kind mismatch",
+            RiskError::InvalidParams => "Parameters failed validation",
+            RiskError::OracleUnavailable => {
+                "No oracle feed passed staleness/confidence/identity checks"
+            }
+            RiskError::NoPendingUpdate => "No parameter update is queued",
+            RiskError::TimelockActive => "Queued parameter update has not reached its eta slot",
+            RiskError::WithdrawalQueued => {
+                "LP withdrawals go through the queue, or a queued withdrawal is pending"
+            }
+            RiskError::LpLocked => "LP capital is within its lockup period and early exit is disabled",
+            RiskError::OwnerLimitExceeded => {
+                "Owner's aggregate notional or capital limit would be exceeded"
+            }
+            RiskError::UtilizationTooHigh => {
+                "Vault utilization is past the band that permits the operation"
+            }
+            RiskError::DrainLimitExceeded => {
+                "Account's per-epoch vault drain cap would be exceeded"
+            }
+            RiskError::ApprovalsMissing => "Admin action lacks the required council approvals",
+            RiskError::SkewLimitExceeded => {
+                "Trade would push net LP skew past what LP capital can absorb"
+            }
+            RiskError::StaleSequence => "Caller's expected state sequence number is out of date",
+            RiskError::ReduceOnly => {
+                "Account is in reduce-only mode and the trade would not reduce its position"
+            }
+            RiskError::OracleRollback => {
+                "Oracle quote was published before the newest quote already accepted"
+            }
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, RiskError>;
+
+/// Receipt for an executed trade, returned by `RiskEngine::execute_trade`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TradeReceipt {
+    /// Monotonically increasing fill ID (first fill = 1); 0 when the matcher did not fill
+    pub fill_id: u64,
+    /// Execution price reported by the matcher
+    pub exec_price: u64,
+    /// Executed size, user side
+    pub exec_size: i128,
+    /// Trading fee charged to the user
+    pub fee: u128,
+    /// User position after the fill
+    pub new_position: i128,
+    /// User MTM equity over position notional after the fill, in bps
+    /// (`u64::MAX` when flat)
+    pub new_margin_ratio_bps: u64,
+}
+
+/// Detail behind a trade rejection (see `RiskEngine::execute_trade_detailed`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TradeRejectReason {
+    /// Nothing beyond the error code
+    #[default]
+    Other,
+    /// Post-trade equity of `account` does not exceed the margin `required`
+    Margin {
+        account: u16,
+        required: u128,
+        equity: u128,
+    },
+    /// The last crank is older than the staleness limit
+    CrankStale {
+        last_crank_slot: u64,
+        max_staleness_slots: u64,
+    },
+    /// The last full sweep started longer ago than the staleness limit
+    SweepStale {
+        last_sweep_start_slot: u64,
+        max_staleness_slots: u64,
+    },
+    /// Vault utilization is in the hard band (risk-reducing trades only)
+    Utilization { bps: u64, hard_bps: u64 },
+    /// Net LP skew cap: position units still available in the trade's direction
+    Skew { remaining: u128 },
+    /// Owner notional cap: notional still available to the account's owner
+    OwnerLimit { remaining: u128 },
+    /// `account` is in reduce-only mode
+    ReduceOnly { account: u16 },
+    /// Matcher fill price is `bps` from the oracle, outside the `band_bps` band
+    PriceBand { exec_price: u64, bps: u64, band_bps: u64 },
+}
+
+/// A rejected trade: the error code plus the numbers a client needs to adjust.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TradeRejection {
+    pub error: RiskError,
+    pub reason: TradeRejectReason,
+}
+
+/// `health_check` anomaly: `c_tot` differs from the sum of account capital
+pub const HEALTH_CAPITAL_TOTAL: u32 = 1 << 0;
+/// `health_check` anomaly: `pnl_pos_tot` differs from the sum of positive PnL
+pub const HEALTH_PNL_POS_TOTAL: u32 = 1 << 1;
+/// `health_check` anomaly: `total_open_interest` differs from the sum of |position|
+pub const HEALTH_OPEN_INTEREST: u32 = 1 << 2;
+/// `health_check` anomaly: `net_lp_pos`, `lp_sum_abs` or `lp_claimable_total` is off
+pub const HEALTH_LP_AGGREGATES: u32 = 1 << 3;
+/// `health_check` anomaly: `num_used_accounts` disagrees with the bitmap or the limit
+pub const HEALTH_ACCOUNT_COUNT: u32 = 1 << 4;
+/// `health_check` anomaly: vault below capital + insurance + claimable LP withdrawals
+pub const HEALTH_VAULT_SHORTFALL: u32 = 1 << 5;
+/// `health_check` anomaly: revenue breakdown does not sum to `fee_revenue`
+pub const HEALTH_REVENUE: u32 = 1 << 6;
+/// `health_check` anomaly: an account holds an impossible value (sentinel `i128::MIN`,
+/// out-of-range position or entry price, reserve above positive PnL)
+pub const HEALTH_ACCOUNT_STATE: u32 = 1 << 7;
+/// `health_check` anomaly: risk or extended parameters fail validation
+pub const HEALTH_PARAMS: u32 = 1 << 8;
+
+/// Outcome of a keeper crank operation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CrankOutcome {
+    /// Whether the crank was a no-op: a new sweep was due before
+    /// `ExtParams::min_crank_interval_slots` elapsed since the last sweep start
+    /// (or in the same slot)
+    pub skipped: bool,
+    /// Whether the crank successfully advanced last_crank_slot
+    pub advanced: bool,
+    /// Slots forgiven for caller's maintenance (50% discount via time forgiveness)
+    pub slots_forgiven: u64,
+    /// Whether caller's maintenance fee settle succeeded (false if undercollateralized)
+    pub caller_settle_ok: bool,
+    /// Whether force-realize mode is active (insurance at/below threshold)
+    pub force_realize_needed: bool,
+    /// Whether panic_settle_all should be called (system in stress)
+    pub panic_needed: bool,
+    /// Number of accounts liquidated during this crank
+    pub num_liquidations: u32,
+    /// Number of liquidation errors (triggers risk_reduction_only)
+    pub num_liq_errors: u16,
+    /// Number of dust accounts garbage collected during this crank
+    pub num_gc_closed: u32,
+    /// Number of positions force-closed during this crank (when force_realize_needed)
+    pub force_realize_closed: u16,
+    /// Number of force-realize errors during this crank
+    pub force_realize_errors: u16,
+    /// Number of positions force-closed due to max PnL cap
+    pub max_pnl_closed: u16,
+    /// Number of max PnL errors during this crank
+    pub max_pnl_errors: u16,
+    /// Whether OI cap is active (total_open_interest > max_oi_abs)
+    pub oi_cap_active: bool,
+    /// Index where this crank stopped (next crank continues from here)
+    pub last_cursor: u16,
+    /// Whether this crank completed a full sweep of all accounts
+    pub sweep_complete: bool,
+    /// Liquidation fees paid to the crank caller during this crank
+    pub liq_fee_to_keeper: u128,
+    /// Liquidation fees paid to the insurance fund during this crank
+    pub liq_fee_to_insurance: u128,
+    /// Liquidation fees distributed to LP accounts during this crank
+    pub liq_fee_to_lp: u128,
+    /// Maintenance fees paid into insurance during this crank
+    pub maintenance_fees_collected: u128,
+    /// Funding paid by longs (to shorts) settled on visited accounts
+    pub funding_long_to_short: u128,
+    /// Funding paid by shorts (to longs) settled on visited accounts
+    pub funding_short_to_long: u128,
+    /// Total paid to the crank caller (currently its liquidation fee share)
+    pub keeper_rewards: u128,
+    /// Fee revenue booked into the insurance fund during this crank
+    pub insurance_contributions: u128,
+    /// Position notional closed by liquidations during this crank
+    pub liq_notional: u128,
+    /// The liquidation notional budget ran out with an account still below
+    /// maintenance; the cursor stays on it, so crank again
+    pub liq_backlog: bool,
+    /// Index slots the account scan stepped over (occupied or not)
+    pub slots_scanned: u32,
+    /// Work of the settlement phase (budget: `ACCOUNTS_PER_CRANK` visits)
+    pub settle_work: CrankPhaseWork,
+    /// Work of the liquidation phase (budget: `LIQ_BUDGET_PER_CRANK` liquidations,
+    /// or `FORCE_REALIZE_BUDGET_PER_CRANK` closes in force-realize mode)
+    pub liquidate_work: CrankPhaseWork,
+    /// Work of dust collection (budget: `GC_CLOSE_BUDGET` closes)
+    pub gc_work: CrankPhaseWork,
+}
+
+/// Work one crank phase did, as a proxy for the compute it used.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CrankPhaseWork {
+    /// Accounts the phase examined
+    pub accounts: u32,
+    /// Budget units the phase consumed
+    pub budget_used: u32,
+    /// Budget the phase had in this crank (0 when it did not run)
+    pub budget: u32,
+}
+
+/// Crank sweep progress (see `RiskEngine::crank_progress`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CrankProgress {
+    /// Slot the last full sweep completed: every account was settled at least then
+    pub last_full_sweep_completed_slot: u64,
+    /// Slot the current (or last) sweep started
+    pub last_full_sweep_start_slot: u64,
+    /// Slots since the last full sweep completed
+    pub slots_since_full_sweep: u64,
+    /// Index the next unsharded crank scans from (last scanned index + 1)
+    pub cursor: u16,
+    /// Whether an unsharded sweep is part-way through the slab
+    pub sweep_in_progress: bool,
+    /// Occupied accounts the current sweep has not reached yet (all occupied
+    /// accounts between sweeps)
+    pub accounts_pending: u32,
+    /// Whether a sharded sweep is in progress
+    pub sharded_sweep_active: bool,
+    /// Shards finished in the current sharded sweep
+    pub shards_done: u16,
+    /// Shards in the current sharded sweep
+    pub shard_count: u16,
+    /// Occupied accounts the sharded sweep has not reached yet
+    pub shard_accounts_pending: u32,
+}
+
+/// One of `count` equal slices of the account index space, cranked by
+/// `RiskEngine::keeper_crank_shard`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CrankShard {
+    pub index: u16,
+    pub count: u16,
+}
+
+impl CrankShard {
+    /// Account index range [start, end) covered by this shard
+    pub fn range(&self) -> (usize, usize) {
+        let count = self.count as usize;
+        let bound = |i: usize| {
+            MAX_ACCOUNTS
+                .saturating_mul(i)
+                .checked_div(count)
+                .unwrap_or(MAX_ACCOUNTS)
+        };
+        (bound(self.index as usize), bound((self.index as usize).saturating_add(1)))
+    }
+
+    fn bit(&self) -> u64 {
+        1u64.checked_shl(self.index as u32).unwrap_or(0)
+    }
+
+    fn all_bits(count: u16) -> u64 {
+        u64::MAX.checked_shr(64u32.saturating_sub(count as u32)).unwrap_or(0)
+    }
+}
+
+/// What a crank sweep would do under one parameter set (see `shadow_crank`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShadowCrankTally {
+    /// Whether force-realize mode would be active (insurance at/below threshold)
+    pub force_realize_active: bool,
+    /// Accounts that would be liquidated (below maintenance margin)
+    pub liquidations: u32,
+    /// Total position notional (at oracle) of the accounts that would be liquidated
+    pub liquidation_notional: u128,
+    /// Positions that would be force-closed (force-realize, zero equity or dust)
+    pub force_closes: u32,
+    /// Positions that would be force-closed by the max-PnL cap
+    pub max_pnl_closes: u32,
+}
+
+/// Comparison of a full crank sweep under current vs candidate parameters
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShadowCrankReport {
+    /// Occupied accounts examined
+    pub accounts_scanned: u32,
+    /// Outcome under the engine's current parameters
+    pub current: ShadowCrankTally,
+    /// Outcome under the candidate parameters
+    pub candidate: ShadowCrankTally,
+    /// Accounts liquidatable under the candidate but not the current parameters
+    pub newly_liquidatable: u32,
+    /// Accounts liquidatable under the current but not the candidate parameters
+    pub no_longer_liquidatable: u32,
+}
+
+/// Action a crank would take on one account (shadow evaluation)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ShadowAction {
+    None,
+    Liquidate,
+    ForceClose,
+    MaxPnlClose,
+}
+
+/// Value-at-risk and expected shortfall of one book, in collateral units
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RiskMeasure {
+    /// Loss not exceeded with the requested confidence
+    pub var: u128,
+    /// Expected loss given the VaR threshold is exceeded
+    pub es: u128,
+}
+
+/// Parametric VaR / ES for the LP book and the vault (see `value_at_risk`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VarReport {
+    /// Confidence level used, in bps (e.g. 9900 = 99%)
+    pub confidence_bps: u64,
+    /// Price move (bps of oracle) at the VaR quantile
+    pub var_shock_bps: u64,
+    /// Price move (bps of oracle) equivalent to the ES tail average
+    pub es_shock_bps: u64,
+    /// Mark-to-market loss of the aggregate LP inventory
+    pub lp: RiskMeasure,
+    /// Bad debt (losses beyond account equity) the vault would absorb
+    pub vault: RiskMeasure,
+}
+
+/// Hypothetical instantaneous oracle move for `stress_test`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PriceShock {
+    /// Signed move in bps of the current oracle price (-2_000 = -20%)
+    pub move_bps: i64,
+}
+
+/// Effect of one `PriceShock` on a cloned engine
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShockOutcome {
+    pub shock: PriceShock,
+    /// Oracle price after the move (clamped to [1, MAX_ORACLE_PRICE])
+    pub shocked_price: u64,
+    /// Accounts liquidated by the engine's liquidation path at the shocked price
+    pub liquidations: u32,
+    /// Position closed by those liquidations
+    pub liquidated_abs: u128,
+    /// Losses beyond account equity at the shocked price (before liquidation)
+    pub bad_debt: u128,
+    /// Insurance fund balance after liquidations
+    pub insurance_after: u128,
+    /// Reduction of the vault's surplus over all account claims
+    /// (vault − insurance − Σ max(0, equity)), before vs after the shock
+    pub vault_drawdown: u128,
+}
+
+/// Per-shock results of `stress_test`
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StressReport {
+    /// Oracle price the shocks were applied to
+    pub oracle_price: u64,
+    pub outcomes: std::vec::Vec<ShockOutcome>,
+}
+
+/// Read-only copy of an account's state (see `RiskEngine::account_view`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AccountView {
+    pub idx: u16,
+    pub account_id: u64,
+    pub kind: AccountKind,
+    pub owner: [u8; 32],
+    pub tag: [u8; 32],
+    pub capital: u128,
+    pub pnl: i128,
+    pub reserved_pnl: u64,
+    pub position: i128,
+    pub entry_price: u64,
+    pub fee_credits: i128,
+    /// Reduce-only flags (see `Account::reduce_only`)
+    pub reduce_only: u8,
+    /// Outstanding haircut claim (see `Account::haircut_claim`)
+    pub haircut_claim: u128,
+    /// Force-realize-first preference (see `Account::adl_first`)
+    pub adl_first: u8,
+}
+
+/// One entry of `deposit_many` / `withdraw_many`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BulkTransfer {
+    pub idx: u16,
+    pub amount: u128,
+}
+
+/// Account views taken from a single engine state, from `read_many`
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchRead {
+    /// `state_seq` of the state the views reflect
+    pub seq: u64,
+    /// Engine slot of that state
+    pub slot: u64,
+    /// Views of the requested accounts that are in use, in request order
+    pub views: std::vec::Vec<AccountView>,
+}
+
+/// Projected effect of a trade, from `what_if`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImpactReport {
+    /// Open interest notional per unit of vault, in bps (see `utilization_bps`)
+    pub utilization_bps_before: u64,
+    pub utilization_bps_after: u64,
+    /// Counterparty LP's position
+    pub lp_position_before: i128,
+    pub lp_position_after: i128,
+    /// Global skew: net position across all LPs (`net_lp_pos`)
+    pub net_lp_pos_before: i128,
+    pub net_lp_pos_after: i128,
+    /// User's position after the trade
+    pub user_position_after: i128,
+    /// Oracle price at which the user would fall to maintenance margin
+    /// (None when flat or when no price in range liquidates the position)
+    pub liquidation_price: Option<u64>,
+}
+
+/// One account's contribution to vault tail risk, from `risk_contribution`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RiskContribution {
+    pub idx: u16,
+    pub kind: AccountKind,
+    pub position: i128,
+    /// Position notional at the current oracle price
+    pub notional: u128,
+    /// Share of total open interest notional, in bps
+    pub oi_share_bps: u64,
+    /// Adverse price the account was stressed at
+    pub stressed_price: u64,
+    /// Loss beyond the account's capital if force-closed at `stressed_price`
+    pub vault_loss: u128,
+}
+
+/// Prices `shock_bps` below and above `oracle_price`, clamped to [1, MAX_ORACLE_PRICE].
+fn shock_band(oracle_price: u64, shock_bps: u64) -> (u64, u64) {
+    let delta = mul_div(oracle_price as u128, shock_bps as u128, 10_000, Rounding::Up);
+    let down = core::cmp::max((oracle_price as u128).saturating_sub(delta), 1) as u64;
+    let up = core::cmp::min((oracle_price as u128).saturating_add(delta), MAX_ORACLE_PRICE as u128)
+        as u64;
+    (down, up)
+}
+
+/// Point-in-time solvency snapshot, from `solvency_status`.
+///
+/// Account equity is capital + pnl + mark at the given oracle price, before
+/// haircut; it is what every account could claim if the market settled now.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SolvencyStatus {
+    /// Vault balance net of released LP withdrawals awaiting claim
+    pub vault: u128,
+    pub insurance: u128,
+    /// Σ max(0, equity): claims of accounts with positive equity
+    pub total_equity: u128,
+    /// Σ max(0, −equity): losses no account capital can cover
+    pub bad_debt: u128,
+    /// Σ max(0, pnl + mark): positive PnL owed to accounts on top of their capital
+    pub pnl_owed: u128,
+    /// vault / total_equity in bps (insurance counts as backing); ≥ 10_000 means
+    /// every claim can be paid in full. u64::MAX when there are no claims.
+    pub health_bps: u64,
+}
+
+impl SolvencyStatus {
+    pub fn is_solvent(&self) -> bool {
+        self.health_bps >= 10_000
+    }
+
+    /// vault − insurance − total_equity: what is left after paying every claim
+    /// without touching insurance (negative when insurance must step in)
+    pub fn surplus(&self) -> i128 {
+        u128_to_i128_clamped(self.vault)
+            .saturating_sub(u128_to_i128_clamped(self.insurance))
+            .saturating_sub(u128_to_i128_clamped(self.total_equity))
+    }
+}
+
+/// Standard-normal quantiles (z) and tail-average multipliers (φ(z) / (1 − α)),
+/// both ×10_000, by one-sided confidence in bps. Linearly interpolated.
+const NORMAL_TAIL_TABLE: [(u64, u64, u64); 6] = [
+    (9_000, 12_816, 17_550),
+    (9_500, 16_449, 20_627),
+    (9_750, 19_600, 23_378),
+    (9_900, 23_263, 26_652),
+    (9_950, 25_758, 28_919),
+    (9_990, 30_902, 33_671),
+];
+
+// ============================================================================
+// Math Helpers (Saturating Arithmetic for Safety)
+// ============================================================================
+
+#[inline]
+fn add_u128(a: u128, b: u128) -> u128 {
+    a.saturating_add(b)
+}
+
+#[inline]
+fn sub_u128(a: u128, b: u128) -> u128 {
+    a.saturating_sub(b)
+}
+
+#[inline]
+fn mul_u128(a: u128, b: u128) -> u128 {
+    a.saturating_mul(b)
+}
+
+/// Rounding direction for `mul_div`.
+///
+/// Policy: amounts owed to the vault (fees, margin requirements) round `Up`;
+/// amounts paid out of the vault (fee shares, rebates) round `Down`. Rounding
+/// never favors the account at the vault's expense.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    /// Round toward negative infinity (truncation for unsigned values)
+    Down,
+    /// Round toward positive infinity
+    Up,
+}
+
+/// Compute `a * b / d` with an explicit rounding direction.
+///
+/// The product saturates at `u128::MAX` (so does the result, divided by `d`);
+/// division by zero yields zero.
+#[inline]
+pub fn mul_div(a: u128, b: u128, d: u128, rounding: Rounding) -> u128 {
+    let product = a.saturating_mul(b);
+    let q = product.checked_div(d).unwrap_or(0);
+    match rounding {
+        Rounding::Down => q,
+        Rounding::Up if product.checked_rem(d).unwrap_or(0) != 0 => q.saturating_add(1),
+        Rounding::Up => q,
+    }
+}
+
+/// (z, ES multiplier), ×10_000, for a one-sided confidence level in bps.
+fn normal_tail_multipliers(confidence_bps: u64) -> Option<(u64, u64)> {
+    let mut prev = NORMAL_TAIL_TABLE[0];
+    if confidence_bps < prev.0 {
+        return None;
+    }
+    for &point in NORMAL_TAIL_TABLE.iter() {
+        if confidence_bps <= point.0 {
+            if confidence_bps == point.0 {
+                return Some((point.1, point.2));
+            }
+            let span = point.0.saturating_sub(prev.0) as u128;
+            let t = confidence_bps.saturating_sub(prev.0) as u128;
+            let lerp = |a: u64, b: u64| {
+                (a as u128).saturating_add(mul_div(
+                    b.saturating_sub(a) as u128,
+                    t,
+                    span,
+                    Rounding::Up,
+                )) as u64
+            };
+            return Some((lerp(prev.1, point.1), lerp(prev.2, point.2)));
+        }
+        prev = point;
+    }
+    None
+}
+
+/// Ring buffer position of event `seq` (1-based).
+#[inline]
+fn event_log_index(seq: u64) -> usize {
+    seq.wrapping_sub(1)
+        .checked_rem(EVENT_LOG_LEN as u64)
+        .unwrap_or(0) as usize
+}
+
+/// Ring buffer position of market sample `n` (1-based).
+#[inline]
+fn market_series_index(n: u64) -> usize {
+    n.wrapping_sub(1)
+        .checked_rem(MARKET_SERIES_LEN as u64)
+        .unwrap_or(0) as usize
+}
+
+/// Ring buffer position of price point `n` (1-based).
+#[inline]
+fn price_history_index(n: u64) -> usize {
+    n.wrapping_sub(1)
+        .checked_rem(PRICE_HISTORY_LEN as u64)
+        .unwrap_or(0) as usize
+}
+
+/// Convert a price between scales (e.g. an e8 feed into an e6 market).
+///
+/// Saturates at `u64::MAX`; a zero `from_scale` yields zero.
+#[inline]
+pub fn rescale_price(price: u64, from_scale: u64, to_scale: u64, rounding: Rounding) -> u64 {
+    let scaled = mul_div(price as u128, to_scale as u128, from_scale as u128, rounding);
+    core::cmp::min(scaled, u64::MAX as u128) as u64
+}
+
+/// Convert `amount` from a `from_decimals` atomic unit to a `to_decimals` one, or
+/// `None` if either exceeds `MAX_TOKEN_DECIMALS` or the result overflows.
+pub fn rescale_decimals(
+    amount: u128,
+    from_decimals: u8,
+    to_decimals: u8,
+    rounding: Rounding,
+) -> Option<u128> {
+    if from_decimals > MAX_TOKEN_DECIMALS || to_decimals > MAX_TOKEN_DECIMALS {
+        return None;
+    }
+    if to_decimals >= from_decimals {
+        amount.checked_mul(pow10(to_decimals.saturating_sub(from_decimals)))
+    } else {
+        let den = pow10(from_decimals.saturating_sub(to_decimals));
+        Some(mul_div(amount, 1, den, rounding))
+    }
+}
+
+/// 10^`decimals`, with `decimals` capped at `MAX_TOKEN_DECIMALS`.
+#[inline]
+fn pow10(decimals: u8) -> u128 {
+    10u128.saturating_pow(core::cmp::min(decimals, MAX_TOKEN_DECIMALS) as u32)
+}
+
+/// Token-2022 transfer fee on `amount`: `ceil(amount × fee_bps / 10_000)`, capped at
+/// `max_fee` (0 = uncapped).
+pub fn transfer_fee(amount: u128, fee_bps: u16, max_fee: u64) -> u128 {
+    let fee = mul_div(amount, fee_bps as u128, 10_000, Rounding::Up);
+    if max_fee > 0 {
+        core::cmp::min(fee, max_fee as u128)
+    } else {
+        fee
+    }
+}
+
+/// Staleness and confidence checks shared by oracle failover and the oracle account
+/// adapters. A zero limit disables that check.
+pub(crate) fn oracle_quote_fresh(
+    quote: &OracleQuote,
+    now_slot: u64,
+    max_staleness_slots: u64,
+    max_conf_bps: u64,
+) -> bool {
+    if max_staleness_slots > 0 && now_slot.saturating_sub(quote.publish_slot) > max_staleness_slots {
+        return false;
+    }
+    if max_conf_bps > 0
+        && mul_u128(quote.conf as u128, 10_000) > mul_u128(quote.price as u128, max_conf_bps as u128)
+    {
+        return false;
+    }
+    true
+}
+
+/// `N` bytes of `data` at `off` for the account parsers (zeros if out of range;
+/// callers check the account length first).
+pub(crate) fn read_le<const N: usize>(data: &[u8], off: usize) -> [u8; N] {
+    let mut out = [0u8; N];
+    if let Some(src) = data.get(off..off.saturating_add(N)) {
+        out.copy_from_slice(src);
+    }
+    out
+}
+
+/// Collateral-unit notional of `abs_pos` at `price`: `abs_pos × price / D` for
+/// linear markets, `abs_pos × D / price` for inverse markets.
+#[inline]
+fn notional_for(mode: PayoffMode, abs_pos: u128, price: u64, divisor: u128, rounding: Rounding) -> u128 {
+    match mode {
+        PayoffMode::Linear | PayoffMode::Quanto => {
+            mul_div(abs_pos, price as u128, divisor, rounding)
+        }
+        PayoffMode::Inverse => mul_div(abs_pos, divisor, price as u128, rounding),
+    }
+}
+
+/// Margin at `bps` of `notional` in collateral units worth `collateral_rate_e9 / 1e9`
+/// each (0 = 1:1).
+#[inline]
+fn margin_required_for(notional: u128, bps: u64, collateral_rate_e9: u64) -> u128 {
+    let required = mul_div(notional, bps as u128, 10_000, Rounding::Up);
+    match collateral_rate_e9 {
+        0 => required,
+        rate => mul_div(required, 1_000_000_000, rate as u128, Rounding::Up),
+    }
+}
+
+/// `bps` raised to the margin implied by each non-zero leverage cap in `caps`.
+#[inline]
+fn leverage_capped_bps(bps: u64, caps: [u16; 2]) -> u64 {
+    caps.iter()
+        .filter(|&&cap| cap > 0)
+        .fold(bps, |bps, &cap| core::cmp::max(bps, 10_000u64.div_ceil(cap as u64)))
+}
+
+/// Event margin (bps) at `slot` for the scheduled `events` and recurring `period`
+/// (see `RiskEngine::event_initial_margin_bps`); 0 outside every event's `window`.
+fn event_margin_bps_for(
+    events: &[u64],
+    window: u64,
+    period: u64,
+    event_bps: u64,
+    base_bps: u64,
+    slot: u64,
+) -> u64 {
+    if window == 0 {
+        return 0;
+    }
+    let mut distance = events
+        .iter()
+        .filter(|&&event| event != 0)
+        .map(|&event| slot.abs_diff(event))
+        .min()
+        .unwrap_or(u64::MAX);
+    if let Some(since) = slot.checked_rem(period) {
+        // Nearest recurring event, the last one or the next
+        let until = period.saturating_sub(since);
+        distance = core::cmp::min(distance, core::cmp::min(since, until));
+    }
+    if distance >= window {
+        return 0;
+    }
+    let extra = mul_div(
+        event_bps.saturating_sub(base_bps) as u128,
+        window.saturating_sub(distance) as u128,
+        window as u128,
+        Rounding::Up,
+    );
+    base_bps.saturating_add(extra as u64)
+}
+
+/// Oracle price scale of `ext` (`DEFAULT_PRICE_SCALE` when unset).
+#[inline]
+fn price_scale_for(ext: &ExtParams) -> u64 {
+    if ext.price_scale == 0 {
+        DEFAULT_PRICE_SCALE
+    } else {
+        ext.price_scale
+    }
+}
+
+/// Effective divisor `price_scale × 10^base_decimals / 10^quote_decimals`, or `None`
+/// if it is not a positive integer or the decimals are out of range.
+fn price_divisor_for(ext: &ExtParams) -> Option<u128> {
+    if ext.base_decimals > MAX_TOKEN_DECIMALS || ext.quote_decimals > MAX_TOKEN_DECIMALS {
+        return None;
+    }
+    let scale = price_scale_for(ext);
+    let num = (scale as u128).checked_mul(10u128.checked_pow(ext.base_decimals as u32)?)?;
+    let den = 10u128.checked_pow(ext.quote_decimals as u32)?;
+    if num.checked_rem(den)? != 0 {
+        return None;
+    }
+    let divisor = num.checked_div(den)?;
+    if divisor == 0 {
+        None
+    } else {
+        Some(divisor)
+    }
+}
+
+#[inline]
+fn clamp_pos_i128(val: i128) -> u128 {
+    if val > 0 {
+        val as u128
+    } else {
+        0
+    }
+}
+
+#[allow(dead_code)]
+#[inline]
+fn clamp_neg_i128(val: i128) -> u128 {
+    if val < 0 {
+        neg_i128_to_u128(val)
+    } else {
+        0
+    }
+}
+
+/// Saturating absolute value for i128 (handles i128::MIN without overflow)
+#[inline]
+fn saturating_abs_i128(val: i128) -> i128 {
+    if val == i128::MIN {
+        i128::MAX
+    } else {
+        val.abs()
+    }
+}
+
+/// Safely convert negative i128 to u128 (handles i128::MIN without overflow)
+///
+/// For i128::MIN, -i128::MIN would overflow because i128::MAX + 1 cannot be represented.
+/// We handle this by returning (i128::MAX as u128) + 1 = 170141183460469231731687303715884105728.
+#[inline]
+fn neg_i128_to_u128(val: i128) -> u128 {
+    debug_assert!(val < 0, "neg_i128_to_u128 called with non-negative value");
+    // unsigned_abs handles i128::MIN without overflow
+    val.unsigned_abs()
+}
+
+/// Safely convert u128 to i128 with clamping (handles values > i128::MAX)
+///
+/// If x > i128::MAX, the cast would wrap to a negative value.
+/// We clamp to i128::MAX instead to preserve correctness of margin checks.
+#[inline]
+fn u128_to_i128_clamped(x: u128) -> i128 {
+    I128::from_u128_clamped(x).get()
+}
+
+// ============================================================================
+// Deterministic PRNG
+// ============================================================================
+
+/// Seedable SplitMix64 generator for randomized mechanisms (audit sampling,
+/// tie-breaking). The sequence depends only on the seed, so simulations replay
+/// exactly and an on-chain caller can seed it from a verifiable source such as a
+/// recent slot hash.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Prng {
+    pub state: u64,
+}
+
+impl Prng {
+    pub const fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Seed from 32 bytes (slot hash, VRF output): the four little-endian words
+    /// are XOR-folded.
+    pub fn from_seed_bytes(seed: &[u8; 32]) -> Self {
+        let mut state = 0u64;
+        for chunk in seed.chunks_exact(8) {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(chunk);
+            state ^= u64::from_le_bytes(word);
+        }
+        Self::new(state)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Value in `0..bound` by multiply-high reduction (0 if `bound` is 0)
+    pub fn next_below(&mut self, bound: u64) -> u64 {
+        let wide = (self.next_u64() as u128).wrapping_mul(bound as u128);
+        (wide >> 64) as u64
+    }
+}
+
+// ============================================================================
+// Matching Engine Trait
+// ============================================================================
+
+/// Result of a successful trade execution from the matching engine
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TradeExecution {
+    /// Actual execution price (may differ from oracle/requested price)
+    pub price: u64,
+    /// Actual executed size (may be partial fill)
+    pub size: i128,
+}
+
+/// Trait for pluggable matching engines
+///
+/// Implementers can provide custom order matching logic via CPI.
+/// The matching engine is responsible for validating and executing trades
+/// according to its own rules (CLOB, AMM, RFQ, etc).
+pub trait MatchingEngine {
+    /// Execute a trade between LP and user
+    ///
+    /// # Arguments
+    /// * `lp_program` - The LP's matching engine program ID
+    /// * `lp_context` - The LP's matching engine context account
+    /// * `lp_account_id` - Unique ID of the LP account (never recycled)
+    /// * `oracle_price` - Current oracle price for reference
+    /// * `size` - Requested position size (positive = long, negative = short)
+    ///
+    /// # Returns
+    /// * `Ok(TradeExecution)` with actual executed price and size
+    /// * `Err(RiskError)` if the trade is rejected
+    ///
+    /// # Safety
+    /// The matching engine MUST verify user authorization before approving trades.
+    /// The risk engine will check solvency after the trade executes.
+    fn execute_match(
+        &self,
+        lp_program: &[u8; 32],
+        lp_context: &[u8; 32],
+        lp_account_id: u64,
+        oracle_price: u64,
+        size: i128,
+    ) -> Result<TradeExecution>;
+}
+
+/// No-op matching engine (for testing)
+/// Returns the requested price and size as-is
+pub struct NoOpMatcher;
+
+impl MatchingEngine for NoOpMatcher {
+    fn execute_match(
+        &self,
+        _lp_program: &[u8; 32],
+        _lp_context: &[u8; 32],
+        _lp_account_id: u64,
+        oracle_price: u64,
+        size: i128,
+    ) -> Result<TradeExecution> {
+        // Return requested price/size unchanged (no actual matching logic)
+        Ok(TradeExecution {
+            price: oracle_price,
+            size,
+        })
+    }
+}
+
+// ============================================================================
+// Telemetry
+// ============================================================================
+
+/// Metrics sink invoked by the `*_with_telemetry` entry points.
+///
+/// All callbacks default to no-ops, so implementers only override what they
+/// export (e.g. forward to Prometheus or StatsD). Names are the `METRIC_*`
+/// constants below.
+pub trait Telemetry {
+    /// Monotonic counter increment
+    fn counter(&mut self, _name: &'static str, _delta: u64) {}
+    /// Point-in-time value
+    fn gauge(&mut self, _name: &'static str, _value: u128) {}
+    /// Observation for a distribution
+    fn histogram(&mut self, _name: &'static str, _value: u128) {}
+}
+
+/// Telemetry sink that discards everything
+pub struct NoOpTelemetry;
+
+impl Telemetry for NoOpTelemetry {}
+
+/// Trades executed (counter)
+pub const METRIC_TRADES: &str = "percolator.trades";
+/// Trades rejected by the engine or matcher (counter)
+pub const METRIC_TRADES_REJECTED: &str = "percolator.trades_rejected";
+/// Absolute filled size per trade (histogram)
+pub const METRIC_TRADE_SIZE: &str = "percolator.trade_size";
+/// Notional at oracle per trade (histogram)
+pub const METRIC_TRADE_NOTIONAL: &str = "percolator.trade_notional";
+/// Liquidations executed (counter)
+pub const METRIC_LIQUIDATIONS: &str = "percolator.liquidations";
+/// Liquidation errors during cranks (counter)
+pub const METRIC_LIQUIDATION_ERRORS: &str = "percolator.liquidation_errors";
+/// Position closed per direct liquidation (histogram)
+pub const METRIC_LIQUIDATION_SIZE: &str = "percolator.liquidation_size";
+/// Fee charged per direct liquidation (histogram)
+pub const METRIC_LIQUIDATION_FEE: &str = "percolator.liquidation_fee";
+/// Cranks run (counter)
+pub const METRIC_CRANKS: &str = "percolator.cranks";
+/// Caller-measured crank duration in microseconds (histogram)
+pub const METRIC_CRANK_DURATION_US: &str = "percolator.crank_duration_us";
+/// Accounts settled per crank (histogram)
+pub const METRIC_CRANK_SETTLE_ACCOUNTS: &str = "percolator.crank_settle_accounts";
+/// Accounts checked for liquidation per crank (histogram)
+pub const METRIC_CRANK_LIQUIDATE_ACCOUNTS: &str = "percolator.crank_liquidate_accounts";
+/// Accounts examined by dust collection per crank (histogram)
+pub const METRIC_CRANK_GC_ACCOUNTS: &str = "percolator.crank_gc_accounts";
+/// Cranks that found open interest above the cap (counter)
+pub const METRIC_OI_CAP_HITS: &str = "percolator.oi_cap_hits";
+/// Positions force-closed by the max-PnL cap (counter)
+pub const METRIC_MAX_PNL_CAP_CLOSES: &str = "percolator.max_pnl_cap_closes";
+/// Positions force-closed in force-realize mode (counter)
+pub const METRIC_FORCE_REALIZE_CLOSES: &str = "percolator.force_realize_closes";
+/// Insurance fund balance (gauge)
+pub const METRIC_INSURANCE_BALANCE: &str = "percolator.insurance_balance";
+/// Total open interest (gauge)
+pub const METRIC_OPEN_INTEREST: &str = "percolator.open_interest";
+/// Vault balance (gauge)
+pub const METRIC_VAULT: &str = "percolator.vault";
+
+// ============================================================================
+// Core Implementation
+// ============================================================================
+
+impl RiskEngine {
+    /// Create a new risk engine (stack-allocates the full struct - avoid in BPF!)
+    ///
+    /// WARNING: This allocates ~6MB on the stack at MAX_ACCOUNTS=4096.
+    /// For Solana BPF programs, use `init_in_place` instead.
+    pub fn new(params: RiskParams) -> Self {
+        let mut engine = Self {
+            vault: U128::ZERO,
+            insurance_fund: InsuranceFund {
+                balance: U128::ZERO,
+                fee_revenue: U128::ZERO,
+            },
+            revenue: RevenueBreakdown::default(),
+            params,
+            current_slot: 0,
+            funding_index_qpb_e6: I128::ZERO,
+            last_funding_slot: 0,
+            funding_rate_bps_per_slot_last: 0,
+            holding_fee_index: U128::ZERO,
+            last_crank_slot: 0,
+            max_crank_staleness_slots: params.max_crank_staleness_slots,
+            total_open_interest: U128::ZERO,
+            c_tot: U128::ZERO,
+            pnl_pos_tot: U128::ZERO,
+            liq_cursor: 0,
+            gc_cursor: 0,
+            last_full_sweep_start_slot: 0,
+            last_full_sweep_completed_slot: 0,
+            crank_cursor: 0,
+            sweep_start_idx: 0,
+            crank_shard_sweep_active: false,
+            crank_shard_count: 0,
+            crank_shard_done: 0,
+            crank_shard_cursors: [0; MAX_CRANK_SHARDS],
+            crank_shard_lp_max_abs: U128::ZERO,
+            lifetime_liquidations: 0,
+            lifetime_force_realize_closes: 0,
+            net_lp_pos: I128::ZERO,
+            lp_sum_abs: U128::ZERO,
+            lp_max_abs: U128::ZERO,
+            lp_max_abs_sweep: U128::ZERO,
+            ext_params: ExtParams::default(),
+            last_oracle_feed: OracleFeed::None,
+            last_oracle_price: 0,
+            last_oracle_publish_slot: 0,
+            pending_params: PendingParamsUpdate {
+                active: false,
+                queued_slot: 0,
+                eta_slot: 0,
+                nonce: 0,
+                params,
+                ext_params: ExtParams::default(),
+                approvals: 0,
+            },
+            guardian: [0; 32],
+            admin_council: AdminCouncil::default(),
+            pending_insurance_withdrawal: PendingInsuranceWithdrawal::default(),
+            event_seq: 0,
+            state_seq: 0,
+            event_log: [EventRecord::default(); EVENT_LOG_LEN],
+            last_fill_id: 0,
+            series_count: 0,
+            market_series: [MarketSample::default(); MARKET_SERIES_LEN],
+            price_history_count: 0,
+            price_history: [PricePoint::default(); PRICE_HISTORY_LEN],
+            liq_analytics: LiquidationAnalytics::default(),
+            oracle_deviation: OracleDeviationMonitor::default(),
+            lp_epoch_start_slot: 0,
+            lp_claimable_total: U128::ZERO,
+            lp_performance_epoch_start_slot: 0,
+            lp_holdings: [LpHolding::default(); MAX_LP_HOLDINGS],
+            epoch_start_slot: 0,
+            epoch_rollovers: 0,
+            collateral_rate_e9: 0,
+            collateral_rate_slot: 0,
+            quanto_price: 0,
+            quanto_price_slot: 0,
+            mark_ema_price: 0,
+            mark_ema_slot: 0,
+            last_exec_price: 0,
+            last_exec_slot: 0,
+            rng: Prng::new(0),
+            fee_holiday: FeeHoliday::default(),
+            margin_events: [0; MAX_MARGIN_EVENTS],
+            gap_insurance: GapInsurance::default(),
+            price_emas: [PriceEma::default(); 3],
+            last_trade_rejection: TradeRejectReason::Other,
+            used: [0; BITMAP_WORDS],
+            num_used_accounts: 0,
+            next_account_id: 0,
+            free_head: 0,
+            next_free: [0; MAX_ACCOUNTS],
+            accounts: [empty_account(); MAX_ACCOUNTS],
+        };
+
+        // Initialize freelist: 0 -> 1 -> 2 -> ... -> 4095 -> NONE
+        for i in 0..MAX_ACCOUNTS - 1 {
+            engine.next_free[i] = i.saturating_add(1) as u16;
+        }
+        engine.next_free[MAX_ACCOUNTS - 1] = u16::MAX; // Sentinel
+
+        engine
+    }
+
+    /// Build an engine from an existing set of accounts (snapshot restore, migration)
+    /// in one pass, instead of replaying add/deposit/trade calls.
+    ///
+    /// Accounts are placed at indices 0.. in iteration order and every aggregate is
+    /// recomputed from them. They must have unique account IDs, positions that net
+    /// to zero, reserved PnL covered by positive PnL, and one common funding and
+    /// holding fee index (which becomes the engine's). The vault is set to the
+    /// minimum backing (capital, positive PnL and unclaimed LP withdrawals); seed
+    /// insurance and reconcile against the token account afterwards.
+    ///
+    /// The engine is returned boxed: it is several megabytes, too large for the stack.
+    #[cfg(feature = "std")]
+    pub fn from_accounts<I>(params: RiskParams, accounts: I) -> Result<std::boxed::Box<Self>>
+    where
+        I: IntoIterator<Item = Account>,
+    {
+        let mut engine = std::boxed::Box::new(Self::new(params));
+        let mut ids = std::vec::Vec::new();
+        let mut net_pos = 0i128;
+        let mut first = true;
+        for account in accounts {
+            let idx = engine.num_used_accounts as usize;
+            if idx >= MAX_ACCOUNTS || idx as u64 >= params.max_accounts {
+                return Err(RiskError::Overflow);
+            }
+            let positive_pnl = clamp_pos_i128(account.pnl.get());
+            let index_mismatch = !first
+                && (account.funding_index != engine.funding_index_qpb_e6
+                    || account.holding_fee_index != engine.holding_fee_index);
+            if index_mismatch || (account.reserved_pnl as u128) > positive_pnl {
+                return Err(RiskError::InvalidParams);
+            }
+            ids.push(account.account_id);
+            if first {
+                engine.funding_index_qpb_e6 = account.funding_index;
+                engine.holding_fee_index = account.holding_fee_index;
+                first = false;
+            }
+
+            let pos = account.position_size.get();
+            let abs_pos = pos.unsigned_abs();
+            net_pos = net_pos.checked_add(pos).ok_or(RiskError::Overflow)?;
+            engine.c_tot = engine.c_tot.saturating_add(account.capital.get());
+            engine.pnl_pos_tot = engine.pnl_pos_tot.saturating_add(positive_pnl);
+            engine.total_open_interest = engine.total_open_interest.saturating_add(abs_pos);
+            engine.lp_claimable_total =
+                engine.lp_claimable_total.saturating_add(account.lp_claimable.get());
+            if account.is_lp() {
+                engine.net_lp_pos = engine.net_lp_pos.saturating_add(pos);
+                engine.lp_sum_abs = engine.lp_sum_abs.saturating_add(abs_pos);
+                engine.lp_max_abs = engine.lp_max_abs.max(U128::new(abs_pos));
+            }
+            engine.next_account_id = core::cmp::max(
+                engine.next_account_id,
+                account.account_id.saturating_add(1),
+            );
+
+            engine.accounts[idx] = account;
+            engine.set_used(idx);
+            engine.num_used_accounts = engine.num_used_accounts.saturating_add(1);
+        }
+        if net_pos != 0 {
+            return Err(RiskError::PositionSizeMismatch);
+        }
+        ids.sort_unstable();
+        if ids.windows(2).any(|w| w[0] == w[1]) {
+            return Err(RiskError::InvalidParams);
+        }
+
+        // The freelist built by `new` already chains the unused tail
+        let used = engine.num_used_accounts as usize;
+        engine.free_head = if used < MAX_ACCOUNTS { used as u16 } else { u16::MAX };
+        engine.vault = U128::new(add_u128(
+            engine.c_tot.get(),
+            engine.pnl_pos_tot.get().saturating_add(engine.lp_claimable_total.get()),
+        ));
+        Ok(engine)
+    }
+
+    /// Initialize a RiskEngine in place (zero-copy friendly).
+    ///
+    /// PREREQUISITE: The memory backing `self` MUST be zeroed before calling.
+    /// This method only sets non-zero fields to avoid touching the entire ~6MB struct.
+    ///
+    /// This is the correct way to initialize RiskEngine in Solana BPF programs
+    /// where stack space is limited to 4KB.
+    pub fn init_in_place(&mut self, params: RiskParams) {
+        // Set params (non-zero field)
+        self.params = params;
+        self.max_crank_staleness_slots = params.max_crank_staleness_slots;
+
+        // Initialize freelist: 0 -> 1 -> 2 -> ... -> MAX_ACCOUNTS-1 -> NONE
+        // All other fields are zero which is correct for:
+        // - vault, insurance_fund, current_slot, funding_index, etc. = 0
+        // - used bitmap = all zeros (no accounts in use)
+        // - accounts = all zeros (equivalent to empty_account())
+        // - free_head = 0 (first free slot is 0)
+        for i in 0..MAX_ACCOUNTS - 1 {
+            self.next_free[i] = i.saturating_add(1) as u16;
+        }
+        self.next_free[MAX_ACCOUNTS - 1] = u16::MAX; // Sentinel
+    }
+
+    /// Replace the extended parameters after validation.
+    ///
+    /// Rejects unknown versions, non-zero reserved space, confidence bounds above
+    /// 100% and a fallback feed identical to the primary. Once a timelock is
+    /// configured, changes must go through `queue_params_update` /
+    /// `apply_params_update` so the guardian can veto them; likewise while an
+    /// admin council is active, so its approval is required.
+    pub fn set_ext_params(&mut self, ext: ExtParams) -> Result<()> {
+        if self.admin_council.threshold > 0 {
+            return Err(RiskError::ApprovalsMissing);
+        }
+        if self.ext_params.param_timelock_slots > 0 {
+            return Err(RiskError::TimelockActive);
+        }
+        Self::validate_ext_params(&ext)?;
+        self.check_ext_params_transition(&ext)?;
+        self.ext_params = ExtParams {
+            version: EXT_PARAMS_VERSION,
+            ..ext
+        };
+        self.bump_state_seq();
+        Ok(())
+    }
+
+    /// Validate extended parameters without applying them.
+    pub fn validate_ext_params(ext: &ExtParams) -> Result<()> {
+        if ext.version > EXT_PARAMS_VERSION || ext.reserved.iter().flatten().any(|&b| b != 0) {
+            return Err(RiskError::InvalidParams);
+        }
+        if ext.max_funding_rate_bps_per_slot > 10_000 {
+            return Err(RiskError::InvalidParams);
+        }
+        if ext.oracle_max_conf_bps > 10_000
+            || ext.transfer_fee_bps > 10_000
+            || ext.lp_early_exit_fee_bps > 10_000
+            || ext.lp_performance_fee_bps > 10_000
+        {
+            return Err(RiskError::InvalidParams);
+        }
+        if ext.fallback_oracle != [0u8; 32] && ext.fallback_oracle == ext.primary_oracle {
+            return Err(RiskError::InvalidParams);
+        }
+        if ext.low_leverage_fee_discount_bps > 10_000
+            || (ext.low_leverage_fee_discount_bps > 0 && ext.low_leverage_tier == 0)
+        {
+            return Err(RiskError::InvalidParams);
+        }
+        if ext.quanto_collateral_decimals > MAX_TOKEN_DECIMALS {
+            return Err(RiskError::InvalidParams);
+        }
+        if ext.min_liquidation_bps > 10_000 {
+            return Err(RiskError::InvalidParams);
+        }
+        if ext.holding_fee_per_slot_e9 > 1_000_000_000 {
+            return Err(RiskError::InvalidParams);
+        }
+        if ext.markout_surcharge_bps > 10_000 || ext.insurance_skim_bps > 10_000 {
+            return Err(RiskError::InvalidParams);
+        }
+        if ext.gap_levy_share_bps > 10_000 || ext.gap_revert_tolerance_bps > 10_000 {
+            return Err(RiskError::InvalidParams);
+        }
+        if ext.exec_price_band_bps > 10_000
+            || ext.haircut_claim_payout_bps > 10_000
+            || ext.risk_reducing_fee_discount_bps > 10_000
+            || ext.event_initial_margin_bps > 10_000
+            || ext.adl_first_fee_discount_bps > 10_000
+        {
+            return Err(RiskError::InvalidParams);
+        }
+        for use_ in PriceUse::ALL {
+            let (slots, weight_bps) = ext.price_smoothing(use_);
+            if weight_bps > 10_000 || (weight_bps > 0 && slots == 0) {
+                return Err(RiskError::InvalidParams);
+            }
+        }
+        if ext.utilization_fee_kink_bps > 0
+            && (ext.utilization_fee_full_bps <= ext.utilization_fee_kink_bps
+                || ext.utilization_fee_max_bps > 10_000)
+        {
+            return Err(RiskError::InvalidParams);
+        }
+        if !ext.max_epoch_drain.is_zero() && ext.drain_epoch_slots == 0 && ext.epoch_slots == 0 {
+            return Err(RiskError::InvalidParams);
+        }
+        if ext.close_price_source != ClosePriceSource::Oracle && ext.close_price_ema_slots == 0 {
+            return Err(RiskError::InvalidParams);
+        }
+        if ext.utilization_soft_bps > 0
+            && ext.utilization_hard_bps > 0
+            && ext.utilization_soft_bps > ext.utilization_hard_bps
+        {
+            return Err(RiskError::InvalidParams);
+        }
+        for (i, c) in ext.collaterals.iter().enumerate() {
+            let valid = if c.is_active() {
+                c.haircut_bps <= 10_000
+                    && !ext.collaterals[..i].iter().any(|prev| prev.oracle == c.oracle)
+            } else {
+                *c == CollateralConfig::default()
+            };
+            if !valid {
+                return Err(RiskError::InvalidParams);
+            }
+        }
+        // Fee curve: increasing notionals, unused points trailing and all zero
+        let curve = &ext.fee_curve;
+        let len = curve.iter().take_while(|p| !p.notional.is_zero()).count();
+        let ordered = curve[..len].windows(2).all(|w| w[0].notional < w[1].notional);
+        let unused_zero = curve[len..].iter().all(|p| *p == FeeCurvePoint::default());
+        if !ordered || !unused_zero || curve.iter().any(|p| p.fee_bps > 10_000) {
+            return Err(RiskError::InvalidParams);
+        }
+        if price_divisor_for(ext).is_none() {
+            return Err(RiskError::InvalidParams);
+        }
+        Ok(())
+    }
+
+    /// Check that replacing the current extended parameters with `ext` is safe in
+    /// the current state: the price scale and token decimals define what open
+    /// positions and entry prices mean, so they are fixed while any are open. The
+    /// payoff mode also decides the collateral token, so it is fixed while any
+    /// account exists.
+    fn check_ext_params_transition(&self, ext: &ExtParams) -> Result<()> {
+        let cur = &self.ext_params;
+        let rescaled = ext.price_scale != cur.price_scale
+            || ext.base_decimals != cur.base_decimals
+            || ext.quote_decimals != cur.quote_decimals;
+        if rescaled && !self.total_open_interest.is_zero() {
+            return Err(RiskError::InvalidParams);
+        }
+        if ext.payoff_mode != cur.payoff_mode
+            && (self.num_used_accounts > 0 || !self.total_open_interest.is_zero())
+        {
+            return Err(RiskError::InvalidParams);
+        }
+        Ok(())
+    }
+
+    /// Replace collateral slot `index` (a zero config clears it). The whole updated
+    /// `ExtParams` is validated before anything is written, so a rejected config
+    /// leaves every slot unchanged.
+    pub fn set_collateral_config(&mut self, index: usize, config: CollateralConfig) -> Result<()> {
+        let mut ext = self.ext_params;
+        *ext.collaterals.get_mut(index).ok_or(RiskError::InvalidParams)? = config;
+        self.set_ext_params(ext)
+    }
+
+    // ========================================
+    // Precision
+    // ========================================
+
+    /// Divisor converting `base units × price` into collateral units for this market
+    /// (1e6 with default parameters).
+    ///
+    /// Quanto markets fold the conversion price into it:
+    /// `quanto_price × 10^base_decimals / 10^quanto_collateral_decimals`, so PnL,
+    /// margin and funding settle in collateral at the latest conversion price.
+    #[inline]
+    pub fn price_divisor(&self) -> u128 {
+        let ext = &self.ext_params;
+        if ext.payoff_mode == PayoffMode::Quanto && self.quanto_price > 0 {
+            let divisor = mul_div(
+                self.quanto_price as u128,
+                pow10(ext.base_decimals),
+                pow10(ext.quanto_collateral_decimals),
+                Rounding::Down,
+            );
+            return core::cmp::max(divisor, 1);
+        }
+        price_divisor_for(ext).unwrap_or(DEFAULT_PRICE_SCALE as u128)
+    }
+
+    /// Oracle price scale: prices are quote per base unit × this (1e6 by default).
+    #[inline]
+    pub fn price_scale(&self) -> u64 {
+        price_scale_for(&self.ext_params)
+    }
+
+    /// Decimals of the collateral token: the quote asset for linear markets, the
+    /// base asset for inverse ones.
+    #[inline]
+    pub fn collateral_decimals(&self) -> u8 {
+        match self.ext_params.payoff_mode {
+            PayoffMode::Linear => self.ext_params.quote_decimals,
+            PayoffMode::Inverse => self.ext_params.base_decimals,
+            PayoffMode::Quanto => self.ext_params.quanto_collateral_decimals,
+        }
+    }
+
+    /// Collateral native units for an `amount` given with `decimals` (e.g. a UI
+    /// amount in whole tokens × 10^`decimals`), rounded down.
+    pub fn collateral_units(&self, amount: u128, decimals: u8) -> Option<u128> {
+        rescale_decimals(amount, decimals, self.collateral_decimals(), Rounding::Down)
+    }
+
+    /// Base native units (position size units) for an `amount` given with `decimals`,
+    /// rounded down.
+    pub fn base_units(&self, amount: u128, decimals: u8) -> Option<u128> {
+        rescale_decimals(amount, decimals, self.ext_params.base_decimals, Rounding::Down)
+    }
+
+    /// Quote-currency value (e.g. USD for USD-quoted markets) of `amount` collateral
+    /// units, expressed at the price scale. Linear markets ignore `price`; inverse
+    /// markets value their base-asset collateral at it.
+    pub fn quote_value(&self, amount: u128, price: u64, rounding: Rounding) -> u128 {
+        let unit_price = match self.ext_params.payoff_mode {
+            PayoffMode::Linear => self.price_scale(),
+            PayoffMode::Inverse => price,
+            PayoffMode::Quanto => self.quanto_price,
+        };
+        mul_div(amount, unit_price as u128, pow10(self.collateral_decimals()), rounding)
+    }
+
+    /// Collateral units worth `value` in the quote currency at the price scale
+    /// (inverse of `quote_value`; 0 if an inverse market's `price` is 0).
+    pub fn collateral_for_quote_value(&self, value: u128, price: u64, rounding: Rounding) -> u128 {
+        let unit_price = match self.ext_params.payoff_mode {
+            PayoffMode::Linear => self.price_scale(),
+            PayoffMode::Inverse => price,
+            PayoffMode::Quanto => self.quanto_price,
+        };
+        mul_div(value, pow10(self.collateral_decimals()), unit_price as u128, rounding)
+    }
+
+    /// Collateral-unit notional of `abs_pos` position units at `price`.
+    #[inline]
+    pub fn notional_at(&self, abs_pos: u128, price: u64, rounding: Rounding) -> u128 {
+        notional_for(
+            self.ext_params.payoff_mode,
+            abs_pos,
+            price,
+            self.price_divisor(),
+            rounding,
+        )
+    }
+
+    /// Position units worth `notional` collateral units at `price` (0 if price is 0).
+    #[inline]
+    pub fn base_for_notional(&self, notional: u128, price: u64, rounding: Rounding) -> u128 {
+        match self.ext_params.payoff_mode {
+            PayoffMode::Linear | PayoffMode::Quanto => {
+                mul_div(notional, self.price_divisor(), price as u128, rounding)
+            }
+            PayoffMode::Inverse => mul_div(notional, price as u128, self.price_divisor(), rounding),
+        }
+    }
+
+    /// Mark-to-market PnL of a position under this market's payoff and precision.
+    #[inline]
+    fn mark_pnl(&self, pos: i128, entry: u64, oracle: u64) -> Result<i128> {
+        let divisor = self.price_divisor();
+        match self.ext_params.payoff_mode {
+            PayoffMode::Linear | PayoffMode::Quanto => {
+                Self::mark_pnl_for_position_scaled(pos, entry, oracle, divisor)
+            }
+            PayoffMode::Inverse => Self::mark_pnl_for_position_inverse(pos, entry, oracle, divisor),
+        }
+    }
+
+    // ========================================
+    // Parameter Governance
+    // ========================================
+
+    /// Set the guardian key (admin function). All zeros removes the guardian.
+    ///
+    /// Rejected with `TimelockActive` while a parameter update is queued, so the
+    /// guardian that may veto it stays in place until it is applied or cancelled.
+    pub fn set_guardian(&mut self, guardian: [u8; 32]) -> Result<()> {
+        if self.pending_params.active {
+            return Err(RiskError::TimelockActive);
+        }
+        self.guardian = guardian;
+        self.bump_state_seq();
+        Ok(())
+    }
+
+    /// Queue a parameter update (admin function).
+    ///
+    /// The update becomes applicable after the currently configured
+    /// `param_timelock_slots`. Queueing replaces any previously queued update and
+    /// moves `PendingParamsUpdate::nonce` on, so approvals of the old one lapse.
+    /// Returns the eta slot.
+    pub fn queue_params_update(
+        &mut self,
+        params: RiskParams,
+        ext_params: ExtParams,
+        now_slot: u64,
+    ) -> Result<u64> {
+        Self::validate_ext_params(&ext_params)?;
+        let eta_slot = now_slot.saturating_add(self.ext_params.param_timelock_slots);
+        self.pending_params = PendingParamsUpdate {
+            active: true,
+            queued_slot: now_slot,
+            eta_slot,
+            nonce: self.pending_params.nonce.wrapping_add(1),
+            params,
+            ext_params,
+            approvals: 0,
+        };
+        self.bump_state_seq();
+        Ok(eta_slot)
+    }
+
+    /// Record `signer`'s approval of the queued parameter update identified by
+    /// `nonce` (`PendingParamsUpdate::nonce`); `StaleSequence` if a different
+    /// update has been queued since. Returns the number of approvals so far.
+    pub fn approve_params_update(&mut self, signer: &[u8; 32], nonce: u64) -> Result<u8> {
+        let i = self
+            .admin_council
+            .signer_index(signer)
+            .ok_or(RiskError::Unauthorized)?;
+        if !self.pending_params.active {
+            return Err(RiskError::NoPendingUpdate);
+        }
+        if nonce != self.pending_params.nonce {
+            return Err(RiskError::StaleSequence);
+        }
+        self.pending_params.approvals |= 1u8 << i;
+        self.bump_state_seq();
+        Ok(self.pending_params.approvals.count_ones() as u8)
+    }
+
+    /// Apply the queued parameter update once its timelock has elapsed.
+    pub fn apply_params_update(&mut self, now_slot: u64) -> Result<()> {
+        if !self.pending_params.active {
+            return Err(RiskError::NoPendingUpdate);
+        }
+        if now_slot < self.pending_params.eta_slot {
+            return Err(RiskError::TimelockActive);
+        }
+        if !self.admin_council.is_approved(self.pending_params.approvals) {
+            return Err(RiskError::ApprovalsMissing);
+        }
+        self.check_ext_params_transition(&self.pending_params.ext_params)?;
+        let pending = self.pending_params;
+        self.params = pending.params;
+        self.max_crank_staleness_slots = pending.params.max_crank_staleness_slots;
+        self.ext_params = ExtParams {
+            version: EXT_PARAMS_VERSION,
+            ..pending.ext_params
+        };
+        self.pending_params.active = false;
+        self.record_event(EventKind::ParamsApplied, EVENT_NO_ACCOUNT, EVENT_NO_ACCOUNT, 0, 0, 0);
+        self.bump_state_seq();
+        Ok(())
+    }
+
+    /// Replace the admin council (admin function).
+    ///
+    /// While a council is active, `approvers` (keys whose signatures the wrapper
+    /// verified) must include `threshold` members of the current council. Pending
+    /// approvals are cleared, since bit positions refer to the old signer list.
+    pub fn set_admin_council(
+        &mut self,
+        approvers: &[[u8; 32]],
+        signers: &[[u8; 32]],
+        threshold: u8,
+    ) -> Result<()> {
+        let current = self.admin_council;
+        if !current.is_approved(current.approvals_of(approvers)) {
+            return Err(RiskError::ApprovalsMissing);
+        }
+        if signers.len() > MAX_ADMIN_SIGNERS || threshold as usize > signers.len() {
+            return Err(RiskError::InvalidParams);
+        }
+        let mut council = AdminCouncil {
+            threshold,
+            ..AdminCouncil::default()
+        };
+        for (i, key) in signers.iter().enumerate() {
+            if *key == [0u8; 32] || signers[..i].contains(key) {
+                return Err(RiskError::InvalidParams);
+            }
+            council.signers[i] = *key;
+        }
+        self.admin_council = council;
+        self.pending_params.approvals = 0;
+        self.pending_insurance_withdrawal.approvals = 0;
+        self.bump_state_seq();
+        Ok(())
+    }
+
+    /// Queue a withdrawal of `amount` from the insurance fund (admin function).
+    /// Replaces any previously queued withdrawal, moving the nonce on so its
+    /// approvals lapse.
+    pub fn queue_insurance_withdrawal(&mut self, amount: u128) -> Result<()> {
+        if amount == 0 {
+            return Err(RiskError::InvalidParams);
+        }
+        self.pending_insurance_withdrawal = PendingInsuranceWithdrawal {
+            active: true,
+            nonce: self.pending_insurance_withdrawal.nonce.wrapping_add(1),
+            amount: U128::new(amount),
+            approvals: 0,
+        };
+        self.bump_state_seq();
+        Ok(())
+    }
+
+    /// Record `signer`'s approval of the queued insurance withdrawal identified by
+    /// `nonce` (`PendingInsuranceWithdrawal::nonce`); `StaleSequence` if a different
+    /// withdrawal has been queued since. Returns the number of approvals so far.
+    pub fn approve_insurance_withdrawal(&mut self, signer: &[u8; 32], nonce: u64) -> Result<u8> {
+        let i = self
+            .admin_council
+            .signer_index(signer)
+            .ok_or(RiskError::Unauthorized)?;
+        if !self.pending_insurance_withdrawal.active {
+            return Err(RiskError::NoPendingUpdate);
+        }
+        if nonce != self.pending_insurance_withdrawal.nonce {
+            return Err(RiskError::StaleSequence);
+        }
+        self.pending_insurance_withdrawal.approvals |= 1u8 << i;
+        self.bump_state_seq();
+        Ok(self.pending_insurance_withdrawal.approvals.count_ones() as u8)
+    }
+
+    /// Execute the queued insurance withdrawal once approved. The fund may not
+    /// drop below `risk_reduction_threshold` plus the gap-insurance pool. Returns the
+    /// amount leaving the vault.
+    pub fn execute_insurance_withdrawal(&mut self) -> Result<u128> {
+        let result = self.execute_insurance_withdrawal_inner();
+        if let Ok(amount) = result {
+            self.record_event(
+                EventKind::InsuranceWithdrawn,
+                EVENT_NO_ACCOUNT,
+                EVENT_NO_ACCOUNT,
+                amount,
+                0,
+                0,
+            );
+        }
+        self.finish_mutation("execute_insurance_withdrawal", result.is_ok());
+        result
+    }
+
+    fn execute_insurance_withdrawal_inner(&mut self) -> Result<u128> {
+        let pending = self.pending_insurance_withdrawal;
+        if !pending.active {
+            return Err(RiskError::NoPendingUpdate);
+        }
+        if !self.admin_council.is_approved(pending.approvals) {
+            return Err(RiskError::ApprovalsMissing);
+        }
+        let amount = pending.amount.get();
+        // The gap-insurance pool is earmarked out of the balance, so it stays behind too
+        let available = self
+            .insurance_fund
+            .balance
+            .get()
+            .saturating_sub(self.params.risk_reduction_threshold.get())
+            .saturating_sub(self.gap_insurance.pool.get());
+        if amount > available {
+            return Err(RiskError::InsufficientBalance);
+        }
+        self.insurance_fund.balance = self.insurance_fund.balance.saturating_sub(amount);
+        self.vault = self.vault.saturating_sub(amount);
+        // Keep the nonce so the next withdrawal cannot reuse this one's identity
+        self.pending_insurance_withdrawal = PendingInsuranceWithdrawal {
+            nonce: pending.nonce,
+            ..PendingInsuranceWithdrawal::default()
+        };
+        Ok(amount)
+    }
+
+    // ========================================
+    // Event Stream
+    // ========================================
+
+    /// Append an event to the ring buffer, assigning the next sequence number.
+    fn record_event(
+        &mut self,
+        kind: EventKind,
+        account: u16,
+        counterparty: u16,
+        amount: u128,
+        value: i128,
+        price: u64,
+    ) {
+        self.event_seq = self.event_seq.saturating_add(1);
+        self.event_log[event_log_index(self.event_seq)] = EventRecord {
+            seq: self.event_seq,
+            slot: self.current_slot,
+            amount: U128::new(amount),
+            value: I128::new(value),
+            price,
+            account,
+            counterparty,
+            kind,
+        };
+        let record = ActivityRecord {
+            seq: self.event_seq,
+            slot: self.current_slot,
+            amount: I128::new(value),
+            kind,
+        };
+        match kind {
+            EventKind::Trade => {
+                self.record_activity(account, record);
+                let amount = I128::new(value.saturating_neg());
+                self.record_activity(counterparty, ActivityRecord { amount, ..record });
+            }
+            EventKind::Liquidation | EventKind::ForceClosed => {
+                self.record_activity(account, record)
+            }
+            EventKind::Withdraw if amount >= self.ext_params.large_withdrawal_threshold.get() => {
+                let amount = I128::new(u128_to_i128_clamped(amount));
+                self.record_activity(account, ActivityRecord { amount, ..record })
+            }
+            _ => {}
+        }
+    }
+
+    /// Keep `record` in account `idx`'s activity log, replacing its oldest entry.
+    fn record_activity(&mut self, idx: u16, record: ActivityRecord) {
+        if let Some(account) = self.accounts.get_mut(idx as usize) {
+            if let Some(oldest) = account.activity.iter_mut().min_by_key(|r| r.seq) {
+                *oldest = record;
+            }
+        }
+    }
+
+    /// Account `idx`'s retained significant events, newest first (empty entries,
+    /// with `seq == 0`, last). Each keeps its sequence number in the event stream.
+    pub fn account_activity(&self, idx: u16) -> Result<[ActivityRecord; ACCOUNT_ACTIVITY_LEN]> {
+        if !self.is_used(idx as usize) {
+            return Err(RiskError::AccountNotFound);
+        }
+        let mut activity = self.accounts[idx as usize].activity;
+        activity.sort_unstable_by_key(|r| core::cmp::Reverse(r.seq));
+        Ok(activity)
+    }
+
+    /// The retained event with sequence number `seq`, if it has not been overwritten.
+    pub fn event(&self, seq: u64) -> Option<&EventRecord> {
+        if seq == 0 || seq > self.event_seq {
+            return None;
+        }
+        let record = &self.event_log[event_log_index(seq)];
+        if record.seq == seq {
+            Some(record)
+        } else {
+            None
+        }
+    }
+
+    /// Deliver every retained event with sequence number greater than `after_seq`
+    /// to `observer`, oldest first. Returns the last sequence number delivered
+    /// (or `after_seq` if there was nothing new); pass it back on the next call.
+    ///
+    /// If more than `EVENT_LOG_LEN` events occurred since `after_seq`, the oldest
+    /// are gone and the observer sees a gap in sequence numbers.
+    pub fn drain_events<O: EventObserver>(&self, after_seq: u64, observer: &mut O) -> u64 {
+        let oldest = self
+            .event_seq
+            .saturating_sub(EVENT_LOG_LEN as u64)
+            .saturating_add(1);
+        let mut seq = core::cmp::max(after_seq.saturating_add(1), oldest);
+        let mut last = after_seq;
+        while seq <= self.event_seq {
+            if let Some(record) = self.event(seq) {
+                observer.on_event(record);
+                last = seq;
+            }
+            seq = seq.saturating_add(1);
+        }
+        last
+    }
+
+    // ========================================
+    // Market Time Series
+    // ========================================
+
+    /// Record a market sample if the recorder is enabled and the interval has elapsed.
+    fn record_market_sample(&mut self, now_slot: u64, oracle_price: u64) {
+        let interval = self.ext_params.series_interval_slots;
+        if interval == 0 {
+            return;
+        }
+        if let Some(last) = self.market_sample(0) {
+            if now_slot < last.slot.saturating_add(interval) {
+                return;
+            }
+        }
+        self.series_count = self.series_count.saturating_add(1);
+        self.market_series[market_series_index(self.series_count)] = MarketSample {
+            slot: now_slot,
+            price: oracle_price,
+            funding_rate_bps_per_slot: self.funding_rate_bps_per_slot_last,
+            open_interest: self.total_open_interest,
+            net_lp_pos: self.net_lp_pos,
+        };
+    }
+
+    /// Number of market samples currently retained.
+    pub fn market_series_len(&self) -> usize {
+        core::cmp::min(self.series_count, MARKET_SERIES_LEN as u64) as usize
+    }
+
+    /// The retained sample `back` steps before the latest (0 = latest).
+    pub fn market_sample(&self, back: usize) -> Option<&MarketSample> {
+        if back >= self.market_series_len() {
+            return None;
+        }
+        let n = self.series_count.saturating_sub(back as u64);
+        Some(&self.market_series[market_series_index(n)])
+    }
+
+    /// Retained samples, oldest first.
+    pub fn market_series(&self) -> impl Iterator<Item = &MarketSample> + '_ {
+        (0..self.market_series_len())
+            .rev()
+            .filter_map(move |back| self.market_sample(back))
+    }
+
+    // ========================================
+    // Price History
+    // ========================================
+
+    /// Record the crank price; a later crank in the same slot replaces it.
+    fn record_price_point(&mut self, now_slot: u64, oracle_price: u64) {
+        let point = PricePoint { slot: now_slot, price: oracle_price };
+        if self.price_point(0).is_some_and(|last| last.slot == now_slot) {
+            self.price_history[price_history_index(self.price_history_count)] = point;
+            return;
+        }
+        self.price_history_count = self.price_history_count.saturating_add(1);
+        self.price_history[price_history_index(self.price_history_count)] = point;
+    }
+
+    /// Number of crank prices currently retained.
+    pub fn price_history_len(&self) -> usize {
+        core::cmp::min(self.price_history_count, PRICE_HISTORY_LEN as u64) as usize
+    }
+
+    /// The retained price `back` steps before the latest (0 = latest).
+    pub fn price_point(&self, back: usize) -> Option<&PricePoint> {
+        if back >= self.price_history_len() {
+            return None;
+        }
+        let n = self.price_history_count.saturating_sub(back as u64);
+        Some(&self.price_history[price_history_index(n)])
+    }
+
+    /// Retained crank prices, oldest first.
+    pub fn price_history(&self) -> impl Iterator<Item = &PricePoint> + '_ {
+        (0..self.price_history_len())
+            .rev()
+            .filter_map(move |back| self.price_point(back))
+    }
+
+    /// Time-weighted average crank price over the `window_slots` up to `now_slot`,
+    /// each price holding until the next crank. Covers only the retained history
+    /// (None when empty); a window ending at the latest point returns its price.
+    pub fn price_twap(&self, now_slot: u64, window_slots: u64) -> Option<u64> {
+        let latest = self.price_point(0)?;
+        let window_start = now_slot.saturating_sub(window_slots);
+        let mut end = now_slot;
+        let mut weighted = 0u128;
+        let mut slots = 0u128;
+        for point in (0..self.price_history_len()).filter_map(|back| self.price_point(back)) {
+            let start = core::cmp::max(point.slot, window_start);
+            if end > start {
+                let dt = end.saturating_sub(start) as u128;
+                weighted = weighted.saturating_add((point.price as u128).saturating_mul(dt));
+                slots = slots.saturating_add(dt);
+            }
+            if point.slot <= window_start {
+                break;
+            }
+            end = core::cmp::min(end, point.slot);
+        }
+        Some(weighted.checked_div(slots).map_or(latest.price, |avg| avg as u64))
+    }
+
+    /// Largest move between consecutive crank prices in the `window_slots` up to
+    /// `now_slot`, in bps of the earlier price (0 with fewer than two points).
+    pub fn max_price_jump_bps(&self, now_slot: u64, window_slots: u64) -> u64 {
+        let window_start = now_slot.saturating_sub(window_slots);
+        let mut max_bps = 0u64;
+        for back in 0..self.price_history_len() {
+            let earlier = self.price_point(back.saturating_add(1));
+            let (Some(later), Some(earlier)) = (self.price_point(back), earlier) else {
+                break;
+            };
+            if later.slot < window_start {
+                break;
+            }
+            let bps = deviation_bps(later.price, earlier.price, earlier.price);
+            max_bps = core::cmp::max(max_bps, bps);
+        }
+        max_bps
+    }
+
+    /// Guardian veto: cancel the queued parameter update during its timelock window.
+    ///
+    /// `signer` must equal the configured guardian. The guardian cannot queue or
+    /// apply updates, and loses the veto once the eta slot is reached.
+    pub fn cancel_params_update(&mut self, signer: &[u8; 32], now_slot: u64) -> Result<()> {
+        if self.guardian == [0u8; 32] || *signer != self.guardian {
+            return Err(RiskError::Unauthorized);
+        }
+        if !self.pending_params.active {
+            return Err(RiskError::NoPendingUpdate);
+        }
+        if now_slot >= self.pending_params.eta_slot {
+            return Err(RiskError::Unauthorized);
+        }
+        self.pending_params.active = false;
+        self.bump_state_seq();
+        Ok(())
+    }
+
+    // ========================================
+    // Bitmap Helpers
+    // ========================================
+
+    pub fn is_used(&self, idx: usize) -> bool {
+        if idx >= MAX_ACCOUNTS {
+            return false;
+        }
+        let w = idx >> 6;
+        let b = idx & 63;
+        ((self.used[w] >> b) & 1) == 1
+    }
+
+    fn set_used(&mut self, idx: usize) {
+        let w = idx >> 6;
+        let b = idx & 63;
+        self.used[w] |= 1u64 << b;
+    }
+
+    fn clear_used(&mut self, idx: usize) {
+        let w = idx >> 6;
+        let b = idx & 63;
+        self.used[w] &= !(1u64 << b);
+    }
+
+    fn for_each_used_mut<F: FnMut(usize, &mut Account)>(&mut self, mut f: F) {
+        for (block, word) in self.used.iter().copied().enumerate() {
+            let mut w = word;
+            while w != 0 {
+                let bit = w.trailing_zeros() as usize;
+                let idx = (block << 6) | bit;
+                w &= w.wrapping_sub(1); // Clear lowest bit (w != 0)
+                if idx >= MAX_ACCOUNTS {
+                    continue; // Guard against stray high bits in bitmap
+                }
+                f(idx, &mut self.accounts[idx]);
+            }
+        }
+    }
+
+    fn for_each_used<F: FnMut(usize, &Account)>(&self, mut f: F) {
+        for (block, word) in self.used.iter().copied().enumerate() {
+            let mut w = word;
+            while w != 0 {
+                let bit = w.trailing_zeros() as usize;
+                let idx = (block << 6) | bit;
+                w &= w.wrapping_sub(1); // Clear lowest bit (w != 0)
+                if idx >= MAX_ACCOUNTS {
+                    continue; // Guard against stray high bits in bitmap
+                }
+                f(idx, &self.accounts[idx]);
+            }
+        }
+    }
+
+    // ========================================
+    // O(1) Aggregate Helpers (spec §4)
+    // ========================================
+
+    /// Mandatory helper: set account PnL and maintain pnl_pos_tot aggregate (spec §4.2).
+    /// All code paths that modify PnL MUST call this.
+    #[inline]
+    pub fn set_pnl(&mut self, idx: usize, new_pnl: i128) {
+        let old = self.accounts[idx].pnl.get();
+        let old_pos = if old > 0 { old as u128 } else { 0 };
+        let new_pos = if new_pnl > 0 { new_pnl as u128 } else { 0 };
+        self.pnl_pos_tot = U128::new(
+            self.pnl_pos_tot
+                .get()
+                .saturating_add(new_pos)
+                .saturating_sub(old_pos),
+        );
+        self.accounts[idx].pnl = I128::new(new_pnl);
+    }
+
+    /// Helper: set account capital and maintain c_tot aggregate (spec §4.1).
+    #[inline]
+    pub fn set_capital(&mut self, idx: usize, new_capital: u128) {
+        let old = self.accounts[idx].capital.get();
+        if new_capital >= old {
+            self.c_tot = U128::new(self.c_tot.get().saturating_add(new_capital.saturating_sub(old)));
+        } else {
+            self.c_tot = U128::new(self.c_tot.get().saturating_sub(old.saturating_sub(new_capital)));
+        }
+        self.accounts[idx].capital = U128::new(new_capital);
+    }
+
+    /// Recompute c_tot, pnl_pos_tot and the open interest aggregates from account data.
+    /// For test use after direct state mutation.
+    pub fn recompute_aggregates(&mut self) {
+        let mut c_tot = 0u128;
+        let mut pnl_pos_tot = 0u128;
+        let mut open_interest = 0u128;
+        let mut net_lp_pos = 0i128;
+        let mut lp_sum_abs = 0u128;
+        self.for_each_used(|_idx, account| {
+            c_tot = c_tot.saturating_add(account.capital.get());
+            let pnl = account.pnl.get();
+            if pnl > 0 {
+                pnl_pos_tot = pnl_pos_tot.saturating_add(pnl as u128);
+            }
+            let pos = account.position_size.get();
+            open_interest = open_interest.saturating_add(pos.unsigned_abs());
+            if account.is_lp() {
+                net_lp_pos = net_lp_pos.saturating_add(pos);
+                lp_sum_abs = lp_sum_abs.saturating_add(pos.unsigned_abs());
+            }
+        });
+        self.c_tot = U128::new(c_tot);
+        self.pnl_pos_tot = U128::new(pnl_pos_tot);
+        self.total_open_interest = U128::new(open_interest);
+        self.net_lp_pos = I128::new(net_lp_pos);
+        self.lp_sum_abs = U128::new(lp_sum_abs);
+    }
+
+    /// Compute haircut ratio (h_num, h_den) per spec §3.2.
+    /// h = min(Residual, PNL_pos_tot) / PNL_pos_tot where Residual is `pnl_residual()`.
+    /// Returns (1, 1) when PNL_pos_tot == 0.
+    #[inline]
+    pub fn haircut_ratio(&self) -> (u128, u128) {
+        let pnl_pos_tot = self.pnl_pos_tot.get();
+        if pnl_pos_tot == 0 {
+            return (1, 1);
+        }
+        let h_num = core::cmp::min(self.pnl_residual(), pnl_pos_tot);
+        (h_num, pnl_pos_tot)
+    }
+
+    /// Vault left to back positive PnL: Residual = max(0, V - C_tot - I - LP_claimable).
+    /// Released LP withdrawals stay in the vault until claimed but are already owed.
+    #[inline]
+    pub fn pnl_residual(&self) -> u128 {
+        self.vault
+            .get()
+            .saturating_sub(self.c_tot.get())
+            .saturating_sub(self.insurance_fund.balance.get())
+            .saturating_sub(self.lp_claimable_total.get())
+    }
+
+    /// Compute effective positive PnL after haircut for a given account PnL (spec §3.3).
+    /// PNL_eff_pos_i = floor(max(PNL_i, 0) * h_num / h_den)
+    #[inline]
+    pub fn effective_pos_pnl(&self, pnl: i128) -> u128 {
+        if pnl <= 0 {
+            return 0;
+        }
+        let pos_pnl = pnl as u128;
+        let (h_num, h_den) = self.haircut_ratio();
+        if h_den == 0 {
+            return pos_pnl;
+        }
+        // floor(pos_pnl * h_num / h_den)
+        mul_u128(pos_pnl, h_num).checked_div(h_den).unwrap_or(0)
+    }
+
+    /// Compute effective realized equity per spec §3.3.
+    /// Eq_real_i = max(0, C_i + min(PNL_i, 0) + PNL_eff_pos_i)
+    #[inline]
+    pub fn effective_equity(&self, account: &Account) -> u128 {
+        let cap_i = u128_to_i128_clamped(account.capital.get());
+        let neg_pnl = core::cmp::min(account.pnl.get(), 0);
+        let eff_pos = self.effective_pos_pnl(account.pnl.get());
+        let eq_i = cap_i
+            .saturating_add(neg_pnl)
+            .saturating_add(u128_to_i128_clamped(eff_pos));
+        if eq_i > 0 {
+            eq_i as u128
+        } else {
+            0
+        }
+    }
+
+    // ========================================
+    // Account Allocation
+    // ========================================
+
+    fn alloc_slot(&mut self) -> Result<u16> {
+        if self.free_head == u16::MAX {
+            return Err(RiskError::Overflow); // Slab full
+        }
+        let idx = self.free_head;
+        self.free_head = self.next_free[idx as usize];
+        self.set_used(idx as usize);
+        // Increment O(1) counter atomically (fixes H2: TOCTOU fee bypass)
+        self.num_used_accounts = self.num_used_accounts.saturating_add(1);
+        Ok(idx)
+    }
+
+    /// Count used accounts
+    fn count_used(&self) -> u64 {
+        let mut count = 0u64;
+        self.for_each_used(|_, _| {
+            count = count.saturating_add(1);
+        });
+        count
+    }
+
+    // ========================================
+    // Account Management
+    // ========================================
+
+    /// Add a new user account
+    pub fn add_user(&mut self, fee_payment: u128) -> Result<u16> {
+        let result = self.add_user_inner(fee_payment);
+        if let Ok(idx) = result {
+            self.record_event(EventKind::AccountOpened, idx, EVENT_NO_ACCOUNT, fee_payment, 0, 0);
+        }
+        self.finish_mutation("add_user", result.is_ok());
+        result
+    }
+
+    fn add_user_inner(&mut self, fee_payment: u128) -> Result<u16> {
+        // Use O(1) counter instead of O(N) count_used() (fixes H2: TOCTOU fee bypass)
+        let used_count = self.num_used_accounts as u64;
+        if used_count >= self.params.max_accounts {
+            return Err(RiskError::Overflow);
+        }
+
+        // Flat fee (no scaling)
+        let required_fee = self.params.new_account_fee.get();
+        if fee_payment < required_fee {
+            return Err(RiskError::InsufficientBalance);
+        }
+
+        // Bug #4 fix: Compute excess payment to credit to user capital
+        let excess = fee_payment.saturating_sub(required_fee);
+
+        // Pay fee to insurance (fee tokens are deposited into vault)
+        // Account for FULL fee_payment in vault, not just required_fee
+        let vault = self.vault.checked_add(fee_payment).ok_or(RiskError::Overflow)?;
+        let insurance = self
+            .insurance_fund
+            .balance
+            .checked_add(required_fee)
+            .ok_or(RiskError::Overflow)?;
+        self.vault = vault;
+        self.insurance_fund.balance = insurance;
+        self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue.saturating_add(required_fee);
+        self.revenue.new_account_fees = self.revenue.new_account_fees.saturating_add(required_fee);
+
+        // Allocate slot and assign unique ID
+        let idx = self.alloc_slot()?;
+        let account_id = self.next_account_id;
+        self.next_account_id = self.next_account_id.saturating_add(1);
+
+        // Initialize account with excess credited to capital
+        self.accounts[idx as usize] = Account {
+            kind: AccountKind::User,
+            account_id,
+            capital: U128::new(excess), // Bug #4 fix: excess goes to user capital
+            pnl: I128::ZERO,
+            reserved_pnl: 0,
+            warmup_started_at_slot: self.current_slot,
+            warmup_slope_per_step: U128::ZERO,
+            position_size: I128::ZERO,
+            entry_price: 0,
+            funding_index: self.funding_index_qpb_e6,
+            matcher_program: [0; 32],
+            matcher_context: [0; 32],
+            owner: [0; 32],
+            fee_credits: I128::ZERO,
+            last_fee_slot: self.current_slot,
+            lp_pnl: LpPnlAttribution::default(),
+            lp_shares: U128::ZERO,
+            lp_queued_shares: U128::ZERO,
+            lp_claimable: U128::ZERO,
+            lp_locked_until_slot: 0,
+            lp_high_water_mark: U128::ZERO,
+            markout: MarkoutStats::default(),
+            holding_fee_index: U128::ZERO,
+            max_leverage: 0,
+            position_opened_slot: 0,
+            position_modified_slot: 0,
+            last_activity_slot: self.current_slot,
+            drain_epoch_start: 0,
+            epoch_drain: U128::ZERO,
+            tag: [0; 32],
+            force_close_deadline_slot: 0,
+            reduce_only: 0,
+            haircut_claim: U128::ZERO,
+            emission_slot: 0,
+            emission_epoch: 0,
+            emission_pending: U128::ZERO,
+            emission_claimable: U128::ZERO,
+            adl_first: 0,
+            credit_limit: U128::ZERO,
+            credit_rate_e9: 0,
+            credit_expiry_slot: 0,
+            credit_accrual_slot: 0,
+            conditional_notional: U128::ZERO,
+            activity: [ActivityRecord::default(); ACCOUNT_ACTIVITY_LEN],
+        };
+
+        // Maintain c_tot aggregate (account was created with capital = excess)
+        if excess > 0 {
+            self.c_tot = U128::new(self.c_tot.get().saturating_add(excess));
+        }
+
+        Ok(idx)
+    }
+
+    /// Add a new LP account
+    pub fn add_lp(
+        &mut self,
+        matching_engine_program: [u8; 32],
+        matching_engine_context: [u8; 32],
+        fee_payment: u128,
+    ) -> Result<u16> {
+        let result = self.add_lp_inner(
+            matching_engine_program,
+            matching_engine_context,
+            fee_payment,
+        );
+        if let Ok(idx) = result {
+            self.record_event(EventKind::AccountOpened, idx, EVENT_NO_ACCOUNT, fee_payment, 1, 0);
+        }
+        self.finish_mutation("add_lp", result.is_ok());
+        result
+    }
+
+    /// Add a new user account carrying an integrator tag
+    pub fn add_user_tagged(&mut self, fee_payment: u128, tag: [u8; 32]) -> Result<u16> {
+        let idx = self.add_user(fee_payment)?;
+        self.accounts[idx as usize].tag = tag;
+        Ok(idx)
+    }
+
+    /// Add a new LP account carrying an integrator tag
+    pub fn add_lp_tagged(
+        &mut self,
+        matching_engine_program: [u8; 32],
+        matching_engine_context: [u8; 32],
+        fee_payment: u128,
+        tag: [u8; 32],
+    ) -> Result<u16> {
+        let idx = self.add_lp(matching_engine_program, matching_engine_context, fee_payment)?;
+        self.accounts[idx as usize].tag = tag;
+        Ok(idx)
+    }
+
+    fn add_lp_inner(
+        &mut self,
+        matching_engine_program: [u8; 32],
+        matching_engine_context: [u8; 32],
+        fee_payment: u128,
+    ) -> Result<u16> {
+        // Use O(1) counter instead of O(N) count_used() (fixes H2: TOCTOU fee bypass)
+        let used_count = self.num_used_accounts as u64;
+        if used_count >= self.params.max_accounts {
+            return Err(RiskError::Overflow);
+        }
+
+        // Flat fee (no scaling)
+        let required_fee = self.params.new_account_fee.get();
+        if fee_payment < required_fee {
+            return Err(RiskError::InsufficientBalance);
+        }
+
+        // Bug #4 fix: Compute excess payment to credit to LP capital
+        let excess = fee_payment.saturating_sub(required_fee);
+
+        // Pay fee to insurance (fee tokens are deposited into vault)
+        // Account for FULL fee_payment in vault, not just required_fee
+        let vault = self.vault.checked_add(fee_payment).ok_or(RiskError::Overflow)?;
+        let insurance = self
+            .insurance_fund
+            .balance
+            .checked_add(required_fee)
+            .ok_or(RiskError::Overflow)?;
+        self.vault = vault;
+        self.insurance_fund.balance = insurance;
+        self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue.saturating_add(required_fee);
+        self.revenue.new_account_fees = self.revenue.new_account_fees.saturating_add(required_fee);
+
+        // Allocate slot and assign unique ID
+        let idx = self.alloc_slot()?;
+        let account_id = self.next_account_id;
+        self.next_account_id = self.next_account_id.saturating_add(1);
+
+        // Initialize account with excess credited to capital
+        self.accounts[idx as usize] = Account {
+            kind: AccountKind::LP,
+            account_id,
+            capital: U128::new(excess), // Bug #4 fix: excess goes to LP capital
+            pnl: I128::ZERO,
+            reserved_pnl: 0,
+            warmup_started_at_slot: self.current_slot,
+            warmup_slope_per_step: U128::ZERO,
+            position_size: I128::ZERO,
+            entry_price: 0,
+            funding_index: self.funding_index_qpb_e6,
+            matcher_program: matching_engine_program,
+            matcher_context: matching_engine_context,
+            owner: [0; 32],
+            fee_credits: I128::ZERO,
+            last_fee_slot: self.current_slot,
+            lp_pnl: LpPnlAttribution::default(),
+            lp_shares: U128::new(excess),
+            lp_queued_shares: U128::ZERO,
+            lp_claimable: U128::ZERO,
+            lp_locked_until_slot: 0,
+            lp_high_water_mark: U128::ZERO,
+            markout: MarkoutStats::default(),
+            holding_fee_index: U128::ZERO,
+            max_leverage: 0,
+            position_opened_slot: 0,
+            position_modified_slot: 0,
+            last_activity_slot: self.current_slot,
+            drain_epoch_start: 0,
+            epoch_drain: U128::ZERO,
+            tag: [0; 32],
+            force_close_deadline_slot: 0,
+            reduce_only: 0,
+            haircut_claim: U128::ZERO,
+            emission_slot: 0,
+            emission_epoch: 0,
+            emission_pending: U128::ZERO,
+            emission_claimable: U128::ZERO,
+            adl_first: 0,
+            credit_limit: U128::ZERO,
+            credit_rate_e9: 0,
+            credit_expiry_slot: 0,
+            credit_accrual_slot: 0,
+            conditional_notional: U128::ZERO,
+            activity: [ActivityRecord::default(); ACCOUNT_ACTIVITY_LEN],
+        };
+
+        // Maintain c_tot aggregate (account was created with capital = excess)
+        if excess > 0 {
+            self.c_tot = U128::new(self.c_tot.get().saturating_add(excess));
+        }
+
+        Ok(idx)
+    }
+
+    // ========================================
+    // Maintenance Fees
+    // ========================================
+
+    /// Settle maintenance fees for an account.
+    ///
+    /// Returns the fee amount due (for keeper rebate calculation).
+    ///
+    /// Algorithm:
+    /// 1. Compute dt = now_slot - account.last_fee_slot
+    /// 2. If dt == 0, return 0 (no-op)
+    /// 3. Compute due = fee_per_slot * dt
+    /// 4. Deduct from fee_credits; if negative, pay from capital to insurance
+    /// 5. If position exists and below maintenance after fee, return Err
+    pub fn settle_maintenance_fee(
+        &mut self,
+        idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<u128> {
+        let result = self.settle_maintenance_fee_inner(idx, now_slot, oracle_price);
+        // The fee is charged before the margin check, so Undercollateralized still commits
+        if result.is_ok() || result == Err(RiskError::Undercollateralized) {
+            self.bump_state_seq();
+        }
+        result
+    }
+
+    fn settle_maintenance_fee_inner(
+        &mut self,
+        idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<u128> {
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return Err(RiskError::Unauthorized);
+        }
+
+        // Calculate elapsed time
+        let dt = now_slot.saturating_sub(self.accounts[idx as usize].last_fee_slot);
+        if dt == 0 {
+            return Ok(0);
+        }
+
+        // Calculate fee due (engine is purely slot-native)
+        let due = self
+            .params
+            .maintenance_fee_per_slot
+            .get()
+            .saturating_mul(dt as u128);
+
+        // Update last_fee_slot
+        self.accounts[idx as usize].last_fee_slot = now_slot;
+
+        // Deduct from fee_credits (coupon: no insurance booking here —
+        // insurance was already paid when credits were granted)
+        self.accounts[idx as usize].fee_credits =
+            self.accounts[idx as usize].fee_credits.saturating_sub_u128(due);
+
+        // If fee_credits is negative, pay from capital using set_capital helper (spec §4.1)
+        let mut paid_from_capital = 0u128;
+        if self.accounts[idx as usize].fee_credits.is_negative() {
+            let owed = neg_i128_to_u128(self.accounts[idx as usize].fee_credits.get());
+            let current_cap = self.accounts[idx as usize].capital.get();
+            let pay = core::cmp::min(owed, current_cap);
+
+            // Use set_capital helper to maintain c_tot aggregate (spec §4.1)
+            self.set_capital(idx as usize, current_cap.saturating_sub(pay));
+            self.insurance_fund.balance = self.insurance_fund.balance.saturating_add(pay);
+            self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue.saturating_add(pay);
+            self.revenue.maintenance_fees = self.revenue.maintenance_fees.saturating_add(pay);
+
+            // Credit back what was paid
+            self.accounts[idx as usize].fee_credits =
+                self.accounts[idx as usize].fee_credits.saturating_add_u128(pay);
+            paid_from_capital = pay;
+        }
+
+        // Check maintenance margin if account has a position (MTM check)
+        if !self.accounts[idx as usize].position_size.is_zero() {
+            let account_ref = &self.accounts[idx as usize];
+            if !self.is_above_maintenance_margin_mtm(account_ref, oracle_price) {
+                return Err(RiskError::Undercollateralized);
+            }
+        }
+
+        Ok(paid_from_capital) // Return actual amount paid into insurance
+    }
+
+    /// Best-effort maintenance settle for crank paths.
+    /// - Always advances last_fee_slot
+    /// - Charges fees into insurance if possible
+    /// - NEVER fails due to margin checks
+    /// - Still returns Unauthorized if idx invalid
+    fn settle_maintenance_fee_best_effort_for_crank(
+        &mut self,
+        idx: u16,
+        now_slot: u64,
+    ) -> Result<u128> {
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return Err(RiskError::Unauthorized);
+        }
+
+        let dt = now_slot.saturating_sub(self.accounts[idx as usize].last_fee_slot);
+        if dt == 0 {
+            return Ok(0);
+        }
+
+        let due = self
+            .params
+            .maintenance_fee_per_slot
+            .get()
+            .saturating_mul(dt as u128);
+
+        // Advance slot marker regardless
+        self.accounts[idx as usize].last_fee_slot = now_slot;
+
+        // Deduct from fee_credits (coupon: no insurance booking here —
+        // insurance was already paid when credits were granted)
+        self.accounts[idx as usize].fee_credits =
+            self.accounts[idx as usize].fee_credits.saturating_sub_u128(due);
+
+        // If negative, pay what we can from capital using set_capital helper (spec §4.1)
+        let mut paid_from_capital = 0u128;
+        if self.accounts[idx as usize].fee_credits.is_negative() {
+            let owed = neg_i128_to_u128(self.accounts[idx as usize].fee_credits.get());
+            let current_cap = self.accounts[idx as usize].capital.get();
+            let pay = core::cmp::min(owed, current_cap);
+
+            // Use set_capital helper to maintain c_tot aggregate (spec §4.1)
+            self.set_capital(idx as usize, current_cap.saturating_sub(pay));
+            self.insurance_fund.balance = self.insurance_fund.balance.saturating_add(pay);
+            self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue.saturating_add(pay);
+            self.revenue.maintenance_fees = self.revenue.maintenance_fees.saturating_add(pay);
+
+            self.accounts[idx as usize].fee_credits =
+                self.accounts[idx as usize].fee_credits.saturating_add_u128(pay);
+            paid_from_capital = pay;
+        }
+
+        Ok(paid_from_capital) // Return actual amount paid into insurance
+    }
+
+    /// Best-effort warmup settlement for crank: settles any warmed positive PnL to capital.
+    /// Silently ignores errors (e.g., account not found) since crank must not stall on
+    /// individual account issues. Used to drain abandoned accounts' positive PnL over time.
+    fn settle_warmup_to_capital_for_crank(&mut self, idx: u16) {
+        // Ignore errors: crank is best-effort and must continue processing other accounts
+        let _ = self.settle_warmup_to_capital_inner(idx);
+    }
+
+    /// Pay down existing fee debt (negative fee_credits) using available capital.
+    /// Does not advance last_fee_slot or charge new fees — just sweeps capital
+    /// that became available (e.g. after warmup settlement) into insurance.
+    /// Uses set_capital helper to maintain c_tot aggregate (spec §4.1).
+    fn pay_fee_debt_from_capital(&mut self, idx: u16) {
+        if self.accounts[idx as usize].fee_credits.is_negative()
+            && !self.accounts[idx as usize].capital.is_zero()
+        {
+            let owed = neg_i128_to_u128(self.accounts[idx as usize].fee_credits.get());
+            let current_cap = self.accounts[idx as usize].capital.get();
+            let pay = core::cmp::min(owed, current_cap);
+            if pay > 0 {
+                // Use set_capital helper to maintain c_tot aggregate (spec §4.1)
+                self.set_capital(idx as usize, current_cap.saturating_sub(pay));
+                self.insurance_fund.balance = self.insurance_fund.balance.saturating_add(pay);
+                self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue.saturating_add(pay);
+                self.revenue.maintenance_fees = self.revenue.maintenance_fees.saturating_add(pay);
+                self.accounts[idx as usize].fee_credits =
+                    self.accounts[idx as usize].fee_credits.saturating_add_u128(pay);
+            }
+        }
+    }
+
+    /// Touch account for force-realize paths: settles funding, mark, and fees but
+    /// uses best-effort fee settle that can't stall on margin checks.
+    fn touch_account_for_force_realize(
+        &mut self,
+        idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<()> {
+        // Funding settle is required for correct pnl
+        self.touch_account_inner(idx)?;
+        // Mark-to-market settlement (variation margin)
+        self.settle_mark_to_oracle_inner(idx, oracle_price)?;
+        // Best-effort fees; never fails due to maintenance margin
+        let _ = self.settle_maintenance_fee_best_effort_for_crank(idx, now_slot)?;
+        Ok(())
+    }
+
+    /// Touch account for liquidation paths: settles funding, mark, and fees but
+    /// uses best-effort fee settle since we're about to liquidate anyway.
+    fn touch_account_for_liquidation(
+        &mut self,
+        idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<()> {
+        // Funding settle is required for correct pnl
+        self.touch_account_inner(idx)?;
+        // Best-effort mark-to-market (saturating — never wedges on extreme PnL)
+        self.settle_mark_to_oracle_best_effort(idx, oracle_price)?;
+        // Best-effort fees; margin check would just block the liquidation we need to do
+        let _ = self.settle_maintenance_fee_best_effort_for_crank(idx, now_slot)?;
+        Ok(())
+    }
+
+    /// Set owner pubkey for an account
+    pub fn set_owner(&mut self, idx: u16, owner: [u8; 32]) -> Result<()> {
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return Err(RiskError::Unauthorized);
+        }
+        self.accounts[idx as usize].owner = owner;
+        self.absorb_owner_lp_holding(idx);
+        self.bump_state_seq();
+        Ok(())
+    }
+
+    /// Snapshot of a used account.
+    pub fn account_view(&self, idx: u16) -> Option<AccountView> {
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return None;
+        }
+        let account = &self.accounts[idx as usize];
+        Some(AccountView {
+            idx,
+            account_id: account.account_id,
+            kind: account.kind,
+            owner: account.owner,
+            tag: account.tag,
+            capital: account.capital.get(),
+            pnl: account.pnl.get(),
+            reserved_pnl: account.reserved_pnl,
+            position: account.position_size.get(),
+            entry_price: account.entry_price,
+            fee_credits: account.fee_credits.get(),
+            reduce_only: account.reduce_only,
+            haircut_claim: account.haircut_claim.get(),
+            adl_first: account.adl_first,
+        })
+    }
+
+    /// Views of several accounts, all from the same state and tagged with its
+    /// sequence number, so an indexer can tell whether two batches straddle a
+    /// mutation. Unused indices are skipped.
+    #[cfg(feature = "std")]
+    pub fn read_many(&self, indices: &[u16]) -> BatchRead {
+        BatchRead {
+            seq: self.state_seq,
+            slot: self.current_slot,
+            views: indices.iter().filter_map(|&idx| self.account_view(idx)).collect(),
+        }
+    }
+
+    /// Integrator tag of a used account.
+    pub fn account_tag(&self, idx: u16) -> Option<[u8; 32]> {
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return None;
+        }
+        Some(self.accounts[idx as usize].tag)
+    }
+
+    /// Used accounts carrying `tag`, in index order.
+    pub fn accounts_with_tag<'a>(&'a self, tag: &'a [u8; 32]) -> impl Iterator<Item = u16> + 'a {
+        (0..MAX_ACCOUNTS)
+            .filter(move |&idx| self.is_used(idx) && self.accounts[idx].tag == *tag)
+            .map(|idx| idx as u16)
+    }
+
+    /// Positions at least `min_age_slots` old at `now_slot`, in index order.
+    pub fn stale_positions(
+        &self,
+        now_slot: u64,
+        min_age_slots: u64,
+    ) -> impl Iterator<Item = StalePosition> + '_ {
+        (0..MAX_ACCOUNTS).filter_map(move |idx| {
+            let account = &self.accounts[idx];
+            if !self.is_used(idx) || account.position_size.is_zero() {
+                return None;
+            }
+            let age_slots = account.position_age(now_slot);
+            if age_slots < min_age_slots {
+                return None;
+            }
+            Some(StalePosition {
+                idx: idx as u16,
+                position: account.position_size.get(),
+                opened_slot: account.position_opened_slot,
+                modified_slot: account.position_modified_slot,
+                age_slots,
+                tag: account.tag,
+            })
+        })
+    }
+
+    /// Summed capital and position notional (at `oracle_price`) of every account
+    /// owned by `owner`. The all-zero key owns nothing.
+    pub fn owner_exposure(&self, owner: &[u8; 32], oracle_price: u64) -> OwnerExposure {
+        self.owner_exposure_where(owner, u16::MAX, oracle_price)
+    }
+
+    /// `owner_exposure` of account `idx`'s owner over its other accounts (`None` if
+    /// the account has no owner, so owner limits do not apply).
+    fn owner_exposure_excluding(&self, idx: u16, oracle_price: u64) -> Option<OwnerExposure> {
+        let owner = self.accounts[idx as usize].owner;
+        if owner == [0u8; 32] {
+            return None;
+        }
+        Some(self.owner_exposure_where(&owner, idx, oracle_price))
+    }
+
+    fn owner_exposure_where(
+        &self,
+        owner: &[u8; 32],
+        skip: u16,
+        oracle_price: u64,
+    ) -> OwnerExposure {
+        let mut exposure = OwnerExposure::default();
+        if *owner == [0u8; 32] {
+            return exposure;
+        }
+        self.for_each_used(|idx, account| {
+            if idx == skip as usize || account.owner != *owner {
+                return;
+            }
+            let abs_pos = saturating_abs_i128(account.position_size.get()) as u128;
+            exposure.accounts = exposure.accounts.saturating_add(1);
+            exposure.capital = exposure.capital.saturating_add(account.capital.get());
+            exposure.notional = exposure
+                .notional
+                .saturating_add(self.notional_at(abs_pos, oracle_price, Rounding::Up));
+        });
+        exposure
+    }
+
+    /// Opt account `idx` into a maximum leverage (0 = none). Its risk-increasing
+    /// trades must then keep initial margin of at least 1 / `max_leverage`, and a cap
+    /// at or below `ExtParams::low_leverage_tier` earns the tier's fee discount.
+    pub fn set_max_leverage(&mut self, idx: u16, max_leverage: u16) -> Result<()> {
+        let result = self.set_max_leverage_inner(idx, max_leverage);
+        self.finish_mutation("set_max_leverage", result.is_ok());
+        result
+    }
+
+    fn set_max_leverage_inner(&mut self, idx: u16, max_leverage: u16) -> Result<()> {
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return Err(RiskError::Unauthorized);
+        }
+        self.accounts[idx as usize].max_leverage = max_leverage;
+        Ok(())
+    }
+
+    /// Register (or withdraw) account `idx`'s standing preference to be unwound first:
+    /// while force-realize is active the crank closes opted-in positions before any
+    /// others, and the max-PnL cap closes them without a grace period. Opted-in
+    /// accounts earn `ExtParams::adl_first_fee_discount_bps` on trading fees. The
+    /// preference cannot be withdrawn while force-realize is active.
+    pub fn set_adl_first(&mut self, idx: u16, enabled: bool) -> Result<()> {
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return Err(RiskError::AccountNotFound);
+        }
+        if !enabled && self.accounts[idx as usize].adl_first != 0 && self.force_realize_active() {
+            return Err(RiskError::Unauthorized);
+        }
+        self.accounts[idx as usize].adl_first = enabled as u8;
+        self.bump_state_seq();
+        Ok(())
+    }
+
+    /// Whether account `idx` opted to be force-realized first.
+    pub fn is_adl_first(&self, idx: u16) -> bool {
+        (idx as usize) < MAX_ACCOUNTS
+            && self.is_used(idx as usize)
+            && self.accounts[idx as usize].adl_first != 0
+    }
+
+    /// Register the total notional of account `idx`'s resting conditional orders that
+    /// would increase its exposure (called by the order program whenever orders are
+    /// placed, cancelled or triggered). `ExtParams::conditional_margin_bps` of it is
+    /// kept free of withdrawals and other risk-increasing trades, so the orders can
+    /// still fill when they trigger; the order program lowers the registration before
+    /// executing a triggered order. Liquidation ignores the reservation.
+    pub fn set_conditional_notional(&mut self, idx: u16, notional: u128) -> Result<()> {
+        let result = self.set_conditional_notional_inner(idx, notional);
+        self.finish_mutation("set_conditional_notional", result.is_ok());
+        result
+    }
+
+    fn set_conditional_notional_inner(&mut self, idx: u16, notional: u128) -> Result<()> {
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return Err(RiskError::AccountNotFound);
+        }
+        self.accounts[idx as usize].conditional_notional = U128::new(notional);
+        Ok(())
+    }
+
+    /// Margin reserved for `account`'s registered conditional orders.
+    pub fn conditional_margin(&self, account: &Account) -> u128 {
+        let bps = self.ext_params.conditional_margin_bps;
+        if bps == 0 {
+            return 0;
+        }
+        self.margin_required(account.conditional_notional.get(), bps)
+    }
+
+    /// Put account `idx` into (or take it out of) self-imposed reduce-only mode.
+    /// An admin-imposed flag stays in force until the admin lifts it.
+    pub fn set_reduce_only(&mut self, idx: u16, enabled: bool) -> Result<()> {
+        let result = self.set_reduce_only_flag(idx, REDUCE_ONLY_SELF, enabled);
+        self.finish_mutation("set_reduce_only", result.is_ok());
+        result
+    }
+
+    /// Put account `idx` into (or take it out of) reduce-only mode (admin function,
+    /// e.g. for compliance holds). Independent of the account's own flag.
+    pub fn set_reduce_only_by_admin(&mut self, idx: u16, enabled: bool) -> Result<()> {
+        let result = self.set_reduce_only_flag(idx, REDUCE_ONLY_ADMIN, enabled);
+        self.finish_mutation("set_reduce_only_by_admin", result.is_ok());
+        result
+    }
+
+    fn set_reduce_only_flag(&mut self, idx: u16, flag: u8, enabled: bool) -> Result<()> {
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return Err(RiskError::AccountNotFound);
+        }
+        let account = &mut self.accounts[idx as usize];
+        if enabled {
+            account.reduce_only |= flag;
+        } else {
+            account.reduce_only &= !flag;
+        }
+        Ok(())
+    }
+
+    /// Whether account `idx` is in reduce-only mode (for either reason).
+    pub fn is_reduce_only(&self, idx: u16) -> bool {
+        (idx as usize) < MAX_ACCOUNTS
+            && self.is_used(idx as usize)
+            && self.accounts[idx as usize].reduce_only != 0
+    }
+
+    /// Initial margin in bps for `account`'s risk-increasing trades: the largest of
+    /// `initial_margin_bps`, the margin implied by the global and account leverage
+    /// caps, and the tightened margin around margin events.
+    pub fn initial_margin_bps_for(&self, account: &Account) -> u64 {
+        let bps = core::cmp::max(
+            self.params.initial_margin_bps,
+            self.event_initial_margin_bps(self.current_slot),
+        );
+        leverage_capped_bps(bps, [self.ext_params.max_leverage, account.max_leverage])
+    }
+
+    /// Margin requirement at `bps` of `notional`, in collateral units: yield-bearing
+    /// collateral is worth `collateral_rate_e9 / 1e9` per unit, so fewer units cover
+    /// the same notional as the rate grows.
+    pub fn margin_required(&self, notional: u128, bps: u64) -> u128 {
+        margin_required_for(notional, bps, self.collateral_rate_e9)
+    }
+
+    /// Margin value of `amount` collateral units at the current exchange rate.
+    fn collateral_value(&self, amount: u128) -> u128 {
+        match self.collateral_rate_e9 {
+            0 => amount,
+            rate => mul_div(amount, rate as u128, 1_000_000_000, Rounding::Down),
+        }
+    }
+
+    /// Record a new collateral exchange rate (margin value per collateral unit, 1e9
+    /// scale) for LST-style collateral such as mSOL or jitoSOL.
+    ///
+    /// Staking yield only accrues, so the rate may not fall, and each update may
+    /// raise it by at most `ExtParams::collateral_rate_max_step_bps` over the last
+    /// rate (1:1 before the first update). Updates are rejected while the step
+    /// bound is 0.
+    pub fn update_collateral_rate(&mut self, rate_e9: u64, now_slot: u64) -> Result<()> {
+        let result = self.update_collateral_rate_inner(rate_e9, now_slot);
+        self.finish_mutation("update_collateral_rate", result.is_ok());
+        result
+    }
+
+    fn update_collateral_rate_inner(&mut self, rate_e9: u64, now_slot: u64) -> Result<()> {
+        let max_step = self.ext_params.collateral_rate_max_step_bps;
+        let current = match self.collateral_rate_e9 {
+            0 => 1_000_000_000,
+            rate => rate,
+        };
+        let max_rate = mul_div(
+            current as u128,
+            10_000u128.saturating_add(max_step as u128),
+            10_000,
+            Rounding::Down,
+        );
+        if max_step == 0 || rate_e9 < current || rate_e9 as u128 > max_rate {
+            return Err(RiskError::InvalidParams);
+        }
+        self.collateral_rate_e9 = rate_e9;
+        self.collateral_rate_slot = now_slot;
+        Ok(())
+    }
+
+    /// Discount on `fee` for account `idx` if it opted into the low-leverage tier.
+    fn leverage_fee_discount(&self, idx: usize, fee: u128) -> u128 {
+        let tier = self.ext_params.low_leverage_tier;
+        let cap = self.accounts[idx].max_leverage;
+        if tier == 0 || cap == 0 || cap > tier {
+            return 0;
+        }
+        mul_div(
+            fee,
+            self.ext_params.low_leverage_fee_discount_bps as u128,
+            10_000,
+            Rounding::Down,
+        )
+    }
+
+    /// Discount on `fee` for account `idx` if it opted to be force-realized first.
+    fn adl_first_fee_discount(&self, idx: usize, fee: u128) -> u128 {
+        if self.accounts[idx].adl_first == 0 {
+            return 0;
+        }
+        mul_div(fee, self.ext_params.adl_first_fee_discount_bps as u128, 10_000, Rounding::Down)
+    }
+
+    /// Rebate on `fee` for a fill of `exec_size` that shrinks both the user's position
+    /// (without flipping it) and the net LP skew.
+    fn risk_reducing_fee_discount(&self, user_idx: usize, exec_size: i128, fee: u128) -> u128 {
+        let bps = self.ext_params.risk_reducing_fee_discount_bps;
+        if bps == 0 {
+            return 0;
+        }
+        let old_pos = self.accounts[user_idx].position_size.get();
+        let new_pos = old_pos.saturating_add(exec_size);
+        let reduces_position = new_pos == 0
+            || (new_pos.signum() == old_pos.signum()
+                && new_pos.unsigned_abs() < old_pos.unsigned_abs());
+        let net = self.net_lp_pos.get();
+        let reduces_skew = net.saturating_sub(exec_size).unsigned_abs() < net.unsigned_abs();
+        if old_pos == 0 || !reduces_position || !reduces_skew {
+            return 0;
+        }
+        mul_div(fee, bps as u128, 10_000, Rounding::Down)
+    }
+
+    /// Pre-fund fee credits for an account.
+    ///
+    /// The wrapper must have already transferred `amount` tokens into the vault.
+    /// This pre-pays future maintenance fees: vault increases, insurance receives
+    /// the amount as revenue (since credits are a coupon — spending them later
+    /// does NOT re-book into insurance), and the account's fee_credits balance
+    /// increases by `amount`.
+    pub fn deposit_fee_credits(&mut self, idx: u16, amount: u128, now_slot: u64) -> Result<()> {
+        let result = self.deposit_fee_credits_inner(idx, amount, now_slot);
+        self.finish_mutation("deposit_fee_credits", result.is_ok());
+        result
+    }
+
+    fn deposit_fee_credits_inner(&mut self, idx: u16, amount: u128, now_slot: u64) -> Result<()> {
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return Err(RiskError::Unauthorized);
+        }
+        let vault = self.vault.checked_add(amount).ok_or(RiskError::Overflow)?;
+        let insurance = self
+            .insurance_fund
+            .balance
+            .checked_add(amount)
+            .ok_or(RiskError::Overflow)?;
+        self.current_slot = now_slot;
+
+        // Wrapper transferred tokens into vault
+        self.vault = vault;
+
+        // Pre-fund: insurance receives the amount now.
+        // When credits are later spent during fee settlement, no further
+        // insurance booking occurs (coupon semantics).
+        self.insurance_fund.balance = insurance;
+        self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue.saturating_add(amount);
+        self.revenue.maintenance_fees = self.revenue.maintenance_fees.saturating_add(amount);
+
+        // Credit the account
+        self.accounts[idx as usize].fee_credits = self.accounts[idx as usize]
+            .fee_credits
+            .saturating_add_u128(amount);
+
+        Ok(())
+    }
+
+    /// Add fee credits without vault/insurance accounting.
+    /// Only for tests and Kani proofs — production code must use deposit_fee_credits.
+    #[cfg(any(test, feature = "test", kani))]
+    pub fn add_fee_credits(&mut self, idx: u16, amount: u128) -> Result<()> {
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return Err(RiskError::Unauthorized);
+        }
+        self.accounts[idx as usize].fee_credits = self.accounts[idx as usize]
+            .fee_credits
+            .saturating_add_u128(amount);
+        Ok(())
+    }
+
+    /// Set the risk reduction threshold (admin function).
+    /// This controls when risk-reduction-only mode is triggered. While an admin
+    /// council or a parameter timelock is active the change must go through
+    /// `queue_params_update`, for the council's approval and the guardian's veto.
+    #[inline]
+    pub fn set_risk_reduction_threshold(&mut self, new_threshold: u128) -> Result<()> {
+        if self.admin_council.threshold > 0 {
+            return Err(RiskError::ApprovalsMissing);
+        }
+        if self.ext_params.param_timelock_slots > 0 {
+            return Err(RiskError::TimelockActive);
+        }
+        self.params.risk_reduction_threshold = U128::new(new_threshold);
+        self.bump_state_seq();
+        Ok(())
+    }
+
+    /// Get the current risk reduction threshold.
+    #[inline]
+    pub fn risk_reduction_threshold(&self) -> u128 {
+        self.params.risk_reduction_threshold.get()
+    }
+
+    /// Schedule a fee holiday (admin function): trades in `[start_slot, end_slot)`
+    /// pay `fee_bps` instead of `trading_fee_bps`. The window expires on its own;
+    /// scheduling replaces any previous one, and an empty window clears it.
+    ///
+    /// A holiday can only discount: `fee_bps` may not exceed `trading_fee_bps`.
+    /// Like the other parameter setters it is unavailable while an admin council
+    /// (ApprovalsMissing) or a parameter timelock (TimelockActive) is active.
+    pub fn set_fee_holiday(&mut self, start_slot: u64, end_slot: u64, fee_bps: u64) -> Result<()> {
+        if self.admin_council.threshold > 0 {
+            return Err(RiskError::ApprovalsMissing);
+        }
+        if self.ext_params.param_timelock_slots > 0 {
+            return Err(RiskError::TimelockActive);
+        }
+        if start_slot > end_slot || fee_bps > self.params.trading_fee_bps {
+            return Err(RiskError::InvalidParams);
+        }
+        self.fee_holiday = FeeHoliday {
+            start_slot,
+            end_slot,
+            fee_bps,
+        };
+        self.bump_state_seq();
+        Ok(())
+    }
+
+    /// Schedule a one-off margin event such as an expiry at `slot` (admin function):
+    /// initial margin is tightened around it (see `ExtParams::event_margin_window_slots`).
+    /// The crank drops events once their window has passed.
+    pub fn schedule_margin_event(&mut self, slot: u64) -> Result<()> {
+        if slot == 0 {
+            return Err(RiskError::InvalidParams);
+        }
+        if self.margin_events.contains(&slot) {
+            return Ok(());
+        }
+        match self.margin_events.iter_mut().find(|event| **event == 0) {
+            Some(entry) => {
+                *entry = slot;
+                self.bump_state_seq();
+                Ok(())
+            }
+            None => Err(RiskError::InvalidParams),
+        }
+    }
+
+    /// Remove the margin event scheduled at `slot` (admin function), if any.
+    pub fn cancel_margin_event(&mut self, slot: u64) {
+        let mut cancelled = false;
+        for event in self.margin_events.iter_mut().filter(|event| **event == slot) {
+            *event = 0;
+            cancelled = true;
+        }
+        if cancelled {
+            self.bump_state_seq();
+        }
+    }
+
+    /// Initial margin (bps) the event schedule requires at `slot`: `event_initial_margin_bps`
+    /// at an event, easing linearly to `initial_margin_bps` at the window edges (0 = no
+    /// event within `event_margin_window_slots`).
+    pub fn event_initial_margin_bps(&self, slot: u64) -> u64 {
+        let ext = &self.ext_params;
+        event_margin_bps_for(
+            &self.margin_events,
+            ext.event_margin_window_slots,
+            ext.event_period_slots,
+            ext.event_initial_margin_bps,
+            self.params.initial_margin_bps,
+            slot,
+        )
+    }
+
+    /// Drop scheduled margin events whose window has passed.
+    fn prune_margin_events(&mut self, now_slot: u64) {
+        let window = self.ext_params.event_margin_window_slots;
+        for event in self.margin_events.iter_mut() {
+            if *event != 0 && event.saturating_add(window) < now_slot {
+                *event = 0;
+            }
+        }
+    }
+
+    /// Close an account and return its capital to the caller.
+    ///
+    /// Requirements:
+    /// - Account must exist
+    /// - Position must be zero (no open positions)
+    /// - fee_credits >= 0 (no outstanding fees owed)
+    /// - pnl must be 0 after settlement (positive pnl must be warmed up first)
+    ///
+    /// Returns Err(PnlNotWarmedUp) if pnl > 0 (user must wait for warmup).
+    /// Returns Err(Undercollateralized) if pnl < 0 (shouldn't happen after settlement).
+    /// Returns the capital amount on success.
+    pub fn close_account(&mut self, idx: u16, now_slot: u64, oracle_price: u64) -> Result<u128> {
+        let result = self.close_account_inner(idx, now_slot, oracle_price);
+        if let Ok(capital) = result {
+            self.record_event(EventKind::AccountClosed, idx, EVENT_NO_ACCOUNT, capital, 0, 0);
+        }
+        self.finish_mutation("close_account", result.is_ok());
+        result
+    }
+
+    fn close_account_inner(&mut self, idx: u16, now_slot: u64, oracle_price: u64) -> Result<u128> {
+        // Update current_slot so warmup/bookkeeping progresses consistently
+        self.current_slot = now_slot;
+
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return Err(RiskError::AccountNotFound);
+        }
+
+        // Full settlement: funding + maintenance fees + warmup
+        // This converts warmed pnl to capital and realizes negative pnl
+        self.touch_account_full_inner(idx, now_slot, oracle_price)?;
+
+        // Position must be zero
+        if !self.accounts[idx as usize].position_size.is_zero() {
+            return Err(RiskError::Undercollateralized); // Has open position
+        }
+
+        // Queued or released LP withdrawals must be cancelled or claimed first
+        if !self.accounts[idx as usize].lp_queued_shares.is_zero()
+            || !self.accounts[idx as usize].lp_claimable.is_zero()
+            || self.lp_holders_claimable(idx as usize) > 0
+        {
+            return Err(RiskError::WithdrawalQueued);
+        }
+
+        // Outside depositors' capital is not the owner's to take
+        if self.lp_external_shares(idx as usize) > 0 && self.lp_nav(idx as usize) > 0 {
+            return Err(RiskError::Unauthorized);
+        }
+
+        // Forgive any remaining fee debt (Finding C: fee debt traps).
+        // pay_fee_debt_from_capital (via touch_account_full above) already paid
+        // what it could. Any remainder is uncollectable — forgive and proceed.
+        if self.accounts[idx as usize].fee_credits.is_negative() {
+            self.accounts[idx as usize].fee_credits = I128::ZERO;
+        }
+
+        let account = &self.accounts[idx as usize];
+
+        // PnL must be zero to close. This enforces:
+        // 1. Users can't bypass warmup by closing with positive unwarmed pnl
+        // 2. Conservation is maintained (forfeiting pnl would create unbounded slack)
+        // 3. Negative pnl after full settlement implies insolvency
+        if account.pnl.is_positive() {
+            return Err(RiskError::PnlNotWarmedUp);
+        }
+        if account.pnl.is_negative() {
+            return Err(RiskError::Undercollateralized);
+        }
+
+        let capital = account.capital;
+
+        // Deduct from vault
+        self.vault = self
+            .vault
+            .checked_sub_u128(capital)
+            .ok_or(RiskError::InsufficientBalance)?;
+
+        // Decrement c_tot before freeing slot (free_slot zeroes account but doesn't update c_tot)
+        self.set_capital(idx as usize, 0);
+
+        // Free the slot
+        self.free_slot(idx);
+
+        Ok(capital.get())
+    }
+
+    /// Merge user account `src_idx` into `dst_idx` (same owner) and free `src_idx`,
+    /// so users can consolidate dust accounts.
+    ///
+    /// Both accounts are fully settled at `oracle_price` first, so their positions
+    /// net at the oracle price; capital, PnL, reserved PnL, fee credits, haircut
+    /// claims and emission points are combined. Positive PnL carried over restarts the destination's warmup. The
+    /// merged account must stay above maintenance margin (initial margin, with the
+    /// destination's leverage caps, and the owner notional cap if the merge grows the
+    /// destination's position), and a reduce-only account may not end up with more
+    /// exposure than it had; all of this is checked before either account changes.
+    /// Reduce-only flags and this epoch's drain carry over to the destination.
+    ///
+    /// Returns the capital moved from the source.
+    /// Relies on Solana transaction atomicity: if this returns Err, the entire TX aborts.
+    pub fn merge_accounts(
+        &mut self,
+        src_idx: u16,
+        dst_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<u128> {
+        let position = self
+            .accounts
+            .get(src_idx as usize)
+            .map_or(0, |a| a.position_size.get());
+        let result = self.merge_accounts_inner(src_idx, dst_idx, now_slot, oracle_price);
+        if let Ok(capital) = result {
+            self.record_event(
+                EventKind::AccountsMerged,
+                dst_idx,
+                src_idx,
+                capital,
+                position,
+                oracle_price,
+            );
+        }
+        self.finish_mutation("merge_accounts", result.is_ok());
+        result
+    }
+
+    fn merge_accounts_inner(
+        &mut self,
+        src_idx: u16,
+        dst_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<u128> {
+        let (src, dst) = (src_idx as usize, dst_idx as usize);
+        if src == dst || src >= MAX_ACCOUNTS || dst >= MAX_ACCOUNTS {
+            return Err(RiskError::InvalidParams);
+        }
+        if !self.is_used(src) || !self.is_used(dst) {
+            return Err(RiskError::AccountNotFound);
+        }
+        if self.accounts[src].owner != self.accounts[dst].owner {
+            return Err(RiskError::Unauthorized);
+        }
+        if !self.accounts[src].is_user() || !self.accounts[dst].is_user() {
+            return Err(RiskError::AccountKindMismatch);
+        }
+        if oracle_price == 0 || oracle_price > MAX_ORACLE_PRICE {
+            return Err(RiskError::Overflow);
+        }
+
+        // Settle funding, mark, fees and warmup so both sit at the oracle price
+        self.touch_account_full_inner(src_idx, now_slot, oracle_price)?;
+        self.touch_account_full_inner(dst_idx, now_slot, oracle_price)?;
+
+        let s = self.accounts[src];
+        let d = self.accounts[dst];
+        let (old_src, old_dst) = (s.position_size.get(), d.position_size.get());
+        let new_pos = old_dst.checked_add(old_src).ok_or(RiskError::Overflow)?;
+        let capital = s.capital.get();
+
+        // A reduce-only side may not come out of the merge with more exposure than it
+        // went in with (no growth, no flip); the flag then carries over to the merge
+        for (account, old) in [(&s, old_src), (&d, old_dst)] {
+            let shrinks = new_pos == 0
+                || (new_pos.signum() == old.signum()
+                    && saturating_abs_i128(new_pos) <= saturating_abs_i128(old));
+            if account.reduce_only != 0 && !shrinks {
+                return Err(RiskError::ReduceOnly);
+            }
+        }
+
+        // Check the merged account before anything moves. The haircut is taken on
+        // the current totals; netting PnL can only lower `pnl_pos_tot`, so this
+        // never overstates the merged equity.
+        if new_pos != 0 {
+            let merged = Account {
+                capital: U128::new(d.capital.get().saturating_add(capital)),
+                pnl: I128::new(d.pnl.get().saturating_add(s.pnl.get())),
+                fee_credits: d.fee_credits.saturating_add(s.fee_credits.get()),
+                position_size: I128::new(new_pos),
+                entry_price: oracle_price,
+                ..d
+            };
+            // Growing the destination's position is risk-increasing for it, so initial
+            // margin (with its leverage caps) and the owner notional cap apply
+            let risk_increasing = saturating_abs_i128(new_pos) > saturating_abs_i128(old_dst)
+                || (old_dst ^ new_pos) < 0;
+            let healthy = if risk_increasing {
+                let bps = self.initial_margin_bps_for(&merged);
+                self.is_above_margin_bps_mtm(&merged, oracle_price, bps)
+            } else {
+                self.is_above_maintenance_margin_mtm(&merged, oracle_price)
+            };
+            if !healthy {
+                return Err(RiskError::Undercollateralized);
+            }
+            let cap = self.ext_params.max_owner_notional.get();
+            if risk_increasing && cap > 0 {
+                if let Some(elsewhere) = self.owner_exposure_excluding(dst_idx, oracle_price) {
+                    let abs_src = saturating_abs_i128(old_src) as u128;
+                    let abs_new = saturating_abs_i128(new_pos) as u128;
+                    let notional = elsewhere
+                        .notional
+                        .saturating_sub(self.notional_at(abs_src, oracle_price, Rounding::Up))
+                        .saturating_add(self.notional_at(abs_new, oracle_price, Rounding::Up));
+                    if notional > cap {
+                        return Err(RiskError::OwnerLimitExceeded);
+                    }
+                }
+            }
+        }
+
+        // The drain limit follows the value both accounts extracted this epoch
+        let drain = self.epoch_drain(src_idx).saturating_add(self.epoch_drain(dst_idx));
+        let drain_len = self.epoch_len_or_shared(self.ext_params.drain_epoch_slots);
+        self.accounts[dst].drain_epoch_start = window_start_slot(self.current_slot, drain_len);
+        self.accounts[dst].epoch_drain = U128::new(drain);
+
+        // Reward points earned by the source carry over rather than being forfeited
+        self.accrue_emissions(src, now_slot);
+        self.accrue_emissions(dst, now_slot);
+        let (s_emit, d_emit) = (self.accounts[src], self.accounts[dst]);
+        self.accounts[dst].emission_pending =
+            d_emit.emission_pending.saturating_add(s_emit.emission_pending.get());
+        self.accounts[dst].emission_claimable =
+            d_emit.emission_claimable.saturating_add(s_emit.emission_claimable.get());
+
+        self.set_capital(src, 0);
+        self.set_capital(dst, d.capital.get().saturating_add(capital));
+
+        self.set_pnl(src, 0);
+        self.set_pnl(dst, d.pnl.get().saturating_add(s.pnl.get()));
+        self.accounts[dst].reserved_pnl = d.reserved_pnl.saturating_add(s.reserved_pnl);
+        self.accounts[dst].fee_credits = d.fee_credits.saturating_add(s.fee_credits.get());
+        self.accounts[dst].haircut_claim = d.haircut_claim.saturating_add(s.haircut_claim.get());
+        self.accounts[dst].reduce_only = d.reduce_only | s.reduce_only;
+        if s.pnl.is_positive() {
+            self.update_warmup_slope(dst_idx)?;
+        }
+
+        // Net positions: open interest falls by whatever the two legs offset
+        let offset = saturating_abs_i128(old_src)
+            .saturating_add(saturating_abs_i128(old_dst))
+            .saturating_sub(saturating_abs_i128(new_pos)) as u128;
+        self.total_open_interest = self.total_open_interest.saturating_sub(offset);
+        self.accounts[src].position_size = I128::ZERO;
+        let account = &mut self.accounts[dst];
+        account.position_size = I128::new(new_pos);
+        account.entry_price = oracle_price;
+        account.record_position_change(old_dst, new_pos, now_slot);
+
+        self.free_slot(src_idx);
+        Ok(capital)
+    }
+
+    /// Free an account slot (internal helper).
+    /// Clears the account, bitmap, and returns slot to freelist.
+    /// Caller must ensure the account is safe to free (no capital, no positive pnl, etc).
+    fn free_slot(&mut self, idx: u16) {
+        self.clear_lp_holdings(idx as usize);
+        self.accounts[idx as usize] = empty_account();
+        self.clear_used(idx as usize);
+        self.next_free[idx as usize] = self.free_head;
+        self.free_head = idx;
+        self.num_used_accounts = self.num_used_accounts.saturating_sub(1);
+    }
+
+    /// Garbage collect dust accounts.
+    ///
+    /// A "dust account" is a slot that can never pay out anything:
+    /// - position_size == 0
+    /// - capital == 0
+    /// - reserved_pnl == 0
+    /// - pnl <= 0
+    /// - no haircut claim and no unclaimed emission points
+    ///
+    /// With `ExtParams::gc_inactive_slots` set, flat zero-capital accounts dormant for
+    /// that long are also closed; their residual positive PnL is claimed for insurance.
+    ///
+    /// Any remaining negative PnL is socialized via ADL waterfall before freeing.
+    /// No token transfers occur - this is purely internal bookkeeping cleanup.
+    ///
+    /// Called at end of keeper_crank after liquidation/settlement has already run.
+    ///
+    /// Returns the number of accounts closed.
+    pub fn garbage_collect_dust(&mut self) -> u32 {
+        let (result, _) = self.garbage_collect_dust_inner();
+        self.finish_mutation("garbage_collect_dust", result > 0);
+        result
+    }
+
+    /// Returns the accounts closed and the (non-LP) accounts examined.
+    fn garbage_collect_dust_inner(&mut self) -> (u32, u32) {
+        // Collect dust candidates: accounts with zero position, capital, reserved, and non-positive pnl
+        let mut to_free: [u16; GC_CLOSE_BUDGET as usize] = [0; GC_CLOSE_BUDGET as usize];
+        let mut num_to_free = 0usize;
+        let mut examined = 0u32;
+
+        // Scan up to ACCOUNTS_PER_CRANK slots, capped to MAX_ACCOUNTS
+        let max_scan = (ACCOUNTS_PER_CRANK as usize).min(MAX_ACCOUNTS);
+        let start = self.gc_cursor as usize;
+
+        for offset in 0..max_scan {
+            // Budget check
+            if num_to_free >= GC_CLOSE_BUDGET as usize {
+                break;
+            }
+
+            let idx = start.wrapping_add(offset) & ACCOUNT_IDX_MASK;
+
+            // Check if slot is used via bitmap
+            let block = idx >> 6;
+            let bit = idx & 63;
+            if (self.used[block] & (1u64 << bit)) == 0 {
+                continue;
+            }
+
+            // NEVER garbage collect LP accounts - they are essential for market operation
+            if self.accounts[idx].is_lp() {
+                continue;
+            }
+            examined = examined.saturating_add(1);
+
+            // Best-effort fee settle so accounts with tiny capital get drained in THIS sweep.
+            let _ = self.settle_maintenance_fee_best_effort_for_crank(idx as u16, self.current_slot);
+
+            // Dust predicate: must have zero position, capital, reserved, and non-positive pnl.
+            // Dormant accounts (`ExtParams::gc_inactive_slots`) may still hold residual pnl.
+            {
+                let account = &self.accounts[idx];
+                if !account.position_size.is_zero() {
+                    continue;
+                }
+                if !account.capital.is_zero() {
+                    continue;
+                }
+                let inactive = self.ext_params.gc_inactive_slots;
+                let dormant = inactive > 0
+                    && self.current_slot.saturating_sub(account.last_activity_slot) >= inactive;
+                if !dormant && (account.reserved_pnl != 0 || account.pnl.is_positive()) {
+                    continue;
+                }
+                // Outstanding haircut claims and emission points would be forfeited
+                if !account.haircut_claim.is_zero()
+                    || !account.emission_pending.is_zero()
+                    || !account.emission_claimable.is_zero()
+                {
+                    continue;
+                }
+            }
+
+            // Claim a dormant account's residual pnl (at the haircut) for insurance
+            let residual = self.effective_pos_pnl(self.accounts[idx].pnl.get());
+            if residual > 0 || self.accounts[idx].reserved_pnl != 0 {
+                self.insurance_fund.balance = self.insurance_fund.balance.saturating_add(residual);
+                self.set_pnl(idx, 0);
+                self.accounts[idx].reserved_pnl = 0;
+            }
+
+            // If flat, funding is irrelevant — snap to global so dust can be collected.
+            // Position size is already confirmed zero above, so no unsettled funding value.
+            if self.accounts[idx].funding_index != self.funding_index_qpb_e6 {
+                self.accounts[idx].funding_index = self.funding_index_qpb_e6;
+            }
+            self.accounts[idx].holding_fee_index = self.holding_fee_index;
+
+            // Write off negative pnl (spec §6.1: unpayable loss just reduces Residual)
+            if self.accounts[idx].pnl.is_negative() {
+                self.set_pnl(idx, 0);
+            }
+
+            // Queue for freeing
+            to_free[num_to_free] = idx as u16;
+            num_to_free = num_to_free.saturating_add(1);
+        }
+
+        // Update cursor for next call
+        self.gc_cursor = (start.wrapping_add(max_scan) & ACCOUNT_IDX_MASK) as u16;
+
+        // Free all collected dust accounts
+        for i in 0..num_to_free {
+            self.free_slot(to_free[i]);
+        }
+
+        (num_to_free as u32, examined)
+    }
+
+    // ========================================
+    // Keeper Crank
+    // ========================================
+
+    /// Check if a fresh crank is required before state-changing operations.
+    /// Returns Err if the crank is stale (too old).
+    pub fn require_fresh_crank(&self, now_slot: u64) -> Result<()> {
+        if now_slot.saturating_sub(self.last_crank_slot) > self.max_crank_staleness_slots {
+            return Err(RiskError::Unauthorized); // NeedsCrank
+        }
+        Ok(())
+    }
+
+    /// Check if a full sweep started recently.
+    /// For risk-increasing ops, we require a sweep to have STARTED recently.
+    /// The priority-liquidation phase runs every crank, so once a sweep starts,
+    /// the worst accounts are immediately addressed.
+    pub fn require_recent_full_sweep(&self, now_slot: u64) -> Result<()> {
+        if now_slot.saturating_sub(self.last_full_sweep_start_slot) > self.max_crank_staleness_slots
+        {
+            return Err(RiskError::Unauthorized); // SweepStale
+        }
+        Ok(())
+    }
+
+
+    /// Check if force-realize mode is active (insurance at or below threshold).
+    /// When active, keeper_crank will run windowed force-realize steps.
     #[inline]
-    pub fn set_capital(&mut self, idx: usize, new_capital: u128) {
-        let old = self.accounts[idx].capital.get();
-        if new_capital >= old {
-            self.c_tot = U128::new(self.c_tot.get().saturating_add(new_capital - old));
+    fn force_realize_active(&self) -> bool {
+        self.insurance_fund.balance <= self.params.risk_reduction_threshold
+    }
+
+    /// Keeper crank entrypoint - advances global state and performs maintenance.
+    ///
+    /// Returns CrankOutcome with flags indicating what happened.
+    ///
+    /// Behavior:
+    /// 1. Accrue funding
+    /// 2. Advance last_crank_slot if now_slot > last_crank_slot
+    /// 3. Settle maintenance fees for caller (50% discount)
+    /// 4. Process up to ACCOUNTS_PER_CRANK occupied accounts:
+    ///    - Liquidation (if not in force-realize mode)
+    ///    - Force-realize (if insurance at/below threshold)
+    ///    - Socialization (haircut profits to cover losses)
+    ///    - LP max tracking
+    /// 5. Detect and finalize full sweep completion
+    ///
+    /// This is the single permissionless "do-the-right-thing" entrypoint.
+    /// - Always attempts caller's maintenance settle with 50% discount (best-effort)
+    /// - Only advances last_crank_slot when now_slot > last_crank_slot
+    /// - Returns last_cursor: the index where this crank stopped
+    /// - Returns sweep_complete: true if this crank completed a full sweep
+    ///
+    /// When the system has fewer than ACCOUNTS_PER_CRANK accounts, one crank
+    /// covers all accounts and completes a full sweep.
+    pub fn keeper_crank(
+        &mut self,
+        caller_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+        funding_rate_bps_per_slot: i64,
+        allow_panic: bool,
+        max_pnl_vault_bps: u64,
+        max_oi_abs: u128,
+    ) -> Result<CrankOutcome> {
+        self.keeper_crank_impl(
+            caller_idx,
+            now_slot,
+            oracle_price,
+            funding_rate_bps_per_slot,
+            allow_panic,
+            max_pnl_vault_bps,
+            max_oi_abs,
+            None,
+            CRANK_PHASES_ALL,
+        )
+    }
+
+    /// `keeper_crank` running only the selected phases (`CRANK_PHASE_*` bits), so a
+    /// compute-constrained keeper can split one crank's work across transactions.
+    ///
+    /// Funding accrual and the caller's fee settlement run every time. Cranks
+    /// without `CRANK_PHASE_LIQUIDATE` leave the cursor in place, so they work the
+    /// window the next liquidating crank covers and never count toward a sweep.
+    /// An empty mask or unknown bits are rejected.
+    #[allow(clippy::too_many_arguments)]
+    pub fn keeper_crank_phases(
+        &mut self,
+        caller_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+        funding_rate_bps_per_slot: i64,
+        allow_panic: bool,
+        max_pnl_vault_bps: u64,
+        max_oi_abs: u128,
+        phases: u8,
+    ) -> Result<CrankOutcome> {
+        self.keeper_crank_impl(
+            caller_idx,
+            now_slot,
+            oracle_price,
+            funding_rate_bps_per_slot,
+            allow_panic,
+            max_pnl_vault_bps,
+            max_oi_abs,
+            None,
+            phases,
+        )
+    }
+
+    /// `keeper_crank` over one shard of the account index space, so several keepers
+    /// can crank disjoint slices of a large slab in parallel transactions.
+    ///
+    /// Each shard keeps its own cursor and scans only its range. A sharded sweep
+    /// starts with the first shard crank and completes when every shard of the same
+    /// `count` has scanned its range; until then a finished shard's cranks are
+    /// skipped and a different `count` is rejected. Unsharded cranks keep their own
+    /// sweep alongside.
+    #[allow(clippy::too_many_arguments)]
+    pub fn keeper_crank_shard(
+        &mut self,
+        caller_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+        funding_rate_bps_per_slot: i64,
+        allow_panic: bool,
+        max_pnl_vault_bps: u64,
+        max_oi_abs: u128,
+        shard: CrankShard,
+    ) -> Result<CrankOutcome> {
+        self.keeper_crank_impl(
+            caller_idx,
+            now_slot,
+            oracle_price,
+            funding_rate_bps_per_slot,
+            allow_panic,
+            max_pnl_vault_bps,
+            max_oi_abs,
+            Some(shard),
+            CRANK_PHASES_ALL,
+        )
+    }
+
+    /// Record the conversion price of a quanto market (quote per collateral unit ×
+    /// price scale, e.g. SOL/USD for a SOL-margined BTC/USD market). Rejected for
+    /// other payoff modes and for prices outside `1..=MAX_ORACLE_PRICE`.
+    pub fn set_quanto_price(&mut self, price: u64, now_slot: u64) -> Result<()> {
+        let result = self.set_quanto_price_inner(price, now_slot);
+        self.finish_mutation("set_quanto_price", result.is_ok());
+        result
+    }
+
+    fn set_quanto_price_inner(&mut self, price: u64, now_slot: u64) -> Result<()> {
+        let quanto = self.ext_params.payoff_mode == PayoffMode::Quanto;
+        if !quanto || price == 0 || price > MAX_ORACLE_PRICE {
+            return Err(RiskError::InvalidParams);
+        }
+        self.quanto_price = price;
+        self.quanto_price_slot = now_slot;
+        Ok(())
+    }
+
+    /// `keeper_crank` for a quanto market that first applies the conversion oracle's
+    /// price (see `set_quanto_price`), so the sweep values collateral at it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn keeper_crank_with_quanto_price(
+        &mut self,
+        caller_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+        funding_rate_bps_per_slot: i64,
+        allow_panic: bool,
+        max_pnl_vault_bps: u64,
+        max_oi_abs: u128,
+        quanto_price: u64,
+    ) -> Result<CrankOutcome> {
+        self.set_quanto_price_inner(quanto_price, now_slot)?;
+        self.keeper_crank(
+            caller_idx,
+            now_slot,
+            oracle_price,
+            funding_rate_bps_per_slot,
+            allow_panic,
+            max_pnl_vault_bps,
+            max_oi_abs,
+        )
+    }
+
+    /// `keeper_crank` that first applies the collateral exchange rate reported by
+    /// the keeper (see `update_collateral_rate`), so margin checks during the sweep
+    /// use the uprated collateral value. A rate failing its bounds rejects the crank.
+    #[allow(clippy::too_many_arguments)]
+    pub fn keeper_crank_with_collateral_rate(
+        &mut self,
+        caller_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+        funding_rate_bps_per_slot: i64,
+        allow_panic: bool,
+        max_pnl_vault_bps: u64,
+        max_oi_abs: u128,
+        collateral_rate_e9: u64,
+    ) -> Result<CrankOutcome> {
+        self.update_collateral_rate_inner(collateral_rate_e9, now_slot)?;
+        self.keeper_crank(
+            caller_idx,
+            now_slot,
+            oracle_price,
+            funding_rate_bps_per_slot,
+            allow_panic,
+            max_pnl_vault_bps,
+            max_oi_abs,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn keeper_crank_impl(
+        &mut self,
+        caller_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+        funding_rate_bps_per_slot: i64,
+        allow_panic: bool,
+        max_pnl_vault_bps: u64,
+        max_oi_abs: u128,
+        shard: Option<CrankShard>,
+        phases: u8,
+    ) -> Result<CrankOutcome> {
+        let result = self.keeper_crank_inner(
+            caller_idx,
+            now_slot,
+            oracle_price,
+            funding_rate_bps_per_slot,
+            allow_panic,
+            max_pnl_vault_bps,
+            max_oi_abs,
+            shard,
+            phases,
+        );
+        if let Some(outcome) = result.as_ref().ok().filter(|outcome| !outcome.skipped) {
+            self.record_event(
+                EventKind::Crank,
+                caller_idx,
+                EVENT_NO_ACCOUNT,
+                outcome.num_liquidations as u128,
+                self.funding_rate_bps_per_slot_last as i128,
+                oracle_price,
+            );
+            self.record_market_sample(now_slot, oracle_price);
+            self.record_price_point(now_slot, oracle_price);
+            self.roll_epoch(now_slot);
+            self.roll_liq_epoch(now_slot);
+            self.observe_crank_deviation(caller_idx, now_slot, oracle_price);
+            self.settle_lp_withdrawal_epoch(now_slot, oracle_price);
+            self.assess_lp_performance_fees(now_slot);
+        }
+        // Throttled and deduplicated cranks change nothing, so they leave state_seq alone
+        let committed = result.as_ref().is_ok_and(|outcome| !outcome.skipped);
+        self.finish_mutation("keeper_crank", committed);
+        result
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn keeper_crank_inner(
+        &mut self,
+        caller_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+        funding_rate_bps_per_slot: i64,
+        allow_panic: bool,
+        max_pnl_vault_bps: u64,
+        max_oi_abs: u128,
+        shard: Option<CrankShard>,
+        phases: u8,
+    ) -> Result<CrankOutcome> {
+        // Validate oracle price bounds (prevents overflow in mark_pnl calculations)
+        if oracle_price == 0 || oracle_price > MAX_ORACLE_PRICE {
+            return Err(RiskError::Overflow);
+        }
+        if phases == 0 || phases & !CRANK_PHASES_ALL != 0 {
+            return Err(RiskError::InvalidParams);
+        }
+        let settle = phases & CRANK_PHASE_SETTLE != 0;
+        let liquidate = phases & CRANK_PHASE_LIQUIDATE != 0;
+        if let Some(shard) = shard {
+            if shard.count == 0
+                || shard.count as usize > core::cmp::min(MAX_CRANK_SHARDS, MAX_ACCOUNTS)
+                || shard.index >= shard.count
+                || (self.crank_shard_sweep_active && shard.count != self.crank_shard_count)
+            {
+                return Err(RiskError::InvalidParams);
+            }
+            // Finished shards wait for the rest of the sweep
+            if self.crank_shard_sweep_active && self.crank_shard_done & shard.bit() != 0 {
+                return Ok(self.skipped_crank_outcome());
+            }
+        }
+
+        // Zero crank caps fall back to the configured ExtParams defaults
+        let max_pnl_vault_bps = if max_pnl_vault_bps == 0 {
+            self.ext_params.max_pnl_vault_bps
+        } else {
+            max_pnl_vault_bps
+        };
+        let max_oi_abs = if max_oi_abs == 0 {
+            self.ext_params.max_oi_abs.get()
+        } else {
+            max_oi_abs
+        };
+
+        // Throttle sweep starts: within the gap (or the same slot) of the last sweep
+        // start a liquidating crank is a no-op. Cranks without the liquidation phase
+        // never start a sweep, so they neither get throttled nor restart the gap.
+        let starting_new_sweep = match shard {
+            None => self.crank_cursor == self.sweep_start_idx,
+            Some(_) => !self.crank_shard_sweep_active,
+        };
+        if starting_new_sweep && liquidate && self.last_full_sweep_start_slot > 0 {
+            let gap = core::cmp::max(self.ext_params.min_crank_interval_slots, 1);
+            if now_slot < self.last_full_sweep_start_slot.saturating_add(gap) {
+                return Ok(self.skipped_crank_outcome());
+            }
+        }
+
+        // Update current_slot so warmup/bookkeeping progresses consistently
+        self.current_slot = now_slot;
+        self.observe_oracle_gap(now_slot, oracle_price);
+
+        // Pass totals are reported as deltas of the revenue counters
+        let maintenance_fees_before = self.revenue.maintenance_fees.get();
+        let fee_revenue_before = self.insurance_fund.fee_revenue.get();
+
+        // Start of a new sweep
+        if starting_new_sweep && liquidate {
+            self.last_full_sweep_start_slot = now_slot;
+            // Reset in-progress lp_max_abs for fresh sweep
+            match shard {
+                None => self.lp_max_abs_sweep = U128::ZERO,
+                Some(shard) => {
+                    self.crank_shard_sweep_active = true;
+                    self.crank_shard_count = shard.count;
+                    self.crank_shard_done = 0;
+                    self.crank_shard_lp_max_abs = U128::ZERO;
+                    for i in 0..shard.count {
+                        let start = CrankShard { index: i, count: shard.count }.range().0;
+                        self.crank_shard_cursors[i as usize] = start as u16;
+                    }
+                }
+            }
+        }
+
+        // Accrue funding first using the STORED rate (anti-retroactivity).
+        // This ensures funding charged for the elapsed interval uses the rate that was
+        // in effect at the start of the interval, NOT the new rate computed from current state.
+        self.accrue_funding_inner(now_slot, oracle_price)?;
+
+        // Now set the new rate for the NEXT interval (anti-retroactivity).
+        // The funding_rate_bps_per_slot parameter becomes the rate for [now_slot, next_accrual).
+        self.store_funding_rate(funding_rate_bps_per_slot);
+        self.update_mark_ema(now_slot, oracle_price);
+        self.update_price_emas(now_slot, oracle_price);
+        self.prune_margin_events(now_slot);
+
+        // Check if we're advancing the global crank slot
+        let advanced = now_slot > self.last_crank_slot;
+        if advanced {
+            self.last_crank_slot = now_slot;
+        }
+
+        // Always attempt caller's maintenance settle (best-effort, no timestamp games)
+        let (slots_forgiven, caller_settle_ok) = if (caller_idx as usize) < MAX_ACCOUNTS
+            && self.is_used(caller_idx as usize)
+        {
+            let last_fee = self.accounts[caller_idx as usize].last_fee_slot;
+            let dt = now_slot.saturating_sub(last_fee);
+            let forgive = dt / 2;
+
+            if forgive > 0 && dt > 0 {
+                self.accounts[caller_idx as usize].last_fee_slot = last_fee.saturating_add(forgive);
+            }
+            let settle_result =
+                self.settle_maintenance_fee_best_effort_for_crank(caller_idx, now_slot);
+            (forgive, settle_result.is_ok())
         } else {
-            self.c_tot = U128::new(self.c_tot.get().saturating_sub(old - new_capital));
+            (0, true)
+        };
+
+        // OI cap enforcement: if total OI exceeds cap, block new position opens
+        // by temporarily boosting risk_reduction_threshold to u128::MAX.
+        // This uses the existing risk-reduction gate in trade handlers.
+        let oi_cap_active = max_oi_abs > 0 && self.total_open_interest.get() > max_oi_abs;
+
+        // Detect conditions for informational flags (before processing)
+        let force_realize_active = self.force_realize_active();
+
+        // Process up to ACCOUNTS_PER_CRANK occupied accounts
+        let mut num_liquidations: u32 = 0;
+        let mut num_liq_errors: u16 = 0;
+        let mut force_realize_closed: u16 = 0;
+        let mut force_realize_errors: u16 = 0;
+        let mut max_pnl_closed: u16 = 0;
+        let mut max_pnl_errors: u16 = 0;
+        let mut sweep_complete = false;
+        let mut accounts_processed: u16 = 0;
+        let mut liq_budget = LIQ_BUDGET_PER_CRANK;
+        let liq_notional_cap = self.ext_params.max_crank_liq_notional.get();
+        let liq_price = self.smoothed_price(PriceUse::Liquidation, oracle_price);
+        let max_pnl_price = self.smoothed_price(PriceUse::MaxPnl, oracle_price);
+        let mut liq_notional: u128 = 0;
+        let mut liq_backlog = false;
+        let mut force_realize_budget = FORCE_REALIZE_BUDGET_PER_CRANK;
+        let mut liq_fee_to_keeper: u128 = 0;
+        let mut liq_fee_to_insurance: u128 = 0;
+        let mut liq_fee_parked_for_lp: u128 = 0;
+        let mut liq_fee_to_lp: u128 = 0;
+        let mut liq_fee_undistributed: u128 = 0;
+        let mut funding_long_to_short: u128 = 0;
+        let mut funding_short_to_long: u128 = 0;
+        let mut settle_work = CrankPhaseWork::default();
+        let mut liquidate_work = CrankPhaseWork::default();
+
+        // Iterate through index space looking for occupied accounts
+        // (a shard scans only its own range, from its own cursor)
+        let (range_start, range_end) = shard.map_or((0, MAX_ACCOUNTS), |shard| shard.range());
+        let mut idx = match shard {
+            None => self.crank_cursor as usize,
+            Some(shard) => self.crank_shard_cursors[shard.index as usize] as usize,
+        };
+        let scan_limit = range_end.saturating_sub(range_start);
+        let mut slots_scanned: usize = 0;
+        let mut shard_finished = false;
+
+        // Accounts that opted to be unwound first are force-realized before the sweep
+        if liquidate && force_realize_active {
+            let (closed, errors) = self.force_realize_adl_first(
+                range_start..range_end,
+                now_slot,
+                oracle_price,
+                force_realize_budget,
+            );
+            force_realize_closed = force_realize_closed.saturating_add(closed);
+            force_realize_errors = force_realize_errors.saturating_add(errors);
+            force_realize_budget = force_realize_budget.saturating_sub(closed);
+        }
+
+        while accounts_processed < ACCOUNTS_PER_CRANK && slots_scanned < scan_limit {
+            slots_scanned = slots_scanned.saturating_add(1);
+
+            // Check if slot is used
+            let block = idx >> 6;
+            let bit = idx & 63;
+            let is_occupied = (self.used[block] & (1u64 << bit)) != 0;
+
+            if is_occupied {
+                accounts_processed = accounts_processed.saturating_add(1);
+
+                // Settle maintenance fees for every visited account.
+                // This drains idle accounts over time so they eventually become dust.
+                if settle {
+                    settle_work.accounts = settle_work.accounts.saturating_add(1);
+                    let _ = self.settle_maintenance_fee_best_effort_for_crank(idx as u16, now_slot);
+                    // Touch account and settle warmup to drain abandoned positive PnL
+                    let funding = self.pending_funding_payment(&self.accounts[idx]);
+                    if self.touch_account_inner(idx as u16).is_ok() && funding > 0 {
+                        if self.accounts[idx].position_size.is_positive() {
+                            funding_long_to_short =
+                                add_u128(funding_long_to_short, funding as u128);
+                        } else {
+                            funding_short_to_long =
+                                add_u128(funding_short_to_long, funding as u128);
+                        }
+                    }
+                    self.settle_warmup_to_capital_for_crank(idx as u16);
+                    self.pay_haircut_claim(idx, oracle_price);
+                    self.mature_markout(idx, now_slot, oracle_price);
+                    self.settle_credit_line(idx, now_slot, oracle_price);
+                }
+
+                if liquidate {
+                    liquidate_work.accounts = liquidate_work.accounts.saturating_add(1);
+                }
+
+                // === Liquidation (if not in force-realize mode) ===
+                if liquidate && !force_realize_active && liq_budget > 0 {
+                    // A smoothed liquidation price must confirm the spot breach
+                    let confirmed = liq_price == oracle_price
+                        || !self.is_above_maintenance_margin_mtm(&self.accounts[idx], liq_price);
+                    if !self.accounts[idx].position_size.is_zero() && confirmed {
+                        let gap_equity = self.gap_print_equity(idx, now_slot);
+                        // Notional budget: close at most what is left of it
+                        let max_close = if liq_notional_cap == 0 {
+                            u128::MAX
+                        } else {
+                            let left = liq_notional_cap.saturating_sub(liq_notional);
+                            self.base_for_notional(left, oracle_price, Rounding::Down)
+                        };
+                        let capped = max_close < self.accounts[idx].position_size.unsigned_abs();
+                        match self.liquidate_at_oracle_core(
+                            idx as u16,
+                            Some(caller_idx),
+                            max_close,
+                            now_slot,
+                            oracle_price,
+                        ) {
+                            Ok(Some(record)) => {
+                                liq_notional = liq_notional.saturating_add(self.notional_at(
+                                    record.closed_abs,
+                                    oracle_price,
+                                    Rounding::Up,
+                                ));
+                                num_liquidations = num_liquidations.saturating_add(1);
+                                liq_budget = liq_budget.saturating_sub(1);
+                                liq_fee_to_keeper = add_u128(liq_fee_to_keeper, record.fee_to_keeper);
+                                liq_fee_to_insurance =
+                                    add_u128(liq_fee_to_insurance, record.fee_to_insurance);
+                                if self.accounts[idx].is_lp() {
+                                    // A liquidated LP takes no share of its own fee
+                                    let fee_to_lp = record.fee_to_lp;
+                                    let distributed =
+                                        self.distribute_insurance_to_lps(fee_to_lp, Some(idx));
+                                    liq_fee_to_lp = add_u128(liq_fee_to_lp, distributed);
+                                    liq_fee_undistributed = add_u128(
+                                        liq_fee_undistributed,
+                                        fee_to_lp.saturating_sub(distributed),
+                                    );
+                                } else {
+                                    liq_fee_parked_for_lp =
+                                        add_u128(liq_fee_parked_for_lp, record.fee_to_lp);
+                                }
+                                if let Some(equity) = gap_equity {
+                                    self.record_gap_claim(idx, equity, oracle_price);
+                                }
+                                liq_backlog = capped
+                                    && !self.accounts[idx].position_size.is_zero()
+                                    && !self.is_above_maintenance_margin_mtm(
+                                        &self.accounts[idx],
+                                        oracle_price,
+                                    );
+                            }
+                            Ok(None) => {}
+                            // The remaining budget is below the minimum liquidation size
+                            Err(RiskError::InvalidParams) if capped => liq_backlog = true,
+                            Err(_) => {
+                                num_liq_errors = num_liq_errors.saturating_add(1);
+                            }
+                        }
+                        // Out of budget: stop here so the next crank resumes at this account
+                        if liq_backlog {
+                            break;
+                        }
+                    }
+
+                    // Force-close negative equity or dust positions
+                    if !self.accounts[idx].position_size.is_zero() {
+                        let equity =
+                            self.account_equity_mtm_at_oracle(&self.accounts[idx], oracle_price);
+                        let abs_pos = self.accounts[idx].position_size.unsigned_abs();
+                        let is_dust = abs_pos < self.params.min_liquidation_abs.get();
+
+                        if equity == 0 || is_dust {
+                            // Force close: settle mark, close position, write off loss
+                            let _ = self.touch_account_for_liquidation(idx as u16, now_slot, oracle_price);
+                            let pos = self.accounts[idx].position_size.get();
+                            if self.oracle_close_position_core(idx as u16, oracle_price).is_ok() {
+                                self.record_force_close(idx, pos, oracle_price);
+                            }
+                            self.lifetime_force_realize_closes =
+                                self.lifetime_force_realize_closes.saturating_add(1);
+                        }
+                    }
+                }
+
+                // === Force-realize (when insurance at/below threshold) ===
+                if liquidate && force_realize_active && force_realize_budget > 0 {
+                    if !self.accounts[idx].position_size.is_zero() {
+                        if self
+                            .touch_account_for_force_realize(idx as u16, now_slot, oracle_price)
+                            .is_ok()
+                        {
+                            let pos = self.accounts[idx].position_size.get();
+                            if self.oracle_close_position_core(idx as u16, oracle_price).is_ok() {
+                                self.record_force_close(idx, pos, oracle_price);
+                                force_realize_closed = force_realize_closed.saturating_add(1);
+                                force_realize_budget = force_realize_budget.saturating_sub(1);
+                                self.lifetime_force_realize_closes =
+                                    self.lifetime_force_realize_closes.saturating_add(1);
+                                self.record_adl_event(now_slot);
+                            } else {
+                                force_realize_errors = force_realize_errors.saturating_add(1);
+                            }
+                        } else {
+                            force_realize_errors = force_realize_errors.saturating_add(1);
+                        }
+                    }
+                }
+
+                // === Max PnL cap check ===
+                // If max_pnl_vault_bps > 0 and position has unrealized profit
+                // exceeding the cap, force-close it to protect LP vault
+                // (after the force-close grace period, if one is configured)
+                let mut over_pnl_cap = false;
+                if liquidate
+                    && max_pnl_vault_bps > 0
+                    && self.ext_params.max_epoch_drain.is_zero()
+                    && !self.accounts[idx].position_size.is_zero()
+                    && !self.accounts[idx].is_lp()
+                {
+                    let pos = self.accounts[idx].position_size.get();
+                    let entry = self.accounts[idx].entry_price;
+                    let settled_pnl = self.accounts[idx].pnl.get();
+
+                    if let Ok(mark_pnl) = self.mark_pnl(pos, entry, max_pnl_price) {
+                        let total_pnl = settled_pnl.saturating_add(mark_pnl);
+                        if total_pnl > 0 {
+                            // max_pnl_vault_bps is pre-computed absolute cap (by program wrapper)
+                            // Wrapper computes: lp_capital_total * bps / 10000
+                            let max_pnl = max_pnl_vault_bps as u128;
+                            over_pnl_cap = (total_pnl as u128) > max_pnl;
+                            if over_pnl_cap
+                                && self.force_close_grace_elapsed(
+                                    idx,
+                                    now_slot,
+                                    total_pnl,
+                                    oracle_price,
+                                )
+                            {
+                                // Force close this position
+                                if self
+                                    .touch_account_for_force_realize(idx as u16, now_slot, oracle_price)
+                                    .is_ok()
+                                {
+                                    if self.oracle_close_position_core(idx as u16, oracle_price).is_ok()
+                                    {
+                                        self.settle_close_price_premium(
+                                            idx,
+                                            pos,
+                                            oracle_price,
+                                            now_slot,
+                                        );
+                                        self.record_force_close(idx, pos, oracle_price);
+                                        max_pnl_closed = max_pnl_closed.saturating_add(1);
+                                        self.accounts[idx].force_close_deadline_slot = 0;
+                                        self.lifetime_force_realize_closes =
+                                            self.lifetime_force_realize_closes.saturating_add(1);
+                                        self.record_adl_event(now_slot);
+                                    } else {
+                                        max_pnl_errors = max_pnl_errors.saturating_add(1);
+                                    }
+                                } else {
+                                    max_pnl_errors = max_pnl_errors.saturating_add(1);
+                                }
+                            }
+                        }
+                    }
+                }
+
+                // Back under the cap (or flat): the pending force-close is withdrawn
+                if liquidate && !over_pnl_cap && self.accounts[idx].force_close_deadline_slot != 0 {
+                    self.accounts[idx].force_close_deadline_slot = 0;
+                    self.record_event(
+                        EventKind::ForceCloseCancelled,
+                        idx as u16,
+                        EVENT_NO_ACCOUNT,
+                        0,
+                        0,
+                        oracle_price,
+                    );
+                }
+
+                // === LP max tracking ===
+                if liquidate && self.accounts[idx].is_lp() {
+                    let abs_pos = U128::new(self.accounts[idx].position_size.unsigned_abs());
+                    if shard.is_some() {
+                        self.crank_shard_lp_max_abs = self.crank_shard_lp_max_abs.max(abs_pos);
+                    } else {
+                        self.lp_max_abs_sweep = self.lp_max_abs_sweep.max(abs_pos);
+                    }
+                }
+            }
+
+            if shard.is_some() {
+                // Advance within the shard's range
+                idx = idx.saturating_add(1);
+                if idx >= range_end {
+                    shard_finished = true;
+                    break;
+                }
+                continue;
+            }
+
+            // Advance to next index (with wrap)
+            idx = idx.wrapping_add(1) & ACCOUNT_IDX_MASK;
+
+            // Check for sweep completion: we've wrapped around to sweep_start_idx
+            // (and we've actually processed some slots, not just starting)
+            if idx == self.sweep_start_idx as usize && slots_scanned > 0 {
+                sweep_complete = true;
+                break;
+            }
         }
-        self.accounts[idx].capital = U128::new(new_capital);
-    }
 
-    /// Recompute c_tot and pnl_pos_tot from account data. For test use after direct state mutation.
-    pub fn recompute_aggregates(&mut self) {
-        let mut c_tot = 0u128;
-        let mut pnl_pos_tot = 0u128;
-        self.for_each_used(|_idx, account| {
-            c_tot = c_tot.saturating_add(account.capital.get());
-            let pnl = account.pnl.get();
-            if pnl > 0 {
-                pnl_pos_tot = pnl_pos_tot.saturating_add(pnl as u128);
+        // Without the liquidation phase the cursor and the sweep stay where they were
+        if !liquidate {
+            sweep_complete = false;
+        }
+        match shard {
+            _ if !liquidate => {}
+            None => {
+                // Update cursor for next crank
+                self.crank_cursor = idx as u16;
+
+                // If sweep complete, finalize
+                if sweep_complete {
+                    self.last_full_sweep_completed_slot = now_slot;
+                    self.lp_max_abs = self.lp_max_abs_sweep;
+                    self.sweep_start_idx = self.crank_cursor;
+                }
             }
-        });
-        self.c_tot = U128::new(c_tot);
-        self.pnl_pos_tot = U128::new(pnl_pos_tot);
-    }
+            Some(shard) => {
+                self.crank_shard_cursors[shard.index as usize] =
+                    if shard_finished { range_start } else { idx } as u16;
+                if shard_finished {
+                    self.crank_shard_done |= shard.bit();
+                }
+                // All shards done: the sharded sweep is complete
+                if self.crank_shard_done == CrankShard::all_bits(shard.count) {
+                    sweep_complete = true;
+                    self.crank_shard_sweep_active = false;
+                    self.crank_shard_done = 0;
+                    self.last_full_sweep_completed_slot = now_slot;
+                    self.lp_max_abs = self.crank_shard_lp_max_abs;
+                }
+            }
+        }
 
-    /// Compute haircut ratio (h_num, h_den) per spec §3.2.
-    /// h = min(Residual, PNL_pos_tot) / PNL_pos_tot where Residual = max(0, V - C_tot - I).
-    /// Returns (1, 1) when PNL_pos_tot == 0.
-    #[inline]
-    pub fn haircut_ratio(&self) -> (u128, u128) {
-        let pnl_pos_tot = self.pnl_pos_tot.get();
-        if pnl_pos_tot == 0 {
-            return (1, 1);
+        // Hand out the LP share of this crank's user liquidation fees in one pass
+        let batch_to_lp = self.distribute_insurance_to_lps(liq_fee_parked_for_lp, None);
+        let liq_fee_to_lp = add_u128(liq_fee_to_lp, batch_to_lp);
+        let undistributed = liq_fee_parked_for_lp
+            .saturating_sub(batch_to_lp)
+            .saturating_add(liq_fee_undistributed);
+        self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue.saturating_add(undistributed);
+        self.revenue.liquidation_fees = self.revenue.liquidation_fees.saturating_add(undistributed);
+        liq_fee_to_insurance = add_u128(liq_fee_to_insurance, undistributed);
+
+        // Garbage collect dust accounts
+        let mut gc_work = CrankPhaseWork::default();
+        if phases & CRANK_PHASE_GC != 0 {
+            let (closed, examined) = self.garbage_collect_dust_inner();
+            self.finish_mutation("garbage_collect_dust", closed > 0);
+            gc_work = CrankPhaseWork {
+                accounts: examined,
+                budget_used: closed,
+                budget: GC_CLOSE_BUDGET,
+            };
         }
-        let residual = self
-            .vault
-            .get()
-            .saturating_sub(self.c_tot.get())
-            .saturating_sub(self.insurance_fund.balance.get());
-        let h_num = core::cmp::min(residual, pnl_pos_tot);
-        (h_num, pnl_pos_tot)
-    }
+        let num_gc_closed = gc_work.budget_used;
 
-    /// Compute effective positive PnL after haircut for a given account PnL (spec §3.3).
-    /// PNL_eff_pos_i = floor(max(PNL_i, 0) * h_num / h_den)
-    #[inline]
-    pub fn effective_pos_pnl(&self, pnl: i128) -> u128 {
-        if pnl <= 0 {
-            return 0;
+        // Per-phase budget use
+        if settle {
+            settle_work.budget_used = settle_work.accounts;
+            settle_work.budget = ACCOUNTS_PER_CRANK as u32;
         }
-        let pos_pnl = pnl as u128;
-        let (h_num, h_den) = self.haircut_ratio();
-        if h_den == 0 {
-            return pos_pnl;
+        if liquidate {
+            let (budget, left) = if force_realize_active {
+                (FORCE_REALIZE_BUDGET_PER_CRANK, force_realize_budget)
+            } else {
+                (LIQ_BUDGET_PER_CRANK, liq_budget)
+            };
+            liquidate_work.budget_used = budget.saturating_sub(left) as u32;
+            liquidate_work.budget = budget as u32;
         }
-        // floor(pos_pnl * h_num / h_den)
-        mul_u128(pos_pnl, h_num) / h_den
+
+        // Detect conditions for informational flags
+        let force_realize_needed = self.force_realize_active();
+        let panic_needed = false; // No longer needed with haircut ratio
+
+        Ok(CrankOutcome {
+            skipped: false,
+            advanced,
+            slots_forgiven,
+            caller_settle_ok,
+            force_realize_needed,
+            panic_needed,
+            num_liquidations,
+            num_liq_errors,
+            num_gc_closed,
+            force_realize_closed,
+            force_realize_errors,
+            max_pnl_closed,
+            max_pnl_errors,
+            oi_cap_active,
+            last_cursor: match shard {
+                None => self.crank_cursor,
+                Some(shard) => self.crank_shard_cursors[shard.index as usize],
+            },
+            sweep_complete,
+            liq_fee_to_keeper,
+            liq_fee_to_insurance,
+            liq_fee_to_lp,
+            maintenance_fees_collected: self
+                .revenue
+                .maintenance_fees
+                .get()
+                .saturating_sub(maintenance_fees_before),
+            funding_long_to_short,
+            funding_short_to_long,
+            keeper_rewards: liq_fee_to_keeper,
+            insurance_contributions: self
+                .insurance_fund
+                .fee_revenue
+                .get()
+                .saturating_sub(fee_revenue_before),
+            liq_notional,
+            liq_backlog,
+            slots_scanned: slots_scanned as u32,
+            settle_work,
+            liquidate_work,
+            gc_work,
+        })
     }
 
-    /// Compute effective realized equity per spec §3.3.
-    /// Eq_real_i = max(0, C_i + min(PNL_i, 0) + PNL_eff_pos_i)
-    #[inline]
-    pub fn effective_equity(&self, account: &Account) -> u128 {
-        let cap_i = u128_to_i128_clamped(account.capital.get());
-        let neg_pnl = core::cmp::min(account.pnl.get(), 0);
-        let eff_pos = self.effective_pos_pnl(account.pnl.get());
-        let eq_i = cap_i
-            .saturating_add(neg_pnl)
-            .saturating_add(u128_to_i128_clamped(eff_pos));
-        if eq_i > 0 {
-            eq_i as u128
+    /// How far the crank has got through the slab, for monitoring: alert when
+    /// `slots_since_full_sweep` or `accounts_pending` grows rather than waiting for
+    /// staleness errors.
+    pub fn crank_progress(&self, now_slot: u64) -> CrankProgress {
+        let sweep_in_progress = self.crank_cursor != self.sweep_start_idx;
+        let accounts_pending = if sweep_in_progress {
+            // Occupied indices in [cursor, sweep_start), wrapping
+            let len = (self.sweep_start_idx as usize)
+                .wrapping_sub(self.crank_cursor as usize)
+                & ACCOUNT_IDX_MASK;
+            self.count_used_in(self.crank_cursor as usize, len)
         } else {
-            0
-        }
-    }
+            self.num_used_accounts as u32
+        };
 
-    // ========================================
-    // Account Allocation
-    // ========================================
+        let mut shards_done = 0u16;
+        let mut shard_accounts_pending = 0u32;
+        if self.crank_shard_sweep_active {
+            for index in 0..self.crank_shard_count {
+                let shard = CrankShard { index, count: self.crank_shard_count };
+                if self.crank_shard_done & shard.bit() != 0 {
+                    shards_done = shards_done.saturating_add(1);
+                    continue;
+                }
+                let cursor = self.crank_shard_cursors[index as usize] as usize;
+                let pending = self.count_used_in(cursor, shard.range().1.saturating_sub(cursor));
+                shard_accounts_pending = shard_accounts_pending.saturating_add(pending);
+            }
+        }
 
-    fn alloc_slot(&mut self) -> Result<u16> {
-        if self.free_head == u16::MAX {
-            return Err(RiskError::Overflow); // Slab full
+        CrankProgress {
+            last_full_sweep_completed_slot: self.last_full_sweep_completed_slot,
+            last_full_sweep_start_slot: self.last_full_sweep_start_slot,
+            slots_since_full_sweep: now_slot.saturating_sub(self.last_full_sweep_completed_slot),
+            cursor: self.crank_cursor,
+            sweep_in_progress,
+            accounts_pending,
+            sharded_sweep_active: self.crank_shard_sweep_active,
+            shards_done,
+            shard_count: if self.crank_shard_sweep_active { self.crank_shard_count } else { 0 },
+            shard_accounts_pending,
         }
-        let idx = self.free_head;
-        self.free_head = self.next_free[idx as usize];
-        self.set_used(idx as usize);
-        // Increment O(1) counter atomically (fixes H2: TOCTOU fee bypass)
-        self.num_used_accounts = self.num_used_accounts.saturating_add(1);
-        Ok(idx)
     }
 
-    /// Count used accounts
-    fn count_used(&self) -> u64 {
-        let mut count = 0u64;
-        self.for_each_used(|_, _| {
-            count += 1;
-        });
+    /// Occupied accounts among the `len` indices from `start` (wrapping).
+    fn count_used_in(&self, start: usize, len: usize) -> u32 {
+        let mut count = 0u32;
+        for offset in 0..len {
+            if self.is_used(start.wrapping_add(offset) & ACCOUNT_IDX_MASK) {
+                count = count.saturating_add(1);
+            }
+        }
         count
     }
 
+    /// Outcome of a throttled crank: nothing ran.
+    fn skipped_crank_outcome(&self) -> CrankOutcome {
+        CrankOutcome {
+            skipped: true,
+            advanced: false,
+            slots_forgiven: 0,
+            caller_settle_ok: true,
+            force_realize_needed: self.force_realize_active(),
+            panic_needed: false,
+            num_liquidations: 0,
+            num_liq_errors: 0,
+            num_gc_closed: 0,
+            force_realize_closed: 0,
+            force_realize_errors: 0,
+            max_pnl_closed: 0,
+            max_pnl_errors: 0,
+            oi_cap_active: false,
+            last_cursor: self.crank_cursor,
+            sweep_complete: false,
+            liq_fee_to_keeper: 0,
+            liq_fee_to_insurance: 0,
+            liq_fee_to_lp: 0,
+            maintenance_fees_collected: 0,
+            funding_long_to_short: 0,
+            funding_short_to_long: 0,
+            keeper_rewards: 0,
+            insurance_contributions: 0,
+            liq_notional: 0,
+            liq_backlog: false,
+            slots_scanned: 0,
+            settle_work: CrankPhaseWork::default(),
+            liquidate_work: CrankPhaseWork::default(),
+            gc_work: CrankPhaseWork::default(),
+        }
+    }
+
     // ========================================
-    // Account Management
+    // Shadow Evaluation
     // ========================================
 
-    /// Add a new user account
-    pub fn add_user(&mut self, fee_payment: u128) -> Result<u16> {
-        // Use O(1) counter instead of O(N) count_used() (fixes H2: TOCTOU fee bypass)
-        let used_count = self.num_used_accounts as u64;
-        if used_count >= self.params.max_accounts {
+    /// Evaluate a full crank sweep under `candidate` parameters without mutating state.
+    ///
+    /// Every occupied account is evaluated twice — once with the current parameters
+    /// and once with the candidate set — using the same predicates the crank uses
+    /// (force-realize, maintenance margin, zero-equity/dust, max-PnL cap). Unsettled
+    /// funding up to the last accrual is included; per-crank budgets are ignored.
+    /// `max_pnl_vault_bps` follows the crank convention (0 = fall back to the
+    /// respective ExtParams).
+    pub fn shadow_crank(
+        &self,
+        candidate: &RiskParams,
+        candidate_ext: &ExtParams,
+        oracle_price: u64,
+        max_pnl_vault_bps: u64,
+    ) -> Result<ShadowCrankReport> {
+        if oracle_price == 0 || oracle_price > MAX_ORACLE_PRICE {
             return Err(RiskError::Overflow);
         }
-
-        // Flat fee (no scaling)
-        let required_fee = self.params.new_account_fee.get();
-        if fee_payment < required_fee {
-            return Err(RiskError::InsufficientBalance);
-        }
-
-        // Bug #4 fix: Compute excess payment to credit to user capital
-        let excess = fee_payment.saturating_sub(required_fee);
-
-        // Pay fee to insurance (fee tokens are deposited into vault)
-        // Account for FULL fee_payment in vault, not just required_fee
-        self.vault = self.vault + fee_payment;
-        self.insurance_fund.balance = self.insurance_fund.balance + required_fee;
-        self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue + required_fee;
-
-        // Allocate slot and assign unique ID
-        let idx = self.alloc_slot()?;
-        let account_id = self.next_account_id;
-        self.next_account_id = self.next_account_id.saturating_add(1);
-
-        // Initialize account with excess credited to capital
-        self.accounts[idx as usize] = Account {
-            kind: AccountKind::User,
-            account_id,
-            capital: U128::new(excess), // Bug #4 fix: excess goes to user capital
-            pnl: I128::ZERO,
-            reserved_pnl: 0,
-            warmup_started_at_slot: self.current_slot,
-            warmup_slope_per_step: U128::ZERO,
-            position_size: I128::ZERO,
-            entry_price: 0,
-            funding_index: self.funding_index_qpb_e6,
-            matcher_program: [0; 32],
-            matcher_context: [0; 32],
-            owner: [0; 32],
-            fee_credits: I128::ZERO,
-            last_fee_slot: self.current_slot,
+        let cap_for = |ext: &ExtParams| {
+            if max_pnl_vault_bps == 0 {
+                ext.max_pnl_vault_bps
+            } else {
+                max_pnl_vault_bps
+            }
+        };
+        let current_cap = cap_for(&self.ext_params);
+        let candidate_cap = cap_for(candidate_ext);
+
+        let mut report = ShadowCrankReport {
+            current: ShadowCrankTally {
+                force_realize_active: self.insurance_fund.balance
+                    <= self.params.risk_reduction_threshold,
+                ..ShadowCrankTally::default()
+            },
+            candidate: ShadowCrankTally {
+                force_realize_active: self.insurance_fund.balance
+                    <= candidate.risk_reduction_threshold,
+                ..ShadowCrankTally::default()
+            },
+            ..ShadowCrankReport::default()
         };
 
-        // Maintain c_tot aggregate (account was created with capital = excess)
-        if excess > 0 {
-            self.c_tot = U128::new(self.c_tot.get().saturating_add(excess));
-        }
+        self.for_each_used(|_, account| {
+            report.accounts_scanned = report.accounts_scanned.saturating_add(1);
+            if account.position_size.is_zero() {
+                return;
+            }
+            // Include funding owed since the account's last settlement
+            let mut acct = *account;
+            let owed = self.pending_funding_payment(account);
+            acct.pnl = I128::new(acct.pnl.get().saturating_sub(owed));
+
+            let cur = self.shadow_action(
+                &acct,
+                &self.params,
+                report.current.force_realize_active,
+                current_cap,
+                oracle_price,
+            );
+            let cand = self.shadow_action(
+                &acct,
+                candidate,
+                report.candidate.force_realize_active,
+                candidate_cap,
+                oracle_price,
+            );
+            let notional =
+                self.notional_at(acct.position_size.unsigned_abs(), oracle_price, Rounding::Down);
+            Self::shadow_tally(&mut report.current, cur, notional);
+            Self::shadow_tally(&mut report.candidate, cand, notional);
+            match (cur == ShadowAction::Liquidate, cand == ShadowAction::Liquidate) {
+                (false, true) => report.newly_liquidatable = report.newly_liquidatable.saturating_add(1),
+                (true, false) => report.no_longer_liquidatable = report.no_longer_liquidatable.saturating_add(1),
+                _ => {}
+            }
+        });
 
-        Ok(idx)
+        Ok(report)
     }
 
-    /// Add a new LP account
-    pub fn add_lp(
-        &mut self,
-        matching_engine_program: [u8; 32],
-        matching_engine_context: [u8; 32],
-        fee_payment: u128,
-    ) -> Result<u16> {
-        // Use O(1) counter instead of O(N) count_used() (fixes H2: TOCTOU fee bypass)
-        let used_count = self.num_used_accounts as u64;
-        if used_count >= self.params.max_accounts {
-            return Err(RiskError::Overflow);
-        }
+    /// Funding payment owed by an account since its last settlement (read-only).
+    /// Positive = account pays. Mirrors `settle_account_funding` rounding.
+    fn pending_funding_payment(&self, account: &Account) -> i128 {
+        let delta_f = self
+            .funding_index_qpb_e6
+            .get()
+            .saturating_sub(account.funding_index.get());
+        let divisor = u128_to_i128_clamped(self.price_divisor());
+        account
+            .position_size
+            .saturating_mul_div(delta_f, divisor, Rounding::Up)
+            .get()
+    }
 
-        // Flat fee (no scaling)
-        let required_fee = self.params.new_account_fee.get();
-        if fee_payment < required_fee {
-            return Err(RiskError::InsufficientBalance);
+    /// Crank decision for one account under a given parameter set.
+    fn shadow_action(
+        &self,
+        account: &Account,
+        params: &RiskParams,
+        force_realize: bool,
+        max_pnl_cap: u64,
+        oracle_price: u64,
+    ) -> ShadowAction {
+        if force_realize {
+            return ShadowAction::ForceClose;
         }
-
-        // Bug #4 fix: Compute excess payment to credit to LP capital
-        let excess = fee_payment.saturating_sub(required_fee);
-
-        // Pay fee to insurance (fee tokens are deposited into vault)
-        // Account for FULL fee_payment in vault, not just required_fee
-        self.vault = self.vault + fee_payment;
-        self.insurance_fund.balance = self.insurance_fund.balance + required_fee;
-        self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue + required_fee;
-
-        // Allocate slot and assign unique ID
-        let idx = self.alloc_slot()?;
-        let account_id = self.next_account_id;
-        self.next_account_id = self.next_account_id.saturating_add(1);
-
-        // Initialize account with excess credited to capital
-        self.accounts[idx as usize] = Account {
-            kind: AccountKind::LP,
-            account_id,
-            capital: U128::new(excess), // Bug #4 fix: excess goes to LP capital
-            pnl: I128::ZERO,
-            reserved_pnl: 0,
-            warmup_started_at_slot: self.current_slot,
-            warmup_slope_per_step: U128::ZERO,
-            position_size: I128::ZERO,
-            entry_price: 0,
-            funding_index: self.funding_index_qpb_e6,
-            matcher_program: matching_engine_program,
-            matcher_context: matching_engine_context,
-            owner: [0; 32],
-            fee_credits: I128::ZERO,
-            last_fee_slot: self.current_slot,
-        };
-
-        // Maintain c_tot aggregate (account was created with capital = excess)
-        if excess > 0 {
-            self.c_tot = U128::new(self.c_tot.get().saturating_add(excess));
+        if !self.is_above_margin_bps_mtm(account, oracle_price, params.maintenance_margin_bps) {
+            return ShadowAction::Liquidate;
+        }
+        let equity = self.account_equity_mtm_at_oracle(account, oracle_price);
+        let is_dust = account.position_size.unsigned_abs() < params.min_liquidation_abs.get();
+        if equity == 0 || is_dust {
+            return ShadowAction::ForceClose;
+        }
+        if max_pnl_cap > 0 && !account.is_lp() {
+            if let Ok(mark) = self.mark_pnl(
+                account.position_size.get(),
+                account.entry_price,
+                oracle_price,
+            ) {
+                let total = account.pnl.get().saturating_add(mark);
+                if total > 0 && (total as u128) > max_pnl_cap as u128 {
+                    return ShadowAction::MaxPnlClose;
+                }
+            }
         }
+        ShadowAction::None
+    }
 
-        Ok(idx)
+    fn shadow_tally(tally: &mut ShadowCrankTally, action: ShadowAction, notional: u128) {
+        match action {
+            ShadowAction::None => {}
+            ShadowAction::Liquidate => {
+                tally.liquidations = tally.liquidations.saturating_add(1);
+                tally.liquidation_notional = add_u128(tally.liquidation_notional, notional);
+            }
+            ShadowAction::ForceClose => tally.force_closes = tally.force_closes.saturating_add(1),
+            ShadowAction::MaxPnlClose => tally.max_pnl_closes = tally.max_pnl_closes.saturating_add(1),
+        }
     }
 
     // ========================================
-    // Maintenance Fees
+    // Risk Analytics
     // ========================================
 
-    /// Settle maintenance fees for an account.
+    /// Parametric value-at-risk and expected shortfall of the current book.
     ///
-    /// Returns the fee amount due (for keeper rebate calculation).
+    /// `vol_bps` is the volatility of the oracle price over the horizon of
+    /// interest (e.g. one-day σ in bps) and `confidence_bps` the one-sided
+    /// confidence level, between 9000 and 9990. Under a normal return
+    /// assumption the VaR quantile and ES tail average map to price moves of
+    /// z·σ and φ(z)/(1−α)·σ; each book is revalued at the move up and down with
+    /// the engine's own mark-to-market math and the worse side is reported.
     ///
-    /// Algorithm:
-    /// 1. Compute dt = now_slot - account.last_fee_slot
-    /// 2. If dt == 0, return 0 (no-op)
-    /// 3. Compute due = fee_per_slot * dt
-    /// 4. Deduct from fee_credits; if negative, pay from capital to insurance
-    /// 5. If position exists and below maintenance after fee, return Err
-    pub fn settle_maintenance_fee(
-        &mut self,
-        idx: u16,
-        now_slot: u64,
+    /// - LP: mark-to-market loss of all LP positions
+    /// - Vault: Σ max(0, −(capital + pnl + mark)) over all accounts, i.e. the
+    ///   losses counterparties could not cover
+    ///
+    /// Overflow if any position's mark at a shocked price does not fit.
+    pub fn value_at_risk(
+        &self,
         oracle_price: u64,
-    ) -> Result<u128> {
-        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
-            return Err(RiskError::Unauthorized);
+        vol_bps: u64,
+        confidence_bps: u64,
+    ) -> Result<VarReport> {
+        if oracle_price == 0 || oracle_price > MAX_ORACLE_PRICE {
+            return Err(RiskError::Overflow);
         }
+        let (z, es_mult) = normal_tail_multipliers(confidence_bps).ok_or(RiskError::InvalidParams)?;
+        let var_shock_bps = mul_div(z as u128, vol_bps as u128, 10_000, Rounding::Up) as u64;
+        let es_shock_bps = mul_div(es_mult as u128, vol_bps as u128, 10_000, Rounding::Up) as u64;
+
+        let var = self.worst_shock_losses(oracle_price, var_shock_bps)?;
+        let es = self.worst_shock_losses(oracle_price, es_shock_bps)?;
+        Ok(VarReport {
+            confidence_bps,
+            var_shock_bps,
+            es_shock_bps,
+            lp: RiskMeasure { var: var.0, es: es.0 },
+            vault: RiskMeasure { var: var.1, es: es.1 },
+        })
+    }
 
-        // Calculate elapsed time
-        let dt = now_slot.saturating_sub(self.accounts[idx as usize].last_fee_slot);
-        if dt == 0 {
-            return Ok(0);
+    /// Apply each shock to a clone of the engine and report its effect; the live
+    /// engine is untouched.
+    ///
+    /// For every shock the clone is marked at the shocked price and every account
+    /// with a position is run through `liquidate_at_oracle`, exactly as a crank
+    /// would. Off-chain only: each clone is a full copy of the slab.
+    #[cfg(feature = "std")]
+    pub fn stress_test(&self, oracle_price: u64, shocks: &[PriceShock]) -> Result<StressReport> {
+        if oracle_price == 0 || oracle_price > MAX_ORACLE_PRICE {
+            return Err(RiskError::Overflow);
+        }
+        let surplus_before = self.solvency_status(oracle_price).surplus();
+        let mut outcomes = std::vec::Vec::with_capacity(shocks.len());
+        for &shock in shocks {
+            let moved = I128::new(oracle_price as i128)
+                .saturating_mul_div(
+                    10_000i128.saturating_add(shock.move_bps as i128),
+                    10_000,
+                    Rounding::Down,
+                )
+                .get();
+            let shocked_price = moved.clamp(1, MAX_ORACLE_PRICE as i128) as u64;
+
+            let mut clone = std::boxed::Box::new(self.clone());
+            let bad_debt = clone.shocked_losses(oracle_price, shocked_price)?.1;
+            let mut liquidations = 0u32;
+            let mut liquidated_abs = 0u128;
+            let now_slot = clone.current_slot;
+            for idx in 0..MAX_ACCOUNTS {
+                if !clone.is_used(idx) || clone.accounts[idx].position_size.is_zero() {
+                    continue;
+                }
+                if let Ok(Some(record)) =
+                    clone.liquidate_at_oracle_with_keeper(idx as u16, None, now_slot, shocked_price)
+                {
+                    liquidations = liquidations.saturating_add(1);
+                    liquidated_abs = add_u128(liquidated_abs, record.closed_abs);
+                }
+            }
+            let surplus_after = clone.solvency_status(shocked_price).surplus();
+            outcomes.push(ShockOutcome {
+                shock,
+                shocked_price,
+                liquidations,
+                liquidated_abs,
+                bad_debt,
+                insurance_after: clone.insurance_fund.balance.get(),
+                vault_drawdown: I128::new(surplus_before.saturating_sub(surplus_after))
+                    .clamp_pos_u128(),
+            });
         }
+        Ok(StressReport {
+            oracle_price,
+            outcomes,
+        })
+    }
 
-        // Calculate fee due (engine is purely slot-native)
-        let due = self
-            .params
-            .maintenance_fee_per_slot
-            .get()
-            .saturating_mul(dt as u128);
+    /// Project `execute_trade` on a clone of the engine without mutating the
+    /// live state. Returns the trade's error if it would be rejected.
+    ///
+    /// Off-chain only (pre-trade risk screens); each call copies the slab.
+    #[cfg(feature = "std")]
+    pub fn what_if<M: MatchingEngine>(
+        &self,
+        matcher: &M,
+        lp_idx: u16,
+        user_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+        size: i128,
+    ) -> Result<ImpactReport> {
+        if !self.is_used(lp_idx as usize) || !self.is_used(user_idx as usize) {
+            return Err(RiskError::AccountNotFound);
+        }
+        let mut clone = std::boxed::Box::new(self.clone());
+        clone.execute_trade(matcher, lp_idx, user_idx, now_slot, oracle_price, size)?;
+        let user = &clone.accounts[user_idx as usize];
+        Ok(ImpactReport {
+            utilization_bps_before: self.utilization_bps(oracle_price),
+            utilization_bps_after: clone.utilization_bps(oracle_price),
+            lp_position_before: self.accounts[lp_idx as usize].position_size.get(),
+            lp_position_after: clone.accounts[lp_idx as usize].position_size.get(),
+            net_lp_pos_before: self.net_lp_pos.get(),
+            net_lp_pos_after: clone.net_lp_pos.get(),
+            user_position_after: user.position_size.get(),
+            liquidation_price: clone.liquidation_price(user, oracle_price),
+        })
+    }
 
-        // Update last_fee_slot
-        self.accounts[idx as usize].last_fee_slot = now_slot;
+    /// Open interest notional (both sides) at `oracle_price` relative to the
+    /// vault, in bps, rounded up. u64::MAX when there is OI but no vault.
+    /// Shorthand for `vault_utilization(oracle_price).bps`.
+    pub fn utilization_bps(&self, oracle_price: u64) -> u64 {
+        self.vault_utilization(oracle_price).bps
+    }
 
-        // Deduct from fee_credits (coupon: no insurance booking here —
-        // insurance was already paid when credits were granted)
-        self.accounts[idx as usize].fee_credits =
-            self.accounts[idx as usize].fee_credits.saturating_sub(due as i128);
+    /// Vault utilization at `oracle_price`: open interest notional against the vault
+    /// balance not yet released to LPs, and the band it falls in. Fee surcharges,
+    /// risk-reduction-only trading and LP withdrawal gating all key off this.
+    pub fn vault_utilization(&self, oracle_price: u64) -> VaultUtilization {
+        let requirement =
+            self.notional_at(self.total_open_interest.get(), oracle_price, Rounding::Up);
+        let available = self.vault.get().saturating_sub(self.lp_claimable_total.get());
+        let bps = if requirement == 0 {
+            0
+        } else if available == 0 {
+            u64::MAX
+        } else {
+            let bps = mul_div(requirement, 10_000, available, Rounding::Up);
+            core::cmp::min(bps, u64::MAX as u128) as u64
+        };
+        let ext = &self.ext_params;
+        let band = if ext.utilization_hard_bps > 0 && bps >= ext.utilization_hard_bps {
+            UtilizationBand::Hard
+        } else if ext.utilization_soft_bps > 0 && bps >= ext.utilization_soft_bps {
+            UtilizationBand::Soft
+        } else {
+            UtilizationBand::Normal
+        };
+        VaultUtilization {
+            requirement,
+            available,
+            bps,
+            band,
+        }
+    }
 
-        // If fee_credits is negative, pay from capital using set_capital helper (spec §4.1)
-        let mut paid_from_capital = 0u128;
-        if self.accounts[idx as usize].fee_credits.is_negative() {
-            let owed = neg_i128_to_u128(self.accounts[idx as usize].fee_credits.get());
-            let current_cap = self.accounts[idx as usize].capital.get();
-            let pay = core::cmp::min(owed, current_cap);
+    /// Skew headroom at `oracle_price` (None when `skew_gap_move_bps` is 0). Users
+    /// buying push the net LP position short, selling pushes it long.
+    pub fn skew_capacity(&self, oracle_price: u64) -> Option<SkewCapacity> {
+        let gap_bps = self.ext_params.skew_gap_move_bps;
+        if gap_bps == 0 {
+            return None;
+        }
+        let mut lp_capital = 0u128;
+        self.for_each_used(|_, account| {
+            if account.is_lp() {
+                lp_capital = add_u128(lp_capital, account.capital.get());
+            }
+        });
+        let max_notional = mul_div(lp_capital, 10_000, gap_bps as u128, Rounding::Down);
+        let max_units = self.base_for_notional(max_notional, oracle_price, Rounding::Down);
+        let net = self.net_lp_pos.get();
+        let max_units_i = u128_to_i128_clamped(max_units);
+        Some(SkewCapacity {
+            max_notional,
+            notional: self.notional_at(net.unsigned_abs(), oracle_price, Rounding::Up),
+            buy_remaining: max_units_i.saturating_add(net).max(0) as u128,
+            sell_remaining: max_units_i.saturating_sub(net).max(0) as u128,
+        })
+    }
 
-            // Use set_capital helper to maintain c_tot aggregate (spec §4.1)
-            self.set_capital(idx as usize, current_cap.saturating_sub(pay));
-            self.insurance_fund.balance = self.insurance_fund.balance + pay;
-            self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue + pay;
+    /// Capital LPs may withdraw before utilization reaches the soft band
+    /// (u128::MAX when the soft band is disabled).
+    fn lp_withdrawal_headroom(&self, oracle_price: u64) -> u128 {
+        let soft = self.ext_params.utilization_soft_bps;
+        if soft == 0 {
+            return u128::MAX;
+        }
+        let util = self.vault_utilization(oracle_price);
+        let floor = mul_div(util.requirement, 10_000, soft as u128, Rounding::Up);
+        util.available.saturating_sub(floor)
+    }
 
-            // Credit back what was paid
-            self.accounts[idx as usize].fee_credits =
-                self.accounts[idx as usize].fee_credits.saturating_add(pay as i128);
-            paid_from_capital = pay;
+    /// Trading fee in bps for a trade at the current utilization: `trading_fee_bps`
+    /// (or the fee holiday rate while one is active at `current_slot`), plus the
+    /// utilization surcharge (`ExtParams::utilization_fee_*`) when the trade
+    /// increases the taker's exposure. The LP side is not considered: a taker
+    /// closing out pays the base fee even when the LP's inventory grows.
+    pub fn effective_trading_fee_bps(&self, oracle_price: u64, risk_increasing: bool) -> u64 {
+        let base = if self.fee_holiday.is_active(self.current_slot) {
+            self.fee_holiday.fee_bps
+        } else {
+            self.params.trading_fee_bps
+        };
+        self.with_utilization_surcharge(base, oracle_price, risk_increasing)
+    }
+
+    /// Trading fee in bps for a trade of `notional`: as `effective_trading_fee_bps`,
+    /// with the fee curve's rate for that size (`ExtParams::fee_curve`) in place of
+    /// `trading_fee_bps` when one is configured.
+    pub fn trading_fee_bps_for(
+        &self,
+        notional: u128,
+        oracle_price: u64,
+        risk_increasing: bool,
+    ) -> u64 {
+        let base = if self.fee_holiday.is_active(self.current_slot) {
+            self.fee_holiday.fee_bps
+        } else {
+            let curve = self.ext_params.fee_curve_bps(notional);
+            curve.unwrap_or(self.params.trading_fee_bps)
+        };
+        self.with_utilization_surcharge(base, oracle_price, risk_increasing)
+    }
+
+    fn with_utilization_surcharge(
+        &self,
+        base: u64,
+        oracle_price: u64,
+        risk_increasing: bool,
+    ) -> u64 {
+        let ext = &self.ext_params;
+        if !risk_increasing || ext.utilization_fee_kink_bps == 0 {
+            return base;
+        }
+        let utilization = self.utilization_bps(oracle_price);
+        if utilization <= ext.utilization_fee_kink_bps {
+            return base;
         }
+        let span = ext.utilization_fee_full_bps.saturating_sub(ext.utilization_fee_kink_bps);
+        let over = core::cmp::min(utilization.saturating_sub(ext.utilization_fee_kink_bps), span);
+        let surcharge = mul_div(
+            ext.utilization_fee_max_bps as u128,
+            over as u128,
+            span as u128,
+            Rounding::Up,
+        );
+        base.saturating_add(surcharge as u64)
+    }
 
-        // Check maintenance margin if account has a position (MTM check)
-        if !self.accounts[idx as usize].position_size.is_zero() {
-            let account_ref = &self.accounts[idx as usize];
-            if !self.is_above_maintenance_margin_mtm(account_ref, oracle_price) {
-                return Err(RiskError::Undercollateralized);
+    /// Insurance target from `ExtParams::insurance_target_bps`: that share of open
+    /// interest notional at `oracle_price` (None = no target).
+    pub fn insurance_target(&self, oracle_price: u64) -> Option<u128> {
+        match self.ext_params.insurance_target_bps {
+            0 => None,
+            bps => {
+                let oi = self.total_open_interest.get();
+                let notional = self.notional_at(oi, oracle_price, Rounding::Up);
+                Some(mul_div(notional, bps as u128, 10_000, Rounding::Up))
             }
         }
+    }
 
-        Ok(paid_from_capital) // Return actual amount paid into insurance
+    /// Price at which liquidations and max-PnL force-closes execute
+    /// (`ExtParams::close_price_source`). Falls back to `oracle_price` while the mark
+    /// EMA is unseeded or the last execution is older than `close_price_ema_slots`.
+    pub fn close_price(&self, oracle_price: u64, now_slot: u64) -> u64 {
+        match self.ext_params.close_price_source {
+            ClosePriceSource::Oracle => oracle_price,
+            ClosePriceSource::MarkEma if self.mark_ema_price > 0 => self.mark_ema_price,
+            ClosePriceSource::LastExecution
+                if self.last_exec_price > 0
+                    && now_slot.saturating_sub(self.last_exec_slot)
+                        <= self.ext_params.close_price_ema_slots =>
+            {
+                self.last_exec_price
+            }
+            _ => oracle_price,
+        }
     }
 
-    /// Best-effort maintenance settle for crank paths.
-    /// - Always advances last_fee_slot
-    /// - Charges fees into insurance if possible
-    /// - NEVER fails due to margin checks
-    /// - Still returns Unauthorized if idx invalid
-    fn settle_maintenance_fee_best_effort_for_crank(
+    /// Reprice a forced close of `closed` (signed size, already closed at
+    /// `oracle_price`) to `close_price`. The insurance fund takes the other side of
+    /// the difference: a worse price moves capital to insurance, a better one is
+    /// paid from insurance, each capped at what the payer holds.
+    fn settle_close_price_premium(
         &mut self,
-        idx: u16,
+        idx: usize,
+        closed: i128,
+        oracle_price: u64,
         now_slot: u64,
-    ) -> Result<u128> {
-        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
-            return Err(RiskError::Unauthorized);
+    ) {
+        let close_price = self.close_price(oracle_price, now_slot);
+        if close_price == oracle_price {
+            return;
         }
-
-        let dt = now_slot.saturating_sub(self.accounts[idx as usize].last_fee_slot);
-        if dt == 0 {
-            return Ok(0);
+        let Ok(premium) = self.mark_pnl(closed, oracle_price, close_price) else {
+            return;
+        };
+        let capital = self.accounts[idx].capital.get();
+        let insurance = self.insurance_fund.balance.get();
+        if premium >= 0 {
+            let pay = core::cmp::min(premium as u128, insurance);
+            self.insurance_fund.balance = U128::new(insurance.saturating_sub(pay));
+            self.set_capital(idx, capital.saturating_add(pay));
+        } else {
+            let pay = core::cmp::min(neg_i128_to_u128(premium), capital);
+            self.set_capital(idx, capital.saturating_sub(pay));
+            self.insurance_fund.balance = U128::new(insurance.saturating_add(pay));
         }
+    }
 
-        let due = self
-            .params
-            .maintenance_fee_per_slot
-            .get()
-            .saturating_mul(dt as u128);
-
-        // Advance slot marker regardless
-        self.accounts[idx as usize].last_fee_slot = now_slot;
-
-        // Deduct from fee_credits (coupon: no insurance booking here —
-        // insurance was already paid when credits were granted)
-        self.accounts[idx as usize].fee_credits =
-            self.accounts[idx as usize].fee_credits.saturating_sub(due as i128);
-
-        // If negative, pay what we can from capital using set_capital helper (spec §4.1)
-        let mut paid_from_capital = 0u128;
-        if self.accounts[idx as usize].fee_credits.is_negative() {
-            let owed = neg_i128_to_u128(self.accounts[idx as usize].fee_credits.get());
-            let current_cap = self.accounts[idx as usize].capital.get();
-            let pay = core::cmp::min(owed, current_cap);
+    /// Move the mark EMA toward `oracle_price`, weighting the new print by the
+    /// elapsed slots over `close_price_ema_slots` (capped at 1). Seeds on first use.
+    fn update_mark_ema(&mut self, now_slot: u64, oracle_price: u64) {
+        let dt = now_slot.saturating_sub(self.mark_ema_slot);
+        let horizon = self.ext_params.close_price_ema_slots;
+        self.mark_ema_price = ema_toward(self.mark_ema_price, oracle_price, dt, horizon);
+        self.mark_ema_slot = now_slot;
+    }
 
-            // Use set_capital helper to maintain c_tot aggregate (spec §4.1)
-            self.set_capital(idx as usize, current_cap.saturating_sub(pay));
-            self.insurance_fund.balance = self.insurance_fund.balance + pay;
-            self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue + pay;
+    /// Advance the per-use oracle EMAs (`price_emas`) to `oracle_price`.
+    fn update_price_emas(&mut self, now_slot: u64, oracle_price: u64) {
+        for use_ in PriceUse::ALL {
+            let (horizon, _) = self.ext_params.price_smoothing(use_);
+            let ema = &mut self.price_emas[use_ as usize];
+            let dt = now_slot.saturating_sub(ema.slot);
+            ema.price = ema_toward(ema.price, oracle_price, dt, horizon);
+            ema.slot = now_slot;
+        }
+    }
 
-            self.accounts[idx as usize].fee_credits =
-                self.accounts[idx as usize].fee_credits.saturating_add(pay as i128);
-            paid_from_capital = pay;
+    /// Oracle price for `use_`: the spot `oracle_price` blended with that use's EMA
+    /// at its configured weight. Spot while smoothing is off or the EMA is unseeded.
+    pub fn smoothed_price(&self, use_: PriceUse, oracle_price: u64) -> u64 {
+        let (_, weight_bps) = self.ext_params.price_smoothing(use_);
+        let ema = self.price_emas[use_ as usize].price;
+        if weight_bps == 0 || ema == 0 {
+            return oracle_price;
         }
+        let weight = core::cmp::min(weight_bps, 10_000) as u128;
+        let blended = (oracle_price as u128)
+            .saturating_mul(10_000u128.saturating_sub(weight))
+            .saturating_add((ema as u128).saturating_mul(weight));
+        (blended / 10_000) as u64
+    }
 
-        Ok(paid_from_capital) // Return actual amount paid into insurance
+    /// Part of `fee` skimmed into insurance ahead of the LP split
+    /// (`ExtParams::insurance_skim_bps`), limited to the shortfall below the
+    /// insurance target so the skim stops once the fund is full.
+    fn insurance_skim(&self, fee: u128, oracle_price: u64) -> u128 {
+        let bps = self.ext_params.insurance_skim_bps;
+        if bps == 0 {
+            return 0;
+        }
+        let skim = mul_div(fee, bps as u128, 10_000, Rounding::Down);
+        match self.insurance_target(oracle_price) {
+            None => skim,
+            Some(target) => {
+                core::cmp::min(skim, target.saturating_sub(self.insurance_fund.balance.get()))
+            }
+        }
     }
 
-    /// Best-effort warmup settlement for crank: settles any warmed positive PnL to capital.
-    /// Silently ignores errors (e.g., account not found) since crank must not stall on
-    /// individual account issues. Used to drain abandoned accounts' positive PnL over time.
-    fn settle_warmup_to_capital_for_crank(&mut self, idx: u16) {
-        // Ignore errors: crank is best-effort and must continue processing other accounts
-        let _ = self.settle_warmup_to_capital(idx);
+    /// Oracle price at which `account` stops being above maintenance margin,
+    /// found by bisection on the MTM margin check with all other state held
+    /// fixed. Longs search below `oracle_price`, shorts above it.
+    pub fn liquidation_price(&self, account: &Account, oracle_price: u64) -> Option<u64> {
+        let pos = account.position_size.get();
+        if pos == 0 {
+            return None;
+        }
+        let healthy = |price: u64| self.is_above_maintenance_margin_mtm(account, price);
+        if !healthy(oracle_price) {
+            return Some(oracle_price);
+        }
+        // Invariant: `safe` is healthy, `liq` is not
+        let (mut safe, mut liq) = if pos > 0 {
+            if healthy(1) {
+                return None;
+            }
+            (oracle_price, 1u64)
+        } else {
+            if healthy(MAX_ORACLE_PRICE) {
+                return None;
+            }
+            (oracle_price, MAX_ORACLE_PRICE)
+        };
+        while safe.abs_diff(liq) > 1 {
+            let mid = safe.min(liq).saturating_add(safe.abs_diff(liq) >> 1);
+            if healthy(mid) {
+                safe = mid;
+            } else {
+                liq = mid;
+            }
+        }
+        Some(liq)
     }
 
-    /// Pay down existing fee debt (negative fee_credits) using available capital.
-    /// Does not advance last_fee_slot or charge new fees — just sweeps capital
-    /// that became available (e.g. after warmup settlement) into insurance.
-    /// Uses set_capital helper to maintain c_tot aggregate (spec §4.1).
-    fn pay_fee_debt_from_capital(&mut self, idx: u16) {
-        if self.accounts[idx as usize].fee_credits.is_negative()
-            && !self.accounts[idx as usize].capital.is_zero()
-        {
-            let owed = neg_i128_to_u128(self.accounts[idx as usize].fee_credits.get());
-            let current_cap = self.accounts[idx as usize].capital.get();
-            let pay = core::cmp::min(owed, current_cap);
-            if pay > 0 {
-                // Use set_capital helper to maintain c_tot aggregate (spec §4.1)
-                self.set_capital(idx as usize, current_cap.saturating_sub(pay));
-                self.insurance_fund.balance = self.insurance_fund.balance + pay;
-                self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue + pay;
-                self.accounts[idx as usize].fee_credits =
-                    self.accounts[idx as usize].fee_credits.saturating_add(pay as i128);
+    /// Is this market solvent right now? One O(accounts) pass at `oracle_price`.
+    pub fn solvency_status(&self, oracle_price: u64) -> SolvencyStatus {
+        let mut total_equity: u128 = 0;
+        let mut bad_debt: u128 = 0;
+        let mut pnl_owed: u128 = 0;
+        self.for_each_used(|_, account| {
+            let mark = self
+                .mark_pnl(account.position_size.get(), account.entry_price, oracle_price)
+                .unwrap_or(0);
+            let pnl = account.pnl.get().saturating_add(mark);
+            if pnl > 0 {
+                pnl_owed = add_u128(pnl_owed, pnl.unsigned_abs());
+            }
+            let equity = u128_to_i128_clamped(account.capital.get()).saturating_add(pnl);
+            if equity > 0 {
+                total_equity = add_u128(total_equity, equity.unsigned_abs());
+            } else {
+                bad_debt = add_u128(bad_debt, equity.unsigned_abs());
             }
+        });
+        let vault = self.vault.get().saturating_sub(self.lp_claimable_total.get());
+        let health_bps = if total_equity == 0 {
+            u64::MAX
+        } else {
+            let bps = mul_div(vault, 10_000, total_equity, Rounding::Down);
+            core::cmp::min(bps, u64::MAX as u128) as u64
+        };
+        SolvencyStatus {
+            vault,
+            insurance: self.insurance_fund.balance.get(),
+            total_equity,
+            bad_debt,
+            pnl_owed,
+            health_bps,
         }
     }
 
-    /// Touch account for force-realize paths: settles funding, mark, and fees but
-    /// uses best-effort fee settle that can't stall on margin checks.
-    fn touch_account_for_force_realize(
-        &mut self,
+    /// (LP loss, vault bad debt) at the worse of a `shock_bps` move up or down.
+    fn worst_shock_losses(&self, oracle_price: u64, shock_bps: u64) -> Result<(u128, u128)> {
+        let (down, up) = shock_band(oracle_price, shock_bps);
+        let (lp_down, vault_down) = self.shocked_losses(oracle_price, down)?;
+        let (lp_up, vault_up) = self.shocked_losses(oracle_price, up)?;
+        Ok((
+            core::cmp::max(lp_down, lp_up),
+            core::cmp::max(vault_down, vault_up),
+        ))
+    }
+
+    /// Loss beyond `account`'s capital if it were closed at `price`:
+    /// max(0, −(capital + pnl + mark)). Overflow if the mark does not fit, since
+    /// the loss cannot be bounded.
+    fn shortfall_at(&self, account: &Account, price: u64) -> Result<u128> {
+        let mark = self.mark_pnl(account.position_size.get(), account.entry_price, price)?;
+        let equity = u128_to_i128_clamped(account.capital.get())
+            .saturating_add(account.pnl.get())
+            .saturating_add(mark);
+        Ok(if equity < 0 { equity.unsigned_abs() } else { 0 })
+    }
+
+    /// Contribution of account `idx` to vault tail risk: the loss the vault
+    /// would absorb if the account were force-closed after an adverse move of
+    /// `stress_bps` (down for longs, up for shorts).
+    pub fn risk_contribution(
+        &self,
         idx: u16,
-        now_slot: u64,
         oracle_price: u64,
-    ) -> Result<()> {
-        // Funding settle is required for correct pnl
-        self.touch_account(idx)?;
-        // Mark-to-market settlement (variation margin)
-        self.settle_mark_to_oracle(idx, oracle_price)?;
-        // Best-effort fees; never fails due to maintenance margin
-        let _ = self.settle_maintenance_fee_best_effort_for_crank(idx, now_slot)?;
-        Ok(())
+        stress_bps: u64,
+    ) -> Result<RiskContribution> {
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return Err(RiskError::AccountNotFound);
+        }
+        let account = &self.accounts[idx as usize];
+        let pos = account.position_size.get();
+        let (down, up) = shock_band(oracle_price, stress_bps);
+        let stressed_price = if pos < 0 { up } else { down };
+        let notional = self.notional_at(pos.unsigned_abs(), oracle_price, Rounding::Up);
+        let oi_notional =
+            self.notional_at(self.total_open_interest.get(), oracle_price, Rounding::Up);
+        let oi_share_bps = if oi_notional == 0 {
+            0
+        } else {
+            mul_div(notional, 10_000, oi_notional, Rounding::Up) as u64
+        };
+        Ok(RiskContribution {
+            idx,
+            kind: account.kind,
+            position: pos,
+            notional,
+            oi_share_bps,
+            stressed_price,
+            vault_loss: self.shortfall_at(account, stressed_price)?,
+        })
     }
 
-    /// Touch account for liquidation paths: settles funding, mark, and fees but
-    /// uses best-effort fee settle since we're about to liquidate anyway.
-    fn touch_account_for_liquidation(
-        &mut self,
-        idx: u16,
-        now_slot: u64,
+    /// `risk_contribution` for every account with a position, largest vault
+    /// loss first (ties broken by notional). Off-chain only.
+    #[cfg(feature = "std")]
+    pub fn risk_contribution_report(
+        &self,
         oracle_price: u64,
-    ) -> Result<()> {
-        // Funding settle is required for correct pnl
-        self.touch_account(idx)?;
-        // Best-effort mark-to-market (saturating — never wedges on extreme PnL)
-        self.settle_mark_to_oracle_best_effort(idx, oracle_price)?;
-        // Best-effort fees; margin check would just block the liquidation we need to do
-        let _ = self.settle_maintenance_fee_best_effort_for_crank(idx, now_slot)?;
-        Ok(())
+        stress_bps: u64,
+    ) -> Result<std::vec::Vec<RiskContribution>> {
+        let mut report = std::vec::Vec::new();
+        for idx in 0..MAX_ACCOUNTS {
+            if !self.is_used(idx) || self.accounts[idx].position_size.is_zero() {
+                continue;
+            }
+            report.push(self.risk_contribution(idx as u16, oracle_price, stress_bps)?);
+        }
+        report.sort_by(|a, b| {
+            b.vault_loss
+                .cmp(&a.vault_loss)
+                .then(b.notional.cmp(&a.notional))
+        });
+        Ok(report)
+    }
+
+    /// (LP loss from `from_price` to `to_price`, vault bad debt at `to_price`).
+    ///
+    /// Uses settled PnL plus mark at `to_price` against each account's entry.
+    /// Overflow if any account's mark does not fit: skipping it would understate
+    /// the very tail these figures measure.
+    fn shocked_losses(&self, from_price: u64, to_price: u64) -> Result<(u128, u128)> {
+        let mut lp_pnl: i128 = 0;
+        let mut bad_debt: u128 = 0;
+        for idx in 0..MAX_ACCOUNTS {
+            let account = &self.accounts[idx];
+            let pos = account.position_size.get();
+            if !self.is_used(idx) || pos == 0 {
+                continue;
+            }
+            if account.is_lp() {
+                lp_pnl = lp_pnl.saturating_add(self.mark_pnl(pos, from_price, to_price)?);
+            }
+            bad_debt = add_u128(bad_debt, self.shortfall_at(account, to_price)?);
+        }
+        let lp_loss = if lp_pnl < 0 { lp_pnl.unsigned_abs() } else { 0 };
+        Ok((lp_loss, bad_debt))
     }
 
-    /// Set owner pubkey for an account
-    pub fn set_owner(&mut self, idx: u16, owner: [u8; 32]) -> Result<()> {
-        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
-            return Err(RiskError::Unauthorized);
+    // ========================================
+    // Oracle Failover
+    // ========================================
+
+    /// Check a quote against an expected feed identity, staleness and confidence bounds.
+    fn oracle_quote_usable(
+        &self,
+        quote: &OracleQuote,
+        expected_feed: &[u8; 32],
+        now_slot: u64,
+    ) -> bool {
+        if quote.price == 0 || quote.price > MAX_ORACLE_PRICE {
+            return false;
         }
-        self.accounts[idx as usize].owner = owner;
-        Ok(())
+        if *expected_feed != [0u8; 32] && quote.feed != *expected_feed {
+            return false;
+        }
+        let ext = &self.ext_params;
+        oracle_quote_fresh(
+            quote,
+            now_slot,
+            ext.oracle_max_staleness_slots,
+            ext.oracle_max_conf_bps,
+        )
     }
 
-    /// Pre-fund fee credits for an account.
-    ///
-    /// The wrapper must have already transferred `amount` tokens into the vault.
-    /// This pre-pays future maintenance fees: vault increases, insurance receives
-    /// the amount as revenue (since credits are a coupon — spending them later
-    /// does NOT re-book into insurance), and the account's fee_credits balance
-    /// increases by `amount`.
-    pub fn deposit_fee_credits(&mut self, idx: u16, amount: u128, now_slot: u64) -> Result<()> {
-        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
-            return Err(RiskError::Unauthorized);
+    /// Read a quote from an oracle account in this market's `ExtParams::oracle_source`
+    /// format, normalized to the market's price scale and subject to the same staleness
+    /// and confidence limits as `select_oracle_price`. Fails with `InvalidParams` if the
+    /// selected adapter is not compiled in.
+    #[cfg(any(feature = "pyth", feature = "switchboard"))]
+    pub fn oracle_account_quote(
+        &self,
+        data: &[u8],
+        feed: [u8; 32],
+        now_slot: u64,
+    ) -> Result<OracleQuote> {
+        let ext = &self.ext_params;
+        let price_scale = self.price_scale();
+        let (max_staleness, max_conf_bps) = (ext.oracle_max_staleness_slots, ext.oracle_max_conf_bps);
+        match ext.oracle_source {
+            #[cfg(feature = "pyth")]
+            OracleSource::Pyth => {
+                pyth::load_pyth_quote(data, feed, now_slot, max_staleness, max_conf_bps, price_scale)
+            }
+            #[cfg(feature = "switchboard")]
+            OracleSource::Switchboard => switchboard::load_switchboard_quote(
+                data,
+                feed,
+                now_slot,
+                max_staleness,
+                max_conf_bps,
+                price_scale,
+            ),
+            #[allow(unreachable_patterns)]
+            _ => Err(RiskError::InvalidParams),
         }
-        self.current_slot = now_slot;
+    }
 
-        // Wrapper transferred tokens into vault
-        self.vault = self.vault + amount;
+    /// Select the oracle price to use, failing over from primary to fallback.
+    ///
+    /// The primary is used whenever it passes identity, staleness and confidence checks.
+    /// The fallback is accepted only when the primary fails those checks, failover is
+    /// configured (`fallback_oracle` non-zero), the fallback itself passes the same
+    /// checks, and it lies within `oracle_max_divergence_bps` of the primary price. A
+    /// primary quote from the wrong feed or with an out-of-range price is no reference,
+    /// so the divergence check is skipped for it.
+    pub fn select_oracle_price(
+        &self,
+        now_slot: u64,
+        primary: &OracleQuote,
+        fallback: Option<&OracleQuote>,
+    ) -> Result<(u64, OracleFeed)> {
+        let ext = self.ext_params;
+        if self.oracle_quote_usable(primary, &ext.primary_oracle, now_slot) {
+            return Ok((primary.price, OracleFeed::Primary));
+        }
 
-        // Pre-fund: insurance receives the amount now.
-        // When credits are later spent during fee settlement, no further
-        // insurance booking occurs (coupon semantics).
-        self.insurance_fund.balance = self.insurance_fund.balance + amount;
-        self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue + amount;
+        let fb = match fallback {
+            Some(fb) if ext.fallback_oracle != [0u8; 32] => fb,
+            _ => return Err(RiskError::OracleUnavailable),
+        };
+        if !self.oracle_quote_usable(fb, &ext.fallback_oracle, now_slot) {
+            return Err(RiskError::OracleUnavailable);
+        }
 
-        // Credit the account
-        self.accounts[idx as usize].fee_credits = self.accounts[idx as usize]
-            .fee_credits
-            .saturating_add(amount as i128);
+        // Divergence is measured against the primary's last reported price, provided it
+        // is a genuine reading of the primary feed (only stale or imprecise)
+        let primary_genuine = primary.price > 0
+            && primary.price <= MAX_ORACLE_PRICE
+            && (ext.primary_oracle == [0u8; 32] || primary.feed == ext.primary_oracle);
+        if ext.oracle_max_divergence_bps > 0 && primary_genuine {
+            let diff = (fb.price as u128).abs_diff(primary.price as u128);
+            let max_diff = mul_u128(primary.price as u128, ext.oracle_max_divergence_bps as u128);
+            if mul_u128(diff, 10_000) > max_diff {
+                return Err(RiskError::OracleUnavailable);
+            }
+        }
 
-        Ok(())
+        Ok((fb.price, OracleFeed::Fallback))
     }
 
-    /// Add fee credits without vault/insurance accounting.
-    /// Only for tests and Kani proofs — production code must use deposit_fee_credits.
-    #[cfg(any(test, feature = "test", kani))]
-    pub fn add_fee_credits(&mut self, idx: u16, amount: u128) -> Result<()> {
-        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
-            return Err(RiskError::Unauthorized);
+    /// Select the oracle price (see `select_oracle_price`), rejecting a quote published
+    /// before the newest one already accepted, so an older, favorable price cannot be
+    /// replayed. Returns the price, the feed used and its publish slot; nothing is
+    /// recorded until the caller's operation succeeds (see `commit_oracle_quote`).
+    fn check_oracle_quote(
+        &self,
+        now_slot: u64,
+        primary: &OracleQuote,
+        fallback: Option<&OracleQuote>,
+    ) -> Result<(u64, OracleFeed, u64)> {
+        let (price, feed) = self.select_oracle_price(now_slot, primary, fallback)?;
+        let publish_slot = match feed {
+            OracleFeed::Fallback => fallback.map_or(0, |q| q.publish_slot),
+            _ => primary.publish_slot,
+        };
+        if publish_slot < self.last_oracle_publish_slot {
+            return Err(RiskError::OracleRollback);
         }
-        self.accounts[idx as usize].fee_credits = self.accounts[idx as usize]
-            .fee_credits
-            .saturating_add(amount as i128);
-        Ok(())
+        Ok((price, feed, publish_slot))
     }
 
-    /// Set the risk reduction threshold (admin function).
-    /// This controls when risk-reduction-only mode is triggered.
-    #[inline]
-    pub fn set_risk_reduction_threshold(&mut self, new_threshold: u128) {
-        self.params.risk_reduction_threshold = U128::new(new_threshold);
+    /// Record a quote checked by `check_oracle_quote` as the newest accepted one.
+    fn commit_oracle_quote(&mut self, price: u64, feed: OracleFeed, publish_slot: u64) {
+        self.last_oracle_feed = feed;
+        self.last_oracle_price = price;
+        self.last_oracle_publish_slot = publish_slot;
     }
 
-    /// Get the current risk reduction threshold.
-    #[inline]
-    pub fn risk_reduction_threshold(&self) -> u128 {
-        self.params.risk_reduction_threshold.get()
+    /// Keeper crank using oracle failover to pick the price.
+    ///
+    /// Selects between `primary` and `fallback` via `select_oracle_price` and runs
+    /// `keeper_crank` at the selected price; only if the crank succeeds is the feed
+    /// used recorded in `last_oracle_feed`. Quotes published before
+    /// `last_oracle_publish_slot` are rejected.
+    #[allow(clippy::too_many_arguments)]
+    pub fn keeper_crank_with_oracles(
+        &mut self,
+        caller_idx: u16,
+        now_slot: u64,
+        primary: &OracleQuote,
+        fallback: Option<&OracleQuote>,
+        funding_rate_bps_per_slot: i64,
+        allow_panic: bool,
+        max_pnl_vault_bps: u64,
+        max_oi_abs: u128,
+    ) -> Result<CrankOutcome> {
+        let (price, feed, publish_slot) = self.check_oracle_quote(now_slot, primary, fallback)?;
+        let outcome = self.keeper_crank(
+            caller_idx,
+            now_slot,
+            price,
+            funding_rate_bps_per_slot,
+            allow_panic,
+            max_pnl_vault_bps,
+            max_oi_abs,
+        )?;
+        self.commit_oracle_quote(price, feed, publish_slot);
+        Ok(outcome)
     }
 
-    /// Close an account and return its capital to the caller.
-    ///
-    /// Requirements:
-    /// - Account must exist
-    /// - Position must be zero (no open positions)
-    /// - fee_credits >= 0 (no outstanding fees owed)
-    /// - pnl must be 0 after settlement (positive pnl must be warmed up first)
+    // ========================================
+    // Liquidation
+    // ========================================
+
+    /// Compute mark PnL for a position at oracle price (pure helper, no side effects).
+    /// Returns the PnL from closing the position at oracle price.
+    /// - Longs: profit when oracle > entry
+    /// - Shorts: profit when entry > oracle
     ///
-    /// Returns Err(PnlNotWarmedUp) if pnl > 0 (user must wait for warmup).
-    /// Returns Err(Undercollateralized) if pnl < 0 (shouldn't happen after settlement).
-    /// Returns the capital amount on success.
-    pub fn close_account(&mut self, idx: u16, now_slot: u64, oracle_price: u64) -> Result<u128> {
-        // Update current_slot so warmup/bookkeeping progresses consistently
-        self.current_slot = now_slot;
+    /// Assumes the default e6 price divisor; see `mark_pnl_for_position_scaled`.
+    pub fn mark_pnl_for_position(pos: i128, entry: u64, oracle: u64) -> Result<i128> {
+        Self::mark_pnl_for_position_scaled(pos, entry, oracle, DEFAULT_PRICE_SCALE as u128)
+    }
 
-        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
-            return Err(RiskError::AccountNotFound);
+    /// `mark_pnl_for_position` with an explicit price divisor (see `price_divisor`).
+    pub fn mark_pnl_for_position_scaled(
+        pos: i128,
+        entry: u64,
+        oracle: u64,
+        price_divisor: u128,
+    ) -> Result<i128> {
+        if pos == 0 {
+            return Ok(0);
         }
 
-        // Full settlement: funding + maintenance fees + warmup
-        // This converts warmed pnl to capital and realizes negative pnl
-        self.touch_account_full(idx, now_slot, oracle_price)?;
+        let abs_pos = saturating_abs_i128(pos) as u128;
 
-        // Position must be zero
-        if !self.accounts[idx as usize].position_size.is_zero() {
-            return Err(RiskError::Undercollateralized); // Has open position
+        let diff: i128 = if pos > 0 {
+            // Long: profit when oracle > entry
+            (oracle as i128).saturating_sub(entry as i128)
+        } else {
+            // Short: profit when entry > oracle
+            (entry as i128).saturating_sub(oracle as i128)
+        };
+
+        // mark_pnl = diff * abs_pos / price_divisor
+        diff.checked_mul(u128_to_i128_clamped(abs_pos))
+            .ok_or(RiskError::Overflow)?
+            .checked_div(u128_to_i128_clamped(price_divisor))
+            .ok_or(RiskError::Overflow)
+    }
+
+    /// Inverse-contract mark PnL, in the base (collateral) asset:
+    /// `pos × D × (1/entry − 1/oracle) = pos × D × (oracle − entry) / (entry × oracle)`.
+    /// Rounded toward negative infinity (losses up, profits down).
+    pub fn mark_pnl_for_position_inverse(
+        pos: i128,
+        entry: u64,
+        oracle: u64,
+        price_divisor: u128,
+    ) -> Result<i128> {
+        if pos == 0 {
+            return Ok(0);
         }
+        let diff = (oracle as i128).saturating_sub(entry as i128);
+        let denom = (entry as i128).saturating_mul(oracle as i128);
+        I128::new(pos.checked_mul(diff).ok_or(RiskError::Overflow)?)
+            .checked_mul_div(u128_to_i128_clamped(price_divisor), denom, Rounding::Down)
+            .map(I128::get)
+            .ok_or(RiskError::Overflow)
+    }
 
-        // Forgive any remaining fee debt (Finding C: fee debt traps).
-        // pay_fee_debt_from_capital (via touch_account_full above) already paid
-        // what it could. Any remainder is uncollectable — forgive and proceed.
-        if self.accounts[idx as usize].fee_credits.is_negative() {
-            self.accounts[idx as usize].fee_credits = I128::ZERO;
+    /// Compute how much position to close for liquidation (closed-form, single-pass).
+    ///
+    /// Returns (close_abs, is_full_close) where:
+    /// - close_abs = absolute position size to close
+    /// - is_full_close = true if this is a full position close (including dust kill-switch)
+    ///
+    /// ## Algorithm:
+    /// 1. Compute target_bps = maintenance_margin_bps + liquidation_buffer_bps
+    /// 2. Compute max safe remaining position: abs_pos_safe_max = floor(E_mtm * 10_000 * D / (P * target_bps))
+    ///    where D is the market's price divisor (1e6 by default)
+    /// 3. close_abs = abs_pos - abs_pos_safe_max
+    /// 4. Close at least `min_liquidation_size(abs_pos)` so large accounts make progress
+    /// 5. If remaining position < min_liquidation_abs, do full close (dust kill-switch)
+    ///
+    /// Uses MTM equity (capital + realized_pnl + mark_pnl) for correct risk calculation.
+    /// This is deterministic, requires no iteration, and guarantees single-pass liquidation.
+    pub fn compute_liquidation_close_amount(
+        &self,
+        account: &Account,
+        oracle_price: u64,
+    ) -> (u128, bool) {
+        let abs_pos = saturating_abs_i128(account.position_size.get()) as u128;
+        if abs_pos == 0 {
+            return (0, false);
         }
 
-        let account = &self.accounts[idx as usize];
+        // MTM equity at oracle price (fail-safe: overflow returns 0 = full liquidation)
+        let equity = self.account_equity_mtm_at_oracle(account, oracle_price);
 
-        // PnL must be zero to close. This enforces:
-        // 1. Users can't bypass warmup by closing with positive unwarmed pnl
-        // 2. Conservation is maintained (forfeiting pnl would create unbounded slack)
-        // 3. Negative pnl after full settlement implies insolvency
-        if account.pnl.is_positive() {
-            return Err(RiskError::PnlNotWarmedUp);
-        }
-        if account.pnl.is_negative() {
-            return Err(RiskError::Undercollateralized);
-        }
+        // Target margin = maintenance + buffer (in basis points)
+        let target_bps = self
+            .params
+            .maintenance_margin_bps
+            .saturating_add(self.params.liquidation_buffer_bps);
 
-        let capital = account.capital;
+        // Maximum safe remaining position (floor-safe calculation)
+        // abs_pos_safe_max = floor(equity * 10_000 * price_divisor / (oracle_price * target_bps))
+        //
+        // Margin requirements round up (notional and bps step each add at most one
+        // unit, scaled by target_bps / 10_000), so reserve that slack from equity.
+        let rounding_slack = 2u128.saturating_add(target_bps as u128 / 10_000);
+        // (inverse markets swap price and divisor: notional = pos × D / P)
+        let budget = mul_u128(
+            self.collateral_value(equity.saturating_sub(rounding_slack)),
+            10_000,
+        );
+        let (numerator, denominator) = match self.ext_params.payoff_mode {
+            PayoffMode::Linear | PayoffMode::Quanto => (
+                mul_u128(budget, self.price_divisor()),
+                mul_u128(oracle_price as u128, target_bps as u128),
+            ),
+            PayoffMode::Inverse => (
+                mul_u128(budget, oracle_price as u128),
+                mul_u128(self.price_divisor(), target_bps as u128),
+            ),
+        };
 
-        // Deduct from vault
-        if capital > self.vault {
-            return Err(RiskError::InsufficientBalance);
-        }
-        self.vault = self.vault - capital;
+        // Edge case: full liquidation if no denominator
+        let mut abs_pos_safe_max = numerator.checked_div(denominator).unwrap_or(0);
 
-        // Decrement c_tot before freeing slot (free_slot zeroes account but doesn't update c_tot)
-        self.set_capital(idx as usize, 0);
+        // Clamp to current position (can't have safe max > actual position)
+        abs_pos_safe_max = core::cmp::min(abs_pos_safe_max, abs_pos);
 
-        // Free the slot
-        self.free_slot(idx);
+        // Conservative rounding guard: subtract 1 unit to ensure we close slightly more
+        // than mathematically required. This guarantees post-liquidation account is
+        // strictly on the safe side of the inequality despite integer truncation.
+        abs_pos_safe_max = abs_pos_safe_max.saturating_sub(1);
 
-        Ok(capital.get())
+        // Required close amount, at least the minimum liquidation size
+        let close_abs = core::cmp::min(
+            abs_pos,
+            core::cmp::max(
+                abs_pos.saturating_sub(abs_pos_safe_max),
+                self.min_liquidation_size(abs_pos),
+            ),
+        );
+
+        // Dust kill-switch: if remaining position would be below min, do full close
+        let remaining = abs_pos.saturating_sub(close_abs);
+        if remaining < self.params.min_liquidation_abs.get() {
+            return (abs_pos, true); // Full close
+        }
+
+        (close_abs, close_abs == abs_pos)
     }
 
-    /// Free an account slot (internal helper).
-    /// Clears the account, bitmap, and returns slot to freelist.
-    /// Caller must ensure the account is safe to free (no capital, no positive pnl, etc).
-    fn free_slot(&mut self, idx: u16) {
-        self.accounts[idx as usize] = empty_account();
-        self.clear_used(idx as usize);
-        self.next_free[idx as usize] = self.free_head;
-        self.free_head = idx;
-        self.num_used_accounts = self.num_used_accounts.saturating_sub(1);
+    /// Smallest partial liquidation of a position of `abs_pos`: the larger of
+    /// `min_liquidation_abs` and `ExtParams::min_liquidation_bps` of the position.
+    pub fn min_liquidation_size(&self, abs_pos: u128) -> u128 {
+        let pct = mul_div(
+            abs_pos,
+            self.ext_params.min_liquidation_bps as u128,
+            10_000,
+            Rounding::Up,
+        );
+        core::cmp::max(self.params.min_liquidation_abs.get(), pct)
     }
 
-    /// Garbage collect dust accounts.
-    ///
-    /// A "dust account" is a slot that can never pay out anything:
-    /// - position_size == 0
-    /// - capital == 0
-    /// - reserved_pnl == 0
-    /// - pnl <= 0
+    /// Core helper for closing a SLICE of a position at oracle price (partial liquidation).
     ///
-    /// Any remaining negative PnL is socialized via ADL waterfall before freeing.
-    /// No token transfers occur - this is purely internal bookkeeping cleanup.
+    /// Similar to oracle_close_position_core but:
+    /// - Only closes `close_abs` units of position (not the entire position)
+    /// - Computes proportional mark_pnl for the closed slice
+    /// - Entry price remains unchanged (correct for same-direction partial reduction)
     ///
-    /// Called at end of keeper_crank after liquidation/settlement has already run.
+    /// ## PnL Routing (same invariant as full close):
+    /// - mark_pnl > 0 (profit) → backed by haircut ratio h (no ADL needed)
+    /// - mark_pnl <= 0 (loss) → realized via settle_warmup_to_capital (capital path)
+    /// - Residual negative PnL (capital exhausted) → written off via set_pnl(i, 0) (spec §6.1)
     ///
-    /// Returns the number of accounts closed.
-    pub fn garbage_collect_dust(&mut self) -> u32 {
-        // Collect dust candidates: accounts with zero position, capital, reserved, and non-positive pnl
-        let mut to_free: [u16; GC_CLOSE_BUDGET as usize] = [0; GC_CLOSE_BUDGET as usize];
-        let mut num_to_free = 0usize;
+    /// ASSUMES: Caller has already called touch_account_full() on this account.
+    fn oracle_close_position_slice_core(
+        &mut self,
+        idx: u16,
+        oracle_price: u64,
+        close_abs: u128,
+    ) -> Result<ClosedOutcome> {
+        let pos = self.accounts[idx as usize].position_size.get();
+        let current_abs_pos = saturating_abs_i128(pos) as u128;
 
-        // Scan up to ACCOUNTS_PER_CRANK slots, capped to MAX_ACCOUNTS
-        let max_scan = (ACCOUNTS_PER_CRANK as usize).min(MAX_ACCOUNTS);
-        let start = self.gc_cursor as usize;
+        if close_abs == 0 || current_abs_pos == 0 {
+            return Ok(ClosedOutcome {
+                abs_pos: 0,
+                mark_pnl: 0,
+                cap_before: self.accounts[idx as usize].capital.get(),
+                cap_after: self.accounts[idx as usize].capital.get(),
+                position_was_closed: false,
+            });
+        }
+
+        if close_abs >= current_abs_pos {
+            return self.oracle_close_position_core(idx, oracle_price);
+        }
+
+        let entry = self.accounts[idx as usize].entry_price;
+        let cap_before = self.accounts[idx as usize].capital.get();
 
-        for offset in 0..max_scan {
-            // Budget check
-            if num_to_free >= GC_CLOSE_BUDGET as usize {
-                break;
-            }
+        let close_signed = if pos > 0 {
+            u128_to_i128_clamped(close_abs)
+        } else {
+            u128_to_i128_clamped(close_abs).saturating_neg()
+        };
 
-            let idx = (start + offset) & ACCOUNT_IDX_MASK;
+        let mark_pnl = match self.mark_pnl(close_signed, entry, oracle_price) {
+            Ok(pnl) => pnl,
+            Err(_) => u128_to_i128_clamped(cap_before).saturating_neg(),
+        };
 
-            // Check if slot is used via bitmap
-            let block = idx >> 6;
-            let bit = idx & 63;
-            if (self.used[block] & (1u64 << bit)) == 0 {
-                continue;
-            }
+        // Apply mark PnL via set_pnl (maintains pnl_pos_tot aggregate)
+        let new_pnl = self.accounts[idx as usize].pnl.get().saturating_add(mark_pnl);
+        self.set_pnl(idx as usize, new_pnl);
+        self.attribute_lp_inventory_pnl(idx as usize, mark_pnl);
 
-            // NEVER garbage collect LP accounts - they are essential for market operation
-            if self.accounts[idx].is_lp() {
-                continue;
-            }
+        // Update position
+        let new_abs_pos = current_abs_pos.saturating_sub(close_abs);
+        let new_pos = if pos > 0 {
+            I128::from_u128_clamped(new_abs_pos)
+        } else {
+            I128::from_u128_clamped(new_abs_pos).saturating_neg()
+        };
+        let now_slot = self.current_slot;
+        let account = &mut self.accounts[idx as usize];
+        account.record_position_change(pos, new_pos.get(), now_slot);
+        account.position_size = new_pos;
 
-            // Best-effort fee settle so accounts with tiny capital get drained in THIS sweep.
-            let _ = self.settle_maintenance_fee_best_effort_for_crank(idx as u16, self.current_slot);
+        // Update OI
+        self.total_open_interest = self.total_open_interest.saturating_sub(close_abs);
 
-            // Dust predicate: must have zero position, capital, reserved, and non-positive pnl
-            {
-                let account = &self.accounts[idx];
-                if !account.position_size.is_zero() {
-                    continue;
-                }
-                if !account.capital.is_zero() {
-                    continue;
-                }
-                if account.reserved_pnl != 0 {
-                    continue;
-                }
-                if account.pnl.is_positive() {
-                    continue;
-                }
-            }
+        // Update LP aggregates if LP
+        if self.accounts[idx as usize].is_lp() {
+            let new_pos = self.accounts[idx as usize].position_size.get();
+            self.net_lp_pos = self.net_lp_pos.saturating_sub(pos).saturating_add(new_pos);
+            self.lp_sum_abs = self.lp_sum_abs.saturating_sub(close_abs);
+        }
 
-            // If flat, funding is irrelevant — snap to global so dust can be collected.
-            // Position size is already confirmed zero above, so no unsettled funding value.
-            if self.accounts[idx].funding_index != self.funding_index_qpb_e6 {
-                self.accounts[idx].funding_index = self.funding_index_qpb_e6;
-            }
+        // Loss beyond capital is written off below (bad debt)
+        self.record_uncovered_loss(idx as usize);
 
-            // Write off negative pnl (spec §6.1: unpayable loss just reduces Residual)
-            if self.accounts[idx].pnl.is_negative() {
-                self.set_pnl(idx, 0);
-            }
+        // Settle warmup (loss settlement + profit conversion per spec §6)
+        self.settle_warmup_to_capital_inner(idx)?;
 
-            // Queue for freeing
-            to_free[num_to_free] = idx as u16;
-            num_to_free += 1;
+        // Write off residual negative PnL (capital exhausted) per spec §6.1
+        if self.accounts[idx as usize].pnl.is_negative() {
+            self.set_pnl(idx as usize, 0);
         }
 
-        // Update cursor for next call
-        self.gc_cursor = ((start + max_scan) & ACCOUNT_IDX_MASK) as u16;
+        let cap_after = self.accounts[idx as usize].capital.get();
 
-        // Free all collected dust accounts
-        for i in 0..num_to_free {
-            self.free_slot(to_free[i]);
+        Ok(ClosedOutcome {
+            abs_pos: close_abs,
+            mark_pnl,
+            cap_before,
+            cap_after,
+            position_was_closed: true,
+        })
+    }
+
+    /// Core helper for oracle-price full position close (spec §6).
+    ///
+    /// Applies mark PnL, closes position, settles warmup, writes off unpayable loss.
+    /// No ADL needed — undercollateralization is reflected via haircut ratio h.
+    ///
+    /// ASSUMES: Caller has already called touch_account_full() on this account.
+    fn oracle_close_position_core(&mut self, idx: u16, oracle_price: u64) -> Result<ClosedOutcome> {
+        if self.accounts[idx as usize].position_size.is_zero() {
+            return Ok(ClosedOutcome {
+                abs_pos: 0,
+                mark_pnl: 0,
+                cap_before: self.accounts[idx as usize].capital.get(),
+                cap_after: self.accounts[idx as usize].capital.get(),
+                position_was_closed: false,
+            });
         }
 
-        num_to_free as u32
-    }
+        let pos = self.accounts[idx as usize].position_size.get();
+        let abs_pos = saturating_abs_i128(pos) as u128;
+        let entry = self.accounts[idx as usize].entry_price;
+        let cap_before = self.accounts[idx as usize].capital.get();
 
-    // ========================================
-    // Keeper Crank
-    // ========================================
+        let mark_pnl = match self.mark_pnl(pos, entry, oracle_price) {
+            Ok(pnl) => pnl,
+            Err(_) => u128_to_i128_clamped(cap_before).saturating_neg(),
+        };
 
-    /// Check if a fresh crank is required before state-changing operations.
-    /// Returns Err if the crank is stale (too old).
-    pub fn require_fresh_crank(&self, now_slot: u64) -> Result<()> {
-        if now_slot.saturating_sub(self.last_crank_slot) > self.max_crank_staleness_slots {
-            return Err(RiskError::Unauthorized); // NeedsCrank
+        // Apply mark PnL via set_pnl (maintains pnl_pos_tot aggregate)
+        let new_pnl = self.accounts[idx as usize].pnl.get().saturating_add(mark_pnl);
+        self.set_pnl(idx as usize, new_pnl);
+        self.attribute_lp_inventory_pnl(idx as usize, mark_pnl);
+
+        // Close position
+        let now_slot = self.current_slot;
+        self.accounts[idx as usize].record_position_change(pos, 0, now_slot);
+        self.accounts[idx as usize].position_size = I128::ZERO;
+        self.accounts[idx as usize].entry_price = oracle_price;
+
+        // Update OI
+        self.total_open_interest = self.total_open_interest.saturating_sub(abs_pos);
+
+        // Update LP aggregates if LP
+        if self.accounts[idx as usize].is_lp() {
+            self.net_lp_pos = self.net_lp_pos.saturating_sub(pos);
+            self.lp_sum_abs = self.lp_sum_abs.saturating_sub(abs_pos);
         }
-        Ok(())
-    }
 
-    /// Check if a full sweep started recently.
-    /// For risk-increasing ops, we require a sweep to have STARTED recently.
-    /// The priority-liquidation phase runs every crank, so once a sweep starts,
-    /// the worst accounts are immediately addressed.
-    pub fn require_recent_full_sweep(&self, now_slot: u64) -> Result<()> {
-        if now_slot.saturating_sub(self.last_full_sweep_start_slot) > self.max_crank_staleness_slots
-        {
-            return Err(RiskError::Unauthorized); // SweepStale
+        // Loss beyond capital is written off below (bad debt)
+        self.record_uncovered_loss(idx as usize);
+
+        // Settle warmup (loss settlement + profit conversion per spec §6)
+        self.settle_warmup_to_capital_inner(idx)?;
+
+        // Write off residual negative PnL (capital exhausted) per spec §6.1
+        if self.accounts[idx as usize].pnl.is_negative() {
+            self.set_pnl(idx as usize, 0);
         }
-        Ok(())
-    }
 
+        let cap_after = self.accounts[idx as usize].capital.get();
 
-    /// Check if force-realize mode is active (insurance at or below threshold).
-    /// When active, keeper_crank will run windowed force-realize steps.
-    #[inline]
-    fn force_realize_active(&self) -> bool {
-        self.insurance_fund.balance <= self.params.risk_reduction_threshold
+        Ok(ClosedOutcome {
+            abs_pos,
+            mark_pnl,
+            cap_before,
+            cap_after,
+            position_was_closed: true,
+        })
     }
 
-    /// Keeper crank entrypoint - advances global state and performs maintenance.
-    ///
-    /// Returns CrankOutcome with flags indicating what happened.
+    /// Liquidate a single account at oracle price if below maintenance margin.
     ///
-    /// Behavior:
-    /// 1. Accrue funding
-    /// 2. Advance last_crank_slot if now_slot > last_crank_slot
-    /// 3. Settle maintenance fees for caller (50% discount)
-    /// 4. Process up to ACCOUNTS_PER_CRANK occupied accounts:
-    ///    - Liquidation (if not in force-realize mode)
-    ///    - Force-realize (if insurance at/below threshold)
-    ///    - Socialization (haircut profits to cover losses)
-    ///    - LP max tracking
-    /// 5. Detect and finalize full sweep completion
+    /// Returns Ok(true) if liquidation occurred, Ok(false) if not needed/possible.
+    /// Per spec: close position, settle losses, write off unpayable PnL, charge fee.
+    /// No ADL — haircut ratio h reflects any undercollateralization.
     ///
-    /// This is the single permissionless "do-the-right-thing" entrypoint.
-    /// - Always attempts caller's maintenance settle with 50% discount (best-effort)
-    /// - Only advances last_crank_slot when now_slot > last_crank_slot
-    /// - Returns last_cursor: the index where this crank stopped
-    /// - Returns sweep_complete: true if this crank completed a full sweep
+    /// No keeper is credited here: the keeper share of the fee goes to the insurance fund.
+    pub fn liquidate_at_oracle(
+        &mut self,
+        idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<bool> {
+        Ok(self
+            .liquidate_at_oracle_with_keeper(idx, None, now_slot, oracle_price)?
+            .is_some())
+    }
+
+    /// Liquidate a single account at oracle price, crediting the keeper share of the
+    /// liquidation fee to `keeper_idx`.
     ///
-    /// When the system has fewer than ACCOUNTS_PER_CRANK accounts, one crank
-    /// covers all accounts and completes a full sweep.
-    pub fn keeper_crank(
+    /// Returns Ok(Some(record)) if liquidation occurred, Ok(None) if not needed/possible.
+    /// The LP share of the fee is distributed immediately to LP accounts pro-rata by capital.
+    pub fn liquidate_at_oracle_with_keeper(
         &mut self,
-        caller_idx: u16,
+        idx: u16,
+        keeper_idx: Option<u16>,
         now_slot: u64,
         oracle_price: u64,
-        funding_rate_bps_per_slot: i64,
-        allow_panic: bool,
-        max_pnl_vault_bps: u64,
-        max_oi_abs: u128,
-    ) -> Result<CrankOutcome> {
-        // Validate oracle price bounds (prevents overflow in mark_pnl calculations)
-        if oracle_price == 0 || oracle_price > MAX_ORACLE_PRICE {
-            return Err(RiskError::Overflow);
-        }
+    ) -> Result<Option<LiquidationRecord>> {
+        let result =
+            self.liquidate_and_distribute(idx, keeper_idx, u128::MAX, now_slot, oracle_price);
+        self.finish_mutation("liquidate_at_oracle_with_keeper", result.is_ok());
+        result
+    }
 
-        // Update current_slot so warmup/bookkeeping progresses consistently
-        self.current_slot = now_slot;
+    /// Liquidate `target_idx` on behalf of `liquidator_idx`, closing at most `max_size`
+    /// of its position at the oracle price. Anyone holding an account may call this
+    /// between cranks; the liquidator receives the keeper share of the fee.
+    ///
+    /// Applies the same checks as the crank: the target must be below maintenance
+    /// margin after touching, and the close never exceeds what restores the target
+    /// margin. A partial close capped by `max_size` must still be at least
+    /// `min_liquidation_size` (InvalidParams otherwise), so slicing cannot be used
+    /// to dodge `liquidation_fee_cap`.
+    ///
+    /// Returns Ok(None) if the target is healthy or flat.
+    pub fn liquidate(
+        &mut self,
+        liquidator_idx: u16,
+        target_idx: u16,
+        max_size: u128,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<Option<LiquidationRecord>> {
+        let result =
+            self.liquidate_inner(liquidator_idx, target_idx, max_size, now_slot, oracle_price);
+        self.finish_mutation("liquidate", result.is_ok());
+        result
+    }
 
-        // Detect if this is the start of a new sweep
-        let starting_new_sweep = self.crank_cursor == self.sweep_start_idx;
-        if starting_new_sweep {
-            self.last_full_sweep_start_slot = now_slot;
-            // Reset in-progress lp_max_abs for fresh sweep
-            self.lp_max_abs_sweep = U128::ZERO;
+    fn liquidate_inner(
+        &mut self,
+        liquidator_idx: u16,
+        target_idx: u16,
+        max_size: u128,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<Option<LiquidationRecord>> {
+        for idx in [liquidator_idx, target_idx] {
+            if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+                return Err(RiskError::AccountNotFound);
+            }
         }
-
-        // Accrue funding first using the STORED rate (anti-retroactivity).
-        // This ensures funding charged for the elapsed interval uses the rate that was
-        // in effect at the start of the interval, NOT the new rate computed from current state.
-        self.accrue_funding(now_slot, oracle_price)?;
-
-        // Now set the new rate for the NEXT interval (anti-retroactivity).
-        // The funding_rate_bps_per_slot parameter becomes the rate for [now_slot, next_accrual).
-        self.set_funding_rate_for_next_interval(funding_rate_bps_per_slot);
-
-        // Check if we're advancing the global crank slot
-        let advanced = now_slot > self.last_crank_slot;
-        if advanced {
-            self.last_crank_slot = now_slot;
+        if liquidator_idx == target_idx || max_size == 0 {
+            return Err(RiskError::InvalidParams);
         }
+        self.liquidate_and_distribute(
+            target_idx,
+            Some(liquidator_idx),
+            max_size,
+            now_slot,
+            oracle_price,
+        )
+    }
 
-        // Always attempt caller's maintenance settle (best-effort, no timestamp games)
-        let (slots_forgiven, caller_settle_ok) = if (caller_idx as usize) < MAX_ACCOUNTS
-            && self.is_used(caller_idx as usize)
-        {
-            let last_fee = self.accounts[caller_idx as usize].last_fee_slot;
-            let dt = now_slot.saturating_sub(last_fee);
-            let forgive = dt / 2;
-
-            if forgive > 0 && dt > 0 {
-                self.accounts[caller_idx as usize].last_fee_slot = last_fee.saturating_add(forgive);
-            }
-            let settle_result =
-                self.settle_maintenance_fee_best_effort_for_crank(caller_idx, now_slot);
-            (forgive, settle_result.is_ok())
-        } else {
-            (0, true)
-        };
-
-        // OI cap enforcement: if total OI exceeds cap, block new position opens
-        // by temporarily boosting risk_reduction_threshold to u128::MAX.
-        // This uses the existing risk-reduction gate in trade handlers.
-        let oi_cap_active = max_oi_abs > 0 && self.total_open_interest.get() > max_oi_abs;
+    /// Like `liquidate`, but the liquidator assumes the closed slice into its own
+    /// account at the oracle (liquidation) price instead of closing it out, so open
+    /// interest stays matched against the LPs' offsetting inventory.
+    ///
+    /// The liquidator is settled to the oracle first and must meet initial margin
+    /// afterwards if its exposure grew (maintenance otherwise); Undercollateralized
+    /// aborts the whole liquidation. User liquidators also count against
+    /// `max_owner_notional`, and a reduce-only liquidator may only take over a
+    /// slice that reduces its position (ReduceOnly).
+    pub fn liquidate_with_takeover(
+        &mut self,
+        liquidator_idx: u16,
+        target_idx: u16,
+        max_size: u128,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<Option<LiquidationRecord>> {
+        let result = self.liquidate_with_takeover_inner(
+            liquidator_idx,
+            target_idx,
+            max_size,
+            now_slot,
+            oracle_price,
+        );
+        self.finish_mutation("liquidate_with_takeover", result.is_ok());
+        result
+    }
 
-        // Detect conditions for informational flags (before processing)
-        let force_realize_active = self.force_realize_active();
+    fn liquidate_with_takeover_inner(
+        &mut self,
+        liquidator_idx: u16,
+        target_idx: u16,
+        max_size: u128,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<Option<LiquidationRecord>> {
+        let target_long = self
+            .accounts
+            .get(target_idx as usize)
+            .is_some_and(|a| a.position_size.is_positive());
+        let liquidation =
+            self.liquidate_inner(liquidator_idx, target_idx, max_size, now_slot, oracle_price);
+        let record = match liquidation? {
+            Some(r) => r,
+            None => return Ok(None),
+        };
 
-        // Process up to ACCOUNTS_PER_CRANK occupied accounts
-        let mut num_liquidations: u32 = 0;
-        let mut num_liq_errors: u16 = 0;
-        let mut force_realize_closed: u16 = 0;
-        let mut force_realize_errors: u16 = 0;
-        let mut max_pnl_closed: u16 = 0;
-        let mut max_pnl_errors: u16 = 0;
-        let mut sweep_complete = false;
-        let mut accounts_processed: u16 = 0;
-        let mut liq_budget = LIQ_BUDGET_PER_CRANK;
-        let mut force_realize_budget = FORCE_REALIZE_BUDGET_PER_CRANK;
+        // Settle the liquidator to the oracle so the assumed slice enters at that price
+        self.touch_account_full_inner(liquidator_idx, now_slot, oracle_price)?;
 
-        let start_cursor = self.crank_cursor;
+        let l = liquidator_idx as usize;
+        let taken = u128_to_i128_clamped(record.closed_abs);
+        let delta = if target_long { taken } else { taken.saturating_neg() };
+        let old_pos = self.accounts[l].position_size.get();
+        let new_pos = old_pos.checked_add(delta).ok_or(RiskError::Overflow)?;
+        let (old_abs, new_abs) = (saturating_abs_i128(old_pos), saturating_abs_i128(new_pos));
+        if new_abs as u128 > MAX_POSITION_ABS {
+            return Err(RiskError::Overflow);
+        }
+        let risk_increasing = new_abs > old_abs || (old_pos ^ new_pos) < 0;
 
-        // Iterate through index space looking for occupied accounts
-        let mut idx = self.crank_cursor as usize;
-        let mut slots_scanned: usize = 0;
+        // Reduce-only liquidators may only take over a slice that moves them toward zero
+        let reduces = new_pos == 0 || (new_pos.signum() == old_pos.signum() && new_abs < old_abs);
+        if self.accounts[l].reduce_only != 0 && !reduces {
+            return Err(RiskError::ReduceOnly);
+        }
 
-        while accounts_processed < ACCOUNTS_PER_CRANK && slots_scanned < MAX_ACCOUNTS {
-            slots_scanned += 1;
+        // The close released `closed_abs` of open interest; the liquidator re-opens it
+        self.total_open_interest = self
+            .total_open_interest
+            .saturating_sub(old_abs as u128)
+            .saturating_add(new_abs as u128);
+        if self.accounts[l].is_lp() {
+            self.net_lp_pos = self.net_lp_pos.saturating_add(delta);
+            self.lp_sum_abs = self
+                .lp_sum_abs
+                .saturating_sub(old_abs as u128)
+                .saturating_add(new_abs as u128);
+        }
+        let account = &mut self.accounts[l];
+        account.position_size = I128::new(new_pos);
+        account.entry_price = oracle_price;
+        account.record_position_change(old_pos, new_pos, now_slot);
+        account.last_activity_slot = now_slot;
+
+        if new_pos != 0 {
+            let account = &self.accounts[l];
+            let bps = if risk_increasing {
+                self.initial_margin_bps_for(account)
+            } else {
+                self.params.maintenance_margin_bps
+            };
+            if !self.is_above_margin_bps_mtm(account, oracle_price, bps) {
+                return Err(RiskError::Undercollateralized);
+            }
+        }
+        let cap = self.ext_params.max_owner_notional.get();
+        if risk_increasing && cap > 0 && self.accounts[l].is_user() {
+            if let Some(elsewhere) = self.owner_exposure_excluding(liquidator_idx, oracle_price) {
+                let notional = self.notional_at(new_abs as u128, oracle_price, Rounding::Up);
+                if elsewhere.notional.saturating_add(notional) > cap {
+                    return Err(RiskError::OwnerLimitExceeded);
+                }
+            }
+        }
 
-            // Check if slot is used
-            let block = idx >> 6;
-            let bit = idx & 63;
-            let is_occupied = (self.used[block] & (1u64 << bit)) != 0;
+        Ok(Some(record))
+    }
 
-            if is_occupied {
-                accounts_processed += 1;
+    /// Run the core liquidation and distribute the parked LP share immediately.
+    fn liquidate_and_distribute(
+        &mut self,
+        idx: u16,
+        keeper_idx: Option<u16>,
+        max_close: u128,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<Option<LiquidationRecord>> {
+        let core =
+            self.liquidate_at_oracle_core(idx, keeper_idx, max_close, now_slot, oracle_price);
+        let mut record = match core? {
+            Some(r) => r,
+            None => return Ok(None),
+        };
 
-                // Always settle maintenance fees for every visited account.
-                // This drains idle accounts over time so they eventually become dust.
-                let _ = self.settle_maintenance_fee_best_effort_for_crank(idx as u16, now_slot);
-                // Touch account and settle warmup to drain abandoned positive PnL
-                let _ = self.touch_account(idx as u16);
-                self.settle_warmup_to_capital_for_crank(idx as u16);
+        // LP share was parked in insurance by the core helper; hand it out now
+        let parked = record.fee_to_lp;
+        let distributed = self.distribute_insurance_to_lps(parked, Some(idx as usize));
+        self.insurance_fund.fee_revenue = self
+            .insurance_fund
+            .fee_revenue
+            .saturating_add(parked.saturating_sub(distributed));
+        self.revenue.liquidation_fees = self
+            .revenue
+            .liquidation_fees
+            .saturating_add(parked.saturating_sub(distributed));
+        record.fee_to_lp = distributed;
+        record.fee_to_insurance = record
+            .fee_to_insurance
+            .saturating_add(parked.saturating_sub(distributed));
+
+        Ok(Some(record))
+    }
 
-                // === Liquidation (if not in force-realize mode) ===
-                if !force_realize_active && liq_budget > 0 {
-                    if !self.accounts[idx].position_size.is_zero() {
-                        match self.liquidate_at_oracle(idx as u16, now_slot, oracle_price) {
-                            Ok(true) => {
-                                num_liquidations += 1;
-                                liq_budget = liq_budget.saturating_sub(1);
-                            }
-                            Ok(false) => {}
-                            Err(_) => {
-                                num_liq_errors += 1;
-                            }
-                        }
-                    }
+    /// Core liquidation helper shared by the public entrypoints and the crank.
+    ///
+    /// Charges the liquidation fee and routes it through the waterfall:
+    /// keeper share to `keeper_idx` (insurance if None/invalid), insurance share to
+    /// the insurance fund, and LP share PARKED in the insurance balance (not booked
+    /// as revenue). Callers must distribute `record.fee_to_lp` via
+    /// `distribute_insurance_to_lps`, excluding a liquidated LP (the crank batches
+    /// user liquidations once per call).
+    ///
+    /// `max_close` caps the closed size (u128::MAX = engine decides); a capped close
+    /// overrides the dust kill-switch and never falls back to a full close.
+    fn liquidate_at_oracle_core(
+        &mut self,
+        idx: u16,
+        keeper_idx: Option<u16>,
+        max_close: u128,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<Option<LiquidationRecord>> {
+        self.current_slot = now_slot;
 
-                    // Force-close negative equity or dust positions
-                    if !self.accounts[idx].position_size.is_zero() {
-                        let equity =
-                            self.account_equity_mtm_at_oracle(&self.accounts[idx], oracle_price);
-                        let abs_pos = self.accounts[idx].position_size.unsigned_abs();
-                        let is_dust = abs_pos < self.params.min_liquidation_abs.get();
+        if (idx as usize) >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return Ok(None);
+        }
 
-                        if equity == 0 || is_dust {
-                            // Force close: settle mark, close position, write off loss
-                            let _ = self.touch_account_for_liquidation(idx as u16, now_slot, oracle_price);
-                            let _ = self.oracle_close_position_core(idx as u16, oracle_price);
-                            self.lifetime_force_realize_closes =
-                                self.lifetime_force_realize_closes.saturating_add(1);
-                        }
-                    }
-                }
+        if oracle_price == 0 || oracle_price > MAX_ORACLE_PRICE {
+            return Err(RiskError::Overflow);
+        }
 
-                // === Force-realize (when insurance at/below threshold) ===
-                if force_realize_active && force_realize_budget > 0 {
-                    if !self.accounts[idx].position_size.is_zero() {
-                        if self
-                            .touch_account_for_force_realize(idx as u16, now_slot, oracle_price)
-                            .is_ok()
-                        {
-                            if self.oracle_close_position_core(idx as u16, oracle_price).is_ok() {
-                                force_realize_closed += 1;
-                                force_realize_budget = force_realize_budget.saturating_sub(1);
-                                self.lifetime_force_realize_closes =
-                                    self.lifetime_force_realize_closes.saturating_add(1);
-                            } else {
-                                force_realize_errors += 1;
-                            }
-                        } else {
-                            force_realize_errors += 1;
-                        }
-                    }
-                }
+        if self.accounts[idx as usize].position_size.is_zero() {
+            return Ok(None);
+        }
 
-                // === Max PnL cap check ===
-                // If max_pnl_vault_bps > 0 and position has unrealized profit
-                // exceeding the cap, force-close it to protect LP vault
-                if max_pnl_vault_bps > 0
-                    && !self.accounts[idx].position_size.is_zero()
-                    && !self.accounts[idx].is_lp()
-                {
-                    let pos = self.accounts[idx].position_size.get();
-                    let entry = self.accounts[idx].entry_price;
-                    let settled_pnl = self.accounts[idx].pnl.get();
+        // Settle funding + mark-to-market + best-effort fees
+        self.touch_account_for_liquidation(idx, now_slot, oracle_price)?;
 
-                    if let Ok(mark_pnl) = Self::mark_pnl_for_position(pos, entry, oracle_price) {
-                        let total_pnl = settled_pnl.saturating_add(mark_pnl);
-                        if total_pnl > 0 {
-                            // max_pnl_vault_bps is pre-computed absolute cap (by program wrapper)
-                            // Wrapper computes: lp_capital_total * bps / 10000
-                            let max_pnl = max_pnl_vault_bps as u128;
-                            if (total_pnl as u128) > max_pnl {
-                                // Force close this position
-                                if self
-                                    .touch_account_for_force_realize(idx as u16, now_slot, oracle_price)
-                                    .is_ok()
-                                {
-                                    if self.oracle_close_position_core(idx as u16, oracle_price).is_ok()
-                                    {
-                                        max_pnl_closed += 1;
-                                        self.lifetime_force_realize_closes =
-                                            self.lifetime_force_realize_closes.saturating_add(1);
-                                    } else {
-                                        max_pnl_errors += 1;
-                                    }
-                                } else {
-                                    max_pnl_errors += 1;
-                                }
-                            }
-                        }
-                    }
-                }
+        let account = &self.accounts[idx as usize];
+        if self.is_above_maintenance_margin_mtm(account, oracle_price) {
+            return Ok(None);
+        }
 
-                // === LP max tracking ===
-                if self.accounts[idx].is_lp() {
-                    let abs_pos = self.accounts[idx].position_size.unsigned_abs();
-                    self.lp_max_abs_sweep = self.lp_max_abs_sweep.max(U128::new(abs_pos));
-                }
-            }
+        let (mut close_abs, mut is_full_close) =
+            self.compute_liquidation_close_amount(account, oracle_price);
 
-            // Advance to next index (with wrap)
-            idx = (idx + 1) & ACCOUNT_IDX_MASK;
+        if close_abs == 0 {
+            return Ok(None);
+        }
 
-            // Check for sweep completion: we've wrapped around to sweep_start_idx
-            // (and we've actually processed some slots, not just starting)
-            if idx == self.sweep_start_idx as usize && slots_scanned > 0 {
-                sweep_complete = true;
-                break;
+        // Full-close fallbacks below only run if the whole position fits the cap
+        let abs_pos = saturating_abs_i128(account.position_size.get()) as u128;
+        let may_close_all = abs_pos <= max_close;
+        if close_abs > max_close {
+            if max_close < self.min_liquidation_size(abs_pos) {
+                return Err(RiskError::InvalidParams);
             }
+            close_abs = max_close;
+            is_full_close = false;
         }
 
-        // Update cursor for next crank
-        self.crank_cursor = idx as u16;
+        // Close position (no ADL — losses written off in close helper)
+        let was_long = account.position_size.is_positive();
+        let mut outcome = if is_full_close {
+            self.oracle_close_position_core(idx, oracle_price)?
+        } else {
+            match self.oracle_close_position_slice_core(idx, oracle_price, close_abs) {
+                Ok(r) => r,
+                Err(RiskError::Overflow) if may_close_all => {
+                    self.oracle_close_position_core(idx, oracle_price)?
+                }
+                Err(e) => return Err(e),
+            }
+        };
 
-        // If sweep complete, finalize
-        if sweep_complete {
-            self.last_full_sweep_completed_slot = now_slot;
-            self.lp_max_abs = self.lp_max_abs_sweep;
-            self.sweep_start_idx = self.crank_cursor;
+        if !outcome.position_was_closed {
+            return Ok(None);
         }
 
-        // Garbage collect dust accounts
-        let num_gc_closed = self.garbage_collect_dust();
+        // Safety check: if position remains and still below target, full close
+        if may_close_all && !self.accounts[idx as usize].position_size.is_zero() {
+            let target_bps = self
+                .params
+                .maintenance_margin_bps
+                .saturating_add(self.params.liquidation_buffer_bps);
+            if !self.is_above_margin_bps_mtm(&self.accounts[idx as usize], oracle_price, target_bps)
+            {
+                let fallback = self.oracle_close_position_core(idx, oracle_price)?;
+                if fallback.position_was_closed {
+                    outcome.abs_pos = outcome.abs_pos.saturating_add(fallback.abs_pos);
+                }
+            }
+        }
 
-        // Detect conditions for informational flags
-        let force_realize_needed = self.force_realize_active();
-        let panic_needed = false; // No longer needed with haircut ratio
+        // Reprice the close from the oracle to the configured close price
+        let closed = u128_to_i128_clamped(outcome.abs_pos);
+        let closed = if was_long { closed } else { closed.saturating_neg() };
+        self.settle_close_price_premium(idx as usize, closed, oracle_price, now_slot);
 
-        Ok(CrankOutcome {
-            advanced,
-            slots_forgiven,
-            caller_settle_ok,
-            force_realize_needed,
-            panic_needed,
-            num_liquidations,
-            num_liq_errors,
-            num_gc_closed,
-            force_realize_closed,
-            force_realize_errors,
-            max_pnl_closed,
-            max_pnl_errors,
-            oi_cap_active,
-            last_cursor: self.crank_cursor,
-            sweep_complete,
-        })
-    }
+        // Charge liquidation fee (from remaining capital → insurance)
+        // Rounded up (vault's favor), consistent with trade fees
+        let notional = self.notional_at(outcome.abs_pos, oracle_price, Rounding::Up);
+        let fee_raw = if notional > 0 && self.params.liquidation_fee_bps > 0 {
+            mul_div(notional, self.params.liquidation_fee_bps as u128, 10_000, Rounding::Up)
+        } else {
+            0
+        };
+        let fee = core::cmp::min(fee_raw, self.params.liquidation_fee_cap.get());
+        let account_capital = self.accounts[idx as usize].capital.get();
+        let pay = core::cmp::min(fee, account_capital);
 
-    // ========================================
-    // Liquidation
-    // ========================================
+        self.set_capital(idx as usize, account_capital.saturating_sub(pay));
 
-    /// Compute mark PnL for a position at oracle price (pure helper, no side effects).
-    /// Returns the PnL from closing the position at oracle price.
-    /// - Longs: profit when oracle > entry
-    /// - Shorts: profit when entry > oracle
-    pub fn mark_pnl_for_position(pos: i128, entry: u64, oracle: u64) -> Result<i128> {
-        if pos == 0 {
-            return Ok(0);
+        // Fee waterfall: keeper share, LP share, remainder to insurance
+        let keeper_bps = core::cmp::min(self.params.liquidation_fee_keeper_share_bps, 10_000);
+        let lp_bps = core::cmp::min(
+            self.params.liquidation_fee_lp_share_bps,
+            10_000u64.saturating_sub(keeper_bps),
+        );
+        // Payouts round down; the remainder stays with insurance
+        let mut to_keeper = mul_div(pay, keeper_bps as u128, 10_000, Rounding::Down);
+        let to_lp = mul_div(pay, lp_bps as u128, 10_000, Rounding::Down);
+
+        let keeper_valid = match keeper_idx {
+            Some(k) => k != idx && (k as usize) < MAX_ACCOUNTS && self.is_used(k as usize),
+            None => false,
+        };
+        if keeper_valid && to_keeper > 0 {
+            let k = keeper_idx.unwrap_or(idx) as usize;
+            let new_cap = add_u128(self.accounts[k].capital.get(), to_keeper);
+            self.set_capital(k, new_cap);
+        } else {
+            to_keeper = 0;
         }
+        let to_insurance = pay.saturating_sub(to_keeper).saturating_sub(to_lp);
 
-        let abs_pos = saturating_abs_i128(pos) as u128;
+        // Insurance holds its own share plus the parked LP share until distribution
+        self.insurance_fund.balance = self
+            .insurance_fund
+            .balance
+            .saturating_add(to_insurance.saturating_add(to_lp));
+        self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue.saturating_add(to_insurance);
+        self.revenue.liquidation_fees = self.revenue.liquidation_fees.saturating_add(to_insurance);
 
-        let diff: i128 = if pos > 0 {
-            // Long: profit when oracle > entry
-            (oracle as i128).saturating_sub(entry as i128)
-        } else {
-            // Short: profit when entry > oracle
-            (entry as i128).saturating_sub(oracle as i128)
-        };
+        self.lifetime_liquidations = self.lifetime_liquidations.saturating_add(1);
+        self.roll_liq_epoch(now_slot);
+        let stats = &mut self.liq_analytics.current;
+        stats.liquidations = stats.liquidations.saturating_add(1);
+        stats.volume_notional = stats.volume_notional.saturating_add(notional);
+        stats.penalty_total = stats.penalty_total.saturating_add(pay);
+        self.record_event(
+            EventKind::Liquidation,
+            idx,
+            if keeper_valid { keeper_idx.unwrap_or(EVENT_NO_ACCOUNT) } else { EVENT_NO_ACCOUNT },
+            pay,
+            u128_to_i128_clamped(outcome.abs_pos),
+            oracle_price,
+        );
 
-        // mark_pnl = diff * abs_pos / 1_000_000
-        diff.checked_mul(abs_pos as i128)
-            .ok_or(RiskError::Overflow)?
-            .checked_div(1_000_000)
-            .ok_or(RiskError::Overflow)
+        Ok(Some(LiquidationRecord {
+            closed_abs: outcome.abs_pos,
+            fee_total: pay,
+            fee_to_keeper: to_keeper,
+            fee_to_insurance: to_insurance,
+            fee_to_lp: to_lp,
+        }))
     }
 
-    /// Compute how much position to close for liquidation (closed-form, single-pass).
-    ///
-    /// Returns (close_abs, is_full_close) where:
-    /// - close_abs = absolute position size to close
-    /// - is_full_close = true if this is a full position close (including dust kill-switch)
+    /// Lifetime return attribution of LP account `idx`.
     ///
-    /// ## Algorithm:
-    /// 1. Compute target_bps = maintenance_margin_bps + liquidation_buffer_bps
-    /// 2. Compute max safe remaining position: abs_pos_safe_max = floor(E_mtm * 10_000 * 1_000_000 / (P * target_bps))
-    /// 3. close_abs = abs_pos - abs_pos_safe_max
-    /// 4. If remaining position < min_liquidation_abs, do full close (dust kill-switch)
+    /// Unrealized mark PnL since the LP was last settled is not included; settle
+    /// to the oracle first for an up-to-date inventory component.
+    pub fn lp_pnl_attribution(&self, idx: u16) -> Result<LpPnlAttribution> {
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return Err(RiskError::AccountNotFound);
+        }
+        let account = &self.accounts[idx as usize];
+        if !account.is_lp() {
+            return Err(RiskError::NotAnLPAccount);
+        }
+        Ok(account.lp_pnl)
+    }
+
+    /// Inventory and initial-margin headroom of LP account `idx` at `oracle_price`.
     ///
-    /// Uses MTM equity (capital + realized_pnl + mark_pnl) for correct risk calculation.
-    /// This is deterministic, requires no iteration, and guarantees single-pass liquidation.
-    pub fn compute_liquidation_close_amount(
-        &self,
-        account: &Account,
-        oracle_price: u64,
-    ) -> (u128, bool) {
-        let abs_pos = saturating_abs_i128(account.position_size.get()) as u128;
-        if abs_pos == 0 {
-            return (0, false);
+    /// Capacity is the MTM equity over the LP's initial margin; other limits (skew,
+    /// utilization, owner caps) may bind sooner and are reported by their own getters.
+    pub fn lp_inventory(&self, idx: u16, oracle_price: u64) -> Result<LpInventory> {
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return Err(RiskError::AccountNotFound);
+        }
+        let account = &self.accounts[idx as usize];
+        if !account.is_lp() {
+            return Err(RiskError::NotAnLPAccount);
+        }
+        let position = account.position_size.get();
+        let notional = self.notional_at(position.unsigned_abs(), oracle_price, Rounding::Up);
+        let equity = self.account_equity_mtm_at_oracle(account, oracle_price);
+        let equity = self.collateral_value(equity);
+        let im_bps = core::cmp::max(self.initial_margin_bps_for(account), 1);
+        let max_notional = mul_div(equity, 10_000, im_bps as u128, Rounding::Down);
+        let utilization_bps = match (notional, max_notional) {
+            (0, _) => 0,
+            (_, 0) => u64::MAX,
+            _ => {
+                let bps = mul_div(notional, 10_000, max_notional, Rounding::Up);
+                core::cmp::min(bps, u64::MAX as u128) as u64
+            }
+        };
+        let max_units = self.base_for_notional(max_notional, oracle_price, Rounding::Down);
+        let max_units_i = u128_to_i128_clamped(max_units);
+        Ok(LpInventory {
+            position,
+            notional,
+            max_notional,
+            utilization_bps,
+            buy_remaining: max_units_i.saturating_add(position).max(0) as u128,
+            sell_remaining: max_units_i.saturating_sub(position).max(0) as u128,
+        })
+    }
+
+    // ========================================
+    // LP Shares
+    // ========================================
+
+    /// Share accounting of LP account `idx`. Deposits mint shares at the current
+    /// NAV per share and withdrawals burn them, so fees and inventory PnL already
+    /// accrued stay with the shares that were outstanding when they accrued.
+    ///
+    /// NAV is capital + pnl + fee credits as of the LP's last settlement; mark PnL
+    /// since then is not included.
+    pub fn lp_shares(&self, idx: u16) -> Result<LpShares> {
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return Err(RiskError::AccountNotFound);
         }
+        if !self.accounts[idx as usize].is_lp() {
+            return Err(RiskError::NotAnLPAccount);
+        }
+        let nav = self.lp_nav(idx as usize);
+        Ok(LpShares {
+            supply: effective_lp_supply(self.accounts[idx as usize].lp_shares.get(), nav),
+            nav,
+        })
+    }
 
-        // MTM equity at oracle price (fail-safe: overflow returns 0 = full liquidation)
-        let equity = self.account_equity_mtm_at_oracle(account, oracle_price);
+    /// Redeem `shares` of LP `idx` at the current NAV, withdrawing their value.
+    /// Returns the amount withdrawn.
+    pub fn withdraw_lp_shares(
+        &mut self,
+        idx: u16,
+        shares: u128,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<u128> {
+        let info = self.lp_shares(idx)?;
+        if shares > self.lp_owner_shares(idx as usize, info.supply) {
+            return Err(RiskError::InsufficientBalance);
+        }
+        let amount = info.value_of(shares);
+        self.withdraw(idx, amount, now_slot, oracle_price)?;
+        Ok(amount)
+    }
 
-        // Target margin = maintenance + buffer (in basis points)
-        let target_bps = self
-            .params
-            .maintenance_margin_bps
-            .saturating_add(self.params.liquidation_buffer_bps);
+    /// capital + pnl + fee credits, floored at zero.
+    fn lp_nav(&self, idx: usize) -> u128 {
+        let a = &self.accounts[idx];
+        let nav = u128_to_i128_clamped(a.capital.get())
+            .saturating_add(a.pnl.get())
+            .saturating_add(a.fee_credits.get());
+        if nav > 0 {
+            nav as u128
+        } else {
+            0
+        }
+    }
 
-        // Maximum safe remaining position (floor-safe calculation)
-        // abs_pos_safe_max = floor(equity * 10_000 * 1_000_000 / (oracle_price * target_bps))
-        // Rearranged to avoid intermediate overflow:
-        // abs_pos_safe_max = floor(equity * 10_000_000_000 / (oracle_price * target_bps))
-        let numerator = mul_u128(equity, 10_000_000_000);
-        let denominator = mul_u128(oracle_price as u128, target_bps as u128);
-
-        let mut abs_pos_safe_max = if denominator == 0 {
-            0 // Edge case: full liquidation if no denominator
+    /// Mint shares for `received` just credited to LP `idx` (no-op for users).
+    /// Returns the shares minted.
+    fn mint_lp_shares(&mut self, idx: u16, received: u128) -> u128 {
+        let i = idx as usize;
+        if !self.accounts[i].is_lp() {
+            return 0;
+        }
+        let pre = self.lp_nav(i).saturating_sub(received);
+        let supply = effective_lp_supply(self.accounts[i].lp_shares.get(), pre);
+        let minted = if supply == 0 {
+            // Worthless shares (and their holders' records) are written off
+            self.clear_lp_holdings(i);
+            received
         } else {
-            numerator / denominator
+            mul_div(supply, received, pre, Rounding::Down)
         };
+        self.set_lp_supply(i, supply.saturating_add(minted));
+        minted
+    }
 
-        // Clamp to current position (can't have safe max > actual position)
-        abs_pos_safe_max = core::cmp::min(abs_pos_safe_max, abs_pos);
-
-        // Conservative rounding guard: subtract 1 unit to ensure we close slightly more
-        // than mathematically required. This guarantees post-liquidation account is
-        // strictly on the safe side of the inequality despite integer truncation.
-        if abs_pos_safe_max > 0 {
-            abs_pos_safe_max -= 1;
+    /// Burn shares for `amount` just withdrawn from LP `idx` (no-op for users).
+    fn burn_lp_shares(&mut self, idx: u16, amount: u128) {
+        let i = idx as usize;
+        if !self.accounts[i].is_lp() {
+            return;
         }
+        let pre = self.lp_nav(i).saturating_add(amount);
+        let supply = effective_lp_supply(self.accounts[i].lp_shares.get(), pre);
+        let burned = core::cmp::min(supply, mul_div(supply, amount, pre, Rounding::Up));
+        self.set_lp_supply(i, supply.saturating_sub(burned));
+    }
 
-        // Required close amount
-        let close_abs = abs_pos.saturating_sub(abs_pos_safe_max);
+    // ========================================
+    // LP Withdrawal Queue
+    // ========================================
 
-        // Dust kill-switch: if remaining position would be below min, do full close
-        let remaining = abs_pos.saturating_sub(close_abs);
-        if remaining < self.params.min_liquidation_abs.get() {
-            return (abs_pos, true); // Full close
+    /// Queue `shares` of LP `idx` for release at the next withdrawal epoch
+    /// (`ExtParams::lp_withdrawal_epoch_slots`). Adds to any shares already queued.
+    pub fn request_lp_withdrawal(&mut self, idx: u16, shares: u128) -> Result<()> {
+        let result = self.request_lp_withdrawal_inner(idx, shares);
+        self.finish_mutation("request_lp_withdrawal", result.is_ok());
+        result
+    }
+
+    fn request_lp_withdrawal_inner(&mut self, idx: u16, shares: u128) -> Result<()> {
+        if self.ext_params.lp_withdrawal_epoch_slots == 0 {
+            return Err(RiskError::InvalidParams);
+        }
+        let info = self.lp_shares(idx)?;
+        let queued = self.accounts[idx as usize].lp_queued_shares.get().saturating_add(shares);
+        if queued > self.lp_owner_shares(idx as usize, info.supply) {
+            return Err(RiskError::InsufficientBalance);
         }
+        let account = &mut self.accounts[idx as usize];
+        account.lp_queued_shares = U128::new(queued);
+        Ok(())
+    }
 
-        (close_abs, close_abs == abs_pos)
+    /// Cancel LP `idx`'s queued withdrawal; already released capital stays claimable.
+    pub fn cancel_lp_withdrawal(&mut self, idx: u16) -> Result<()> {
+        let result = self.cancel_lp_withdrawal_inner(idx);
+        self.finish_mutation("cancel_lp_withdrawal", result.is_ok());
+        result
     }
 
-    /// Core helper for closing a SLICE of a position at oracle price (partial liquidation).
-    ///
-    /// Similar to oracle_close_position_core but:
-    /// - Only closes `close_abs` units of position (not the entire position)
-    /// - Computes proportional mark_pnl for the closed slice
-    /// - Entry price remains unchanged (correct for same-direction partial reduction)
-    ///
-    /// ## PnL Routing (same invariant as full close):
-    /// - mark_pnl > 0 (profit) → backed by haircut ratio h (no ADL needed)
-    /// - mark_pnl <= 0 (loss) → realized via settle_warmup_to_capital (capital path)
-    /// - Residual negative PnL (capital exhausted) → written off via set_pnl(i, 0) (spec §6.1)
-    ///
-    /// ASSUMES: Caller has already called touch_account_full() on this account.
-    fn oracle_close_position_slice_core(
-        &mut self,
-        idx: u16,
-        oracle_price: u64,
-        close_abs: u128,
-    ) -> Result<ClosedOutcome> {
-        let pos = self.accounts[idx as usize].position_size.get();
-        let current_abs_pos = saturating_abs_i128(pos) as u128;
+    fn cancel_lp_withdrawal_inner(&mut self, idx: u16) -> Result<()> {
+        self.lp_shares(idx)?;
+        self.accounts[idx as usize].lp_queued_shares = U128::ZERO;
+        Ok(())
+    }
 
-        if close_abs == 0 || current_abs_pos == 0 {
-            return Ok(ClosedOutcome {
-                abs_pos: 0,
-                mark_pnl: 0,
-                cap_before: self.accounts[idx as usize].capital.get(),
-                cap_after: self.accounts[idx as usize].capital.get(),
-                position_was_closed: false,
-            });
+    /// Pay out LP `idx`'s released capital, returning the amount to transfer.
+    pub fn claim_lp_withdrawal(&mut self, idx: u16) -> Result<u128> {
+        let result = self.claim_lp_withdrawal_inner(idx);
+        if let Ok(amount) = result {
+            if amount > 0 {
+                self.record_event(EventKind::Withdraw, idx, EVENT_NO_ACCOUNT, amount, 0, 0);
+            }
         }
+        self.finish_mutation("claim_lp_withdrawal", result.is_ok());
+        result
+    }
 
-        if close_abs >= current_abs_pos {
-            return self.oracle_close_position_core(idx, oracle_price);
+    fn claim_lp_withdrawal_inner(&mut self, idx: u16) -> Result<u128> {
+        self.lp_shares(idx)?;
+        let amount = self.accounts[idx as usize].lp_claimable.get();
+        if amount > self.vault.get() {
+            return Err(RiskError::InsufficientBalance);
         }
+        self.accounts[idx as usize].lp_claimable = U128::ZERO;
+        self.lp_claimable_total = self.lp_claimable_total.saturating_sub(amount);
+        self.vault = self.vault.saturating_sub(amount);
+        Ok(amount)
+    }
 
-        let entry = self.accounts[idx as usize].entry_price;
-        let cap_before = self.accounts[idx as usize].capital.get();
+    /// At the first crank of a new epoch, release queued LP withdrawals. Each LP
+    /// gets the value of its queued shares, capped at the capital not needed for
+    /// initial margin on its position at `oracle_price` and at the headroom left
+    /// below the soft utilization band; the rest stays queued.
+    fn settle_lp_withdrawal_epoch(&mut self, now_slot: u64, oracle_price: u64) {
+        let len = self.ext_params.lp_withdrawal_epoch_slots;
+        if len == 0 {
+            return;
+        }
+        let start = window_start_slot(now_slot, len);
+        if start <= self.lp_epoch_start_slot {
+            return;
+        }
+        self.lp_epoch_start_slot = start;
+        let mut headroom = self.lp_withdrawal_headroom(oracle_price);
 
-        let diff: i128 = if pos > 0 {
-            (oracle_price as i128).saturating_sub(entry as i128)
-        } else {
-            (entry as i128).saturating_sub(oracle_price as i128)
-        };
+        for idx in 0..MAX_ACCOUNTS {
+            if !self.is_used(idx) || !self.accounts[idx].is_lp() {
+                continue;
+            }
+            let owner_queued = self.accounts[idx].lp_queued_shares.get();
+            let holders_queued = self.lp_holders_queued(idx);
+            if owner_queued == 0 && holders_queued == 0 {
+                continue;
+            }
+            let account = &self.accounts[idx];
+            let nav = self.lp_nav(idx);
+            let supply = effective_lp_supply(account.lp_shares.get(), nav);
+            let owner_queued = core::cmp::min(owner_queued, self.lp_owner_shares(idx, supply));
+            let queued = owner_queued.saturating_add(holders_queued);
+            if queued == 0 || supply == 0 {
+                continue;
+            }
+            let owed = mul_div(queued, nav, supply, Rounding::Down);
 
-        let mark_pnl = match diff
-            .checked_mul(close_abs as i128)
-            .and_then(|v| v.checked_div(1_000_000))
-        {
-            Some(pnl) => pnl,
-            None => -u128_to_i128_clamped(cap_before),
-        };
+            let pos_value = self.notional_at(
+                account.position_size.unsigned_abs(),
+                oracle_price,
+                Rounding::Up,
+            );
+            let initial = self.margin_required(pos_value, self.initial_margin_bps_for(account));
+            let equity = self.account_equity_mtm_at_oracle(account, oracle_price);
+            let free = core::cmp::min(account.capital.get(), equity.saturating_sub(initial));
+            let release = core::cmp::min(core::cmp::min(owed, free), headroom);
+            let burned = if release == owed {
+                queued
+            } else {
+                mul_div(queued, release, owed, Rounding::Up)
+            };
 
-        // Apply mark PnL via set_pnl (maintains pnl_pos_tot aggregate)
-        let new_pnl = self.accounts[idx as usize].pnl.get().saturating_add(mark_pnl);
-        self.set_pnl(idx as usize, new_pnl);
+            // Locked capital stays queued unless early exit is allowed
+            let Ok(exit_fee) = self.lp_exit_fee(idx, release, now_slot) else {
+                continue;
+            };
+            let payout = release.saturating_sub(exit_fee);
+            headroom = headroom.saturating_sub(payout);
+
+            let capital = account.capital.get();
+            self.set_capital(idx, capital.saturating_sub(release));
+            // Holders take their pro-rata part; the owner takes the rest
+            let (holders_burned, holders_paid) =
+                self.release_lp_holder_queue(idx, queued, burned, payout);
+            let owner_burned = burned.saturating_sub(holders_burned);
+            let owner_paid = payout.saturating_sub(holders_paid);
+            self.set_lp_supply(idx, supply.saturating_sub(burned));
+            let account = &mut self.accounts[idx];
+            account.lp_queued_shares = U128::new(owner_queued.saturating_sub(owner_burned));
+            account.lp_claimable = account.lp_claimable.saturating_add(owner_paid);
+            self.lp_claimable_total = self.lp_claimable_total.saturating_add(payout);
+            self.distribute_exit_fee(exit_fee, idx);
+        }
+    }
 
-        // Update position
-        let new_abs_pos = current_abs_pos.saturating_sub(close_abs);
-        self.accounts[idx as usize].position_size = if pos > 0 {
-            I128::new(new_abs_pos as i128)
-        } else {
-            I128::new(-(new_abs_pos as i128))
-        };
+    // ========================================
+    // LP Lockup
+    // ========================================
 
-        // Update OI
-        self.total_open_interest = self.total_open_interest - close_abs;
+    /// Restart LP `idx`'s lockup at a deposit (no-op for users or without a lockup).
+    fn extend_lp_lockup(&mut self, idx: u16, now_slot: u64) {
+        let lockup = self.ext_params.lp_lockup_slots;
+        let account = &mut self.accounts[idx as usize];
+        if lockup == 0 || !account.is_lp() {
+            return;
+        }
+        account.lp_locked_until_slot = core::cmp::max(
+            account.lp_locked_until_slot,
+            now_slot.saturating_add(lockup),
+        );
+    }
 
-        // Update LP aggregates if LP
-        if self.accounts[idx as usize].is_lp() {
-            let new_pos = self.accounts[idx as usize].position_size.get();
-            self.net_lp_pos = self.net_lp_pos - pos + new_pos;
-            self.lp_sum_abs = self.lp_sum_abs - close_abs;
+    /// Early-exit fee on `amount` leaving LP `idx` at `now_slot` (0 once unlocked).
+    /// `LpLocked` if the capital is locked and early exit is disabled.
+    fn lp_exit_fee(&self, idx: usize, amount: u128, now_slot: u64) -> Result<u128> {
+        let account = &self.accounts[idx];
+        if !account.is_lp() || now_slot >= account.lp_locked_until_slot {
+            return Ok(0);
+        }
+        let bps = self.ext_params.lp_early_exit_fee_bps;
+        if bps == 0 {
+            return Err(RiskError::LpLocked);
         }
+        Ok(mul_div(amount, bps as u128, 10_000, Rounding::Up))
+    }
 
-        // Settle warmup (loss settlement + profit conversion per spec §6)
-        self.settle_warmup_to_capital(idx)?;
+    /// Largest amount LP `idx` can withdraw at `now_slot` with the early-exit fee
+    /// charged on top still within `gross`.
+    fn lp_exit_net_amount(&self, idx: usize, gross: u128, now_slot: u64) -> Result<u128> {
+        if self.lp_exit_fee(idx, gross, now_slot)? == 0 {
+            return Ok(gross);
+        }
+        let bps = self.ext_params.lp_early_exit_fee_bps as u128;
+        Ok(mul_div(gross, 10_000, 10_000u128.saturating_add(bps), Rounding::Down))
+    }
 
-        // Write off residual negative PnL (capital exhausted) per spec §6.1
-        if self.accounts[idx as usize].pnl.is_negative() {
-            self.set_pnl(idx as usize, 0);
+    /// Credit an early-exit fee (already debited from `exiting_idx`) to the other
+    /// LPs pro rata to capital; rounding dust, or the whole fee if there are no
+    /// other LPs, goes to insurance.
+    fn distribute_exit_fee(&mut self, fee: u128, exiting_idx: usize) {
+        if fee == 0 {
+            return;
+        }
+        let mut lp_capital_total = 0u128;
+        self.for_each_used(|idx, account| {
+            if account.is_lp() && idx != exiting_idx {
+                lp_capital_total = add_u128(lp_capital_total, account.capital.get());
+            }
+        });
+        let mut distributed = 0u128;
+        for idx in 0..MAX_ACCOUNTS {
+            if lp_capital_total == 0 {
+                break;
+            }
+            if !self.is_used(idx) || !self.accounts[idx].is_lp() || idx == exiting_idx {
+                continue;
+            }
+            let cap = self.accounts[idx].capital.get();
+            let share = mul_div(fee, cap, lp_capital_total, Rounding::Down);
+            if share > 0 {
+                self.set_capital(idx, add_u128(cap, share));
+                let lp_pnl = &mut self.accounts[idx].lp_pnl;
+                lp_pnl.exit_fees_received = lp_pnl.exit_fees_received.saturating_add(share);
+                distributed = add_u128(distributed, share);
+            }
         }
+        self.insurance_fund.balance = self
+            .insurance_fund
+            .balance
+            .saturating_add(fee.saturating_sub(distributed));
+    }
 
-        let cap_after = self.accounts[idx as usize].capital.get();
+    // ========================================
+    // LP Performance Fee
+    // ========================================
 
-        Ok(ClosedOutcome {
-            abs_pos: close_abs,
-            mark_pnl,
-            cap_before,
-            cap_after,
-            position_was_closed: true,
-        })
+    /// At the first crank of a new performance epoch, skim
+    /// `ExtParams::lp_performance_fee_bps` of each LP's gain in NAV per share above
+    /// its high-water mark from capital to the insurance fund, then raise the mark
+    /// to the post-fee price. The first assessment only sets the mark.
+    fn assess_lp_performance_fees(&mut self, now_slot: u64) {
+        let bps = self.ext_params.lp_performance_fee_bps;
+        let len = self.epoch_len_or_shared(self.ext_params.lp_performance_epoch_slots);
+        if bps == 0 || len == 0 {
+            return;
+        }
+        let start = window_start_slot(now_slot, len);
+        if start <= self.lp_performance_epoch_start_slot {
+            return;
+        }
+        self.lp_performance_epoch_start_slot = start;
+
+        for idx in 0..MAX_ACCOUNTS {
+            if !self.is_used(idx) || !self.accounts[idx].is_lp() {
+                continue;
+            }
+            let nav = self.lp_nav(idx);
+            let supply = effective_lp_supply(self.accounts[idx].lp_shares.get(), nav);
+            if supply == 0 {
+                continue;
+            }
+            let price = mul_div(nav, LP_SHARE_PRICE_SCALE, supply, Rounding::Down);
+            let mark = self.accounts[idx].lp_high_water_mark.get();
+            if mark == 0 || price <= mark {
+                if mark == 0 {
+                    self.accounts[idx].lp_high_water_mark = U128::new(price);
+                }
+                continue;
+            }
+            let profit = mul_div(
+                price.saturating_sub(mark),
+                supply,
+                LP_SHARE_PRICE_SCALE,
+                Rounding::Down,
+            );
+            let capital = self.accounts[idx].capital.get();
+            let fee = core::cmp::min(
+                capital,
+                mul_div(profit, bps as u128, 10_000, Rounding::Down),
+            );
+            self.set_capital(idx, capital.saturating_sub(fee));
+            self.insurance_fund.balance = self.insurance_fund.balance.saturating_add(fee);
+            self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue.saturating_add(fee);
+            self.revenue.lp_performance_fees = self.revenue.lp_performance_fees.saturating_add(fee);
+
+            let post = mul_div(self.lp_nav(idx), LP_SHARE_PRICE_SCALE, supply, Rounding::Down);
+            self.set_lp_supply(idx, supply);
+            let account = &mut self.accounts[idx];
+            account.lp_high_water_mark = U128::new(core::cmp::max(mark, post));
+        }
     }
 
-    /// Core helper for oracle-price full position close (spec §6).
-    ///
-    /// Applies mark PnL, closes position, settles warmup, writes off unpayable loss.
-    /// No ADL needed — undercollateralization is reflected via haircut ratio h.
-    ///
-    /// ASSUMES: Caller has already called touch_account_full() on this account.
-    fn oracle_close_position_core(&mut self, idx: u16, oracle_price: u64) -> Result<ClosedOutcome> {
-        if self.accounts[idx as usize].position_size.is_zero() {
-            return Ok(ClosedOutcome {
-                abs_pos: 0,
-                mark_pnl: 0,
-                cap_before: self.accounts[idx as usize].capital.get(),
-                cap_after: self.accounts[idx as usize].capital.get(),
-                position_was_closed: false,
-            });
+    // ========================================
+    // Markout Surcharge
+    // ========================================
+
+    /// Trading fee surcharge in bps owed by taker `idx`: `ExtParams::markout_surcharge_bps`
+    /// if its average markout is at least `markout_toxic_bps`, else 0.
+    pub fn markout_surcharge_bps(&self, idx: u16) -> Result<u64> {
+        if !self.is_used(idx as usize) {
+            return Err(RiskError::AccountNotFound);
+        }
+        let ext = &self.ext_params;
+        if ext.markout_horizon_slots == 0 || ext.markout_surcharge_bps == 0 {
+            return Ok(0);
+        }
+        let stats = &self.accounts[idx as usize].markout;
+        if stats.notional_sum.is_zero() || stats.score_bps() < ext.markout_toxic_bps as i128 {
+            return Ok(0);
         }
+        Ok(ext.markout_surcharge_bps)
+    }
 
-        let pos = self.accounts[idx as usize].position_size.get();
-        let abs_pos = saturating_abs_i128(pos) as u128;
-        let entry = self.accounts[idx as usize].entry_price;
-        let cap_before = self.accounts[idx as usize].capital.get();
+    /// Start tracking a taker fill unless another is already pending.
+    fn record_markout_fill(&mut self, idx: usize, size: i128, price: u64, now_slot: u64) {
+        let stats = &mut self.accounts[idx].markout;
+        if self.ext_params.markout_horizon_slots == 0 || !stats.pending_size.is_zero() {
+            return;
+        }
+        stats.pending_size = I128::new(size);
+        stats.pending_price = price;
+        stats.pending_slot = now_slot;
+    }
 
-        let mark_pnl = match Self::mark_pnl_for_position(pos, entry, oracle_price) {
-            Ok(pnl) => pnl,
-            Err(_) => -u128_to_i128_clamped(cap_before),
+    /// Mark out account `idx`'s pending fill at `oracle_price` once the horizon has
+    /// passed, decaying the statistics at window boundaries first.
+    fn mature_markout(&mut self, idx: usize, now_slot: u64, oracle_price: u64) {
+        let horizon = self.ext_params.markout_horizon_slots;
+        let window = self.ext_params.markout_window_slots;
+        let stats = self.accounts[idx].markout;
+        let size = stats.pending_size.get();
+        if horizon == 0 || size == 0 || now_slot < stats.pending_slot.saturating_add(horizon) {
+            return;
+        }
+        let Ok(markout) = self.mark_pnl(size, stats.pending_price, oracle_price) else {
+            return;
         };
+        let notional = self.notional_at(size.unsigned_abs(), stats.pending_price, Rounding::Up);
 
-        // Apply mark PnL via set_pnl (maintains pnl_pos_tot aggregate)
-        let new_pnl = self.accounts[idx as usize].pnl.get().saturating_add(mark_pnl);
-        self.set_pnl(idx as usize, new_pnl);
+        let stats = &mut self.accounts[idx].markout;
+        if window > 0 && now_slot >= stats.window_start_slot.saturating_add(window) {
+            stats.markout_sum = I128::new(stats.markout_sum.get() / 2);
+            stats.notional_sum = U128::new(stats.notional_sum.get() / 2);
+            stats.window_start_slot = window_start_slot(now_slot, window);
+        }
+        stats.markout_sum = stats.markout_sum.saturating_add(markout);
+        stats.notional_sum = stats.notional_sum.saturating_add(notional);
+        stats.pending_size = I128::ZERO;
+    }
 
-        // Close position
-        self.accounts[idx as usize].position_size = I128::ZERO;
-        self.accounts[idx as usize].entry_price = oracle_price;
+    /// Credit realized mark PnL to an LP's inventory attribution (no-op for users).
+    #[inline]
+    fn attribute_lp_inventory_pnl(&mut self, idx: usize, mark: i128) {
+        if self.accounts[idx].is_lp() {
+            let lp_pnl = &mut self.accounts[idx].lp_pnl;
+            lp_pnl.inventory_pnl = lp_pnl.inventory_pnl.saturating_add(mark);
+        }
+    }
 
-        // Update OI
-        self.total_open_interest = self.total_open_interest - abs_pos;
+    // ========================================
+    // Oracle Deviation Monitor
+    // ========================================
 
-        // Update LP aggregates if LP
-        if self.accounts[idx as usize].is_lp() {
-            self.net_lp_pos = self.net_lp_pos - pos;
-            self.lp_sum_abs = self.lp_sum_abs - abs_pos;
+    /// Deviation statistics for the current and last completed window.
+    pub fn oracle_deviation(&self) -> &OracleDeviationMonitor {
+        &self.oracle_deviation
+    }
+
+    /// Start a new deviation window if `now_slot` is past the current one.
+    /// Returns false when the monitor is disabled.
+    fn roll_deviation_window(&mut self, now_slot: u64) -> bool {
+        let len = self.ext_params.deviation_window_slots;
+        if len == 0 {
+            return false;
+        }
+        let start = window_start_slot(now_slot, len);
+        let current = self.oracle_deviation.current;
+        if start > current.window_start_slot {
+            self.oracle_deviation.previous =
+                if current.window_start_slot.saturating_add(len) == start {
+                    current
+                } else {
+                    DeviationWindow {
+                        window_start_slot: start.saturating_sub(len),
+                        ..DeviationWindow::default()
+                    }
+                };
+            self.oracle_deviation.current = DeviationWindow {
+                window_start_slot: start,
+                ..DeviationWindow::default()
+            };
         }
+        true
+    }
 
-        // Settle warmup (loss settlement + profit conversion per spec §6)
-        self.settle_warmup_to_capital(idx)?;
+    /// Record the move from the previous crank price; alert past the threshold.
+    fn observe_crank_deviation(&mut self, caller_idx: u16, now_slot: u64, oracle_price: u64) {
+        if !self.roll_deviation_window(now_slot) {
+            return;
+        }
+        let last = self.oracle_deviation.last_crank_price;
+        self.oracle_deviation.last_crank_price = oracle_price;
+        if last == 0 {
+            return;
+        }
+        let bps = deviation_bps(oracle_price, last, last);
+        let window = &mut self.oracle_deviation.current;
+        window.crank_samples = window.crank_samples.saturating_add(1);
+        window.crank_max_bps = core::cmp::max(window.crank_max_bps, bps);
+        window.crank_sum_bps = window.crank_sum_bps.saturating_add(bps as u128);
+        let threshold = self.ext_params.crank_deviation_alert_bps;
+        if threshold > 0 && bps > threshold {
+            self.record_event(
+                EventKind::OracleDeviation,
+                caller_idx,
+                EVENT_NO_ACCOUNT,
+                0,
+                bps as i128,
+                oracle_price,
+            );
+        }
+    }
 
-        // Write off residual negative PnL (capital exhausted) per spec §6.1
-        if self.accounts[idx as usize].pnl.is_negative() {
-            self.set_pnl(idx as usize, 0);
+    /// Record the gap between an execution price and the oracle; alert past the threshold.
+    fn observe_exec_deviation(
+        &mut self,
+        user_idx: u16,
+        lp_idx: u16,
+        now_slot: u64,
+        exec_price: u64,
+        oracle_price: u64,
+    ) {
+        if !self.roll_deviation_window(now_slot) {
+            return;
+        }
+        let bps = deviation_bps(exec_price, oracle_price, oracle_price);
+        let window = &mut self.oracle_deviation.current;
+        window.exec_samples = window.exec_samples.saturating_add(1);
+        window.exec_max_bps = core::cmp::max(window.exec_max_bps, bps);
+        window.exec_sum_bps = window.exec_sum_bps.saturating_add(bps as u128);
+        let threshold = self.ext_params.exec_deviation_alert_bps;
+        if threshold > 0 && bps > threshold {
+            self.record_event(
+                EventKind::OracleDeviation,
+                user_idx,
+                lp_idx,
+                1,
+                bps as i128,
+                exec_price,
+            );
+        }
+    }
+
+    // ========================================
+    // Force-Close Grace
+    // ========================================
+
+    /// Slot from which the crank may force-close `idx` over the max-PnL cap, if a
+    /// force-close is pending (see `ExtParams::force_close_grace_slots`).
+    pub fn force_close_pending(&self, idx: u16) -> Option<u64> {
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return None;
+        }
+        match self.accounts[idx as usize].force_close_deadline_slot {
+            0 => None,
+            deadline => Some(deadline),
+        }
+    }
+
+    /// Whether the crank may force-close `idx` over the max-PnL cap now. The first
+    /// time it is seen over the cap, the grace period starts and is announced.
+    fn force_close_grace_elapsed(
+        &mut self,
+        idx: usize,
+        now_slot: u64,
+        total_pnl: i128,
+        oracle_price: u64,
+    ) -> bool {
+        let grace = self.ext_params.force_close_grace_slots;
+        if grace == 0 || self.accounts[idx].adl_first != 0 {
+            return true;
         }
+        let deadline = self.accounts[idx].force_close_deadline_slot;
+        if deadline != 0 {
+            return now_slot >= deadline;
+        }
+        let deadline = now_slot.saturating_add(grace);
+        self.accounts[idx].force_close_deadline_slot = deadline;
+        self.record_event(
+            EventKind::ForceClosePending,
+            idx as u16,
+            EVENT_NO_ACCOUNT,
+            deadline as u128,
+            total_pnl,
+            oracle_price,
+        );
+        false
+    }
 
-        let cap_after = self.accounts[idx as usize].capital.get();
+    // ========================================
+    // Gap Insurance
+    // ========================================
 
-        Ok(ClosedOutcome {
-            abs_pos,
-            mark_pnl,
-            cap_before,
-            cap_after,
-            position_was_closed: true,
-        })
+    /// Gap-insurance pool, open gap window and pending claims.
+    pub fn gap_insurance(&self) -> &GapInsurance {
+        &self.gap_insurance
     }
 
-    /// Liquidate a single account at oracle price if below maintenance margin.
+    /// Track single-print oracle gaps from the first crank of each slot.
     ///
-    /// Returns Ok(true) if liquidation occurred, Ok(false) if not needed/possible.
-    /// Per spec: close position, settle losses, write off unpayable PnL, charge fee.
-    /// No ADL — haircut ratio h reflects any undercollateralization.
-    pub fn liquidate_at_oracle(
-        &mut self,
-        idx: u16,
-        now_slot: u64,
-        oracle_price: u64,
-    ) -> Result<bool> {
-        self.current_slot = now_slot;
+    /// A crank price at least `oracle_gap_bps` away from the previous crank price opens a
+    /// gap window. If a later crank within `gap_revert_slots` prints back within
+    /// `gap_revert_tolerance_bps` of the pre-gap price, the window's claims are paid;
+    /// otherwise they lapse and a new gap may open.
+    fn observe_oracle_gap(&mut self, now_slot: u64, oracle_price: u64) {
+        let gap_bps = self.ext_params.oracle_gap_bps;
+        let revert_slots = self.ext_params.gap_revert_slots;
+        if gap_bps == 0 || revert_slots == 0 {
+            return;
+        }
+        let last = self.gap_insurance.last_price;
+        if last != 0 && now_slot <= self.gap_insurance.last_slot {
+            return;
+        }
+        self.gap_insurance.last_price = oracle_price;
+        self.gap_insurance.last_slot = now_slot;
+
+        let pre_gap = self.gap_insurance.pre_gap_price;
+        if pre_gap != 0 {
+            let tolerance = self.ext_params.gap_revert_tolerance_bps;
+            if deviation_bps(oracle_price, pre_gap, pre_gap) <= tolerance {
+                self.pay_gap_claims(oracle_price);
+                return;
+            }
+            if now_slot <= self.gap_insurance.gap_slot.saturating_add(revert_slots) {
+                return;
+            }
+            self.gap_insurance.claims = [GapClaim::default(); MAX_GAP_CLAIMS];
+            self.gap_insurance.pre_gap_price = 0;
+        }
+        if last != 0 && deviation_bps(oracle_price, last, last) >= gap_bps {
+            self.gap_insurance.gap_slot = now_slot;
+            self.gap_insurance.pre_gap_price = last;
+        }
+    }
 
-        if (idx as usize) >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
-            return Ok(false);
+    /// Equity of `idx` at the pre-gap price, if this crank is the gap print and the
+    /// account was above maintenance there (i.e. a liquidation now is due to the gap).
+    fn gap_print_equity(&self, idx: usize, now_slot: u64) -> Option<u128> {
+        let gap = &self.gap_insurance;
+        if gap.pre_gap_price == 0 || gap.gap_slot != now_slot {
+            return None;
+        }
+        let account = &self.accounts[idx];
+        if !self.is_above_maintenance_margin_mtm(account, gap.pre_gap_price) {
+            return None;
         }
+        Some(self.account_equity_mtm_at_oracle(account, gap.pre_gap_price))
+    }
 
-        if oracle_price == 0 || oracle_price > MAX_ORACLE_PRICE {
-            return Err(RiskError::Overflow);
+    /// File a claim for the equity `idx` lost to a gap-print liquidation.
+    fn record_gap_claim(&mut self, idx: usize, pre_gap_equity: u128, oracle_price: u64) {
+        let equity = self.account_equity_mtm_at_oracle(&self.accounts[idx], oracle_price);
+        let loss = pre_gap_equity.saturating_sub(equity);
+        if loss == 0 {
+            return;
         }
+        let account_id = self.accounts[idx].account_id;
+        if let Some(claim) = self.gap_insurance.claims.iter_mut().find(|c| c.loss.is_zero()) {
+            *claim = GapClaim { idx: idx as u16, account_id, loss: U128::new(loss) };
+        }
+    }
 
-        if self.accounts[idx as usize].position_size.is_zero() {
-            return Ok(false);
+    /// Pay the open window's claims in filing order from the pool, then close it.
+    fn pay_gap_claims(&mut self, oracle_price: u64) {
+        let claims = self.gap_insurance.claims;
+        for claim in claims.iter().filter(|c| !c.loss.is_zero()) {
+            let idx = claim.idx as usize;
+            if idx >= MAX_ACCOUNTS
+                || !self.is_used(idx)
+                || self.accounts[idx].account_id != claim.account_id
+            {
+                continue;
+            }
+            let available = core::cmp::min(
+                self.gap_insurance.pool.get(),
+                self.insurance_fund.balance.get(),
+            );
+            let pay = core::cmp::min(claim.loss.get(), available);
+            if pay == 0 {
+                break;
+            }
+            let new_cap = add_u128(self.accounts[idx].capital.get(), pay);
+            self.set_capital(idx, new_cap);
+            self.insurance_fund.balance = self.insurance_fund.balance.saturating_sub(pay);
+            self.gap_insurance.pool = self.gap_insurance.pool.saturating_sub(pay);
+            self.gap_insurance.total_paid = self.gap_insurance.total_paid.saturating_add(pay);
+            self.record_event(
+                EventKind::GapCompensation,
+                claim.idx,
+                EVENT_NO_ACCOUNT,
+                pay,
+                u128_to_i128_clamped(claim.loss.get()),
+                oracle_price,
+            );
         }
+        self.gap_insurance.claims = [GapClaim::default(); MAX_GAP_CLAIMS];
+        self.gap_insurance.pre_gap_price = 0;
+    }
 
-        // Settle funding + mark-to-market + best-effort fees
-        self.touch_account_for_liquidation(idx, now_slot, oracle_price)?;
+    // ========================================
+    // Haircut Claims
+    // ========================================
 
-        let account = &self.accounts[idx as usize];
-        if self.is_above_maintenance_margin_mtm(account, oracle_price) {
-            return Ok(false);
+    /// Insurance balance above everything it is earmarked for: the risk-reduction
+    /// threshold, the insurance target at `oracle_price` and the gap-insurance pool.
+    fn insurance_surplus(&self, oracle_price: u64) -> u128 {
+        let floor = core::cmp::max(
+            self.params.risk_reduction_threshold.get(),
+            self.insurance_target(oracle_price).unwrap_or(0),
+        );
+        self.insurance_fund
+            .balance
+            .get()
+            .saturating_sub(floor)
+            .saturating_sub(self.gap_insurance.pool.get())
+    }
+
+    /// Pay down `idx`'s haircut claim with up to `haircut_claim_payout_bps` of the
+    /// insurance surplus. Insurance moves to capital, so the vault is unchanged.
+    fn pay_haircut_claim(&mut self, idx: usize, oracle_price: u64) {
+        let bps = self.ext_params.haircut_claim_payout_bps;
+        let claim = self.accounts[idx].haircut_claim.get();
+        if bps == 0 || claim == 0 {
+            return;
         }
+        let surplus = self.insurance_surplus(oracle_price);
+        let budget = mul_div(surplus, bps as u128, 10_000, Rounding::Down);
+        let pay = core::cmp::min(claim, budget);
+        if pay == 0 {
+            return;
+        }
+        let new_cap = add_u128(self.accounts[idx].capital.get(), pay);
+        self.set_capital(idx, new_cap);
+        self.insurance_fund.balance = self.insurance_fund.balance.saturating_sub(pay);
+        self.accounts[idx].haircut_claim = U128::new(claim.saturating_sub(pay));
+        self.record_epoch_drain(idx, pay, 0);
+        self.record_event(
+            EventKind::HaircutClaimPaid,
+            idx as u16,
+            EVENT_NO_ACCOUNT,
+            pay,
+            u128_to_i128_clamped(claim.saturating_sub(pay)),
+            oracle_price,
+        );
+    }
 
-        let (close_abs, is_full_close) =
-            self.compute_liquidation_close_amount(account, oracle_price);
+    // ========================================
+    // Liquidation Analytics
+    // ========================================
 
-        if close_abs == 0 {
-            return Ok(false);
+    /// Liquidation counters for the current and last completed epoch.
+    pub fn liquidation_analytics(&self) -> &LiquidationAnalytics {
+        &self.liq_analytics
+    }
+
+    /// The shared epoch as of the last crank (all zero when `ExtParams::epoch_slots`
+    /// is unset).
+    pub fn epoch(&self) -> Epoch {
+        let len = self.ext_params.epoch_slots;
+        Epoch {
+            index: self.epoch_start_slot.checked_div(len).unwrap_or(0),
+            start_slot: if len == 0 { 0 } else { self.epoch_start_slot },
+            len,
         }
+    }
 
-        // Close position (no ADL — losses written off in close helper)
-        let mut outcome = if is_full_close {
-            self.oracle_close_position_core(idx, oracle_price)?
+    /// `len`, or the shared epoch length when `len` is 0.
+    fn epoch_len_or_shared(&self, len: u64) -> u64 {
+        if len > 0 {
+            len
         } else {
-            match self.oracle_close_position_slice_core(idx, oracle_price, close_abs) {
-                Ok(r) => r,
-                Err(RiskError::Overflow) => {
-                    self.oracle_close_position_core(idx, oracle_price)?
-                }
-                Err(e) => return Err(e),
-            }
-        };
+            self.ext_params.epoch_slots
+        }
+    }
 
-        if !outcome.position_was_closed {
-            return Ok(false);
+    /// Advance the shared epoch if `now_slot` is past the current one.
+    fn roll_epoch(&mut self, now_slot: u64) {
+        let start = window_start_slot(now_slot, self.ext_params.epoch_slots);
+        if self.ext_params.epoch_slots == 0 || start <= self.epoch_start_slot {
+            return;
         }
+        self.epoch_start_slot = start;
+        self.epoch_rollovers = self.epoch_rollovers.saturating_add(1);
+    }
 
-        // Safety check: if position remains and still below target, full close
-        if !self.accounts[idx as usize].position_size.is_zero() {
-            let target_bps = self
-                .params
-                .maintenance_margin_bps
-                .saturating_add(self.params.liquidation_buffer_bps);
-            if !self.is_above_margin_bps_mtm(&self.accounts[idx as usize], oracle_price, target_bps)
-            {
-                let fallback = self.oracle_close_position_core(idx, oracle_price)?;
-                if fallback.position_was_closed {
-                    outcome.abs_pos = outcome.abs_pos.saturating_add(fallback.abs_pos);
+    /// Reseed the engine's generator, e.g. from a recent slot hash.
+    pub fn set_rng_seed(&mut self, seed: &[u8; 32]) {
+        self.rng = Prng::from_seed_bytes(seed);
+    }
+
+    /// Next value from the engine's generator.
+    pub fn next_random(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    /// Uniform sample of used account indices (reservoir sampling, index order
+    /// scan). Fills at most `out.len()` entries and returns how many were written.
+    pub fn sample_accounts(&mut self, out: &mut [u16]) -> usize {
+        let mut rng = self.rng;
+        let mut seen = 0u64;
+        self.for_each_used(|idx, _| {
+            if (seen as usize) < out.len() {
+                out[seen as usize] = idx as u16;
+            } else {
+                let j = rng.next_below(seen.saturating_add(1)) as usize;
+                if j < out.len() {
+                    out[j] = idx as u16;
                 }
             }
-        }
+            seen = seen.saturating_add(1);
+        });
+        self.rng = rng;
+        core::cmp::min(seen as usize, out.len())
+    }
 
-        // Charge liquidation fee (from remaining capital → insurance)
-        // Use ceiling division for consistency with trade fees
-        let notional = mul_u128(outcome.abs_pos, oracle_price as u128) / 1_000_000;
-        let fee_raw = if notional > 0 && self.params.liquidation_fee_bps > 0 {
-            (mul_u128(notional, self.params.liquidation_fee_bps as u128) + 9999) / 10_000
+    /// Start a new analytics epoch if `now_slot` is past the current one.
+    fn roll_liq_epoch(&mut self, now_slot: u64) {
+        let len = self.epoch_len_or_shared(self.ext_params.liq_stats_epoch_slots);
+        if len == 0 {
+            return;
+        }
+        let start = window_start_slot(now_slot, len);
+        let current = self.liq_analytics.current;
+        if start <= current.epoch_start_slot {
+            return;
+        }
+        // An idle gap of more than one epoch leaves an empty previous epoch
+        self.liq_analytics.previous = if current.epoch_start_slot.saturating_add(len) == start {
+            current
         } else {
-            0
+            LiquidationStats {
+                epoch_start_slot: start.saturating_sub(len),
+                ..LiquidationStats::default()
+            }
         };
-        let fee = core::cmp::min(fee_raw, self.params.liquidation_fee_cap.get());
-        let account_capital = self.accounts[idx as usize].capital.get();
-        let pay = core::cmp::min(fee, account_capital);
+        self.liq_analytics.current = LiquidationStats {
+            epoch_start_slot: start,
+            ..LiquidationStats::default()
+        };
+    }
 
-        self.set_capital(idx as usize, account_capital.saturating_sub(pay));
-        self.insurance_fund.balance = self.insurance_fund.balance.saturating_add_u128(U128::new(pay));
-        self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue.saturating_add_u128(U128::new(pay));
+    /// Force-realize the open positions of `adl_first` accounts in `range`, up to
+    /// `budget` closes. Returns (closed, errors).
+    fn force_realize_adl_first(
+        &mut self,
+        range: core::ops::Range<usize>,
+        now_slot: u64,
+        oracle_price: u64,
+        budget: u16,
+    ) -> (u16, u16) {
+        let mut closed: u16 = 0;
+        let mut errors: u16 = 0;
+        for idx in range {
+            if closed >= budget {
+                break;
+            }
+            let account = &self.accounts[idx];
+            if !self.is_used(idx) || account.adl_first == 0 || account.position_size.is_zero() {
+                continue;
+            }
+            let pos = account.position_size.get();
+            let ok = self
+                .touch_account_for_force_realize(idx as u16, now_slot, oracle_price)
+                .is_ok()
+                && self.oracle_close_position_core(idx as u16, oracle_price).is_ok();
+            if ok {
+                self.record_force_close(idx, pos, oracle_price);
+                closed = closed.saturating_add(1);
+                self.lifetime_force_realize_closes =
+                    self.lifetime_force_realize_closes.saturating_add(1);
+                self.record_adl_event(now_slot);
+            } else {
+                errors = errors.saturating_add(1);
+            }
+        }
+        (closed, errors)
+    }
 
-        self.lifetime_liquidations = self.lifetime_liquidations.saturating_add(1);
+    fn record_force_close(&mut self, idx: usize, pos: i128, oracle_price: u64) {
+        let idx = idx as u16;
+        self.record_event(EventKind::ForceClosed, idx, EVENT_NO_ACCOUNT, 0, pos, oracle_price);
+    }
+
+    fn record_adl_event(&mut self, now_slot: u64) {
+        self.roll_liq_epoch(now_slot);
+        let stats = &mut self.liq_analytics.current;
+        stats.adl_events = stats.adl_events.saturating_add(1);
+    }
+
+    /// Add the part of `idx`'s negative PnL that its capital cannot cover to bad debt.
+    fn record_uncovered_loss(&mut self, idx: usize) {
+        self.roll_liq_epoch(self.current_slot);
+        let account = &self.accounts[idx];
+        let uncovered = u128_to_i128_clamped(account.capital.get())
+            .saturating_add(account.pnl.get());
+        if uncovered < 0 {
+            let stats = &mut self.liq_analytics.current;
+            stats.bad_debt = stats.bad_debt.saturating_add(uncovered.unsigned_abs());
+        }
+    }
+
+    /// Move `amount` from the insurance fund to LP accounts, pro-rata by LP capital.
+    ///
+    /// Vault is unchanged (value moves from I to C_tot). Rounding dust stays in
+    /// insurance. Returns the amount actually distributed (0 if there is no LP capital).
+    fn distribute_insurance_to_lps(&mut self, amount: u128, exclude_idx: Option<usize>) -> u128 {
+        if amount == 0 {
+            return 0;
+        }
+        let mut lp_capital_total = 0u128;
+        self.for_each_used(|idx, account| {
+            if account.is_lp() && Some(idx) != exclude_idx {
+                lp_capital_total = add_u128(lp_capital_total, account.capital.get());
+            }
+        });
+        if lp_capital_total == 0 {
+            return 0;
+        }
 
-        Ok(true)
+        let mut distributed = 0u128;
+        for idx in 0..MAX_ACCOUNTS {
+            if !self.is_used(idx) || !self.accounts[idx].is_lp() || Some(idx) == exclude_idx {
+                continue;
+            }
+            let cap = self.accounts[idx].capital.get();
+            let share = mul_u128(amount, cap).checked_div(lp_capital_total).unwrap_or(0);
+            if share > 0 {
+                self.set_capital(idx, add_u128(cap, share));
+                let lp_pnl = &mut self.accounts[idx].lp_pnl;
+                lp_pnl.liquidation_proceeds = lp_pnl.liquidation_proceeds.saturating_add(share);
+                distributed = add_u128(distributed, share);
+            }
+        }
+        self.insurance_fund.balance = self.insurance_fund.balance.saturating_sub(distributed);
+        distributed
     }
 
     // ========================================
@@ -2109,6 +9635,72 @@ impl RiskEngine {
         core::cmp::min(available_pnl, warmed_up_cap)
     }
 
+    /// What account `idx` will settle at its next touch at `now_slot`: funding,
+    /// maintenance and holding fees, and the warmup split of its positive PnL.
+    /// Read-only; None if the account is not in use.
+    pub fn pending_settlement(&self, idx: u16, now_slot: u64) -> Option<PendingSettlement> {
+        if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
+            return None;
+        }
+        let account = &self.accounts[idx as usize];
+        let position = account.position_size.get();
+
+        let delta_f = self
+            .funding_index_qpb_e6
+            .get()
+            .saturating_sub(account.funding_index.get());
+        let funding_owed = if delta_f == 0 || position == 0 {
+            0
+        } else {
+            let divisor = u128_to_i128_clamped(self.price_divisor());
+            account
+                .position_size
+                .checked_mul_div(delta_f, divisor, Rounding::Up)
+                .map(|p| p.get())
+                .unwrap_or(0)
+        };
+
+        let fee_slots = now_slot.saturating_sub(account.last_fee_slot);
+        let maintenance_fee = self
+            .params
+            .maintenance_fee_per_slot
+            .get()
+            .saturating_mul(fee_slots as u128);
+
+        let holding_delta = self
+            .holding_fee_index
+            .get()
+            .saturating_sub(account.holding_fee_index.get());
+        let holding_fee = if holding_delta == 0 || position == 0 || account.is_lp() {
+            0
+        } else {
+            let multiplier = self.holding_fee_multiplier(account);
+            mul_div(
+                position.unsigned_abs(),
+                holding_delta.saturating_mul(multiplier as u128),
+                self.price_divisor().saturating_mul(1_000_000_000),
+                Rounding::Up,
+            )
+        };
+
+        let available_pnl = sub_u128(
+            clamp_pos_i128(account.pnl.get()),
+            account.reserved_pnl as u128,
+        );
+        let elapsed = now_slot.saturating_sub(account.warmup_started_at_slot);
+        let warmed = mul_u128(account.warmup_slope_per_step.get(), elapsed as u128);
+        let vested_pnl = core::cmp::min(available_pnl, warmed);
+
+        Some(PendingSettlement {
+            funding_owed,
+            maintenance_fee,
+            holding_fee,
+            fee_credits: account.fee_credits.get(),
+            vested_pnl,
+            unvested_pnl: available_pnl.saturating_sub(vested_pnl),
+        })
+    }
+
     /// Update warmup slope for an account
     /// NOTE: No warmup rate cap (removed for simplicity)
     pub fn update_warmup_slope(&mut self, idx: u16) -> Result<()> {
@@ -2125,7 +9717,9 @@ impl RiskEngine {
         // Calculate slope: avail_gross / warmup_period
         // Ensure slope >= 1 when avail_gross > 0 to prevent "zero forever" bug
         let slope = if self.params.warmup_period_slots > 0 {
-            let base = avail_gross / (self.params.warmup_period_slots as u128);
+            let base = avail_gross
+                .checked_div(u128::from(self.params.warmup_period_slots))
+                .unwrap_or(0);
             if avail_gross > 0 {
                 core::cmp::max(1, base)
             } else {
@@ -2163,6 +9757,12 @@ impl RiskEngine {
     ///
     /// Anti-retroactivity guarantee: state changes at slot t can only affect funding for slots >= t.
     pub fn accrue_funding(&mut self, now_slot: u64, oracle_price: u64) -> Result<()> {
+        let result = self.accrue_funding_inner(now_slot, oracle_price);
+        self.finish_mutation("accrue_funding", result.is_ok());
+        result
+    }
+
+    fn accrue_funding_inner(&mut self, now_slot: u64, oracle_price: u64) -> Result<()> {
         let dt = now_slot.saturating_sub(self.last_funding_slot);
         if dt == 0 {
             return Ok(());
@@ -2173,12 +9773,15 @@ impl RiskEngine {
             return Err(RiskError::Overflow);
         }
 
+        // Accrue on the funding price (spot unless `funding_ema_*` is configured)
+        let oracle_price = self.smoothed_price(PriceUse::Funding, oracle_price);
+
         // Use the STORED rate (anti-retroactivity: rate was set at start of interval)
         let funding_rate = self.funding_rate_bps_per_slot_last;
 
         // Cap funding rate at 10000 bps (100%) per slot as sanity bound
         // Real-world funding rates should be much smaller (typically < 1 bps/slot)
-        if funding_rate.abs() > 10_000 {
+        if funding_rate.unsigned_abs() > 10_000 {
             return Err(RiskError::Overflow);
         }
 
@@ -2186,8 +9789,16 @@ impl RiskEngine {
             return Err(RiskError::Overflow);
         }
 
-        // Use checked math to prevent silent overflow
-        let price = oracle_price as i128;
+        // Use checked math to prevent silent overflow.
+        // Inverse markets accrue on the inverted price D² / P so that payments,
+        // divided by D at settlement, come out as (pos × D / P) × rate in collateral.
+        let price = match self.ext_params.payoff_mode {
+            PayoffMode::Linear | PayoffMode::Quanto => oracle_price as i128,
+            PayoffMode::Inverse => {
+                let divisor = self.price_divisor();
+                u128_to_i128_clamped(mul_div(divisor, divisor, oracle_price as u128, Rounding::Down))
+            }
+        };
         let rate = funding_rate as i128;
         let dt_i = dt as i128;
 
@@ -2205,6 +9816,14 @@ impl RiskEngine {
             .checked_add(delta)
             .ok_or(RiskError::Overflow)?;
 
+        let holding_rate = self.ext_params.holding_fee_per_slot_e9 as u128;
+        if holding_rate > 0 {
+            let delta = (price.unsigned_abs())
+                .saturating_mul(holding_rate)
+                .saturating_mul(dt as u128);
+            self.holding_fee_index = self.holding_fee_index.saturating_add(delta);
+        }
+
         self.last_funding_slot = now_slot;
         Ok(())
     }
@@ -2216,8 +9835,23 @@ impl RiskEngine {
     ///
     /// This implements the "rate-change rule" from the spec: state changes at slot t
     /// can only affect funding for slots >= t.
+    ///
+    /// If `ExtParams::max_funding_rate_bps_per_slot` is set, the rate is clamped to it.
     pub fn set_funding_rate_for_next_interval(&mut self, new_rate_bps_per_slot: i64) {
-        self.funding_rate_bps_per_slot_last = new_rate_bps_per_slot;
+        self.store_funding_rate(new_rate_bps_per_slot);
+        self.bump_state_seq();
+    }
+
+    /// Store the next-interval funding rate (clamped) without bumping `state_seq`;
+    /// for callers that commit through their own `finish_mutation`.
+    fn store_funding_rate(&mut self, new_rate_bps_per_slot: i64) {
+        let cap = self.ext_params.max_funding_rate_bps_per_slot;
+        self.funding_rate_bps_per_slot_last = if cap > 0 {
+            let cap = cap.min(i64::MAX as u64) as i64;
+            new_rate_bps_per_slot.clamp(cap.saturating_neg(), cap)
+        } else {
+            new_rate_bps_per_slot
+        };
     }
 
     /// Convenience: Set rate then accrue in one call.
@@ -2231,8 +9865,11 @@ impl RiskEngine {
         oracle_price: u64,
         funding_rate_bps_per_slot: i64,
     ) -> Result<()> {
-        self.set_funding_rate_for_next_interval(funding_rate_bps_per_slot);
-        self.accrue_funding(now_slot, oracle_price)
+        self.store_funding_rate(funding_rate_bps_per_slot);
+        let result = self.accrue_funding_inner(now_slot, oracle_price);
+        // The new rate is stored even if the accrual fails.
+        self.finish_mutation("accrue_funding_with_rate", true);
+        result
     }
 
     /// Settle funding for an account (lazy update).
@@ -2246,25 +9883,15 @@ impl RiskEngine {
             .ok_or(RiskError::Overflow)?;
 
         if delta_f != 0 && !account.position_size.is_zero() {
-            // payment = position × ΔF / 1e6
-            // Round UP for positive payments (account pays), truncate for negative (account receives)
-            // This ensures vault always has at least what's owed (one-sided conservation slack).
-            let raw = account
+            // payment = position × ΔF / price_divisor, rounded toward +∞:
+            // an account paying pays at least the theoretical amount, an account
+            // receiving gets at most it (one-sided conservation slack).
+            let divisor = u128_to_i128_clamped(self.price_divisor());
+            let payment = account
                 .position_size
-                .get()
-                .checked_mul(delta_f)
-                .ok_or(RiskError::Overflow)?;
-
-            let payment = if raw > 0 {
-                // Account is paying: round UP to ensure vault gets at least theoretical amount
-                raw.checked_add(999_999)
-                    .ok_or(RiskError::Overflow)?
-                    .checked_div(1_000_000)
-                    .ok_or(RiskError::Overflow)?
-            } else {
-                // Account is receiving: truncate towards zero to give at most theoretical amount
-                raw.checked_div(1_000_000).ok_or(RiskError::Overflow)?
-            };
+                .checked_mul_div(delta_f, divisor, Rounding::Up)
+                .ok_or(RiskError::Overflow)?
+                .get();
 
             // Longs pay when funding positive: pnl -= payment
             // Use set_pnl helper to maintain pnl_pos_tot aggregate (spec §4.2)
@@ -2274,18 +9901,86 @@ impl RiskEngine {
                 .checked_sub(payment)
                 .ok_or(RiskError::Overflow)?;
             self.set_pnl(idx, new_pnl);
+            if self.accounts[idx].is_lp() {
+                let lp_pnl = &mut self.accounts[idx].lp_pnl;
+                lp_pnl.funding_pnl = lp_pnl.funding_pnl.saturating_sub(payment);
+            }
         }
 
         self.accounts[idx].funding_index = global_fi;
+        self.settle_holding_fee(idx);
         Ok(())
     }
 
+    /// Holding fee rate multiple for `account`'s position age: 1 plus one per full
+    /// `holding_fee_escalation_slots`, capped at `holding_fee_max_multiplier`.
+    pub fn holding_fee_multiplier(&self, account: &Account) -> u64 {
+        let period = self.ext_params.holding_fee_escalation_slots;
+        if period == 0 {
+            return 1;
+        }
+        let multiplier = account
+            .position_age(self.current_slot)
+            .checked_div(period)
+            .unwrap_or(0)
+            .saturating_add(1);
+        match self.ext_params.holding_fee_max_multiplier {
+            0 => multiplier,
+            max => core::cmp::min(multiplier, max),
+        }
+    }
+
+    /// Charge user `idx` the holding fee accrued on its position since its last
+    /// settlement. Like maintenance fees, it is drawn from fee credits, then capital
+    /// into insurance; any shortfall remains as fee debt.
+    fn settle_holding_fee(&mut self, idx: usize) {
+        let global = self.holding_fee_index;
+        let account = &mut self.accounts[idx];
+        let delta = global.get().saturating_sub(account.holding_fee_index.get());
+        account.holding_fee_index = global;
+        let abs_pos = account.position_size.unsigned_abs();
+        if delta == 0 || abs_pos == 0 || account.is_lp() {
+            return;
+        }
+        let multiplier = self.holding_fee_multiplier(&self.accounts[idx]);
+        let due = mul_div(
+            abs_pos,
+            delta.saturating_mul(multiplier as u128),
+            self.price_divisor().saturating_mul(1_000_000_000),
+            Rounding::Up,
+        );
+        let account = &mut self.accounts[idx];
+        account.fee_credits = account.fee_credits.saturating_sub_u128(due);
+        if account.fee_credits.is_negative() {
+            let owed = neg_i128_to_u128(account.fee_credits.get());
+            let current_cap = account.capital.get();
+            let pay = core::cmp::min(owed, current_cap);
+            self.set_capital(idx, current_cap.saturating_sub(pay));
+            self.insurance_fund.balance = self.insurance_fund.balance.saturating_add(pay);
+            self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue.saturating_add(pay);
+            self.revenue.holding_fees = self.revenue.holding_fees.saturating_add(pay);
+            self.accounts[idx].fee_credits = self.accounts[idx].fee_credits.saturating_add_u128(pay);
+        }
+    }
+
     /// Touch an account (settle funding before operations)
+    ///
+    /// Like the other single-leg settle entry points this bumps `state_seq` but
+    /// skips the strict invariant pass, which only holds across whole operations.
     pub fn touch_account(&mut self, idx: u16) -> Result<()> {
+        let result = self.touch_account_inner(idx);
+        if result.is_ok() {
+            self.bump_state_seq();
+        }
+        result
+    }
+
+    fn touch_account_inner(&mut self, idx: u16) -> Result<()> {
         if !self.is_used(idx as usize) {
             return Err(RiskError::AccountNotFound);
         }
 
+        self.accrue_emissions(idx as usize, self.current_slot);
         self.settle_account_funding(idx as usize)
     }
 
@@ -2298,6 +9993,14 @@ impl RiskEngine {
     /// This makes positions fungible: any LP can close any user's position
     /// because PnL is settled to a common reference price.
     pub fn settle_mark_to_oracle(&mut self, idx: u16, oracle_price: u64) -> Result<()> {
+        let result = self.settle_mark_to_oracle_inner(idx, oracle_price);
+        if result.is_ok() {
+            self.bump_state_seq();
+        }
+        result
+    }
+
+    fn settle_mark_to_oracle_inner(&mut self, idx: u16, oracle_price: u64) -> Result<()> {
         if idx as usize >= MAX_ACCOUNTS || !self.is_used(idx as usize) {
             return Err(RiskError::AccountNotFound);
         }
@@ -2309,7 +10012,7 @@ impl RiskEngine {
         }
 
         // Compute mark PnL at current oracle
-        let mark = Self::mark_pnl_for_position(
+        let mark = self.mark_pnl(
             self.accounts[idx as usize].position_size.get(),
             self.accounts[idx as usize].entry_price,
             oracle_price,
@@ -2322,6 +10025,7 @@ impl RiskEngine {
             .checked_add(mark)
             .ok_or(RiskError::Overflow)?;
         self.set_pnl(idx as usize, new_pnl);
+        self.attribute_lp_inventory_pnl(idx as usize, mark);
 
         // Reset entry to oracle (mark PnL is now 0 at this price)
         self.accounts[idx as usize].entry_price = oracle_price;
@@ -2343,7 +10047,7 @@ impl RiskEngine {
         }
 
         // Compute mark PnL at current oracle
-        let mark = Self::mark_pnl_for_position(
+        let mark = self.mark_pnl(
             self.accounts[idx as usize].position_size.get(),
             self.accounts[idx as usize].entry_price,
             oracle_price,
@@ -2352,6 +10056,7 @@ impl RiskEngine {
         // Realize the mark PnL via set_pnl (saturating — never fails on overflow)
         let new_pnl = self.accounts[idx as usize].pnl.get().saturating_add(mark);
         self.set_pnl(idx as usize, new_pnl);
+        self.attribute_lp_inventory_pnl(idx as usize, mark);
 
         // Reset entry to oracle (mark PnL is now 0 at this price)
         self.accounts[idx as usize].entry_price = oracle_price;
@@ -2363,11 +10068,17 @@ impl RiskEngine {
     /// This is the standard "lazy settlement" path called on every user operation.
     /// Triggers liquidation check if fees push account below maintenance margin.
     pub fn touch_account_full(&mut self, idx: u16, now_slot: u64, oracle_price: u64) -> Result<()> {
+        let result = self.touch_account_full_inner(idx, now_slot, oracle_price);
+        self.finish_mutation("touch_account_full", result.is_ok());
+        result
+    }
+
+    fn touch_account_full_inner(&mut self, idx: u16, now_slot: u64, oracle_price: u64) -> Result<()> {
         // Update current_slot for consistent warmup/bookkeeping
         self.current_slot = now_slot;
 
         // 1. Settle funding
-        self.touch_account(idx)?;
+        self.touch_account_inner(idx)?;
 
         // 2. Settle mark-to-market (variation margin)
         // Per spec §5.4: if AvailGross increases, warmup must restart.
@@ -2380,7 +10091,7 @@ impl RiskEngine {
                 0
             }
         };
-        self.settle_mark_to_oracle(idx, oracle_price)?;
+        self.settle_mark_to_oracle_inner(idx, oracle_price)?;
         // If AvailGross increased, update warmup slope (restarts warmup timer)
         let new_avail_gross = {
             let pnl = self.accounts[idx as usize].pnl.get();
@@ -2395,10 +10106,10 @@ impl RiskEngine {
         }
 
         // 3. Settle maintenance fees (may trigger undercollateralized error)
-        self.settle_maintenance_fee(idx, now_slot, oracle_price)?;
+        self.settle_maintenance_fee_inner(idx, now_slot, oracle_price)?;
 
         // 4. Settle warmup (convert warmed PnL to capital, realize losses)
-        self.settle_warmup_to_capital(idx)?;
+        self.settle_warmup_to_capital_inner(idx)?;
 
         // 5. Sweep any fee debt from newly-available capital (warmup may
         //    have created capital that should pay outstanding fee debt)
@@ -2427,10 +10138,10 @@ impl RiskEngine {
         oracle_price: u64,
     ) -> Result<()> {
         // 1. Settle funding
-        self.touch_account(idx)?;
+        self.touch_account_inner(idx)?;
 
         // 2. Settle maintenance fees (may trigger undercollateralized error)
-        self.settle_maintenance_fee(idx, now_slot, oracle_price)?;
+        self.settle_maintenance_fee_inner(idx, now_slot, oracle_price)?;
 
         // NOTE: No warmup settlement - handled inline for losses in close helpers
         Ok(())
@@ -2445,15 +10156,87 @@ impl RiskEngine {
     /// Settles any accrued maintenance fees from the deposit first,
     /// with the remainder added to capital. This ensures fee conservation
     /// (fees are never forgiven) and prevents stuck accounts.
+    ///
+    /// `amount` is what the depositor sent; with a transfer-fee mint
+    /// (`ExtParams::transfer_fee_bps`) only the amount net of the fee is credited.
+    /// Overflow if the vault would exceed u128::MAX.
     pub fn deposit(&mut self, idx: u16, amount: u128, now_slot: u64) -> Result<()> {
+        let received = self.transfer_net_amount(amount);
+        self.deposit_received(idx, received, now_slot)
+    }
+
+    /// Deposit crediting exactly `received`, for callers that measure the vault
+    /// token balance before and after the transfer instead of relying on
+    /// `ExtParams::transfer_fee_bps`.
+    pub fn deposit_received(&mut self, idx: u16, received: u128, now_slot: u64) -> Result<()> {
+        let result = self.deposit_inner(idx, received, now_slot);
+        if result.is_ok() {
+            self.mint_lp_shares(idx, received);
+            self.extend_lp_lockup(idx, now_slot);
+            self.record_event(EventKind::Deposit, idx, EVENT_NO_ACCOUNT, received, 0, 0);
+        }
+        self.finish_mutation("deposit", result.is_ok());
+        result
+    }
+
+    /// Deposit into many accounts in one call, for custodial integrators and LP vault
+    /// programs funding their sub-accounts with a single token transfer. Each entry is
+    /// credited exactly its `amount` (as in `deposit_received`); returns the total.
+    /// The batch is committed as one state update. Relies on Solana transaction
+    /// atomicity: an Err (e.g. an unknown account) aborts the earlier entries too.
+    pub fn deposit_many(&mut self, deposits: &[BulkTransfer], now_slot: u64) -> Result<u128> {
+        let result = self.deposit_many_inner(deposits, now_slot);
+        self.finish_mutation("deposit_many", result.is_ok());
+        result
+    }
+
+    fn deposit_many_inner(&mut self, deposits: &[BulkTransfer], now_slot: u64) -> Result<u128> {
+        let mut total: u128 = 0;
+        for deposit in deposits {
+            let (idx, amount) = (deposit.idx, deposit.amount);
+            self.deposit_inner(idx, amount, now_slot)?;
+            self.mint_lp_shares(idx, amount);
+            self.extend_lp_lockup(idx, now_slot);
+            self.record_event(EventKind::Deposit, idx, EVENT_NO_ACCOUNT, amount, 0, 0);
+            total = total.saturating_add(amount);
+        }
+        Ok(total)
+    }
+
+    /// Amount that arrives when `amount` collateral is transferred, net of the
+    /// mint's transfer fee (`amount` itself when no fee is configured). `deposit`
+    /// credits this; a `withdraw` of `amount` delivers this to the recipient.
+    pub fn transfer_net_amount(&self, amount: u128) -> u128 {
+        let ext = &self.ext_params;
+        amount.saturating_sub(transfer_fee(amount, ext.transfer_fee_bps, ext.transfer_fee_max))
+    }
+
+    fn deposit_inner(&mut self, idx: u16, amount: u128, now_slot: u64) -> Result<()> {
         // Update current_slot so warmup/bookkeeping progresses consistently
         self.current_slot = now_slot;
 
         if !self.is_used(idx as usize) {
             return Err(RiskError::AccountNotFound);
         }
+        // The vault bounds capital and insurance, so it is the only sum that can overflow
+        if self.vault.get().checked_add(amount).is_none() {
+            return Err(RiskError::Overflow);
+        }
+        self.accrue_emissions(idx as usize, now_slot);
+
+        let capital_cap = self.ext_params.max_owner_capital.get();
+        if capital_cap > 0 {
+            if let Some(others) = self.owner_exposure_excluding(idx, 0) {
+                let own = self.accounts[idx as usize].capital.get();
+                if others.capital.saturating_add(own).saturating_add(amount) > capital_cap {
+                    return Err(RiskError::OwnerLimitExceeded);
+                }
+            }
+        }
 
+        self.record_epoch_drain(idx as usize, 0, amount);
         let account = &mut self.accounts[idx as usize];
+        account.last_activity_slot = now_slot;
         let mut deposit_remaining = amount;
 
         // Calculate and settle accrued fees
@@ -2468,7 +10251,7 @@ impl RiskEngine {
 
             // Deduct from fee_credits (coupon: no insurance booking here —
             // insurance was already paid when credits were granted)
-            account.fee_credits = account.fee_credits.saturating_sub(due as i128);
+            account.fee_credits = account.fee_credits.saturating_sub_u128(due);
         }
 
         // Pay any owed fees from deposit first
@@ -2476,12 +10259,13 @@ impl RiskEngine {
             let owed = neg_i128_to_u128(account.fee_credits.get());
             let pay = core::cmp::min(owed, deposit_remaining);
 
-            deposit_remaining -= pay;
-            self.insurance_fund.balance = self.insurance_fund.balance + pay;
-            self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue + pay;
+            deposit_remaining = deposit_remaining.saturating_sub(pay);
+            self.insurance_fund.balance = self.insurance_fund.balance.saturating_add(pay);
+            self.insurance_fund.fee_revenue = self.insurance_fund.fee_revenue.saturating_add(pay);
+            self.revenue.maintenance_fees = self.revenue.maintenance_fees.saturating_add(pay);
 
             // Credit back what was paid
-            account.fee_credits = account.fee_credits.saturating_add(pay as i128);
+            account.fee_credits = account.fee_credits.saturating_add_u128(pay);
         }
 
         // Vault gets full deposit (tokens received)
@@ -2492,7 +10276,7 @@ impl RiskEngine {
         self.set_capital(idx as usize, new_cap);
 
         // Settle warmup after deposit (allows losses to be paid promptly if underwater)
-        self.settle_warmup_to_capital(idx)?;
+        self.settle_warmup_to_capital_inner(idx)?;
 
         // If any older fee debt remains, use capital to pay it now.
         self.pay_fee_debt_from_capital(idx);
@@ -2502,13 +10286,65 @@ impl RiskEngine {
 
     /// Withdraw capital from an account.
     /// Relies on Solana transaction atomicity: if this returns Err, the entire TX aborts.
+    /// `amount` leaves the vault; with a transfer-fee mint the recipient receives
+    /// `transfer_net_amount(amount)`.
     pub fn withdraw(
         &mut self,
         idx: u16,
         amount: u128,
         now_slot: u64,
         oracle_price: u64,
-    ) -> Result<()> {
+    ) -> Result<()> {
+        let result = self.withdraw_inner(idx, amount, now_slot, oracle_price, true);
+        if let Ok(exit_fee) = result {
+            self.burn_lp_shares(idx, amount.saturating_add(exit_fee));
+            self.record_event(EventKind::Withdraw, idx, EVENT_NO_ACCOUNT, amount, 0, 0);
+        }
+        self.finish_mutation("withdraw", result.is_ok());
+        result.map(|_| ())
+    }
+
+    /// Withdraw from many accounts in one call (see `deposit_many`). Returns the total
+    /// leaving the vault, for the caller's single token transfer. Each entry passes the
+    /// same checks as `withdraw`; the batch is committed as one state update, and an
+    /// Err aborts the transaction, earlier entries included.
+    pub fn withdraw_many(
+        &mut self,
+        withdrawals: &[BulkTransfer],
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<u128> {
+        let result = self.withdraw_many_inner(withdrawals, now_slot, oracle_price);
+        self.finish_mutation("withdraw_many", result.is_ok());
+        result
+    }
+
+    fn withdraw_many_inner(
+        &mut self,
+        withdrawals: &[BulkTransfer],
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<u128> {
+        let mut total: u128 = 0;
+        for withdrawal in withdrawals {
+            let (idx, amount) = (withdrawal.idx, withdrawal.amount);
+            let exit_fee = self.withdraw_inner(idx, amount, now_slot, oracle_price, true)?;
+            self.burn_lp_shares(idx, amount.saturating_add(exit_fee));
+            self.record_event(EventKind::Withdraw, idx, EVENT_NO_ACCOUNT, amount, 0, 0);
+            total = total.saturating_add(amount);
+        }
+        Ok(total)
+    }
+
+    /// With `check_margin` false the caller checks the final state's margin itself.
+    fn withdraw_inner(
+        &mut self,
+        idx: u16,
+        amount: u128,
+        now_slot: u64,
+        oracle_price: u64,
+        check_margin: bool,
+    ) -> Result<u128> {
         // Update current_slot so warmup/bookkeeping progresses consistently
         self.current_slot = now_slot;
 
@@ -2528,8 +10364,13 @@ impl RiskEngine {
             return Err(RiskError::AccountNotFound);
         }
 
+        // LP capital leaves through the withdrawal queue when one is configured
+        if self.accounts[idx as usize].is_lp() && self.ext_params.lp_withdrawal_epoch_slots > 0 {
+            return Err(RiskError::WithdrawalQueued);
+        }
+
         // Full settlement: funding + maintenance fees + warmup
-        self.touch_account_full(idx, now_slot, oracle_price)?;
+        self.touch_account_full_inner(idx, now_slot, oracle_price)?;
 
         // Read account state (scope the borrow)
         let (old_capital, pnl, position_size, entry_price, fee_credits) = {
@@ -2542,18 +10383,38 @@ impl RiskEngine {
                 account.fee_credits,
             )
         };
+        let reserved = self.conditional_margin(&self.accounts[idx as usize]);
+
+        // Locked LP capital pays the early-exit fee on top of the amount
+        let exit_fee = self.lp_exit_fee(idx as usize, amount, now_slot)?;
+        let debit = add_u128(amount, exit_fee);
+        if self.accounts[idx as usize].is_lp() {
+            self.check_owner_lp_exit(idx as usize, debit)?;
+        }
+
+        // LP capital may not push utilization into the soft band
+        if self.accounts[idx as usize].is_lp()
+            && amount > self.lp_withdrawal_headroom(oracle_price)
+        {
+            return Err(RiskError::UtilizationTooHigh);
+        }
+
+        let drain_cap = self.ext_params.max_epoch_drain.get();
+        if drain_cap > 0 && self.epoch_drain(idx).saturating_add(amount) > drain_cap {
+            return Err(RiskError::DrainLimitExceeded);
+        }
 
         // Check we have enough capital
-        if old_capital.get() < amount {
+        if old_capital.get() < debit {
             return Err(RiskError::InsufficientBalance);
         }
 
         // Calculate MTM equity after withdrawal with haircut (spec §3.3)
         // equity_mtm = max(0, new_capital + min(pnl, 0) + effective_pos_pnl(pnl) + mark_pnl)
         // Fail-safe: if mark_pnl overflows (corrupted entry_price/position_size), treat as 0 equity
-        let new_capital = sub_u128(old_capital.get(), amount);
+        let new_capital = sub_u128(old_capital.get(), debit);
         let new_equity_mtm = {
-            let eq = match Self::mark_pnl_for_position(position_size.get(), entry_price, oracle_price)
+            let eq = match self.mark_pnl(position_size.get(), entry_price, oracle_price)
             {
                 Ok(mark_pnl) => {
                     let cap_i = u128_to_i128_clamped(new_capital);
@@ -2582,18 +10443,23 @@ impl RiskEngine {
 
         // If account has position, must maintain initial margin at ORACLE price (MTM check)
         // This prevents withdrawing to a state that's immediately liquidatable
-        if !position_size.is_zero() {
-            let position_notional = mul_u128(
+        if check_margin && !position_size.is_zero() {
+            let position_notional = self.notional_at(
                 saturating_abs_i128(position_size.get()) as u128,
-                oracle_price as u128,
-            ) / 1_000_000;
+                oracle_price,
+                Rounding::Up,
+            );
 
-            let initial_margin_required =
-                mul_u128(position_notional, self.params.initial_margin_bps as u128) / 10_000;
+            // The account's tier, so margin events tighten withdrawals as they do trades
+            let initial_bps = self.initial_margin_bps_for(&self.accounts[idx as usize]);
+            let initial_margin_required = self.margin_required(position_notional, initial_bps);
 
-            if new_equity_mtm < initial_margin_required {
+            if new_equity_mtm < initial_margin_required.saturating_add(reserved) {
                 return Err(RiskError::Undercollateralized);
             }
+        } else if check_margin && new_equity_mtm < reserved {
+            // Margin reserved for resting conditional orders stays in the account
+            return Err(RiskError::Undercollateralized);
         }
 
         // Commit the withdrawal (via set_capital to maintain c_tot)
@@ -2602,7 +10468,7 @@ impl RiskEngine {
 
         // Post-withdrawal MTM maintenance margin check at oracle price
         // This is a safety belt to ensure we never leave an account in liquidatable state
-        if !self.accounts[idx as usize].position_size.is_zero() {
+        if check_margin && !self.accounts[idx as usize].position_size.is_zero() {
             if !self.is_above_maintenance_margin_mtm(&self.accounts[idx as usize], oracle_price) {
                 // Revert the withdrawal (via set_capital to maintain c_tot)
                 self.set_capital(idx as usize, old_capital.get());
@@ -2619,7 +10485,37 @@ impl RiskEngine {
             "Withdraw: negative PnL must settle immediately"
         );
 
-        Ok(())
+        self.record_epoch_drain(idx as usize, amount, 0);
+        self.accounts[idx as usize].last_activity_slot = now_slot;
+        self.distribute_exit_fee(exit_fee, idx as usize);
+        Ok(exit_fee)
+    }
+
+    /// Value account `idx` has extracted in the current drain epoch
+    /// (`ExtParams::drain_epoch_slots`): withdrawals plus profit realized into capital,
+    /// net of deposits. Withdrawals may not take it past `ExtParams::max_epoch_drain`.
+    pub fn epoch_drain(&self, idx: u16) -> u128 {
+        let len = self.epoch_len_or_shared(self.ext_params.drain_epoch_slots);
+        match self.accounts.get(idx as usize) {
+            Some(a) if a.drain_epoch_start == window_start_slot(self.current_slot, len) => {
+                a.epoch_drain.get()
+            }
+            _ => 0,
+        }
+    }
+
+    /// Add `extracted` and subtract `deposited` from `idx`'s drain this epoch
+    /// (no-op while the drain cap is disabled).
+    fn record_epoch_drain(&mut self, idx: usize, extracted: u128, deposited: u128) {
+        if self.ext_params.max_epoch_drain.is_zero() {
+            return;
+        }
+        let drain = self.epoch_drain(idx as u16);
+        let len = self.epoch_len_or_shared(self.ext_params.drain_epoch_slots);
+        let start = window_start_slot(self.current_slot, len);
+        let account = &mut self.accounts[idx];
+        account.drain_epoch_start = start;
+        account.epoch_drain = U128::new(drain.saturating_add(extracted).saturating_sub(deposited));
     }
 
     // ========================================
@@ -2649,7 +10545,7 @@ impl RiskEngine {
     /// FAIL-SAFE: On overflow, returns 0 (worst-case equity) to ensure liquidation
     /// can still trigger. This prevents overflow from blocking liquidation.
     pub fn account_equity_mtm_at_oracle(&self, account: &Account, oracle_price: u64) -> u128 {
-        let mark = match Self::mark_pnl_for_position(
+        let mark = match self.mark_pnl(
             account.position_size.get(),
             account.entry_price,
             oracle_price,
@@ -2679,20 +10575,38 @@ impl RiskEngine {
     ///
     /// FAIL-SAFE: Returns false on any error (treat as below margin / liquidatable).
     pub fn is_above_margin_bps_mtm(&self, account: &Account, oracle_price: u64, bps: u64) -> bool {
-        let equity = self.account_equity_mtm_at_oracle(account, oracle_price);
+        // An LP's credit line counts as margin (see src/credit.rs)
+        let equity = self
+            .account_equity_mtm_at_oracle(account, oracle_price)
+            .saturating_add(self.credit_available(account));
 
         // Position value at oracle price
-        let position_value = mul_u128(
+        let position_value = self.notional_at(
             saturating_abs_i128(account.position_size.get()) as u128,
-            oracle_price as u128,
-        ) / 1_000_000;
+            oracle_price,
+            Rounding::Up,
+        );
 
         // Margin requirement at given bps
-        let margin_required = mul_u128(position_value, bps as u128) / 10_000;
+        let margin_required = self.margin_required(position_value, bps);
 
         equity > margin_required
     }
 
+    /// MTM equity over position notional at `oracle_price`, in bps (`u64::MAX` when
+    /// flat). Compare against `maintenance_margin_bps` / `initial_margin_bps`.
+    pub fn margin_ratio_bps(&self, account: &Account, oracle_price: u64) -> u64 {
+        let abs_pos = saturating_abs_i128(account.position_size.get()) as u128;
+        let notional = self.notional_at(abs_pos, oracle_price, Rounding::Up);
+        if notional == 0 {
+            return u64::MAX;
+        }
+        let equity = self.account_equity_mtm_at_oracle(account, oracle_price);
+        let equity = self.collateral_value(equity);
+        let ratio = mul_div(equity, 10_000, notional, Rounding::Down);
+        core::cmp::min(ratio, u64::MAX as u128) as u64
+    }
+
     /// MTM maintenance margin check (fail-safe: returns false on overflow)
     #[inline]
     pub fn is_above_maintenance_margin_mtm(&self, account: &Account, oracle_price: u64) -> bool {
@@ -2715,17 +10629,18 @@ impl RiskEngine {
         // MTM equity (fail-safe: overflow returns 0, making account appear liquidatable)
         let equity = self.account_equity_mtm_at_oracle(a, oracle_price);
 
-        let pos_value = mul_u128(
+        let pos_value = self.notional_at(
             saturating_abs_i128(a.position_size.get()) as u128,
-            oracle_price as u128,
-        ) / 1_000_000;
+            oracle_price,
+            Rounding::Up,
+        );
 
-        let maint = mul_u128(pos_value, self.params.maintenance_margin_bps as u128) / 10_000;
+        let maint = self.margin_required(pos_value, self.params.maintenance_margin_bps);
 
         if equity >= maint {
             0
         } else {
-            maint - equity
+            maint.saturating_sub(equity)
         }
     }
 
@@ -2740,12 +10655,177 @@ impl RiskEngine {
         now_slot: u64,
         oracle_price: u64,
         size: i128,
-    ) -> Result<()> {
+    ) -> Result<TradeReceipt> {
+        let result = self.execute_trade_inner(
+            matcher,
+            lp_idx,
+            user_idx,
+            now_slot,
+            oracle_price,
+            size,
+        );
+        self.finish_mutation("execute_trade", result.is_ok());
+        result
+    }
+
+    /// `execute_trade` at the price selected from `primary` / `fallback` (see
+    /// `select_oracle_price`). Like `keeper_crank_with_oracles`, it rejects quotes
+    /// published before the newest one already accepted.
+    #[allow(clippy::too_many_arguments)]
+    pub fn execute_trade_with_oracles<M: MatchingEngine>(
+        &mut self,
+        matcher: &M,
+        lp_idx: u16,
+        user_idx: u16,
+        now_slot: u64,
+        primary: &OracleQuote,
+        fallback: Option<&OracleQuote>,
+        size: i128,
+    ) -> Result<TradeReceipt> {
+        let (price, feed, publish_slot) = self.check_oracle_quote(now_slot, primary, fallback)?;
+        let receipt = self.execute_trade(matcher, lp_idx, user_idx, now_slot, price, size)?;
+        self.commit_oracle_quote(price, feed, publish_slot);
+        Ok(receipt)
+    }
+
+    /// `execute_trade`, with failures reported as a `TradeRejection` carrying the
+    /// numbers behind the error (margin shortfall, remaining capacity, staleness).
+    pub fn execute_trade_detailed<M: MatchingEngine>(
+        &mut self,
+        matcher: &M,
+        lp_idx: u16,
+        user_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+        size: i128,
+    ) -> core::result::Result<TradeReceipt, TradeRejection> {
+        self.execute_trade(matcher, lp_idx, user_idx, now_slot, oracle_price, size)
+            .map_err(|error| TradeRejection {
+                error,
+                reason: self.last_trade_rejection,
+            })
+    }
+
+    /// Deposit `amount` to `user_idx` and execute a trade against `lp_idx` in one
+    /// call, so the trade is margin-checked on the post-deposit balance at the same
+    /// oracle price.
+    /// Relies on Solana transaction atomicity: if this returns Err, the entire TX aborts
+    /// (deposit included).
+    #[allow(clippy::too_many_arguments)]
+    pub fn deposit_and_trade<M: MatchingEngine>(
+        &mut self,
+        matcher: &M,
+        lp_idx: u16,
+        user_idx: u16,
+        amount: u128,
+        now_slot: u64,
+        oracle_price: u64,
+        size: i128,
+    ) -> Result<TradeReceipt> {
+        self.deposit(user_idx, amount, now_slot)?;
+        self.execute_trade(matcher, lp_idx, user_idx, now_slot, oracle_price, size)
+    }
+
+    /// Reduce `user_idx`'s position by `close_size` against `lp_idx` and withdraw
+    /// `amount` of the freed collateral in one call. `close_size` must oppose the
+    /// position and not exceed it (InvalidParams otherwise). Both are applied first,
+    /// then the account's initial margin (see `initial_margin_bps_for`), plus any
+    /// conditional-order reservation, is checked once on the final state.
+    /// Relies on Solana transaction atomicity: if this returns Err, the entire TX aborts
+    /// (trade included).
+    #[allow(clippy::too_many_arguments)]
+    pub fn close_and_withdraw<M: MatchingEngine>(
+        &mut self,
+        matcher: &M,
+        lp_idx: u16,
+        user_idx: u16,
+        close_size: i128,
+        amount: u128,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<TradeReceipt> {
+        let result = self.close_and_withdraw_inner(
+            matcher,
+            lp_idx,
+            user_idx,
+            close_size,
+            amount,
+            now_slot,
+            oracle_price,
+        );
+        self.finish_mutation("close_and_withdraw", result.is_ok());
+        result
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn close_and_withdraw_inner<M: MatchingEngine>(
+        &mut self,
+        matcher: &M,
+        lp_idx: u16,
+        user_idx: u16,
+        close_size: i128,
+        amount: u128,
+        now_slot: u64,
+        oracle_price: u64,
+    ) -> Result<TradeReceipt> {
+        let pos = self
+            .accounts
+            .get(user_idx as usize)
+            .map_or(0, |a| a.position_size.get());
+        if close_size == 0
+            || (close_size > 0) == (pos > 0)
+            || saturating_abs_i128(close_size) > saturating_abs_i128(pos)
+        {
+            return Err(RiskError::InvalidParams);
+        }
+        let receipt = self.execute_trade_inner(
+            matcher,
+            lp_idx,
+            user_idx,
+            now_slot,
+            oracle_price,
+            close_size,
+        )?;
+        self.withdraw_inner(user_idx, amount, now_slot, oracle_price, false)?;
+
+        let account = &self.accounts[user_idx as usize];
+        let notional = self.notional_at(
+            saturating_abs_i128(account.position_size.get()) as u128,
+            oracle_price,
+            Rounding::Up,
+        );
+        let required = self
+            .margin_required(notional, self.initial_margin_bps_for(account))
+            .saturating_add(self.conditional_margin(account));
+        if self.account_equity_mtm_at_oracle(account, oracle_price) < required {
+            return Err(RiskError::Undercollateralized);
+        }
+
+        self.record_event(EventKind::Withdraw, user_idx, EVENT_NO_ACCOUNT, amount, 0, 0);
+        Ok(receipt)
+    }
+
+    fn execute_trade_inner<M: MatchingEngine>(
+        &mut self,
+        matcher: &M,
+        lp_idx: u16,
+        user_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+        size: i128,
+    ) -> Result<TradeReceipt> {
         // Update current_slot so warmup/bookkeeping progresses consistently
         self.current_slot = now_slot;
+        self.last_trade_rejection = TradeRejectReason::Other;
 
         // Require fresh crank (time-based) before state-changing operations
-        self.require_fresh_crank(now_slot)?;
+        if let Err(e) = self.require_fresh_crank(now_slot) {
+            self.last_trade_rejection = TradeRejectReason::CrankStale {
+                last_crank_slot: self.last_crank_slot,
+                max_staleness_slots: self.max_crank_staleness_slots,
+            };
+            return Err(e);
+        }
 
         // Validate indices
         if !self.is_used(lp_idx as usize) || !self.is_used(user_idx as usize) {
@@ -2757,6 +10837,11 @@ impl RiskEngine {
             return Err(RiskError::Overflow);
         }
 
+        // Quanto markets cannot value positions until the conversion price is known
+        if self.ext_params.payoff_mode == PayoffMode::Quanto && self.quanto_price == 0 {
+            return Err(RiskError::OracleUnavailable);
+        }
+
         // Validate requested size bounds
         if size == 0 || size == i128::MIN {
             return Err(RiskError::Overflow);
@@ -2784,7 +10869,50 @@ impl RiskEngine {
 
         if user_inc || lp_inc {
             // Risk-increasing: require recent full sweep
-            self.require_recent_full_sweep(now_slot)?;
+            if let Err(e) = self.require_recent_full_sweep(now_slot) {
+                self.last_trade_rejection = TradeRejectReason::SweepStale {
+                    last_sweep_start_slot: self.last_full_sweep_start_slot,
+                    max_staleness_slots: self.max_crank_staleness_slots,
+                };
+                return Err(e);
+            }
+        }
+
+        // Reduce-only accounts may only move toward zero (no growth, no flip)
+        let sides = [(user_idx, old_user_pos, new_user_pos), (lp_idx, old_lp_pos, new_lp_pos)];
+        for (idx, old, new) in sides {
+            let reduces = new == 0
+                || (new.signum() == old.signum()
+                    && saturating_abs_i128(new) < saturating_abs_i128(old));
+            if self.accounts[idx as usize].reduce_only != 0 && !reduces {
+                self.last_trade_rejection = TradeRejectReason::ReduceOnly { account: idx };
+                return Err(RiskError::ReduceOnly);
+            }
+        }
+
+        // Hard utilization band: risk-reducing trades only
+        let utilization = self.vault_utilization(oracle_price);
+        if user_inc && utilization.band == UtilizationBand::Hard {
+            self.last_trade_rejection = TradeRejectReason::Utilization {
+                bps: utilization.bps,
+                hard_bps: self.ext_params.utilization_hard_bps,
+            };
+            return Err(RiskError::UtilizationTooHigh);
+        }
+
+        // Skew cap: the net LP position may only grow while LP capital covers the gap move
+        if let Some(capacity) = self.skew_capacity(oracle_price) {
+            let net = self.net_lp_pos.get();
+            let new_net = net.saturating_sub(size);
+            let remaining = if size > 0 {
+                capacity.buy_remaining
+            } else {
+                capacity.sell_remaining
+            };
+            if new_net.unsigned_abs() > net.unsigned_abs() && size.unsigned_abs() > remaining {
+                self.last_trade_rejection = TradeRejectReason::Skew { remaining };
+                return Err(RiskError::SkewLimitExceeded);
+            }
         }
 
         // Call matching engine
@@ -2805,11 +10933,18 @@ impl RiskEngine {
         if exec_price == 0 || exec_price > MAX_ORACLE_PRICE {
             return Err(RiskError::InvalidMatchingEngine);
         }
-
         // Size bounds
         if exec_size == 0 {
             // No fill: treat as no-op trade (no side effects, deterministic)
-            return Ok(());
+            let user = &self.accounts[user_idx as usize];
+            return Ok(TradeReceipt {
+                fill_id: 0,
+                exec_price,
+                exec_size: 0,
+                fee: 0,
+                new_position: user.position_size.get(),
+                new_margin_ratio_bps: self.margin_ratio_bps(user, oracle_price),
+            });
         }
         if exec_size == i128::MIN {
             return Err(RiskError::InvalidMatchingEngine);
@@ -2828,11 +10963,26 @@ impl RiskEngine {
             return Err(RiskError::InvalidMatchingEngine);
         }
 
+        // Last look: the fill must print within the band around the oracle
+        let band_bps = self.ext_params.exec_price_band_bps;
+        if band_bps > 0 {
+            let bps = deviation_bps(exec_price, oracle_price, oracle_price);
+            if bps > band_bps {
+                self.last_trade_rejection = TradeRejectReason::PriceBand {
+                    exec_price,
+                    bps,
+                    band_bps,
+                };
+                return Err(RiskError::InvalidMatchingEngine);
+            }
+        }
+
         // Settle funding, mark-to-market, and maintenance fees for both accounts
         // Mark settlement MUST happen before position changes (variation margin)
         // Note: warmup is settled at the END after trade PnL is generated
-        self.touch_account(user_idx)?;
-        self.touch_account(lp_idx)?;
+        self.touch_account_inner(user_idx)?;
+        self.touch_account_inner(lp_idx)?;
+        self.settle_credit_line(lp_idx as usize, now_slot, oracle_price);
 
         // Per spec §5.4: if AvailGross increases from mark settlement, warmup must restart.
         // Capture old AvailGross before mark settlement for both accounts.
@@ -2844,8 +10994,8 @@ impl RiskEngine {
             let pnl = self.accounts[lp_idx as usize].pnl.get();
             if pnl > 0 { (pnl as u128).saturating_sub(self.accounts[lp_idx as usize].reserved_pnl as u128) } else { 0 }
         };
-        self.settle_mark_to_oracle(user_idx, oracle_price)?;
-        self.settle_mark_to_oracle(lp_idx, oracle_price)?;
+        self.settle_mark_to_oracle_inner(user_idx, oracle_price)?;
+        self.settle_mark_to_oracle_inner(lp_idx, oracle_price)?;
         // If AvailGross increased from mark settlement, update warmup slope (restarts warmup)
         let user_new_avail = {
             let pnl = self.accounts[user_idx as usize].pnl.get();
@@ -2862,21 +11012,56 @@ impl RiskEngine {
             self.update_warmup_slope(lp_idx)?;
         }
 
-        self.settle_maintenance_fee(user_idx, now_slot, oracle_price)?;
-        self.settle_maintenance_fee(lp_idx, now_slot, oracle_price)?;
+        self.settle_maintenance_fee_inner(user_idx, now_slot, oracle_price)?;
+        self.settle_maintenance_fee_inner(lp_idx, now_slot, oracle_price)?;
+
+        let price_divisor = self.price_divisor();
+        let payoff_mode = self.ext_params.payoff_mode;
+        let collateral_rate_e9 = self.collateral_rate_e9;
+        // Notional the user's owner holds on its other accounts, if capped
+        let owner_notional_elsewhere = match self.ext_params.max_owner_notional.get() {
+            0 => None,
+            cap => self
+                .owner_exposure_excluding(user_idx, oracle_price)
+                .map(|e| (e.notional, cap)),
+        };
 
-        // Calculate fee (ceiling division to prevent micro-trade fee evasion)
-        let notional =
-            mul_u128(saturating_abs_i128(exec_size) as u128, exec_price as u128) / 1_000_000;
-        let fee = if notional > 0 && self.params.trading_fee_bps > 0 {
-            // Ceiling division: ensures at least 1 atomic unit fee for any real trade
-            (mul_u128(notional, self.params.trading_fee_bps as u128) + 9999) / 10_000
+        // Calculate fee (rounded up to prevent micro-trade fee evasion)
+        let notional = notional_for(
+            payoff_mode,
+            saturating_abs_i128(exec_size) as u128,
+            exec_price,
+            price_divisor,
+            Rounding::Up,
+        );
+        self.mature_markout(user_idx as usize, now_slot, oracle_price);
+        let fee_bps = self
+            .trading_fee_bps_for(notional, oracle_price, user_inc)
+            .saturating_add(self.markout_surcharge_bps(user_idx)?);
+        let fee = if notional > 0 && fee_bps > 0 {
+            // Rounding up ensures at least 1 atomic unit fee for any real trade
+            mul_div(notional, fee_bps as u128, 10_000, Rounding::Up)
         } else {
             0
         };
+        let fee = fee.saturating_sub(self.leverage_fee_discount(user_idx as usize, fee));
+        let fee = fee.saturating_sub(self.adl_first_fee_discount(user_idx as usize, fee));
+        let rebate = self.risk_reducing_fee_discount(user_idx as usize, exec_size, fee);
+        let fee = fee.saturating_sub(rebate);
+        let user_initial_bps = self.initial_margin_bps_for(&self.accounts[user_idx as usize]);
+        let lp_initial_bps = self.initial_margin_bps_for(&self.accounts[lp_idx as usize]);
+        let lp_credit = self.credit_available(&self.accounts[lp_idx as usize]);
+        let user_reserved = self.conditional_margin(&self.accounts[user_idx as usize]);
+
+        // Trade PnL = (oracle - exec_price) * exec_size (zero-sum between parties)
+        // User gains if buying below oracle (exec_size > 0, oracle > exec_price)
+        // LP gets opposite sign
+        // Note: entry_price is already oracle_price after settle_mark_to_oracle
+        let trade_pnl = self.mark_pnl(exec_size, exec_price, oracle_price)?;
 
-        // Split fee: 50% to LP capital, 50% to insurance
-        let lp_fee = fee / 2;
+        // Split fee: insurance skim off the top, then 50% to LP capital, 50% to insurance
+        let skim = self.insurance_skim(fee, oracle_price);
+        let lp_fee = fee.saturating_sub(skim) / 2;
         let insurance_fee = fee.saturating_sub(lp_fee);
 
         // Access both accounts
@@ -2907,20 +11092,6 @@ impl RiskEngine {
             return Err(RiskError::Overflow);
         }
 
-        // Trade PnL = (oracle - exec_price) * exec_size (zero-sum between parties)
-        // User gains if buying below oracle (exec_size > 0, oracle > exec_price)
-        // LP gets opposite sign
-        // Note: entry_price is already oracle_price after settle_mark_to_oracle
-        let price_diff = (oracle_price as i128)
-            .checked_sub(exec_price as i128)
-            .ok_or(RiskError::Overflow)?;
-
-        let trade_pnl = price_diff
-            .checked_mul(exec_size)
-            .ok_or(RiskError::Overflow)?
-            .checked_div(1_000_000)
-            .ok_or(RiskError::Overflow)?;
-
         // Compute final PNL values (checked math - overflow returns Err)
         let new_user_pnl = user
             .pnl
@@ -2952,7 +11123,7 @@ impl RiskEngine {
         let new_lp_pnl_pos = if new_lp_pnl > 0 { new_lp_pnl as u128 } else { 0 };
 
         // Recompute haircut using projected post-trade pnl_pos_tot (spec §3.3).
-        // Fee moves C→I so Residual = V - C_tot - I is unchanged; only pnl_pos_tot changes.
+        // Fee moves C→I so the residual is unchanged; only pnl_pos_tot changes.
         let projected_pnl_pos_tot = self.pnl_pos_tot
             .get()
             .saturating_add(new_user_pnl_pos)
@@ -2963,9 +11134,11 @@ impl RiskEngine {
         let (h_num, h_den) = if projected_pnl_pos_tot == 0 {
             (1u128, 1u128)
         } else {
+            // As `pnl_residual` (fields only: accounts are mutably borrowed)
             let residual = self.vault.get()
                 .saturating_sub(self.c_tot.get())
-                .saturating_sub(self.insurance_fund.balance.get());
+                .saturating_sub(self.insurance_fund.balance.get())
+                .saturating_sub(self.lp_claimable_total.get());
             (core::cmp::min(residual, projected_pnl_pos_tot), projected_pnl_pos_tot)
         };
 
@@ -2978,7 +11151,7 @@ impl RiskEngine {
             if h_den == 0 {
                 return pos_pnl;
             }
-            mul_u128(pos_pnl, h_num) / h_den
+            mul_u128(pos_pnl, h_num).checked_div(h_den).unwrap_or(0)
         };
 
         // Check user margin with haircut (spec §3.3, §10.4 step 7)
@@ -3000,10 +11173,13 @@ impl RiskEngine {
                 0
             };
             let user_equity = user_equity.saturating_sub(user_fee_debt);
-            let position_value = mul_u128(
+            let position_value = notional_for(
+                payoff_mode,
                 saturating_abs_i128(new_user_position) as u128,
-                oracle_price as u128,
-            ) / 1_000_000;
+                oracle_price,
+                price_divisor,
+                Rounding::Up,
+            );
             // Risk-increasing if |new_pos| > |old_pos| OR position crosses zero (flip)
             // A flip is semantically a close + open, so the new side must meet initial margin
             let old_user_pos = user.position_size.get();
@@ -3013,14 +11189,34 @@ impl RiskEngine {
                 (old_user_pos > 0 && new_user_position < 0) || (old_user_pos < 0 && new_user_position > 0);
             let user_risk_increasing = new_user_pos_abs > old_user_pos_abs || user_crosses_zero;
             let margin_bps = if user_risk_increasing {
-                self.params.initial_margin_bps
+                user_initial_bps
             } else {
                 self.params.maintenance_margin_bps
             };
-            let margin_required = mul_u128(position_value, margin_bps as u128) / 10_000;
+            let margin_required =
+                margin_required_for(position_value, margin_bps, collateral_rate_e9);
+            // Risk-increasing trades must leave the conditional-order reservation free
+            let margin_required = if user_risk_increasing {
+                margin_required.saturating_add(user_reserved)
+            } else {
+                margin_required
+            };
             if user_equity <= margin_required {
+                self.last_trade_rejection = TradeRejectReason::Margin {
+                    account: user_idx,
+                    required: margin_required,
+                    equity: user_equity,
+                };
                 return Err(RiskError::Undercollateralized);
             }
+            if let Some((elsewhere, cap)) = owner_notional_elsewhere {
+                if user_risk_increasing && elsewhere.saturating_add(position_value) > cap {
+                    self.last_trade_rejection = TradeRejectReason::OwnerLimit {
+                        remaining: cap.saturating_sub(elsewhere),
+                    };
+                    return Err(RiskError::OwnerLimitExceeded);
+                }
+            }
         }
 
         // Check LP margin with haircut (spec §3.3, §10.4 step 7)
@@ -3040,11 +11236,14 @@ impl RiskEngine {
             } else {
                 0
             };
-            let lp_equity = lp_equity.saturating_sub(lp_fee_debt);
-            let position_value = mul_u128(
+            let lp_equity = lp_equity.saturating_sub(lp_fee_debt).saturating_add(lp_credit);
+            let position_value = notional_for(
+                payoff_mode,
                 saturating_abs_i128(new_lp_position) as u128,
-                oracle_price as u128,
-            ) / 1_000_000;
+                oracle_price,
+                price_divisor,
+                Rounding::Up,
+            );
             // Risk-increasing if |new_pos| > |old_pos| OR position crosses zero (flip)
             // A flip is semantically a close + open, so the new side must meet initial margin
             let old_lp_pos = lp.position_size.get();
@@ -3054,12 +11253,18 @@ impl RiskEngine {
                 (old_lp_pos > 0 && new_lp_position < 0) || (old_lp_pos < 0 && new_lp_position > 0);
             let lp_risk_increasing = new_lp_pos_abs > old_lp_pos_abs || lp_crosses_zero;
             let margin_bps = if lp_risk_increasing {
-                self.params.initial_margin_bps
+                lp_initial_bps
             } else {
                 self.params.maintenance_margin_bps
             };
-            let margin_required = mul_u128(position_value, margin_bps as u128) / 10_000;
+            let margin_required =
+                margin_required_for(position_value, margin_bps, collateral_rate_e9);
             if lp_equity <= margin_required {
+                self.last_trade_rejection = TradeRejectReason::Margin {
+                    account: lp_idx,
+                    required: margin_required,
+                    equity: lp_equity,
+                };
                 return Err(RiskError::Undercollateralized);
             }
         }
@@ -3068,24 +11273,39 @@ impl RiskEngine {
         // Insurance gets its share of the fee, LP fee already accounted in new_lp_capital
         self.insurance_fund.fee_revenue =
             U128::new(add_u128(self.insurance_fund.fee_revenue.get(), insurance_fee));
+        self.revenue.trading_fees = self.revenue.trading_fees.saturating_add(insurance_fee);
         self.insurance_fund.balance = U128::new(add_u128(self.insurance_fund.balance.get(), insurance_fee));
+        // Earmark the gap-insurance levy out of the insurance cut
+        let gap_levy = mul_div(
+            insurance_fee,
+            self.ext_params.gap_levy_share_bps as u128,
+            10_000,
+            Rounding::Down,
+        );
+        self.gap_insurance.pool = self.gap_insurance.pool.saturating_add(gap_levy);
 
         // Credit fee to user's fee_credits (active traders earn credits that offset maintenance)
-        user.fee_credits = user.fee_credits.saturating_add(fee as i128);
+        user.fee_credits = user.fee_credits.saturating_add_u128(fee);
 
         // §4.3 Batch update exception: Direct field assignment for performance.
         // All aggregate deltas (old/new pnl_pos values) computed above before assignment;
         // aggregates (c_tot, pnl_pos_tot) updated atomically below.
         user.pnl = I128::new(new_user_pnl);
+        user.record_position_change(user.position_size.get(), new_user_position, now_slot);
+        user.last_activity_slot = now_slot;
         user.position_size = I128::new(new_user_position);
         user.entry_price = oracle_price;
         // Commit fee deduction from user capital (spec §8.1)
         user.capital = U128::new(new_user_capital);
 
         lp.pnl = I128::new(new_lp_pnl);
+        lp.record_position_change(lp.position_size.get(), new_lp_position, now_slot);
+        lp.last_activity_slot = now_slot;
         lp.position_size = I128::new(new_lp_position);
         lp.entry_price = oracle_price;
         lp.capital = U128::new(new_lp_capital); // LP receives fee share
+        lp.lp_pnl.fees_earned = lp.lp_pnl.fees_earned.saturating_add(lp_fee);
+        lp.lp_pnl.spread_pnl = lp.lp_pnl.spread_pnl.saturating_sub(trade_pnl);
 
         // §4.1, §4.2: Atomic aggregate maintenance after batch field assignments
         // c_tot delta: user lost fee, LP gained lp_fee → net change = -(fee - lp_fee) = -insurance_fee
@@ -3103,14 +11323,16 @@ impl RiskEngine {
 
         // Update total open interest tracking (O(1))
         // OI = sum of abs(position_size) across all accounts
-        let old_oi =
-            saturating_abs_i128(old_user_pos) as u128 + saturating_abs_i128(old_lp_pos) as u128;
-        let new_oi = saturating_abs_i128(new_user_position) as u128
-            + saturating_abs_i128(new_lp_position) as u128;
+        let old_oi = (saturating_abs_i128(old_user_pos) as u128)
+            .saturating_add(saturating_abs_i128(old_lp_pos) as u128);
+        let new_oi = (saturating_abs_i128(new_user_position) as u128)
+            .saturating_add(saturating_abs_i128(new_lp_position) as u128);
         if new_oi > old_oi {
-            self.total_open_interest = self.total_open_interest.saturating_add(new_oi - old_oi);
+            self.total_open_interest =
+                self.total_open_interest.saturating_add(new_oi.saturating_sub(old_oi));
         } else {
-            self.total_open_interest = self.total_open_interest.saturating_sub(old_oi - new_oi);
+            self.total_open_interest =
+                self.total_open_interest.saturating_sub(old_oi.saturating_sub(new_oi));
         }
 
         // Update LP aggregates for funding/threshold (O(1))
@@ -3123,9 +11345,9 @@ impl RiskEngine {
             .saturating_add(new_lp_position);
         // lp_sum_abs: delta of abs values
         if new_lp_abs > old_lp_abs {
-            self.lp_sum_abs = self.lp_sum_abs.saturating_add(new_lp_abs - old_lp_abs);
+            self.lp_sum_abs = self.lp_sum_abs.saturating_add(new_lp_abs.saturating_sub(old_lp_abs));
         } else {
-            self.lp_sum_abs = self.lp_sum_abs.saturating_sub(old_lp_abs - new_lp_abs);
+            self.lp_sum_abs = self.lp_sum_abs.saturating_sub(old_lp_abs.saturating_sub(new_lp_abs));
         }
         // lp_max_abs: monotone increase only (conservative upper bound)
         self.lp_max_abs = U128::new(self.lp_max_abs.get().max(new_lp_abs));
@@ -3135,23 +11357,47 @@ impl RiskEngine {
         // the winner's profit conversion reads the haircut ratio. Without this,
         // the winner's matured PnL can be haircutted to 0 because Residual
         // hasn't been increased by the loser's loss settlement yet (Finding G).
-        self.settle_loss_only(user_idx)?;
-        self.settle_loss_only(lp_idx)?;
+        self.settle_loss_only_inner(user_idx)?;
+        self.settle_loss_only_inner(lp_idx)?;
         // Now Residual reflects realized losses; profit conversion uses correct h.
-        self.settle_warmup_to_capital(user_idx)?;
-        self.settle_warmup_to_capital(lp_idx)?;
+        self.settle_warmup_to_capital_inner(user_idx)?;
+        self.settle_warmup_to_capital_inner(lp_idx)?;
 
         // Now recompute warmup slopes after PnL changes (resets started_at_slot)
         self.update_warmup_slope(user_idx)?;
         self.update_warmup_slope(lp_idx)?;
 
-        Ok(())
+        self.record_event(EventKind::Trade, user_idx, lp_idx, fee, exec_size, exec_price);
+        self.observe_exec_deviation(user_idx, lp_idx, now_slot, exec_price, oracle_price);
+        self.record_markout_fill(user_idx as usize, exec_size, exec_price, now_slot);
+        self.accrue_volume_emissions(lp_idx as usize, notional);
+
+        self.last_fill_id = self.last_fill_id.saturating_add(1);
+        self.last_exec_price = exec_price;
+        self.last_exec_slot = now_slot;
+        let user = &self.accounts[user_idx as usize];
+        Ok(TradeReceipt {
+            fill_id: self.last_fill_id,
+            exec_price,
+            exec_size,
+            fee,
+            new_position: user.position_size.get(),
+            new_margin_ratio_bps: self.margin_ratio_bps(user, oracle_price),
+        })
     }
     /// Settle loss only (§6.1): negative PnL pays from capital immediately.
     /// If PnL still negative after capital exhausted, write off via set_pnl(i, 0).
     /// Used in two-pass settlement to ensure all losses are realized (increasing
     /// Residual) before any profit conversions use the haircut ratio.
     pub fn settle_loss_only(&mut self, idx: u16) -> Result<()> {
+        let result = self.settle_loss_only_inner(idx);
+        if result.is_ok() {
+            self.bump_state_seq();
+        }
+        result
+    }
+
+    fn settle_loss_only_inner(&mut self, idx: u16) -> Result<()> {
         if !self.is_used(idx as usize) {
             return Err(RiskError::AccountNotFound);
         }
@@ -3163,8 +11409,8 @@ impl RiskEngine {
             let pay = core::cmp::min(need, capital);
 
             if pay > 0 {
-                self.set_capital(idx as usize, capital - pay);
-                self.set_pnl(idx as usize, pnl.saturating_add(pay as i128));
+                self.set_capital(idx as usize, capital.saturating_sub(pay));
+                self.set_pnl(idx as usize, pnl.saturating_add_unsigned(pay));
             }
 
             // Write off any remaining negative PnL (spec §6.1 step 4)
@@ -3184,6 +11430,12 @@ impl RiskEngine {
     /// §6.2 Profit conversion: warmable gross profit converts to capital at haircut ratio h.
     ///   y = floor(x * h_num / h_den), where (h_num, h_den) is computed pre-conversion.
     pub fn settle_warmup_to_capital(&mut self, idx: u16) -> Result<()> {
+        let result = self.settle_warmup_to_capital_inner(idx);
+        self.finish_mutation("settle_warmup_to_capital", result.is_ok());
+        result
+    }
+
+    fn settle_warmup_to_capital_inner(&mut self, idx: u16) -> Result<()> {
         if !self.is_used(idx as usize) {
             return Err(RiskError::AccountNotFound);
         }
@@ -3196,8 +11448,8 @@ impl RiskEngine {
             let pay = core::cmp::min(need, capital);
 
             if pay > 0 {
-                self.set_capital(idx as usize, capital - pay);
-                self.set_pnl(idx as usize, pnl.saturating_add(pay as i128));
+                self.set_capital(idx as usize, capital.saturating_sub(pay));
+                self.set_pnl(idx as usize, pnl.saturating_add_unsigned(pay));
             }
 
             // Write off any remaining negative PnL (spec §6.1 step 4)
@@ -3227,14 +11479,20 @@ impl RiskEngine {
                 let y = if h_den == 0 {
                     x
                 } else {
-                    mul_u128(x, h_num) / h_den
+                    mul_u128(x, h_num).checked_div(h_den).unwrap_or(0)
                 };
 
                 // Reduce junior profit claim by x
-                self.set_pnl(idx as usize, pnl - (x as i128));
+                self.set_pnl(idx as usize, pnl.saturating_sub_unsigned(x));
                 // Increase protected principal by y
                 let new_cap = add_u128(self.accounts[idx as usize].capital.get(), y);
                 self.set_capital(idx as usize, new_cap);
+                self.record_epoch_drain(idx as usize, y, 0);
+                // The haircut becomes a claim on future insurance surplus
+                if self.ext_params.haircut_claim_payout_bps > 0 && y < x {
+                    let claim = &mut self.accounts[idx as usize].haircut_claim;
+                    *claim = claim.saturating_add(x.saturating_sub(y));
+                }
             }
 
             // Advance warmup time base and update slope (spec §5.4)
@@ -3250,7 +11508,10 @@ impl RiskEngine {
             let slope = if new_avail == 0 {
                 0
             } else if self.params.warmup_period_slots > 0 {
-                core::cmp::max(1, new_avail / (self.params.warmup_period_slots as u128))
+                let base = new_avail
+                    .checked_div(u128::from(self.params.warmup_period_slots))
+                    .unwrap_or(0);
+                core::cmp::max(1, base)
             } else {
                 new_avail
             };
@@ -3265,9 +11526,23 @@ impl RiskEngine {
 
     /// Top up insurance fund
     ///
-    /// Adds tokens to both vault and insurance fund.
+    /// Adds tokens to both vault and insurance fund (Overflow if the vault would
+    /// exceed u128::MAX).
     /// Returns true if the top-up brings insurance above the risk reduction threshold.
     pub fn top_up_insurance_fund(&mut self, amount: u128) -> Result<bool> {
+        let result = self.top_up_insurance_fund_inner(amount);
+        if result.is_ok() {
+            self.record_event(EventKind::InsuranceTopUp, EVENT_NO_ACCOUNT, EVENT_NO_ACCOUNT, amount, 0, 0);
+        }
+        self.finish_mutation("top_up_insurance_fund", result.is_ok());
+        result
+    }
+
+    fn top_up_insurance_fund_inner(&mut self, amount: u128) -> Result<bool> {
+        if self.vault.get().checked_add(amount).is_none() {
+            return Err(RiskError::Overflow);
+        }
+
         // Add to vault
         self.vault = U128::new(add_u128(self.vault.get(), amount));
 
@@ -3302,6 +11577,7 @@ impl RiskEngine {
         let mut net_mark: i128 = 0;
         let mut mark_ok = true;
         let global_index = self.funding_index_qpb_e6;
+        let divisor = u128_to_i128_clamped(self.price_divisor());
 
         self.for_each_used(|_idx, account| {
             total_capital = add_u128(total_capital, account.capital.get());
@@ -3313,16 +11589,14 @@ impl RiskEngine {
                     .get()
                     .saturating_sub(account.funding_index.get());
                 if delta_f != 0 {
-                    let raw = account.position_size.get().saturating_mul(delta_f);
-                    let payment = if raw > 0 {
-                        raw.saturating_add(999_999).saturating_div(1_000_000)
-                    } else {
-                        raw.saturating_div(1_000_000)
-                    };
+                    let payment = account
+                        .position_size
+                        .saturating_mul_div(delta_f, divisor, Rounding::Up)
+                        .get();
                     settled_pnl = settled_pnl.saturating_sub(payment);
                 }
 
-                match Self::mark_pnl_for_position(
+                match self.mark_pnl(
                     account.position_size.get(),
                     account.entry_price,
                     oracle_price,
@@ -3342,16 +11616,24 @@ impl RiskEngine {
             return false;
         }
 
+        // Released LP withdrawals stay in the vault until claimed
+        let claimable = self.lp_claimable_total.get();
+
         // Conservation: vault >= C_tot + I (primary invariant)
         let primary = self.vault.get()
-            >= total_capital.saturating_add(self.insurance_fund.balance.get());
+            >= total_capital
+                .saturating_add(self.insurance_fund.balance.get())
+                .saturating_add(claimable);
         if !primary {
             return false;
         }
 
         // Extended: vault >= sum(capital) + sum(settled_pnl + mark_pnl) + insurance
         let total_pnl = net_pnl.saturating_add(net_mark);
-        let base = add_u128(total_capital, self.insurance_fund.balance.get());
+        let base = add_u128(
+            add_u128(total_capital, self.insurance_fund.balance.get()),
+            claimable,
+        );
 
         let expected = if total_pnl >= 0 {
             add_u128(base, total_pnl as u128)
@@ -3364,10 +11646,319 @@ impl RiskEngine {
         if actual < expected {
             return false;
         }
-        let slack = actual - expected;
+        let slack = actual.saturating_sub(expected);
         slack <= MAX_ROUNDING_SLACK
     }
 
+    /// Cheap consistency checks for monitoring: recomputes the aggregates in one
+    /// pass over used accounts and sanity-checks account values and parameters.
+    /// Returns a bitmask of `HEALTH_*` anomalies (0 = healthy). Never mutates.
+    pub fn health_check(&self) -> u32 {
+        let mut anomalies = 0u32;
+        let mut sum_capital = 0u128;
+        let mut sum_pnl_pos = 0u128;
+        let mut sum_oi = 0u128;
+        let mut net_lp_pos = 0i128;
+        let mut lp_sum_abs = 0u128;
+        let mut lp_claimable = 0u128;
+        let mut used = 0u64;
+        self.for_each_used(|idx, account| {
+            used = used.saturating_add(1);
+            sum_capital = sum_capital.saturating_add(account.capital.get());
+            sum_pnl_pos = sum_pnl_pos.saturating_add(clamp_pos_i128(account.pnl.get()));
+            let pos = account.position_size.get();
+            sum_oi = sum_oi.saturating_add(pos.unsigned_abs());
+            if account.is_lp() {
+                net_lp_pos = net_lp_pos.saturating_add(pos);
+                lp_sum_abs = lp_sum_abs.saturating_add(pos.unsigned_abs());
+                lp_claimable = lp_claimable
+                    .saturating_add(account.lp_claimable.get())
+                    .saturating_add(self.lp_holders_claimable(idx));
+            }
+            let sentinel = account.pnl.get() == i128::MIN
+                || pos == i128::MIN
+                || account.fee_credits.get() == i128::MIN;
+            let bad_entry = account.entry_price == 0 || account.entry_price > MAX_ORACLE_PRICE;
+            let bad_position = pos.unsigned_abs() > MAX_POSITION_ABS || (pos != 0 && bad_entry);
+            let bad_reserve = account.reserved_pnl as u128 > clamp_pos_i128(account.pnl.get());
+            if sentinel || bad_position || bad_reserve {
+                anomalies |= HEALTH_ACCOUNT_STATE;
+            }
+        });
+
+        if sum_capital != self.c_tot.get() {
+            anomalies |= HEALTH_CAPITAL_TOTAL;
+        }
+        if sum_pnl_pos != self.pnl_pos_tot.get() {
+            anomalies |= HEALTH_PNL_POS_TOTAL;
+        }
+        if sum_oi != self.total_open_interest.get() {
+            anomalies |= HEALTH_OPEN_INTEREST;
+        }
+        if net_lp_pos != self.net_lp_pos.get()
+            || lp_sum_abs != self.lp_sum_abs.get()
+            || lp_claimable != self.lp_claimable_total.get()
+        {
+            anomalies |= HEALTH_LP_AGGREGATES;
+        }
+        if used != self.num_used_accounts as u64 || used > self.params.max_accounts {
+            anomalies |= HEALTH_ACCOUNT_COUNT;
+        }
+        let backing = sum_capital
+            .saturating_add(self.insurance_fund.balance.get())
+            .saturating_add(self.lp_claimable_total.get());
+        if self.vault.get() < backing {
+            anomalies |= HEALTH_VAULT_SHORTFALL;
+        }
+        if self.revenue.total() != self.insurance_fund.fee_revenue.get() {
+            anomalies |= HEALTH_REVENUE;
+        }
+        let params = &self.params;
+        let params_ok = params.maintenance_margin_bps <= params.initial_margin_bps
+            && params.initial_margin_bps <= 10_000
+            && params.trading_fee_bps <= 10_000
+            && params.liquidation_fee_bps <= 10_000
+            && params.max_accounts <= MAX_ACCOUNTS as u64;
+        if !params_ok || Self::validate_ext_params(&self.ext_params).is_err() {
+            anomalies |= HEALTH_PARAMS;
+        }
+        anomalies
+    }
+
+    // ========================================
+    // Telemetry
+    // ========================================
+
+    /// `execute_trade`, reporting the fill (or rejection) to `telemetry`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn execute_trade_with_telemetry<M: MatchingEngine, T: Telemetry>(
+        &mut self,
+        matcher: &M,
+        lp_idx: u16,
+        user_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+        size: i128,
+        telemetry: &mut T,
+    ) -> Result<TradeReceipt> {
+        let pos_before = self
+            .accounts
+            .get(user_idx as usize)
+            .map(|a| a.position_size.get())
+            .unwrap_or(0);
+        let result = self.execute_trade(matcher, lp_idx, user_idx, now_slot, oracle_price, size);
+        match result {
+            Ok(_) => {
+                let filled = self.accounts[user_idx as usize]
+                    .position_size
+                    .get()
+                    .saturating_sub(pos_before)
+                    .unsigned_abs();
+                telemetry.counter(METRIC_TRADES, 1);
+                telemetry.histogram(METRIC_TRADE_SIZE, filled);
+                telemetry.histogram(
+                    METRIC_TRADE_NOTIONAL,
+                    self.notional_at(filled, oracle_price, Rounding::Down),
+                );
+                self.emit_gauges(telemetry);
+            }
+            Err(_) => telemetry.counter(METRIC_TRADES_REJECTED, 1),
+        }
+        result
+    }
+
+    /// `liquidate_at_oracle_with_keeper`, reporting any liquidation to `telemetry`.
+    pub fn liquidate_with_telemetry<T: Telemetry>(
+        &mut self,
+        idx: u16,
+        keeper_idx: Option<u16>,
+        now_slot: u64,
+        oracle_price: u64,
+        telemetry: &mut T,
+    ) -> Result<Option<LiquidationRecord>> {
+        let result = self.liquidate_at_oracle_with_keeper(idx, keeper_idx, now_slot, oracle_price);
+        if let Ok(Some(record)) = &result {
+            telemetry.counter(METRIC_LIQUIDATIONS, 1);
+            telemetry.histogram(METRIC_LIQUIDATION_SIZE, record.closed_abs);
+            telemetry.histogram(METRIC_LIQUIDATION_FEE, record.fee_total);
+            self.emit_gauges(telemetry);
+        }
+        result
+    }
+
+    /// `keeper_crank`, reporting the outcome and cap hits to `telemetry`.
+    ///
+    /// `duration_us` is measured by the caller (the engine has no clock) and is
+    /// recorded as-is when present.
+    #[allow(clippy::too_many_arguments)]
+    pub fn keeper_crank_with_telemetry<T: Telemetry>(
+        &mut self,
+        caller_idx: u16,
+        now_slot: u64,
+        oracle_price: u64,
+        funding_rate_bps_per_slot: i64,
+        allow_panic: bool,
+        max_pnl_vault_bps: u64,
+        max_oi_abs: u128,
+        duration_us: Option<u64>,
+        telemetry: &mut T,
+    ) -> Result<CrankOutcome> {
+        let result = self.keeper_crank(
+            caller_idx,
+            now_slot,
+            oracle_price,
+            funding_rate_bps_per_slot,
+            allow_panic,
+            max_pnl_vault_bps,
+            max_oi_abs,
+        );
+        if let Some(d) = duration_us {
+            telemetry.histogram(METRIC_CRANK_DURATION_US, d as u128);
+        }
+        if let Ok(outcome) = &result {
+            telemetry.counter(METRIC_CRANKS, 1);
+            if !outcome.skipped {
+                let work = [
+                    (METRIC_CRANK_SETTLE_ACCOUNTS, outcome.settle_work),
+                    (METRIC_CRANK_LIQUIDATE_ACCOUNTS, outcome.liquidate_work),
+                    (METRIC_CRANK_GC_ACCOUNTS, outcome.gc_work),
+                ];
+                for (name, phase) in work {
+                    telemetry.histogram(name, phase.accounts as u128);
+                }
+            }
+            if outcome.num_liquidations > 0 {
+                telemetry.counter(METRIC_LIQUIDATIONS, outcome.num_liquidations as u64);
+            }
+            if outcome.num_liq_errors > 0 {
+                telemetry.counter(METRIC_LIQUIDATION_ERRORS, outcome.num_liq_errors as u64);
+            }
+            if outcome.oi_cap_active {
+                telemetry.counter(METRIC_OI_CAP_HITS, 1);
+            }
+            if outcome.max_pnl_closed > 0 {
+                telemetry.counter(METRIC_MAX_PNL_CAP_CLOSES, outcome.max_pnl_closed as u64);
+            }
+            if outcome.force_realize_closed > 0 {
+                telemetry.counter(METRIC_FORCE_REALIZE_CLOSES, outcome.force_realize_closed as u64);
+            }
+            self.emit_gauges(telemetry);
+        }
+        result
+    }
+
+    fn emit_gauges<T: Telemetry>(&self, telemetry: &mut T) {
+        telemetry.gauge(METRIC_INSURANCE_BALANCE, self.insurance_fund.balance.get());
+        telemetry.gauge(METRIC_OPEN_INTEREST, self.total_open_interest.get());
+        telemetry.gauge(METRIC_VAULT, self.vault.get());
+    }
+
+    /// Strict invariant check run after each public entry point that moves value
+    /// (`add_user`, `add_lp`, `deposit`, `deposit_fee_credits`, `withdraw`,
+    /// `execute_trade`, `close_account`, `keeper_crank`,
+    /// `liquidate_at_oracle_with_keeper`, `top_up_insurance_fund`,
+    /// `garbage_collect_dust`, `touch_account_full`).
+    ///
+    /// Only active with the `strict-invariants` feature; panics with a diagnostic
+    /// naming the operation and the violated invariant:
+    /// - c_tot == Σ capital, pnl_pos_tot == Σ max(pnl, 0)
+    /// - total_open_interest == Σ |position|
+    /// - vault >= c_tot + insurance, and no single account's capital exceeds vault
+    #[cfg(feature = "strict-invariants")]
+    fn strict_check_invariants(&self, op: &'static str) {
+        let mut sum_capital = 0u128;
+        let mut sum_pnl_pos = 0u128;
+        let mut sum_oi = 0u128;
+        let vault = self.vault.get();
+        self.for_each_used(|idx, account| {
+            let capital = account.capital.get();
+            assert!(
+                capital <= vault,
+                "strict-invariants after {}: account {} capital {} exceeds vault {} (wrapped?)",
+                op,
+                idx,
+                capital,
+                vault
+            );
+            sum_capital = add_u128(sum_capital, capital);
+            sum_pnl_pos = add_u128(sum_pnl_pos, clamp_pos_i128(account.pnl.get()));
+            sum_oi = add_u128(sum_oi, account.position_size.unsigned_abs());
+        });
+        assert!(
+            sum_capital == self.c_tot.get(),
+            "strict-invariants after {}: c_tot {} != sum(capital) {}",
+            op,
+            self.c_tot.get(),
+            sum_capital
+        );
+        assert!(
+            sum_pnl_pos == self.pnl_pos_tot.get(),
+            "strict-invariants after {}: pnl_pos_tot {} != sum(max(pnl, 0)) {}",
+            op,
+            self.pnl_pos_tot.get(),
+            sum_pnl_pos
+        );
+        assert!(
+            sum_oi == self.total_open_interest.get(),
+            "strict-invariants after {}: total_open_interest {} != sum(|position|) {}",
+            op,
+            self.total_open_interest.get(),
+            sum_oi
+        );
+        let insurance = self.insurance_fund.balance.get();
+        let claimable = self.lp_claimable_total.get();
+        assert!(
+            vault >= add_u128(add_u128(sum_capital, insurance), claimable),
+            "strict-invariants after {}: vault {} < c_tot {} + insurance {} + lp claimable {}",
+            op,
+            vault,
+            sum_capital,
+            insurance,
+            claimable
+        );
+        assert!(
+            self.revenue.total() == self.insurance_fund.fee_revenue.get(),
+            "strict-invariants after {}: revenue breakdown {} != fee_revenue {}",
+            op,
+            self.revenue.total(),
+            self.insurance_fund.fee_revenue.get()
+        );
+    }
+
+    #[cfg(not(feature = "strict-invariants"))]
+    #[inline(always)]
+    fn strict_check_invariants(&self, _op: &'static str) {}
+
+    /// Epilogue of a public mutating wrapper: bump `state_seq` if the operation
+    /// succeeded, then run the strict invariant checks.
+    fn finish_mutation(&mut self, op: &'static str, committed: bool) {
+        if committed {
+            self.bump_state_seq();
+        }
+        self.strict_check_invariants(op);
+    }
+
+    fn bump_state_seq(&mut self) {
+        self.state_seq = self.state_seq.saturating_add(1);
+    }
+
+    /// Run `op` only if the state is still at `expected` (`None` = unconditional).
+    ///
+    /// Optimistic concurrency for off-chain routers: read `state_seq` with a
+    /// snapshot, build the action from it, and submit the action wrapped here so
+    /// it fails with `StaleSequence` if anything changed in between.
+    pub fn with_expected_seq<T, F>(&mut self, expected: Option<u64>, op: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        if let Some(seq) = expected {
+            if seq != self.state_seq {
+                return Err(RiskError::StaleSequence);
+            }
+        }
+        op(self)
+    }
+
     /// Advance to next slot (for testing warmup)
     pub fn advance_slot(&mut self, slots: u64) {
         self.current_slot = self.current_slot.saturating_add(slots);
@@ -0,0 +1,142 @@
+// ============================================================================
+// Portfolio Margining
+// ============================================================================
+//
+// A `RiskEngine` margins one market. A wrapper that runs several engines for
+// one owner can use this module to compute a single requirement across all of
+// them, offsetting correlated exposures instead of summing per-market
+// requirements.
+//
+// Scenario grid: each market i has a stress move m_i (its maintenance margin,
+// in bps). For every driver market k and direction d ∈ {+1, −1}, market i
+// moves by d · ρ_ik · m_i, where ρ is the configured correlation matrix. The
+// portfolio requirement is the worst loss over the 2N scenarios, floored at
+// `floor_bps` of gross notional and capped at the sum of per-market
+// requirements (portfolio margining never costs more than isolated margin).
+
+use crate::{mul_div, Result, RiskError, Rounding};
+
+/// Maximum number of markets in one portfolio.
+pub const MAX_PORTFOLIO_MARKETS: usize = 16;
+
+/// Correlation of 1.0 in bps.
+pub const CORRELATION_ONE_BPS: i64 = 10_000;
+
+/// One market's exposure within a portfolio.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PortfolioExposure {
+    /// Signed position notional in collateral units (+ long, − short)
+    pub notional: i128,
+    /// Stress move for this market, in bps (typically its maintenance margin)
+    pub margin_bps: u64,
+}
+
+/// Breakdown of a portfolio requirement.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PortfolioMargin {
+    /// Sum of per-market requirements (isolated margin)
+    pub isolated: u128,
+    /// Worst scenario loss on the grid
+    pub worst_scenario_loss: u128,
+    /// Requirement to enforce: worst loss, floored and capped at `isolated`
+    pub requirement: u128,
+    /// Index of the driver market of the worst scenario
+    pub worst_driver: usize,
+    /// Direction of the worst scenario (true = driver up)
+    pub worst_driver_up: bool,
+}
+
+/// Portfolio requirement over `exposures` with an N×N row-major correlation
+/// matrix in bps (`correlation_bps[i * n + k]` = ρ_ik).
+///
+/// The matrix must be symmetric with 1.0 on the diagonal and entries in
+/// [−1.0, 1.0].
+pub fn portfolio_margin(
+    exposures: &[PortfolioExposure],
+    correlation_bps: &[i64],
+    floor_bps: u64,
+) -> Result<PortfolioMargin> {
+    let n = exposures.len();
+    if n > MAX_PORTFOLIO_MARKETS || correlation_bps.len() != n.saturating_mul(n) {
+        return Err(RiskError::InvalidParams);
+    }
+    for i in 0..n {
+        for k in 0..n {
+            let rho = correlation_bps[i.saturating_mul(n).saturating_add(k)];
+            let mirrored = correlation_bps[k.saturating_mul(n).saturating_add(i)];
+            if rho.unsigned_abs() > CORRELATION_ONE_BPS as u64
+                || rho != mirrored
+                || (i == k && rho != CORRELATION_ONE_BPS)
+            {
+                return Err(RiskError::InvalidParams);
+            }
+        }
+    }
+
+    let mut isolated: u128 = 0;
+    let mut gross: u128 = 0;
+    for e in exposures {
+        let abs = e.notional.unsigned_abs();
+        gross = gross.saturating_add(abs);
+        let requirement = mul_div(abs, e.margin_bps as u128, 10_000, Rounding::Up);
+        isolated = isolated.saturating_add(requirement);
+    }
+
+    let mut result = PortfolioMargin {
+        isolated,
+        ..PortfolioMargin::default()
+    };
+    for driver in 0..n {
+        for up in [true, false] {
+            let loss = scenario_loss(exposures, correlation_bps, driver, up);
+            if loss > result.worst_scenario_loss {
+                result.worst_scenario_loss = loss;
+                result.worst_driver = driver;
+                result.worst_driver_up = up;
+            }
+        }
+    }
+
+    let floor = mul_div(gross, floor_bps as u128, 10_000, Rounding::Up);
+    result.requirement = core::cmp::min(
+        core::cmp::max(result.worst_scenario_loss, floor),
+        isolated,
+    );
+    Ok(result)
+}
+
+/// Portfolio loss (0 if the scenario is a gain) when `driver` moves by its
+/// full stress in direction `up` and every other market follows by its correlation.
+fn scenario_loss(
+    exposures: &[PortfolioExposure],
+    correlation_bps: &[i64],
+    driver: usize,
+    up: bool,
+) -> u128 {
+    let n = exposures.len();
+    let mut pnl: i128 = 0;
+    for (i, e) in exposures.iter().enumerate() {
+        let rho = correlation_bps[i.saturating_mul(n).saturating_add(driver)];
+        // Sign of the PnL: position sign × move sign
+        let move_up = up == (rho >= 0);
+        let gains = (e.notional >= 0) == move_up;
+        // |PnL| = |notional| · |ρ| · m_i (bps × bps); losses round up, gains down
+        let move_abs = mul_div(
+            e.notional.unsigned_abs(),
+            (rho.unsigned_abs() as u128).saturating_mul(e.margin_bps as u128),
+            10_000 * 10_000,
+            if gains { Rounding::Down } else { Rounding::Up },
+        );
+        let signed = i128::try_from(move_abs).unwrap_or(i128::MAX);
+        pnl = if gains {
+            pnl.saturating_add(signed)
+        } else {
+            pnl.saturating_sub(signed)
+        };
+    }
+    if pnl < 0 {
+        pnl.unsigned_abs()
+    } else {
+        0
+    }
+}
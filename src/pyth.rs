@@ -0,0 +1,104 @@
+// ============================================================================
+// Pyth Oracle Adapter (requires the `pyth` feature)
+// ============================================================================
+//
+// Parses a Pyth v2 price account into an `OracleQuote` at the market's price
+// scale, so the on-chain program and off-chain simulators share one parser.
+// Only the aggregate price is used. Byte offsets (little-endian):
+//
+//   0   magic u32 (0xa1b2c3d4)     4  version u32 (2)     8  account type u32 (3 = price)
+//   20  expo i32
+//   208 agg.price i64   216 agg.conf u64   224 agg.status u32   232 agg.pub_slot u64
+
+use crate::{oracle_quote_fresh, read_le, rescale_price, OracleQuote, Result, RiskError, Rounding};
+
+pub const PYTH_MAGIC: u32 = 0xa1b2_c3d4;
+pub const PYTH_VERSION: u32 = 2;
+pub const PYTH_ACCOUNT_TYPE_PRICE: u32 = 3;
+/// Aggregate status: publishers are trading and the price is valid
+pub const PYTH_STATUS_TRADING: u32 = 1;
+/// Bytes needed to read every field this adapter uses
+pub const PYTH_PRICE_ACCOUNT_MIN_LEN: usize = 240;
+
+/// Largest |expo| accepted (10^18 still fits in u64)
+const MAX_PYTH_EXPO: u32 = 18;
+
+/// Aggregate price read from a Pyth price account, in Pyth's own units.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PythPrice {
+    /// price × 10^expo is the real price
+    pub price: i64,
+    pub conf: u64,
+    pub expo: i32,
+    pub status: u32,
+    pub publish_slot: u64,
+}
+
+impl PythPrice {
+    /// Normalize to `price_scale` (e.g. 1_000_000 for e6). Price rounds down,
+    /// confidence rounds up.
+    pub fn to_quote(&self, feed: [u8; 32], price_scale: u64) -> Result<OracleQuote> {
+        if self.status != PYTH_STATUS_TRADING || self.price <= 0 {
+            return Err(RiskError::OracleUnavailable);
+        }
+        if self.expo.unsigned_abs() > MAX_PYTH_EXPO {
+            return Err(RiskError::InvalidParams);
+        }
+        let pow = 10u64.pow(self.expo.unsigned_abs());
+        // price × 10^expo × scale: divide by 10^|expo| or multiply by 10^expo
+        let (from_scale, to_scale) = if self.expo < 0 {
+            (pow, price_scale)
+        } else {
+            (1, price_scale.saturating_mul(pow))
+        };
+        let price = rescale_price(self.price.unsigned_abs(), from_scale, to_scale, Rounding::Down);
+        if price == 0 {
+            return Err(RiskError::OracleUnavailable);
+        }
+        Ok(OracleQuote {
+            feed,
+            price,
+            conf: rescale_price(self.conf, from_scale, to_scale, Rounding::Up),
+            publish_slot: self.publish_slot,
+        })
+    }
+}
+
+/// Parse the aggregate price from a Pyth v2 price account's data.
+pub fn parse_price_account(data: &[u8]) -> Result<PythPrice> {
+    if data.len() < PYTH_PRICE_ACCOUNT_MIN_LEN {
+        return Err(RiskError::InvalidParams);
+    }
+    if u32::from_le_bytes(read_le(data, 0)) != PYTH_MAGIC
+        || u32::from_le_bytes(read_le(data, 4)) != PYTH_VERSION
+        || u32::from_le_bytes(read_le(data, 8)) != PYTH_ACCOUNT_TYPE_PRICE
+    {
+        return Err(RiskError::InvalidParams);
+    }
+    Ok(PythPrice {
+        price: i64::from_le_bytes(read_le(data, 208)),
+        conf: u64::from_le_bytes(read_le(data, 216)),
+        expo: i32::from_le_bytes(read_le(data, 20)),
+        status: u32::from_le_bytes(read_le(data, 224)),
+        publish_slot: u64::from_le_bytes(read_le(data, 232)),
+    })
+}
+
+/// Parse, normalize and check a Pyth price account in one step.
+///
+/// Rejects quotes older than `max_staleness_slots` (0 = unchecked) or with a
+/// confidence interval wider than `max_conf_bps` of price (0 = unchecked).
+pub fn load_pyth_quote(
+    data: &[u8],
+    feed: [u8; 32],
+    now_slot: u64,
+    max_staleness_slots: u64,
+    max_conf_bps: u64,
+    price_scale: u64,
+) -> Result<OracleQuote> {
+    let quote = parse_price_account(data)?.to_quote(feed, price_scale)?;
+    if !oracle_quote_fresh(&quote, now_slot, max_staleness_slots, max_conf_bps) {
+        return Err(RiskError::OracleUnavailable);
+    }
+    Ok(quote)
+}
@@ -0,0 +1,345 @@
+// ============================================================================
+// Read Replicas
+// ============================================================================
+//
+// RPC services answer margin queries for thousands of accounts a second and
+// have no use for the matcher, mutation or crank machinery of a full engine.
+// `encode_read_replica` writes just what those queries need, with every
+// state-dependent input (haircut, fee debt, credit line, conditional-order
+// reservation) already resolved, and `RiskEngineView` reads it in place from the
+// raw bytes: no copy of the account table, and account lookup is a binary search
+// over the records. Initial margin depends on the slot through the margin event
+// schedule, so the schedule and leverage caps are replicated rather than a
+// resolved tier. Results match the engine's own margin predicates at any oracle
+// price (and, for initial margin, any slot), as of the encoded state.
+//
+// Replica layout (little-endian):
+//
+//   version u8 | slot u64 | state_seq u64 | payoff_mode u8 | price_divisor u128 |
+//   collateral_rate_e9 u64 | maintenance_margin_bps u64 | last_oracle_price u64 |
+//   initial_margin_bps u64 | max_leverage u16 | event_initial_margin_bps u64 |
+//   event_margin_window_slots u64 | event_period_slots u64 |
+//   margin_events [u64; MAX_MARGIN_EVENTS] | num_accounts u16 |
+//   num_accounts × (idx u16 | account_id u64 | kind u8 | owner [32] |
+//                   capital u128 | pnl i128 | effective_pos_pnl u128 | fee_debt u128 |
+//                   position i128 | entry_price u64 | max_leverage u16 |
+//                   credit u128 | conditional_margin u128)
+//
+// Accounts appear in index order. Version 1 carried each account's initial
+// margin resolved at the encoding slot and no reservation; it is no longer read.
+
+use crate::{
+    event_margin_bps_for, leverage_capped_bps, margin_required_for, mul_div, neg_i128_to_u128,
+    notional_for, saturating_abs_i128, u128_to_i128_clamped, AccountKind, ByteReader, ByteWriter,
+    PayoffMode, Result, RiskEngine, RiskError, Rounding, MAX_ACCOUNTS, MAX_MARGIN_EVENTS,
+};
+
+/// Current read replica encoding version.
+pub const REPLICA_VERSION: u8 = 2;
+
+/// Encoded size of the replica header.
+pub const REPLICA_HEADER_LEN: usize =
+    1 + 8 * 2 + 1 + 16 + 8 * 3 + 8 + 2 + 8 * 3 + 8 * MAX_MARGIN_EVENTS + 2;
+
+/// Encoded size of one account record in a replica.
+pub const REPLICA_ACCOUNT_LEN: usize = 2 + 8 + 1 + 32 + 16 * 7 + 8 + 2;
+
+/// One account as stored in a read replica.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReplicaAccount {
+    pub idx: u16,
+    pub account_id: u64,
+    pub kind: AccountKind,
+    pub owner: [u8; 32],
+    pub capital: u128,
+    pub pnl: i128,
+    /// Positive PnL after the haircut (see `RiskEngine::effective_pos_pnl`)
+    pub effective_pos_pnl: u128,
+    /// Unpaid fees (negative `fee_credits`)
+    pub fee_debt: u128,
+    pub position: i128,
+    pub entry_price: u64,
+    /// Self-imposed leverage cap (0 = none)
+    pub max_leverage: u16,
+    /// Credit line counted as margin (see `RiskEngine::credit_available`)
+    pub credit: u128,
+    /// Margin held for resting conditional orders (see `RiskEngine::conditional_margin`)
+    pub conditional_margin: u128,
+}
+
+/// Read-only view of an engine state, decoded in place from replica bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RiskEngineView<'a> {
+    pub slot: u64,
+    /// `state_seq` of the replicated state
+    pub state_seq: u64,
+    pub payoff_mode: PayoffMode,
+    pub price_divisor: u128,
+    pub collateral_rate_e9: u64,
+    pub maintenance_margin_bps: u64,
+    pub last_oracle_price: u64,
+    pub initial_margin_bps: u64,
+    /// Global leverage cap (`ExtParams::max_leverage`)
+    pub max_leverage: u16,
+    pub event_initial_margin_bps: u64,
+    pub event_margin_window_slots: u64,
+    pub event_period_slots: u64,
+    pub margin_events: [u64; MAX_MARGIN_EVENTS],
+    pub num_accounts: u16,
+    records: &'a [u8],
+}
+
+impl<'a> RiskEngineView<'a> {
+    /// Decode the header of `bytes` (from `encode_read_replica`); account records
+    /// are read on demand.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        if bytes.len() < REPLICA_HEADER_LEN || bytes[0] != REPLICA_VERSION {
+            return Err(RiskError::InvalidParams);
+        }
+        let mut r = ByteReader { buf: bytes, pos: 1 };
+        let slot = r.u64();
+        let state_seq = r.u64();
+        let payoff_mode = PayoffMode::from_u8(r.take::<1>()[0]).ok_or(RiskError::InvalidParams)?;
+        let price_divisor = r.u128();
+        let collateral_rate_e9 = r.u64();
+        let maintenance_margin_bps = r.u64();
+        let last_oracle_price = r.u64();
+        let initial_margin_bps = r.u64();
+        let max_leverage = u16::from_le_bytes(r.take::<2>());
+        let event_initial_margin_bps = r.u64();
+        let event_margin_window_slots = r.u64();
+        let event_period_slots = r.u64();
+        let mut margin_events = [0; MAX_MARGIN_EVENTS];
+        for event in margin_events.iter_mut() {
+            *event = r.u64();
+        }
+        let num_accounts = u16::from_le_bytes(r.take::<2>());
+        let records = &bytes[REPLICA_HEADER_LEN..];
+        if price_divisor == 0
+            || records.len() != REPLICA_ACCOUNT_LEN.saturating_mul(num_accounts as usize)
+        {
+            return Err(RiskError::InvalidParams);
+        }
+        Ok(Self {
+            slot,
+            state_seq,
+            payoff_mode,
+            price_divisor,
+            collateral_rate_e9,
+            maintenance_margin_bps,
+            last_oracle_price,
+            initial_margin_bps,
+            max_leverage,
+            event_initial_margin_bps,
+            event_margin_window_slots,
+            event_period_slots,
+            margin_events,
+            num_accounts,
+            records,
+        })
+    }
+
+    /// Account `idx`, if it was in use in the replicated state.
+    pub fn account(&self, idx: u16) -> Option<ReplicaAccount> {
+        let (mut lo, mut hi) = (0usize, self.num_accounts as usize);
+        while lo < hi {
+            let mid = lo.saturating_add(hi.saturating_sub(lo).checked_div(2)?);
+            let account = self.record(mid);
+            match account.idx.cmp(&idx) {
+                core::cmp::Ordering::Equal => return Some(account),
+                core::cmp::Ordering::Less => lo = mid.saturating_add(1),
+                core::cmp::Ordering::Greater => hi = mid,
+            }
+        }
+        None
+    }
+
+    /// All replicated accounts in index order.
+    pub fn accounts(&self) -> impl Iterator<Item = ReplicaAccount> + '_ {
+        (0..self.num_accounts as usize).map(move |i| self.record(i))
+    }
+
+    /// MTM equity of `account` at `oracle_price`, as `account_equity_mtm_at_oracle`.
+    pub fn equity_mtm(&self, account: &ReplicaAccount, oracle_price: u64) -> u128 {
+        let mark = match self.payoff_mode {
+            PayoffMode::Linear | PayoffMode::Quanto => RiskEngine::mark_pnl_for_position_scaled(
+                account.position,
+                account.entry_price,
+                oracle_price,
+                self.price_divisor,
+            ),
+            PayoffMode::Inverse => RiskEngine::mark_pnl_for_position_inverse(
+                account.position,
+                account.entry_price,
+                oracle_price,
+                self.price_divisor,
+            ),
+        };
+        let mark = match mark {
+            Ok(m) => m,
+            Err(_) => return 0,
+        };
+        let eq_i = u128_to_i128_clamped(account.capital)
+            .saturating_add(core::cmp::min(account.pnl, 0))
+            .saturating_add(u128_to_i128_clamped(account.effective_pos_pnl))
+            .saturating_add(mark);
+        let eq = if eq_i > 0 { eq_i as u128 } else { 0 };
+        eq.saturating_sub(account.fee_debt)
+    }
+
+    /// Position notional of `account` at `oracle_price` (rounded up).
+    pub fn notional(&self, account: &ReplicaAccount, oracle_price: u64) -> u128 {
+        let abs_pos = saturating_abs_i128(account.position) as u128;
+        notional_for(self.payoff_mode, abs_pos, oracle_price, self.price_divisor, Rounding::Up)
+    }
+
+    /// Margin ratio in bps, as `RiskEngine::margin_ratio_bps` (`u64::MAX` when flat).
+    pub fn margin_ratio_bps(&self, account: &ReplicaAccount, oracle_price: u64) -> u64 {
+        let notional = self.notional(account, oracle_price);
+        if notional == 0 {
+            return u64::MAX;
+        }
+        let equity = self.equity_mtm(account, oracle_price);
+        let equity = match self.collateral_rate_e9 {
+            0 => equity,
+            rate => mul_div(equity, rate as u128, 1_000_000_000, Rounding::Down),
+        };
+        let ratio = mul_div(equity, 10_000, notional, Rounding::Down);
+        core::cmp::min(ratio, u64::MAX as u128) as u64
+    }
+
+    /// Whether `account` is above `bps` margin at `oracle_price`, as
+    /// `RiskEngine::is_above_margin_bps_mtm`.
+    pub fn is_above_margin_bps(
+        &self,
+        account: &ReplicaAccount,
+        oracle_price: u64,
+        bps: u64,
+    ) -> bool {
+        let equity = self.equity_mtm(account, oracle_price).saturating_add(account.credit);
+        let required =
+            margin_required_for(self.notional(account, oracle_price), bps, self.collateral_rate_e9);
+        equity > required
+    }
+
+    pub fn is_above_maintenance_margin(&self, account: &ReplicaAccount, oracle_price: u64) -> bool {
+        self.is_above_margin_bps(account, oracle_price, self.maintenance_margin_bps)
+    }
+
+    /// Initial margin in bps for `account` to add risk at `slot`, as
+    /// `RiskEngine::initial_margin_bps_for` with the engine at `slot`.
+    pub fn initial_margin_bps(&self, account: &ReplicaAccount, slot: u64) -> u64 {
+        let event_bps = event_margin_bps_for(
+            &self.margin_events,
+            self.event_margin_window_slots,
+            self.event_period_slots,
+            self.event_initial_margin_bps,
+            self.initial_margin_bps,
+            slot,
+        );
+        let bps = core::cmp::max(self.initial_margin_bps, event_bps);
+        leverage_capped_bps(bps, [self.max_leverage, account.max_leverage])
+    }
+
+    /// Whether `account` could add risk at `oracle_price` and `slot`: above its
+    /// initial margin with the conditional-order reservation kept free, as the
+    /// engine requires of risk-increasing trades.
+    pub fn is_above_initial_margin(
+        &self,
+        account: &ReplicaAccount,
+        oracle_price: u64,
+        slot: u64,
+    ) -> bool {
+        let equity = self.equity_mtm(account, oracle_price).saturating_add(account.credit);
+        let required = margin_required_for(
+            self.notional(account, oracle_price),
+            self.initial_margin_bps(account, slot),
+            self.collateral_rate_e9,
+        );
+        equity > required.saturating_add(account.conditional_margin)
+    }
+
+    /// Decode the `i`-th record (`i < num_accounts`, checked by `from_bytes`).
+    fn record(&self, i: usize) -> ReplicaAccount {
+        let mut r = ByteReader {
+            buf: self.records,
+            pos: i.saturating_mul(REPLICA_ACCOUNT_LEN),
+        };
+        ReplicaAccount {
+            idx: u16::from_le_bytes(r.take::<2>()),
+            account_id: r.u64(),
+            kind: if r.take::<1>()[0] == AccountKind::LP as u8 {
+                AccountKind::LP
+            } else {
+                AccountKind::User
+            },
+            owner: r.take::<32>(),
+            capital: r.u128(),
+            pnl: i128::from_le_bytes(r.take::<16>()),
+            effective_pos_pnl: r.u128(),
+            fee_debt: r.u128(),
+            position: i128::from_le_bytes(r.take::<16>()),
+            entry_price: r.u64(),
+            max_leverage: u16::from_le_bytes(r.take::<2>()),
+            credit: r.u128(),
+            conditional_margin: r.u128(),
+        }
+    }
+}
+
+impl RiskEngine {
+    /// Encoded size of the read replica of the current state.
+    pub fn read_replica_len(&self) -> usize {
+        REPLICA_HEADER_LEN
+            .saturating_add(REPLICA_ACCOUNT_LEN.saturating_mul(self.num_used_accounts as usize))
+    }
+
+    /// Write the read replica of the current state into `out` (at least
+    /// `read_replica_len()` bytes). Returns the length written.
+    pub fn encode_read_replica(&self, out: &mut [u8]) -> Result<usize> {
+        let mut w = ByteWriter { buf: out, pos: 0 };
+        w.put(&[REPLICA_VERSION])?;
+        w.put(&self.current_slot.to_le_bytes())?;
+        w.put(&self.state_seq.to_le_bytes())?;
+        w.put(&[self.ext_params.payoff_mode as u8])?;
+        w.put(&self.price_divisor().to_le_bytes())?;
+        w.put(&self.collateral_rate_e9.to_le_bytes())?;
+        w.put(&self.params.maintenance_margin_bps.to_le_bytes())?;
+        w.put(&self.last_oracle_price.to_le_bytes())?;
+        let ext = &self.ext_params;
+        w.put(&self.params.initial_margin_bps.to_le_bytes())?;
+        w.put(&ext.max_leverage.to_le_bytes())?;
+        w.put(&ext.event_initial_margin_bps.to_le_bytes())?;
+        w.put(&ext.event_margin_window_slots.to_le_bytes())?;
+        w.put(&ext.event_period_slots.to_le_bytes())?;
+        for event in self.margin_events.iter() {
+            w.put(&event.to_le_bytes())?;
+        }
+        w.put(&self.num_used_accounts.to_le_bytes())?;
+        for idx in 0..MAX_ACCOUNTS {
+            if !self.is_used(idx) {
+                continue;
+            }
+            let a = &self.accounts[idx];
+            let fee_debt = if a.fee_credits.is_negative() {
+                neg_i128_to_u128(a.fee_credits.get())
+            } else {
+                0
+            };
+            w.put(&(idx as u16).to_le_bytes())?;
+            w.put(&a.account_id.to_le_bytes())?;
+            w.put(&[a.kind as u8])?;
+            w.put(&a.owner)?;
+            w.put(&a.capital.get().to_le_bytes())?;
+            w.put(&a.pnl.get().to_le_bytes())?;
+            w.put(&self.effective_pos_pnl(a.pnl.get()).to_le_bytes())?;
+            w.put(&fee_debt.to_le_bytes())?;
+            w.put(&a.position_size.get().to_le_bytes())?;
+            w.put(&a.entry_price.to_le_bytes())?;
+            w.put(&a.max_leverage.to_le_bytes())?;
+            w.put(&self.credit_available(a).to_le_bytes())?;
+            w.put(&self.conditional_margin(a).to_le_bytes())?;
+        }
+        Ok(w.pos)
+    }
+}
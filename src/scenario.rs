@@ -0,0 +1,625 @@
+// ============================================================================
+// Scenario Harness (off-chain, requires the `std` feature)
+// ============================================================================
+//
+// Builders and assertion helpers for deployment-gating simulations: set up a
+// market, add actors, drive the oracle along a price path while cranking, and
+// assert on the resulting state. Everything here panics on failure with a
+// descriptive message — it is meant for tests and simulations, never on-chain.
+// `Checks` / `run_checked` state the same expectations declaratively and collect
+// them into a pass/fail `ScenarioReport` instead of stopping at the first one.
+
+// Off-chain only: the panic-free arithmetic policy does not apply here
+#![allow(clippy::arithmetic_side_effects)]
+
+use std::boxed::Box;
+use std::vec::Vec;
+
+use crate::{
+    AccountKind, CrankOutcome, ExtParams, MatchingEngine, NoOpMatcher, Result, RiskEngine,
+    RiskParams, TradeReceipt, DEFAULT_PRICE_SCALE, U128,
+};
+
+/// Default oracle price for a new market (1.0 in e6)
+pub const DEFAULT_SCENARIO_PRICE: u64 = DEFAULT_PRICE_SCALE;
+
+/// Simulation-friendly parameters: instant warmup, 5% MM / 10% IM, 0.1% trading fee.
+pub fn default_scenario_params() -> RiskParams {
+    RiskParams {
+        warmup_period_slots: 0,
+        maintenance_margin_bps: 500,
+        initial_margin_bps: 1000,
+        trading_fee_bps: 10,
+        max_accounts: 1000,
+        new_account_fee: U128::ZERO,
+        risk_reduction_threshold: U128::ZERO,
+        maintenance_fee_per_slot: U128::ZERO,
+        max_crank_staleness_slots: u64::MAX,
+        liquidation_fee_bps: 50,
+        liquidation_fee_cap: U128::new(100_000_000),
+        liquidation_buffer_bps: 100,
+        min_liquidation_abs: U128::new(100),
+        liquidation_fee_keeper_share_bps: 0,
+        liquidation_fee_lp_share_bps: 0,
+    }
+}
+
+/// Builder for a simulated market.
+#[derive(Clone, Copy, Debug)]
+pub struct MarketBuilder {
+    params: RiskParams,
+    ext_params: ExtParams,
+    insurance: u128,
+    price: u64,
+    start_slot: u64,
+}
+
+impl Default for MarketBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MarketBuilder {
+    pub fn new() -> Self {
+        Self {
+            params: default_scenario_params(),
+            ext_params: ExtParams::default(),
+            insurance: 0,
+            price: DEFAULT_SCENARIO_PRICE,
+            start_slot: 0,
+        }
+    }
+
+    /// Replace the full risk parameter set
+    pub fn params(mut self, params: RiskParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// Replace the extended parameter set
+    pub fn ext_params(mut self, ext_params: ExtParams) -> Self {
+        self.ext_params = ext_params;
+        self
+    }
+
+    pub fn warmup_period_slots(mut self, slots: u64) -> Self {
+        self.params.warmup_period_slots = slots;
+        self
+    }
+
+    pub fn margins_bps(mut self, maintenance: u64, initial: u64) -> Self {
+        self.params.maintenance_margin_bps = maintenance;
+        self.params.initial_margin_bps = initial;
+        self
+    }
+
+    pub fn trading_fee_bps(mut self, bps: u64) -> Self {
+        self.params.trading_fee_bps = bps;
+        self
+    }
+
+    /// Seed the insurance fund (funded through `top_up_insurance_fund`)
+    pub fn insurance(mut self, amount: u128) -> Self {
+        self.insurance = amount;
+        self
+    }
+
+    /// Initial oracle price
+    pub fn price(mut self, price: u64) -> Self {
+        self.price = price;
+        self
+    }
+
+    pub fn start_slot(mut self, slot: u64) -> Self {
+        self.start_slot = slot;
+        self
+    }
+
+    /// Build the market. Panics if the extended parameters are invalid.
+    pub fn build(self) -> Market {
+        let mut engine = Box::new(RiskEngine::new(self.params));
+        engine
+            .set_ext_params(self.ext_params)
+            .expect("scenario: invalid ExtParams");
+        engine.current_slot = self.start_slot;
+        engine.last_crank_slot = self.start_slot;
+        engine.last_funding_slot = self.start_slot;
+        if self.insurance > 0 {
+            engine
+                .top_up_insurance_fund(self.insurance)
+                .expect("scenario: insurance top-up failed");
+        }
+        Market {
+            engine,
+            clock: SimClock::at(self.start_slot),
+            price: self.price,
+        }
+    }
+}
+
+/// Handle to an account created through a `Market`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Actor {
+    pub idx: u16,
+    pub kind: AccountKind,
+}
+
+/// Explicit simulation clock.
+///
+/// Every `Market` operation reads the current slot from its clock, so warmup,
+/// funding, lockups and fees all observe the same time. Tests move time with
+/// `advance`/`set` instead of passing slot numbers to each engine call.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SimClock {
+    slot: u64,
+}
+
+impl SimClock {
+    pub fn at(slot: u64) -> Self {
+        Self { slot }
+    }
+
+    /// Current slot
+    pub fn now(&self) -> u64 {
+        self.slot
+    }
+
+    /// Move the clock forward by `slots`, returning the new slot
+    pub fn advance(&mut self, slots: u64) -> u64 {
+        self.slot = self.slot.saturating_add(slots);
+        self.slot
+    }
+
+    /// Jump to `slot`. Panics if that would move time backwards.
+    pub fn set(&mut self, slot: u64) {
+        assert!(
+            slot >= self.slot,
+            "scenario: clock cannot move backwards ({} -> {})",
+            self.slot,
+            slot
+        );
+        self.slot = slot;
+    }
+}
+
+/// A simulated market: engine plus the scenario clock and oracle price.
+#[derive(Clone, Debug)]
+pub struct Market {
+    pub engine: Box<RiskEngine>,
+    /// Simulation clock; the source of `now_slot` for every engine call
+    pub clock: SimClock,
+    /// Current oracle price
+    pub price: u64,
+}
+
+impl Market {
+    /// Add an LP account funded with `capital`
+    pub fn add_lp(&mut self, capital: u128) -> Actor {
+        let idx = self
+            .engine
+            .add_lp([0; 32], [0; 32], 0)
+            .expect("scenario: add_lp failed");
+        if capital > 0 {
+            self.engine
+                .deposit(idx, capital, self.slot())
+                .expect("scenario: LP deposit failed");
+        }
+        Actor {
+            idx,
+            kind: AccountKind::LP,
+        }
+    }
+
+    /// Add a user account funded with `capital`
+    pub fn add_user(&mut self, capital: u128) -> Actor {
+        let idx = self.engine.add_user(0).expect("scenario: add_user failed");
+        if capital > 0 {
+            self.engine
+                .deposit(idx, capital, self.slot())
+                .expect("scenario: user deposit failed");
+        }
+        Actor {
+            idx,
+            kind: AccountKind::User,
+        }
+    }
+
+    /// Trade `size` (positive = user buys) between `lp` and `user` at the current price
+    pub fn trade(&mut self, lp: Actor, user: Actor, size: i128) -> Result<TradeReceipt> {
+        self.trade_with(&NoOpMatcher, lp, user, size)
+    }
+
+    /// Trade through a custom matcher
+    pub fn trade_with<M: MatchingEngine>(
+        &mut self,
+        matcher: &M,
+        lp: Actor,
+        user: Actor,
+        size: i128,
+    ) -> Result<TradeReceipt> {
+        self.engine
+            .execute_trade(matcher, lp.idx, user.idx, self.slot(), self.price, size)
+    }
+
+    pub fn set_price(&mut self, price: u64) {
+        self.price = price;
+    }
+
+    /// Current slot of the simulation clock
+    pub fn slot(&self) -> u64 {
+        self.clock.now()
+    }
+
+    /// Advance the simulation clock (does not crank)
+    pub fn advance(&mut self, slots: u64) {
+        self.clock.advance(slots);
+    }
+
+    /// Deposit `amount` into `actor` at the current slot
+    pub fn deposit(&mut self, actor: Actor, amount: u128) -> Result<()> {
+        self.engine.deposit(actor.idx, amount, self.slot())
+    }
+
+    /// Withdraw `amount` from `actor` at the current slot and price
+    pub fn withdraw(&mut self, actor: Actor, amount: u128) -> Result<()> {
+        self.engine.withdraw(actor.idx, amount, self.slot(), self.price)
+    }
+
+    /// Bring `actor` up to the current slot: funding, maintenance fees and
+    /// warmup settlement, without trading
+    pub fn touch(&mut self, actor: Actor) -> Result<()> {
+        self.engine.touch_account_full(actor.idx, self.slot(), self.price)
+    }
+
+    /// Permissionless crank at the current slot and price, no caps
+    pub fn crank(&mut self) -> Result<CrankOutcome> {
+        self.crank_with(0, 0, 0)
+    }
+
+    /// Crank with an explicit funding rate and max-PnL / OI caps
+    pub fn crank_with(
+        &mut self,
+        funding_rate_bps_per_slot: i64,
+        max_pnl_vault_bps: u64,
+        max_oi_abs: u128,
+    ) -> Result<CrankOutcome> {
+        self.engine.keeper_crank(
+            u16::MAX,
+            self.slot(),
+            self.price,
+            funding_rate_bps_per_slot,
+            false,
+            max_pnl_vault_bps,
+            max_oi_abs,
+        )
+    }
+
+    /// Walk `path`: for each step advance the clock, set the price, crank, then
+    /// call `on_step` with the step index and crank outcome.
+    pub fn run_path<F: FnMut(&mut Market, usize, &CrankOutcome)>(
+        &mut self,
+        path: &PricePath,
+        mut on_step: F,
+    ) -> Result<()> {
+        for (i, &price) in path.prices.iter().enumerate() {
+            self.advance(path.slots_per_step);
+            self.set_price(price);
+            let outcome = self.crank()?;
+            on_step(self, i, &outcome);
+        }
+        Ok(())
+    }
+
+    // ========================================
+    // Assertions
+    // ========================================
+
+    /// Assert the conservation invariant at the current price
+    pub fn assert_conserved(&self) {
+        assert!(
+            self.engine.check_conservation(self.price),
+            "scenario: conservation violated at slot {} (vault={}, c_tot={}, insurance={})",
+            self.slot(),
+            self.engine.vault.get(),
+            self.engine.c_tot.get(),
+            self.engine.insurance_fund.balance.get()
+        );
+    }
+
+    pub fn assert_position(&self, actor: Actor, expected: i128) {
+        let actual = self.engine.accounts[actor.idx as usize].position_size.get();
+        assert_eq!(
+            actual, expected,
+            "scenario: account {} position {} != expected {} at slot {}",
+            actor.idx, actual, expected, self.slot()
+        );
+    }
+
+    pub fn assert_flat(&self, actor: Actor) {
+        self.assert_position(actor, 0);
+    }
+
+    pub fn assert_capital_at_least(&self, actor: Actor, min: u128) {
+        let actual = self.engine.accounts[actor.idx as usize].capital.get();
+        assert!(
+            actual >= min,
+            "scenario: account {} capital {} < expected minimum {} at slot {}",
+            actor.idx,
+            actual,
+            min,
+            self.slot()
+        );
+    }
+
+    /// Assert the insurance fund never fell below `min`
+    pub fn assert_insurance_at_least(&self, min: u128) {
+        let actual = self.engine.insurance_fund.balance.get();
+        assert!(
+            actual >= min,
+            "scenario: insurance {} < expected minimum {} at slot {}",
+            actual,
+            min,
+            self.slot()
+        );
+    }
+}
+
+/// A sequence of oracle prices applied one step at a time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PricePath {
+    pub prices: Vec<u64>,
+    /// Slots the clock advances before each step
+    pub slots_per_step: u64,
+}
+
+impl PricePath {
+    pub fn from_prices(prices: &[u64]) -> Self {
+        Self {
+            prices: prices.to_vec(),
+            slots_per_step: 1,
+        }
+    }
+
+    /// `steps` prices all equal to `price`
+    pub fn constant(price: u64, steps: usize) -> Self {
+        Self {
+            prices: std::vec![price; steps],
+            slots_per_step: 1,
+        }
+    }
+
+    /// `steps` prices moving linearly from `start` (exclusive) to `end` (inclusive)
+    pub fn linear(start: u64, end: u64, steps: usize) -> Self {
+        let mut prices = Vec::with_capacity(steps);
+        for i in 1..=steps {
+            let delta = (end as i128 - start as i128) * i as i128 / steps as i128;
+            prices.push((start as i128 + delta) as u64);
+        }
+        Self {
+            prices,
+            slots_per_step: 1,
+        }
+    }
+
+    /// Single jump from `base` by `move_bps` (signed) at the first step, then flat
+    pub fn shock(base: u64, move_bps: i64, steps: usize) -> Self {
+        let shocked = (base as i128 + base as i128 * move_bps as i128 / 10_000).max(1) as u64;
+        Self::constant(shocked, steps)
+    }
+
+    pub fn slots_per_step(mut self, slots: u64) -> Self {
+        self.slots_per_step = slots;
+        self
+    }
+
+    /// Append another path
+    pub fn then(mut self, other: &PricePath) -> Self {
+        self.prices.extend_from_slice(&other.prices);
+        self
+    }
+}
+
+// ========================================
+// Declarative Checks
+// ========================================
+
+/// A quantity a scenario check reads from the market.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    /// MTM margin ratio at the current price, in bps (`u64::MAX` when flat)
+    MarginRatioBps(Actor),
+    Position(Actor),
+    Capital(Actor),
+    /// MTM equity at the current price
+    Equity(Actor),
+    Insurance,
+    /// Drop in the vault balance since the run started (0 if it grew)
+    VaultLoss,
+}
+
+/// Bound a metric must satisfy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    AtLeast(i128),
+    AtMost(i128),
+    Equal(i128),
+}
+
+impl Bound {
+    pub fn holds(&self, actual: i128) -> bool {
+        match *self {
+            Bound::AtLeast(min) => actual >= min,
+            Bound::AtMost(max) => actual <= max,
+            Bound::Equal(expected) => actual == expected,
+        }
+    }
+}
+
+/// When during a run a check is evaluated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckPoint {
+    /// After the crank of path step `n` (0-based)
+    AfterStep(usize),
+    /// After every step's crank
+    EveryStep,
+    /// Once, after the last step
+    End,
+}
+
+/// One named expectation, e.g. "after crank 3, trader margin ratio ≥ 600 bps".
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub at: CheckPoint,
+    pub metric: Metric,
+    pub bound: Bound,
+}
+
+/// A reusable set of checks for `Market::run_checked`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Checks {
+    pub checks: Vec<Check>,
+}
+
+impl Checks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check `metric` after the crank of step `step`
+    pub fn after_step(
+        self,
+        step: usize,
+        name: &'static str,
+        metric: Metric,
+        bound: Bound,
+    ) -> Self {
+        self.with(name, CheckPoint::AfterStep(step), metric, bound)
+    }
+
+    /// Check `metric` after every step
+    pub fn always(self, name: &'static str, metric: Metric, bound: Bound) -> Self {
+        self.with(name, CheckPoint::EveryStep, metric, bound)
+    }
+
+    /// Check `metric` once the path is done
+    pub fn at_end(self, name: &'static str, metric: Metric, bound: Bound) -> Self {
+        self.with(name, CheckPoint::End, metric, bound)
+    }
+
+    fn with(mut self, name: &'static str, at: CheckPoint, metric: Metric, bound: Bound) -> Self {
+        self.checks.push(Check {
+            name,
+            at,
+            metric,
+            bound,
+        });
+        self
+    }
+}
+
+/// Result of evaluating one check once.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CheckResult {
+    pub check: Check,
+    /// Step evaluated after (None for `CheckPoint::End`)
+    pub step: Option<usize>,
+    pub slot: u64,
+    pub price: u64,
+    pub actual: i128,
+    pub passed: bool,
+}
+
+/// Structured pass/fail report of a checked run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScenarioReport {
+    /// Every evaluation, in the order it happened
+    pub results: Vec<CheckResult>,
+}
+
+impl ScenarioReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| r.passed)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &CheckResult> {
+        self.results.iter().filter(|r| !r.passed)
+    }
+
+    /// Panic listing every failed check, for deployment-gating tests
+    pub fn assert_passed(&self) {
+        if self.passed() {
+            return;
+        }
+        let mut message = std::string::String::from("scenario: checks failed:");
+        for r in self.failures() {
+            message.push_str(&std::format!(
+                "\n  {} at step {:?} (slot {}, price {}): {:?} = {}, expected {:?}",
+                r.check.name,
+                r.step,
+                r.slot,
+                r.price,
+                r.check.metric,
+                r.actual,
+                r.check.bound
+            ));
+        }
+        panic!("{}", message);
+    }
+}
+
+impl Market {
+    /// Current value of `metric`; `vault_start` is the baseline for `Metric::VaultLoss`.
+    pub fn measure(&self, metric: Metric, vault_start: u128) -> i128 {
+        let clamp = |v: u128| v.min(i128::MAX as u128) as i128;
+        let account = |actor: Actor| &self.engine.accounts[actor.idx as usize];
+        match metric {
+            Metric::MarginRatioBps(actor) => {
+                self.engine.margin_ratio_bps(account(actor), self.price) as i128
+            }
+            Metric::Position(actor) => account(actor).position_size.get(),
+            Metric::Capital(actor) => clamp(account(actor).capital.get()),
+            Metric::Equity(actor) => {
+                clamp(self.engine.account_equity_mtm_at_oracle(account(actor), self.price))
+            }
+            Metric::Insurance => clamp(self.engine.insurance_fund.balance.get()),
+            Metric::VaultLoss => clamp(vault_start.saturating_sub(self.engine.vault.get())),
+        }
+    }
+
+    /// Walk `path` like `run_path`, evaluating `checks` after each crank and at the
+    /// end. Failed checks are reported, not panicked on; engine errors abort the run.
+    pub fn run_checked(&mut self, path: &PricePath, checks: &Checks) -> Result<ScenarioReport> {
+        let vault_start = self.engine.vault.get();
+        let mut report = ScenarioReport::default();
+        self.run_path(path, |market, step, _| {
+            for check in &checks.checks {
+                let due = match check.at {
+                    CheckPoint::AfterStep(n) => n == step,
+                    CheckPoint::EveryStep => true,
+                    CheckPoint::End => false,
+                };
+                if due {
+                    report.results.push(market.evaluate(check, Some(step), vault_start));
+                }
+            }
+        })?;
+        for check in checks.checks.iter().filter(|c| c.at == CheckPoint::End) {
+            report.results.push(self.evaluate(check, None, vault_start));
+        }
+        Ok(report)
+    }
+
+    fn evaluate(&self, check: &Check, step: Option<usize>, vault_start: u128) -> CheckResult {
+        let actual = self.measure(check.metric, vault_start);
+        CheckResult {
+            check: *check,
+            step,
+            slot: self.slot(),
+            price: self.price,
+            actual,
+            passed: check.bound.holds(actual),
+        }
+    }
+}
@@ -0,0 +1,289 @@
+// ============================================================================
+// Engine Snapshots
+// ============================================================================
+//
+// A canonical byte encoding of the balances users can audit, with its SHA-256
+// hash. An operator publishes `encode_snapshot` output periodically together
+// with a `SnapshotAttestation` (slot, hash and the operator's signature over
+// the hash, produced by the wrapper). Anyone holding the snapshot can check it
+// against the attested hash with `verify_snapshot`; the engine can recompute
+// the hash of its own state with `snapshot_hash` without buffering the bytes.
+//
+// Snapshot layout (little-endian):
+//
+//   version u8 | slot u64 | state_seq u64 | vault u128 | insurance u128 |
+//   c_tot u128 | pnl_pos_tot u128 | total_open_interest u128 | net_lp_pos i128 |
+//   funding_index i128 | num_accounts u16 |
+//   num_accounts × (idx u16 | account_id u64 | kind u8 | owner [32] |
+//                   capital u128 | pnl i128 | reserved_pnl u64 | position i128 |
+//                   entry_price u64 | fee_credits i128)
+//
+// Accounts appear in index order.
+
+use crate::{ByteReader, ByteWriter, Result, RiskEngine, RiskError, MAX_ACCOUNTS};
+
+/// Current snapshot encoding version.
+pub const SNAPSHOT_VERSION: u8 = 1;
+
+/// Encoded size of the snapshot header.
+pub const SNAPSHOT_HEADER_LEN: usize = 1 + 8 * 2 + 16 * 7 + 2;
+
+/// Encoded size of one account record in a snapshot.
+pub const SNAPSHOT_ACCOUNT_LEN: usize = 2 + 8 + 1 + 32 + 16 * 4 + 8 * 2;
+
+/// Length of the operator's signature over a snapshot hash.
+pub const SNAPSHOT_SIGNATURE_LEN: usize = 64;
+
+/// An operator's signed claim that the engine state at `slot` hashed to `hash`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SnapshotAttestation {
+    pub slot: u64,
+    /// `state_seq` of the snapshotted state
+    pub state_seq: u64,
+    /// SHA-256 of the encoded snapshot
+    pub hash: [u8; 32],
+    /// Operator signature over `hash` (checked by the wrapper or the auditor)
+    pub signature: [u8; SNAPSHOT_SIGNATURE_LEN],
+}
+
+impl SnapshotAttestation {
+    /// Encoded attestation size.
+    pub const ENCODED_LEN: usize = 8 * 2 + 32 + SNAPSHOT_SIGNATURE_LEN;
+
+    pub fn encode(&self, out: &mut [u8]) -> Result<usize> {
+        let mut w = ByteWriter { buf: out, pos: 0 };
+        w.put(&self.slot.to_le_bytes())?;
+        w.put(&self.state_seq.to_le_bytes())?;
+        w.put(&self.hash)?;
+        w.put(&self.signature)?;
+        Ok(w.pos)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != Self::ENCODED_LEN {
+            return Err(RiskError::InvalidParams);
+        }
+        let mut r = ByteReader { buf: bytes, pos: 0 };
+        Ok(Self {
+            slot: r.u64(),
+            state_seq: r.u64(),
+            hash: r.take(),
+            signature: r.take(),
+        })
+    }
+
+    /// Whether `snapshot` is the state this attestation vouches for.
+    pub fn matches(&self, snapshot: &[u8]) -> bool {
+        verify_snapshot(snapshot, &self.hash) && snapshot_slot(snapshot) == Some(self.slot)
+    }
+}
+
+/// Whether `snapshot` hashes to `hash`.
+pub fn verify_snapshot(snapshot: &[u8], hash: &[u8; 32]) -> bool {
+    sha256(snapshot) == *hash
+}
+
+/// Slot recorded in an encoded snapshot (None if it is not a current-version snapshot).
+pub fn snapshot_slot(snapshot: &[u8]) -> Option<u64> {
+    if snapshot.len() < SNAPSHOT_HEADER_LEN || snapshot[0] != SNAPSHOT_VERSION {
+        return None;
+    }
+    let mut r = ByteReader { buf: &snapshot[1..], pos: 0 };
+    Some(r.u64())
+}
+
+/// Destination of snapshot bytes: a buffer or a running hash.
+trait SnapshotSink {
+    fn put(&mut self, bytes: &[u8]) -> Result<()>;
+}
+
+impl SnapshotSink for ByteWriter<'_> {
+    fn put(&mut self, bytes: &[u8]) -> Result<()> {
+        ByteWriter::put(self, bytes)
+    }
+}
+
+impl SnapshotSink for Sha256 {
+    fn put(&mut self, bytes: &[u8]) -> Result<()> {
+        self.update(bytes);
+        Ok(())
+    }
+}
+
+impl RiskEngine {
+    /// Encoded size of the snapshot of the current state.
+    pub fn snapshot_len(&self) -> usize {
+        SNAPSHOT_HEADER_LEN
+            .saturating_add(SNAPSHOT_ACCOUNT_LEN.saturating_mul(self.num_used_accounts as usize))
+    }
+
+    /// Write the canonical snapshot of the current state, tagged with `slot`, into
+    /// `out` (at least `snapshot_len()` bytes). Returns the length and SHA-256 hash.
+    pub fn encode_snapshot(&self, slot: u64, out: &mut [u8]) -> Result<(usize, [u8; 32])> {
+        let mut w = ByteWriter { buf: out, pos: 0 };
+        self.write_snapshot(slot, &mut w)?;
+        let len = w.pos;
+        Ok((len, sha256(&w.buf[..len])))
+    }
+
+    /// SHA-256 of the snapshot tagged with `slot`, streamed without a buffer.
+    pub fn snapshot_hash(&self, slot: u64) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        // Hashing cannot fail
+        let _ = self.write_snapshot(slot, &mut hasher);
+        hasher.finish()
+    }
+
+    fn write_snapshot<S: SnapshotSink>(&self, slot: u64, sink: &mut S) -> Result<()> {
+        sink.put(&[SNAPSHOT_VERSION])?;
+        sink.put(&slot.to_le_bytes())?;
+        sink.put(&self.state_seq.to_le_bytes())?;
+        sink.put(&self.vault.get().to_le_bytes())?;
+        sink.put(&self.insurance_fund.balance.get().to_le_bytes())?;
+        sink.put(&self.c_tot.get().to_le_bytes())?;
+        sink.put(&self.pnl_pos_tot.get().to_le_bytes())?;
+        sink.put(&self.total_open_interest.get().to_le_bytes())?;
+        sink.put(&self.net_lp_pos.get().to_le_bytes())?;
+        sink.put(&self.funding_index_qpb_e6.get().to_le_bytes())?;
+        sink.put(&self.num_used_accounts.to_le_bytes())?;
+        for idx in 0..MAX_ACCOUNTS {
+            if !self.is_used(idx) {
+                continue;
+            }
+            let a = &self.accounts[idx];
+            sink.put(&(idx as u16).to_le_bytes())?;
+            sink.put(&a.account_id.to_le_bytes())?;
+            sink.put(&[a.kind as u8])?;
+            sink.put(&a.owner)?;
+            sink.put(&a.capital.get().to_le_bytes())?;
+            sink.put(&a.pnl.get().to_le_bytes())?;
+            sink.put(&a.reserved_pnl.to_le_bytes())?;
+            sink.put(&a.position_size.get().to_le_bytes())?;
+            sink.put(&a.entry_price.to_le_bytes())?;
+            sink.put(&a.fee_credits.get().to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+// ============================================================================
+// SHA-256 (FIPS 180-4); the crate has no dependencies to borrow one from
+// ============================================================================
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const SHA256_H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// SHA-256 of `bytes`.
+pub fn sha256(bytes: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(bytes);
+    hasher.finish()
+}
+
+/// Streaming SHA-256.
+#[derive(Clone, Debug)]
+pub struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Self {
+            state: SHA256_H0,
+            block: [0; 64],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        self.total_len = self.total_len.wrapping_add(bytes.len() as u64);
+        while !bytes.is_empty() {
+            let take = core::cmp::min(64usize.saturating_sub(self.block_len), bytes.len());
+            let end = self.block_len.saturating_add(take);
+            self.block[self.block_len..end].copy_from_slice(&bytes[..take]);
+            self.block_len = end;
+            bytes = &bytes[take..];
+            if self.block_len == 64 {
+                let block = self.block;
+                self.compress(&block);
+                self.block_len = 0;
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> [u8; 32] {
+        let bit_len = self.total_len.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bit_len.to_be_bytes());
+        let mut out = [0u8; 32];
+        for (chunk, word) in out.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut w = [0u32; 64];
+        for (word, chunk) in w.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for t in 16usize..64 {
+            let (w15, w2) = (w[t.wrapping_sub(15)], w[t.wrapping_sub(2)]);
+            let s0 = w15.rotate_right(7) ^ w15.rotate_right(18) ^ (w15 >> 3);
+            let s1 = w2.rotate_right(17) ^ w2.rotate_right(19) ^ (w2 >> 10);
+            w[t] = w[t.wrapping_sub(16)]
+                .wrapping_add(s0)
+                .wrapping_add(w[t.wrapping_sub(7)])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for (k, wt) in SHA256_K.iter().zip(w) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(*k)
+                .wrapping_add(wt);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, v) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(v);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+// ============================================================================
+// Switchboard Oracle Adapter (requires the `switchboard` feature)
+// ============================================================================
+//
+// Parses a Switchboard v2 aggregator account into an `OracleQuote` with the
+// same normalization and checks as the Pyth adapter, so a market can switch
+// feeds through `ExtParams::oracle_source`. The latest confirmed round is
+// used; its standard deviation serves as the confidence interval. Byte
+// offsets into the packed account (little-endian):
+//
+//   0   Anchor discriminator [u8; 8]
+//   341 latest_confirmed_round.num_success u32
+//   350 latest_confirmed_round.round_open_slot u64
+//   366 result.mantissa i128         382 result.scale u32
+//   386 std_deviation.mantissa i128  402 std_deviation.scale u32
+
+use crate::{mul_div, oracle_quote_fresh, read_le, OracleQuote, Result, RiskError, Rounding};
+
+/// Anchor discriminator of `AggregatorAccountData`
+pub const SWITCHBOARD_AGGREGATOR_DISCRIMINATOR: [u8; 8] = [217, 230, 65, 101, 201, 162, 27, 125];
+/// Bytes needed to read every field this adapter uses
+pub const SWITCHBOARD_AGGREGATOR_MIN_LEN: usize = 406;
+
+/// Largest decimal scale accepted (10^28 still fits in u128 with headroom)
+const MAX_SWITCHBOARD_SCALE: u32 = 28;
+
+/// Latest confirmed round read from a Switchboard aggregator, in decimal form.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SwitchboardRound {
+    /// Oracle responses accepted in the round (0 = no result)
+    pub num_success: u32,
+    pub round_open_slot: u64,
+    /// result_mantissa / 10^result_scale is the real price
+    pub result_mantissa: i128,
+    pub result_scale: u32,
+    pub std_dev_mantissa: i128,
+    pub std_dev_scale: u32,
+}
+
+impl SwitchboardRound {
+    /// Normalize to `price_scale` (e.g. 1_000_000 for e6). Price rounds down,
+    /// confidence rounds up.
+    pub fn to_quote(&self, feed: [u8; 32], price_scale: u64) -> Result<OracleQuote> {
+        if self.num_success == 0 || self.result_mantissa <= 0 || self.std_dev_mantissa < 0 {
+            return Err(RiskError::OracleUnavailable);
+        }
+        let price = rescale_decimal(self.result_mantissa, self.result_scale, price_scale, Rounding::Down)?;
+        if price == 0 {
+            return Err(RiskError::OracleUnavailable);
+        }
+        Ok(OracleQuote {
+            feed,
+            price,
+            conf: rescale_decimal(self.std_dev_mantissa, self.std_dev_scale, price_scale, Rounding::Up)?,
+            publish_slot: self.round_open_slot,
+        })
+    }
+}
+
+/// Parse the latest confirmed round from a Switchboard v2 aggregator account's data.
+pub fn parse_aggregator_account(data: &[u8]) -> Result<SwitchboardRound> {
+    if data.len() < SWITCHBOARD_AGGREGATOR_MIN_LEN
+        || read_le::<8>(data, 0) != SWITCHBOARD_AGGREGATOR_DISCRIMINATOR
+    {
+        return Err(RiskError::InvalidParams);
+    }
+    Ok(SwitchboardRound {
+        num_success: u32::from_le_bytes(read_le(data, 341)),
+        round_open_slot: u64::from_le_bytes(read_le(data, 350)),
+        result_mantissa: i128::from_le_bytes(read_le(data, 366)),
+        result_scale: u32::from_le_bytes(read_le(data, 382)),
+        std_dev_mantissa: i128::from_le_bytes(read_le(data, 386)),
+        std_dev_scale: u32::from_le_bytes(read_le(data, 402)),
+    })
+}
+
+/// Parse, normalize and check a Switchboard aggregator account in one step.
+///
+/// Rejects rounds older than `max_staleness_slots` (0 = unchecked) or with a
+/// standard deviation wider than `max_conf_bps` of price (0 = unchecked).
+pub fn load_switchboard_quote(
+    data: &[u8],
+    feed: [u8; 32],
+    now_slot: u64,
+    max_staleness_slots: u64,
+    max_conf_bps: u64,
+    price_scale: u64,
+) -> Result<OracleQuote> {
+    let quote = parse_aggregator_account(data)?.to_quote(feed, price_scale)?;
+    if !oracle_quote_fresh(&quote, now_slot, max_staleness_slots, max_conf_bps) {
+        return Err(RiskError::OracleUnavailable);
+    }
+    Ok(quote)
+}
+
+/// `mantissa / 10^scale` at `price_scale`; `Overflow` if it does not fit in u64.
+fn rescale_decimal(mantissa: i128, scale: u32, price_scale: u64, rounding: Rounding) -> Result<u64> {
+    if scale > MAX_SWITCHBOARD_SCALE {
+        return Err(RiskError::InvalidParams);
+    }
+    let divisor = 10u128.checked_pow(scale).ok_or(RiskError::InvalidParams)?;
+    let value = mul_div(mantissa.unsigned_abs(), price_scale as u128, divisor, rounding);
+    u64::try_from(value).map_err(|_| RiskError::Overflow)
+}
@@ -0,0 +1,154 @@
+// ============================================================================
+// SPL Token Vault Reconciliation
+// ============================================================================
+//
+// The engine's `vault` is its own record of the collateral it holds; the
+// program's token account is what it actually holds. The two drift when
+// tokens are sent to the vault outside `deposit` (donations) or when fewer
+// tokens arrive than were credited (e.g. transfer fees). These helpers read
+// the token account, classify the difference and apply the correction, so
+// every program wrapper reconciles the same way:
+//
+//   token balance > vault  → donation: absorbed into the insurance fund
+//   token balance < vault  → shortfall: written off insurance if it covers
+//                            the gap, otherwise the program must halt
+//
+// Layout of an SPL token account (Token-2022 accounts share the same prefix):
+//
+//   0 mint [u8; 32]   32 owner [u8; 32]   64 amount u64   108 state u8
+
+use crate::events::{EventKind, EVENT_NO_ACCOUNT};
+use crate::{read_le, Result, RiskEngine, RiskError, U128};
+
+/// Size of the base SPL token account layout
+pub const TOKEN_ACCOUNT_LEN: usize = 165;
+
+/// `AccountState::Initialized`
+const TOKEN_STATE_INITIALIZED: u8 = 1;
+/// `AccountState::Frozen`
+const TOKEN_STATE_FROZEN: u8 = 2;
+
+/// The fields of an SPL token account the vault helpers need.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TokenAccount {
+    pub mint: [u8; 32],
+    pub owner: [u8; 32],
+    pub amount: u64,
+    pub frozen: bool,
+}
+
+/// Parse an initialized SPL (or Token-2022) token account.
+pub fn parse_token_account(data: &[u8]) -> Result<TokenAccount> {
+    if data.len() < TOKEN_ACCOUNT_LEN {
+        return Err(RiskError::InvalidParams);
+    }
+    let [state] = read_le::<1>(data, 108);
+    if state != TOKEN_STATE_INITIALIZED && state != TOKEN_STATE_FROZEN {
+        return Err(RiskError::InvalidParams);
+    }
+    Ok(TokenAccount {
+        mint: read_le(data, 0),
+        owner: read_le(data, 32),
+        amount: u64::from_le_bytes(read_le(data, 64)),
+        frozen: state == TOKEN_STATE_FROZEN,
+    })
+}
+
+/// Parse a token account and check it is the market's vault: the expected
+/// mint and owner (the program's vault authority). Returns its balance.
+pub fn vault_token_balance(data: &[u8], mint: &[u8; 32], owner: &[u8; 32]) -> Result<u64> {
+    let account = parse_token_account(data)?;
+    if account.mint != *mint || account.owner != *owner {
+        return Err(RiskError::Unauthorized);
+    }
+    Ok(account.amount)
+}
+
+/// Correction needed to bring `vault` in line with the token balance.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VaultAction {
+    /// Balances agree
+    #[default]
+    None,
+    /// Untracked tokens in the vault: credit them to vault and insurance
+    AbsorbDonation { amount: u128 },
+    /// Missing tokens covered by insurance: debit vault and insurance
+    WriteDownInsurance { amount: u128 },
+    /// Missing tokens exceed insurance; the engine cannot correct this and the
+    /// program should stop withdrawals until the vault is recapitalized
+    Halt { shortfall: u128 },
+}
+
+/// Engine vault vs token balance, from `RiskEngine::reconcile_vault`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VaultReconciliation {
+    pub engine_vault: u128,
+    pub token_balance: u128,
+    /// token_balance − engine_vault
+    pub discrepancy: i128,
+    pub action: VaultAction,
+}
+
+impl RiskEngine {
+    /// Compare `vault` against the vault token account's balance and plan the
+    /// correction. Read-only; see `apply_vault_reconciliation`.
+    pub fn reconcile_vault(&self, token_balance: u128) -> VaultReconciliation {
+        let engine_vault = self.vault.get();
+        let action = if token_balance > engine_vault {
+            VaultAction::AbsorbDonation {
+                amount: token_balance.saturating_sub(engine_vault),
+            }
+        } else if token_balance < engine_vault {
+            let shortfall = engine_vault.saturating_sub(token_balance);
+            if shortfall <= self.insurance_fund.balance.get() {
+                VaultAction::WriteDownInsurance { amount: shortfall }
+            } else {
+                VaultAction::Halt { shortfall }
+            }
+        } else {
+            VaultAction::None
+        };
+        VaultReconciliation {
+            engine_vault,
+            token_balance,
+            discrepancy: i128::try_from(token_balance)
+                .unwrap_or(i128::MAX)
+                .saturating_sub(i128::try_from(engine_vault).unwrap_or(i128::MAX)),
+            action,
+        }
+    }
+
+    /// Reconcile and apply the correction. `Halt` is returned without changing
+    /// state; the caller decides how to stop the market.
+    pub fn apply_vault_reconciliation(&mut self, token_balance: u128) -> Result<VaultReconciliation> {
+        let result = self.apply_vault_reconciliation_inner(token_balance);
+        self.finish_mutation("apply_vault_reconciliation", result.is_ok());
+        result
+    }
+
+    fn apply_vault_reconciliation_inner(&mut self, token_balance: u128) -> Result<VaultReconciliation> {
+        let recon = self.reconcile_vault(token_balance);
+        match recon.action {
+            VaultAction::AbsorbDonation { amount } => {
+                self.vault = U128::new(self.vault.get().saturating_add(amount));
+                self.insurance_fund.balance =
+                    U128::new(self.insurance_fund.balance.get().saturating_add(amount));
+            }
+            VaultAction::WriteDownInsurance { amount } => {
+                self.vault = U128::new(self.vault.get().saturating_sub(amount));
+                self.insurance_fund.balance =
+                    U128::new(self.insurance_fund.balance.get().saturating_sub(amount));
+            }
+            VaultAction::None | VaultAction::Halt { .. } => return Ok(recon),
+        }
+        self.record_event(
+            EventKind::VaultReconciled,
+            EVENT_NO_ACCOUNT,
+            EVENT_NO_ACCOUNT,
+            token_balance,
+            recon.discrepancy,
+            0,
+        );
+        Ok(recon)
+    }
+}
@@ -18,6 +18,8 @@ fn default_params() -> RiskParams {
         liquidation_fee_cap: U128::new(100_000), // Cap at 100k units
         liquidation_buffer_bps: 100,             // 1% buffer above maintenance
         min_liquidation_abs: U128::new(100_000), // Minimum 0.1 units
+        liquidation_fee_keeper_share_bps: 0,
+        liquidation_fee_lp_share_bps: 0,
     }
 }
 
@@ -64,21 +66,19 @@ fn test_e2e_complete_user_journey() {
     let mut engine = Box::new(RiskEngine::new(default_params()));
 
     // Initialize insurance fund
-    engine.insurance_fund.balance = U128::new(50_000);
+    engine.top_up_insurance_fund(50_000).unwrap();
 
     // Add LP with capital (LP takes leveraged position opposite to users)
-    let lp = engine.add_lp([1u8; 32], [2u8; 32], 10_000).unwrap();
-    engine.accounts[lp as usize].capital = U128::new(100_000);
-    engine.vault = U128::new(100_000);
+    let lp = engine.add_lp([1u8; 32], [2u8; 32], 0).unwrap();
+    engine.deposit(lp, 100_000, 0).unwrap();
 
     // Add two users
-    let alice = engine.add_user(10_000).unwrap();
-    let bob = engine.add_user(10_000).unwrap();
+    let alice = engine.add_user(0).unwrap();
+    let bob = engine.add_user(0).unwrap();
 
     // Users deposit principal
     engine.deposit(alice, 10_000, 0).unwrap();
     engine.deposit(bob, 15_000, 0).unwrap();
-    engine.vault = U128::new(125_000); // 100k LP + 10k Alice + 15k Bob
 
     // === Phase 1: Trading ===
 
@@ -297,18 +297,16 @@ fn test_e2e_funding_complete_cycle() {
     // Scenario: Users trade, funding accrues over time, positions flip, funding reverses
 
     let mut engine = Box::new(RiskEngine::new(default_params()));
-    engine.insurance_fund.balance = U128::new(50_000);
+    engine.top_up_insurance_fund(50_000).unwrap();
 
-    let lp = engine.add_lp([1u8; 32], [2u8; 32], 10_000).unwrap();
-    engine.accounts[lp as usize].capital = U128::new(100_000);
-    engine.vault = U128::new(100_000);
+    let lp = engine.add_lp([1u8; 32], [2u8; 32], 0).unwrap();
+    engine.deposit(lp, 100_000, 0).unwrap();
 
-    let alice = engine.add_user(10_000).unwrap();
-    let bob = engine.add_user(10_000).unwrap();
+    let alice = engine.add_user(0).unwrap();
+    let bob = engine.add_user(0).unwrap();
 
     engine.deposit(alice, 20_000, 0).unwrap();
     engine.deposit(bob, 20_000, 0).unwrap();
-    engine.vault = U128::new(140_000);
 
     // Alice goes long, Bob goes short
     engine
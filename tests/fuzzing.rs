@@ -178,6 +178,8 @@ fn params_regime_a() -> RiskParams {
         liquidation_fee_cap: U128::new(100_000),
         liquidation_buffer_bps: 100,
         min_liquidation_abs: U128::new(100_000),
+        liquidation_fee_keeper_share_bps: 0,
+        liquidation_fee_lp_share_bps: 0,
     }
 }
 
@@ -197,6 +199,8 @@ fn params_regime_b() -> RiskParams {
         liquidation_fee_cap: U128::new(100_000),
         liquidation_buffer_bps: 100,
         min_liquidation_abs: U128::new(100_000),
+        liquidation_fee_keeper_share_bps: 0,
+        liquidation_fee_lp_share_bps: 0,
     }
 }
 
@@ -384,7 +388,7 @@ impl FuzzState {
         match action {
             Action::AddUser { fee_payment } => {
                 // Snapshot engine and harness state for rollback
-                let before = (*self.engine).clone();
+                let before = self.engine.clone();
                 let live_before = self.live_accounts.clone();
                 let ids_before = self.account_ids.clone();
                 let num_used_before = self.count_used();
@@ -427,7 +431,7 @@ impl FuzzState {
                     }
                     Err(_) => {
                         // Simulate Solana rollback - restore engine and harness state
-                        *self.engine = before;
+                        self.engine = before;
                         self.live_accounts = live_before;
                         self.account_ids = ids_before;
                     }
@@ -436,7 +440,7 @@ impl FuzzState {
 
             Action::AddLp { fee_payment } => {
                 // Snapshot engine and harness state for rollback
-                let before = (*self.engine).clone();
+                let before = self.engine.clone();
                 let live_before = self.live_accounts.clone();
                 let ids_before = self.account_ids.clone();
                 let lp_before = self.lp_idx;
@@ -473,7 +477,7 @@ impl FuzzState {
                     }
                     Err(_) => {
                         // Simulate Solana rollback - restore engine and harness state
-                        *self.engine = before;
+                        self.engine = before;
                         self.live_accounts = live_before;
                         self.account_ids = ids_before;
                         self.lp_idx = lp_before;
@@ -483,7 +487,7 @@ impl FuzzState {
 
             Action::Deposit { who, amount } => {
                 let idx = self.resolve_selector(who);
-                let before = (*self.engine).clone();
+                let before = self.engine.clone();
                 let vault_before = self.engine.vault;
 
                 let result = self.engine.deposit(idx, *amount, 0);
@@ -501,14 +505,14 @@ impl FuzzState {
                     }
                     Err(_) => {
                         // Simulate Solana rollback
-                        *self.engine = before;
+                        self.engine = before;
                     }
                 }
             }
 
             Action::Withdraw { who, amount } => {
                 let idx = self.resolve_selector(who);
-                let before = (*self.engine).clone();
+                let before = self.engine.clone();
                 let vault_before = self.engine.vault;
 
                 let result = self.engine.withdraw(idx, *amount, 0, 1_000_000);
@@ -526,7 +530,7 @@ impl FuzzState {
                     }
                     Err(_) => {
                         // Simulate Solana rollback
-                        *self.engine = before;
+                        self.engine = before;
                     }
                 }
             }
@@ -548,7 +552,7 @@ impl FuzzState {
                 oracle_price,
                 rate_bps,
             } => {
-                let before = (*self.engine).clone();
+                let before = self.engine.clone();
                 let last_slot_before = self.engine.last_funding_slot;
                 let now_slot = self.engine.current_slot.saturating_add(*dt);
 
@@ -571,14 +575,14 @@ impl FuzzState {
                     }
                     Err(_) => {
                         // Simulate Solana rollback
-                        *self.engine = before;
+                        self.engine = before;
                     }
                 }
             }
 
             Action::Touch { who } => {
                 let idx = self.resolve_selector(who);
-                let before = (*self.engine).clone();
+                let before = self.engine.clone();
 
                 let result = self.engine.touch_account(idx);
 
@@ -595,7 +599,7 @@ impl FuzzState {
                     }
                     Err(_) => {
                         // Simulate Solana rollback
-                        *self.engine = before;
+                        self.engine = before;
                     }
                 }
             }
@@ -614,7 +618,7 @@ impl FuzzState {
                     return;
                 }
 
-                let before = (*self.engine).clone();
+                let before = self.engine.clone();
 
                 let result =
                     self.engine
@@ -628,13 +632,13 @@ impl FuzzState {
                     }
                     Err(_) => {
                         // Simulate Solana rollback
-                        *self.engine = before;
+                        self.engine = before;
                     }
                 }
             }
 
             Action::TopUpInsurance { amount } => {
-                let before = (*self.engine).clone();
+                let before = self.engine.clone();
                 let vault_before = self.engine.vault;
 
                 let result = self.engine.top_up_insurance_fund(*amount);
@@ -652,7 +656,7 @@ impl FuzzState {
                     }
                     Err(_) => {
                         // Simulate Solana rollback
-                        *self.engine = before;
+                        self.engine = before;
                     }
                 }
             }
@@ -844,10 +848,10 @@ proptest! {
         let mut engine = Box::new(RiskEngine::new(params_regime_b()));
         let user_idx = engine.add_user(1).unwrap();
 
-        engine.insurance_fund.balance = U128::new(100_000);
-        engine.vault = U128::new(100_000);
+        engine.top_up_insurance_fund(100_000).unwrap();
         engine.deposit(user_idx, capital, 0).unwrap();
         engine.accounts[user_idx as usize].pnl = I128::new(pnl);
+        engine.recompute_aggregates();
         engine.accounts[user_idx as usize].warmup_slope_per_step = U128::new(slope);
         engine.accounts[user_idx as usize].warmup_started_at_slot = 0;
         engine.current_slot = slot;
@@ -1332,7 +1336,7 @@ proptest! {
         engine.deposit(user_idx, deposit_amount, 0).unwrap();
 
         // Snapshot for rollback simulation
-        let before = (*engine).clone();
+        let before = engine.clone();
 
         let result = engine.withdraw(user_idx, withdraw_amount, 0, 1_000_000);
 
@@ -1341,7 +1345,7 @@ proptest! {
             prop_assert!(engine.accounts[user_idx as usize].capital <= before.accounts[user_idx as usize].capital);
         } else {
             // Simulate Solana rollback then verify state is restored
-            *engine = before.clone();
+            engine = before.clone();
             prop_assert_eq!(engine.vault, before.vault);
             prop_assert_eq!(engine.accounts[user_idx as usize].capital, before.accounts[user_idx as usize].capital);
         }
@@ -1511,7 +1515,7 @@ fn harness_rollback_simulation_test() {
     engine.accrue_funding_with_rate(100, 1_000_000, 100).unwrap();
 
     // Capture complete state before failed operation (deep clone of RiskEngine)
-    let before = (*engine).clone();
+    let before = engine.clone();
 
     // Capture expected values before any operation
     let expected_vault = engine.vault;
@@ -1528,7 +1532,7 @@ fn harness_rollback_simulation_test() {
 
     // Simulate Solana rollback (this is what the harness does)
     // Deep restore of RiskEngine contents
-    *engine = before;
+    engine = before;
 
     // Verify state is exactly restored
     assert_eq!(engine.vault, expected_vault, "vault must be restored");
@@ -1551,3 +1555,48 @@ fn harness_rollback_simulation_test() {
         "Conservation must hold after harness rollback"
     );
 }
+
+// ============================================================================
+// SECTION 10: PANIC-FREE EXTREME INPUTS
+// ============================================================================
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(200))]
+
+    // Public entry points must return errors (or saturate), never panic, on
+    // extreme amounts, sizes, prices, funding rates and out-of-range indices.
+    #[test]
+    fn fuzz_no_panic_extreme_inputs(
+        amount in prop_oneof![Just(0u128), Just(1u128), Just(u128::MAX), Just(u128::MAX / 2), any::<u128>()],
+        size in prop_oneof![Just(i128::MIN), Just(i128::MAX), Just(-1i128), Just(1i128), any::<i128>()],
+        price in prop_oneof![Just(0u64), Just(1u64), Just(MAX_ORACLE_PRICE), Just(u64::MAX), any::<u64>()],
+        rate in prop_oneof![Just(i64::MIN), Just(i64::MAX), Just(0i64), any::<i64>()],
+        slot in prop_oneof![Just(0u64), Just(u64::MAX), any::<u64>()],
+        idx in prop_oneof![Just(0u16), Just(1u16), Just(u16::MAX), any::<u16>()],
+    ) {
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut engine = Box::new(RiskEngine::new(params_regime_a()));
+            let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+            let user = engine.add_user(0).unwrap();
+            let _ = engine.deposit(lp, 1_000_000_000, 0);
+            let _ = engine.deposit(user, 1_000_000, 0);
+
+            let _ = engine.deposit(user, amount, slot);
+            let _ = engine.deposit(idx, amount, slot);
+            let _ = engine.execute_trade(&MATCHER, lp, user, slot, price, size);
+            let _ = engine.execute_trade(&MATCHER, lp, idx, slot, price, size);
+            engine.set_funding_rate_for_next_interval(rate);
+            let _ = engine.accrue_funding(slot, price);
+            let _ = engine.keeper_crank(idx, slot, price, rate, false, 0, amount);
+            let _ = engine.liquidate_at_oracle(user, slot, price);
+            let _ = engine.liquidate_at_oracle(idx, slot, price);
+            let _ = engine.withdraw(user, amount, slot, price);
+            let _ = engine.withdraw(idx, amount, slot, price);
+            let _ = engine.top_up_insurance_fund(amount);
+            let _ = engine.close_account(user, slot, price);
+            let _ = engine.settle_warmup_to_capital(idx);
+            let _ = engine.check_conservation(price);
+        }));
+        prop_assert!(result.is_ok(), "engine panicked on extreme inputs");
+    }
+}
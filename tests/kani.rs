@@ -47,6 +47,8 @@ fn test_params() -> RiskParams {
         liquidation_fee_cap: U128::new(10_000),
         liquidation_buffer_bps: 100,
         min_liquidation_abs: U128::new(100_000),
+        liquidation_fee_keeper_share_bps: 0,
+        liquidation_fee_lp_share_bps: 0,
     }
 }
 
@@ -66,6 +68,8 @@ fn test_params_with_floor() -> RiskParams {
         liquidation_fee_cap: U128::new(10_000),
         liquidation_buffer_bps: 100,
         min_liquidation_abs: U128::new(100_000),
+        liquidation_fee_keeper_share_bps: 0,
+        liquidation_fee_lp_share_bps: 0,
     }
 }
 
@@ -85,6 +89,8 @@ fn test_params_with_maintenance_fee() -> RiskParams {
         liquidation_fee_cap: U128::new(10_000),
         liquidation_buffer_bps: 100,
         min_liquidation_abs: U128::new(100_000),
+        liquidation_fee_keeper_share_bps: 0,
+        liquidation_fee_lp_share_bps: 0,
     }
 }
 
@@ -433,8 +439,6 @@ fn canonical_inv(engine: &RiskEngine) -> bool {
 
 /// Sync all engine aggregates (c_tot, pnl_pos_tot, total_open_interest) from account data.
 /// Call this after manually setting account.capital, account.pnl, or account.position_size.
-/// Unlike engine.recompute_aggregates() which only handles c_tot and pnl_pos_tot,
-/// this also recomputes total_open_interest.
 fn sync_engine_aggregates(engine: &mut RiskEngine) {
     engine.recompute_aggregates();
     let mut oi: u128 = 0;
@@ -2036,6 +2040,35 @@ fn fast_account_equity_computes_correctly() {
         owner: [0; 32],
         fee_credits: I128::ZERO,
         last_fee_slot: 0,
+        lp_pnl: LpPnlAttribution::default(),
+        lp_shares: U128::ZERO,
+        lp_queued_shares: U128::ZERO,
+        lp_claimable: U128::ZERO,
+        lp_locked_until_slot: 0,
+        lp_high_water_mark: U128::ZERO,
+        markout: MarkoutStats::default(),
+        holding_fee_index: U128::ZERO,
+        max_leverage: 0,
+        position_opened_slot: 0,
+        position_modified_slot: 0,
+        last_activity_slot: 0,
+        drain_epoch_start: 0,
+        epoch_drain: U128::ZERO,
+        tag: [0; 32],
+        force_close_deadline_slot: 0,
+        reduce_only: 0,
+        haircut_claim: U128::ZERO,
+        emission_slot: 0,
+        emission_epoch: 0,
+        emission_pending: U128::ZERO,
+        emission_claimable: U128::ZERO,
+        adl_first: 0,
+        credit_limit: U128::ZERO,
+        credit_rate_e9: 0,
+        credit_expiry_slot: 0,
+        credit_accrual_slot: 0,
+        conditional_notional: U128::ZERO,
+        activity: [ActivityRecord::default(); ACCOUNT_ACTIVITY_LEN],
     };
 
     let equity = engine.account_equity(&account);
@@ -2560,7 +2593,7 @@ fn proof_set_risk_reduction_threshold_updates() {
     let new_threshold: u128 = kani::any();
     kani::assume(new_threshold < u128::MAX / 2); // Bounded for sanity
 
-    engine.set_risk_reduction_threshold(new_threshold);
+    engine.set_risk_reduction_threshold(new_threshold).unwrap();
 
     assert!(
         engine.params.risk_reduction_threshold.get() == new_threshold,
@@ -5018,6 +5051,8 @@ fn params_for_inline_kani() -> RiskParams {
 
         liquidation_buffer_bps: 0,
         min_liquidation_abs: U128::new(0),
+        liquidation_fee_keeper_share_bps: 0,
+        liquidation_fee_lp_share_bps: 0,
     }
 }
 
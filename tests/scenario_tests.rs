@@ -0,0 +1,307 @@
+//! Scenario harness tests
+//! Run with: RUST_MIN_STACK=16777216 cargo test --features std --test scenario_tests
+
+#![cfg(feature = "std")]
+
+use percolator::scenario::*;
+use percolator::{AccountKind, NoOpMatcher, PriceShock, RiskError};
+
+#[test]
+fn test_scenario_pump_with_max_pnl_cap() {
+    let mut market = MarketBuilder::new().insurance(1_000_000).build();
+    let lp = market.add_lp(10_000_000);
+    let trader = market.add_user(1_000_000);
+
+    market.trade(lp, trader, 5_000_000).unwrap();
+    market.assert_position(trader, 5_000_000);
+    market.assert_conserved();
+
+    // Flat, then a +30% gap: PnL ~1.5M exceeds the 1M absolute cap
+    let path = PricePath::constant(1_000_000, 2)
+        .then(&PricePath::from_prices(&[1_300_000]))
+        .slots_per_step(10);
+    let mut closed_at = None;
+    for (i, &price) in path.prices.iter().enumerate() {
+        market.advance(path.slots_per_step);
+        market.set_price(price);
+        let outcome = market.crank_with(0, 1_000_000, 0).unwrap();
+        if outcome.max_pnl_closed > 0 && closed_at.is_none() {
+            closed_at = Some(i);
+        }
+        market.assert_conserved();
+    }
+
+    assert_eq!(closed_at, Some(2), "trader should be force-closed by the cap");
+    market.assert_flat(trader);
+    market.assert_insurance_at_least(1_000_000);
+}
+
+#[test]
+fn test_scenario_run_path_crash_liquidates() {
+    let mut market = MarketBuilder::new().insurance(1_000_000).build();
+    let lp = market.add_lp(10_000_000);
+    let trader = market.add_user(600_000);
+    market.trade(lp, trader, 5_000_000).unwrap();
+
+    let path = PricePath::constant(1_000_000, 2).then(&PricePath::shock(1_000_000, -1_000, 2));
+    assert_eq!(path.prices, vec![1_000_000, 1_000_000, 900_000, 900_000]);
+
+    let mut liquidations = 0;
+    market
+        .run_path(&path, |m, _, outcome| {
+            liquidations += outcome.num_liquidations;
+            m.assert_conserved();
+        })
+        .unwrap();
+
+    assert_eq!(liquidations, 1);
+    assert_eq!(market.slot(), 4);
+    assert!(market.engine.accounts[trader.idx as usize].position_size.get() < 5_000_000);
+}
+
+#[test]
+fn test_sim_clock_drives_warmup() {
+    let mut market = MarketBuilder::new()
+        .warmup_period_slots(100)
+        .insurance(1_000_000)
+        .start_slot(1_000)
+        .build();
+    assert_eq!(market.clock, SimClock::at(1_000));
+    let lp = market.add_lp(10_000_000);
+    let trader = market.add_user(1_000_000);
+
+    market.trade(lp, trader, 1_000_000).unwrap();
+    market.set_price(1_100_000);
+    market.trade(lp, trader, -1_000_000).unwrap();
+    market.assert_flat(trader);
+    let capital = |m: &Market| m.engine.accounts[trader.idx as usize].capital.get();
+    let start = capital(&market);
+
+    // No time has passed: nothing has warmed up yet
+    market.touch(trader).unwrap();
+    assert_eq!(capital(&market), start);
+
+    market.advance(50);
+    market.touch(trader).unwrap();
+    let halfway = capital(&market);
+    assert!(halfway > start);
+
+    market.clock.set(1_200);
+    market.touch(trader).unwrap();
+    assert!(capital(&market) > halfway);
+    assert_eq!(market.engine.accounts[trader.idx as usize].pnl.get(), 0);
+    market.withdraw(trader, capital(&market)).unwrap();
+    market.assert_conserved();
+}
+
+#[test]
+#[should_panic(expected = "clock cannot move backwards")]
+fn test_sim_clock_rejects_rewind() {
+    let mut clock = SimClock::at(10);
+    assert_eq!(clock.advance(5), 15);
+    clock.set(14);
+}
+
+#[test]
+fn test_stress_test_reports_per_shock_without_mutating() {
+    let mut market = MarketBuilder::new().insurance(1_000_000).build();
+    let lp = market.add_lp(10_000_000);
+    let trader = market.add_user(600_000);
+    market.trade(lp, trader, 5_000_000).unwrap();
+    let before = market.engine.clone();
+
+    let shocks = [
+        PriceShock { move_bps: 500 },
+        PriceShock { move_bps: -1_000 },
+        PriceShock { move_bps: -2_000 },
+    ];
+    let report = market.engine.stress_test(market.price, &shocks).unwrap();
+    assert_eq!(*market.engine, *before);
+    assert_eq!(report.oracle_price, 1_000_000);
+    assert_eq!(report.outcomes.len(), 3);
+
+    // Up move: long trader gains, nothing to liquidate
+    let up = report.outcomes[0];
+    assert_eq!(up.shocked_price, 1_050_000);
+    assert_eq!(up.liquidations, 0);
+    assert_eq!(up.bad_debt, 0);
+    assert_eq!(up.vault_drawdown, 0);
+
+    // -10%: trader under maintenance but still solvent
+    let mild = report.outcomes[1];
+    assert_eq!(mild.shocked_price, 900_000);
+    assert_eq!(mild.liquidations, 1);
+    assert!(mild.liquidated_abs > 0);
+    assert_eq!(mild.bad_debt, 0);
+    assert_eq!(mild.vault_drawdown, 0);
+
+    // -20%: loss of 1M against 595k capital (after the 5k fee) leaves 405k of bad debt
+    let severe = report.outcomes[2];
+    assert_eq!(severe.shocked_price, 800_000);
+    assert_eq!(severe.liquidations, 1);
+    assert_eq!(severe.bad_debt, 405_000);
+    assert!(severe.vault_drawdown >= 405_000);
+
+    // The live market still liquidates on the real path
+    market.set_price(900_000);
+    assert_eq!(market.crank().unwrap().num_liquidations, 1);
+}
+
+#[test]
+fn test_what_if_projects_trade_without_mutating() {
+    let mut market = MarketBuilder::new().insurance(1_000_000).build();
+    let lp = market.add_lp(10_000_000);
+    let trader = market.add_user(600_000);
+    let before = market.engine.clone();
+
+    let report = market
+        .engine
+        .what_if(&NoOpMatcher, lp.idx, trader.idx, market.slot(), market.price, 5_000_000)
+        .unwrap();
+    assert_eq!(*market.engine, *before);
+
+    assert_eq!(report.utilization_bps_before, 0);
+    // 10M OI notional (both sides) against an 11.6M vault
+    assert_eq!(report.utilization_bps_after, 8_621);
+    assert_eq!(report.lp_position_before, 0);
+    assert_eq!(report.lp_position_after, -5_000_000);
+    assert_eq!(report.net_lp_pos_before, 0);
+    assert_eq!(report.net_lp_pos_after, -5_000_000);
+    assert_eq!(report.user_position_after, 5_000_000);
+
+    // 595k equity after fee: 595k + 5M(P - 1) = 5% of 5M·P  =>  P ≈ 0.9274
+    let liq = report.liquidation_price.unwrap();
+    assert!((927_000..928_000).contains(&liq), "liq price {}", liq);
+
+    // The projection matches the live trade and the crank's view of it
+    market.trade(lp, trader, 5_000_000).unwrap();
+    let account = &market.engine.accounts[trader.idx as usize];
+    assert_eq!(market.engine.liquidation_price(account, market.price), Some(liq));
+    assert!(!market.engine.is_above_maintenance_margin_mtm(account, liq));
+    assert!(market.engine.is_above_maintenance_margin_mtm(account, liq + 1));
+
+    // Rejected trades surface the engine's error
+    let oversized = market
+        .engine
+        .what_if(&NoOpMatcher, lp.idx, trader.idx, market.slot(), market.price, 50_000_000);
+    assert_eq!(oversized, Err(RiskError::Undercollateralized));
+}
+
+#[test]
+fn test_risk_contribution_report_sorted_by_vault_loss() {
+    let mut market = MarketBuilder::new().insurance(1_000_000).build();
+    let lp = market.add_lp(50_000_000);
+    let small = market.add_user(600_000);
+    let big = market.add_user(1_000_000);
+    let _flat = market.add_user(1_000_000);
+    market.trade(lp, small, 2_000_000).unwrap();
+    market.trade(lp, big, 9_000_000).unwrap();
+
+    let report = market.engine.risk_contribution_report(market.price, 2_000).unwrap();
+    let order: Vec<u16> = report.iter().map(|c| c.idx).collect();
+    // Flat accounts are omitted; the LP has no shortfall but the largest notional
+    assert_eq!(order, vec![big.idx, lp.idx, small.idx]);
+    assert!(report[0].vault_loss > 0);
+    assert_eq!(report[2].vault_loss, 0);
+}
+
+#[test]
+fn test_keeper_plan_matches_actual_crank() {
+    use percolator::keeper::*;
+
+    let mut market = MarketBuilder::new().insurance(1_000_000).build();
+    let lp = market.add_lp(10_000_000);
+    let trader = market.add_user(600_000);
+    let healthy = market.add_user(600_000);
+    market.trade(lp, trader, 5_000_000).unwrap();
+    market.trade(lp, healthy, 1_000_000).unwrap();
+
+    // Nothing to liquidate: routine crank only
+    let plan =
+        plan_keeper_actions(&market.engine, u16::MAX, market.slot(), market.price, 0).unwrap();
+    assert!(plan.liquidation_targets.is_empty());
+    assert_eq!(plan.crank_calls.len(), 1);
+    assert!(plan.crank_calls[0].expected.sweep_complete);
+    assert_eq!(plan.priority, KeeperPriority::Low);
+
+    // -10%: the leveraged trader is below maintenance
+    market.advance(1);
+    market.set_price(900_000);
+    let before = market.engine.clone();
+    let plan =
+        plan_keeper_actions(&market.engine, u16::MAX, market.slot(), market.price, 0).unwrap();
+    assert_eq!(*market.engine, *before);
+    assert_eq!(plan.liquidation_targets.len(), 1);
+    let target = plan.liquidation_targets[0];
+    assert_eq!(target.idx, trader.idx);
+    assert_eq!(target.notional, 4_500_000);
+    assert!(target.margin_shortfall > 0 && !target.bad_debt);
+    assert_eq!(plan.priority, KeeperPriority::High);
+    assert_eq!(plan.priority.priority_fee_hint(1_000), 5_000);
+
+    // The real crank does what the plan predicted
+    let outcome = market.crank().unwrap();
+    assert_eq!(outcome, plan.crank_calls[0].expected);
+    let after = market.engine.accounts[trader.idx as usize].position_size.get();
+    assert_eq!(target.expected_close, (5_000_000 - after) as u128);
+    assert!(target.expected_close > 0);
+
+    assert_eq!(
+        plan_keeper_actions(&market.engine, u16::MAX, market.slot(), 0, 0),
+        Err(RiskError::Overflow)
+    );
+}
+
+#[test]
+fn test_read_many_views_share_one_state() {
+    let mut market = MarketBuilder::new().build();
+    let lp = market.add_lp(10_000_000);
+    let trader = market.add_user(1_000_000);
+    market.trade(lp, trader, 2_000_000).unwrap();
+
+    let batch = market.engine.read_many(&[trader.idx, 999, lp.idx]);
+    assert_eq!(batch.seq, market.engine.state_seq);
+    assert_eq!(batch.slot, market.engine.current_slot);
+    assert_eq!(batch.views.len(), 2);
+    assert_eq!(batch.views[0], market.engine.account_view(trader.idx).unwrap());
+    assert_eq!(batch.views[0].position, 2_000_000);
+    assert_eq!(batch.views[1].position, -2_000_000);
+    assert_eq!(batch.views[1].kind, AccountKind::LP);
+
+    // Any successful mutation moves the sequence number on
+    market.deposit(trader, 1).unwrap();
+    let next = market.engine.read_many(&[trader.idx]);
+    assert!(next.seq > batch.seq);
+    assert_eq!(next.views[0].capital, batch.views[0].capital + 1);
+}
+
+#[test]
+fn test_checked_run_reports_pass_and_fail() {
+    let mut market = MarketBuilder::new().insurance(1_000_000).build();
+    let lp = market.add_lp(10_000_000);
+    let trader = market.add_user(1_000_000);
+    market.trade(lp, trader, 5_000_000).unwrap();
+
+    let checks = Checks::new()
+        .after_step(0, "healthy at step 0", Metric::MarginRatioBps(trader), Bound::AtLeast(1_500))
+        .always("insurance intact", Metric::Insurance, Bound::AtLeast(1_000_000))
+        .at_end("vault loss bounded", Metric::VaultLoss, Bound::AtMost(0))
+        .at_end("trader still long", Metric::Position(trader), Bound::Equal(5_000_000))
+        .after_step(2, "margin after dip", Metric::MarginRatioBps(trader), Bound::AtLeast(1_500));
+    let path = PricePath::from_prices(&[1_000_000, 950_000, 900_000]);
+    let report = market.run_checked(&path, &checks).unwrap();
+
+    // 2 step checks + 3 every-step checks + 2 end checks
+    assert_eq!(report.results.len(), 7);
+    assert!(!report.passed());
+    let failures: Vec<&str> = report.failures().map(|r| r.check.name).collect();
+    assert_eq!(failures, vec!["margin after dip"]);
+    let failed = report.failures().next().unwrap();
+    assert_eq!(failed.step, Some(2));
+    assert_eq!(failed.price, 900_000);
+    assert_eq!(failed.actual, market.measure(Metric::MarginRatioBps(trader), 0));
+    assert!(failed.actual < 1_500);
+
+    let result = std::panic::catch_unwind(|| report.assert_passed());
+    assert!(result.is_err());
+}
@@ -0,0 +1,60 @@
+//! Strict invariant checking tests
+//! Run with: RUST_MIN_STACK=16777216 cargo test --features strict-invariants --test strict_invariants
+
+#![cfg(feature = "strict-invariants")]
+
+use percolator::*;
+
+const MATCHER: NoOpMatcher = NoOpMatcher;
+
+fn params() -> RiskParams {
+    RiskParams {
+        warmup_period_slots: 100,
+        maintenance_margin_bps: 500,
+        initial_margin_bps: 1000,
+        trading_fee_bps: 10,
+        max_accounts: 1000,
+        new_account_fee: U128::new(0),
+        risk_reduction_threshold: U128::new(0),
+        maintenance_fee_per_slot: U128::new(0),
+        max_crank_staleness_slots: u64::MAX,
+        liquidation_fee_bps: 50,
+        liquidation_fee_cap: U128::new(100_000),
+        liquidation_buffer_bps: 100,
+        min_liquidation_abs: U128::new(100_000),
+        liquidation_fee_keeper_share_bps: 0,
+        liquidation_fee_lp_share_bps: 0,
+    }
+}
+
+#[test]
+fn test_strict_invariants_hold_through_normal_flow() {
+    let mut engine = Box::new(RiskEngine::new(params()));
+    engine.top_up_insurance_fund(1_000_000).unwrap();
+    let lp = engine.add_lp([1u8; 32], [2u8; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 10_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+
+    engine
+        .execute_trade(&MATCHER, lp, user, 1, 1_000_000, 2_000_000)
+        .unwrap();
+    engine
+        .keeper_crank(user, 2, 1_050_000, 0, false, 0, 0)
+        .unwrap();
+    engine
+        .execute_trade(&MATCHER, lp, user, 3, 1_050_000, -2_000_000)
+        .unwrap();
+    engine.withdraw(user, 100_000, 4, 1_050_000).unwrap();
+}
+
+#[test]
+#[should_panic(expected = "strict-invariants after deposit: c_tot")]
+fn test_strict_invariants_catch_aggregate_drift() {
+    let mut engine = Box::new(RiskEngine::new(params()));
+    let user = engine.add_user(0).unwrap();
+    // Corrupt capital without maintaining c_tot; the next mutation must catch it
+    engine.accounts[user as usize].capital = U128::new(500);
+    engine.vault = U128::new(500);
+    engine.deposit(user, 100, 0).unwrap();
+}
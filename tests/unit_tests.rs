@@ -67,6 +67,8 @@ fn default_params() -> RiskParams {
         liquidation_fee_cap: U128::new(100_000), // Cap at 100k units
         liquidation_buffer_bps: 100,             // 1% buffer above maintenance
         min_liquidation_abs: U128::new(100_000), // Minimum 0.1 units (scaled by 1e6)
+        liquidation_fee_keeper_share_bps: 0,
+        liquidation_fee_lp_share_bps: 0,
     }
 }
 
@@ -217,6 +219,7 @@ fn test_withdraw_principal_with_negative_pnl_should_fail() {
     engine.accounts[user_idx as usize].position_size = I128::new(10_000);
     engine.accounts[user_idx as usize].entry_price = 1_000_000; // $1 entry price
     engine.accounts[user_idx as usize].pnl = I128::new(-800);
+    engine.recompute_aggregates();
 
     // Trying to withdraw all principal would leave collateral = 0 + max(0, -800) = 0
     // This should fail because user has an open position
@@ -303,6 +306,7 @@ fn test_withdraw_pnl_not_warmed_up() {
     assert_eq!(engine.accounts[counterparty as usize].pnl.get(), 0);
     engine.accounts[user_idx as usize].pnl = I128::new(500);
     engine.accounts[counterparty as usize].pnl = I128::new(-500);
+    engine.recompute_aggregates();
     assert_conserved(&engine);
 
     // Try to withdraw more than principal + warmed up PNL
@@ -371,6 +375,7 @@ fn test_conservation_simple() {
     assert_eq!(engine.accounts[user2 as usize].pnl.get(), 0);
     engine.accounts[user1 as usize].pnl = I128::new(500);
     engine.accounts[user2 as usize].pnl = I128::new(-500);
+    engine.recompute_aggregates();
     assert!(engine.check_conservation(DEFAULT_ORACLE));
 
     // Withdraw from user1's capital
@@ -392,6 +397,7 @@ fn test_trading_opens_position() {
     // WHITEBOX: Set LP capital directly. Add to vault to preserve conservation.
     engine.accounts[lp_idx as usize].capital = U128::new(100_000);
     engine.vault += 100_000;
+    engine.recompute_aggregates();
     assert_conserved(&engine);
 
     // Execute trade: user buys 1000 units at $1
@@ -423,6 +429,7 @@ fn test_trading_realizes_pnl() {
     // WHITEBOX: Set LP capital directly. Add to vault (not override) to preserve account fees.
     engine.accounts[lp_idx as usize].capital = U128::new(100_000);
     engine.vault += 100_000;
+    engine.recompute_aggregates();
     assert_conserved(&engine);
 
     // Open long position at $1
@@ -507,6 +514,7 @@ fn test_fee_accumulation() {
     // WHITEBOX: Set LP capital directly. Add to vault (not override) to preserve account fees.
     engine.accounts[lp_idx as usize].capital = U128::new(1_000_000);
     engine.vault += 1_000_000;
+    engine.recompute_aggregates();
     assert_conserved(&engine);
 
     // Track fee revenue and balance BEFORE trades
@@ -669,6 +677,7 @@ fn test_funding_positive_rate_longs_pay_shorts() {
     // LP has opposite short position
     engine.accounts[lp_idx as usize].position_size = I128::new(-1_000_000);
     engine.accounts[lp_idx as usize].entry_price = 100_000_000;
+    engine.recompute_aggregates();
 
     // Zero warmup/reserved to avoid side effects from touch_account
     engine.accounts[user_idx as usize].warmup_slope_per_step = U128::new(0);
@@ -735,6 +744,7 @@ fn test_funding_negative_rate_shorts_pay_longs() {
     // LP has opposite long position
     engine.accounts[lp_idx as usize].position_size = I128::new(1_000_000);
     engine.accounts[lp_idx as usize].entry_price = 100_000_000;
+    engine.recompute_aggregates();
 
     // Zero warmup/reserved to avoid side effects from touch_account
     engine.accounts[user_idx as usize].warmup_slope_per_step = U128::new(0);
@@ -778,6 +788,7 @@ fn test_funding_idempotence() {
 
     engine.deposit(user_idx, 100_000, 0).unwrap();
     engine.accounts[user_idx as usize].position_size = I128::new(1_000_000);
+    engine.recompute_aggregates();
 
     // Accrue funding
     engine.accrue_funding_with_rate(1, 100_000_000, 10).unwrap();
@@ -808,6 +819,7 @@ fn test_funding_partial_close() {
     // WHITEBOX: Set LP capital directly. Add to vault (not override) to preserve account fees.
     engine.accounts[lp_idx as usize].capital = U128::new(50_000_000);
     engine.vault += 50_000_000;
+    engine.recompute_aggregates();
     assert_conserved(&engine);
 
     // Open long position of 2M base units
@@ -860,6 +872,7 @@ fn test_funding_position_flip() {
     // WHITEBOX: Set LP capital directly. Add to vault (not override) to preserve account fees.
     engine.accounts[lp_idx as usize].capital = U128::new(20_000_000);
     engine.vault += 20_000_000;
+    engine.recompute_aggregates();
     assert_conserved(&engine);
 
     // Open long
@@ -937,6 +950,7 @@ fn test_funding_does_not_touch_principal() {
     engine.deposit(user_idx, initial_principal, 0).unwrap();
 
     engine.accounts[user_idx as usize].position_size = I128::new(1_000_000);
+    engine.recompute_aggregates();
 
     // Accrue funding
     engine.accrue_funding_with_rate(1, 100_000_000, 100).unwrap();
@@ -1421,12 +1435,14 @@ fn test_warmup_slope_nonzero() {
 
     // Set minimal positive PnL (1 unit, less than warmup_period_slots)
     engine.accounts[user as usize].pnl = I128::new(1);
+    engine.recompute_aggregates();
 
     // Create counterparty for zero-sum
     // Zero-sum pattern: net_pnl = 0, so no vault funding needed
     let loser = engine.add_user(0).unwrap();
     engine.deposit(loser, 10_000, 0).unwrap();
     engine.accounts[loser as usize].pnl = I128::new(-1);
+    engine.recompute_aggregates();
 
     assert_conserved(&engine);
 
@@ -1554,6 +1570,7 @@ fn test_withdraw_rejected_when_closed_and_negative_pnl() {
     engine.accounts[user_idx as usize].position_size = I128::new(0); // No position
     engine.accounts[user_idx as usize].warmup_slope_per_step = U128::new(0);
     engine.vault = U128::new(10_000);
+    engine.recompute_aggregates();
 
     // Attempt to withdraw full capital - should fail because losses must be realized first
     let result = engine.withdraw(user_idx, 10_000, 0, 1_000_000);
@@ -1592,6 +1609,7 @@ fn test_withdraw_allows_remaining_principal_after_loss_realization() {
     engine.accounts[user_idx as usize].position_size = I128::new(0);
     engine.accounts[user_idx as usize].warmup_slope_per_step = U128::new(0);
     engine.vault = U128::new(10_000);
+    engine.recompute_aggregates();
 
     // First, trigger loss settlement
     engine.settle_warmup_to_capital(user_idx).unwrap();
@@ -1623,6 +1641,7 @@ fn test_negative_pnl_settles_immediately_independent_of_slope() {
     engine.accounts[user_idx as usize].warmup_slope_per_step = U128::new(0); // Zero slope
     engine.accounts[user_idx as usize].warmup_started_at_slot = 0;
     engine.vault = U128::new(capital);
+    engine.recompute_aggregates();
     engine.current_slot = 100; // Time has passed
 
 
@@ -1697,6 +1716,7 @@ fn test_withdraw_open_position_blocks_due_to_equity() {
     engine.accounts[user_idx as usize].entry_price = 1_000_000;
     engine.accounts[user_idx as usize].warmup_slope_per_step = U128::new(0);
     engine.vault = U128::new(150);
+    engine.recompute_aggregates();
 
     // withdraw(60) should fail - loss settles first, then MM re-check catches
     // that equity(50) is not strictly above MM(50)
@@ -1740,6 +1760,35 @@ fn test_account_equity_computes_correctly() {
         owner: [0; 32],
         fee_credits: I128::ZERO,
         last_fee_slot: 0,
+        lp_pnl: LpPnlAttribution::default(),
+        lp_shares: U128::ZERO,
+        lp_queued_shares: U128::ZERO,
+        lp_claimable: U128::ZERO,
+        lp_locked_until_slot: 0,
+        lp_high_water_mark: U128::ZERO,
+        markout: MarkoutStats::default(),
+        holding_fee_index: U128::ZERO,
+        max_leverage: 0,
+        position_opened_slot: 0,
+        position_modified_slot: 0,
+        last_activity_slot: 0,
+        drain_epoch_start: 0,
+        epoch_drain: U128::ZERO,
+        tag: [0; 32],
+        force_close_deadline_slot: 0,
+        reduce_only: 0,
+        haircut_claim: U128::ZERO,
+        emission_slot: 0,
+        emission_epoch: 0,
+        emission_pending: U128::ZERO,
+        emission_claimable: U128::ZERO,
+        adl_first: 0,
+        credit_limit: U128::ZERO,
+        credit_rate_e9: 0,
+        credit_expiry_slot: 0,
+        credit_accrual_slot: 0,
+        conditional_notional: U128::ZERO,
+        activity: [ActivityRecord::default(); ACCOUNT_ACTIVITY_LEN],
     };
     assert_eq!(engine.account_equity(&account_pos), 7_000);
 
@@ -1760,6 +1809,35 @@ fn test_account_equity_computes_correctly() {
         owner: [0; 32],
         fee_credits: I128::ZERO,
         last_fee_slot: 0,
+        lp_pnl: LpPnlAttribution::default(),
+        lp_shares: U128::ZERO,
+        lp_queued_shares: U128::ZERO,
+        lp_claimable: U128::ZERO,
+        lp_locked_until_slot: 0,
+        lp_high_water_mark: U128::ZERO,
+        markout: MarkoutStats::default(),
+        holding_fee_index: U128::ZERO,
+        max_leverage: 0,
+        position_opened_slot: 0,
+        position_modified_slot: 0,
+        last_activity_slot: 0,
+        drain_epoch_start: 0,
+        epoch_drain: U128::ZERO,
+        tag: [0; 32],
+        force_close_deadline_slot: 0,
+        reduce_only: 0,
+        haircut_claim: U128::ZERO,
+        emission_slot: 0,
+        emission_epoch: 0,
+        emission_pending: U128::ZERO,
+        emission_claimable: U128::ZERO,
+        adl_first: 0,
+        credit_limit: U128::ZERO,
+        credit_rate_e9: 0,
+        credit_expiry_slot: 0,
+        credit_accrual_slot: 0,
+        conditional_notional: U128::ZERO,
+        activity: [ActivityRecord::default(); ACCOUNT_ACTIVITY_LEN],
     };
     assert_eq!(engine.account_equity(&account_neg), 0);
 
@@ -1780,6 +1858,35 @@ fn test_account_equity_computes_correctly() {
         owner: [0; 32],
         fee_credits: I128::ZERO,
         last_fee_slot: 0,
+        lp_pnl: LpPnlAttribution::default(),
+        lp_shares: U128::ZERO,
+        lp_queued_shares: U128::ZERO,
+        lp_claimable: U128::ZERO,
+        lp_locked_until_slot: 0,
+        lp_high_water_mark: U128::ZERO,
+        markout: MarkoutStats::default(),
+        holding_fee_index: U128::ZERO,
+        max_leverage: 0,
+        position_opened_slot: 0,
+        position_modified_slot: 0,
+        last_activity_slot: 0,
+        drain_epoch_start: 0,
+        epoch_drain: U128::ZERO,
+        tag: [0; 32],
+        force_close_deadline_slot: 0,
+        reduce_only: 0,
+        haircut_claim: U128::ZERO,
+        emission_slot: 0,
+        emission_epoch: 0,
+        emission_pending: U128::ZERO,
+        emission_claimable: U128::ZERO,
+        adl_first: 0,
+        credit_limit: U128::ZERO,
+        credit_rate_e9: 0,
+        credit_expiry_slot: 0,
+        credit_accrual_slot: 0,
+        conditional_notional: U128::ZERO,
+        activity: [ActivityRecord::default(); ACCOUNT_ACTIVITY_LEN],
     };
     assert_eq!(engine.account_equity(&account_profit), 15_000);
 }
@@ -1867,6 +1974,7 @@ fn test_withdraw_im_check_blocks_when_equity_below_im() {
     engine.accounts[user_idx as usize].pnl = I128::new(0);
     engine.accounts[user_idx as usize].position_size = I128::new(1000);
     engine.accounts[user_idx as usize].entry_price = 1_000_000;
+    engine.recompute_aggregates();
     engine.funding_index_qpb_e6 = I128::new(0);
     engine.accounts[user_idx as usize].funding_index = I128::new(0);
 
@@ -1891,7 +1999,7 @@ fn test_keeper_crank_liquidates_undercollateralized_user() {
     let mut engine = Box::new(RiskEngine::new(default_params()));
 
     // Fund insurance to avoid force-realize mode (threshold=0 means balance=0 triggers it)
-    engine.insurance_fund.balance = U128::new(1_000_000);
+    set_insurance(&mut engine, 1_000_000);
 
     // Create user and LP
     let user = engine.add_user(0).unwrap();
@@ -1904,13 +2012,13 @@ fn test_keeper_crank_liquidates_undercollateralized_user() {
     engine.accounts[user as usize].entry_price = 1_000_000;
     engine.accounts[lp as usize].position_size = I128::new(-1_000_000);
     engine.accounts[lp as usize].entry_price = 1_000_000;
-    engine.total_open_interest = U128::new(2_000_000);
 
     // Set negative PnL to make user undercollateralized
     // Position value at oracle 0.5 = 500_000
     // Maintenance margin = 500_000 * 5% = 25_000
     // User has capital 10_000, needs equity > 25_000 to avoid liquidation
     engine.accounts[user as usize].pnl = I128::new(-9_500); // equity = 500 < 25_000
+    engine.recompute_aggregates();
 
     let _insurance_before = engine.insurance_fund.balance;
 
@@ -1963,8 +2071,8 @@ fn test_liquidation_fee_calculation() {
     engine.accounts[user as usize].position_size = I128::new(100_000); // 0.1 unit
     engine.accounts[user as usize].entry_price = 1_000_000;
     engine.accounts[user as usize].pnl = I128::new(0);
-    engine.total_open_interest = U128::new(100_000);
     engine.vault = U128::new(4_000);
+    engine.recompute_aggregates();
 
     let insurance_before = engine.insurance_fund.balance;
     let oracle_price: u64 = 1_000_000; // Same as entry = no mark pnl
@@ -1996,6 +2104,269 @@ fn test_liquidation_fee_calculation() {
     );
 }
 
+/// Set up a keeper, an LP, and a user just below maintenance at oracle = entry.
+/// Liquidation fee = 0.5% of 100_000 notional = 500.
+fn setup_fee_waterfall(engine: &mut RiskEngine) -> (u16, u16, u16) {
+    set_insurance(engine, 1_000_000);
+    let keeper = engine.add_user(0).unwrap();
+    let lp = engine.add_lp([1u8; 32], [2u8; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(keeper, 10_000, 0).unwrap();
+    engine.deposit(lp, 100_000, 0).unwrap();
+    engine.deposit(user, 4_000, 0).unwrap();
+
+    engine.accounts[user as usize].position_size = I128::new(100_000);
+    engine.accounts[user as usize].entry_price = 1_000_000;
+    engine.accounts[lp as usize].position_size = I128::new(-100_000);
+    engine.accounts[lp as usize].entry_price = 1_000_000;
+    engine.total_open_interest = U128::new(200_000);
+    (keeper, lp, user)
+}
+
+/// Test: Liquidation fee is split between keeper, LPs and insurance per params
+#[test]
+fn test_liquidation_fee_waterfall_split() {
+    let mut params = default_params();
+    params.liquidation_fee_keeper_share_bps = 2_000; // 20%
+    params.liquidation_fee_lp_share_bps = 3_000; // 30%
+    let mut engine = Box::new(RiskEngine::new(params));
+    let (keeper, lp, user) = setup_fee_waterfall(&mut engine);
+
+    let insurance_before = engine.insurance_fund.balance.get();
+    let revenue_before = engine.insurance_fund.fee_revenue.get();
+
+    let record = engine
+        .liquidate_at_oracle_with_keeper(user, Some(keeper), 0, 1_000_000)
+        .unwrap()
+        .expect("Liquidation should occur");
+
+    assert_eq!(record.fee_total, 500);
+    assert_eq!(record.fee_to_keeper, 100);
+    assert_eq!(record.fee_to_lp, 150);
+    assert_eq!(record.fee_to_insurance, 250);
+    assert_eq!(engine.accounts[keeper as usize].capital.get(), 10_100);
+    assert_eq!(engine.accounts[lp as usize].capital.get(), 100_150);
+    assert_eq!(engine.insurance_fund.balance.get() - insurance_before, 250);
+    assert_eq!(engine.insurance_fund.fee_revenue.get() - revenue_before, 250);
+    assert_conserved(&engine);
+
+    // Without a keeper, the keeper share falls through to insurance
+    let mut engine = Box::new(RiskEngine::new(params));
+    let (_keeper, _lp, user) = setup_fee_waterfall(&mut engine);
+    let insurance_before = engine.insurance_fund.balance.get();
+    assert!(engine.liquidate_at_oracle(user, 0, 1_000_000).unwrap());
+    assert_eq!(engine.insurance_fund.balance.get() - insurance_before, 350);
+    assert_conserved(&engine);
+}
+
+/// Test: keeper_crank credits the caller and reports the fee split in its outcome
+#[test]
+fn test_keeper_crank_reports_liquidation_fee_split() {
+    let mut params = default_params();
+    params.liquidation_fee_keeper_share_bps = 2_000;
+    params.liquidation_fee_lp_share_bps = 3_000;
+    let mut engine = Box::new(RiskEngine::new(params));
+    let (keeper, lp, user) = setup_fee_waterfall(&mut engine);
+
+    let outcome = engine.keeper_crank(keeper, 1, 1_000_000, 0, false, 0, 0).unwrap();
+
+    assert_eq!(outcome.num_liquidations, 1);
+    assert_eq!(engine.accounts[user as usize].position_size.get(), 0);
+    assert_eq!(outcome.liq_fee_to_keeper, 100);
+    assert_eq!(outcome.liq_fee_to_lp, 150);
+    assert_eq!(outcome.liq_fee_to_insurance, 250);
+    assert_eq!(outcome.keeper_rewards, 100);
+    assert_eq!(outcome.insurance_contributions, 250);
+    assert_eq!(engine.accounts[keeper as usize].capital.get(), 10_100);
+    assert_eq!(engine.accounts[lp as usize].capital.get(), 100_150);
+    assert_conserved(&engine);
+}
+
+/// Test: an LP liquidated by the crank takes no share of its own liquidation fee
+#[test]
+fn test_keeper_crank_liquidated_lp_excluded_from_fee_share() {
+    let mut params = default_params();
+    params.liquidation_fee_keeper_share_bps = 2_000;
+    params.liquidation_fee_lp_share_bps = 3_000;
+    let mut engine = Box::new(RiskEngine::new(params));
+    set_insurance(&mut engine, 1_000_000);
+    let keeper = engine.add_user(0).unwrap();
+    let healthy = engine.add_lp([1u8; 32], [2u8; 32], 0).unwrap();
+    let failing = engine.add_lp([3u8; 32], [4u8; 32], 0).unwrap();
+    engine.deposit(keeper, 10_000, 0).unwrap();
+    engine.deposit(healthy, 100_000, 0).unwrap();
+    engine.deposit(failing, 4_000, 0).unwrap();
+    engine.accounts[failing as usize].position_size = I128::new(100_000);
+    engine.accounts[failing as usize].entry_price = 1_000_000;
+    engine.accounts[healthy as usize].position_size = I128::new(-100_000);
+    engine.accounts[healthy as usize].entry_price = 1_000_000;
+    engine.total_open_interest = U128::new(200_000);
+
+    let outcome = engine.keeper_crank(keeper, 1, 1_000_000, 0, false, 0, 0).unwrap();
+
+    assert_eq!(outcome.num_liquidations, 1);
+    assert_eq!(outcome.liq_fee_to_lp, 150);
+    assert_eq!(engine.accounts[healthy as usize].capital.get(), 100_150);
+    assert_eq!(engine.accounts[failing as usize].capital.get(), 3_500);
+    assert_conserved(&engine);
+}
+
+/// Test: cranks inside the minimum interval, or twice in a slot, are no-ops
+#[test]
+fn test_keeper_crank_min_interval_and_same_slot_dedup() {
+    let mut params = default_params();
+    params.maintenance_fee_per_slot = U128::new(1);
+    let mut engine = Box::new(RiskEngine::new(params));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    engine.deposit(lp, 1_000_000, 0).unwrap();
+
+    let first = engine.keeper_crank(lp, 10, 1_000_000, 0, false, 0, 0).unwrap();
+    assert!(!first.skipped && first.sweep_complete);
+    let events = engine.event_seq;
+
+    // Same slot: idempotent
+    let again = engine.keeper_crank(lp, 10, 1_000_000, 0, false, 0, 0).unwrap();
+    assert!(again.skipped && !again.advanced);
+    assert_eq!(engine.event_seq, events);
+
+    let ext = ExtParams {
+        min_crank_interval_slots: 20,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let capital = engine.accounts[lp as usize].capital.get();
+    assert!(engine.keeper_crank(lp, 29, 1_000_000, 0, false, 0, 0).unwrap().skipped);
+    assert_eq!(engine.last_crank_slot, 10);
+    assert_eq!(engine.accounts[lp as usize].capital.get(), capital);
+
+    let outcome = engine.keeper_crank(lp, 30, 1_000_000, 0, false, 0, 0).unwrap();
+    assert!(!outcome.skipped && outcome.advanced);
+    assert_eq!(engine.last_crank_slot, 30);
+}
+
+/// Test: sharded cranks scan disjoint ranges and complete the sweep together
+#[test]
+fn test_keeper_crank_shards_merge_progress() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let count = MAX_CRANK_SHARDS as u16;
+    let width = MAX_ACCOUNTS / MAX_CRANK_SHARDS;
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    engine.deposit(lp, 10_000_000, 0).unwrap();
+    let mut far = lp;
+    while (far as usize) < width {
+        far = engine.add_user(0).unwrap();
+    }
+    engine.deposit(far, 1_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, far, 0, 1_000_000, 1_000_000).unwrap();
+    // Push the user in shard 1 under maintenance margin
+    engine.set_capital(far as usize, 10_000);
+    let far = far as usize;
+    let shard = |index: u16| CrankShard { index, count };
+    assert_eq!(shard(0).range(), (0, width));
+    assert_eq!(shard(1).range(), (width, 2 * width));
+
+    let outcome = engine.keeper_crank_shard(lp, 10, 1_000_000, 0, false, 0, 0, shard(0)).unwrap();
+    assert!(!outcome.sweep_complete);
+    assert_eq!(outcome.num_liquidations, 0, "other shard untouched");
+
+    // A finished shard waits; the sweep's shard count is fixed
+    let again = engine.keeper_crank_shard(lp, 11, 1_000_000, 0, false, 0, 0, shard(0)).unwrap();
+    assert!(again.skipped);
+    let other = CrankShard { index: 0, count: 3 };
+    assert_eq!(
+        engine.keeper_crank_shard(lp, 11, 1_000_000, 0, false, 0, 0, other),
+        Err(RiskError::InvalidParams)
+    );
+
+    let outcome = engine.keeper_crank_shard(lp, 11, 1_000_000, 0, false, 0, 0, shard(1)).unwrap();
+    assert_eq!(outcome.num_liquidations, 1);
+    assert!(engine.accounts[far].position_size.get() < 1_000_000);
+    for index in 2..count {
+        let outcome =
+            engine.keeper_crank_shard(lp, 12, 1_000_000, 0, false, 0, 0, shard(index)).unwrap();
+        assert_eq!(outcome.sweep_complete, index == count - 1);
+    }
+    assert_eq!(engine.last_full_sweep_completed_slot, 12);
+    assert!(!engine.crank_shard_sweep_active);
+
+    // The next sharded sweep can use a different split
+    let whole = CrankShard { index: 0, count: 1 };
+    let outcome = engine.keeper_crank_shard(lp, 13, 1_000_000, 0, false, 0, 0, whole).unwrap();
+    assert!(outcome.sweep_complete);
+}
+
+/// Test: crank_progress reports the checkpoint and what the sweep has left
+#[test]
+fn test_crank_progress_reports_pending_accounts() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    engine.deposit(lp, 1_000_000, 0).unwrap();
+    let total = (ACCOUNTS_PER_CRANK as usize + 45).min(MAX_ACCOUNTS) as u32;
+    // What the first crank leaves (nothing when one crank covers the whole slab)
+    let pending = total.saturating_sub(ACCOUNTS_PER_CRANK as u32);
+    for _ in 1..total {
+        let user = engine.add_user(0).unwrap();
+        engine.deposit(user, 1_000, 0).unwrap();
+    }
+
+    let progress = engine.crank_progress(0);
+    assert!(!progress.sweep_in_progress);
+    assert_eq!(progress.accounts_pending, total);
+
+    let outcome = engine.keeper_crank(lp, 10, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(outcome.sweep_complete, pending == 0);
+    if pending > 0 {
+        let progress = engine.crank_progress(15);
+        assert!(progress.sweep_in_progress);
+        assert_eq!(progress.cursor, outcome.last_cursor);
+        assert_eq!(progress.accounts_pending, pending);
+        assert_eq!(progress.last_full_sweep_start_slot, 10);
+        assert_eq!(progress.slots_since_full_sweep, 15);
+
+        // Continuing the sweep in the same slot finishes it
+        assert!(engine.keeper_crank(lp, 10, 1_000_000, 0, false, 0, 0).unwrap().sweep_complete);
+    }
+    let progress = engine.crank_progress(15);
+    assert!(!progress.sweep_in_progress);
+    assert_eq!(progress.last_full_sweep_completed_slot, 10);
+    assert_eq!(progress.slots_since_full_sweep, 5);
+
+    // Sharded sweep progress
+    let count = MAX_CRANK_SHARDS as u16;
+    let shard = CrankShard { index: 0, count };
+    engine.keeper_crank_shard(lp, 20, 1_000_000, 0, false, 0, 0, shard).unwrap();
+    let progress = engine.crank_progress(20);
+    assert!(progress.sharded_sweep_active);
+    assert_eq!((progress.shards_done, progress.shard_count), (1, count));
+    let width = (MAX_ACCOUNTS / MAX_CRANK_SHARDS) as u32;
+    assert_eq!(progress.shard_accounts_pending, total - width);
+}
+
+/// Test: keeper_crank reports the maintenance fees and funding it settled
+#[test]
+fn test_keeper_crank_reports_fee_and_funding_totals() {
+    let mut params = default_params();
+    params.maintenance_fee_per_slot = U128::new(1);
+    let mut engine = Box::new(RiskEngine::new(params));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 10_000_000, 0).unwrap();
+    engine.deposit(user, 10_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 1_000_000).unwrap();
+
+    // Sets 1 bps/slot for the next interval
+    engine.keeper_crank(u16::MAX, 1, 1_000_000, 1, false, 0, 0).unwrap();
+
+    // 10 slots: the long pays 1_000, each account owes 10 in maintenance;
+    // the user's fee credits cover its share
+    let outcome = engine.keeper_crank(u16::MAX, 11, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(outcome.funding_long_to_short, 1_000);
+    assert_eq!(outcome.funding_short_to_long, 0);
+    assert_eq!(outcome.maintenance_fees_collected, 10);
+    assert_eq!(outcome.insurance_contributions, 10);
+    assert_eq!(outcome.keeper_rewards, 0);
+}
+
 // ============================================================================
 // PARTIAL LIQUIDATION TESTS
 // ============================================================================
@@ -2021,8 +2392,8 @@ fn test_dust_killswitch_forces_full_close() {
     engine.accounts[user as usize].position_size = I128::new(6_000_000);
     engine.accounts[user as usize].entry_price = 1_000_000;
     engine.accounts[user as usize].pnl = I128::new(0);
-    engine.total_open_interest = U128::new(6_000_000);
     engine.vault = U128::new(200_000);
+    engine.recompute_aggregates();
 
     // Oracle at entry price (no mark pnl)
     let oracle_price = 1_000_000;
@@ -2059,8 +2430,8 @@ fn test_partial_liquidation_brings_to_safety() {
     engine.accounts[user as usize].position_size = I128::new(10_000_000);
     engine.accounts[user as usize].entry_price = 1_000_000;
     engine.accounts[user as usize].pnl = I128::new(0);
-    engine.total_open_interest = U128::new(10_000_000);
     engine.vault = U128::new(100_000);
+    engine.recompute_aggregates();
 
     let oracle_price = 1_000_000;
     let pos_before = engine.accounts[user as usize].position_size;
@@ -2102,8 +2473,8 @@ fn test_partial_liquidation_fee_charged() {
     engine.accounts[user as usize].position_size = I128::new(500_000); // 0.5 units
     engine.accounts[user as usize].entry_price = 1_000_000;
     engine.accounts[user as usize].pnl = I128::new(0);
-    engine.total_open_interest = U128::new(500_000);
     engine.vault = U128::new(20_000);
+    engine.recompute_aggregates();
 
     let insurance_before = engine.insurance_fund.balance;
     let oracle_price = 1_000_000;
@@ -2121,6 +2492,37 @@ fn test_partial_liquidation_fee_charged() {
 }
 
 /// Test 4: Compute liquidation close amount basic test
+#[test]
+fn test_min_liquidation_bps_forces_meaningful_progress() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let user = engine.add_user(0).unwrap();
+    engine.accounts[user as usize].capital = U128::new(500_000);
+    engine.accounts[user as usize].position_size = I128::new(10_000_000);
+    engine.accounts[user as usize].entry_price = 1_000_000;
+
+    // Margin alone needs ~1.67M closed
+    let (close_abs, _) =
+        engine.compute_liquidation_close_amount(&engine.accounts[user as usize], 1_000_000);
+    assert!(close_abs < 2_000_000);
+
+    let ext = ExtParams {
+        min_liquidation_bps: 2_500,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    assert_eq!(engine.min_liquidation_size(10_000_000), 2_500_000);
+    let (close_abs, is_full) =
+        engine.compute_liquidation_close_amount(&engine.accounts[user as usize], 1_000_000);
+    assert_eq!(close_abs, 2_500_000);
+    assert!(!is_full);
+
+    // The absolute floor still applies to small positions
+    assert_eq!(
+        engine.min_liquidation_size(1_000),
+        engine.params.min_liquidation_abs.get().max(250)
+    );
+}
+
 #[test]
 fn test_compute_liquidation_close_amount_basic() {
     let params = default_params();
@@ -2217,15 +2619,15 @@ fn test_set_threshold_updates_value() {
     assert_eq!(engine.risk_reduction_threshold(), 0);
 
     // Set new threshold
-    engine.set_risk_reduction_threshold(5_000);
+    engine.set_risk_reduction_threshold(5_000).unwrap();
     assert_eq!(engine.risk_reduction_threshold(), 5_000);
 
     // Update again
-    engine.set_risk_reduction_threshold(10_000);
+    engine.set_risk_reduction_threshold(10_000).unwrap();
     assert_eq!(engine.risk_reduction_threshold(), 10_000);
 
     // Set to zero
-    engine.set_risk_reduction_threshold(0);
+    engine.set_risk_reduction_threshold(0).unwrap();
     assert_eq!(engine.risk_reduction_threshold(), 0);
 }
 
@@ -2236,7 +2638,7 @@ fn test_set_threshold_large_value() {
 
     // Set to large value
     let large = u128::MAX / 2;
-    engine.set_risk_reduction_threshold(large);
+    engine.set_risk_reduction_threshold(large).unwrap();
     assert_eq!(engine.risk_reduction_threshold(), large);
 }
 
@@ -2280,6 +2682,7 @@ fn test_gc_positive_pnl_never_collected() {
     let user = engine.add_user(0).unwrap();
     // No deposit - capital = 0
     engine.accounts[user as usize].pnl = I128::new(1000); // Positive PnL
+    engine.recompute_aggregates();
 
     assert!(engine.is_used(user as usize), "User should exist");
 
@@ -2380,6 +2783,56 @@ fn test_gc_with_position_not_collected() {
     assert_eq!(outcome.num_gc_closed, 0, "Should not GC any accounts");
 }
 
+#[test]
+fn test_gc_dormant_account_residual_claimed_for_insurance() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        gc_inactive_slots: 500,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    set_insurance(&mut engine, 10_000);
+
+    // Zero capital with 1_000 of residual PnL, backed by the vault
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(user, 0, 100).unwrap();
+    assert_eq!(engine.accounts[user as usize].last_activity_slot, 100);
+    engine.set_pnl(user as usize, 1_000);
+    engine.vault = U128::new(engine.vault.get() + 1_000);
+
+    // Active within the window: kept
+    engine.current_slot = 599;
+    assert_eq!(engine.garbage_collect_dust(), 0);
+    assert!(engine.is_used(user as usize));
+
+    // Dormant: closed, residual claimed
+    engine.current_slot = 600;
+    engine.gc_cursor = 0;
+    assert_eq!(engine.garbage_collect_dust(), 1);
+    assert!(!engine.is_used(user as usize));
+    assert_eq!(engine.insurance_fund.balance.get(), 11_000);
+    assert_eq!(engine.pnl_pos_tot.get(), 0);
+}
+
+#[test]
+fn test_gc_keeps_haircut_claims_and_emission_points() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let claim = engine.add_user(0).unwrap();
+    let pending = engine.add_user(0).unwrap();
+    let claimable = engine.add_user(0).unwrap();
+    let dust = engine.add_user(0).unwrap();
+    engine.accounts[claim as usize].haircut_claim = U128::new(100);
+    engine.accounts[pending as usize].emission_pending = U128::new(100);
+    engine.accounts[claimable as usize].emission_claimable = U128::new(100);
+
+    // Otherwise flat and empty, only the plain dust account is closed
+    assert_eq!(engine.garbage_collect_dust(), 1);
+    assert!(!engine.is_used(dust as usize));
+    for idx in [claim, pending, claimable] {
+        assert!(engine.is_used(idx as usize));
+    }
+}
+
 // ==============================================================================
 // BATCHED ADL TESTS
 // ==============================================================================
@@ -2400,8 +2853,18 @@ fn test_batched_adl_profit_exclusion() {
     set_insurance(&mut engine, 100_000);
 
     // IMPORTANT: Account creation order matters for per-account processing.
-    // We create the liquidated account FIRST so targets are processed AFTER,
-    // allowing them to be haircutted to fund the liquidation profit.
+    // The counterparty comes first so its losses are realized before any profit
+    // converts; the liquidated account comes before the targets so they are
+    // processed AFTER, allowing them to be haircutted to fund the liquidation profit.
+
+    // Create a counterparty with negative pnl to balance the targets (for conservation),
+    // short against the winner for zero-sum
+    let counterparty = engine.add_user(0).unwrap();
+    engine.deposit(counterparty, 100_000, 0).unwrap();
+    engine.accounts[counterparty as usize].pnl = I128::new(-40_000); // Negative pnl balances targets
+    engine.accounts[counterparty as usize].position_size = I128::new(-1_000_000);
+    engine.accounts[counterparty as usize].entry_price = 800_000;
+    engine.recompute_aggregates();
 
     // Create the account to be liquidated FIRST: long from 0.8, so has PROFIT at 0.81
     // But with very low capital, maintenance margin will fail.
@@ -2410,6 +2873,7 @@ fn test_batched_adl_profit_exclusion() {
     engine.deposit(winner_liq, 1_000, 0).unwrap(); // Only 1000 capital
     engine.accounts[winner_liq as usize].position_size = I128::new(1_000_000); // Long 1 unit
     engine.accounts[winner_liq as usize].entry_price = 800_000; // Entered at 0.8
+    engine.recompute_aggregates();
 
     // Create two accounts that will be the socialization targets (they have positive REALIZED PnL)
     // Socialization haircuts unwrapped PnL (not yet warmed), so keep slope=0.
@@ -2420,6 +2884,7 @@ fn test_batched_adl_profit_exclusion() {
                                                                    // Keep PnL unwrapped (not warmed) so socialization can haircut it
     engine.accounts[adl_target1 as usize].warmup_slope_per_step = U128::new(0);
     engine.accounts[adl_target1 as usize].warmup_started_at_slot = 0;
+    engine.recompute_aggregates();
 
     // Target 2: Also has realized profit
     let adl_target2 = engine.add_user(0).unwrap();
@@ -2427,16 +2892,7 @@ fn test_batched_adl_profit_exclusion() {
     engine.accounts[adl_target2 as usize].pnl = I128::new(20_000); // Realized profit
     engine.accounts[adl_target2 as usize].warmup_slope_per_step = U128::new(0);
     engine.accounts[adl_target2 as usize].warmup_started_at_slot = 0;
-
-    // Create a counterparty with negative pnl to balance the targets (for conservation)
-    let counterparty = engine.add_user(0).unwrap();
-    engine.deposit(counterparty, 100_000, 0).unwrap();
-    engine.accounts[counterparty as usize].pnl = I128::new(-40_000); // Negative pnl balances targets
-
-    // Set up counterparty short position for zero-sum (counterparty takes other side)
-    engine.accounts[counterparty as usize].position_size = I128::new(-1_000_000);
-    engine.accounts[counterparty as usize].entry_price = 800_000;
-    engine.total_open_interest = U128::new(2_000_000); // Both positions counted
+    engine.recompute_aggregates();
 
     // At oracle 0.81:
     // mark_pnl = (0.81 - 0.8) * 1 = 10_000
@@ -3573,6 +4029,8 @@ fn params_for_inline_tests() -> RiskParams {
 
         liquidation_buffer_bps: 0,
         min_liquidation_abs: U128::new(0),
+        liquidation_fee_keeper_share_bps: 0,
+        liquidation_fee_lp_share_bps: 0,
     }
 }
 
@@ -3871,13 +4329,16 @@ fn test_warmup_matured_not_lost_on_trade() {
     // Provide warmup budget: the warmup budget system requires losses or
     // spendable insurance to fund positive PnL settlement. Seed insurance
     // so the warmup budget allows settlement.
-    engine.insurance_fund.balance = engine.insurance_fund.balance + 1_000_000;
+    let insurance = engine.insurance_fund.balance.get() + 1_000_000;
+    set_insurance(&mut engine, insurance);
 
-    // Give user positive PnL and set warmup started far in the past
+    // Give user positive PnL (backed by vault residual) and set warmup started far in the past
     engine.accounts[user_idx as usize].pnl = I128::new(10_000);
+    engine.vault += 10_000;
     engine.accounts[user_idx as usize].warmup_started_at_slot = 1;
     // slope = max(1, 10000/100) = 100
     engine.accounts[user_idx as usize].warmup_slope_per_step = U128::new(100);
+    engine.recompute_aggregates();
 
     let cap_before = engine.accounts[user_idx as usize].capital.get();
 
@@ -3977,6 +4438,7 @@ fn test_finding_l_new_position_requires_initial_margin() {
     // LP needs capital to take the other side
     engine.accounts[lp_idx as usize].capital = U128::new(100_000_000_000);
     engine.vault += 100_000_000_000;
+    engine.recompute_aggregates();
 
     // Oracle price: $138 (in e6 = 138_000_000)
     let oracle_price = 138_000_000u64;
@@ -4028,6 +4490,7 @@ fn test_position_flip_margin_check() {
     // LP capital
     engine.accounts[lp_idx as usize].capital = U128::new(100_000_000);
     engine.vault += 100_000_000;
+    engine.recompute_aggregates();
 
     let oracle_price = 100_000_000u64; // $100
 
@@ -4038,7 +4501,7 @@ fn test_position_flip_margin_check() {
 
     // Set user capital to 5.5M (above maintenance 5% = 5M, but below initial 10% = 10M)
     engine.accounts[user_idx as usize].capital = U128::new(5_500_000);
-    engine.c_tot = U128::new(5_500_000);
+    engine.recompute_aggregates();
 
     // Try to flip from +1M to -1M (trade -2M)
     // This crosses zero, so it's risk-increasing and requires initial margin (10% = 10M)
@@ -4058,7 +4521,7 @@ fn test_position_flip_margin_check() {
 
     // Now give user enough capital for initial margin (10% of 100M = 10M, plus buffer)
     engine.accounts[user_idx as usize].capital = U128::new(11_000_000);
-    engine.c_tot = U128::new(11_000_000);
+    engine.recompute_aggregates();
 
     // Now flip should succeed
     let result2 = engine.execute_trade(&MATCHER, lp_idx, user_idx, 0, oracle_price, flip_size);
@@ -4573,3 +5036,5247 @@ fn test_rounding_bound_with_many_positive_pnl_accounts() {
         MAX_ROUNDING_SLACK
     );
 }
+
+// ==============================================================================
+// ORACLE FAILOVER TESTS
+// ==============================================================================
+
+#[allow(clippy::field_reassign_with_default)]
+fn oracle_ext_params() -> ExtParams {
+    let mut ext = ExtParams::default();
+    ext.primary_oracle = [1u8; 32];
+    ext.fallback_oracle = [2u8; 32];
+    ext.oracle_max_staleness_slots = 10;
+    ext.oracle_max_conf_bps = 100; // 1%
+    ext.oracle_max_divergence_bps = 200; // 2%
+    ext
+}
+
+fn quote(feed: u8, price: u64, conf: u64, publish_slot: u64) -> OracleQuote {
+    OracleQuote {
+        feed: [feed; 32],
+        price,
+        conf,
+        publish_slot,
+    }
+}
+
+#[test]
+fn test_oracle_failover_selection() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    engine.set_ext_params(oracle_ext_params()).unwrap();
+
+    // Fresh, tight primary wins even when a fallback is supplied
+    let primary = quote(1, 1_000_000, 1_000, 100);
+    let fallback = quote(2, 1_010_000, 1_000, 100);
+    assert_eq!(
+        engine.select_oracle_price(105, &primary, Some(&fallback)),
+        Ok((1_000_000, OracleFeed::Primary))
+    );
+
+    // Stale primary -> fallback
+    assert_eq!(
+        engine.select_oracle_price(111, &primary, Some(&quote(2, 1_010_000, 1_000, 110))),
+        Ok((1_010_000, OracleFeed::Fallback))
+    );
+
+    // Primary confidence too wide -> fallback
+    let wide = quote(1, 1_000_000, 20_000, 100);
+    assert_eq!(
+        engine.select_oracle_price(100, &wide, Some(&fallback)),
+        Ok((1_010_000, OracleFeed::Fallback))
+    );
+
+    // Fallback diverging more than 2% from primary is rejected
+    let far = quote(2, 1_050_000, 1_000, 100);
+    assert_eq!(
+        engine.select_oracle_price(100, &wide, Some(&far)),
+        Err(RiskError::OracleUnavailable)
+    );
+
+    // A primary from the wrong feed is no divergence reference
+    assert_eq!(
+        engine.select_oracle_price(100, &quote(7, 2_000_000, 1_000, 100), Some(&far)),
+        Ok((1_050_000, OracleFeed::Fallback))
+    );
+
+    // Wrong fallback identity is rejected
+    assert_eq!(
+        engine.select_oracle_price(100, &wide, Some(&quote(3, 1_010_000, 1_000, 100))),
+        Err(RiskError::OracleUnavailable)
+    );
+
+    // Failover disabled: no fallback accepted
+    let mut ext = oracle_ext_params();
+    ext.fallback_oracle = [0u8; 32];
+    engine.set_ext_params(ext).unwrap();
+    assert_eq!(
+        engine.select_oracle_price(100, &wide, Some(&fallback)),
+        Err(RiskError::OracleUnavailable)
+    );
+
+    // Fallback identical to primary is invalid config
+    let mut ext = oracle_ext_params();
+    ext.fallback_oracle = ext.primary_oracle;
+    assert_eq!(engine.set_ext_params(ext), Err(RiskError::InvalidParams));
+}
+
+#[test]
+fn test_keeper_crank_records_oracle_feed() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    engine.set_ext_params(oracle_ext_params()).unwrap();
+    set_insurance(&mut engine, 1_000_000);
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(user, 10_000, 0).unwrap();
+
+    let primary = quote(1, 1_000_000, 1_000, 0);
+    engine
+        .keeper_crank_with_oracles(user, 5, &primary, None, 0, false, 0, 0)
+        .unwrap();
+    assert_eq!(engine.last_oracle_feed, OracleFeed::Primary);
+    assert_eq!(engine.last_oracle_price, 1_000_000);
+
+    let fallback = quote(2, 1_005_000, 1_000, 20);
+    engine
+        .keeper_crank_with_oracles(user, 20, &primary, Some(&fallback), 0, false, 0, 0)
+        .unwrap();
+    assert_eq!(engine.last_oracle_feed, OracleFeed::Fallback);
+    assert_eq!(engine.last_oracle_price, 1_005_000);
+    assert_eq!(engine.last_oracle_publish_slot, 20);
+
+    // Neither feed usable: crank refuses to run and state is untouched
+    let stale_fallback = quote(2, 1_005_000, 1_000, 0);
+    assert_eq!(
+        engine.keeper_crank_with_oracles(user, 40, &primary, Some(&stale_fallback), 0, false, 0, 0),
+        Err(RiskError::OracleUnavailable)
+    );
+    assert_eq!(engine.last_crank_slot, 20);
+    assert_conserved(&engine);
+}
+
+// ==============================================================================
+// PARAMETER GOVERNANCE TESTS
+// ==============================================================================
+
+fn timelocked_engine() -> Box<RiskEngine> {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        param_timelock_slots: 100,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    engine.set_guardian([9u8; 32]).unwrap();
+    engine
+}
+
+#[test]
+fn test_params_update_timelock() {
+    let mut engine = timelocked_engine();
+    let mut new_params = default_params();
+    new_params.trading_fee_bps = 25;
+
+    let eta = engine
+        .queue_params_update(new_params, engine.ext_params, 10)
+        .unwrap();
+    assert_eq!(eta, 110);
+    assert_eq!(engine.apply_params_update(109), Err(RiskError::TimelockActive));
+    assert_eq!(engine.params.trading_fee_bps, 10);
+
+    engine.apply_params_update(110).unwrap();
+    assert_eq!(engine.params.trading_fee_bps, 25);
+    assert_eq!(engine.apply_params_update(111), Err(RiskError::NoPendingUpdate));
+}
+
+#[test]
+fn test_guardian_veto_pending_params() {
+    let mut engine = timelocked_engine();
+    let mut new_params = default_params();
+    new_params.maintenance_margin_bps = 50;
+    engine
+        .queue_params_update(new_params, engine.ext_params, 0)
+        .unwrap();
+
+    // The admin cannot swap out or remove the guardian under a pending update
+    assert_eq!(engine.set_guardian([0u8; 32]), Err(RiskError::TimelockActive));
+    assert_eq!(engine.set_guardian([1u8; 32]), Err(RiskError::TimelockActive));
+
+    // Only the guardian may cancel
+    assert_eq!(
+        engine.cancel_params_update(&[1u8; 32], 50),
+        Err(RiskError::Unauthorized)
+    );
+    engine.cancel_params_update(&[9u8; 32], 50).unwrap();
+    assert_eq!(engine.apply_params_update(200), Err(RiskError::NoPendingUpdate));
+    assert_eq!(engine.params.maintenance_margin_bps, 500);
+
+    // Veto power ends at the eta slot
+    engine
+        .queue_params_update(new_params, engine.ext_params, 300)
+        .unwrap();
+    assert_eq!(
+        engine.cancel_params_update(&[9u8; 32], 400),
+        Err(RiskError::Unauthorized)
+    );
+    engine.apply_params_update(400).unwrap();
+    assert_eq!(engine.params.maintenance_margin_bps, 50);
+    engine.set_guardian([0u8; 32]).unwrap();
+}
+
+#[test]
+fn test_timelock_blocks_direct_ext_params_change() {
+    let mut engine = timelocked_engine();
+    let ext = ExtParams {
+        param_timelock_slots: 0,
+        ..engine.ext_params
+    };
+    assert_eq!(engine.set_ext_params(ext), Err(RiskError::TimelockActive));
+    assert_eq!(engine.ext_params.param_timelock_slots, 100);
+    let threshold = engine.params.risk_reduction_threshold;
+    assert_eq!(
+        engine.set_risk_reduction_threshold(u128::MAX),
+        Err(RiskError::TimelockActive)
+    );
+    assert_eq!(engine.params.risk_reduction_threshold, threshold);
+
+    // The same change goes through the queue, where the guardian could veto it
+    engine.queue_params_update(engine.params, ext, 0).unwrap();
+    engine.apply_params_update(100).unwrap();
+    assert_eq!(engine.ext_params.param_timelock_slots, 0);
+    engine.set_ext_params(ext).unwrap();
+}
+
+#[test]
+fn test_admin_council_gates_params_update() {
+    let mut engine = timelocked_engine();
+    let council = [[1u8; 32], [2u8; 32], [3u8; 32]];
+    assert_eq!(engine.set_admin_council(&[], &council, 4), Err(RiskError::InvalidParams));
+    assert_eq!(
+        engine.set_admin_council(&[], &[[1u8; 32], [1u8; 32]], 1),
+        Err(RiskError::InvalidParams)
+    );
+    engine.set_admin_council(&[], &council, 2).unwrap();
+
+    let mut new_params = default_params();
+    new_params.trading_fee_bps = 25;
+    assert_eq!(engine.approve_params_update(&[1u8; 32], engine.pending_params.nonce), Err(RiskError::NoPendingUpdate));
+    engine
+        .queue_params_update(new_params, engine.ext_params, 0)
+        .unwrap();
+    assert_eq!(engine.approve_params_update(&[9u8; 32], engine.pending_params.nonce), Err(RiskError::Unauthorized));
+    assert_eq!(engine.approve_params_update(&[1u8; 32], engine.pending_params.nonce), Ok(1));
+    // Approving twice does not count twice
+    assert_eq!(engine.approve_params_update(&[1u8; 32], engine.pending_params.nonce), Ok(1));
+    assert_eq!(engine.apply_params_update(100), Err(RiskError::ApprovalsMissing));
+    assert_eq!(engine.approve_params_update(&[3u8; 32], engine.pending_params.nonce), Ok(2));
+    engine.apply_params_update(100).unwrap();
+    assert_eq!(engine.params.trading_fee_bps, 25);
+
+    // Requeueing starts from zero approvals, and approvals of the old update lapse
+    let stale = engine.pending_params.nonce;
+    engine
+        .queue_params_update(default_params(), engine.ext_params, 200)
+        .unwrap();
+    assert_eq!(engine.apply_params_update(300), Err(RiskError::ApprovalsMissing));
+    assert_eq!(engine.approve_params_update(&[1u8; 32], stale), Err(RiskError::StaleSequence));
+
+    // Replacing the council needs the current threshold
+    assert_eq!(
+        engine.set_admin_council(&[[1u8; 32], [9u8; 32]], &[[4u8; 32]], 1),
+        Err(RiskError::ApprovalsMissing)
+    );
+    engine
+        .set_admin_council(&[[1u8; 32], [2u8; 32]], &[[4u8; 32]], 1)
+        .unwrap();
+    assert_eq!(engine.approve_params_update(&[1u8; 32], engine.pending_params.nonce), Err(RiskError::Unauthorized));
+    assert_eq!(engine.approve_params_update(&[4u8; 32], engine.pending_params.nonce), Ok(1));
+    engine.apply_params_update(300).unwrap();
+}
+
+#[test]
+fn test_admin_council_blocks_direct_param_setters() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    engine.set_admin_council(&[], &[[1u8; 32], [2u8; 32]], 2).unwrap();
+
+    let ext = ExtParams {
+        transfer_fee_bps: 5,
+        ..engine.ext_params
+    };
+    assert_eq!(engine.set_ext_params(ext), Err(RiskError::ApprovalsMissing));
+    assert_eq!(engine.set_risk_reduction_threshold(5_000), Err(RiskError::ApprovalsMissing));
+    assert_eq!(engine.ext_params.transfer_fee_bps, 0);
+    assert_eq!(engine.risk_reduction_threshold(), 0);
+
+    // Both go through the council-approved queue instead
+    let mut params = default_params();
+    params.risk_reduction_threshold = U128::new(5_000);
+    engine.queue_params_update(params, ext, 0).unwrap();
+    engine.approve_params_update(&[1u8; 32], engine.pending_params.nonce).unwrap();
+    engine.approve_params_update(&[2u8; 32], engine.pending_params.nonce).unwrap();
+    engine.apply_params_update(0).unwrap();
+    assert_eq!(engine.ext_params.transfer_fee_bps, 5);
+    assert_eq!(engine.risk_reduction_threshold(), 5_000);
+}
+
+#[test]
+fn test_admin_council_insurance_withdrawal() {
+    let mut params = default_params();
+    params.risk_reduction_threshold = U128::new(400_000);
+    let mut engine = Box::new(RiskEngine::new(params));
+    set_insurance(&mut engine, 1_000_000);
+    engine
+        .set_admin_council(&[], &[[1u8; 32], [2u8; 32]], 2)
+        .unwrap();
+
+    assert_eq!(engine.execute_insurance_withdrawal(), Err(RiskError::NoPendingUpdate));
+    assert_eq!(engine.queue_insurance_withdrawal(0), Err(RiskError::InvalidParams));
+    engine.queue_insurance_withdrawal(700_000).unwrap();
+    engine.approve_insurance_withdrawal(&[1u8; 32], engine.pending_insurance_withdrawal.nonce).unwrap();
+    assert_eq!(engine.execute_insurance_withdrawal(), Err(RiskError::ApprovalsMissing));
+    engine.approve_insurance_withdrawal(&[2u8; 32], engine.pending_insurance_withdrawal.nonce).unwrap();
+    // Would take the fund below the risk reduction threshold
+    assert_eq!(engine.execute_insurance_withdrawal(), Err(RiskError::InsufficientBalance));
+
+    // An approval signed for the earlier withdrawal does not count toward this one
+    let stale = engine.pending_insurance_withdrawal.nonce;
+    engine.queue_insurance_withdrawal(600_000).unwrap();
+    assert_eq!(
+        engine.approve_insurance_withdrawal(&[1u8; 32], stale),
+        Err(RiskError::StaleSequence)
+    );
+    engine.approve_insurance_withdrawal(&[1u8; 32], engine.pending_insurance_withdrawal.nonce).unwrap();
+    engine.approve_insurance_withdrawal(&[2u8; 32], engine.pending_insurance_withdrawal.nonce).unwrap();
+    let vault_before = vault_snapshot(&engine);
+    assert_eq!(engine.execute_insurance_withdrawal(), Ok(600_000));
+    assert_eq!(engine.insurance_fund.balance.get(), 400_000);
+    assert_vault_delta(&engine, vault_before, -600_000);
+    assert!(!engine.pending_insurance_withdrawal.active);
+    assert_conserved(&engine);
+}
+
+#[test]
+fn test_insurance_withdrawal_keeps_gap_pool() {
+    let mut params = default_params();
+    params.risk_reduction_threshold = U128::new(400_000);
+    let mut engine = Box::new(RiskEngine::new(params));
+    set_insurance(&mut engine, 1_000_000);
+    engine.gap_insurance.pool = U128::new(100_000);
+    engine
+        .set_admin_council(&[], &[[1u8; 32]], 1)
+        .unwrap();
+
+    // 600k clears the floor alone but would spend the earmarked gap pool
+    engine.queue_insurance_withdrawal(600_000).unwrap();
+    engine.approve_insurance_withdrawal(&[1u8; 32], engine.pending_insurance_withdrawal.nonce).unwrap();
+    assert_eq!(engine.execute_insurance_withdrawal(), Err(RiskError::InsufficientBalance));
+
+    engine.queue_insurance_withdrawal(500_000).unwrap();
+    engine.approve_insurance_withdrawal(&[1u8; 32], engine.pending_insurance_withdrawal.nonce).unwrap();
+    assert_eq!(engine.execute_insurance_withdrawal(), Ok(500_000));
+    assert_eq!(engine.insurance_fund.balance.get(), 500_000);
+    assert_eq!(engine.gap_insurance.pool.get(), 100_000);
+    assert_conserved(&engine);
+}
+
+// ==============================================================================
+// EXT PARAMS VERSIONING TESTS
+// ==============================================================================
+
+#[test]
+fn test_ext_params_encode_decode_compat() {
+    let ext = ExtParams {
+        version: EXT_PARAMS_VERSION,
+        primary_oracle: [1u8; 32],
+        param_timelock_slots: 50,
+        max_pnl_vault_bps: 1_000,
+        max_oi_abs: U128::new(5_000_000),
+        max_funding_rate_bps_per_slot: 3,
+        price_scale: 1_000_000_000,
+        base_decimals: 9,
+        quote_decimals: 6,
+        payoff_mode: PayoffMode::Inverse,
+        series_interval_slots: 10,
+        liq_stats_epoch_slots: 1_000,
+        deviation_window_slots: 100,
+        crank_deviation_alert_bps: 500,
+        exec_deviation_alert_bps: 100,
+        oracle_source: OracleSource::Switchboard,
+        transfer_fee_bps: 50,
+        transfer_fee_max: 1_000,
+        lp_withdrawal_epoch_slots: 200,
+        lp_lockup_slots: 300,
+        lp_early_exit_fee_bps: 75,
+        lp_performance_fee_bps: 1_500,
+        lp_performance_epoch_slots: 400,
+        utilization_fee_kink_bps: 6_000,
+        utilization_fee_full_bps: 9_000,
+        utilization_fee_max_bps: 25,
+        markout_horizon_slots: 30,
+        markout_window_slots: 3_000,
+        markout_toxic_bps: 40,
+        markout_surcharge_bps: 15,
+        holding_fee_per_slot_e9: 500,
+        max_leverage: 20,
+        low_leverage_tier: 3,
+        low_leverage_fee_discount_bps: 2_500,
+        holding_fee_escalation_slots: 900,
+        holding_fee_max_multiplier: 4,
+        min_liquidation_bps: 1_000,
+        min_crank_interval_slots: 5,
+        collateral_rate_max_step_bps: 250,
+        collaterals: [
+            CollateralConfig {
+                oracle: [8; 32],
+                haircut_bps: 500,
+                deposit_cap: U128::new(1_000_000),
+            },
+            CollateralConfig::default(),
+            CollateralConfig::default(),
+            CollateralConfig::default(),
+        ],
+        quanto_collateral_decimals: 9,
+        gc_inactive_slots: 10_000,
+        max_owner_notional: U128::new(50_000_000),
+        max_owner_capital: U128::new(20_000_000),
+        insurance_skim_bps: 2_500,
+        insurance_target_bps: 300,
+        utilization_soft_bps: 7_000,
+        utilization_hard_bps: 9_500,
+        close_price_source: ClosePriceSource::MarkEma,
+        close_price_ema_slots: 150,
+        max_epoch_drain: U128::new(1_000_000),
+        drain_epoch_slots: 432_000,
+        epoch_slots: 216_000,
+        skew_gap_move_bps: 2_000,
+        gap_levy_share_bps: 2_500,
+        oracle_gap_bps: 1_500,
+        gap_revert_slots: 150,
+        gap_revert_tolerance_bps: 50,
+        force_close_grace_slots: 9_000,
+        max_crank_liq_notional: U128::new(5_000_000),
+        exec_price_band_bps: 300,
+        funding_ema_slots: 600,
+        funding_ema_weight_bps: 5_000,
+        liquidation_ema_slots: 30,
+        liquidation_ema_weight_bps: 10_000,
+        max_pnl_ema_slots: 1_200,
+        max_pnl_ema_weight_bps: 2_500,
+        haircut_claim_payout_bps: 1_000,
+        emission_epoch_slots: 9_000,
+        emission_volume_weight_bps: 500,
+        emission_oi_weight_bps: 2,
+        emission_lp_weight_bps: 1,
+        risk_reducing_fee_discount_bps: 10_000,
+        event_margin_window_slots: 50,
+        event_initial_margin_bps: 2_500,
+        event_period_slots: 3_600,
+        adl_first_fee_discount_bps: 1_500,
+        fee_curve: [
+            FeeCurvePoint { notional: U128::new(10_000), fee_bps: 5 },
+            FeeCurvePoint { notional: U128::new(1_000_000), fee_bps: 20 },
+            FeeCurvePoint::default(),
+            FeeCurvePoint::default(),
+        ],
+        conditional_margin_bps: 1_000,
+        large_withdrawal_threshold: U128::new(50_000),
+        lp_min_holding: U128::new(10_000),
+        max_credit_limit: U128::new(500_000),
+        ..ExtParams::default()
+    };
+    let mut buf = [0u8; 2048];
+    let n = ext.encode(&mut buf).unwrap();
+    assert_eq!(n, ExtParams::ENCODED_LEN);
+    assert_eq!(ExtParams::decode(&buf[..n]), Ok(ext));
+
+    // Older encoding: only the v1 body (oracle + timelock fields) -> v2 fields zero
+    let v1_body = 32 + 32 + 8 * 4;
+    let mut old = buf;
+    old[0..2].copy_from_slice(&1u16.to_le_bytes());
+    old[2..4].copy_from_slice(&(v1_body as u16).to_le_bytes());
+    let decoded = ExtParams::decode(&old[..4 + v1_body]).unwrap();
+    assert_eq!(decoded.version, 1);
+    assert_eq!(decoded.param_timelock_slots, 50);
+    assert_eq!(decoded.max_pnl_vault_bps, 0);
+    assert_eq!(decoded.max_oi_abs, U128::ZERO);
+
+    // Newer encoding with an unknown tail: known fields still decode
+    let mut newer = buf;
+    newer[0..2].copy_from_slice(&(EXT_PARAMS_VERSION + 1).to_le_bytes());
+    newer[2..4].copy_from_slice(&((n - 4 + 40) as u16).to_le_bytes());
+    let decoded = ExtParams::decode(&newer[..n + 40]).unwrap();
+    assert_eq!(decoded, ext);
+
+    // Truncated input and unknown payoff modes or oracle sources are rejected
+    assert_eq!(ExtParams::decode(&buf[..n - 1]), Err(RiskError::InvalidParams));
+    // Field offsets come from the codec: the one byte that changes with the field
+    let offset_of = |changed: ExtParams| {
+        let mut other = [0u8; 2048];
+        changed.encode(&mut other).unwrap();
+        (0..n).find(|&i| other[i] != buf[i]).unwrap()
+    };
+    let mut bad_mode = buf;
+    bad_mode[offset_of(ExtParams { payoff_mode: PayoffMode::Linear, ..ext })] = 7;
+    assert_eq!(ExtParams::decode(&bad_mode[..n]), Err(RiskError::InvalidParams));
+    let mut bad_source = buf;
+    bad_source[offset_of(ExtParams { oracle_source: OracleSource::Pyth, ..ext })] = 7;
+    assert_eq!(ExtParams::decode(&bad_source[..n]), Err(RiskError::InvalidParams));
+    assert!(ext.encode(&mut [0u8; 16]).is_err());
+}
+
+#[test]
+fn test_ext_params_fixed_size_and_reserved_space() {
+    assert_eq!(core::mem::size_of::<ExtParams>(), EXT_PARAMS_SIZE);
+
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let mut ext = ExtParams::default();
+    ext.reserved[EXT_PARAMS_RESERVED_CHUNKS - 1][15] = 1;
+    assert_eq!(RiskEngine::validate_ext_params(&ext), Err(RiskError::InvalidParams));
+    assert_eq!(engine.set_ext_params(ext), Err(RiskError::InvalidParams));
+}
+
+#[test]
+fn test_ext_params_crank_defaults_and_funding_clamp() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    set_insurance(&mut engine, 1_000_000);
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(user, 10_000, 0).unwrap();
+    engine.accounts[user as usize].position_size = I128::new(2_000);
+    engine.accounts[user as usize].entry_price = 1_000_000;
+    engine.recompute_aggregates();
+
+    let ext = ExtParams {
+        max_oi_abs: U128::new(1_000),
+        max_funding_rate_bps_per_slot: 5,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    assert_eq!(engine.ext_params.version, EXT_PARAMS_VERSION);
+
+    // Crank OI cap arg of 0 falls back to ExtParams; funding rate is clamped
+    let outcome = engine.keeper_crank(user, 1, 1_000_000, -50, false, 0, 0).unwrap();
+    assert!(outcome.oi_cap_active);
+    assert_eq!(engine.funding_rate_bps_per_slot_last, -5);
+
+    // Versions newer than this build are rejected
+    let mut bad = ext;
+    bad.version = EXT_PARAMS_VERSION + 1;
+    assert_eq!(engine.set_ext_params(bad), Err(RiskError::InvalidParams));
+}
+
+// ==============================================================================
+// SHADOW CRANK TESTS
+// ==============================================================================
+
+#[test]
+fn test_shadow_crank_compares_candidate_params() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    set_insurance(&mut engine, 1_000_000);
+    let lp = engine.add_lp([1u8; 32], [2u8; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 1_000_000, 0).unwrap();
+    // 7% margin on a 1-unit position: safe at 5% MM, liquidatable at 8% MM
+    engine.deposit(user, 70_000, 0).unwrap();
+    engine.accounts[user as usize].position_size = I128::new(1_000_000);
+    engine.accounts[user as usize].entry_price = 1_000_000;
+    engine.accounts[lp as usize].position_size = I128::new(-1_000_000);
+    engine.accounts[lp as usize].entry_price = 1_000_000;
+    engine.total_open_interest = U128::new(2_000_000);
+
+    let mut candidate = default_params();
+    candidate.maintenance_margin_bps = 800;
+
+    let before = engine.clone();
+    let report = engine
+        .shadow_crank(&candidate, &ExtParams::default(), 1_000_000, 0)
+        .unwrap();
+
+    assert_eq!(report.accounts_scanned, 2);
+    assert_eq!(report.current.liquidations, 0);
+    assert_eq!(report.candidate.liquidations, 1);
+    assert_eq!(report.candidate.liquidation_notional, 1_000_000);
+    assert_eq!(report.newly_liquidatable, 1);
+    assert_eq!(report.no_longer_liquidatable, 0);
+    assert!(!report.candidate.force_realize_active);
+
+    // Raising the risk-reduction threshold flips the candidate into force-realize
+    candidate.risk_reduction_threshold = U128::new(2_000_000);
+    let report = engine
+        .shadow_crank(&candidate, &ExtParams::default(), 1_000_000, 0)
+        .unwrap();
+    assert!(report.candidate.force_realize_active);
+    assert_eq!(report.candidate.force_closes, 2);
+    assert_eq!(report.candidate.liquidations, 0);
+
+    // Shadow evaluation never mutates state
+    assert!(*engine == *before);
+}
+
+// ==============================================================================
+// ROUNDING POLICY TESTS
+// ==============================================================================
+
+#[test]
+fn test_mul_div_rounding_directions() {
+    assert_eq!(mul_div(7, 3, 2, Rounding::Down), 10);
+    assert_eq!(mul_div(7, 3, 2, Rounding::Up), 11);
+    // Exact results are unaffected by the direction
+    assert_eq!(mul_div(8, 3, 2, Rounding::Down), 12);
+    assert_eq!(mul_div(8, 3, 2, Rounding::Up), 12);
+    // Division by zero yields zero, overflow saturates
+    assert_eq!(mul_div(5, 5, 0, Rounding::Up), 0);
+    assert_eq!(mul_div(u128::MAX, 2, 1, Rounding::Down), u128::MAX);
+    assert_eq!(mul_div(u128::MAX, 2, 1, Rounding::Up), u128::MAX);
+}
+
+#[test]
+fn test_trading_fee_rounds_up_in_vault_favor() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 10_000_000, 0).unwrap();
+    engine.deposit(user, 10_000_000, 0).unwrap();
+
+    // Notional 0.999999 units: truncation would charge nothing
+    let insurance_before = engine.insurance_fund.balance.get();
+    engine
+        .execute_trade(&NoOpMatcher, lp, user, 0, 999_999, 1)
+        .unwrap();
+    assert_eq!(engine.insurance_fund.balance.get(), insurance_before + 1);
+}
+
+#[test]
+fn test_margin_requirement_rounds_up_in_vault_favor() {
+    let engine = Box::new(RiskEngine::new(default_params()));
+    let mut account = engine.accounts[0];
+    account.capital = U128::new(1_000);
+    account.position_size = I128::new(19_999);
+    account.entry_price = 1_000_000;
+
+    // 5% of 19_999 = 999.95 → requirement rounds up to 1_000, equity 1_000 is not above it
+    assert!(!engine.is_above_margin_bps_mtm(&account, 1_000_000, 500));
+    account.capital = U128::new(1_001);
+    assert!(engine.is_above_margin_bps_mtm(&account, 1_000_000, 500));
+}
+
+#[test]
+fn test_liquidation_fee_shares_round_down() {
+    let mut params = default_params();
+    params.liquidation_fee_keeper_share_bps = 3_333;
+    let mut engine = Box::new(RiskEngine::new(params));
+    let user = engine.add_user(0).unwrap();
+    let keeper = engine.add_user(0).unwrap();
+    engine.deposit(user, 100_000, 0).unwrap();
+
+    engine.accounts[user as usize].position_size = I128::new(10_000_000);
+    engine.accounts[user as usize].entry_price = 1_000_000;
+    engine.total_open_interest = U128::new(10_000_000);
+
+    let record = engine
+        .liquidate_at_oracle_with_keeper(user, Some(keeper), 0, 1_000_000)
+        .unwrap()
+        .unwrap();
+    // Keeper gets floor(fee * 33.33%); the rounding remainder stays with insurance
+    assert_eq!(record.fee_to_keeper, record.fee_total * 3_333 / 10_000);
+    assert_eq!(
+        record.fee_to_keeper + record.fee_to_insurance + record.fee_to_lp,
+        record.fee_total
+    );
+}
+
+// ==============================================================================
+// PRICE / AMOUNT PRECISION TESTS
+// ==============================================================================
+
+#[test]
+fn test_price_divisor_from_ext_params() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    assert_eq!(engine.price_divisor(), 1_000_000);
+
+    // e9 prices, 9-decimal base, 6-decimal quote: divisor = 1e9 * 1e9 / 1e6
+    let ext = ExtParams {
+        price_scale: 1_000_000_000,
+        base_decimals: 9,
+        quote_decimals: 6,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    assert_eq!(engine.price_divisor(), 1_000_000_000_000);
+
+    // 2 SOL (9 decimals) at $150 (e9) = 300 USDC (6 decimals)
+    let notional = engine.notional_at(2_000_000_000, 150_000_000_000, Rounding::Down);
+    assert_eq!(notional, 300_000_000);
+    assert_eq!(
+        engine.base_for_notional(notional, 150_000_000_000, Rounding::Down),
+        2_000_000_000
+    );
+
+    // Non-integer divisor and out-of-range decimals are rejected
+    let bad = ExtParams {
+        base_decimals: 0,
+        quote_decimals: 9,
+        ..ExtParams::default()
+    };
+    assert_eq!(engine.set_ext_params(bad), Err(RiskError::InvalidParams));
+    let bad = ExtParams {
+        base_decimals: 19,
+        ..ExtParams::default()
+    };
+    assert_eq!(engine.set_ext_params(bad), Err(RiskError::InvalidParams));
+}
+
+#[test]
+fn test_rescale_price() {
+    // e8 feed into an e6 market
+    assert_eq!(rescale_price(12_345_678_901, 100_000_000, 1_000_000, Rounding::Down), 123_456_789);
+    assert_eq!(rescale_price(12_345_678_901, 100_000_000, 1_000_000, Rounding::Up), 123_456_790);
+    assert_eq!(rescale_price(1, 1, 1_000_000, Rounding::Down), 1_000_000);
+    assert_eq!(rescale_price(u64::MAX, 1, 1_000, Rounding::Down), u64::MAX);
+    assert_eq!(rescale_price(5, 0, 1_000, Rounding::Down), 0);
+}
+
+#[test]
+fn test_rescale_decimals() {
+    assert_eq!(rescale_decimals(1_500_000, 6, 9, Rounding::Down), Some(1_500_000_000));
+    assert_eq!(rescale_decimals(1_999, 9, 6, Rounding::Down), Some(1));
+    assert_eq!(rescale_decimals(1_999, 9, 6, Rounding::Up), Some(2));
+    assert_eq!(rescale_decimals(42, 6, 6, Rounding::Down), Some(42));
+    assert_eq!(rescale_decimals(u128::MAX, 0, 18, Rounding::Down), None);
+    assert_eq!(rescale_decimals(1, 19, 6, Rounding::Down), None);
+}
+
+#[test]
+fn test_quote_value_normalization() {
+    // SOL (9 decimals) / USDC (6 decimals) at e6 prices
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        base_decimals: 9,
+        quote_decimals: 6,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    assert_eq!(engine.price_scale(), DEFAULT_PRICE_SCALE);
+    assert_eq!(engine.collateral_decimals(), 6);
+    assert_eq!(engine.collateral_units(25, 1), Some(2_500_000));
+    assert_eq!(engine.base_units(2, 0), Some(2_000_000_000));
+
+    // 2 SOL at $150 = 300 USDC = $300 at e6
+    let notional = engine.notional_at(2_000_000_000, 150_000_000, Rounding::Down);
+    assert_eq!(notional, 300_000_000);
+    assert_eq!(engine.quote_value(notional, 150_000_000, Rounding::Down), 300_000_000);
+    assert_eq!(
+        engine.collateral_for_quote_value(300_000_000, 150_000_000, Rounding::Down),
+        notional
+    );
+
+    // Inverse market: SOL collateral valued at the oracle price
+    let ext = ExtParams {
+        payoff_mode: PayoffMode::Inverse,
+        ..ext
+    };
+    engine.set_ext_params(ext).unwrap();
+    assert_eq!(engine.collateral_decimals(), 9);
+    assert_eq!(engine.quote_value(1_000_000_000, 150_000_000, Rounding::Down), 150_000_000);
+    assert_eq!(
+        engine.collateral_for_quote_value(150_000_000, 150_000_000, Rounding::Down),
+        1_000_000_000
+    );
+    assert_eq!(engine.collateral_for_quote_value(1, 0, Rounding::Down), 0);
+}
+
+#[test]
+fn test_trade_math_uses_market_precision() {
+    // Same economic trade in an e6 market and an e9-price / 9-decimal-base market
+    let mut e6 = Box::new(RiskEngine::new(default_params()));
+    let mut e9 = Box::new(RiskEngine::new(default_params()));
+    e9.set_ext_params(ExtParams {
+        price_scale: 1_000_000_000,
+        base_decimals: 9,
+        quote_decimals: 6,
+        ..ExtParams::default()
+    })
+    .unwrap();
+
+    for (engine, size, price) in [
+        (&mut e6, 10_000_000i128, 100_000_000u64),
+        (&mut e9, 10_000_000_000i128, 100_000_000_000u64),
+    ] {
+        let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+        let user = engine.add_user(0).unwrap();
+        engine.deposit(lp, 100_000_000_000, 0).unwrap();
+        engine.deposit(user, 1_000_000_000, 0).unwrap();
+        engine
+            .execute_trade(&NoOpMatcher, lp, user, 0, price, size)
+            .unwrap();
+    }
+
+    // 10 units at $100 = 1_000 USDC notional: identical fee and MTM equity in both markets
+    assert!(e6.insurance_fund.balance.get() > 0);
+    assert_eq!(e6.insurance_fund.balance.get(), e9.insurance_fund.balance.get());
+    let user = 1usize;
+    assert_eq!(
+        e6.account_equity_mtm_at_oracle(&e6.accounts[user], 90_000_000),
+        e9.account_equity_mtm_at_oracle(&e9.accounts[user], 90_000_000_000)
+    );
+}
+
+#[test]
+fn test_market_precision_fixed_while_positions_open() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000_000, 0).unwrap();
+    engine
+        .execute_trade(&NoOpMatcher, lp, user, 0, 100_000_000, 10_000_000)
+        .unwrap();
+
+    for ext in [
+        ExtParams {
+            price_scale: 1_000_000_000,
+            ..engine.ext_params
+        },
+        ExtParams {
+            base_decimals: 9,
+            quote_decimals: 6,
+            ..engine.ext_params
+        },
+    ] {
+        assert_eq!(engine.set_ext_params(ext), Err(RiskError::InvalidParams));
+        engine.queue_params_update(engine.params, ext, 0).unwrap();
+        assert_eq!(engine.apply_params_update(0), Err(RiskError::InvalidParams));
+    }
+    assert_eq!(engine.price_scale(), DEFAULT_PRICE_SCALE);
+
+    // Other fields may still change
+    let ext = ExtParams {
+        transfer_fee_bps: 5,
+        ..engine.ext_params
+    };
+    engine.set_ext_params(ext).unwrap();
+}
+
+#[test]
+fn test_payoff_mode_fixed_while_accounts_exist() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+
+    let inverse = ExtParams {
+        payoff_mode: PayoffMode::Inverse,
+        ..engine.ext_params
+    };
+    assert_eq!(engine.set_ext_params(inverse), Err(RiskError::InvalidParams));
+    engine.queue_params_update(engine.params, inverse, 0).unwrap();
+    assert_eq!(engine.apply_params_update(0), Err(RiskError::InvalidParams));
+    assert_eq!(engine.ext_params.payoff_mode, PayoffMode::Linear);
+
+    // An empty market may switch
+    engine.close_account(user, 0, 1_000_000).unwrap();
+    engine.apply_params_update(0).unwrap();
+    assert_eq!(engine.ext_params.payoff_mode, PayoffMode::Inverse);
+}
+
+// ==============================================================================
+// I128 HELPER TESTS
+// ==============================================================================
+
+#[test]
+fn test_i128_conversions_clamp_instead_of_wrapping() {
+    assert_eq!(I128::from_u128_clamped(u128::MAX), I128::MAX);
+    assert_eq!(I128::from_u128_clamped(42).get(), 42);
+    assert_eq!(I128::checked_from_u128(u128::MAX), None);
+    assert_eq!(I128::checked_from_u128(7), Some(I128::new(7)));
+    assert_eq!(I128::new(-5).clamp_pos_u128(), 0);
+    assert_eq!(I128::new(5).clamp_pos_u128(), 5);
+
+    // Unsigned amounts above i128::MAX saturate rather than flipping sign
+    assert_eq!(I128::ZERO.saturating_sub_u128(u128::MAX), I128::MIN);
+    assert_eq!(I128::new(-1).saturating_add_u128(u128::MAX), I128::MAX);
+    assert_eq!(I128::MIN.saturating_neg(), I128::MAX);
+    assert_eq!(I128::MAX.saturating_mul(2), I128::MAX);
+}
+
+#[test]
+fn test_i128_mul_div_rounding() {
+    // 7 * 3 / 2 = 10.5, -10.5
+    assert_eq!(I128::new(7).checked_mul_div(3, 2, Rounding::Down), Some(I128::new(10)));
+    assert_eq!(I128::new(7).checked_mul_div(3, 2, Rounding::Up), Some(I128::new(11)));
+    assert_eq!(I128::new(-7).checked_mul_div(3, 2, Rounding::Down), Some(I128::new(-11)));
+    assert_eq!(I128::new(-7).checked_mul_div(3, 2, Rounding::Up), Some(I128::new(-10)));
+    assert_eq!(I128::new(7).checked_mul_div(3, -2, Rounding::Down), Some(I128::new(-11)));
+    // Exact results ignore the direction
+    assert_eq!(I128::new(-8).checked_mul_div(3, 2, Rounding::Down), Some(I128::new(-12)));
+
+    assert_eq!(I128::new(1).checked_mul_div(1, 0, Rounding::Up), None);
+    assert_eq!(I128::MAX.checked_mul_div(2, 1, Rounding::Up), None);
+    assert_eq!(I128::MAX.saturating_mul_div(2, 2, Rounding::Down).get(), i128::MAX / 2);
+    assert_eq!(I128::new(1).saturating_mul_div(1, 0, Rounding::Up), I128::ZERO);
+}
+
+#[test]
+fn test_u128_checked_ops_report_overflow() {
+    assert_eq!(U128::new(2).checked_add_u128(U128::new(3)), Some(U128::new(5)));
+    assert_eq!(U128::MAX.checked_add_u128(U128::new(1)), None);
+    assert_eq!(U128::new(5).checked_sub_u128(U128::new(3)), Some(U128::new(2)));
+    assert_eq!(U128::new(3).checked_sub_u128(U128::new(5)), None);
+    assert_eq!(U128::new(7).checked_div(0), None);
+    assert_eq!(U128::new(7) / 2, U128::new(3));
+
+    // Engine paths surface the overflow instead of clamping the vault
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let user = engine.add_user(0).unwrap();
+    engine.vault = U128::MAX;
+    assert_eq!(engine.deposit_fee_credits(user, 1, 0), Err(RiskError::Overflow));
+    assert_eq!(engine.add_user(1), Err(RiskError::Overflow));
+    assert_eq!(engine.vault, U128::MAX);
+    assert_eq!(engine.accounts[user as usize].fee_credits.get(), 0);
+}
+
+#[test]
+#[should_panic]
+fn test_u128_division_by_zero_panics() {
+    let _ = U128::new(7) / U128::ZERO;
+}
+
+// ==============================================================================
+// INVERSE PAYOFF TESTS
+// ==============================================================================
+
+/// SOL-margined SOL/USD: positions in USD (6 decimals), collateral in SOL (9 decimals)
+fn inverse_engine() -> Box<RiskEngine> {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    engine
+        .set_ext_params(ExtParams {
+            base_decimals: 9,
+            quote_decimals: 6,
+            payoff_mode: PayoffMode::Inverse,
+            ..ExtParams::default()
+        })
+        .unwrap();
+    engine
+}
+
+#[test]
+fn test_inverse_notional_and_mark_pnl() {
+    let engine = inverse_engine();
+    // $1_000 of contracts at $100 = 10 SOL, at $125 = 8 SOL
+    assert_eq!(engine.notional_at(1_000_000_000, 100_000_000, Rounding::Down), 10_000_000_000);
+    assert_eq!(engine.notional_at(1_000_000_000, 125_000_000, Rounding::Down), 8_000_000_000);
+    assert_eq!(
+        engine.base_for_notional(8_000_000_000, 125_000_000, Rounding::Down),
+        1_000_000_000
+    );
+
+    // Long $1_000 from $100 to $125 earns 10 - 8 = 2 SOL; the short loses 2 SOL
+    let d = engine.price_divisor();
+    let long =
+        RiskEngine::mark_pnl_for_position_inverse(1_000_000_000, 100_000_000, 125_000_000, d);
+    let short =
+        RiskEngine::mark_pnl_for_position_inverse(-1_000_000_000, 100_000_000, 125_000_000, d);
+    assert_eq!(long, Ok(2_000_000_000));
+    assert_eq!(short, Ok(-2_000_000_000));
+
+    // Inexact results round against the account
+    let up = RiskEngine::mark_pnl_for_position_inverse(1, 3_000_000, 7_000_000, d).unwrap();
+    let down = RiskEngine::mark_pnl_for_position_inverse(-1, 3_000_000, 7_000_000, d).unwrap();
+    assert_eq!(up, 190);
+    assert_eq!(down, -191);
+}
+
+#[test]
+fn test_inverse_trade_fee_margin_and_equity_in_base() {
+    let mut engine = inverse_engine();
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 1_000_000_000_000, 0).unwrap();
+    engine.deposit(user, 2_000_000_000, 0).unwrap();
+
+    // 10 SOL notional needs 1 SOL initial margin; fee is 10 bps of 10 SOL
+    engine
+        .execute_trade(&NoOpMatcher, lp, user, 0, 100_000_000, 1_000_000_000)
+        .unwrap();
+    let capital = engine.accounts[user as usize].capital.get();
+    assert_eq!(capital, 2_000_000_000 - 10_000_000);
+
+    let equity = engine.account_equity_mtm_at_oracle(&engine.accounts[user as usize], 125_000_000);
+    assert_eq!(equity, capital + 2_000_000_000);
+
+    // 30 SOL notional exceeds the remaining margin
+    assert_eq!(
+        engine.execute_trade(&NoOpMatcher, lp, user, 0, 100_000_000, 2_000_000_000),
+        Err(RiskError::Undercollateralized)
+    );
+}
+
+// ==============================================================================
+// QUANTO PAYOFF TESTS
+// ==============================================================================
+
+/// SOL-margined BTC/USD: positions in sats (8 decimals), collateral in SOL (9 decimals)
+fn quanto_engine() -> Box<RiskEngine> {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    engine
+        .set_ext_params(ExtParams {
+            base_decimals: 8,
+            quote_decimals: 6,
+            payoff_mode: PayoffMode::Quanto,
+            quanto_collateral_decimals: 9,
+            ..ExtParams::default()
+        })
+        .unwrap();
+    engine
+}
+
+#[test]
+fn test_quanto_conversion_price_required() {
+    let mut linear = Box::new(RiskEngine::new(default_params()));
+    assert_eq!(linear.set_quanto_price(150_000_000, 0), Err(RiskError::InvalidParams));
+
+    let mut engine = quanto_engine();
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000_000, 0).unwrap();
+    assert_eq!(
+        engine.execute_trade(&NoOpMatcher, lp, user, 0, 60_000_000_000, 1_000_000),
+        Err(RiskError::OracleUnavailable)
+    );
+    assert_eq!(
+        engine.keeper_crank_with_quanto_price(lp, 0, 60_000_000_000, 0, false, 0, 0, 0),
+        Err(RiskError::InvalidParams)
+    );
+    assert_eq!(engine.quanto_price, 0);
+}
+
+#[test]
+fn test_quanto_pnl_and_margin_in_collateral() {
+    let mut engine = quanto_engine();
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000_000, 0).unwrap();
+
+    // SOL at $150: 0.01 BTC at $60_000 = $600 = 4 SOL notional
+    engine
+        .keeper_crank_with_quanto_price(lp, 0, 60_000_000_000, 0, false, 0, 0, 150_000_000)
+        .unwrap();
+    assert_eq!(engine.quanto_price_slot, 0);
+    assert_eq!(engine.collateral_decimals(), 9);
+    let notional = engine.notional_at(1_000_000, 60_000_000_000, Rounding::Down);
+    assert_eq!(notional, 4_000_000_000);
+    assert_eq!(engine.quote_value(notional, 60_000_000_000, Rounding::Down), 600_000_000);
+
+    // Fee is 10 bps of 4 SOL; 40 SOL notional exceeds 1 SOL of margin
+    engine
+        .execute_trade(&NoOpMatcher, lp, user, 0, 60_000_000_000, 1_000_000)
+        .unwrap();
+    let capital = engine.accounts[user as usize].capital.get();
+    assert_eq!(capital, 1_000_000_000 - 4_000_000);
+    assert_eq!(
+        engine.execute_trade(&NoOpMatcher, lp, user, 0, 60_000_000_000, 10_000_000),
+        Err(RiskError::Undercollateralized)
+    );
+
+    // BTC +10% earns $60 = 0.4 SOL, or 0.2 SOL once SOL doubles
+    let account = engine.accounts[user as usize];
+    let equity = engine.account_equity_mtm_at_oracle(&account, 66_000_000_000);
+    assert_eq!(equity, capital + 400_000_000);
+    engine.set_quanto_price(300_000_000, 1).unwrap();
+    let equity = engine.account_equity_mtm_at_oracle(&account, 66_000_000_000);
+    assert_eq!(equity, capital + 200_000_000);
+}
+
+// ==============================================================================
+// TELEMETRY TESTS
+// ==============================================================================
+
+#[derive(Default)]
+struct RecordingTelemetry {
+    counters: Vec<(&'static str, u64)>,
+    gauges: Vec<(&'static str, u128)>,
+    histograms: Vec<(&'static str, u128)>,
+}
+
+impl RecordingTelemetry {
+    fn counter_total(&self, name: &str) -> u64 {
+        self.counters.iter().filter(|(n, _)| *n == name).map(|(_, v)| v).sum()
+    }
+}
+
+impl Telemetry for RecordingTelemetry {
+    fn counter(&mut self, name: &'static str, delta: u64) {
+        self.counters.push((name, delta));
+    }
+    fn gauge(&mut self, name: &'static str, value: u128) {
+        self.gauges.push((name, value));
+    }
+    fn histogram(&mut self, name: &'static str, value: u128) {
+        self.histograms.push((name, value));
+    }
+}
+
+#[test]
+fn test_telemetry_trades_and_rejections() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+
+    let mut t = RecordingTelemetry::default();
+    engine
+        .execute_trade_with_telemetry(&NoOpMatcher, lp, user, 0, 2_000_000, 3_000_000, &mut t)
+        .unwrap();
+    assert_eq!(t.counter_total(METRIC_TRADES), 1);
+    assert!(t.histograms.contains(&(METRIC_TRADE_SIZE, 3_000_000)));
+    assert!(t.histograms.contains(&(METRIC_TRADE_NOTIONAL, 6_000_000)));
+    assert!(t.gauges.contains(&(METRIC_OPEN_INTEREST, 6_000_000)));
+
+    // Far too large for the margin: rejected, counted, no fill metrics
+    let result = engine.execute_trade_with_telemetry(
+        &NoOpMatcher,
+        lp,
+        user,
+        0,
+        2_000_000,
+        1_000_000_000,
+        &mut t,
+    );
+    assert!(result.is_err());
+    assert_eq!(t.counter_total(METRIC_TRADES_REJECTED), 1);
+    assert_eq!(t.counter_total(METRIC_TRADES), 1);
+}
+
+#[test]
+fn test_telemetry_crank_duration_and_cap_hits() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    set_insurance(&mut engine, 1_000_000);
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(user, 10_000, 0).unwrap();
+    engine.accounts[user as usize].position_size = I128::new(2_000);
+    engine.accounts[user as usize].entry_price = 1_000_000;
+    engine.recompute_aggregates();
+
+    let mut t = RecordingTelemetry::default();
+    engine
+        .keeper_crank_with_telemetry(user, 1, 1_000_000, 0, false, 0, 1_000, Some(1_250), &mut t)
+        .unwrap();
+    assert_eq!(t.counter_total(METRIC_CRANKS), 1);
+    assert_eq!(t.counter_total(METRIC_OI_CAP_HITS), 1);
+    assert!(t.histograms.contains(&(METRIC_CRANK_DURATION_US, 1_250)));
+    assert!(t.histograms.contains(&(METRIC_CRANK_SETTLE_ACCOUNTS, 1)));
+    assert!(t.histograms.contains(&(METRIC_CRANK_LIQUIDATE_ACCOUNTS, 1)));
+    assert!(t.gauges.contains(&(METRIC_INSURANCE_BALANCE, 1_000_000)));
+
+    // NoOpTelemetry compiles away
+    engine
+        .keeper_crank_with_telemetry(user, 2, 1_000_000, 0, false, 0, 0, None, &mut NoOpTelemetry)
+        .unwrap();
+}
+
+#[test]
+fn test_telemetry_liquidation() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(user, 100_000, 0).unwrap();
+    engine.accounts[user as usize].position_size = I128::new(10_000_000);
+    engine.accounts[user as usize].entry_price = 1_000_000;
+    engine.total_open_interest = U128::new(10_000_000);
+
+    let mut t = RecordingTelemetry::default();
+    let record = engine
+        .liquidate_with_telemetry(user, None, 0, 1_000_000, &mut t)
+        .unwrap()
+        .unwrap();
+    assert_eq!(t.counter_total(METRIC_LIQUIDATIONS), 1);
+    assert!(t.histograms.contains(&(METRIC_LIQUIDATION_SIZE, record.closed_abs)));
+    assert!(t.histograms.contains(&(METRIC_LIQUIDATION_FEE, record.fee_total)));
+}
+
+// ==============================================================================
+// EVENT STREAM TESTS
+// ==============================================================================
+
+#[derive(Default)]
+struct CollectingObserver {
+    events: Vec<EventRecord>,
+}
+
+impl EventObserver for CollectingObserver {
+    fn on_event(&mut self, event: &EventRecord) {
+        self.events.push(*event);
+    }
+}
+
+#[test]
+fn test_events_emitted_with_sequence_numbers() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+    engine
+        .execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 2_000_000)
+        .unwrap();
+    // Failed operations emit nothing
+    assert!(engine.withdraw(user, u128::MAX, 0, 1_000_000).is_err());
+
+    let mut obs = CollectingObserver::default();
+    let last = engine.drain_events(0, &mut obs);
+    assert_eq!(last, 5);
+    let kinds: Vec<EventKind> = obs.events.iter().map(|e| e.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            EventKind::AccountOpened,
+            EventKind::AccountOpened,
+            EventKind::Deposit,
+            EventKind::Deposit,
+            EventKind::Trade,
+        ]
+    );
+    assert!(obs.events.iter().enumerate().all(|(i, e)| e.seq == i as u64 + 1));
+    let trade = obs.events[4];
+    assert_eq!((trade.account, trade.counterparty), (user, lp));
+    assert_eq!(trade.value.get(), 2_000_000);
+    assert_eq!(trade.amount.get(), 2_000); // 10 bps fee on 2.0 notional
+
+    // Draining again from the returned cursor yields only new events
+    engine.keeper_crank(user, 1, 1_000_000, 0, false, 0, 0).unwrap();
+    let mut obs = CollectingObserver::default();
+    assert_eq!(engine.drain_events(last, &mut obs), 6);
+    assert_eq!(obs.events.len(), 1);
+    assert_eq!(obs.events[0].kind, EventKind::Crank);
+}
+
+#[test]
+fn test_event_ring_wraps_and_reports_gap() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let user = engine.add_user(0).unwrap();
+    for _ in 0..EVENT_LOG_LEN + 10 {
+        engine.deposit(user, 1, 0).unwrap();
+    }
+    let total = engine.event_seq;
+    assert_eq!(total, EVENT_LOG_LEN as u64 + 11);
+    assert!(engine.event(1).is_none());
+    assert_eq!(engine.event(total).unwrap().kind, EventKind::Deposit);
+
+    let mut obs = CollectingObserver::default();
+    assert_eq!(engine.drain_events(0, &mut obs), total);
+    assert_eq!(obs.events.len(), EVENT_LOG_LEN);
+    assert_eq!(obs.events[0].seq, total - EVENT_LOG_LEN as u64 + 1);
+}
+
+#[test]
+fn test_event_binary_roundtrip_and_decoder() {
+    use percolator::events::{decode, EventDecoder, EVENT_ENCODED_LEN};
+
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(user, 777, 0).unwrap();
+
+    let mut buf = [0u8; EVENT_ENCODED_LEN * 2];
+    let a = *engine.event(1).unwrap();
+    let b = *engine.event(2).unwrap();
+    a.encode(&mut buf[..EVENT_ENCODED_LEN]).unwrap();
+    b.encode(&mut buf[EVENT_ENCODED_LEN..]).unwrap();
+
+    assert_eq!(decode(&buf), Ok(a));
+    let decoded: Vec<EventRecord> = EventDecoder::new(&buf).collect();
+    assert_eq!(decoded, vec![a, b]);
+    assert_eq!(decoded[1].amount.get(), 777);
+
+    // Truncated, future-version and unknown-kind records are rejected
+    assert!(decode(&buf[..EVENT_ENCODED_LEN - 1]).is_err());
+    let mut bad = buf;
+    bad[0] = 99;
+    assert!(decode(&bad).is_err());
+    let mut bad = buf;
+    bad[1] = 200;
+    assert!(decode(&bad).is_err());
+}
+
+// ==============================================================================
+// VALUE-AT-RISK TESTS
+// ==============================================================================
+
+#[test]
+fn test_value_at_risk_lp_and_vault() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 1_500_000, 0).unwrap();
+    engine
+        .execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 10_000_000)
+        .unwrap();
+    let user_capital = engine.accounts[user as usize].capital.get();
+
+    // 10% vol at 99%: z = 2.3263 -> 23.27% move; ES multiplier 2.6652 -> 26.66%
+    let report = engine.value_at_risk(1_000_000, 1_000, 9_900).unwrap();
+    assert_eq!(report.var_shock_bps, 2_327);
+    assert_eq!(report.es_shock_bps, 2_666);
+
+    // LP is short 10 units: loses on the move up
+    assert_eq!(report.lp.var, 2_327_000);
+    assert_eq!(report.lp.es, 2_666_000);
+    // User long 10 units loses more than its equity on the move down
+    assert_eq!(report.vault.var, 2_327_000 - user_capital);
+    assert_eq!(report.vault.es, 2_666_000 - user_capital);
+    assert!(report.lp.es >= report.lp.var && report.vault.es >= report.vault.var);
+
+    // Calm market: no bad debt
+    let calm = engine.value_at_risk(1_000_000, 100, 9_900).unwrap();
+    assert_eq!(calm.vault, RiskMeasure::default());
+
+    // A position whose mark cannot be computed is not left out of the tail
+    let broken = engine.add_user(0).unwrap();
+    engine.accounts[broken as usize].position_size = I128::new(i128::MAX);
+    engine.accounts[broken as usize].entry_price = MAX_ORACLE_PRICE;
+    assert_eq!(engine.value_at_risk(1_000_000, 1_000, 9_900), Err(RiskError::Overflow));
+    assert_eq!(
+        engine.risk_contribution(broken, 1_000_000, 2_000).map(|c| c.vault_loss),
+        Err(RiskError::Overflow)
+    );
+}
+
+#[test]
+fn test_value_at_risk_confidence_levels() {
+    let engine = Box::new(RiskEngine::new(default_params()));
+    // Interpolated between the 97.5% and 99% table points
+    let report = engine.value_at_risk(1_000_000, 10_000, 9_800).unwrap();
+    assert_eq!(report.var_shock_bps, 20_821);
+    assert_eq!(report.lp, RiskMeasure::default());
+
+    assert_eq!(
+        engine.value_at_risk(1_000_000, 100, 8_000),
+        Err(RiskError::InvalidParams)
+    );
+    assert_eq!(
+        engine.value_at_risk(1_000_000, 100, 9_999),
+        Err(RiskError::InvalidParams)
+    );
+}
+
+// ==============================================================================
+// MARKET TIME SERIES TESTS
+// ==============================================================================
+
+#[test]
+fn test_market_series_records_per_crank_interval() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 10_000_000, 0).unwrap();
+
+    // Disabled by default
+    engine.keeper_crank(u16::MAX, 1, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.market_series_len(), 0);
+    assert!(engine.market_sample(0).is_none());
+
+    engine
+        .set_ext_params(ExtParams {
+            series_interval_slots: 10,
+            ..ExtParams::default()
+        })
+        .unwrap();
+    engine
+        .execute_trade(&NoOpMatcher, lp, user, 1, 1_000_000, 2_000_000)
+        .unwrap();
+    engine.keeper_crank(u16::MAX, 10, 1_000_000, 3, false, 0, 0).unwrap();
+    // Within the interval: skipped
+    engine.keeper_crank(u16::MAX, 15, 1_010_000, 3, false, 0, 0).unwrap();
+    engine.keeper_crank(u16::MAX, 20, 1_020_000, -2, false, 0, 0).unwrap();
+    assert_eq!(engine.market_series_len(), 2);
+
+    let latest = engine.market_sample(0).unwrap();
+    assert_eq!(latest.slot, 20);
+    assert_eq!(latest.price, 1_020_000);
+    assert_eq!(latest.funding_rate_bps_per_slot, -2);
+    assert_eq!(latest.open_interest.get(), 4_000_000);
+    assert_eq!(latest.net_lp_pos.get(), -2_000_000);
+    let slots: Vec<u64> = engine.market_series().map(|s| s.slot).collect();
+    assert_eq!(slots, vec![10, 20]);
+
+    // Ring keeps only the most recent MARKET_SERIES_LEN samples
+    for i in 0..(MARKET_SERIES_LEN as u64 + 5) {
+        let slot = 30 + i * 10;
+        engine.keeper_crank(u16::MAX, slot, 1_000_000, 0, false, 0, 0).unwrap();
+    }
+    assert_eq!(engine.market_series_len(), MARKET_SERIES_LEN);
+    assert!(engine.market_sample(MARKET_SERIES_LEN).is_none());
+    let oldest = engine.market_series().next().unwrap().slot;
+    // 135 samples recorded: the first 7 (slots 10..=70) were overwritten
+    assert_eq!(oldest, 80);
+    assert_eq!(engine.market_sample(0).unwrap().slot, 30 + (MARKET_SERIES_LEN as u64 + 4) * 10);
+}
+
+// ==============================================================================
+// LP PNL ATTRIBUTION TESTS
+// ==============================================================================
+
+#[test]
+fn test_lp_pnl_attribution_components() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    set_insurance(&mut engine, 1_000_000);
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 10_000_000, 0).unwrap();
+    assert_eq!(engine.lp_pnl_attribution(user), Err(RiskError::NotAnLPAccount));
+    assert_eq!(engine.lp_pnl_attribution(lp).unwrap(), LpPnlAttribution::default());
+
+    // Fill at 1.01 with oracle at 1.00: user buys 10M, LP captures 100k of spread
+    struct Spread;
+    impl MatchingEngine for Spread {
+        fn execute_match(
+            &self,
+            _lp_program: &[u8; 32],
+            _lp_context: &[u8; 32],
+            _lp_account_id: u64,
+            _oracle_price: u64,
+            size: i128,
+        ) -> Result<TradeExecution> {
+            Ok(TradeExecution {
+                price: 1_010_000,
+                size,
+            })
+        }
+    }
+    engine
+        .execute_trade(&Spread, lp, user, 0, 1_000_000, 10_000_000)
+        .unwrap();
+    let attr = engine.lp_pnl_attribution(lp).unwrap();
+    assert_eq!(attr.spread_pnl.get(), 100_000);
+    // 10 bps on 10.1M notional, half to the LP
+    assert_eq!(attr.fees_earned.get(), 5_050);
+    assert_eq!(attr.inventory_pnl.get(), 0);
+
+    // Oracle rises 2%: the short LP inventory loses 200k when settled
+    engine.settle_mark_to_oracle(lp, 1_020_000).unwrap();
+    let attr = engine.lp_pnl_attribution(lp).unwrap();
+    assert_eq!(attr.inventory_pnl.get(), -200_000);
+
+    // Positive funding: longs pay, the short LP receives
+    engine.keeper_crank(u16::MAX, 1, 1_020_000, 5, false, 0, 0).unwrap();
+    engine.keeper_crank(u16::MAX, 11, 1_020_000, 5, false, 0, 0).unwrap();
+    engine.touch_account(lp).unwrap();
+    let attr = engine.lp_pnl_attribution(lp).unwrap();
+    assert!(attr.funding_pnl.get() > 0);
+    assert_eq!(
+        attr.total(),
+        100_000 + 5_050 - 200_000 + attr.funding_pnl.get() + attr.liquidation_proceeds.get() as i128
+    );
+}
+
+// ==============================================================================
+// REVENUE BREAKDOWN TESTS
+// ==============================================================================
+
+#[test]
+fn test_revenue_breakdown_by_source() {
+    let mut params = default_params();
+    params.new_account_fee = U128::new(1_000);
+    params.maintenance_fee_per_slot = U128::new(10);
+    let mut engine = Box::new(RiskEngine::new(params));
+    set_insurance(&mut engine, 1_000_000);
+    let lp = engine.add_lp([0; 32], [0; 32], 1_000).unwrap();
+    let user = engine.add_user(1_000).unwrap();
+    assert_eq!(engine.revenue.new_account_fees.get(), 2_000);
+
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 600_000, 0).unwrap();
+    engine
+        .execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 5_000_000)
+        .unwrap();
+    // 5k fee, half to the LP
+    assert_eq!(engine.revenue.trading_fees.get(), 2_500);
+
+    // 10k due over 1000 slots; the 5k of trading fee credits cover half (coupon, not revenue)
+    engine.settle_maintenance_fee(user, 1_000, 1_000_000).unwrap();
+    assert_eq!(engine.revenue.maintenance_fees.get(), 5_000);
+
+    engine.keeper_crank(u16::MAX, 1_000, 920_000, 0, false, 0, 0).unwrap();
+    assert!(engine.revenue.liquidation_fees.get() > 0);
+
+    assert_eq!(engine.revenue.total(), engine.insurance_fund.fee_revenue.get());
+}
+
+// ==============================================================================
+// LIQUIDATION ANALYTICS TESTS
+// ==============================================================================
+
+#[test]
+fn test_liquidation_analytics_per_epoch() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    set_insurance(&mut engine, 10_000_000);
+    engine
+        .set_ext_params(ExtParams {
+            liq_stats_epoch_slots: 100,
+            ..ExtParams::default()
+        })
+        .unwrap();
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let a = engine.add_user(0).unwrap();
+    let b = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(a, 600_000, 0).unwrap();
+    engine.deposit(b, 250_000, 0).unwrap();
+    engine
+        .execute_trade(&NoOpMatcher, lp, a, 0, 1_000_000, 5_000_000)
+        .unwrap();
+    engine
+        .execute_trade(&NoOpMatcher, lp, b, 0, 1_000_000, 2_000_000)
+        .unwrap();
+
+    // -10%: both users breach maintenance with equity left, no bad debt
+    engine.keeper_crank(u16::MAX, 10, 900_000, 0, false, 0, 0).unwrap();
+    let stats = engine.liquidation_analytics().current;
+    assert_eq!(stats.epoch_start_slot, 0);
+    assert_eq!(stats.liquidations, 2);
+    assert!(stats.volume_notional.get() > 0);
+    assert_eq!(stats.bad_debt.get(), 0);
+    // 50 bps liquidation fee
+    assert_eq!(stats.average_penalty_bps(), 50);
+
+    // Next epoch: counters roll into `previous`
+    let first_epoch = stats;
+    engine.keeper_crank(u16::MAX, 150, 900_000, 0, false, 0, 0).unwrap();
+    let analytics = engine.liquidation_analytics();
+    assert_eq!(analytics.previous, first_epoch);
+    assert_eq!(analytics.current.epoch_start_slot, 100);
+    assert_eq!(analytics.current.liquidations, 0);
+
+    // Idle for several epochs: previous is an empty epoch
+    engine.keeper_crank(u16::MAX, 450, 900_000, 0, false, 0, 0).unwrap();
+    let analytics = engine.liquidation_analytics();
+    assert_eq!(analytics.current.epoch_start_slot, 400);
+    assert_eq!(analytics.previous.epoch_start_slot, 300);
+    assert_eq!(analytics.previous.liquidations, 0);
+}
+
+#[test]
+fn test_liquidation_analytics_bad_debt() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    set_insurance(&mut engine, 10_000_000);
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 600_000, 0).unwrap();
+    engine
+        .execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 5_000_000)
+        .unwrap();
+
+    // -20% gap: 1M loss against 595k capital
+    engine.keeper_crank(u16::MAX, 1, 800_000, 0, false, 0, 0).unwrap();
+    let stats = engine.liquidation_analytics().current;
+    assert_eq!(stats.bad_debt.get(), 405_000);
+    assert!(engine.accounts[user as usize].position_size.is_zero());
+}
+
+// ==============================================================================
+// ORACLE DEVIATION MONITOR TESTS
+// ==============================================================================
+
+#[test]
+fn test_oracle_deviation_monitor_windows_and_alerts() {
+    struct Offset;
+    impl MatchingEngine for Offset {
+        fn execute_match(
+            &self,
+            _lp_program: &[u8; 32],
+            _lp_context: &[u8; 32],
+            _lp_account_id: u64,
+            oracle_price: u64,
+            size: i128,
+        ) -> Result<TradeExecution> {
+            // 2% through the oracle
+            Ok(TradeExecution {
+                price: oracle_price + oracle_price / 50,
+                size,
+            })
+        }
+    }
+
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    set_insurance(&mut engine, 1_000_000);
+    engine
+        .set_ext_params(ExtParams {
+            deviation_window_slots: 100,
+            crank_deviation_alert_bps: 500,
+            exec_deviation_alert_bps: 100,
+            ..ExtParams::default()
+        })
+        .unwrap();
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 10_000_000, 0).unwrap();
+    let seq_before = engine.event_seq;
+
+    // First crank only seeds the reference price
+    engine.keeper_crank(u16::MAX, 1, 1_000_000, 0, false, 0, 0).unwrap();
+    engine.keeper_crank(u16::MAX, 2, 1_010_000, 0, false, 0, 0).unwrap();
+    engine.keeper_crank(u16::MAX, 3, 1_090_000, 0, false, 0, 0).unwrap();
+    engine
+        .execute_trade(&NoOpMatcher, lp, user, 3, 1_090_000, 1_000_000)
+        .unwrap();
+    engine
+        .execute_trade(&Offset, lp, user, 3, 1_090_000, 1_000_000)
+        .unwrap();
+
+    let window = engine.oracle_deviation().current;
+    assert_eq!(window.window_start_slot, 0);
+    assert_eq!(window.crank_samples, 2);
+    // 1.0 -> 1.01 = 100 bps, 1.01 -> 1.09 = 793 bps (rounded up)
+    assert_eq!(window.crank_max_bps, 793);
+    assert_eq!(window.crank_mean_bps(), 446);
+    assert_eq!(window.exec_samples, 2);
+    assert_eq!(window.exec_max_bps, 200);
+    assert_eq!(window.exec_mean_bps(), 100);
+
+    // One alert per breach: the 793 bps crank move and the 200 bps execution
+    let mut observer = CollectingObserver { events: Vec::new() };
+    engine.drain_events(seq_before, &mut observer);
+    let alerts: Vec<_> = observer
+        .events
+        .iter()
+        .filter(|e| e.kind == EventKind::OracleDeviation)
+        .collect();
+    assert_eq!(alerts.len(), 2);
+    assert_eq!((alerts[0].amount.get(), alerts[0].value.get()), (0, 793));
+    assert_eq!(alerts[1].account, user);
+    assert_eq!(alerts[1].counterparty, lp);
+    assert_eq!((alerts[1].amount.get(), alerts[1].value.get()), (1, 200));
+
+    // Next window rolls the statistics
+    engine.keeper_crank(u16::MAX, 120, 1_090_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.oracle_deviation().previous, window);
+    assert_eq!(engine.oracle_deviation().current.crank_samples, 1);
+    assert_eq!(engine.oracle_deviation().current.crank_max_bps, 0);
+}
+
+// ==============================================================================
+// SOLVENCY STATUS TESTS
+// ==============================================================================
+
+#[test]
+fn test_solvency_status() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    set_insurance(&mut engine, 1_000_000);
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 10_000_000, 0).unwrap();
+    engine.deposit(user, 600_000, 0).unwrap();
+    engine
+        .execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 5_000_000)
+        .unwrap();
+
+    let status = engine.solvency_status(1_000_000);
+    assert_eq!(status.vault, 11_600_000);
+    assert_eq!(status.insurance, engine.insurance_fund.balance.get());
+    assert_eq!(status.total_equity, engine.c_tot.get());
+    assert_eq!(status.bad_debt, 0);
+    assert_eq!(status.pnl_owed, 0);
+    assert!(status.is_solvent());
+    assert_eq!(status.surplus(), 0);
+
+    // +10%: the user is owed 500k of mark PnL, paid out of the LP's equity
+    let status = engine.solvency_status(1_100_000);
+    assert_eq!(status.pnl_owed, 500_000);
+    assert_eq!(status.bad_debt, 0);
+    assert!(status.is_solvent());
+
+    // -20%: the user's 1M loss exceeds their 595k capital
+    let status = engine.solvency_status(800_000);
+    assert_eq!(status.bad_debt, 405_000);
+    assert_eq!(status.pnl_owed, 1_000_000);
+    assert_eq!(status.surplus(), -405_000);
+    // Insurance still backs every claim
+    assert!(status.is_solvent());
+    assert_eq!(
+        status.health_bps,
+        (status.vault * 10_000 / status.total_equity) as u64
+    );
+}
+
+// ==============================================================================
+// RISK CONTRIBUTION TESTS
+// ==============================================================================
+
+#[test]
+fn test_risk_contribution_stresses_adverse_side() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    set_insurance(&mut engine, 1_000_000);
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 10_000_000, 0).unwrap();
+    engine.deposit(user, 600_000, 0).unwrap();
+    engine
+        .execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 5_000_000)
+        .unwrap();
+
+    // Long user stressed down 20%: 1M loss against 595k capital
+    let c = engine.risk_contribution(user, 1_000_000, 2_000).unwrap();
+    assert_eq!(c.kind, AccountKind::User);
+    assert_eq!(c.position, 5_000_000);
+    assert_eq!(c.notional, 5_000_000);
+    assert_eq!(c.oi_share_bps, 5_000);
+    assert_eq!(c.stressed_price, 800_000);
+    assert_eq!(c.vault_loss, 405_000);
+
+    // Short LP stressed up 20%: well capitalized, no vault loss
+    let c = engine.risk_contribution(lp, 1_000_000, 2_000).unwrap();
+    assert_eq!(c.stressed_price, 1_200_000);
+    assert_eq!(c.vault_loss, 0);
+
+    assert_eq!(
+        engine.risk_contribution(999, 1_000_000, 2_000),
+        Err(RiskError::AccountNotFound)
+    );
+}
+
+// ==============================================================================
+// PORTFOLIO MARGIN TESTS
+// ==============================================================================
+
+#[test]
+fn test_portfolio_margin_offsets_correlated_hedge() {
+    let exposures = [
+        PortfolioExposure {
+            notional: 1_000_000,
+            margin_bps: 1_000,
+        },
+        PortfolioExposure {
+            notional: -1_000_000,
+            margin_bps: 1_000,
+        },
+    ];
+    // ρ = 0.9: long A / short B loses at most 10% - 9% = 1% of notional
+    let m = portfolio_margin(&exposures, &[10_000, 9_000, 9_000, 10_000], 0).unwrap();
+    assert_eq!(m.isolated, 200_000);
+    assert_eq!(m.worst_scenario_loss, 10_000);
+    assert_eq!(m.requirement, 10_000);
+
+    // Uncorrelated: worst single-market move
+    let m = portfolio_margin(&exposures, &[10_000, 0, 0, 10_000], 0).unwrap();
+    assert_eq!(m.requirement, 100_000);
+
+    // Anti-correlated: the "hedge" doubles the risk, capped at isolated margin
+    let m = portfolio_margin(&exposures, &[10_000, -10_000, -10_000, 10_000], 0).unwrap();
+    assert_eq!(m.worst_scenario_loss, 200_000);
+    assert_eq!(m.requirement, 200_000);
+
+    // Perfect hedge still pays the floor
+    let m = portfolio_margin(&exposures, &[10_000, 10_000, 10_000, 10_000], 50).unwrap();
+    assert_eq!(m.worst_scenario_loss, 0);
+    assert_eq!(m.requirement, 10_000);
+}
+
+#[test]
+fn test_portfolio_margin_rejects_bad_matrix() {
+    let exposures = [PortfolioExposure::default(); 2];
+    // Wrong size, asymmetric, bad diagonal, out of range
+    assert!(portfolio_margin(&exposures, &[10_000], 0).is_err());
+    assert!(portfolio_margin(&exposures, &[10_000, 5_000, 4_000, 10_000], 0).is_err());
+    assert!(portfolio_margin(&exposures, &[9_000, 0, 0, 10_000], 0).is_err());
+    assert!(portfolio_margin(&exposures, &[10_000, 20_000, 20_000, 10_000], 0).is_err());
+}
+
+// ==============================================================================
+// ERROR CODE TESTS
+// ==============================================================================
+
+#[test]
+fn test_error_codes_stable_and_invertible() {
+    // Codes are part of the client ABI: pin a few
+    assert_eq!(RiskError::InsufficientBalance.code(), 6000);
+    assert_eq!(RiskError::Overflow.code(), 6005);
+    assert_eq!(RiskError::InvalidParams.code(), 6010);
+    assert_eq!(RiskError::TimelockActive.code(), 6013);
+
+    for (i, e) in RiskError::ALL.iter().enumerate() {
+        assert_eq!(e.code(), ERROR_CODE_OFFSET + i as u32);
+        assert_eq!(RiskError::from_code(e.code()), Some(*e));
+        assert_eq!(e.name(), format!("{:?}", e));
+        assert!(!e.message().is_empty());
+    }
+    assert_eq!(RiskError::from_code(5999), None);
+    assert_eq!(
+        RiskError::from_code(ERROR_CODE_OFFSET + RiskError::ALL.len() as u32),
+        None
+    );
+}
+
+#[cfg(feature = "idl")]
+#[test]
+fn test_idl_metadata() {
+    let errors = percolator::idl::idl_errors();
+    assert!(errors.starts_with(
+        "[{\"code\":6000,\"name\":\"InsufficientBalance\",\"msg\":\"Insufficient balance for operation\"}"
+    ));
+    assert_eq!(errors.matches("\"code\"").count(), RiskError::ALL.len());
+
+    let meta = percolator::idl::idl_metadata();
+    assert!(meta.contains(
+        "{\"name\":\"PayoffMode\",\"type\":{\"kind\":\"enum\",\"variants\":[{\"name\":\"Linear\"},{\"name\":\"Inverse\"},{\"name\":\"Quanto\"}]}}"
+    ));
+    assert!(meta.contains("{\"name\":\"OracleDeviation\"}"));
+    assert!(meta.contains(
+        "{\"name\":\"ClosePriceSource\",\"type\":{\"kind\":\"enum\",\"variants\":[{\"name\":\"Oracle\"},{\"name\":\"MarkEma\"},{\"name\":\"LastExecution\"}]}}"
+    ));
+}
+
+// ==============================================================================
+// PYTH ADAPTER TESTS
+// ==============================================================================
+
+#[cfg(feature = "pyth")]
+fn pyth_account(price: i64, conf: u64, expo: i32, status: u32, pub_slot: u64) -> Vec<u8> {
+    use percolator::pyth::*;
+    let mut data = vec![0u8; 3312];
+    data[0..4].copy_from_slice(&PYTH_MAGIC.to_le_bytes());
+    data[4..8].copy_from_slice(&PYTH_VERSION.to_le_bytes());
+    data[8..12].copy_from_slice(&PYTH_ACCOUNT_TYPE_PRICE.to_le_bytes());
+    data[20..24].copy_from_slice(&expo.to_le_bytes());
+    data[208..216].copy_from_slice(&price.to_le_bytes());
+    data[216..224].copy_from_slice(&conf.to_le_bytes());
+    data[224..228].copy_from_slice(&status.to_le_bytes());
+    data[232..240].copy_from_slice(&pub_slot.to_le_bytes());
+    data
+}
+
+#[cfg(feature = "pyth")]
+#[test]
+fn test_pyth_parse_and_normalize() {
+    use percolator::pyth::*;
+
+    // $123.45678 at expo -8 → e6, price rounds down, conf rounds up
+    let data = pyth_account(12_345_678_000, 1_500, -8, PYTH_STATUS_TRADING, 100);
+    let parsed = parse_price_account(&data).unwrap();
+    assert_eq!(parsed.expo, -8);
+    let q = parsed.to_quote([7u8; 32], 1_000_000).unwrap();
+    assert_eq!(q.price, 123_456_780);
+    assert_eq!(q.conf, 15);
+    assert_eq!(q.publish_slot, 100);
+    assert_eq!(q.feed, [7u8; 32]);
+
+    // Positive exponent scales up
+    let data = pyth_account(5, 0, 2, PYTH_STATUS_TRADING, 100);
+    let q = parse_price_account(&data).unwrap().to_quote([0; 32], 1_000_000).unwrap();
+    assert_eq!(q.price, 500_000_000);
+
+    // Bad header, short buffer, halted status, non-positive price
+    let mut bad = pyth_account(1, 0, -6, PYTH_STATUS_TRADING, 100);
+    bad[0] ^= 0xff;
+    assert_eq!(parse_price_account(&bad), Err(RiskError::InvalidParams));
+    assert_eq!(parse_price_account(&data[..200]), Err(RiskError::InvalidParams));
+    let halted = pyth_account(1_000_000, 0, -6, 0, 100);
+    assert_eq!(
+        parse_price_account(&halted).unwrap().to_quote([0; 32], 1_000_000),
+        Err(RiskError::OracleUnavailable)
+    );
+    let negative = pyth_account(-1, 0, -6, PYTH_STATUS_TRADING, 100);
+    assert_eq!(
+        parse_price_account(&negative).unwrap().to_quote([0; 32], 1_000_000),
+        Err(RiskError::OracleUnavailable)
+    );
+}
+
+#[cfg(feature = "pyth")]
+#[test]
+fn test_pyth_quote_staleness_and_confidence() {
+    use percolator::pyth::*;
+
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    engine.set_ext_params(oracle_ext_params()).unwrap();
+
+    // $1.00 at expo -8 with 0.5% conf, published at slot 100
+    let data = pyth_account(100_000_000, 500_000, -8, PYTH_STATUS_TRADING, 100);
+    let q = engine.oracle_account_quote(&data, [1u8; 32], 105).unwrap();
+    assert_eq!(q.price, 1_000_000);
+    assert_eq!(q.conf, 5_000);
+    assert_eq!(
+        engine.select_oracle_price(105, &q, None),
+        Ok((1_000_000, OracleFeed::Primary))
+    );
+
+    // Older than 10 slots
+    assert_eq!(
+        engine.oracle_account_quote(&data, [1u8; 32], 111),
+        Err(RiskError::OracleUnavailable)
+    );
+
+    // 2% conf exceeds the 1% limit
+    let wide = pyth_account(100_000_000, 2_000_000, -8, PYTH_STATUS_TRADING, 100);
+    assert_eq!(
+        engine.oracle_account_quote(&wide, [1u8; 32], 105),
+        Err(RiskError::OracleUnavailable)
+    );
+
+    // Checks disabled when limits are zero
+    assert!(load_pyth_quote(&wide, [1u8; 32], 10_000, 0, 0, 1_000_000).is_ok());
+}
+
+// ==============================================================================
+// SWITCHBOARD ADAPTER TESTS
+// ==============================================================================
+
+#[cfg(feature = "switchboard")]
+fn switchboard_account(num_success: u32, open_slot: u64, result: (i128, u32), std_dev: (i128, u32)) -> Vec<u8> {
+    use percolator::switchboard::*;
+    let mut data = vec![0u8; 3851];
+    data[0..8].copy_from_slice(&SWITCHBOARD_AGGREGATOR_DISCRIMINATOR);
+    data[341..345].copy_from_slice(&num_success.to_le_bytes());
+    data[350..358].copy_from_slice(&open_slot.to_le_bytes());
+    data[366..382].copy_from_slice(&result.0.to_le_bytes());
+    data[382..386].copy_from_slice(&result.1.to_le_bytes());
+    data[386..402].copy_from_slice(&std_dev.0.to_le_bytes());
+    data[402..406].copy_from_slice(&std_dev.1.to_le_bytes());
+    data
+}
+
+#[cfg(feature = "switchboard")]
+#[test]
+fn test_switchboard_parse_and_normalize() {
+    use percolator::switchboard::*;
+
+    // $123.4567891 (scale 7) → e6, price rounds down, std dev rounds up
+    let data = switchboard_account(3, 100, (1_234_567_891, 7), (15, 7));
+    let round = parse_aggregator_account(&data).unwrap();
+    assert_eq!(round.num_success, 3);
+    let q = round.to_quote([9u8; 32], 1_000_000).unwrap();
+    assert_eq!(q.price, 123_456_789);
+    assert_eq!(q.conf, 2);
+    assert_eq!(q.publish_slot, 100);
+
+    // Large scales stay exact in u128
+    let data = switchboard_account(1, 100, (2 * 10i128.pow(24), 24), (0, 0));
+    let q = parse_aggregator_account(&data).unwrap().to_quote([0; 32], 1_000_000).unwrap();
+    assert_eq!(q.price, 2_000_000);
+
+    // Bad discriminator, short buffer, empty round, non-positive result, huge scale
+    let mut bad = data.clone();
+    bad[0] ^= 0xff;
+    assert_eq!(parse_aggregator_account(&bad), Err(RiskError::InvalidParams));
+    assert_eq!(parse_aggregator_account(&data[..400]), Err(RiskError::InvalidParams));
+    for (acct, err) in [
+        (switchboard_account(0, 100, (1, 0), (0, 0)), RiskError::OracleUnavailable),
+        (switchboard_account(1, 100, (-1, 0), (0, 0)), RiskError::OracleUnavailable),
+        (switchboard_account(1, 100, (1, 40), (0, 0)), RiskError::InvalidParams),
+    ] {
+        let round = parse_aggregator_account(&acct).unwrap();
+        assert_eq!(round.to_quote([0; 32], 1_000_000), Err(err));
+    }
+}
+
+#[cfg(all(feature = "switchboard", feature = "pyth"))]
+#[test]
+fn test_oracle_source_selects_adapter() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let mut ext = oracle_ext_params();
+    engine.set_ext_params(ext).unwrap();
+
+    let pyth = pyth_account(100_000_000, 500_000, -8, percolator::pyth::PYTH_STATUS_TRADING, 100);
+    let sb = switchboard_account(1, 100, (1_010_000_000, 9), (5_000_000, 9));
+
+    // Default source is Pyth: a Switchboard account does not parse
+    assert_eq!(engine.oracle_account_quote(&pyth, [1u8; 32], 105).unwrap().price, 1_000_000);
+    assert_eq!(
+        engine.oracle_account_quote(&sb, [1u8; 32], 105),
+        Err(RiskError::InvalidParams)
+    );
+
+    // Switching the source needs no code change, and the same limits apply
+    ext.oracle_source = OracleSource::Switchboard;
+    engine.set_ext_params(ext).unwrap();
+    let q = engine.oracle_account_quote(&sb, [1u8; 32], 105).unwrap();
+    assert_eq!((q.price, q.conf), (1_010_000, 5_000));
+    assert_eq!(
+        engine.oracle_account_quote(&sb, [1u8; 32], 111),
+        Err(RiskError::OracleUnavailable)
+    );
+    assert_eq!(
+        engine.oracle_account_quote(&pyth, [1u8; 32], 105),
+        Err(RiskError::InvalidParams)
+    );
+}
+
+// ==============================================================================
+// VAULT RECONCILIATION TESTS
+// ==============================================================================
+
+fn token_account_data(mint: u8, owner: u8, amount: u64, state: u8) -> Vec<u8> {
+    let mut data = vec![0u8; percolator::vault::TOKEN_ACCOUNT_LEN];
+    data[0..32].copy_from_slice(&[mint; 32]);
+    data[32..64].copy_from_slice(&[owner; 32]);
+    data[64..72].copy_from_slice(&amount.to_le_bytes());
+    data[108] = state;
+    data
+}
+
+#[test]
+fn test_vault_token_account_parsing() {
+    use percolator::vault::*;
+
+    let data = token_account_data(1, 2, 5_000, 1);
+    assert_eq!(vault_token_balance(&data, &[1; 32], &[2; 32]), Ok(5_000));
+    assert_eq!(vault_token_balance(&data, &[9; 32], &[2; 32]), Err(RiskError::Unauthorized));
+    assert_eq!(vault_token_balance(&data, &[1; 32], &[9; 32]), Err(RiskError::Unauthorized));
+    assert!(parse_token_account(&token_account_data(1, 2, 0, 2)).unwrap().frozen);
+    assert_eq!(parse_token_account(&token_account_data(1, 2, 0, 0)), Err(RiskError::InvalidParams));
+    assert_eq!(parse_token_account(&data[..100]), Err(RiskError::InvalidParams));
+}
+
+#[test]
+fn test_vault_reconciliation_actions() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(user, 100_000, 0).unwrap();
+    set_insurance(&mut engine, 10_000);
+    let vault = engine.vault.get();
+
+    // In balance: nothing to do, nothing recorded
+    let seq = engine.event_seq;
+    let recon = engine.apply_vault_reconciliation(vault).unwrap();
+    assert_eq!(recon.action, VaultAction::None);
+    assert_eq!(engine.event_seq, seq);
+
+    // Donation is absorbed into insurance
+    let recon = engine.apply_vault_reconciliation(vault + 2_500).unwrap();
+    assert_eq!(recon.discrepancy, 2_500);
+    assert_eq!(recon.action, VaultAction::AbsorbDonation { amount: 2_500 });
+    assert_eq!(engine.vault.get(), vault + 2_500);
+    assert_eq!(engine.insurance_fund.balance.get(), 12_500);
+    let ev = engine.event(engine.event_seq).unwrap();
+    assert_eq!((ev.kind, ev.value.get()), (EventKind::VaultReconciled, 2_500));
+
+    // Shortfall within insurance is written off it
+    let recon = engine.apply_vault_reconciliation(vault - 500).unwrap();
+    assert_eq!(recon.action, VaultAction::WriteDownInsurance { amount: 3_000 });
+    assert_eq!(engine.vault.get(), vault - 500);
+    assert_eq!(engine.insurance_fund.balance.get(), 9_500);
+    assert!(engine.check_conservation(1_000_000));
+
+    // Shortfall beyond insurance halts without touching state
+    let recon = engine.apply_vault_reconciliation(50_000).unwrap();
+    assert_eq!(recon.action, VaultAction::Halt { shortfall: vault - 500 - 50_000 });
+    assert_eq!(recon.discrepancy, -((vault - 500 - 50_000) as i128));
+    assert_eq!(engine.vault.get(), vault - 500);
+    assert_eq!(engine.reconcile_vault(50_000), recon);
+}
+
+// ==============================================================================
+// TRANSFER FEE TESTS
+// ==============================================================================
+
+#[test]
+fn test_transfer_fee_deposit_and_withdraw() {
+    assert_eq!(transfer_fee(10_000, 100, 0), 100);
+    assert_eq!(transfer_fee(10_001, 100, 0), 101); // rounds up
+    assert_eq!(transfer_fee(10_000_000, 100, 5_000), 5_000); // capped
+
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        transfer_fee_bps: 100,
+        transfer_fee_max: 5_000,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let user = engine.add_user(0).unwrap();
+    let vault0 = engine.vault.get();
+
+    // 1% of the transfer never reaches the vault: credit the net amount
+    engine.deposit(user, 100_000, 0).unwrap();
+    assert_eq!(engine.accounts[user as usize].capital.get(), 99_000);
+    assert_eq!(engine.vault.get(), vault0 + 99_000);
+    let token_balance = vault0 + 99_000;
+    assert_eq!(engine.reconcile_vault(token_balance).action, VaultAction::None);
+
+    // Measured balance delta is credited as is
+    engine.deposit_received(user, 1_000, 0).unwrap();
+    assert_eq!(engine.accounts[user as usize].capital.get(), 100_000);
+
+    // Withdrawals debit the gross amount; the recipient gets it net of the fee
+    engine.keeper_crank(user, 1, 1_000_000, 0, false, 0, 0).unwrap();
+    engine.withdraw(user, 50_000, 1, 1_000_000).unwrap();
+    assert_eq!(engine.accounts[user as usize].capital.get(), 50_000);
+    assert_eq!(engine.vault.get(), vault0 + 50_000);
+    assert_eq!(engine.transfer_net_amount(50_000), 49_500);
+    assert!(engine.check_conservation(1_000_000));
+
+    let bad = ExtParams {
+        transfer_fee_bps: 10_001,
+        ..ExtParams::default()
+    };
+    assert_eq!(engine.set_ext_params(bad), Err(RiskError::InvalidParams));
+}
+
+// ==============================================================================
+// LP SHARE TESTS
+// ==============================================================================
+
+#[test]
+fn test_lp_shares_mint_at_nav_and_burn_on_withdraw() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1u8; 32], [2u8; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    assert_eq!(engine.lp_shares(user), Err(RiskError::NotAnLPAccount));
+
+    // First deposit mints one share per unit
+    engine.deposit(lp, 1_000_000, 0).unwrap();
+    let info = engine.lp_shares(lp).unwrap();
+    assert_eq!((info.supply, info.nav, info.price_e6()), (1_000_000, 1_000_000, 1_000_000));
+
+    // 10% accrued (e.g. fees credited to capital): a late deposit gets fewer shares
+    engine.set_capital(lp as usize, 1_100_000);
+    engine.vault = U128::new(engine.vault.get() + 100_000);
+    engine.deposit(lp, 1_100_000, 0).unwrap();
+    let info = engine.lp_shares(lp).unwrap();
+    assert_eq!(info.supply, 2_000_000);
+    assert_eq!(info.price_e6(), 1_100_000);
+    // The original shares kept their accrued value
+    assert_eq!(info.value_of(1_000_000), 1_100_000);
+
+    // Redeeming shares burns them at NAV
+    engine.keeper_crank(user, 1, 1_000_000, 0, false, 0, 0).unwrap();
+    let paid = engine.withdraw_lp_shares(lp, 500_000, 1, 1_000_000).unwrap();
+    assert_eq!(paid, 550_000);
+    let info = engine.lp_shares(lp).unwrap();
+    assert_eq!((info.supply, info.nav), (1_500_000, 1_650_000));
+
+    // A plain withdraw burns rounded up
+    engine.withdraw(lp, 1_000, 1, 1_000_000).unwrap();
+    assert_eq!(engine.lp_shares(lp).unwrap().supply, 1_500_000 - 910);
+    assert_eq!(
+        engine.withdraw_lp_shares(lp, 2_000_000, 1, 1_000_000),
+        Err(RiskError::InsufficientBalance)
+    );
+    assert!(engine.check_conservation(1_000_000));
+}
+
+#[test]
+fn test_lp_shares_bootstrap_legacy_account() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1u8; 32], [2u8; 32], 0).unwrap();
+    engine.deposit(lp, 500_000, 0).unwrap();
+
+    // Pre-share accounts have no supply: bootstrapped at one share per unit of NAV
+    engine.accounts[lp as usize].lp_shares = U128::ZERO;
+    assert_eq!(engine.lp_shares(lp).unwrap().supply, 500_000);
+    engine.deposit(lp, 500_000, 0).unwrap();
+    assert_eq!(engine.accounts[lp as usize].lp_shares.get(), 1_000_000);
+}
+
+#[test]
+fn test_lp_pool_depositors_hold_their_own_shares() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1u8; 32], [2u8; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    let (owner, alice, bob) = ([7u8; 32], [8u8; 32], [9u8; 32]);
+    engine.set_owner(lp, owner).unwrap();
+    engine.deposit(lp, 1_000_000, 0).unwrap();
+
+    // Alice joins at 1.0, then 10% accrues to the pool
+    assert_eq!(engine.deposit_lp_shares(lp, alice, 1_000_000, 0), Ok(1_000_000));
+    engine.set_capital(lp as usize, 2_200_000);
+    engine.vault = U128::new(engine.vault.get() + 200_000);
+
+    // Bob buys in at the accrued NAV and does not dilute the earlier holders
+    assert_eq!(engine.deposit_lp_shares(lp, bob, 1_100_000, 0), Ok(1_000_000));
+    assert_eq!(engine.lp_shares(lp).unwrap().supply, 3_000_000);
+    for holder in [owner, alice, bob] {
+        assert_eq!(engine.lp_holder_shares(lp, holder), Ok(1_000_000));
+    }
+
+    // Each depositor redeems only their own shares
+    engine.keeper_crank(user, 1, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(
+        engine.redeem_lp_shares(lp, alice, 1_000_001, 1, 1_000_000),
+        Err(RiskError::InsufficientBalance)
+    );
+    assert_eq!(engine.redeem_lp_shares(lp, alice, 1_000_000, 1, 1_000_000), Ok(1_100_000));
+    assert_eq!(engine.lp_holder_shares(lp, alice), Ok(0));
+    assert_eq!(engine.lp_holder_shares(lp, bob), Ok(1_000_000));
+
+    // The owner cannot take the depositors' capital
+    assert_eq!(
+        engine.withdraw_lp_shares(lp, 1_000_001, 1, 1_000_000),
+        Err(RiskError::InsufficientBalance)
+    );
+    assert_eq!(engine.withdraw(lp, 1_200_000, 1, 1_000_000), Err(RiskError::InsufficientBalance));
+    assert_eq!(engine.withdraw_lp_shares(lp, 1_000_000, 1, 1_000_000), Ok(1_100_000));
+    assert_eq!(engine.close_account(lp, 1, 1_000_000), Err(RiskError::Unauthorized));
+    let info = engine.lp_shares(lp).unwrap();
+    assert_eq!((info.supply, info.nav), (1_000_000, 1_100_000));
+    assert_eq!(engine.lp_holder_shares(lp, owner), Ok(0));
+    assert!(engine.check_conservation(1_000_000));
+}
+
+#[test]
+fn test_lp_pool_holdings_resist_dust_depositors() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        lp_min_holding: U128::new(10_000),
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let lp = engine.add_lp([1u8; 32], [2u8; 32], 0).unwrap();
+    let other = engine.add_lp([3u8; 32], [4u8; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 1_000_000, 0).unwrap();
+    engine.deposit(other, 1_000_000, 0).unwrap();
+
+    // Dust below the minimum cannot take a record
+    assert_eq!(engine.deposit_lp_shares(lp, [100u8; 32], 1, 0), Err(RiskError::InvalidParams));
+    assert_eq!(engine.lp_holder_shares(lp, [100u8; 32]), Ok(0));
+
+    // One LP's depositors fill only that LP's allowance
+    for i in 0..MAX_LP_HOLDINGS_PER_LP {
+        engine.deposit_lp_shares(lp, [100 + i as u8; 32], 10_000, 0).unwrap();
+    }
+    assert_eq!(engine.deposit_lp_shares(lp, [99u8; 32], 10_000, 0), Err(RiskError::Overflow));
+    engine.deposit_lp_shares(lp, [100u8; 32], 1, 0).unwrap();
+    assert_eq!(engine.deposit_lp_shares(other, [99u8; 32], 10_000, 0), Ok(10_000));
+
+    // A partial redemption may not leave dust behind; a full one frees the record
+    engine.keeper_crank(user, 1, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(
+        engine.redeem_lp_shares(lp, [101u8; 32], 5_000, 1, 1_000_000),
+        Err(RiskError::InvalidParams)
+    );
+    assert_eq!(engine.redeem_lp_shares(lp, [101u8; 32], 10_000, 1, 1_000_000), Ok(10_000));
+    assert_eq!(engine.deposit_lp_shares(lp, [99u8; 32], 10_000, 0), Ok(10_000));
+    assert!(engine.check_conservation(1_000_000));
+}
+
+// ==============================================================================
+// LP WITHDRAWAL QUEUE TESTS
+// ==============================================================================
+
+#[test]
+fn test_lp_withdrawal_queue_releases_depositor_redemptions() {
+    let mut params = default_params();
+    params.max_accounts = MAX_ACCOUNTS as u64;
+    let mut engine = Box::new(RiskEngine::new(params));
+    let ext = ExtParams {
+        lp_withdrawal_epoch_slots: 100,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let lp = engine.add_lp([1u8; 32], [2u8; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    let (owner, alice) = ([7u8; 32], [8u8; 32]);
+    engine.set_owner(lp, owner).unwrap();
+    engine.deposit(lp, 1_000_000, 0).unwrap();
+    engine.deposit_lp_shares(lp, alice, 3_000_000, 0).unwrap();
+    engine.keeper_crank(user, 1, 1_000_000, 0, false, 0, 0).unwrap();
+
+    // Depositors leave through the queue like the owner
+    assert_eq!(
+        engine.redeem_lp_shares(lp, alice, 1_000_000, 1, 1_000_000),
+        Err(RiskError::WithdrawalQueued)
+    );
+    assert_eq!(
+        engine.request_lp_redemption(lp, alice, 3_000_001),
+        Err(RiskError::InsufficientBalance)
+    );
+    engine.request_lp_redemption(lp, alice, 3_000_000).unwrap();
+    assert_eq!(
+        engine.request_lp_withdrawal(lp, 1_000_001),
+        Err(RiskError::InsufficientBalance)
+    );
+    engine.request_lp_redemption(lp, owner, 500_000).unwrap();
+
+    let vault = engine.vault.get();
+    engine.keeper_crank(user, 100, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.lp_holder_shares(lp, alice), Ok(0));
+    assert_eq!(engine.lp_holder_shares(lp, owner), Ok(500_000));
+    assert_eq!(engine.lp_claimable_total.get(), 3_500_000);
+    assert_eq!(engine.health_check(), 0);
+    assert!(engine.check_conservation(1_000_000));
+
+    // Released capital keeps the record alive until claimed
+    assert_eq!(engine.close_account(lp, 100, 1_000_000), Err(RiskError::WithdrawalQueued));
+    assert_eq!(engine.claim_lp_redemption(lp, alice), Ok(3_000_000));
+    assert_eq!(engine.claim_lp_redemption(lp, alice), Ok(0));
+    assert_eq!(engine.claim_lp_redemption(lp, owner), Ok(500_000));
+    assert_eq!(engine.vault.get(), vault - 3_500_000);
+    assert_eq!(engine.health_check(), 0);
+    assert!(engine.check_conservation(1_000_000));
+}
+
+#[test]
+fn test_lp_withdrawal_queue_releases_free_capital_at_epoch() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        lp_withdrawal_epoch_slots: 100,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let lp = engine.add_lp([1u8; 32], [2u8; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 1_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+    engine.keeper_crank(user, 1, 1_000_000, 0, false, 0, 0).unwrap();
+    engine
+        .execute_trade(&MATCHER, lp, user, 1, 1_000_000, 2_000_000)
+        .unwrap();
+
+    // Direct withdrawals are closed to LPs; the queue takes share requests
+    assert_eq!(engine.withdraw(lp, 1_000, 1, 1_000_000), Err(RiskError::WithdrawalQueued));
+    let supply = engine.lp_shares(lp).unwrap().supply;
+    assert_eq!(
+        engine.request_lp_withdrawal(lp, supply + 1),
+        Err(RiskError::InsufficientBalance)
+    );
+    let seq = engine.state_seq;
+    engine.request_lp_withdrawal(lp, supply).unwrap();
+    assert_eq!(engine.state_seq, seq + 1);
+
+    // Nothing moves within the epoch
+    engine.keeper_crank(user, 50, 1_000_000, 0, false, 0, 0).unwrap();
+    assert!(engine.accounts[lp as usize].lp_claimable.is_zero());
+
+    // Epoch boundary: release all but the initial margin on the LP's 2M short
+    let equity = engine.account_equity_mtm_at_oracle(&engine.accounts[lp as usize], 1_000_000);
+    let vault = engine.vault.get();
+    engine.keeper_crank(user, 100, 1_000_000, 0, false, 0, 0).unwrap();
+    let released = engine.accounts[lp as usize].lp_claimable.get();
+    assert_eq!(released, equity - 200_000);
+    assert_eq!(engine.lp_claimable_total.get(), released);
+    assert_eq!(engine.vault.get(), vault);
+    assert!(!engine.accounts[lp as usize].lp_queued_shares.is_zero());
+    assert_eq!(
+        engine.account_equity_mtm_at_oracle(&engine.accounts[lp as usize], 1_000_000),
+        200_000
+    );
+    assert!(engine.check_conservation(1_000_000));
+    // Released capital is owed to the LP, so it no longer backs positive PnL
+    let backing = engine.c_tot.get() + engine.insurance_fund.balance.get() + released;
+    assert_eq!(engine.pnl_residual(), vault.saturating_sub(backing));
+    assert_eq!(engine.solvency_status(1_000_000).vault, vault - released);
+
+    // Claiming pays out of the vault
+    assert_eq!(engine.claim_lp_withdrawal(lp), Ok(released));
+    assert_eq!(engine.vault.get(), vault - released);
+    assert!(engine.lp_claimable_total.is_zero());
+    assert_eq!(engine.claim_lp_withdrawal(lp), Ok(0));
+    assert!(engine.check_conservation(1_000_000));
+
+    engine.cancel_lp_withdrawal(lp).unwrap();
+    assert!(engine.accounts[lp as usize].lp_queued_shares.is_zero());
+}
+
+// ==============================================================================
+// LP LOCKUP TESTS
+// ==============================================================================
+
+#[test]
+fn test_lp_lockup_early_exit_fee_goes_to_other_lps() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        lp_lockup_slots: 100,
+        lp_early_exit_fee_bps: 200,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let lp_a = engine.add_lp([1u8; 32], [2u8; 32], 0).unwrap();
+    let lp_b = engine.add_lp([1u8; 32], [2u8; 32], 0).unwrap();
+    engine.deposit(lp_a, 1_000_000, 0).unwrap();
+    engine.deposit(lp_b, 1_000_000, 0).unwrap();
+    assert_eq!(engine.accounts[lp_a as usize].lp_locked_until_slot, 100);
+    engine.keeper_crank(lp_a, 1, 1_000_000, 0, false, 0, 0).unwrap();
+    let vault = engine.vault.get();
+
+    // Early: 2% on top of the amount, credited to the remaining LP
+    engine.withdraw(lp_a, 100_000, 10, 1_000_000).unwrap();
+    assert_eq!(engine.accounts[lp_a as usize].capital.get(), 898_000);
+    assert_eq!(engine.accounts[lp_b as usize].capital.get(), 1_002_000);
+    assert_eq!(engine.lp_pnl_attribution(lp_b).unwrap().exit_fees_received.get(), 2_000);
+    assert_eq!(engine.vault.get(), vault - 100_000);
+    assert_eq!(engine.lp_shares(lp_a).unwrap().supply, 898_000);
+    assert!(engine.check_conservation(1_000_000));
+
+    // Unlocked: no fee
+    engine.withdraw(lp_a, 98_000, 100, 1_000_000).unwrap();
+    assert_eq!(engine.accounts[lp_a as usize].capital.get(), 800_000);
+
+    // Without an early-exit fee the lockup is hard; a new deposit restarts it
+    let hard = ExtParams {
+        lp_lockup_slots: 100,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(hard).unwrap();
+    engine.deposit(lp_a, 1_000, 150).unwrap();
+    assert_eq!(engine.withdraw(lp_a, 1_000, 200, 1_000_000), Err(RiskError::LpLocked));
+    engine.withdraw(lp_a, 1_000, 250, 1_000_000).unwrap();
+}
+
+#[test]
+fn test_lp_pool_redemption_pays_early_exit_fee_once() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        lp_lockup_slots: 100,
+        lp_early_exit_fee_bps: 1_000,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let lp = engine.add_lp([1u8; 32], [2u8; 32], 0).unwrap();
+    let other = engine.add_lp([3u8; 32], [4u8; 32], 0).unwrap();
+    let (owner, alice) = ([7u8; 32], [8u8; 32]);
+    engine.set_owner(lp, owner).unwrap();
+    engine.deposit(lp, 1_000_000, 0).unwrap();
+    engine.deposit(other, 1_000_000, 0).unwrap();
+    engine.deposit_lp_shares(lp, alice, 1_100_000, 0).unwrap();
+    engine.keeper_crank(other, 1, 1_000_000, 0, false, 0, 0).unwrap();
+
+    // 1.1M of value: 1M withdrawn and the whole 100k fee to the other LP
+    assert_eq!(engine.redeem_lp_shares(lp, alice, 1_100_000, 10, 1_000_000), Ok(1_000_000));
+    assert_eq!(engine.accounts[other as usize].capital.get(), 1_100_000);
+    assert_eq!(engine.lp_pnl_attribution(other).unwrap().exit_fees_received.get(), 100_000);
+
+    // Exactly the holder's shares are burned; the owner keeps its balance
+    assert_eq!(engine.lp_holder_shares(lp, alice), Ok(0));
+    assert_eq!(engine.lp_holder_shares(lp, owner), Ok(1_000_000));
+    let info = engine.lp_shares(lp).unwrap();
+    assert_eq!((info.supply, info.nav), (1_000_000, 1_000_000));
+    assert!(engine.check_conservation(1_000_000));
+}
+
+// ==============================================================================
+// LP PERFORMANCE FEE TESTS
+// ==============================================================================
+
+#[test]
+fn test_lp_performance_fee_above_high_water_mark() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        lp_performance_fee_bps: 2_000,
+        lp_performance_epoch_slots: 100,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let lp = engine.add_lp([1u8; 32], [2u8; 32], 0).unwrap();
+    engine.deposit(lp, 1_000_000, 0).unwrap();
+
+    // First assessment only sets the mark
+    engine.keeper_crank(lp, 100, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.accounts[lp as usize].lp_high_water_mark.get(), 1_000_000);
+    assert_eq!(engine.accounts[lp as usize].capital.get(), 1_000_000);
+
+    // LP gains 10%: 20% of the gain is skimmed to insurance
+    engine.accounts[lp as usize].capital = U128::new(1_100_000);
+    engine.c_tot = engine.c_tot.saturating_add(100_000);
+    engine.vault = engine.vault.saturating_add(100_000);
+    let insurance = engine.insurance_fund.balance.get();
+    engine.keeper_crank(lp, 150, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.accounts[lp as usize].capital.get(), 1_100_000, "same epoch");
+    engine.keeper_crank(lp, 200, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.accounts[lp as usize].capital.get(), 1_080_000);
+    assert_eq!(engine.insurance_fund.balance.get(), insurance + 20_000);
+    assert_eq!(engine.revenue.lp_performance_fees.get(), 20_000);
+    assert_eq!(engine.accounts[lp as usize].lp_high_water_mark.get(), 1_080_000);
+    assert!(engine.check_conservation(1_000_000));
+
+    // No new high: no fee
+    engine.keeper_crank(lp, 300, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.accounts[lp as usize].capital.get(), 1_080_000);
+    assert_eq!(engine.revenue.lp_performance_fees.get(), 20_000);
+}
+
+// ==============================================================================
+// UTILIZATION FEE TESTS
+// ==============================================================================
+
+#[test]
+fn test_utilization_fee_surcharges_risk_increasing_trades() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        utilization_fee_kink_bps: 2_500,
+        utilization_fee_full_bps: 7_500,
+        utilization_fee_max_bps: 40,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 10_000_000, 0).unwrap();
+    engine.deposit(user, 10_000_000, 0).unwrap();
+
+    // Idle vault: base fee
+    let capital = |e: &RiskEngine| e.accounts[user as usize].capital.get();
+    let before = capital(&engine);
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 5_000_000).unwrap();
+    assert_eq!(before - capital(&engine), 5_000);
+    assert_eq!(engine.utilization_bps(1_000_000), 5_000);
+
+    // Halfway up the curve: 10 + 20 bps
+    assert_eq!(engine.effective_trading_fee_bps(1_000_000, true), 30);
+    let before = capital(&engine);
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 1_000_000).unwrap();
+    assert_eq!(before - capital(&engine), 3_000);
+
+    // Risk-reducing trades keep the base fee
+    let before = capital(&engine);
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, -1_000_000).unwrap();
+    assert_eq!(before - capital(&engine), 1_000);
+
+    // Capped past full utilization
+    engine.total_open_interest = U128::new(100_000_000);
+    assert_eq!(engine.effective_trading_fee_bps(1_000_000, true), 50);
+
+    let bad = ExtParams {
+        utilization_fee_kink_bps: 2_500,
+        utilization_fee_full_bps: 2_500,
+        ..ExtParams::default()
+    };
+    assert_eq!(engine.set_ext_params(bad), Err(RiskError::InvalidParams));
+}
+
+#[test]
+fn test_utilization_fee_spares_reducing_taker_against_growing_lp() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        utilization_fee_kink_bps: 2_500,
+        utilization_fee_full_bps: 7_500,
+        utilization_fee_max_bps: 40,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let other_lp = engine.add_lp([1; 32], [1; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 10_000_000, 0).unwrap();
+    engine.deposit(other_lp, 1_000_000, 0).unwrap();
+    engine.deposit(user, 10_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 5_000_000).unwrap();
+    assert!(engine.effective_trading_fee_bps(1_000_000, true) > 10);
+
+    // The user sells down its long into a flat LP, which goes long: base fee only
+    let before = engine.accounts[user as usize].capital.get();
+    engine.execute_trade(&NoOpMatcher, other_lp, user, 0, 1_000_000, -1_000_000).unwrap();
+    assert_eq!(engine.accounts[other_lp as usize].position_size.get(), 1_000_000);
+    assert_eq!(before - engine.accounts[user as usize].capital.get(), 1_000);
+}
+
+// ==============================================================================
+// MARKOUT SURCHARGE TESTS
+// ==============================================================================
+
+#[test]
+fn test_markout_surcharge_for_toxic_taker() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        markout_horizon_slots: 10,
+        markout_window_slots: 1_000,
+        markout_toxic_bps: 50,
+        markout_surcharge_bps: 20,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 10_000_000, 0).unwrap();
+    engine.deposit(user, 10_000_000, 0).unwrap();
+
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 1_000_000).unwrap();
+    let stats = engine.accounts[user as usize].markout;
+    assert_eq!(stats.pending_size.get(), 1_000_000);
+    assert_eq!(engine.markout_surcharge_bps(user), Ok(0));
+
+    // Before the horizon nothing is measured
+    engine.keeper_crank(lp, 5, 1_100_000, 0, false, 0, 0).unwrap();
+    assert!(engine.accounts[user as usize].markout.notional_sum.is_zero());
+
+    // Price ran 10% in the taker's favor: 1_000 bps markout
+    engine.keeper_crank(lp, 10, 1_100_000, 0, false, 0, 0).unwrap();
+    let stats = engine.accounts[user as usize].markout;
+    assert!(stats.pending_size.is_zero());
+    assert_eq!(stats.markout_sum.get(), 100_000);
+    assert_eq!(stats.score_bps(), 1_000);
+    assert_eq!(engine.markout_surcharge_bps(user), Ok(20));
+
+    // 10 + 20 bps on 550_000 notional; insurance keeps half
+    let insurance = engine.insurance_fund.balance.get();
+    engine.execute_trade(&NoOpMatcher, lp, user, 10, 1_100_000, -500_000).unwrap();
+    assert_eq!(engine.insurance_fund.balance.get(), insurance + 825);
+
+    // The LP is never surcharged as maker
+    assert!(engine.accounts[lp as usize].markout.notional_sum.is_zero());
+}
+
+// ==============================================================================
+// HOLDING FEE TESTS
+// ==============================================================================
+
+#[test]
+fn test_holding_fee_accrues_lazily_on_user_notional() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        holding_fee_per_slot_e9: 1_000,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    let flat = engine.add_user(0).unwrap();
+    engine.deposit(lp, 10_000_000, 0).unwrap();
+    engine.deposit(user, 10_000_000, 0).unwrap();
+    engine.deposit(flat, 1_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, -1_000_000).unwrap();
+    let user_capital = engine.accounts[user as usize].capital.get();
+    let user_credits = engine.accounts[user as usize].fee_credits.get();
+    let lp_capital = engine.accounts[lp as usize].capital.get();
+    assert_eq!(user_credits, 1_000);
+
+    // 1e-6 of 1_000_000 notional per slot, for 100 slots: drawn from fee credits first
+    engine.keeper_crank(lp, 100, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.holding_fee_index.get(), 100_000_000_000);
+    assert_eq!(engine.accounts[user as usize].fee_credits.get(), 900);
+    assert_eq!(engine.accounts[user as usize].capital.get(), user_capital);
+
+    // Another 1_000 slots: credits exhausted, the rest comes from capital
+    engine.keeper_crank(lp, 1_100, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.accounts[user as usize].fee_credits.get(), 0);
+    assert_eq!(engine.accounts[user as usize].capital.get(), user_capital - 100);
+    assert_eq!(engine.revenue.holding_fees.get(), 100);
+    assert_eq!(engine.accounts[lp as usize].capital.get(), lp_capital);
+    assert_eq!(engine.accounts[flat as usize].capital.get(), 1_000);
+    assert!(engine.check_conservation(1_000_000));
+
+    // Settled: touching again charges nothing more
+    engine.touch_account(user).unwrap();
+    assert_eq!(engine.revenue.holding_fees.get(), 100);
+}
+
+// ==============================================================================
+// LEVERAGE CAP TESTS
+// ==============================================================================
+
+#[test]
+fn test_account_leverage_cap_and_fee_tier() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        low_leverage_tier: 5,
+        low_leverage_fee_discount_bps: 5_000,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+
+    // 5x: 20% initial margin instead of the default 10%
+    engine.set_max_leverage(user, 5).unwrap();
+    assert_eq!(engine.initial_margin_bps_for(&engine.accounts[user as usize]), 2_000);
+    assert_eq!(
+        engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 6_000_000),
+        Err(RiskError::Undercollateralized)
+    );
+
+    // Half the 10 bps fee on 4_000_000 notional
+    let capital = engine.accounts[user as usize].capital.get();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 4_000_000).unwrap();
+    assert_eq!(capital - engine.accounts[user as usize].capital.get(), 2_000);
+
+    // Above the tier: full fee
+    engine.set_max_leverage(user, 8).unwrap();
+    let capital = engine.accounts[user as usize].capital.get();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 1_000_000).unwrap();
+    assert_eq!(capital - engine.accounts[user as usize].capital.get(), 1_000);
+}
+
+#[test]
+fn test_global_leverage_cap_overrides_margin_params() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        max_leverage: 2,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+
+    // An account can opt for a looser cap, but not past the global one
+    engine.set_max_leverage(user, 10).unwrap();
+    assert_eq!(engine.initial_margin_bps_for(&engine.accounts[user as usize]), 5_000);
+    assert_eq!(
+        engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 3_000_000),
+        Err(RiskError::Undercollateralized)
+    );
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 1_500_000).unwrap();
+
+    let bad = ExtParams {
+        low_leverage_fee_discount_bps: 1_000,
+        ..ExtParams::default()
+    };
+    assert_eq!(engine.set_ext_params(bad), Err(RiskError::InvalidParams));
+}
+
+// ==============================================================================
+// POSITION AGE TESTS
+// ==============================================================================
+
+#[test]
+fn test_position_age_tracking_and_stale_report() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let old = engine.add_user(0).unwrap();
+    let young = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(old, 10_000_000, 0).unwrap();
+    engine.deposit(young, 10_000_000, 0).unwrap();
+
+    engine.execute_trade(&NoOpMatcher, lp, old, 0, 1_000_000, 1_000_000).unwrap();
+    engine.keeper_crank(lp, 400, 1_000_000, 0, false, 0, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, old, 400, 1_000_000, 500_000).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, young, 400, 1_000_000, -200_000).unwrap();
+
+    // Adding keeps the open slot; only the modification slot moves
+    let a = &engine.accounts[old as usize];
+    assert_eq!((a.position_opened_slot, a.position_modified_slot), (0, 400));
+    assert_eq!(a.position_age(500), 500);
+
+    let stale: Vec<_> = engine.stale_positions(500, 300).collect();
+    assert_eq!(stale.len(), 2, "the LP has been exposed since slot 0 too");
+    assert_eq!(stale[1].idx, old);
+    assert_eq!(stale[1].position, 1_500_000);
+    assert_eq!(stale[1].age_slots, 500);
+    assert_eq!(engine.stale_positions(500, 100).count(), 3);
+
+    // Flipping restarts the age; closing clears it
+    engine.execute_trade(&NoOpMatcher, lp, old, 450, 1_000_000, -2_000_000).unwrap();
+    assert_eq!(engine.accounts[old as usize].position_opened_slot, 450);
+    engine.execute_trade(&NoOpMatcher, lp, old, 460, 1_000_000, 500_000).unwrap();
+    assert_eq!(engine.accounts[old as usize].position_opened_slot, 0);
+    assert_eq!(engine.accounts[old as usize].position_age(500), 0);
+}
+
+#[test]
+fn test_holding_fee_escalates_with_position_age() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        holding_fee_per_slot_e9: 1_000,
+        holding_fee_escalation_slots: 100,
+        holding_fee_max_multiplier: 3,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 10_000_000, 0).unwrap();
+    engine.deposit(user, 10_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 1_000_000).unwrap();
+    let capital = engine.accounts[user as usize].capital.get();
+
+    // Age 100: twice the base 100
+    engine.keeper_crank(lp, 100, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.accounts[user as usize].fee_credits.get(), 1_000 - 200);
+
+    // Age 1_100: capped at 3x the base 1_000
+    engine.keeper_crank(lp, 1_100, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.accounts[user as usize].capital.get(), capital - 2_200);
+}
+
+// ==============================================================================
+// YIELD-BEARING COLLATERAL TESTS
+// ==============================================================================
+
+#[test]
+fn test_collateral_rate_uprates_margin() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        collateral_rate_max_step_bps: 1_000,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+
+    // 1_050_000 initial margin exceeds 1_000_000 units at 1:1
+    assert_eq!(engine.margin_required(10_500_000, 1_000), 1_050_000);
+    assert_eq!(
+        engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 10_500_000),
+        Err(RiskError::Undercollateralized)
+    );
+
+    // At 1.1 the same requirement needs only 954_546 units
+    engine
+        .keeper_crank_with_collateral_rate(lp, 1, 1_000_000, 0, false, 0, 0, 1_100_000_000)
+        .unwrap();
+    assert_eq!(engine.collateral_rate_e9, 1_100_000_000);
+    assert_eq!(engine.collateral_rate_slot, 1);
+    assert_eq!(engine.margin_required(10_500_000, 1_000), 954_546);
+    engine.execute_trade(&NoOpMatcher, lp, user, 1, 1_000_000, 10_500_000).unwrap();
+}
+
+#[test]
+fn test_collateral_rate_update_bounds() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    engine.deposit(lp, 1_000_000, 0).unwrap();
+
+    // Disabled until a step bound is configured
+    assert_eq!(
+        engine.update_collateral_rate(1_000_000_000, 1),
+        Err(RiskError::InvalidParams)
+    );
+
+    let ext = ExtParams {
+        collateral_rate_max_step_bps: 100,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    engine.update_collateral_rate(1_010_000_000, 1).unwrap();
+
+    // Rate may not fall, nor rise more than 1% per update
+    assert_eq!(
+        engine.update_collateral_rate(1_009_999_999, 2),
+        Err(RiskError::InvalidParams)
+    );
+    assert_eq!(
+        engine.update_collateral_rate(1_020_100_001, 2),
+        Err(RiskError::InvalidParams)
+    );
+    assert_eq!(
+        engine.keeper_crank_with_collateral_rate(lp, 2, 1_000_000, 0, false, 0, 0, 900_000_000),
+        Err(RiskError::InvalidParams)
+    );
+    engine.update_collateral_rate(1_020_100_000, 2).unwrap();
+    assert_eq!(engine.collateral_rate_e9, 1_020_100_000);
+}
+
+// ==============================================================================
+// MULTI-COLLATERAL CONFIG TESTS
+// ==============================================================================
+
+#[test]
+fn test_collateral_config_validation_and_atomic_update() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let msol = CollateralConfig {
+        oracle: [1; 32],
+        haircut_bps: 1_000,
+        deposit_cap: U128::new(5_000_000),
+    };
+    engine.set_collateral_config(0, msol).unwrap();
+    assert_eq!(engine.ext_params.collaterals[0], msol);
+    assert!(msol.is_active());
+    assert_eq!(msol.haircut_value(1_000_000), 900_000);
+
+    // Duplicate oracle, excess haircut, stray fields in an unused slot, bad index
+    let dup = CollateralConfig {
+        haircut_bps: 0,
+        ..msol
+    };
+    assert_eq!(engine.set_collateral_config(1, dup), Err(RiskError::InvalidParams));
+    let steep = CollateralConfig {
+        oracle: [2; 32],
+        haircut_bps: 10_001,
+        ..CollateralConfig::default()
+    };
+    assert_eq!(engine.set_collateral_config(1, steep), Err(RiskError::InvalidParams));
+    let stray = CollateralConfig {
+        haircut_bps: 100,
+        ..CollateralConfig::default()
+    };
+    assert_eq!(engine.set_collateral_config(1, stray), Err(RiskError::InvalidParams));
+    assert_eq!(
+        engine.set_collateral_config(MAX_COLLATERALS, msol),
+        Err(RiskError::InvalidParams)
+    );
+    assert_eq!(engine.ext_params.collaterals[1], CollateralConfig::default());
+
+    // Retune in place, then clear
+    let retuned = CollateralConfig {
+        haircut_bps: 2_000,
+        ..msol
+    };
+    engine.set_collateral_config(0, retuned).unwrap();
+    assert_eq!(engine.ext_params.collaterals[0].haircut_bps, 2_000);
+    engine.set_collateral_config(0, CollateralConfig::default()).unwrap();
+    assert!(!engine.ext_params.collaterals[0].is_active());
+}
+
+// ==============================================================================
+// ACCOUNT MERGE TESTS
+// ==============================================================================
+
+#[test]
+fn test_merge_accounts_nets_positions_and_frees_source() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let src = engine.add_user(0).unwrap();
+    let dst = engine.add_user(0).unwrap();
+    engine.set_owner(src, [7; 32]).unwrap();
+    engine.set_owner(dst, [7; 32]).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(src, 1_000_000, 0).unwrap();
+    engine.deposit(dst, 2_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, src, 0, 1_000_000, 1_000_000).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, dst, 0, 1_000_000, -400_000).unwrap();
+    let src_capital = engine.accounts[src as usize].capital.get();
+    let dst_capital = engine.accounts[dst as usize].capital.get();
+    let oi = engine.total_open_interest.get();
+
+    let moved = engine.merge_accounts(src, dst, 1, 1_000_000).unwrap();
+    assert_eq!(moved, src_capital);
+    assert!(!engine.is_used(src as usize));
+    let merged = &engine.accounts[dst as usize];
+    assert_eq!(merged.position_size.get(), 600_000);
+    assert_eq!(merged.capital.get(), src_capital + dst_capital);
+    assert_eq!(merged.position_opened_slot, 1, "the short flipped long");
+    assert_eq!(engine.total_open_interest.get(), oi - 800_000);
+
+    let ev = engine.event(engine.event_seq).unwrap();
+    assert_eq!(ev.kind, EventKind::AccountsMerged);
+    assert_eq!((ev.account, ev.counterparty), (dst, src));
+    assert_eq!(ev.value.get(), 1_000_000);
+}
+
+#[test]
+fn test_merge_accounts_carries_emission_points() {
+    let mut params = default_params();
+    params.trading_fee_bps = 0;
+    let mut engine = Box::new(RiskEngine::new(params));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let src = engine.add_user(0).unwrap();
+    let dst = engine.add_user(0).unwrap();
+    engine.set_owner(src, [7; 32]).unwrap();
+    engine.set_owner(dst, [7; 32]).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(src, 1_000_000, 0).unwrap();
+    engine.deposit(dst, 1_000_000, 0).unwrap();
+    let mut ext = engine.ext_params;
+    ext.emission_epoch_slots = 100;
+    ext.emission_oi_weight_bps = 1;
+    engine.set_ext_params(ext).unwrap();
+    engine.keeper_crank(u16::MAX, 10, 1_000_000, 0, false, 0, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, src, 10, 1_000_000, 1_000_000).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, dst, 10, 1_000_000, 500_000).unwrap();
+
+    // Points from the closed epoch and the open one both survive the merge
+    let (s, d) = (engine.emissions_of(src, 150).unwrap(), engine.emissions_of(dst, 150).unwrap());
+    assert_eq!((s.pending, s.claimable), (5_000, 9_000));
+    engine.keeper_crank(u16::MAX, 150, 1_000_000, 0, false, 0, 0).unwrap();
+    engine.merge_accounts(src, dst, 150, 1_000_000).unwrap();
+    let merged = engine.emissions_of(dst, 150).unwrap();
+    assert_eq!(merged.pending, s.pending + d.pending);
+    assert_eq!(merged.claimable, s.claimable + d.claimable);
+}
+
+#[test]
+fn test_merge_accounts_carries_haircut_claim() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let src = engine.add_user(0).unwrap();
+    let dst = engine.add_user(0).unwrap();
+    engine.set_owner(src, [7; 32]).unwrap();
+    engine.set_owner(dst, [7; 32]).unwrap();
+    engine.deposit(src, 10_000, 0).unwrap();
+    engine.deposit(dst, 10_000, 0).unwrap();
+    engine.accounts[src as usize].haircut_claim = U128::new(4_000);
+    engine.accounts[dst as usize].haircut_claim = U128::new(1_000);
+
+    // Consolidating one owner's accounts is not a close: the claim survives
+    engine.merge_accounts(src, dst, 0, 1_000_000).unwrap();
+    assert_eq!(engine.accounts[dst as usize].haircut_claim.get(), 5_000);
+}
+
+#[test]
+fn test_merge_accounts_rejections() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let a = engine.add_user(0).unwrap();
+    let b = engine.add_user(0).unwrap();
+    engine.set_owner(a, [1; 32]).unwrap();
+    engine.set_owner(b, [2; 32]).unwrap();
+    engine.deposit(a, 1_000, 0).unwrap();
+    engine.deposit(b, 1_000, 0).unwrap();
+
+    assert_eq!(engine.merge_accounts(a, a, 0, 1_000_000), Err(RiskError::InvalidParams));
+    assert_eq!(engine.merge_accounts(a, b, 0, 1_000_000), Err(RiskError::Unauthorized));
+    engine.set_owner(b, [1; 32]).unwrap();
+    engine.set_owner(lp, [1; 32]).unwrap();
+    assert_eq!(
+        engine.merge_accounts(a, lp, 0, 1_000_000),
+        Err(RiskError::AccountKindMismatch)
+    );
+    assert_eq!(engine.merge_accounts(a, b, 0, 1_000_000), Ok(1_000));
+    assert_eq!(engine.accounts[b as usize].capital.get(), 2_000);
+
+    // A merge that would leave the destination under maintenance moves nothing
+    let c = engine.add_user(0).unwrap();
+    engine.set_owner(c, [1; 32]).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(c, 120_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, c, 0, 1_000_000, 1_000_000).unwrap();
+    assert_eq!(
+        engine.merge_accounts(b, c, 1, 900_000),
+        Err(RiskError::Undercollateralized)
+    );
+    assert!(engine.is_used(b as usize));
+    assert_eq!(engine.accounts[b as usize].capital.get(), 2_000);
+    assert_eq!(engine.accounts[c as usize].position_size.get(), 1_000_000);
+}
+
+#[test]
+fn test_merge_accounts_respects_reduce_only() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let long = engine.add_user(0).unwrap();
+    let short = engine.add_user(0).unwrap();
+    let dust = engine.add_user(0).unwrap();
+    for idx in [long, short, dust] {
+        engine.set_owner(idx, [7; 32]).unwrap();
+        engine.deposit(idx, 1_000_000, 0).unwrap();
+    }
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, long, 0, 1_000_000, 1_000_000).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, short, 0, 1_000_000, -400_000).unwrap();
+
+    // A restricted short may not be flipped long by merging the long into it, nor
+    // moved into the long to escape the flag
+    engine.set_reduce_only_by_admin(short, true).unwrap();
+    assert_eq!(engine.merge_accounts(long, short, 0, 1_000_000), Err(RiskError::ReduceOnly));
+    engine.set_reduce_only_by_admin(short, false).unwrap();
+    engine.set_reduce_only(long, true).unwrap();
+    assert_eq!(engine.merge_accounts(dust, long, 0, 1_000_000), Ok(1_000_000));
+
+    // Merging into an unrestricted destination shrinks the position and keeps the flag
+    engine.merge_accounts(long, short, 0, 1_000_000).unwrap();
+    assert_eq!(engine.accounts[short as usize].position_size.get(), 600_000);
+    assert!(engine.is_reduce_only(short));
+}
+
+#[test]
+fn test_merge_accounts_initial_margin_owner_cap_and_drain() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let src = engine.add_user(0).unwrap();
+    let dst = engine.add_user(0).unwrap();
+    engine.set_owner(src, [7; 32]).unwrap();
+    engine.set_owner(dst, [7; 32]).unwrap();
+    let mut ext = engine.ext_params;
+    ext.max_epoch_drain = U128::new(1_000_000);
+    ext.drain_epoch_slots = 1_000;
+    engine.set_ext_params(ext).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(src, 330_000, 0).unwrap();
+    engine.deposit(dst, 20_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, src, 0, 1_000_000, 1_000_000).unwrap();
+
+    // The flat destination capped at 2x cannot take on ~3x through a merge
+    engine.set_max_leverage(dst, 2).unwrap();
+    assert_eq!(engine.merge_accounts(src, dst, 0, 1_000_000), Err(RiskError::Undercollateralized));
+    engine.set_max_leverage(dst, 0).unwrap();
+
+    // Nor can it grow past an owner cap lowered below the current exposure
+    ext.max_owner_notional = U128::new(900_000);
+    engine.set_ext_params(ext).unwrap();
+    assert_eq!(engine.merge_accounts(src, dst, 0, 1_000_000), Err(RiskError::OwnerLimitExceeded));
+    ext.max_owner_notional = U128::ZERO;
+    engine.set_ext_params(ext).unwrap();
+
+    // What both accounts drained this epoch stays counted against the destination
+    engine.withdraw(src, 10_000, 0, 1_000_000).unwrap();
+    engine.withdraw(dst, 5_000, 0, 1_000_000).unwrap();
+    engine.merge_accounts(src, dst, 0, 1_000_000).unwrap();
+    assert_eq!(engine.epoch_drain(dst), 15_000);
+    assert_eq!(engine.accounts[dst as usize].position_size.get(), 1_000_000);
+}
+
+// ==============================================================================
+// OWNER LIMIT TESTS
+// ==============================================================================
+
+#[test]
+fn test_owner_notional_limit_spans_accounts() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        max_owner_notional: U128::new(3_000_000),
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let a = engine.add_user(0).unwrap();
+    let b = engine.add_user(0).unwrap();
+    engine.set_owner(a, [9; 32]).unwrap();
+    engine.set_owner(b, [9; 32]).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(a, 1_000_000, 0).unwrap();
+    engine.deposit(b, 1_000_000, 0).unwrap();
+
+    engine.execute_trade(&NoOpMatcher, lp, a, 0, 1_000_000, 2_000_000).unwrap();
+    assert_eq!(
+        engine.execute_trade(&NoOpMatcher, lp, b, 0, 1_000_000, -1_500_000),
+        Err(RiskError::OwnerLimitExceeded)
+    );
+    engine.execute_trade(&NoOpMatcher, lp, b, 0, 1_000_000, -1_000_000).unwrap();
+
+    let exposure = engine.owner_exposure(&[9; 32], 1_000_000);
+    assert_eq!((exposure.accounts, exposure.notional), (2, 3_000_000));
+
+    // Reducing is always allowed, even after the price pushes notional over the cap
+    engine.execute_trade(&NoOpMatcher, lp, a, 0, 1_100_000, -500_000).unwrap();
+    assert_eq!(engine.owner_exposure(&[9; 32], 1_100_000).notional, 2_750_000);
+}
+
+#[test]
+fn test_owner_capital_limit_on_deposit() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        max_owner_capital: U128::new(5_000_000),
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let a = engine.add_user(0).unwrap();
+    let b = engine.add_user(0).unwrap();
+    let other = engine.add_user(0).unwrap();
+    engine.set_owner(a, [9; 32]).unwrap();
+    engine.set_owner(b, [9; 32]).unwrap();
+
+    engine.deposit(a, 3_000_000, 0).unwrap();
+    engine.deposit(b, 2_000_000, 0).unwrap();
+    assert_eq!(engine.deposit(b, 1, 0), Err(RiskError::OwnerLimitExceeded));
+    assert_eq!(engine.deposit(a, 1, 0), Err(RiskError::OwnerLimitExceeded));
+
+    // Unowned accounts are not aggregated
+    engine.deposit(other, 6_000_000, 0).unwrap();
+}
+
+#[test]
+fn test_trade_receipts_carry_increasing_fill_ids() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+
+    let first = engine
+        .execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 2_000_000)
+        .unwrap();
+    assert_eq!(first.fill_id, 1);
+    assert_eq!(first.exec_price, 1_000_000);
+    assert_eq!(first.exec_size, 2_000_000);
+    assert_eq!(first.fee, 2_000);
+    assert_eq!(first.new_position, 2_000_000);
+    // (1_000_000 - 2_000 fee) / 2_000_000 notional
+    assert_eq!(first.new_margin_ratio_bps, 4_990);
+
+    let second = engine
+        .execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, -2_000_000)
+        .unwrap();
+    assert_eq!(second.fill_id, 2);
+    assert_eq!(second.new_position, 0);
+    assert_eq!(second.new_margin_ratio_bps, u64::MAX);
+    assert_eq!(engine.last_fill_id, 2);
+
+    // Rejected trades do not consume a fill ID
+    assert!(engine
+        .execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 100_000_000)
+        .is_err());
+    let third = engine
+        .execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 1_000)
+        .unwrap();
+    assert_eq!(third.fill_id, 3);
+}
+
+#[test]
+fn test_liquidator_initiated_liquidation() {
+    let mut params = default_params();
+    params.liquidation_fee_keeper_share_bps = 2_000; // 20%
+    let mut engine = Box::new(RiskEngine::new(params));
+    let (keeper, lp, user) = setup_fee_waterfall(&mut engine);
+
+    assert_eq!(engine.liquidate(user, user, 100_000, 0, 1_000_000), Err(RiskError::InvalidParams));
+    assert_eq!(engine.liquidate(keeper, user, 0, 0, 1_000_000), Err(RiskError::InvalidParams));
+    assert_eq!(engine.liquidate(999, user, 100_000, 0, 1_000_000), Err(RiskError::AccountNotFound));
+    // Healthy target: nothing to do
+    assert_eq!(engine.liquidate(user, lp, 100_000, 0, 1_000_000), Ok(None));
+
+    let record = engine.liquidate(keeper, user, 100_000, 0, 1_000_000).unwrap().unwrap();
+    assert_eq!(record.fee_total, 500);
+    assert_eq!(record.fee_to_keeper, 100);
+    assert_eq!(engine.accounts[keeper as usize].capital.get(), 10_100);
+    assert_conserved(&engine);
+}
+
+#[test]
+fn test_liquidator_max_size_caps_close() {
+    let mut params = default_params();
+    params.min_liquidation_abs = U128::new(1_000);
+    let mut engine = Box::new(RiskEngine::new(params));
+    let (keeper, _lp, user) = setup_fee_waterfall(&mut engine);
+
+    // Below the minimum liquidation slice
+    assert_eq!(engine.liquidate(keeper, user, 999, 0, 1_000_000), Err(RiskError::InvalidParams));
+
+    let record = engine.liquidate(keeper, user, 30_000, 0, 1_000_000).unwrap().unwrap();
+    assert_eq!(record.closed_abs, 30_000);
+    assert_eq!(engine.accounts[user as usize].position_size.get(), 70_000);
+    assert_conserved(&engine);
+}
+
+#[test]
+fn test_liquidation_takeover_moves_position_to_liquidator() {
+    let mut params = default_params();
+    params.liquidation_fee_keeper_share_bps = 2_000; // 20%
+    let mut engine = Box::new(RiskEngine::new(params));
+    let (keeper, lp, user) = setup_fee_waterfall(&mut engine);
+    engine.deposit(keeper, 10_000, 0).unwrap();
+
+    let record = engine
+        .liquidate_with_takeover(keeper, user, 100_000, 0, 1_000_000)
+        .unwrap()
+        .expect("Liquidation should occur");
+    assert_eq!(record.closed_abs, 100_000);
+    assert_eq!(engine.accounts[user as usize].position_size.get(), 0);
+    assert_eq!(engine.accounts[keeper as usize].position_size.get(), 100_000);
+    assert_eq!(engine.accounts[keeper as usize].entry_price, 1_000_000);
+    // The LP's short is still matched by the liquidator's long
+    assert_eq!(engine.accounts[lp as usize].position_size.get(), -100_000);
+    assert_eq!(engine.total_open_interest.get(), 200_000);
+    assert_conserved(&engine);
+}
+
+#[test]
+fn test_liquidation_takeover_requires_liquidator_margin() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let (keeper, _lp, user) = setup_fee_waterfall(&mut engine);
+
+    // 10_000 capital cannot carry 100_000 notional at 10% initial margin
+    assert_eq!(
+        engine.liquidate_with_takeover(keeper, user, 100_000, 0, 1_000_000),
+        Err(RiskError::Undercollateralized)
+    );
+}
+
+#[test]
+fn test_liquidation_takeover_respects_liquidator_reduce_only() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let (keeper, _lp, user) = setup_fee_waterfall(&mut engine);
+    engine.deposit(keeper, 10_000, 0).unwrap();
+
+    // A restricted liquidator cannot open a position by taking over the slice
+    engine.set_reduce_only(keeper, true).unwrap();
+    assert_eq!(
+        engine.liquidate_with_takeover(keeper, user, 100_000, 0, 1_000_000),
+        Err(RiskError::ReduceOnly)
+    );
+}
+
+#[test]
+fn test_insurance_skim_stops_at_target() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 20_000_000, 0).unwrap();
+
+    // 0.1% fee on 10_000_000 notional = 10_000, split evenly without a skim
+    let before = engine.insurance_fund.balance.get();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 10_000_000).unwrap();
+    assert_eq!(engine.insurance_fund.balance.get() - before, 5_000);
+
+    let ext = ExtParams {
+        insurance_skim_bps: 5_000,
+        insurance_target_bps: 1_000,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    assert_eq!(engine.insurance_target(1_000_000), Some(2_000_000));
+
+    // Half the fee is skimmed first, the rest split evenly
+    let before = engine.insurance_fund.balance.get();
+    let lp_before = engine.accounts[lp as usize].capital.get();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 10_000_000).unwrap();
+    assert_eq!(engine.insurance_fund.balance.get() - before, 7_500);
+    assert_eq!(engine.accounts[lp as usize].capital.get() - lp_before, 2_500);
+
+    // Target reached (4_000_000 OI notional x 10%): the skim stops
+    set_insurance(&mut engine, 4_000_000);
+    let before = engine.insurance_fund.balance.get();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, -10_000_000).unwrap();
+    assert_eq!(engine.insurance_fund.balance.get() - before, 5_000);
+    assert_conserved(&engine);
+
+    let bad = ExtParams {
+        insurance_skim_bps: 10_001,
+        ..ExtParams::default()
+    };
+    assert_eq!(engine.set_ext_params(bad), Err(RiskError::InvalidParams));
+}
+
+#[test]
+fn test_utilization_bands_gate_trades_and_lp_withdrawals() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 10_000_000, 0).unwrap();
+    engine.deposit(user, 10_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 5_000_000).unwrap();
+
+    let ext = ExtParams {
+        utilization_soft_bps: 6_000,
+        utilization_hard_bps: 7_000,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let util = engine.vault_utilization(1_000_000);
+    assert_eq!((util.requirement, util.available), (10_000_000, 20_000_000));
+    assert_eq!((util.bps, util.band), (5_000, UtilizationBand::Normal));
+    assert_eq!(engine.utilization_bps(1_000_000), util.bps);
+
+    // LP withdrawals stop at the soft band: 10M / 6_000 bps = 16_666_667 must stay
+    assert_eq!(
+        engine.withdraw(lp, 3_400_000, 0, 1_000_000),
+        Err(RiskError::UtilizationTooHigh)
+    );
+    engine.withdraw(lp, 3_000_000, 0, 1_000_000).unwrap();
+    assert_eq!(engine.vault_utilization(1_000_000).band, UtilizationBand::Normal);
+
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 500_000).unwrap();
+    assert_eq!(engine.vault_utilization(1_000_000).band, UtilizationBand::Soft);
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 700_000).unwrap();
+    assert_eq!(engine.vault_utilization(1_000_000).band, UtilizationBand::Hard);
+
+    // Hard band: risk-reducing trades only
+    assert_eq!(
+        engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 100_000),
+        Err(RiskError::UtilizationTooHigh)
+    );
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, -2_000_000).unwrap();
+    assert_eq!(engine.vault_utilization(1_000_000).band, UtilizationBand::Normal);
+
+    let bad = ExtParams {
+        utilization_soft_bps: 8_000,
+        utilization_hard_bps: 7_000,
+        ..ExtParams::default()
+    };
+    assert_eq!(engine.set_ext_params(bad), Err(RiskError::InvalidParams));
+}
+
+#[test]
+fn test_mark_ema_tracks_crank_prices() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        close_price_source: ClosePriceSource::MarkEma,
+        close_price_ema_slots: 100,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let keeper = engine.add_user(0).unwrap();
+
+    engine.keeper_crank(keeper, 1, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.mark_ema_price, 1_000_000);
+    // Half the horizon elapsed: halfway to the new print
+    engine.keeper_crank(keeper, 51, 1_200_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.mark_ema_price, 1_100_000);
+    assert_eq!(engine.close_price(1_200_000, 51), 1_100_000);
+
+    let bad = ExtParams {
+        close_price_source: ClosePriceSource::LastExecution,
+        ..ExtParams::default()
+    };
+    assert_eq!(engine.set_ext_params(bad), Err(RiskError::InvalidParams));
+}
+
+#[test]
+fn test_liquidation_closes_at_configured_price() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let (_keeper, _lp, user) = setup_fee_waterfall(&mut engine);
+    let ext = ExtParams {
+        close_price_source: ClosePriceSource::MarkEma,
+        close_price_ema_slots: 100,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    engine.mark_ema_price = 1_010_000;
+
+    // Still below maintenance at the oracle, but the close books the EMA premium
+    assert!(engine.liquidate_at_oracle(user, 0, 1_000_000).unwrap());
+    let account = &engine.accounts[user as usize];
+    assert_eq!(account.position_size.get(), 0);
+    assert_eq!(account.capital.get() as i128 + account.pnl.get(), 3_500 + 1_000);
+    assert_conserved(&engine);
+
+    // LastExecution falls back to the oracle once the fill is stale
+    engine.ext_params.close_price_source = ClosePriceSource::LastExecution;
+    engine.last_exec_price = 990_000;
+    engine.last_exec_slot = 10;
+    assert_eq!(engine.close_price(1_000_000, 110), 990_000);
+    assert_eq!(engine.close_price(1_000_000, 111), 1_000_000);
+}
+
+#[test]
+fn test_epoch_drain_cap_limits_withdrawals() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        max_epoch_drain: U128::new(300_000),
+        drain_epoch_slots: 100,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+    // Deposits only offset withdrawals within the same epoch
+    assert_eq!(engine.epoch_drain(user), 0);
+
+    engine.withdraw(user, 200_000, 0, 1_000_000).unwrap();
+    assert_eq!(engine.epoch_drain(user), 200_000);
+    assert_eq!(
+        engine.withdraw(user, 150_000, 0, 1_000_000),
+        Err(RiskError::DrainLimitExceeded)
+    );
+
+    // Depositing back nets against the drain
+    engine.deposit(user, 100_000, 0).unwrap();
+    engine.withdraw(user, 150_000, 0, 1_000_000).unwrap();
+    assert_eq!(engine.epoch_drain(user), 250_000);
+
+    // A new epoch starts from zero
+    engine.keeper_crank(user, 100, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.epoch_drain(user), 0);
+    engine.withdraw(user, 300_000, 100, 1_000_000).unwrap();
+
+    let bad = ExtParams {
+        max_epoch_drain: U128::new(1),
+        ..ExtParams::default()
+    };
+    assert_eq!(engine.set_ext_params(bad), Err(RiskError::InvalidParams));
+}
+
+#[test]
+fn test_shared_epoch_rolls_in_crank() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        epoch_slots: 100,
+        max_epoch_drain: U128::new(300_000),
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+
+    engine.keeper_crank(user, 10, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.epoch(), Epoch { index: 0, start_slot: 0, len: 100 });
+    engine.keeper_crank(user, 250, 1_000_000, 0, false, 0, 0).unwrap();
+    let epoch = engine.epoch();
+    assert_eq!((epoch.index, epoch.start_slot, epoch.end_slot()), (2, 200, 300));
+    assert_eq!(engine.epoch_rollovers, 1);
+
+    // The drain cap and liquidation analytics follow the shared epoch
+    engine.withdraw(user, 300_000, 250, 1_000_000).unwrap();
+    assert_eq!(engine.accounts[user as usize].drain_epoch_start, 200);
+    assert_eq!(engine.liquidation_analytics().current.epoch_start_slot, 200);
+    engine.keeper_crank(user, 300, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.epoch_drain(user), 0);
+}
+
+#[test]
+fn test_deposit_and_trade_margins_on_post_deposit_balance() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+
+    // An empty account cannot pay the fee, let alone margin
+    assert!(engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 2_000_000).is_err());
+
+    let vault_before = vault_snapshot(&engine);
+    let receipt = engine
+        .deposit_and_trade(&NoOpMatcher, lp, user, 1_000_000, 0, 1_000_000, 2_000_000)
+        .unwrap();
+    assert_vault_delta(&engine, vault_before, 1_000_000);
+    assert_eq!(receipt.new_position, 2_000_000);
+    assert_eq!(engine.accounts[user as usize].capital.get(), 1_000_000 - receipt.fee);
+    assert_conserved(&engine);
+}
+
+#[test]
+fn test_close_and_withdraw() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 8_000_000).unwrap();
+
+    // Increasing or flipping is not a close
+    for size in [1_000_000, -9_000_000, 0] {
+        assert_eq!(
+            engine.close_and_withdraw(&NoOpMatcher, lp, user, size, 0, 0, 1_000_000),
+            Err(RiskError::InvalidParams)
+        );
+    }
+    // Withdrawing more than the close frees fails the initial-margin check
+    assert_eq!(
+        engine.close_and_withdraw(&NoOpMatcher, lp, user, -4_000_000, 700_000, 0, 1_000_000),
+        Err(RiskError::Undercollateralized)
+    );
+
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 8_000_000).unwrap();
+
+    let vault_before = vault_snapshot(&engine);
+    let receipt = engine
+        .close_and_withdraw(&NoOpMatcher, lp, user, -4_000_000, 500_000, 0, 1_000_000)
+        .unwrap();
+    assert_eq!(receipt.new_position, 4_000_000);
+    assert_vault_delta(&engine, vault_before, -500_000);
+    assert_conserved(&engine);
+}
+
+#[test]
+fn test_close_and_withdraw_checks_account_initial_margin_once() {
+    // The final state must meet the account's own initial margin, not just the
+    // market's: a 4x cap needs 25% of the remaining 2M notional
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 3_000_000).unwrap();
+    engine.set_max_leverage(user, 4).unwrap();
+    assert_eq!(
+        engine.close_and_withdraw(&NoOpMatcher, lp, user, -1_000_000, 600_000, 0, 1_000_000),
+        Err(RiskError::Undercollateralized)
+    );
+
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 3_000_000).unwrap();
+    engine.set_max_leverage(user, 4).unwrap();
+    let receipt = engine
+        .close_and_withdraw(&NoOpMatcher, lp, user, -1_000_000, 400_000, 0, 1_000_000)
+        .unwrap();
+    assert_eq!(receipt.new_position, 2_000_000);
+    assert_conserved(&engine);
+}
+
+#[test]
+fn test_prng_is_reproducible_and_drives_sampling() {
+    let mut a = Prng::from_seed_bytes(&[7; 32]);
+    let mut b = Prng::from_seed_bytes(&[7; 32]);
+    for _ in 0..16 {
+        assert_eq!(a.next_u64(), b.next_u64());
+        assert!(a.next_below(10) < 10);
+        b.next_below(10);
+    }
+    assert_eq!(a.next_below(0), 0);
+    assert_ne!(Prng::new(1).next_u64(), Prng::new(2).next_u64());
+
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    for _ in 0..20 {
+        engine.add_user(0).unwrap();
+    }
+    let mut twin = engine.clone();
+    engine.set_rng_seed(&[42; 32]);
+    twin.set_rng_seed(&[42; 32]);
+
+    let mut sample = [u16::MAX; 5];
+    let mut twin_sample = [u16::MAX; 5];
+    assert_eq!(engine.sample_accounts(&mut sample), 5);
+    assert_eq!(twin.sample_accounts(&mut twin_sample), 5);
+    assert_eq!(sample, twin_sample);
+    for (i, idx) in sample.iter().enumerate() {
+        assert!(engine.is_used(*idx as usize));
+        assert!(!sample[..i].contains(idx));
+    }
+
+    // Fewer accounts than slots: every account, in index order
+    let mut small = Box::new(RiskEngine::new(default_params()));
+    small.add_user(0).unwrap();
+    small.add_user(0).unwrap();
+    let mut out = [u16::MAX; 4];
+    assert_eq!(small.sample_accounts(&mut out), 2);
+    assert_eq!(&out[..2], &[0, 1]);
+}
+
+#[test]
+fn test_account_tags() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let plain = engine.add_user(0).unwrap();
+    let user = engine.add_user_tagged(0, [7; 32]).unwrap();
+    let lp = engine.add_lp_tagged([1; 32], [2; 32], 0, [7; 32]).unwrap();
+
+    assert_eq!(engine.account_tag(plain), Some([0; 32]));
+    assert_eq!(engine.account_tag(user), Some([7; 32]));
+    assert_eq!(engine.accounts[lp as usize].kind, AccountKind::LP);
+    assert_eq!(engine.accounts[lp as usize].matcher_program, [1; 32]);
+    assert_eq!(engine.accounts_with_tag(&[7; 32]).collect::<Vec<_>>(), vec![user, lp]);
+
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 1_000_000).unwrap();
+    let stale: Vec<_> = engine.stale_positions(0, 0).collect();
+    assert!(stale.iter().all(|p| p.tag == [7; 32]));
+
+    // Freed slots forget the tag
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, -1_000_000).unwrap();
+    engine.close_account(user, 0, 1_000_000).unwrap();
+    assert_eq!(engine.account_tag(user), None);
+    let reused = engine.add_user(0).unwrap();
+    assert_eq!(engine.account_tag(reused), Some([0; 32]));
+}
+
+#[test]
+fn test_fee_holiday_window() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 10_000_000, 0).unwrap();
+    assert_eq!(engine.set_fee_holiday(200, 100, 0), Err(RiskError::InvalidParams));
+    // A holiday only discounts the 10 bps base fee
+    assert_eq!(engine.set_fee_holiday(100, 200, 11), Err(RiskError::InvalidParams));
+    engine.set_fee_holiday(100, 200, 2).unwrap();
+
+    let capital = |e: &RiskEngine| e.accounts[user as usize].capital.get();
+    let fee_at = |e: &mut RiskEngine, slot: u64| {
+        let before = capital(e);
+        e.execute_trade(&NoOpMatcher, lp, user, slot, 1_000_000, 1_000_000).unwrap();
+        before - capital(e)
+    };
+    // 10 bps before the window, 2 bps inside it, back to 10 bps at end_slot
+    assert_eq!(fee_at(&mut engine, 99), 1_000);
+    assert_eq!(fee_at(&mut engine, 100), 200);
+    assert_eq!(fee_at(&mut engine, 199), 200);
+    assert_eq!(fee_at(&mut engine, 200), 1_000);
+
+    // An empty window clears the schedule
+    engine.set_fee_holiday(0, 0, 0).unwrap();
+    assert!(!engine.fee_holiday.is_active(0));
+    assert_conserved(&engine);
+
+    // Governed markets cannot change fees outside the queue
+    engine.ext_params.param_timelock_slots = 100;
+    assert_eq!(engine.set_fee_holiday(300, 400, 0), Err(RiskError::TimelockActive));
+    engine.set_admin_council(&[], &[[5; 32]], 1).unwrap();
+    assert_eq!(engine.set_fee_holiday(300, 400, 0), Err(RiskError::ApprovalsMissing));
+}
+
+#[test]
+fn test_skew_cap_tracks_lp_capital() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let buyer = engine.add_user(0).unwrap();
+    let seller = engine.add_user(0).unwrap();
+    engine.deposit(lp, 10_000_000, 0).unwrap();
+    engine.deposit(buyer, 10_000_000, 0).unwrap();
+    engine.deposit(seller, 10_000_000, 0).unwrap();
+    assert_eq!(engine.skew_capacity(1_000_000), None);
+
+    // A 20% gap on the skew must fit in 10M of LP capital: 50M notional
+    let ext = ExtParams {
+        skew_gap_move_bps: 2_000,
+        ..engine.ext_params
+    };
+    engine.set_ext_params(ext).unwrap();
+    let capacity = engine.skew_capacity(1_000_000).unwrap();
+    assert_eq!(capacity.max_notional, 50_000_000);
+    assert_eq!((capacity.buy_remaining, capacity.sell_remaining), (50_000_000, 50_000_000));
+
+    engine.execute_trade(&NoOpMatcher, lp, buyer, 0, 1_000_000, 40_000_000).unwrap();
+    let capacity = engine.skew_capacity(1_000_000).unwrap();
+    // Fee income grew the LP's capital, and with it the cap
+    let lp_capital = engine.accounts[lp as usize].capital.get();
+    assert_eq!(capacity.max_notional, lp_capital * 5);
+    assert_eq!(capacity.notional, 40_000_000);
+    assert_eq!(capacity.buy_remaining, lp_capital * 5 - 40_000_000);
+    assert_eq!(
+        engine.execute_trade(&NoOpMatcher, lp, buyer, 0, 1_000_000, 15_000_000),
+        Err(RiskError::SkewLimitExceeded)
+    );
+
+    // Trades that shrink the skew are always accepted, and free up capacity
+    engine.execute_trade(&NoOpMatcher, lp, seller, 0, 1_000_000, -20_000_000).unwrap();
+    assert!(engine.skew_capacity(1_000_000).unwrap().buy_remaining >= 30_000_000);
+    engine.execute_trade(&NoOpMatcher, lp, buyer, 0, 1_000_000, 15_000_000).unwrap();
+    assert_conserved(&engine);
+}
+
+#[test]
+fn test_execute_trade_detailed_rejections() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+
+    // 20M notional needs 2M of initial margin; the user has ~1M
+    let rejection = engine
+        .execute_trade_detailed(&NoOpMatcher, lp, user, 0, 1_000_000, 20_000_000)
+        .unwrap_err();
+    assert_eq!(rejection.error, RiskError::Undercollateralized);
+    match rejection.reason {
+        TradeRejectReason::Margin {
+            account,
+            required,
+            equity,
+        } => {
+            assert_eq!(account, user);
+            assert_eq!(required, 2_000_000);
+            assert!(equity < required);
+        }
+        other => panic!("unexpected reason {:?}", other),
+    }
+
+    // A stale crank reports when the last crank ran
+    engine.max_crank_staleness_slots = 100;
+    let rejection = engine
+        .execute_trade_detailed(&NoOpMatcher, lp, user, 500, 1_000_000, 1_000_000)
+        .unwrap_err();
+    assert_eq!(
+        rejection,
+        TradeRejection {
+            error: RiskError::Unauthorized,
+            reason: TradeRejectReason::CrankStale {
+                last_crank_slot: 0,
+                max_staleness_slots: 100,
+            },
+        }
+    );
+
+    // Success leaves nothing behind; unexplained failures carry no detail
+    engine
+        .execute_trade_detailed(&NoOpMatcher, lp, user, 0, 1_000_000, 1_000_000)
+        .unwrap();
+    assert_eq!(engine.last_trade_rejection, TradeRejectReason::Other);
+    let rejection = engine
+        .execute_trade_detailed(&NoOpMatcher, lp, lp, 0, 1_000_000, 1_000_000)
+        .unwrap_err();
+    assert_eq!(rejection.reason, TradeRejectReason::Other);
+}
+
+#[test]
+fn test_pending_settlement_matches_next_touch() {
+    let mut params = default_params();
+    params.warmup_period_slots = 100;
+    params.maintenance_fee_per_slot = U128::new(10);
+    let mut engine = Box::new(RiskEngine::new(params));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    engine.deposit(user, 10_000_000, 0).unwrap();
+    assert_eq!(engine.pending_settlement(999, 0), None);
+
+    // Realize a profit: long 10M at 1.0, flat again at 1.1
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 10_000_000).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_100_000, -10_000_000).unwrap();
+    let profit = engine.accounts[user as usize].pnl.get() as u128;
+    assert!(profit > 0);
+
+    let pending = engine.pending_settlement(user, 50).unwrap();
+    assert_eq!(pending.maintenance_fee, 500);
+    assert_eq!(pending.funding_owed, 0);
+    assert_eq!(pending.vested_pnl + pending.unvested_pnl, profit);
+    assert!(pending.vested_pnl > 0 && pending.unvested_pnl > 0);
+
+    // The next touch converts exactly the vested part
+    let capital = engine.accounts[user as usize].capital.get();
+    engine.current_slot = 50;
+    engine.settle_warmup_to_capital(user).unwrap();
+    assert_eq!(engine.accounts[user as usize].capital.get(), capital + pending.vested_pnl);
+    assert_eq!(engine.accounts[user as usize].pnl.get() as u128, pending.unvested_pnl);
+
+    // Funding accrued into the index shows up before the account is touched
+    engine.execute_trade(&NoOpMatcher, lp, user, 50, 1_100_000, 5_000_000).unwrap();
+    engine.funding_index_qpb_e6 += 2_000;
+    let pending = engine.pending_settlement(user, 70).unwrap();
+    assert!(pending.funding_owed > 0);
+    let pnl = engine.accounts[user as usize].pnl.get();
+    engine.touch_account(user).unwrap();
+    assert_eq!(engine.accounts[user as usize].pnl.get(), pnl - pending.funding_owed);
+    assert_eq!(engine.pending_settlement(user, 70).unwrap().funding_owed, 0);
+}
+
+#[test]
+fn test_state_seq_optimistic_concurrency() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 100_000_000, 0).unwrap();
+    assert_eq!(engine.state_seq, 3);
+
+    // Failed operations leave the sequence alone
+    assert!(engine.withdraw(user, 1, 0, 1_000_000).is_err());
+    assert_eq!(engine.state_seq, 3);
+
+    let seen = engine.state_seq;
+    engine
+        .with_expected_seq(Some(seen), |e| e.deposit(user, 1_000_000, 0))
+        .unwrap();
+    assert_eq!(engine.state_seq, 4);
+
+    // A router quoting against the old state is turned away
+    assert_eq!(
+        engine.with_expected_seq(Some(seen), |e| {
+            e.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 1_000_000)
+        }),
+        Err(RiskError::StaleSequence)
+    );
+    assert!(engine.accounts[user as usize].position_size.is_zero());
+    engine
+        .with_expected_seq(None, |e| {
+            e.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 1_000_000)
+        })
+        .unwrap();
+    assert_eq!(engine.state_seq, 5);
+
+    engine.set_ext_params(engine.ext_params).unwrap();
+    assert_eq!(engine.state_seq, 6);
+}
+
+#[test]
+fn test_state_seq_unchanged_by_skipped_crank() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    engine.deposit(lp, 1_000_000, 0).unwrap();
+    let outcome = engine.keeper_crank(lp, 10, 1_000_000, 0, false, 0, 0).unwrap();
+    assert!(!outcome.skipped);
+    let seq = engine.state_seq;
+
+    // A deduplicated crank in the same slot does not invalidate pending actions
+    assert!(engine.keeper_crank(lp, 10, 1_000_000, 0, false, 0, 0).unwrap().skipped);
+    assert_eq!(engine.state_seq, seq);
+    assert_eq!(engine.with_expected_seq(Some(seq), |e| e.deposit(lp, 1, 10)), Ok(()));
+}
+
+#[test]
+fn test_state_seq_bumped_by_setters() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([0; 32], [0; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.ext_params.max_credit_limit = U128::new(1_000);
+    engine.grant_credit_line(lp, 1_000, 0, 100).unwrap();
+
+    let seq = engine.state_seq;
+    engine.set_owner(user, [7; 32]).unwrap();
+    assert_eq!(engine.state_seq, seq + 1);
+    engine.set_adl_first(user, true).unwrap();
+    assert_eq!(engine.state_seq, seq + 2);
+    engine.set_fee_holiday(10, 20, 0).unwrap();
+    assert_eq!(engine.state_seq, seq + 3);
+    engine.set_guardian([9; 32]).unwrap();
+    assert_eq!(engine.state_seq, seq + 4);
+    engine.set_funding_rate_for_next_interval(5);
+    assert_eq!(engine.state_seq, seq + 5);
+    engine.revoke_credit_line(lp, 0, 1_000_000).unwrap();
+    assert_eq!(engine.state_seq, seq + 6);
+
+    // Rejected calls leave it alone
+    assert!(engine.set_owner(999, [7; 32]).is_err());
+    assert!(engine.set_fee_holiday(20, 10, 0).is_err());
+    assert_eq!(engine.state_seq, seq + 6);
+}
+
+#[test]
+fn test_state_seq_bumped_by_governance_and_settlement() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+    let mut seq = engine.state_seq;
+    let mut bumped = |engine: &RiskEngine| {
+        seq += 1;
+        assert_eq!(engine.state_seq, seq);
+    };
+
+    engine.set_risk_reduction_threshold(0).unwrap();
+    bumped(&engine);
+    engine.schedule_margin_event(50).unwrap();
+    bumped(&engine);
+    engine.cancel_margin_event(50);
+    bumped(&engine);
+    engine.touch_account(user).unwrap();
+    bumped(&engine);
+    engine.settle_mark_to_oracle(user, 1_000_000).unwrap();
+    bumped(&engine);
+    engine.settle_maintenance_fee(user, 10, 1_000_000).unwrap();
+    bumped(&engine);
+    engine.settle_loss_only(user).unwrap();
+    bumped(&engine);
+
+    let ext = ExtParams {
+        param_timelock_slots: 100,
+        ..engine.ext_params
+    };
+    engine.set_ext_params(ext).unwrap();
+    bumped(&engine);
+    engine.set_guardian([9u8; 32]).unwrap();
+    bumped(&engine);
+    engine.set_admin_council(&[], &[[1u8; 32]], 1).unwrap();
+    bumped(&engine);
+    engine.queue_params_update(default_params(), ext, 0).unwrap();
+    bumped(&engine);
+    engine.approve_params_update(&[1u8; 32], engine.pending_params.nonce).unwrap();
+    bumped(&engine);
+    engine.cancel_params_update(&[9u8; 32], 1).unwrap();
+    bumped(&engine);
+    engine.queue_insurance_withdrawal(1).unwrap();
+    bumped(&engine);
+    engine
+        .approve_insurance_withdrawal(&[1u8; 32], engine.pending_insurance_withdrawal.nonce)
+        .unwrap();
+    bumped(&engine);
+
+    // Nothing to cancel, nothing to bump
+    engine.cancel_margin_event(50);
+    assert_eq!(engine.state_seq, seq);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_from_accounts_bulk_load() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    let idle = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 5_000_000, 0).unwrap();
+    engine.deposit(idle, 700, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 3_000_000).unwrap();
+    let snapshot: Vec<Account> = [lp, user, idle]
+        .iter()
+        .map(|&i| engine.accounts[i as usize])
+        .collect();
+
+    let loaded = RiskEngine::from_accounts(default_params(), snapshot.clone()).unwrap();
+    assert_eq!(loaded.num_used_accounts, 3);
+    assert_eq!(loaded.c_tot, engine.c_tot);
+    assert_eq!(loaded.pnl_pos_tot, engine.pnl_pos_tot);
+    assert_eq!(loaded.total_open_interest, engine.total_open_interest);
+    assert_eq!(loaded.net_lp_pos, engine.net_lp_pos);
+    assert_eq!(loaded.accounts[1], engine.accounts[user as usize]);
+    assert_conserved(&loaded);
+
+    // Loaded accounts keep trading; new accounts get fresh IDs and free slots
+    let mut loaded = loaded;
+    loaded.execute_trade(&NoOpMatcher, 0, 1, 0, 1_000_000, -3_000_000).unwrap();
+    let next = loaded.add_user(0).unwrap();
+    assert_eq!(next, 3);
+    assert!(loaded.accounts[3].account_id > snapshot[2].account_id);
+    assert_conserved(&loaded);
+
+    // One-sided positions, duplicate IDs and unsettled funding are rejected
+    assert_eq!(
+        RiskEngine::from_accounts(default_params(), snapshot[1..].to_vec()).map(|_| ()),
+        Err(RiskError::PositionSizeMismatch)
+    );
+    let mut dup = snapshot.clone();
+    dup[2].account_id = dup[0].account_id;
+    assert_eq!(
+        RiskEngine::from_accounts(default_params(), dup).map(|_| ()),
+        Err(RiskError::InvalidParams)
+    );
+    let mut unsettled = snapshot.clone();
+    unsettled[2].funding_index = I128::new(5);
+    assert_eq!(
+        RiskEngine::from_accounts(default_params(), unsettled).map(|_| ()),
+        Err(RiskError::InvalidParams)
+    );
+}
+
+#[test]
+fn test_gap_insurance_compensates_reverted_gap_liquidation() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        gap_levy_share_bps: 10_000,
+        oracle_gap_bps: 1_000,
+        gap_revert_slots: 10,
+        gap_revert_tolerance_bps: 100,
+        ..engine.ext_params
+    };
+    engine.set_ext_params(ext).unwrap();
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    let late = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 400_000, 0).unwrap();
+    engine.deposit(late, 400_000, 0).unwrap();
+    set_insurance(&mut engine, 2_000_000);
+    engine.keeper_crank(u16::MAX, 1, 1_000_000, 0, false, 0, 0).unwrap();
+
+    // The whole insurance cut of the fee is levied into the pool
+    let fees_before = engine.insurance_fund.fee_revenue.get();
+    engine.execute_trade(&NoOpMatcher, lp, user, 1, 1_000_000, 3_000_000).unwrap();
+    let levy = engine.insurance_fund.fee_revenue.get() - fees_before;
+    assert!(levy > 0);
+    assert_eq!(engine.gap_insurance().pool.get(), levy);
+    engine.gap_insurance.pool = U128::new(1_000_000);
+
+    // A 10% single-print drop liquidates a position that was healthy before it
+    engine.keeper_crank(u16::MAX, 2, 900_000, 0, false, 0, 0).unwrap();
+    let claim = engine.gap_insurance().claims[0];
+    assert_eq!(claim.idx, user);
+    assert!(claim.loss.get() > 0);
+    assert_eq!(engine.gap_insurance().pre_gap_price, 1_000_000);
+
+    // The print reverts within the window: the claim is paid from the pool
+    let capital = engine.accounts[user as usize].capital.get();
+    let insurance = engine.insurance_fund.balance.get();
+    let last = engine.drain_events(0, &mut CollectingObserver::default());
+    engine.keeper_crank(u16::MAX, 5, 995_000, 0, false, 0, 0).unwrap();
+    let paid = claim.loss.get();
+    assert_eq!(engine.accounts[user as usize].capital.get(), capital + paid);
+    assert_eq!(engine.insurance_fund.balance.get(), insurance - paid);
+    assert_eq!(engine.gap_insurance().pool.get(), 1_000_000 - paid);
+    assert_eq!(engine.gap_insurance().total_paid.get(), paid);
+    assert_eq!(engine.gap_insurance().pre_gap_price, 0);
+    assert!(engine.gap_insurance().claims.iter().all(|c| c.loss.is_zero()));
+    let mut obs = CollectingObserver::default();
+    engine.drain_events(last, &mut obs);
+    let event = obs.events.iter().find(|e| e.kind == EventKind::GapCompensation).unwrap();
+    assert_eq!((event.account, event.amount.get()), (user, paid));
+
+    // A gap that does not revert in time lapses without compensation
+    engine.execute_trade(&NoOpMatcher, lp, late, 6, 995_000, 3_000_000).unwrap();
+    engine.keeper_crank(u16::MAX, 7, 880_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.gap_insurance().claims[0].idx, late);
+    let pool = engine.gap_insurance().pool.get();
+    engine.keeper_crank(u16::MAX, 20, 880_000, 0, false, 0, 0).unwrap();
+    engine.keeper_crank(u16::MAX, 21, 995_000, 0, false, 0, 0).unwrap();
+    assert!(engine.gap_insurance().claims.iter().all(|c| c.loss.is_zero()));
+    assert_eq!(engine.gap_insurance().total_paid.get(), paid);
+    assert!(engine.gap_insurance().pool.get() >= pool);
+}
+
+#[test]
+fn test_force_close_grace_period() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        force_close_grace_slots: 50,
+        ..engine.ext_params
+    };
+    engine.set_ext_params(ext).unwrap();
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 10_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 1, 1_000_000, 5_000_000).unwrap();
+    let last = engine.drain_events(0, &mut CollectingObserver::default());
+
+    // Over the cap: notice is given instead of an immediate close
+    let outcome = engine.keeper_crank(u16::MAX, 10, 1_300_000, 0, false, 1_000_000, 0).unwrap();
+    assert_eq!(outcome.max_pnl_closed, 0);
+    assert_eq!(engine.accounts[user as usize].position_size.get(), 5_000_000);
+    assert_eq!(engine.force_close_pending(user), Some(60));
+    let mut obs = CollectingObserver::default();
+    engine.drain_events(last, &mut obs);
+    let notice = obs.events.iter().find(|e| e.kind == EventKind::ForceClosePending).unwrap();
+    assert_eq!((notice.account, notice.amount.get()), (user, 60));
+
+    // Still inside the grace period
+    let outcome = engine.keeper_crank(u16::MAX, 30, 1_300_000, 0, false, 1_000_000, 0).unwrap();
+    assert_eq!(outcome.max_pnl_closed, 0);
+    assert_eq!(engine.force_close_pending(user), Some(60));
+
+    // Back under the cap: the notice is withdrawn
+    engine.keeper_crank(u16::MAX, 40, 1_100_000, 0, false, 1_000_000, 0).unwrap();
+    assert_eq!(engine.force_close_pending(user), None);
+    let mut obs = CollectingObserver::default();
+    engine.drain_events(last, &mut obs);
+    assert!(obs.events.iter().any(|e| e.kind == EventKind::ForceCloseCancelled));
+
+    // A fresh breach restarts the grace period, after which the crank closes
+    engine.keeper_crank(u16::MAX, 50, 1_300_000, 0, false, 1_000_000, 0).unwrap();
+    assert_eq!(engine.force_close_pending(user), Some(100));
+    let outcome = engine.keeper_crank(u16::MAX, 100, 1_300_000, 0, false, 1_000_000, 0).unwrap();
+    assert_eq!(outcome.max_pnl_closed, 1);
+    assert!(engine.accounts[user as usize].position_size.is_zero());
+    assert_eq!(engine.force_close_pending(user), None);
+}
+
+#[test]
+fn test_reduce_only_mode() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 5_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 3_000_000).unwrap();
+
+    // Self-imposed: growing or flipping the position is refused, reducing is fine
+    engine.set_reduce_only(user, true).unwrap();
+    assert!(engine.is_reduce_only(user));
+    assert_eq!(engine.account_view(user).unwrap().reduce_only, REDUCE_ONLY_SELF);
+    let rejection = engine
+        .execute_trade_detailed(&NoOpMatcher, lp, user, 0, 1_000_000, 1_000_000)
+        .unwrap_err();
+    assert_eq!(rejection.error, RiskError::ReduceOnly);
+    assert_eq!(rejection.reason, TradeRejectReason::ReduceOnly { account: user });
+    assert_eq!(
+        engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, -4_000_000),
+        Err(RiskError::ReduceOnly)
+    );
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, -1_000_000).unwrap();
+    assert_eq!(engine.accounts[user as usize].position_size.get(), 2_000_000);
+
+    // An admin hold outlives the account lifting its own flag
+    engine.set_reduce_only_by_admin(user, true).unwrap();
+    engine.set_reduce_only(user, false).unwrap();
+    assert_eq!(engine.accounts[user as usize].reduce_only, REDUCE_ONLY_ADMIN);
+    assert_eq!(
+        engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 1),
+        Err(RiskError::ReduceOnly)
+    );
+    engine.set_reduce_only_by_admin(user, false).unwrap();
+    assert!(!engine.is_reduce_only(user));
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 1_000_000).unwrap();
+
+    // The LP side is checked too
+    engine.set_reduce_only_by_admin(lp, true).unwrap();
+    assert_eq!(
+        engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 1_000_000),
+        Err(RiskError::ReduceOnly)
+    );
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, -3_000_000).unwrap();
+    assert!(engine.accounts[lp as usize].position_size.is_zero());
+    assert_eq!(engine.set_reduce_only(99, true), Err(RiskError::AccountNotFound));
+}
+
+#[test]
+fn test_health_check_flags_anomalies() {
+    // Healthy params cannot allow more accounts than the slab holds
+    let mut params = default_params();
+    params.max_accounts = MAX_ACCOUNTS as u64;
+    let mut engine = Box::new(RiskEngine::new(params));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 5_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 3_000_000).unwrap();
+    engine.keeper_crank(u16::MAX, 1, 1_050_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.health_check(), 0);
+
+    let mut broken = engine.clone();
+    broken.c_tot = broken.c_tot.saturating_add(1);
+    broken.vault = broken.vault.saturating_sub(1_000_000_000);
+    assert_eq!(broken.health_check(), HEALTH_CAPITAL_TOTAL | HEALTH_VAULT_SHORTFALL);
+
+    let mut broken = engine.clone();
+    broken.accounts[user as usize].entry_price = 0;
+    broken.net_lp_pos = I128::ZERO;
+    assert_eq!(broken.health_check(), HEALTH_ACCOUNT_STATE | HEALTH_LP_AGGREGATES);
+
+    let mut broken = engine.clone();
+    broken.num_used_accounts += 1;
+    broken.total_open_interest = U128::ZERO;
+    broken.params.maintenance_margin_bps = broken.params.initial_margin_bps + 1;
+    assert_eq!(
+        broken.health_check(),
+        HEALTH_ACCOUNT_COUNT | HEALTH_OPEN_INTEREST | HEALTH_PARAMS
+    );
+}
+
+#[test]
+fn test_account_export_import_roundtrip() {
+    let new_market = || {
+        let mut params = default_params();
+        params.max_accounts = MAX_ACCOUNTS as u64;
+        let mut engine = Box::new(RiskEngine::new(params));
+        let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+        engine.deposit(lp, 50_000_000, 0).unwrap();
+        (engine, lp)
+    };
+    let (mut source, source_lp) = new_market();
+    let user = source.add_user_tagged(0, [7; 32]).unwrap();
+    source.accounts[user as usize].owner = [9; 32];
+    source.deposit(user, 5_000_000, 0).unwrap();
+    source.execute_trade(&NoOpMatcher, source_lp, user, 0, 1_000_000, 3_000_000).unwrap();
+    source.set_max_leverage(user, 5).unwrap();
+    source.accounts[user as usize].haircut_claim = U128::new(2_500);
+    source.accounts[user as usize].emission_pending = U128::new(300);
+    source.accounts[user as usize].emission_claimable = U128::new(700);
+
+    // Export: the source LP inherits the position and the collateral leaves the vault
+    let vault_before = source.vault.get();
+    let export = source.export_account(user, source_lp, 10, 1_000_000, [3; 32], [4; 32]).unwrap();
+    assert!(!source.is_used(user as usize));
+    assert_eq!(source.vault.get(), vault_before - export.outflow());
+    assert!(source.accounts[source_lp as usize].position_size.is_zero());
+    assert_eq!(source.health_check(), 0);
+    assert!(source.check_conservation(1_000_000));
+    assert_eq!(export.position.get(), 3_000_000);
+    assert_eq!((export.owner, export.tag, export.max_leverage), ([9; 32], [7; 32], 5));
+    assert_eq!(export.haircut_claim.get(), 2_500);
+    assert_eq!((export.emission_pending.get(), export.emission_claimable.get()), (300, 700));
+
+    // The signed blob round-trips; the signature is the wrapper's to check
+    let mut blob = [0u8; AccountExport::SIGNED_LEN];
+    let n = export.encode_signed(&[5; 64], &mut blob).unwrap();
+    assert_eq!(n, AccountExport::SIGNED_LEN);
+    assert_eq!(AccountExport::decode_signed(&blob), Ok((export, [5; 64])));
+    assert!(AccountExport::decode_signed(&blob[..n - 1]).is_err());
+
+    // A version 1 blob in flight across the upgrade decodes without the newer fields
+    let v1_len = AccountExport::payload_len(1).unwrap();
+    let mut v1 = [0u8; AccountExport::SIGNED_LEN];
+    v1[..v1_len].copy_from_slice(&blob[..v1_len]);
+    v1[0] = 1;
+    v1[v1_len..v1_len + 64].copy_from_slice(&[5; 64]);
+    let legacy = AccountExport {
+        haircut_claim: U128::ZERO,
+        emission_pending: U128::ZERO,
+        emission_claimable: U128::ZERO,
+        ..export
+    };
+    assert_eq!(AccountExport::decode_signed(&v1[..v1_len + 64]), Ok((legacy, [5; 64])));
+
+    // Import: the target LP takes the other side at the target oracle
+    let (mut target, target_lp) = new_market();
+    target.add_user(0).unwrap();
+    let idx = target.import_account(&export, target_lp, 20, 1_010_000).unwrap();
+    let account = target.accounts[idx as usize];
+    assert_eq!(account.capital, export.capital);
+    assert_eq!(account.position_size.get(), 3_000_000);
+    assert_eq!(account.entry_price, 1_010_000);
+    assert_eq!((account.owner, account.tag, account.max_leverage), ([9; 32], [7; 32], 5));
+    assert_eq!(account.haircut_claim.get(), 2_500);
+    let points = target.emissions_of(idx, 20).unwrap();
+    assert_eq!((points.pending, points.claimable), (300, 700));
+    assert_eq!(target.accounts[target_lp as usize].position_size.get(), -3_000_000);
+    assert_eq!(target.health_check(), 0);
+    assert!(target.check_conservation(1_010_000));
+
+    // An import that could not carry its position is turned away untouched
+    let thin = AccountExport { capital: U128::new(1_000), ..export };
+    let (vault, used) = (target.vault.get(), target.num_used_accounts);
+    let lp_pos = target.accounts[target_lp as usize].position_size;
+    assert_eq!(
+        target.import_account(&thin, target_lp, 20, 1_010_000),
+        Err(RiskError::Undercollateralized)
+    );
+    assert_eq!((target.vault.get(), target.num_used_accounts), (vault, used));
+    assert_eq!(target.accounts[target_lp as usize].position_size, lp_pos);
+
+    // Positions need an LP counterparty on both ends
+    assert_eq!(
+        target.export_account(idx, idx, 30, 1_010_000, [3; 32], [4; 32]),
+        Err(RiskError::NotAnLPAccount)
+    );
+    assert_eq!(
+        target.export_account(target_lp, target_lp, 30, 1_010_000, [3; 32], [4; 32]),
+        Err(RiskError::AccountKindMismatch)
+    );
+}
+
+#[test]
+fn test_crank_liquidation_notional_budget() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let ext = ExtParams {
+        max_crank_liq_notional: U128::new(2_700_000),
+        ..engine.ext_params
+    };
+    engine.set_ext_params(ext).unwrap();
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let a = engine.add_user(0).unwrap();
+    let b = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    for user in [a, b] {
+        engine.deposit(user, 400_000, 0).unwrap();
+        engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 3_000_000).unwrap();
+    }
+
+    // Both longs are underwater at 0.88, but one crank may only close 2.7M notional
+    let first = engine.keeper_crank(u16::MAX, 1, 880_000, 0, false, 0, 0).unwrap();
+    assert!(first.liq_backlog);
+    assert!(!first.sweep_complete);
+    assert!(first.liq_notional > 0 && first.liq_notional <= 2_700_000);
+    assert!(!engine.accounts[b as usize].position_size.is_zero());
+    assert_eq!(first.last_cursor, b);
+
+    // The next crank resumes at the backlogged account and works it off
+    let position = engine.accounts[b as usize].position_size.get();
+    let second = engine.keeper_crank(u16::MAX, 2, 880_000, 0, false, 0, 0).unwrap();
+    assert!(!second.liq_backlog);
+    assert_eq!(second.num_liquidations, 1);
+    assert!(second.liq_notional <= 2_700_000);
+    assert!(engine.accounts[b as usize].position_size.get() < position);
+}
+
+#[test]
+fn test_exec_price_band_rejects_off_oracle_fills() {
+    /// Fills `offset` away from the oracle
+    struct OffsetMatcher {
+        offset: i64,
+    }
+    impl MatchingEngine for OffsetMatcher {
+        fn execute_match(
+            &self,
+            _lp_program: &[u8; 32],
+            _lp_context: &[u8; 32],
+            _lp_account_id: u64,
+            oracle_price: u64,
+            size: i128,
+        ) -> Result<TradeExecution> {
+            Ok(TradeExecution {
+                price: (oracle_price as i64 + self.offset) as u64,
+                size,
+            })
+        }
+    }
+
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 5_000_000, 0).unwrap();
+
+    let mut ext = engine.ext_params;
+    ext.exec_price_band_bps = 10_001;
+    assert_eq!(engine.set_ext_params(ext), Err(RiskError::InvalidParams));
+    ext.exec_price_band_bps = 100;
+    engine.set_ext_params(ext).unwrap();
+
+    // 1% either side of the oracle is inside the band
+    let inside = OffsetMatcher { offset: -10_000 };
+    engine.execute_trade(&inside, lp, user, 0, 1_000_000, 1_000_000).unwrap();
+    let inside = OffsetMatcher { offset: 10_000 };
+    engine.execute_trade(&inside, lp, user, 0, 1_000_000, -1_000_000).unwrap();
+
+    // Past it the fill is refused and nothing moves
+    let before = engine.accounts[user as usize];
+    let outside = OffsetMatcher { offset: -10_001 };
+    let rejection = engine
+        .execute_trade_detailed(&outside, lp, user, 0, 1_000_000, 1_000_000)
+        .unwrap_err();
+    assert_eq!(rejection.error, RiskError::InvalidMatchingEngine);
+    assert_eq!(
+        rejection.reason,
+        TradeRejectReason::PriceBand {
+            exec_price: 989_999,
+            bps: 101,
+            band_bps: 100,
+        }
+    );
+    assert_eq!(engine.accounts[user as usize], before);
+
+    // Zero disables the band
+    ext.exec_price_band_bps = 0;
+    engine.set_ext_params(ext).unwrap();
+    engine.execute_trade(&outside, lp, user, 0, 1_000_000, 1_000_000).unwrap();
+}
+
+#[test]
+fn test_lp_inventory_getter() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 5_000_000, 0).unwrap();
+
+    let flat = engine.lp_inventory(lp, 1_000_000).unwrap();
+    assert_eq!(flat.position, 0);
+    assert_eq!(flat.utilization_bps, 0);
+    assert_eq!(flat.max_notional, 500_000_000);
+    assert_eq!(flat.buy_remaining, 500_000_000);
+    assert_eq!(flat.sell_remaining, 500_000_000);
+
+    // The user buys, so the LP is short and has less room left to sell to users
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 25_000_000).unwrap();
+    let inv = engine.lp_inventory(lp, 1_000_000).unwrap();
+    assert_eq!(inv.position, -25_000_000);
+    assert_eq!(inv.notional, 25_000_000);
+    // The LP earned its share of the trading fee
+    assert_eq!(inv.max_notional, 500_125_000);
+    assert_eq!(inv.utilization_bps, 500);
+    assert_eq!(inv.buy_remaining, 475_125_000);
+    assert_eq!(inv.sell_remaining, 525_125_000);
+
+    assert_eq!(engine.lp_inventory(user, 1_000_000), Err(RiskError::NotAnLPAccount));
+    assert_eq!(engine.lp_inventory(99, 1_000_000), Err(RiskError::AccountNotFound));
+}
+
+#[test]
+fn test_smoothed_prices_per_use() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 3_000_000).unwrap();
+
+    let mut ext = engine.ext_params;
+    ext.liquidation_ema_weight_bps = 10_000;
+    assert_eq!(engine.set_ext_params(ext), Err(RiskError::InvalidParams));
+    ext.liquidation_ema_slots = 1_000;
+    ext.funding_ema_weight_bps = 10_001;
+    ext.funding_ema_slots = 100;
+    assert_eq!(engine.set_ext_params(ext), Err(RiskError::InvalidParams));
+    ext.funding_ema_weight_bps = 5_000;
+    engine.set_ext_params(ext).unwrap();
+
+    // Spot until the first crank seeds the EMAs
+    assert_eq!(engine.smoothed_price(PriceUse::Funding, 900_000), 900_000);
+    engine.keeper_crank(u16::MAX, 1, 1_000_000, 0, false, 0, 0).unwrap();
+    let unsmoothed = engine.clone();
+
+    // A 30% drop breaches maintenance at spot, but the liquidation EMA has barely
+    // moved, so the crank waits for the slower price to confirm it
+    let outcome = engine.keeper_crank(u16::MAX, 2, 700_000, 0, false, 0, 0).unwrap();
+    assert_eq!(outcome.num_liquidations, 0);
+    assert_eq!(engine.price_emas[PriceUse::Liquidation as usize].price, 999_700);
+    assert_eq!(engine.smoothed_price(PriceUse::Liquidation, 700_000), 999_700);
+    assert_eq!(engine.smoothed_price(PriceUse::Funding, 700_000), 848_500);
+    assert_eq!(engine.smoothed_price(PriceUse::MaxPnl, 700_000), 700_000);
+    assert!(!engine.accounts[user as usize].position_size.is_zero());
+
+    let mut unsmoothed = unsmoothed;
+    let mut ext = unsmoothed.ext_params;
+    ext.liquidation_ema_weight_bps = 0;
+    unsmoothed.set_ext_params(ext).unwrap();
+    let outcome = unsmoothed.keeper_crank(u16::MAX, 2, 700_000, 0, false, 0, 0).unwrap();
+    assert_eq!(outcome.num_liquidations, 1);
+}
+
+#[test]
+fn test_haircut_claim_repaid_from_insurance_surplus() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(user, 10_000, 0).unwrap();
+    let mut ext = engine.ext_params;
+    ext.haircut_claim_payout_bps = 5_000;
+    engine.set_ext_params(ext).unwrap();
+
+    // 20k of fully warmed profit against 10k of residual: a 50% haircut
+    engine.accounts[user as usize].pnl = I128::new(20_000);
+    engine.accounts[user as usize].warmup_slope_per_step = U128::new(20_000);
+    engine.pnl_pos_tot = U128::new(20_000);
+    engine.vault = engine.vault.saturating_add(10_000);
+    engine.current_slot = 1;
+    engine.settle_warmup_to_capital(user).unwrap();
+    assert_eq!(engine.accounts[user as usize].capital.get(), 20_000);
+    assert_eq!(engine.account_view(user).unwrap().haircut_claim, 10_000);
+
+    // Each crank pays half of the surplus above the insurance floor
+    set_insurance(&mut engine, 8_000);
+    let last = engine.drain_events(0, &mut CollectingObserver::default());
+    engine.keeper_crank(u16::MAX, 1, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.accounts[user as usize].capital.get(), 24_000);
+    assert_eq!(engine.accounts[user as usize].haircut_claim.get(), 6_000);
+    assert_eq!(engine.insurance_fund.balance.get(), 4_000);
+    let mut obs = CollectingObserver::default();
+    engine.drain_events(last, &mut obs);
+    let event = obs.events.iter().find(|e| e.kind == EventKind::HaircutClaimPaid).unwrap();
+    assert_eq!(event.account, user);
+    assert_eq!((event.amount.get(), event.value.get()), (4_000, 6_000));
+
+    engine.keeper_crank(u16::MAX, 2, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.accounts[user as usize].haircut_claim.get(), 4_000);
+
+    // Insurance at the risk-reduction threshold has no surplus to give
+    engine.params.risk_reduction_threshold = U128::new(2_000);
+    engine.keeper_crank(u16::MAX, 3, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.accounts[user as usize].haircut_claim.get(), 4_000);
+    assert_eq!(engine.insurance_fund.balance.get(), 2_000);
+}
+
+#[test]
+fn test_keeper_crank_phase_selection() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    let dust = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 3_000_000).unwrap();
+
+    for bad in [0, 1 << 3] {
+        assert_eq!(
+            engine.keeper_crank_phases(u16::MAX, 1, 700_000, 0, false, 0, 0, bad),
+            Err(RiskError::InvalidParams)
+        );
+    }
+
+    // Settlement alone neither liquidates, collects dust nor moves the sweep
+    let outcome = engine
+        .keeper_crank_phases(u16::MAX, 1, 700_000, 0, false, 0, 0, CRANK_PHASE_SETTLE)
+        .unwrap();
+    assert_eq!((outcome.num_liquidations, outcome.num_gc_closed), (0, 0));
+    assert!(!outcome.sweep_complete);
+    assert_eq!(outcome.last_cursor, 0);
+    assert_eq!(engine.last_full_sweep_start_slot, 0);
+    assert!(engine.is_used(dust as usize));
+
+    // The liquidation phase works the same window and completes the sweep
+    let outcome = engine
+        .keeper_crank_phases(u16::MAX, 2, 700_000, 0, false, 0, 0, CRANK_PHASE_LIQUIDATE)
+        .unwrap();
+    assert_eq!((outcome.num_liquidations, outcome.num_gc_closed), (1, 0));
+    assert!(outcome.sweep_complete);
+    assert_eq!(engine.last_full_sweep_start_slot, 2);
+    assert!(engine.is_used(dust as usize));
+
+    let outcome = engine
+        .keeper_crank_phases(u16::MAX, 3, 700_000, 0, false, 0, 0, CRANK_PHASE_GC)
+        .unwrap();
+    assert_eq!((outcome.num_liquidations, outcome.num_gc_closed), (0, 1));
+    assert!(!engine.is_used(dust as usize));
+}
+
+#[test]
+fn test_settle_crank_does_not_throttle_liquidation_in_same_slot() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 3_000_000).unwrap();
+    let ext = ExtParams {
+        min_crank_interval_slots: 10,
+        ..ExtParams::default()
+    };
+    engine.set_ext_params(ext).unwrap();
+    engine.keeper_crank(u16::MAX, 1, 1_000_000, 0, false, 0, 0).unwrap();
+
+    // Settle-only cranks run inside the gap without restarting it
+    for slot in [5, 11] {
+        let outcome = engine
+            .keeper_crank_phases(u16::MAX, slot, 700_000, 0, false, 0, 0, CRANK_PHASE_SETTLE)
+            .unwrap();
+        assert!(!outcome.skipped);
+        assert_eq!(engine.last_full_sweep_start_slot, 1);
+    }
+
+    // ...so the liquidating crank in the same slot still starts its sweep
+    let outcome = engine
+        .keeper_crank_phases(u16::MAX, 11, 700_000, 0, false, 0, 0, CRANK_PHASE_LIQUIDATE)
+        .unwrap();
+    assert!(!outcome.skipped);
+    assert_eq!(outcome.num_liquidations, 1);
+    assert_eq!(engine.last_full_sweep_start_slot, 11);
+}
+
+#[test]
+fn test_crank_outcome_reports_phase_work() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    let _dust = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 3_000_000).unwrap();
+
+    let outcome = engine.keeper_crank(u16::MAX, 1, 700_000, 0, false, 0, 0).unwrap();
+    assert_eq!(outcome.slots_scanned, MAX_ACCOUNTS as u32);
+    let visits = CrankPhaseWork { accounts: 3, budget_used: 3, budget: ACCOUNTS_PER_CRANK as u32 };
+    assert_eq!(outcome.settle_work, visits);
+    let liquidations = CrankPhaseWork {
+        accounts: 3,
+        budget_used: 1,
+        budget: LIQ_BUDGET_PER_CRANK as u32,
+    };
+    assert_eq!(outcome.liquidate_work, liquidations);
+    // LPs are never collected, so only the two users are examined
+    let gc = CrankPhaseWork { accounts: 2, budget_used: 1, budget: GC_CLOSE_BUDGET };
+    assert_eq!(outcome.gc_work, gc);
+    assert_eq!(outcome.num_gc_closed, 1);
+
+    // Phases that did not run report nothing
+    let outcome = engine
+        .keeper_crank_phases(u16::MAX, 2, 700_000, 0, false, 0, 0, CRANK_PHASE_SETTLE)
+        .unwrap();
+    assert_eq!(outcome.settle_work.accounts, 2);
+    assert_eq!(outcome.liquidate_work, CrankPhaseWork::default());
+    assert_eq!(outcome.gc_work, CrankPhaseWork::default());
+}
+
+#[test]
+fn test_price_history_twap_and_jumps() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    assert_eq!(engine.price_twap(10, 10), None);
+    for (slot, price) in [(10, 1_000_000), (20, 1_100_000), (30, 1_320_000)] {
+        engine.keeper_crank(u16::MAX, slot, price, 0, false, 0, 0).unwrap();
+    }
+    assert_eq!(engine.price_history_len(), 3);
+    assert_eq!(engine.price_point(0), Some(&PricePoint { slot: 30, price: 1_320_000 }));
+    let slots: Vec<u64> = engine.price_history().map(|p| p.slot).collect();
+    assert_eq!(slots, vec![10, 20, 30]);
+
+    // Each price holds until the next crank
+    assert_eq!(engine.price_twap(40, 20), Some(1_210_000));
+    assert_eq!(engine.price_twap(40, 1_000), Some(1_140_000));
+    assert_eq!(engine.price_twap(30, 0), Some(1_320_000));
+    assert_eq!(engine.max_price_jump_bps(40, 1_000), 2_000);
+    assert_eq!(engine.max_price_jump_bps(100, 10), 0);
+
+    // The ring keeps the latest PRICE_HISTORY_LEN prices
+    for slot in 31..31 + PRICE_HISTORY_LEN as u64 {
+        engine.keeper_crank(u16::MAX, slot, 1_000_000, 0, false, 0, 0).unwrap();
+    }
+    assert_eq!(engine.price_history_len(), PRICE_HISTORY_LEN);
+    assert_eq!(engine.price_history().next().unwrap().slot, 31);
+}
+
+#[test]
+fn test_engine_snapshot_hash_and_attestation() {
+    use percolator::snapshot::{snapshot_slot, SNAPSHOT_SIGNATURE_LEN};
+
+    fn hex(bytes: &[u8; 32]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+    assert_eq!(
+        hex(&sha256(b"")),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    assert_eq!(
+        hex(&sha256(b"abc")),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(
+        hex(&sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+        "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+    );
+
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 5_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 3_000_000).unwrap();
+
+    let mut snapshot = vec![0u8; engine.snapshot_len()];
+    let (len, hash) = engine.encode_snapshot(100, &mut snapshot).unwrap();
+    assert_eq!(len, snapshot.len());
+    assert_eq!(hash, engine.snapshot_hash(100));
+    assert!(verify_snapshot(&snapshot, &hash));
+    assert_eq!(snapshot_slot(&snapshot), Some(100));
+    assert_ne!(engine.snapshot_hash(101), hash);
+    assert!(engine.encode_snapshot(100, &mut [0u8; 64]).is_err());
+
+    // The attestation round-trips and pins both the bytes and the slot
+    let attestation = SnapshotAttestation {
+        slot: 100,
+        state_seq: engine.state_seq,
+        hash,
+        signature: [7; SNAPSHOT_SIGNATURE_LEN],
+    };
+    let mut buf = [0u8; SnapshotAttestation::ENCODED_LEN];
+    assert_eq!(attestation.encode(&mut buf).unwrap(), SnapshotAttestation::ENCODED_LEN);
+    assert_eq!(SnapshotAttestation::decode(&buf).unwrap(), attestation);
+    assert!(attestation.matches(&snapshot));
+    let mut tampered = snapshot.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(!attestation.matches(&tampered));
+
+    // Any balance change moves the hash
+    engine.deposit(user, 1, 0).unwrap();
+    assert_ne!(engine.snapshot_hash(100), hash);
+}
+
+#[test]
+fn test_emissions_accrue_per_epoch_and_claim() {
+    let mut params = default_params();
+    params.trading_fee_bps = 0;
+    let mut engine = Box::new(RiskEngine::new(params));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 1_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+    let mut ext = engine.ext_params;
+    ext.emission_epoch_slots = 100;
+    ext.emission_volume_weight_bps = 100;
+    ext.emission_oi_weight_bps = 2;
+    ext.emission_lp_weight_bps = 1;
+    engine.set_ext_params(ext).unwrap();
+
+    // The first crank visit starts the clock; LP capital earns 100 points a slot
+    engine.keeper_crank(u16::MAX, 10, 1_000_000, 0, false, 0, 0).unwrap();
+    engine.keeper_crank(u16::MAX, 50, 1_000_000, 0, false, 0, 0).unwrap();
+    let view = engine.emissions_of(lp, 50).unwrap();
+    assert_eq!((view.epoch, view.pending, view.claimable), (0, 4_000, 0));
+    assert_eq!(engine.emissions_of(user, 50).unwrap().pending, 0);
+
+    // The LP makes a 1M fill (10k volume points); both sides now hold 1M of OI
+    engine.execute_trade(&NoOpMatcher, lp, user, 50, 1_000_000, 1_000_000).unwrap();
+    assert_eq!(engine.emissions_of(lp, 50).unwrap().pending, 14_000);
+
+    // Crossing the epoch boundary at slot 100 closes out everything before it
+    let view = engine.emissions_of(lp, 150).unwrap();
+    assert_eq!((view.epoch, view.pending, view.claimable), (1, 15_000, 29_000));
+    let view = engine.emissions_of(user, 150).unwrap();
+    assert_eq!((view.pending, view.claimable), (10_000, 10_000));
+    assert_eq!(engine.total_claimable_emissions(150), 39_000);
+
+    let last = engine.drain_events(0, &mut CollectingObserver::default());
+    assert_eq!(engine.claim_emissions(lp, 150), Ok(29_000));
+    let mut obs = CollectingObserver::default();
+    engine.drain_events(last, &mut obs);
+    let event = obs.events.iter().find(|e| e.kind == EventKind::EmissionsClaimed).unwrap();
+    assert_eq!((event.account, event.amount.get()), (lp, 29_000));
+
+    let view = engine.emissions_of(lp, 150).unwrap();
+    assert_eq!((view.epoch, view.pending, view.claimable), (1, 15_000, 0));
+    assert_eq!(engine.claim_emissions(lp, 150), Ok(0));
+    assert_eq!(engine.total_claimable_emissions(150), 10_000);
+}
+
+#[test]
+fn test_risk_reducing_trades_get_fee_rebate() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let a = engine.add_user(0).unwrap();
+    let b = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(a, 5_000_000, 0).unwrap();
+    engine.deposit(b, 5_000_000, 0).unwrap();
+    let mut ext = engine.ext_params;
+    ext.risk_reducing_fee_discount_bps = 10_000;
+    engine.set_ext_params(ext).unwrap();
+
+    // Opening trades pay the full 10 bps, even when they shrink the skew
+    let receipt = engine.execute_trade(&NoOpMatcher, lp, a, 0, 1_000_000, 2_000_000).unwrap();
+    assert_eq!(receipt.fee, 2_000);
+    let receipt = engine.execute_trade(&NoOpMatcher, lp, b, 0, 1_000_000, -1_000_000).unwrap();
+    assert_eq!(receipt.fee, 1_000);
+    assert_eq!(engine.net_lp_pos.get(), -1_000_000);
+
+    // b closing part of its short grows the LP skew: no rebate
+    let receipt = engine.execute_trade(&NoOpMatcher, lp, b, 0, 1_000_000, 500_000).unwrap();
+    assert_eq!(receipt.fee, 500);
+
+    // a reducing its long also shrinks the skew: the fee is waived
+    let receipt = engine.execute_trade(&NoOpMatcher, lp, a, 0, 1_000_000, -1_000_000).unwrap();
+    assert_eq!(receipt.fee, 0);
+    assert_eq!(engine.net_lp_pos.get(), -500_000);
+
+    // A partial rebate
+    ext.risk_reducing_fee_discount_bps = 5_000;
+    engine.set_ext_params(ext).unwrap();
+    let receipt = engine.execute_trade(&NoOpMatcher, lp, a, 0, 1_000_000, -200_000).unwrap();
+    assert_eq!(receipt.fee, 100);
+
+    ext.risk_reducing_fee_discount_bps = 10_001;
+    assert_eq!(engine.set_ext_params(ext), Err(RiskError::InvalidParams));
+}
+
+#[test]
+fn test_initial_margin_tightens_around_margin_events() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+    let mut ext = engine.ext_params;
+    ext.event_margin_window_slots = 100;
+    ext.event_initial_margin_bps = 3_000;
+    engine.set_ext_params(ext).unwrap();
+    engine.schedule_margin_event(500).unwrap();
+
+    // 10% normally, ramping to 30% at the event and back down after it
+    assert_eq!(engine.event_initial_margin_bps(300), 0);
+    assert_eq!(engine.event_initial_margin_bps(450), 2_000);
+    assert_eq!(engine.event_initial_margin_bps(500), 3_000);
+    assert_eq!(engine.event_initial_margin_bps(560), 1_800);
+
+    // At the event 1M of capital supports 3M of notional, not 4M
+    engine.keeper_crank(u16::MAX, 500, 1_000_000, 0, false, 0, 0).unwrap();
+    let result = engine.execute_trade(&NoOpMatcher, lp, user, 500, 1_000_000, 4_000_000);
+    assert_eq!(result.unwrap_err(), RiskError::Undercollateralized);
+    engine.execute_trade(&NoOpMatcher, lp, user, 500, 1_000_000, 3_000_000).unwrap();
+
+    // Recurring events, e.g. funding settlements every 1000 slots
+    ext.event_period_slots = 1_000;
+    engine.set_ext_params(ext).unwrap();
+    assert_eq!(engine.event_initial_margin_bps(1_990), 2_800);
+    assert_eq!(engine.event_initial_margin_bps(2_000), 3_000);
+    assert_eq!(engine.event_initial_margin_bps(1_500), 0);
+
+    // The crank drops events once their window has passed
+    engine.keeper_crank(u16::MAX, 600, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.margin_events[0], 500);
+    engine.keeper_crank(u16::MAX, 601, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.margin_events, [0; MAX_MARGIN_EVENTS]);
+
+    // The schedule has a fixed size; cancelled events free their entry
+    for i in 1..=MAX_MARGIN_EVENTS as u64 {
+        engine.schedule_margin_event(10_000 + i).unwrap();
+    }
+    assert_eq!(engine.schedule_margin_event(20_000), Err(RiskError::InvalidParams));
+    engine.cancel_margin_event(10_001);
+    engine.schedule_margin_event(20_000).unwrap();
+    assert!(engine.margin_events.contains(&20_000));
+}
+
+#[test]
+fn test_withdraw_follows_margin_event_schedule() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 3_000_000).unwrap();
+    let mut ext = engine.ext_params;
+    ext.event_margin_window_slots = 100;
+    ext.event_initial_margin_bps = 3_000;
+    engine.set_ext_params(ext).unwrap();
+    engine.schedule_margin_event(500).unwrap();
+    engine.keeper_crank(u16::MAX, 500, 1_000_000, 0, false, 0, 0).unwrap();
+
+    // At the event 3M of notional needs 900k, not the normal 300k
+    let capital = engine.accounts[user as usize].capital.get();
+    assert_eq!(
+        engine.withdraw(user, capital - 300_000, 500, 1_000_000),
+        Err(RiskError::Undercollateralized)
+    );
+    engine.withdraw(user, capital - 900_000, 500, 1_000_000).unwrap();
+}
+
+#[test]
+fn test_adl_first_preference_unwound_first_with_fee_discount() {
+    let mut params = default_params();
+    params.risk_reduction_threshold = U128::new(1000);
+    let mut engine = Box::new(RiskEngine::new(params));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    engine.deposit(lp, 500_000, 0).unwrap();
+    let mut users = Vec::new();
+    for _ in 0..40 {
+        let user = engine.add_user(0).unwrap();
+        engine.deposit(user, 5_000, 0).unwrap();
+        users.push(user);
+    }
+    for &user in &users {
+        engine.accounts[user as usize].position_size = I128::new(10_000);
+        engine.accounts[user as usize].entry_price = 1_000_000;
+    }
+    engine.accounts[lp as usize].position_size = I128::new(-400_000);
+    engine.accounts[lp as usize].entry_price = 1_000_000;
+    engine.recompute_aggregates();
+
+    let last = *users.last().unwrap();
+    engine.set_adl_first(last, true).unwrap();
+    assert!(engine.is_adl_first(last));
+    assert_eq!(engine.account_view(last).unwrap().adl_first, 1);
+
+    // Insurance at the threshold: the opted-in account goes first, ahead of the
+    // cursor sweep that spends the rest of the force-realize budget
+    set_insurance(&mut engine, 1000);
+    assert_eq!(engine.set_adl_first(last, false), Err(RiskError::Unauthorized));
+    let outcome = engine.keeper_crank(u16::MAX, 1, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(outcome.force_realize_closed, FORCE_REALIZE_BUDGET_PER_CRANK);
+    assert!(engine.accounts[last as usize].position_size.is_zero());
+    assert!(!engine.accounts[users[38] as usize].position_size.is_zero());
+
+    // Opted-in accounts pay less to trade
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 5_000_000, 0).unwrap();
+    let mut ext = engine.ext_params;
+    ext.adl_first_fee_discount_bps = 2_000;
+    engine.set_ext_params(ext).unwrap();
+    let receipt = engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 1_000_000).unwrap();
+    assert_eq!(receipt.fee, 1_000);
+    engine.set_adl_first(user, true).unwrap();
+    let receipt = engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 1_000_000).unwrap();
+    assert_eq!(receipt.fee, 800);
+}
+
+#[test]
+fn test_bulk_deposit_and_withdraw() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let subs: Vec<u16> = (0..3).map(|_| engine.add_user(0).unwrap()).collect();
+    let deposits: Vec<BulkTransfer> = subs
+        .iter()
+        .zip([1_000, 2_000, 3_000])
+        .map(|(&idx, amount)| BulkTransfer { idx, amount })
+        .collect();
+
+    // One state update for the whole batch, one event per entry
+    let seq = engine.state_seq;
+    let vault = engine.vault.get();
+    let last = engine.drain_events(0, &mut CollectingObserver::default());
+    assert_eq!(engine.deposit_many(&deposits, 0), Ok(6_000));
+    assert_eq!(engine.state_seq, seq + 1);
+    assert_eq!(engine.vault.get(), vault + 6_000);
+    assert_eq!(engine.accounts[subs[2] as usize].capital.get(), 3_000);
+    let mut obs = CollectingObserver::default();
+    engine.drain_events(last, &mut obs);
+    assert_eq!(obs.events.iter().filter(|e| e.kind == EventKind::Deposit).count(), 3);
+
+    let withdrawals = [
+        BulkTransfer { idx: subs[0], amount: 500 },
+        BulkTransfer { idx: subs[1], amount: 2_000 },
+    ];
+    assert_eq!(engine.withdraw_many(&withdrawals, 0, 1_000_000), Ok(2_500));
+    assert_eq!(engine.state_seq, seq + 2);
+    assert_eq!(engine.accounts[subs[0] as usize].capital.get(), 500);
+    assert_eq!(engine.accounts[subs[1] as usize].capital.get(), 0);
+    assert_eq!(engine.vault.get(), vault + 3_500);
+
+    // A bad entry fails the whole call (the transaction reverts the rest)
+    let bad = [BulkTransfer { idx: subs[2], amount: 1 }, BulkTransfer { idx: 999, amount: 1 }];
+    assert_eq!(engine.deposit_many(&bad, 0), Err(RiskError::AccountNotFound));
+    let too_much = [BulkTransfer { idx: subs[2], amount: 5_000 }];
+    assert_eq!(
+        engine.withdraw_many(&too_much, 0, 1_000_000),
+        Err(RiskError::InsufficientBalance)
+    );
+    assert_eq!(engine.state_seq, seq + 2);
+}
+
+#[test]
+fn test_lp_credit_line_margin_interest_and_expiry() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 1_000_000, 0).unwrap();
+    engine.deposit(user, 5_000_000, 0).unwrap();
+    // Credit is only extended while insurance is above the risk-reduction threshold
+    set_insurance(&mut engine, 100_000);
+    assert_eq!(
+        engine.grant_credit_line(user, 500_000, 1_000, 1_000),
+        Err(RiskError::NotAnLPAccount)
+    );
+
+    // 1M of LP capital cannot back 12M of notional at 10% initial margin...
+    let result = engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 12_000_000);
+    assert_eq!(result.unwrap_err(), RiskError::Undercollateralized);
+
+    // ...but can with a 500k credit line, drawing the 194k shortfall
+    assert_eq!(engine.grant_credit_line(lp, 500_000, 1_000, 1_000), Err(RiskError::InvalidParams));
+    let mut ext = engine.ext_params;
+    ext.max_credit_limit = U128::new(500_000);
+    engine.set_ext_params(ext).unwrap();
+    assert_eq!(engine.grant_credit_line(lp, 500_001, 1_000, 1_000), Err(RiskError::InvalidParams));
+    engine.grant_credit_line(lp, 500_000, 1_000, 1_000).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 12_000_000).unwrap();
+    let capital = engine.accounts[lp as usize].capital.get();
+    assert_eq!(capital, 1_006_000);
+    assert_eq!(engine.credit_drawn(&engine.accounts[lp as usize], 1_000_000), 194_000);
+
+    // Borrowed margin cannot be withdrawn
+    assert_eq!(
+        engine.withdraw(lp, 100_000, 0, 1_000_000),
+        Err(RiskError::Undercollateralized)
+    );
+
+    // Interest on the drawn amount accrues per slot and is charged by the crank
+    engine.keeper_crank(u16::MAX, 100, 1_000_000, 0, false, 0, 0).unwrap();
+    assert_eq!(engine.revenue.credit_interest.get(), 20);
+    assert_eq!(engine.accounts[lp as usize].capital.get(), capital - 20);
+
+    // Expiry revokes the line; the LP is still above maintenance on its own
+    let last = engine.drain_events(0, &mut CollectingObserver::default());
+    engine.keeper_crank(u16::MAX, 1_000, 1_000_000, 0, false, 0, 0).unwrap();
+    assert!(engine.accounts[lp as usize].credit_limit.is_zero());
+    assert_eq!(engine.credit_available(&engine.accounts[lp as usize]), 0);
+    assert_eq!(engine.accounts[lp as usize].position_size.get(), -12_000_000);
+    let mut obs = CollectingObserver::default();
+    engine.drain_events(last, &mut obs);
+    let event = obs.events.iter().find(|e| e.kind == EventKind::CreditLineRevoked).unwrap();
+    assert_eq!((event.account, event.amount.get()), (lp, 500_000));
+}
+
+#[test]
+fn test_revoke_credit_line_charges_accrued_interest() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 1_000_000, 0).unwrap();
+    engine.deposit(user, 5_000_000, 0).unwrap();
+    set_insurance(&mut engine, 100_000);
+    let mut ext = engine.ext_params;
+    ext.max_credit_limit = U128::new(500_000);
+    engine.set_ext_params(ext).unwrap();
+    engine.grant_credit_line(lp, 500_000, 1_000, 1_000).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 12_000_000).unwrap();
+    assert_eq!(engine.revoke_credit_line(user, 100, 1_000_000), Err(RiskError::NotAnLPAccount));
+
+    // Interest on the 194k drawn over 100 slots is charged before the line goes
+    let capital = engine.accounts[lp as usize].capital.get();
+    engine.revoke_credit_line(lp, 100, 1_000_000).unwrap();
+    assert_eq!(engine.revenue.credit_interest.get(), 20);
+    assert_eq!(engine.accounts[lp as usize].capital.get(), capital - 20);
+    assert!(engine.accounts[lp as usize].credit_limit.is_zero());
+    assert!(engine.check_conservation(1_000_000));
+
+    // Granting needs the council's approval once one is active
+    engine.set_admin_council(&[], &[[5; 32]], 1).unwrap();
+    assert_eq!(
+        engine.grant_credit_line(lp, 500_000, 1_000, 1_000),
+        Err(RiskError::ApprovalsMissing)
+    );
+}
+
+#[test]
+fn test_trade_size_fee_curve() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 5_000_000, 0).unwrap();
+    assert_eq!(engine.ext_params.fee_curve_bps(1_000), None);
+
+    let point = |notional: u128, fee_bps: u64| FeeCurvePoint {
+        notional: U128::new(notional),
+        fee_bps,
+    };
+    let mut ext = engine.ext_params;
+    ext.fee_curve = [
+        point(10_000, 2),
+        point(1_000_000, 10),
+        point(10_000_000, 30),
+        FeeCurvePoint::default(),
+    ];
+    engine.set_ext_params(ext).unwrap();
+
+    // Flat below the first point and past the last, linear in between
+    assert_eq!(engine.ext_params.fee_curve_bps(5_000), Some(2));
+    assert_eq!(engine.ext_params.fee_curve_bps(505_000), Some(6));
+    assert_eq!(engine.ext_params.fee_curve_bps(20_000_000), Some(30));
+
+    // A 5.5M trade pays 20 bps instead of the flat 10
+    let receipt = engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 5_500_000).unwrap();
+    assert_eq!(receipt.fee, 11_000);
+    let receipt = engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 10_000).unwrap();
+    assert_eq!(receipt.fee, 2);
+
+    // Points must increase in notional, with unused points at the end
+    let mut bad = ext;
+    bad.fee_curve.swap(0, 1);
+    assert_eq!(engine.set_ext_params(bad), Err(RiskError::InvalidParams));
+    let mut bad = ext;
+    bad.fee_curve.swap(2, 3);
+    assert_eq!(engine.set_ext_params(bad), Err(RiskError::InvalidParams));
+}
+
+#[test]
+fn test_oracle_rollback_rejected() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    engine.set_ext_params(oracle_ext_params()).unwrap();
+    set_insurance(&mut engine, 1_000_000);
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 5_000_000, 0).unwrap();
+
+    engine
+        .keeper_crank_with_oracles(user, 12, &quote(1, 1_000_000, 1_000, 10), None, 0, false, 0, 0)
+        .unwrap();
+    assert_eq!(engine.last_oracle_publish_slot, 10);
+
+    // An older quote, though still fresh enough, cannot replace the settled one
+    let older = quote(1, 900_000, 1_000, 8);
+    assert_eq!(
+        engine.keeper_crank_with_oracles(user, 14, &older, None, 0, false, 0, 0),
+        Err(RiskError::OracleRollback)
+    );
+    assert_eq!(engine.last_crank_slot, 12);
+    assert_eq!(
+        engine.execute_trade_with_oracles(&NoOpMatcher, lp, user, 14, &older, None, 1_000),
+        Err(RiskError::OracleRollback)
+    );
+    assert_eq!(engine.accounts[user as usize].position_size.get(), 0);
+
+    // The same or a newer quote is accepted, and trades advance the watermark too
+    let newer = quote(1, 1_001_000, 1_000, 13);
+    engine.execute_trade_with_oracles(&NoOpMatcher, lp, user, 14, &newer, None, 1_000).unwrap();
+    assert_eq!(engine.last_oracle_publish_slot, 13);
+    assert_eq!(engine.last_oracle_price, 1_001_000);
+    let replay = quote(1, 1_000_000, 1_000, 10);
+    assert_eq!(
+        engine.keeper_crank_with_oracles(user, 15, &replay, None, 0, false, 0, 0),
+        Err(RiskError::OracleRollback)
+    );
+    engine.keeper_crank_with_oracles(user, 15, &newer, None, 0, false, 0, 0).unwrap();
+    assert_eq!(RiskError::OracleRollback.name(), "OracleRollback");
+
+    // A rejected trade leaves the accepted quote as it was
+    let latest = quote(1, 1_002_000, 1_000, 15);
+    assert_eq!(
+        engine.execute_trade_with_oracles(&NoOpMatcher, lp, user, 16, &latest, None, 100_000_000),
+        Err(RiskError::Undercollateralized)
+    );
+    assert_eq!(engine.last_oracle_publish_slot, 13);
+    assert_eq!(engine.last_oracle_price, 1_001_000);
+}
+
+#[test]
+fn test_conditional_order_margin_reservation() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+
+    // Without a configured rate the registration reserves nothing
+    engine.set_conditional_notional(user, 5_000_000).unwrap();
+    assert_eq!(engine.conditional_margin(&engine.accounts[user as usize]), 0);
+    assert_eq!(engine.set_conditional_notional(99, 1), Err(RiskError::AccountNotFound));
+
+    let mut ext = engine.ext_params;
+    ext.conditional_margin_bps = 1_000;
+    engine.set_ext_params(ext).unwrap();
+    assert_eq!(engine.conditional_margin(&engine.accounts[user as usize]), 500_000);
+
+    // Withdrawals must leave the reservation in the account
+    assert_eq!(
+        engine.withdraw(user, 600_000, 0, 1_000_000),
+        Err(RiskError::Undercollateralized)
+    );
+    engine.withdraw(user, 500_000, 0, 1_000_000).unwrap();
+
+    // A risk-increasing trade needs initial margin on top of the reservation
+    assert_eq!(
+        engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 4_000_000),
+        Err(RiskError::Undercollateralized)
+    );
+
+    // Once the order program releases the orders the margin is free again
+    engine.set_conditional_notional(user, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 4_000_000).unwrap();
+    assert_eq!(engine.accounts[user as usize].position_size.get(), 4_000_000);
+}
+
+#[test]
+fn test_read_replica_matches_engine_margin() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    let flat = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+    engine.deposit(flat, 10, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 8_000_000).unwrap();
+    engine.ext_params.max_credit_limit = U128::new(1_000);
+    engine.grant_credit_line(lp, 1_000, 0, 500).unwrap();
+
+    let mut buf = vec![0u8; engine.read_replica_len()];
+    assert_eq!(engine.encode_read_replica(&mut buf).unwrap(), buf.len());
+    let view = RiskEngineView::from_bytes(&buf).unwrap();
+    assert_eq!(view.state_seq, engine.state_seq);
+    assert_eq!(view.num_accounts, 3);
+    assert_eq!(view.accounts().map(|a| a.idx).collect::<Vec<_>>(), vec![lp, user, flat]);
+    assert!(view.account(99).is_none());
+
+    let lp_view = view.account(lp).unwrap();
+    assert_eq!(lp_view.kind, AccountKind::LP);
+    assert_eq!(lp_view.credit, 1_000);
+    for idx in [lp, user, flat] {
+        let replica = view.account(idx).unwrap();
+        let account = &engine.accounts[idx as usize];
+        assert_eq!(replica.position, account.position_size.get());
+        for price in [800_000, 900_000, 930_000, 1_000_000, 1_200_000] {
+            assert_eq!(
+                view.equity_mtm(&replica, price),
+                engine.account_equity_mtm_at_oracle(account, price)
+            );
+            assert_eq!(
+                view.margin_ratio_bps(&replica, price),
+                engine.margin_ratio_bps(account, price)
+            );
+            assert_eq!(
+                view.is_above_maintenance_margin(&replica, price),
+                engine.is_above_maintenance_margin_mtm(account, price)
+            );
+        }
+    }
+    // The replica crosses maintenance where the engine does
+    let replica = view.account(user).unwrap();
+    assert!(view.is_above_maintenance_margin(&replica, 1_000_000));
+    assert!(!view.is_above_maintenance_margin(&replica, 900_000));
+
+    // Truncated or foreign bytes are rejected
+    assert_eq!(
+        RiskEngineView::from_bytes(&buf[..buf.len() - 1]),
+        Err(RiskError::InvalidParams)
+    );
+    buf[0] = 0;
+    assert_eq!(RiskEngineView::from_bytes(&buf), Err(RiskError::InvalidParams));
+}
+
+#[test]
+fn test_read_replica_initial_margin_follows_event_schedule() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 1_000_000, 0).unwrap();
+    engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 3_000_000).unwrap();
+    let mut ext = engine.ext_params;
+    ext.event_margin_window_slots = 100;
+    ext.event_initial_margin_bps = 3_000;
+    ext.conditional_margin_bps = 1_000;
+    engine.set_ext_params(ext).unwrap();
+    engine.schedule_margin_event(500).unwrap();
+    engine.set_max_leverage(user, 5).unwrap();
+    engine.set_conditional_notional(user, 2_000_000).unwrap();
+
+    let mut buf = vec![0u8; engine.read_replica_len()];
+    engine.encode_read_replica(&mut buf).unwrap();
+    let view = RiskEngineView::from_bytes(&buf).unwrap();
+    let replica = view.account(user).unwrap();
+    assert_eq!(replica.conditional_margin, 200_000);
+
+    // The tier is resolved at the queried slot, not frozen at the encoding slot
+    for slot in [0, 300, 450, 500, 560] {
+        engine.current_slot = slot;
+        let account = &engine.accounts[user as usize];
+        assert_eq!(view.initial_margin_bps(&replica, slot), engine.initial_margin_bps_for(account));
+    }
+    assert_eq!(view.initial_margin_bps(&replica, 0), 2_000);
+    assert_eq!(view.initial_margin_bps(&replica, 500), 3_000);
+
+    // 600k of margin plus the 200k reservation fits now; 900k plus 200k at the event does not
+    assert!(view.is_above_initial_margin(&replica, 1_000_000, 0));
+    assert!(!view.is_above_initial_margin(&replica, 1_000_000, 500));
+}
+
+#[test]
+fn test_account_activity_keeps_last_significant_events() {
+    let mut engine = Box::new(RiskEngine::new(default_params()));
+    let lp = engine.add_lp([1; 32], [2; 32], 0).unwrap();
+    let user = engine.add_user(0).unwrap();
+    engine.deposit(lp, 50_000_000, 0).unwrap();
+    engine.deposit(user, 5_000_000, 0).unwrap();
+    let mut ext = engine.ext_params;
+    ext.large_withdrawal_threshold = U128::new(100_000);
+    engine.set_ext_params(ext).unwrap();
+
+    for _ in 0..3 {
+        engine.execute_trade(&NoOpMatcher, lp, user, 0, 1_000_000, 1_000_000).unwrap();
+    }
+    // Only withdrawals at the threshold or above are kept
+    engine.withdraw(user, 10_000, 0, 1_000_000).unwrap();
+    engine.withdraw(user, 200_000, 0, 1_000_000).unwrap();
+
+    // Over the max-PnL cap: force-closed, evicting the oldest trade
+    engine.keeper_crank(u16::MAX, 1, 1_200_000, 0, false, 1, 0).unwrap();
+    assert!(engine.accounts[user as usize].position_size.is_zero());
+
+    let activity = engine.account_activity(user).unwrap();
+    let kinds: Vec<EventKind> = activity.iter().map(|r| r.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![EventKind::ForceClosed, EventKind::Withdraw, EventKind::Trade, EventKind::Trade]
+    );
+    assert_eq!((activity[0].amount.get(), activity[0].slot), (3_000_000, 1));
+    assert_eq!(activity[1].amount.get(), 200_000);
+    assert!(activity.windows(2).all(|w| w[0].seq > w[1].seq));
+    // Entries point at the full records of the event stream
+    let event = engine.event(activity[0].seq).unwrap();
+    assert_eq!((event.kind, event.account, event.price), (EventKind::ForceClosed, user, 1_200_000));
+
+    // The LP sees its side of each trade; unused entries come last
+    let lp_activity = engine.account_activity(lp).unwrap();
+    assert!(lp_activity[..3]
+        .iter()
+        .all(|r| r.kind == EventKind::Trade && r.amount.get() == -1_000_000));
+    assert_eq!(lp_activity[3].seq, 0);
+    assert_eq!(engine.account_activity(99), Err(RiskError::AccountNotFound));
+}
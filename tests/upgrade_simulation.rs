@@ -1,33 +1,22 @@
 //! Upgrade simulation tests — validates all new features before deployment
-//! Run with: RUST_MIN_STACK=16777216 cargo test --test upgrade_simulation
+//! Run with: RUST_MIN_STACK=16777216 cargo test --features std --test upgrade_simulation
 
-use percolator::*;
+#![cfg(feature = "std")]
+
+use percolator::scenario::*;
 
-const MATCHER: NoOpMatcher = NoOpMatcher;
 const ORACLE_1M: u64 = 1_000_000; // 1.0 in e6
 const ORACLE_1_3M: u64 = 1_300_000; // 1.3 in e6 (+30%)
 const ORACLE_800K: u64 = 800_000; // 0.8 in e6 (-20%)
 
-fn default_params() -> RiskParams {
-    RiskParams {
-        warmup_period_slots: 0, // Instant PnL for simulation
-        maintenance_margin_bps: 500,  // 5%
-        initial_margin_bps: 1000,     // 10%
-        trading_fee_bps: 10,          // 0.1%
-        max_accounts: 1000,
-        new_account_fee: U128::new(0),
-        risk_reduction_threshold: U128::new(0),
-        maintenance_fee_per_slot: U128::new(0),
-        max_crank_staleness_slots: u64::MAX,
-        liquidation_fee_bps: 50,
-        liquidation_fee_cap: U128::new(100_000_000),
-        liquidation_buffer_bps: 100,
-        min_liquidation_abs: U128::new(100),
-    }
-}
-
-fn setup_market() -> Box<RiskEngine> {
-    Box::new(RiskEngine::new(default_params()))
+/// Market at price 1.0 with an LP and one trader, the trader opening `size` at slot 1.
+fn setup_market(lp_capital: u128, trader_capital: u128, size: i128) -> (Market, Actor, Actor) {
+    let mut market = MarketBuilder::new().price(ORACLE_1M).build();
+    let lp = market.add_lp(lp_capital);
+    let trader = market.add_user(trader_capital);
+    market.advance(1);
+    market.trade(lp, trader, size).unwrap();
+    (market, lp, trader)
 }
 
 // =============================================================================
@@ -36,24 +25,10 @@ fn setup_market() -> Box<RiskEngine> {
 
 #[test]
 fn test_max_pnl_force_close_profitable_trader() {
-    let mut engine = setup_market();
-
-    // Add LP and trader
-    let lp_idx = engine.add_lp([0; 32], [0; 32], 0).unwrap();
-    let user_idx = engine.add_user(0).unwrap();
-
-    // LP deposits 10M units (= 10 SOL at price 1.0)
-    engine.deposit(lp_idx, 10_000_000, 0).unwrap();
-    // Trader deposits 1M units (= 1 SOL)
-    engine.deposit(user_idx, 1_000_000, 0).unwrap();
-
-    // Trader opens long: +5M units at price 1.0 (5x leverage)
-    engine
-        .execute_trade(&MATCHER, lp_idx, user_idx, 1, ORACLE_1M, 5_000_000)
-        .unwrap();
-
-    let pos_before = engine.accounts[user_idx as usize].position_size.get();
-    assert_eq!(pos_before, 5_000_000);
+    // LP deposits 10M units (= 10 SOL at price 1.0), trader 1M (= 1 SOL) and
+    // opens long: +5M units at price 1.0 (5x leverage)
+    let (mut market, _lp, trader) = setup_market(10_000_000, 1_000_000, 5_000_000);
+    market.assert_position(trader, 5_000_000);
 
     // Price pumps +30% -> trader PnL = 5M * 0.3 / 1.0 = 1.5M units
     // Now max_pnl is absolute (pre-computed by wrapper as lp_capital * bps / 10000)
@@ -61,53 +36,40 @@ fn test_max_pnl_force_close_profitable_trader() {
     // PnL ~1.5M < 2M cap -> NOT force-closed yet
 
     // Run crank with max_pnl = 2M absolute (= LP 10M * 20%)
-    let outcome = engine
-        .keeper_crank(u16::MAX, 10, ORACLE_1_3M, 0, false, 2_000_000, 0)
-        .unwrap();
-
-    let pos_after = engine.accounts[user_idx as usize].position_size.get();
+    market.clock.set(10);
+    market.set_price(ORACLE_1_3M);
+    let outcome = market.crank_with(0, 2_000_000, 0).unwrap();
 
     // 1.5M < 2M -> should NOT be closed
-    assert_eq!(pos_after, 5_000_000, "Position should still be open (PnL below cap)");
+    market.assert_position(trader, 5_000_000);
     assert_eq!(outcome.max_pnl_closed, 0);
 
     println!("[Scenario 1a] PnL below cap: position kept open. max_pnl_closed={}", outcome.max_pnl_closed);
 
     // Now set a tighter cap: 1M absolute (= LP 10M * 10%)
     // PnL ~1.5M > 1M -> SHOULD force close
-    let outcome2 = engine
-        .keeper_crank(u16::MAX, 20, ORACLE_1_3M, 0, false, 1_000_000, 0)
-        .unwrap();
+    market.clock.set(20);
+    let outcome2 = market.crank_with(0, 1_000_000, 0).unwrap();
 
-    let pos_after2 = engine.accounts[user_idx as usize].position_size.get();
-    assert_eq!(pos_after2, 0, "Position should be force-closed (PnL exceeded cap)");
+    market.assert_flat(trader);
     assert!(outcome2.max_pnl_closed >= 1, "max_pnl_closed should be >= 1");
+    market.assert_conserved();
 
     println!("[Scenario 1b] PnL above cap: position force-closed! max_pnl_closed={}", outcome2.max_pnl_closed);
 }
 
 #[test]
 fn test_max_pnl_does_not_close_lp() {
-    let mut engine = setup_market();
-
-    let lp_idx = engine.add_lp([0; 32], [0; 32], 0).unwrap();
-    let user_idx = engine.add_user(0).unwrap();
-
-    engine.deposit(lp_idx, 10_000_000, 0).unwrap();
-    engine.deposit(user_idx, 1_000_000, 0).unwrap();
-
     // Trade: user goes long -> LP goes short
-    engine
-        .execute_trade(&MATCHER, lp_idx, user_idx, 1, ORACLE_1M, 5_000_000)
-        .unwrap();
+    let (mut market, lp, _trader) = setup_market(10_000_000, 1_000_000, 5_000_000);
 
     // Price drops -> LP profits (LP is short)
     // LP PnL could exceed cap, but LP should NOT be force-closed
-    let outcome = engine
-        .keeper_crank(u16::MAX, 10, ORACLE_800K, 0, false, 500_000, 0) // LP 10M * 5% = 500K absolute cap
-        .unwrap();
+    market.clock.set(10);
+    market.set_price(ORACLE_800K);
+    let outcome = market.crank_with(0, 500_000, 0).unwrap(); // LP 10M * 5% = 500K absolute cap
 
-    let lp_pos = engine.accounts[lp_idx as usize].position_size.get();
+    let lp_pos = market.engine.accounts[lp.idx as usize].position_size.get();
     assert!(lp_pos != 0, "LP position should NOT be force-closed by max PnL");
     assert_eq!(outcome.max_pnl_closed, 0, "No LP should be closed");
 
@@ -116,25 +78,14 @@ fn test_max_pnl_does_not_close_lp() {
 
 #[test]
 fn test_max_pnl_disabled_when_zero() {
-    let mut engine = setup_market();
-
-    let lp_idx = engine.add_lp([0; 32], [0; 32], 0).unwrap();
-    let user_idx = engine.add_user(0).unwrap();
-
-    engine.deposit(lp_idx, 10_000_000, 0).unwrap();
-    engine.deposit(user_idx, 1_000_000, 0).unwrap();
-
-    engine
-        .execute_trade(&MATCHER, lp_idx, user_idx, 1, ORACLE_1M, 5_000_000)
-        .unwrap();
+    let (mut market, _lp, trader) = setup_market(10_000_000, 1_000_000, 5_000_000);
 
     // max_pnl_vault_bps = 0 -> disabled
-    let outcome = engine
-        .keeper_crank(u16::MAX, 10, ORACLE_1_3M, 0, false, 0, 0)
-        .unwrap();
+    market.clock.set(10);
+    market.set_price(ORACLE_1_3M);
+    let outcome = market.crank().unwrap();
 
-    let pos_after = engine.accounts[user_idx as usize].position_size.get();
-    assert_eq!(pos_after, 5_000_000, "Position should remain open (max PnL disabled)");
+    market.assert_position(trader, 5_000_000);
     assert_eq!(outcome.max_pnl_closed, 0);
 
     println!("[Scenario 3] max_pnl=0 (disabled): position kept open");
@@ -146,35 +97,28 @@ fn test_max_pnl_disabled_when_zero() {
 
 #[test]
 fn test_max_pnl_selective_close_only_profitable() {
-    let mut engine = setup_market();
-
-    let lp_idx = engine.add_lp([0; 32], [0; 32], 0).unwrap();
-    let user_a = engine.add_user(0).unwrap();
-    let user_b = engine.add_user(0).unwrap();
-
-    engine.deposit(lp_idx, 20_000_000, 0).unwrap();
-    engine.deposit(user_a, 1_000_000, 0).unwrap();
-    engine.deposit(user_b, 1_000_000, 0).unwrap();
+    let mut market = MarketBuilder::new().price(ORACLE_1M).build();
+    let lp = market.add_lp(20_000_000);
+    let user_a = market.add_user(1_000_000);
+    let user_b = market.add_user(1_000_000);
 
     // User A: long 8M (will profit on pump)
-    engine
-        .execute_trade(&MATCHER, lp_idx, user_a, 1, ORACLE_1M, 8_000_000)
-        .unwrap();
+    market.advance(1);
+    market.trade(lp, user_a, 8_000_000).unwrap();
 
     // User B: short 3M (will lose on pump)
-    engine
-        .execute_trade(&MATCHER, lp_idx, user_b, 2, ORACLE_1M, -3_000_000)
-        .unwrap();
+    market.advance(1);
+    market.trade(lp, user_b, -3_000_000).unwrap();
 
     // Price pumps 30%: User A profits, User B loses
     // User A PnL: 8M * 0.3 = 2.4M (positive)
     // User B PnL: -3M * 0.3 = -0.9M (negative, no force close needed)
-    let outcome = engine
-        .keeper_crank(u16::MAX, 20, ORACLE_1_3M, 0, false, 2_000_000, 0) // LP 20M * 10% = 2M absolute cap
-        .unwrap();
+    market.clock.set(20);
+    market.set_price(ORACLE_1_3M);
+    let outcome = market.crank_with(0, 2_000_000, 0).unwrap(); // LP 20M * 10% = 2M absolute cap
 
-    let pos_a = engine.accounts[user_a as usize].position_size.get();
-    let pos_b = engine.accounts[user_b as usize].position_size.get();
+    let pos_a = market.engine.accounts[user_a.idx as usize].position_size.get();
+    let pos_b = market.engine.accounts[user_b.idx as usize].position_size.get();
 
     println!("[Scenario 4] Selective close:");
     println!("  User A (long, profitable) pos={} (should be 0 if force-closed)", pos_a);
@@ -191,28 +135,23 @@ fn test_max_pnl_selective_close_only_profitable() {
 
 #[test]
 fn test_vault_drain_protection_e2e() {
-    let mut engine = setup_market();
-
-    let lp_idx = engine.add_lp([0; 32], [0; 32], 0).unwrap();
-    let trader = engine.add_user(0).unwrap();
-
     // LP deposits 10 SOL equivalent
     let lp_capital = 10_000_000u128;
-    engine.deposit(lp_idx, lp_capital, 0).unwrap();
+    let mut market = MarketBuilder::new().price(ORACLE_1M).build();
+    let lp = market.add_lp(lp_capital);
 
     // Trader deposits 1 SOL (needs enough for initial margin at 10% = 300k margin for 3M pos)
-    engine.deposit(trader, 1_000_000, 0).unwrap();
+    let trader = market.add_user(1_000_000);
 
-    let vault_before = engine.vault.get();
-    let c_tot_before = engine.c_tot.get();
+    let vault_before = market.engine.vault.get();
+    let c_tot_before = market.engine.c_tot.get();
 
     println!("[Scenario 5] Vault Drain Protection E2E:");
     println!("  Initial vault={}, c_tot={}", vault_before, c_tot_before);
 
     // Trader opens ~5x long: 3M units notional (needs 300k margin at 10%)
-    engine
-        .execute_trade(&MATCHER, lp_idx, trader, 1, ORACLE_1M, 3_000_000)
-        .unwrap();
+    market.advance(1);
+    market.trade(lp, trader, 3_000_000).unwrap();
 
     // Price pumps 50%: trader PnL = 5M * 0.5 = 2.5M
     // Without protection: trader could extract 2.5M (25% of vault)
@@ -220,13 +159,14 @@ fn test_vault_drain_protection_e2e() {
     // c_tot ~ 10.5M, cap = 2.1M
 
     // Run crank at pumped price with 20% cap
-    let outcome = engine
-        .keeper_crank(u16::MAX, 100, 1_500_000, 0, false, 2_000_000, 0) // LP 10M * 20% = 2M absolute cap
-        .unwrap();
+    market.clock.set(100);
+    market.set_price(1_500_000);
+    let outcome = market.crank_with(0, 2_000_000, 0).unwrap(); // LP 10M * 20% = 2M absolute cap
+    market.assert_conserved();
 
-    let trader_pos = engine.accounts[trader as usize].position_size.get();
-    let trader_capital = engine.accounts[trader as usize].capital.get();
-    let vault_after = engine.vault.get();
+    let trader_pos = market.engine.accounts[trader.idx as usize].position_size.get();
+    let trader_capital = market.engine.accounts[trader.idx as usize].capital.get();
+    let vault_after = market.engine.vault.get();
 
     println!("  After 50% pump + crank (20% cap):");
     println!("  Trader pos={}, capital={}", trader_pos, trader_capital);
@@ -250,13 +190,11 @@ fn test_vault_drain_protection_e2e() {
 
 #[test]
 fn test_crank_outcome_has_max_pnl_fields() {
-    let mut engine = setup_market();
-    let user = engine.add_user(0).unwrap();
-    engine.deposit(user, 1_000_000, 0).unwrap();
+    let mut market = MarketBuilder::new().price(ORACLE_1M).build();
+    market.add_user(1_000_000);
 
-    let outcome = engine
-        .keeper_crank(u16::MAX, 1, ORACLE_1M, 0, false, 0, 0)
-        .unwrap();
+    market.advance(1);
+    let outcome = market.crank().unwrap();
 
     // Verify new fields exist and are initialized
     assert_eq!(outcome.max_pnl_closed, 0);
@@ -272,36 +210,27 @@ fn test_crank_outcome_has_max_pnl_fields() {
 
 #[test]
 fn test_progressive_pnl_growth_triggers_force_close() {
-    let mut engine = setup_market();
-
-    let lp_idx = engine.add_lp([0; 32], [0; 32], 0).unwrap();
-    let trader = engine.add_user(0).unwrap();
-
-    engine.deposit(lp_idx, 10_000_000, 0).unwrap();
-    engine.deposit(trader, 1_000_000, 0).unwrap();
-
     // Open position
-    engine
-        .execute_trade(&MATCHER, lp_idx, trader, 1, ORACLE_1M, 3_000_000)
-        .unwrap();
+    let (mut market, _lp, trader) = setup_market(10_000_000, 1_000_000, 3_000_000);
 
     println!("[Scenario 7] Progressive PnL:");
 
-    // Simulate gradual price increase
+    // Simulate gradual price increase, one step every 10 slots
     let prices = [1_050_000u64, 1_100_000, 1_150_000, 1_200_000, 1_300_000, 1_500_000];
+    let path = PricePath::from_prices(&prices).slots_per_step(10);
     let mut closed = false;
 
-    for (i, &price) in prices.iter().enumerate() {
-        let slot = (i as u64 + 1) * 10;
-        let outcome = engine
-            .keeper_crank(u16::MAX, slot, price, 0, false, 1_500_000, 0) // LP 10M * 15% = 1.5M absolute cap
-            .unwrap();
+    for (i, &price) in path.prices.iter().enumerate() {
+        market.clock.set((i as u64 + 1) * path.slots_per_step);
+        market.set_price(price);
+        let outcome = market.crank_with(0, 1_500_000, 0).unwrap(); // LP 10M * 15% = 1.5M absolute cap
+        market.assert_conserved();
 
-        let pos = engine.accounts[trader as usize].position_size.get();
-        let pnl = engine.accounts[trader as usize].pnl.get();
+        let pos = market.engine.accounts[trader.idx as usize].position_size.get();
+        let pnl = market.engine.accounts[trader.idx as usize].pnl.get();
 
         println!("  Slot {}: price={:.4}, pos={}, pnl={}, max_pnl_closed={}",
-                 slot, price as f64 / 1_000_000.0, pos, pnl, outcome.max_pnl_closed);
+                 market.slot(), price as f64 / 1_000_000.0, pos, pnl, outcome.max_pnl_closed);
 
         if pos == 0 && !closed {
             println!("  >>> Position force-closed at price {:.4}!", price as f64 / 1_000_000.0);